@@ -14,7 +14,7 @@ use cortex_m_rt::entry; // The runtime
 use nb::block;
 use stm32_hal2::{
     self,
-    can::Can,
+    can::{self, Can},
     clocks::{self, ApbPrescaler, Clocks, InputSrc, PllSrc, Pllp},
     gpio::{OutputType, Pin, PinMode, Port},
     pac,
@@ -61,9 +61,8 @@ fn main() -> ! {
         let can = Can::new(dp.CAN1, &mut rcc);
 
         bxcan::Can::builder(can)
-            // APB1 (PCLK1): 45MHz, Bit rate: 1000kBit/s, Sample Point 86.7%
-            // Value was calculated with http://www.bittiming.can-wiki.info/
-            .set_bit_timing(0x001b0002)
+            // APB1 (PCLK1): 45MHz, Bit rate: 1000kBit/s, Sample Point 86.7%. (0x001b0002)
+            .set_bit_timing(can::bit_timing(&clock_cfg, 1_000_000).unwrap())
             .enable()
     };
 
@@ -12,7 +12,10 @@
 use cortex_m_rt::entry;
 
 use stm32_hal2::{
-    clocks::{self, ApbPrescaler, Clocks, InputSrc, MsiRng, PllCfg, PllSrc, Pllm, Pllr},
+    clocks::{
+        self, ApbPrescaler, Clocks, InputSrc, MsiRng, PllCfg, PllSrc, Pllm, Pllr, TemperatureGrade,
+        VosRange,
+    },
     low_power, pac,
 };
 
@@ -106,6 +109,13 @@ fn main() -> ! {
     // clock_cfg.vos_range = VosRange::VOS0;
     // You can use `Clocks::full_speed()` to configure an H743 etc at 480Mhz.
 
+    // If using an industrial-temperature part (eg the `3` or `7` suffix), set its temperature
+    // grade, so `setup()` rejects speeds the datasheet doesn't allow at that temperature. You can
+    // query the limit directly with `clocks::max_sysclk()`:
+    clock_cfg.temperature_grade = TemperatureGrade::Grade3;
+    let max_speed = clocks::max_sysclk(VosRange::VOS0, TemperatureGrade::Grade3);
+    assert!(clock_cfg.sysclk() <= max_speed);
+
     // Change the default wakeup from Stop mode to be HSI instead of MSI. (L4 and L5 only)
     clock_cfg.stop_wuck = StopWuck::Hsi;
 
@@ -19,7 +19,7 @@ use cortex_m_rt::entry;
 use stm32_hal2::{
     adc::{Adc, AdcChannel, Align, CkMode, InputType, OperationMode},
     clocks::Clocks,
-    gpio::{self, Edge, OutputSpeed, Pin, PinMode, PinState, Port},
+    gpio::{self, Edge, OutputSpeed, OutputType, Pin, PinMode, PinState, Port, Pull},
     low_power, pac,
     prelude::*,
 };
@@ -47,34 +47,112 @@ fn example_type_sigs<O: OutputPin>(pin1: &mut O, pin2: &mut Pin) {
 /// For example, ones used with buses (eg I2C, SPI, UART), USB, ADC, and DAC pins.
 /// This may also include input pins that trigger interrupts, and aren't polled.
 pub fn setup_pins() {
-    // Set up I2C pins
-    let mut scl = Pin::new(Port::B, 6, PinMode::Alt(4));
-    scl.output_type(OutputType::OpenDrain);
-
-    let mut sda = Pin::new(Port::B, 7, PinMode::Alt(4));
-    sda.output_type(OutputType::OpenDrain);
-
-    // Set up SPI pins
-    let _sck = Pin::new(Port::A, 5, PinMode::Alt(5));
-    let _miso = Pin::new(Port::A, 6, PinMode::Alt(5));
-    let _mosi = Pin::new(Port::A, 7, PinMode::Alt(5));
-
-    // Set up UART pins
-    let _uart_tx = Pin::new(Port::A, 9, PinMode::Alt(7));
-    let _uart_rx = Pin::new(Port::A, 10, PinMode::Alt(7));
-
-    // Set up USB pins
-    let _usb_dm = Pin::new(Port::A, 11, PinMode::Alt(14));
-    let _usb_dp = Pin::new(Port::A, 12, PinMode::Alt(14));
-
-    // Set the ADC pin to analog mode, to prevent parasitic power use.
-    let _adc_pin = Pin::new(Port::B, 0, PinMode::Analog);
-
-    // Set DAC pin to analog mode, to prevent parasitic power use.
-    let _dac_pin = Pin::new(Port::A, 4, PinMode::Analog);
-
-    // Set up PWM.  // Timer 2, channel 1.
-    let _pwm_pin = Pin::new(Port::A, 0, PinMode::Alt(1));
+    // Configure pins for buses, USB, ADC, DAC, and PWM in one call. Each port's registers are
+    // written once, instead of once per pin setting.
+    gpio::configure_table(&[
+        // I2C
+        (
+            Port::B,
+            6,
+            PinMode::Alt(4),
+            Pull::Floating,
+            OutputType::OpenDrain,
+            OutputSpeed::Low,
+        ),
+        (
+            Port::B,
+            7,
+            PinMode::Alt(4),
+            Pull::Floating,
+            OutputType::OpenDrain,
+            OutputSpeed::Low,
+        ),
+        // SPI
+        (
+            Port::A,
+            5,
+            PinMode::Alt(5),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::High,
+        ),
+        (
+            Port::A,
+            6,
+            PinMode::Alt(5),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::High,
+        ),
+        (
+            Port::A,
+            7,
+            PinMode::Alt(5),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::High,
+        ),
+        // UART
+        (
+            Port::A,
+            9,
+            PinMode::Alt(7),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::Low,
+        ),
+        (
+            Port::A,
+            10,
+            PinMode::Alt(7),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::Low,
+        ),
+        // USB
+        (
+            Port::A,
+            11,
+            PinMode::Alt(14),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::VeryHigh,
+        ),
+        (
+            Port::A,
+            12,
+            PinMode::Alt(14),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::VeryHigh,
+        ),
+        // Set the ADC and DAC pins to analog mode, to prevent parasitic power use.
+        (
+            Port::B,
+            0,
+            PinMode::Analog,
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::Low,
+        ),
+        (
+            Port::A,
+            4,
+            PinMode::Analog,
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::Low,
+        ),
+        // PWM: Timer 2, channel 1.
+        (
+            Port::A,
+            0,
+            PinMode::Alt(1),
+            Pull::Floating,
+            OutputType::PushPull,
+            OutputSpeed::Low,
+        ),
+    ]);
 
     // Set up buttons, with pull-up resistors that trigger on the falling edge.
     let mut up_btn = Pin::new(Port::B, 3, PinMode::Input);
@@ -113,7 +191,10 @@ fn main() -> ! {
 
     // A simple button debounce: Use a timer with a period between the maximum bouncing
     // time you expect, and the minimum time bewteen actuations. In this time, we've chosen 5Hz,
-    // or 200ms. Note that there are other approaches as well.
+    // or 200ms. Note that there are other approaches as well. For example, `timer::DebouncedInput`
+    // debounces a pin connected to a timer's channel 1 in hardware, with a single interrupt per
+    // debounced transition:
+    // `let mut button = DebouncedInput::new(Timer::new_tim2(dp.TIM2, 1., Default::default(), &clock_cfg), pin, 0.02).unwrap();`
     let mut debounce_timer = Timer::new_tim15(dp.TIM15, 5., &clock_cfg);
     debounce_timer.enable_interrupt(TimerInterrupt::Update);
 
@@ -131,7 +212,9 @@ fn main() -> ! {
     example_output.set_low();
 
     // Unmask interrupt lines associated with the input pins we've configured interrupts
-    // for in `setup_pins`.
+    // for in `setup_pins`. Alternatively, with the `exti_dispatch` feature, the HAL provides the
+    // `EXTIx` handlers; register a function for each pin instead of writing the handlers below:
+    // `gpio::attach_interrupt(&mut button, Edge::Falling, on_button_press);`
     unsafe {
         // EXTI line 3 is associated with pins numbered 0 (PA3, PB3 etc)
         NVIC::unmask(pac::Interrupt::EXTI3);
@@ -26,23 +26,115 @@ use crate::dma::DmaInput;
 // L4x2 implies ADC1 only.
 cfg_if! {
     if #[cfg(feature = "h7")] {
-        // These values are from the H723 User manual
-        const VREFINT_ADDR: u32 = 0x1FF1_E860;
-        const VREFINT_VOLTAGE: f32 = 3.3;
-        const VREFINT_CH: u8 = 0; // todo: Unknown. What is it?
+        // These values are from the H723 User manual. The internal channels are on ADC3.
+        pub(crate) const VREFINT_ADDR: u32 = 0x1FF1_E860;
+        pub(crate) const VREFINT_VOLTAGE: f32 = 3.3;
+        const VREFINT_CH: u8 = 19;
     } else if #[cfg(feature = "g4")] {
-        const VREFINT_ADDR: u32 = 0x1FFF_75AA;
-        const VREFINT_VOLTAGE: f32 = 3.0;
+        pub(crate) const VREFINT_ADDR: u32 = 0x1FFF_75AA;
+        pub(crate) const VREFINT_VOLTAGE: f32 = 3.0;
         const VREFINT_CH: u8 = 18; // G491
+    } else if #[cfg(feature = "f3")] {
+        // F303 datasheet, section 3.10.2.
+        pub(crate) const VREFINT_ADDR: u32 = 0x1FFF_F7BA;
+        pub(crate) const VREFINT_VOLTAGE: f32 = 3.3;
+        const VREFINT_CH: u8 = 18;
+    } else if #[cfg(feature = "l5")] {
+        // L552 datasheet, section 3.19.
+        pub(crate) const VREFINT_ADDR: u32 = 0x0BFA_05AA;
+        pub(crate) const VREFINT_VOLTAGE: f32 = 3.0;
+        const VREFINT_CH: u8 = 0;
     } else {
-        const VREFINT_ADDR: u32 = 0x1FFF_75AA;
-        const VREFINT_VOLTAGE: f32 = 3.0;
+        pub(crate) const VREFINT_ADDR: u32 = 0x1FFF_75AA;
+        pub(crate) const VREFINT_VOLTAGE: f32 = 3.0;
         const VREFINT_CH: u8 = 0; // L412
     }
 }
 
+// Temperature sensor calibration addresses and temperatures, and the temperature sensor and VBAT
+// channels. These are only defined on families with an ADC implementation here.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const TS_CAL1_ADDR: u32 = 0x1FF1_E820;
+        const TS_CAL2_ADDR: u32 = 0x1FF1_E840;
+        const TS_CAL2_TEMP: f32 = 110.;
+        const TEMP_CH: u8 = 18;
+        const VBAT_CH: u8 = 17;
+        const VBAT_DIVIDER: f32 = 4.;
+    } else if #[cfg(feature = "g4")] {
+        const TS_CAL1_ADDR: u32 = 0x1FFF_75A8;
+        const TS_CAL2_ADDR: u32 = 0x1FFF_75CA;
+        const TS_CAL2_TEMP: f32 = 130.;
+        const TEMP_CH: u8 = 16;
+        const VBAT_CH: u8 = 17;
+        const VBAT_DIVIDER: f32 = 3.;
+    } else if #[cfg(feature = "f303")] {
+        const TS_CAL1_ADDR: u32 = 0x1FFF_F7B8;
+        const TS_CAL2_ADDR: u32 = 0x1FFF_F7C2;
+        const TS_CAL2_TEMP: f32 = 110.;
+        const TEMP_CH: u8 = 16;
+        const VBAT_CH: u8 = 17;
+        const VBAT_DIVIDER: f32 = 2.;
+    } else if #[cfg(feature = "l5")] {
+        const TS_CAL1_ADDR: u32 = 0x0BFA_05A8;
+        const TS_CAL2_ADDR: u32 = 0x0BFA_05CA;
+        const TS_CAL2_TEMP: f32 = 130.;
+        const TEMP_CH: u8 = 17;
+        const VBAT_CH: u8 = 18;
+        const VBAT_DIVIDER: f32 = 3.;
+    } else if #[cfg(feature = "l4")] {
+        const TS_CAL1_ADDR: u32 = 0x1FFF_75A8;
+        const TS_CAL2_ADDR: u32 = 0x1FFF_75CA;
+        // L47x and L49x are calibrated at 110°C; other L4 variants at 130°C.
+        #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+        const TS_CAL2_TEMP: f32 = 110.;
+        #[cfg(not(any(feature = "l4x5", feature = "l4x6")))]
+        const TS_CAL2_TEMP: f32 = 130.;
+        const TEMP_CH: u8 = 17;
+        const VBAT_CH: u8 = 18;
+        const VBAT_DIVIDER: f32 = 3.;
+    }
+}
+
+// The temperature sensor's first calibration point is taken at 30°C on all supported families,
+// with VDDA at `VREFINT_VOLTAGE`.
+#[cfg(any(
+    feature = "f303",
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "h7"
+))]
+const TS_CAL1_TEMP: f32 = 30.;
+
+// ADC common control register bits that connect the temperature sensor and VBAT to their channels.
+// These are in the same position on all supported families, but have different field names.
+#[cfg(any(
+    feature = "f303",
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "h7"
+))]
+const CCR_TSEN: u32 = 1 << 23;
+#[cfg(any(
+    feature = "f303",
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "h7"
+))]
+const CCR_VBATEN: u32 = 1 << 24;
+
 const MAX_ADVREGEN_STARTUP_US: u32 = 10;
 
+// Differential conversion results are centered on mid-scale; this corresponds to 0V between
+// the inputs. H7 uses 16-bit resolution by default; other families use 12-bit.
+#[cfg(feature = "h7")]
+const DIFF_MIDSCALE: i32 = 32_768;
+#[cfg(any(feature = "f303", feature = "l4", feature = "l5", feature = "g4"))]
+const DIFF_MIDSCALE: i32 = 2_048;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum AdcDevice {
     One,
@@ -75,7 +167,87 @@ pub enum Trigger {
     Tim6Trgo = 0b01101,
     Tim15Trgo = 0b01110,
     Tim3Cc4 = 0b01111,
-    // todo: Fill in remaining ones.
+    #[cfg(feature = "g4")]
+    Tim20Trgo = 0b10000,
+    #[cfg(feature = "g4")]
+    Tim20Trgo2 = 0b10001,
+    #[cfg(feature = "g4")]
+    Tim20Cc1 = 0b10010,
+    #[cfg(feature = "g4")]
+    Tim20Cc2 = 0b10011,
+    #[cfg(feature = "g4")]
+    Tim20Cc3 = 0b10100,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg1 = 0b10101,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg3 = 0b10110,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg5 = 0b10111,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg6 = 0b11000,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg7 = 0b11001,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg8 = 0b11010,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg9 = 0b11011,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg10 = 0b11100,
+    #[cfg(feature = "g4")]
+    LptimOut = 0b11101,
+    Tim7Trgo = 0b11110,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Select a trigger for the injected group. Sets JSQR reg, JEXTSEL field. See G4 RM, table 165:
+/// ADC1/2 - External triggers for injected channels. The values are G4's ADC1 and ADC2 mapping;
+/// the first 16 match the other families' tables for ADC1. The remaining triggers are only
+/// available on G4.
+pub enum InjectedTrigger {
+    Tim1Trgo = 0b00000,
+    Tim1Cc4 = 0b00001,
+    Tim2Trgo = 0b00010,
+    Tim2Cc1 = 0b00011,
+    Tim3Cc4 = 0b00100,
+    Tim4Trgo = 0b00101,
+    Exti15 = 0b00110,
+    Tim8Cc4 = 0b00111,
+    Tim1Trgo2 = 0b01000,
+    Tim8Trgo = 0b01001,
+    Tim8Trgo2 = 0b01010,
+    Tim3Cc3 = 0b01011,
+    Tim3Trgo = 0b01100,
+    Tim3Cc1 = 0b01101,
+    Tim6Trgo = 0b01110,
+    Tim15Trgo = 0b01111,
+    #[cfg(feature = "g4")]
+    Tim20Trgo = 0b10000,
+    #[cfg(feature = "g4")]
+    Tim20Trgo2 = 0b10001,
+    #[cfg(feature = "g4")]
+    Tim20Cc4 = 0b10010,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg2 = 0b10011,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg4 = 0b10100,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg5 = 0b10101,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg6 = 0b10110,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg7 = 0b10111,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg8 = 0b11000,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg9 = 0b11001,
+    #[cfg(feature = "g4")]
+    HrtimAdcTrg10 = 0b11010,
+    #[cfg(feature = "g4")]
+    Tim16Cc1 = 0b11011,
+    #[cfg(feature = "g4")]
+    LptimOut = 0b11101,
+    #[cfg(feature = "g4")]
     Tim7Trgo = 0b11110,
 }
 
@@ -274,6 +446,192 @@ impl Default for Align {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// Select an analog watchdog. AWD1 monitors a single channel or all channels, at full
+/// resolution. AWD2 and AWD3 monitor any set of channels; on families other than H7, they only
+/// compare the 8 MSBs of the conversion result.
+pub enum AnalogWatchdog {
+    One,
+    Two,
+    Three,
+}
+
+#[derive(Clone, Copy)]
+/// Channels monitored by an analog watchdog.
+pub enum WatchdogChannels {
+    /// Monitor all channels that are converted.
+    All,
+    /// Monitor a single channel.
+    Single(u8),
+    /// Monitor a set of channels, as a bitmask. (eg `(1 << 3) | (1 << 5)` for channels 3 and 5).
+    /// Not available on AWD1. Sets the ADC_AWD2CR or ADC_AWD3CR register.
+    Mask(u32),
+}
+
+#[derive(Clone, Copy, Default)]
+/// Analog watchdog flags, returned by `Adc::take_watchdog_flags`. Each is true if a conversion
+/// result was outside the watchdog's thresholds.
+pub struct WatchdogFlags {
+    pub awd1: bool,
+    pub awd2: bool,
+    pub awd3: bool,
+}
+
+impl WatchdogFlags {
+    /// Returns true if any watchdog was triggered.
+    pub fn any(&self) -> bool {
+        self.awd1 || self.awd2 || self.awd3
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Dual ADC mode, for ADCs sharing common registers. (eg ADC1 and 2). Sets ADCx_CCR register,
+/// DUAL field. See G4 RM, section 21.4.31: Dual ADC modes.
+pub enum MultiMode {
+    /// Each ADC operates independently.
+    Independent = 0b00000,
+    /// Combined regular simultaneous, and injected simultaneous modes.
+    RegularInjectedSimultaneous = 0b00001,
+    /// Combined regular simultaneous, and alternate trigger modes.
+    RegularSimultaneousAltTrigger = 0b00010,
+    /// Combined interleaved, and injected simultaneous modes.
+    InterleavedInjectedSimultaneous = 0b00011,
+    /// Injected simultaneous mode only.
+    InjectedSimultaneous = 0b00101,
+    /// Regular simultaneous mode only: Both ADCs convert their regular sequences at the same time.
+    /// Use sequences of the same length, and the same sample times on matching ranks.
+    RegularSimultaneous = 0b00110,
+    /// Interleaved mode only: A single trigger starts the master, then the slave after a
+    /// delay. Use this to double the sample rate of a single channel.
+    Interleaved = 0b00111,
+    /// Alternate trigger mode only.
+    AlternateTrigger = 0b01001,
+}
+
+#[cfg(not(feature = "f3"))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Oversampling ratio: The number of conversions accumulated for each result.
+/// Sets ADC_CFGR2 register, OVSR field.
+pub enum OversamplingRatio {
+    X2 = 0,
+    X4 = 1,
+    X8 = 2,
+    X16 = 3,
+    X32 = 4,
+    X64 = 5,
+    X128 = 6,
+    X256 = 7,
+}
+
+#[cfg(not(feature = "f3"))]
+#[derive(Clone, Copy, PartialEq)]
+/// Which conversion groups oversampling applies to. See G4 RM, section 21.4.30.
+pub enum OversamplingScope {
+    /// Regular conversions only. If an injected conversion interrupts an oversampling
+    /// sequence, the sequence continues afterwards, keeping the accumulated data.
+    Regular,
+    /// Regular conversions only. If an injected conversion interrupts an oversampling
+    /// sequence, the accumulated data is discarded, and the sequence restarts afterwards.
+    RegularResumed,
+    /// Injected conversions only.
+    Injected,
+    /// Both regular and injected conversions.
+    Both,
+}
+
+#[cfg(not(feature = "f3"))]
+#[derive(Clone, Copy)]
+/// ADC hardware oversampling configuration. The sum of `ratio` conversions is right-shifted by
+/// `shift` bits. For example, with a 12-bit ADC, `X256` and a shift of 4 produces a 16-bit result.
+/// The sum must fit in the data register after shifting: Up to 16 bits on families other than H7,
+/// and 26 bits on H7.
+pub struct OversamplingConfig {
+    pub ratio: OversamplingRatio,
+    /// Right shift applied to the accumulated result. 0 - 8 bits; 0 - 11 bits on H7.
+    pub shift: u8,
+    /// If true, each conversion of the oversampling sequence requires a new trigger. If false,
+    /// a single trigger starts the whole sequence. Regular conversions only.
+    pub triggered: bool,
+    pub scope: OversamplingScope,
+}
+
+// Below this VDDA, the I/O analog switches need the booster, or a VDD supply, to keep their
+// resistance low. Without either, we refuse sample times shorter than `LOW_VDDA_MIN_SAMPLE_TIME`,
+// a conservative minimum for the sampling capacitor to charge through the switches. See the
+// ADC characteristics table in your MCU's datasheet for RAIN limits at a given sample time.
+#[cfg(feature = "h7")]
+const LOW_VDDA_THRESH: f32 = 2.7;
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+const LOW_VDDA_THRESH: f32 = 2.4;
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+const LOW_VDDA_MIN_SAMPLE_TIME: SampleTime = SampleTime::T19;
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+#[derive(Clone, Copy, PartialEq)]
+/// The supply of the I/O analog switches, which connect pins to the ADC. Their resistance rises
+/// when VDDA is below 2.4V (2.7V on H7), eg with a 1.8V supply. Sets SYSCFG_CFGR1 register, BOOSTEN
+/// and ANASWVDD fields, or on H7, SYSCFG_PMCR register, BOOSTE and BOOSTVDDSEL fields.
+pub enum AnalogSupply {
+    /// Supply the switches from VDDA, with the booster off. Use this if VDDA is at least 2.4V (2.7V
+    /// on H7).
+    Vdda,
+    #[cfg(not(feature = "l4"))]
+    /// VDDA is low, but VDD is at least 2.4V (2.7V on H7): Supply the switches from VDD. This is
+    /// preferred over the booster when available, since it doesn't draw extra current.
+    Vdd,
+    /// VDDA and VDD are both low: Enable the booster, which supplies the switches from a charge
+    /// pump.
+    Booster,
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+/// Configure the supply of the I/O analog switches. This is set from `AdcConfig::analog_supply`
+/// when initializing an ADC; use this to change it later, eg if VDDA changes.
+pub fn set_analog_supply(supply: AnalogSupply) {
+    let (boost, vdd) = match supply {
+        AnalogSupply::Vdda => (false, false),
+        #[cfg(not(feature = "l4"))]
+        AnalogSupply::Vdd => (false, true),
+        AnalogSupply::Booster => (true, false),
+    };
+
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+
+        // We use raw bits, since these fields are named inconsistently among families, and missing
+        // from some PACs.
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
+                // PMCR: BOOSTE (bit 8), and BOOSTVDDSEL (bit 9).
+                syscfg.pmcr.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b11 << 8)) | (boost as u32) << 8 | (vdd as u32) << 9)
+                });
+            } else {
+                rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+                // CFGR1: BOOSTEN (bit 8), and ANASWVDD (bit 9). ANASWVDD is reserved on L4, where
+                // `vdd` is always false.
+                syscfg.cfgr1.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b11 << 8)) | (boost as u32) << 8 | (vdd as u32) << 9)
+                });
+            }
+        }
+    });
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ADC error
+pub enum Error {
+    /// The sample time is too short for the measured VDDA, with the analog switches supplied from
+    /// VDDA. Set `AdcConfig::analog_supply`, or use a longer sample time. See
+    /// `Adc::low_vdda_unboosted()`.
+    SampleTimeTooShort,
+}
+
 /// Initial configuration data for the ADC peripheral.
 #[derive(Clone)]
 pub struct AdcConfig {
@@ -288,6 +646,10 @@ pub struct AdcConfig {
     pub cal_single_ended: Option<u16>,
     /// Optional calibration data for differential measurements.
     pub cal_differential: Option<u16>,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+    /// The supply of the I/O analog switches. Set this to `Booster`, or `Vdd` if VDDA is below
+    /// 2.4V (2.7V on H7). Defaults to `Vdda`.
+    pub analog_supply: AnalogSupply,
 }
 
 impl Default for AdcConfig {
@@ -298,6 +660,8 @@ impl Default for AdcConfig {
             operation_mode: OperationMode::OneShot,
             cal_single_ended: None,
             cal_differential: None,
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+            analog_supply: AnalogSupply::Vdda,
         }
     }
 }
@@ -364,6 +728,9 @@ macro_rules! hal {
 
                     result.set_align(Align::default());
 
+                    #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+                    set_analog_supply(result.cfg.analog_supply);
+
                     result.advregen_enable(clock_cfg);
 
                     result.calibrate(InputType::SingleEnded, clock_cfg);
@@ -691,8 +1058,12 @@ macro_rules! hal {
                 }
             }
 
-            /// Select a sequence to sample, by inputting a single channel and position.
+            /// Select a sequence to sample, by inputting a single channel and position. On H7, also
+            /// preselects the channel.
             pub fn set_sequence(&mut self, chan: u8, position: u8) {
+                #[cfg(feature = "h7")]
+                self.preselect_channel(chan);
+
                 match position {
                     1 => self.regs.sqr1.modify(|_, w| unsafe { w.sq1().bits(chan) }),
                     2 => self.regs.sqr1.modify(|_, w| unsafe { w.sq2().bits(chan) }),
@@ -714,7 +1085,51 @@ macro_rules! hal {
                 }
             }
 
-            /// Select the sample time for a given channel.
+            #[cfg(feature = "h7")]
+            /// Preselect a channel, so it can be converted. H7 RM, section 25.4.12: "For each channel
+            /// selected through SQRx or JSQRx, the corresponding ADC_PCSEL bit must be previously configured."
+            /// If a channel isn't preselected, its readings are silently 0. This is done automatically by
+            /// `set_sequence()`. Sets the `PCSEL` register.
+            pub fn preselect_channel(&mut self, chan: u8) {
+                self.regs.pcsel.modify(|r, w| unsafe { w.bits(r.bits() | (1 << chan)) });
+            }
+
+            #[cfg(feature = "h7")]
+            /// Remove a channel's preselection. Sets the `PCSEL` register.
+            pub fn deselect_channel(&mut self, chan: u8) {
+                self.regs.pcsel.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << chan)) });
+            }
+
+            #[cfg(feature = "h7")]
+            /// Check that each channel in the regular sequence is preselected.
+            fn check_preselection(&self) {
+                let sqr = [
+                    self.regs.sqr1.read().bits(),
+                    self.regs.sqr2.read().bits(),
+                    self.regs.sqr3.read().bits(),
+                    self.regs.sqr4.read().bits(),
+                ];
+                let pcsel = self.regs.pcsel.read().bits();
+                let len = (sqr[0] & 0b1111) as usize + 1;
+
+                for position in 1..=len {
+                    // SQ1 - 4 start at bit 6 of SQR1; others start at bit 0 of SQR2 - 4.
+                    let (reg, offset) = if position <= 4 {
+                        (0, position * 6)
+                    } else {
+                        ((position - 5) / 5 + 1, ((position - 5) % 5) * 6)
+                    };
+                    let chan = (sqr[reg] >> offset) & 0b1_1111;
+
+                    debug_assert!(
+                        pcsel & (1 << chan) != 0,
+                        "ADC channel used in a sequence isn't preselected (PCSEL); its readings would be 0."
+                    );
+                }
+            }
+
+            /// Select the sample time for a given channel. See `try_set_sample_time()` for a version
+            /// that checks it against VDDA.
             pub fn set_sample_time(&mut self, chan: u8, smp: SampleTime) {
                 // Channel is the ADC channel to use.
 
@@ -754,11 +1169,33 @@ macro_rules! hal {
                 // self.enable();
             }
 
+            /// Select the sample time for a given channel. Returns an error if the sample time is
+            /// too short for a low VDDA; see `low_vdda_unboosted()`.
+            pub fn try_set_sample_time(&mut self, chan: u8, smp: SampleTime) -> Result<(), Error> {
+                #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+                if self.low_vdda_unboosted() && (smp as u8) < LOW_VDDA_MIN_SAMPLE_TIME as u8 {
+                    return Err(Error::SampleTimeTooShort);
+                }
+
+                self.set_sample_time(chan, smp);
+                Ok(())
+            }
+
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "h7"))]
+            /// Returns true if VDDA, as last measured by `read_vref()`, is below 2.4V (2.7V on H7),
+            /// and the analog switches are supplied from VDDA without the booster. In this case,
+            /// `try_set_sample_time()` returns an error for short sample times, since the switches'
+            /// resistance is too high for the sampling capacitor to charge. Returns false if VDDA
+            /// hasn't been measured.
+            pub fn low_vdda_unboosted(&self) -> bool {
+                self.cfg.analog_supply == AnalogSupply::Vdda
+                    && self.vdda_calibrated > 0.
+                    && self.vdda_calibrated < LOW_VDDA_THRESH
+            }
+
             /// Find and store the internal voltage reference, to improve conversion from reading
             /// to voltage accuracy. See L44 RM, section 16.4.34: "Monitoring the internal voltage reference"
             fn setup_vdda(&mut self, clock_cfg: &Clocks) {
-                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
-
                 // RM: It is possible to monitor the internal voltage reference (VREFINT) to have a reference point for
                 // evaluating the ADC VREF+ voltage level.
                 // The internal voltage reference is internally connected to the input channel 0 of the ADC1
@@ -800,46 +1237,117 @@ macro_rules! hal {
                     // adc1.vdda_calibrated
                     3.3
                 } else {
-                    // "Table 24. Embedded internal voltage reference" states that the sample time needs to be
-                    // at a minimum 4 us. With 640.5 ADC cycles we have a minimum of 8 us at 80 MHz, leaving
-                    // some headroom.
-
-                    common_regs.ccr.modify(|_, w| w.vrefen().set_bit());
-                    // User manual table: "Embedded internal voltage reference" states that it takes a maximum of 12 us
-                    // to stabilize the internal voltage reference, we wait a little more.
-                    let cp = unsafe { cortex_m::Peripherals::steal() };
-                    let mut delay = Delay::new(cp.SYST, clock_cfg.systick());
-
-                    // todo: Not sure what to set this delay to and how to change it based on variant, so picking
-                    // todo something conservative.
-                    delay.delay_us(100);
-
-                    // This sample time is overkill.
-                    // Note that you will need to reset the sample time if you use this channel on this
-                    // ADC for something other than reading vref later.
-                    self.set_sample_time(VREFINT_CH, SampleTime::T601);
-                    let reading = self.read(VREFINT_CH);
-                    self.stop_conversions();
-
-                    common_regs.ccr.modify(|_, w| w.vrefen().clear_bit());
-
-                    // The VDDA power supply voltage applied to the microcontroller may be subject to variation or
-                    // not precisely known. The embedded internal voltage reference (VREFINT) and its calibration
-                    // data acquired by the ADC during the manufacturing process at VDDA = 3.0 V can be used to
-                    // evaluate the actual VDDA voltage level.
-                    // The following formula gives the actual VDDA voltage supplying the device:
-                    // VDDA = 3.0 V x VREFINT_CAL / VREFINT_DATA
-                    // where:
-                    // • VREFINT_CAL is the VREFINT calibration value
-                    // • VREFINT_DATA is the actual VREFINT output value converted by ADC
-
-                    // todo: This address may be different on different MCUs, even within the same family.
-                    // Although, it seems relatively consistent. Check User Manuals.
-                    let vrefint_cal: u16 = unsafe { ptr::read_volatile(&*(VREFINT_ADDR as *const _)) };
-                    VREFINT_VOLTAGE * vrefint_cal as f32 / reading as f32
+                    self.read_vref(clock_cfg)
                 };
             }
 
+            /// Measure the internal voltage reference, and use it to find the actual VDDA, in Volts.
+            /// Updates the VDDA used by `reading_to_voltage()`. The internal voltage reference is only
+            /// available on ADC1 (ADC3 on H7). See L44 RM, section 16.4.34.
+            /// If VDDA is low, and the analog switch booster isn't configured, short sample times are
+            /// refused afterwards; see `low_vdda_unboosted()`.
+            pub fn read_vref(&mut self, clock_cfg: &Clocks) -> f32 {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+
+                // "Table 24. Embedded internal voltage reference" states that the sample time needs to be
+                // at a minimum 4 us. With 640.5 ADC cycles we have a minimum of 8 us at 80 MHz, leaving
+                // some headroom.
+
+                common_regs.ccr.modify(|_, w| w.vrefen().set_bit());
+                // User manual table: "Embedded internal voltage reference" states that it takes a maximum of 12 us
+                // to stabilize the internal voltage reference, we wait a little more.
+                let cp = unsafe { cortex_m::Peripherals::steal() };
+                let mut delay = Delay::new(cp.SYST, clock_cfg.systick());
+
+                // todo: Not sure what to set this delay to and how to change it based on variant, so picking
+                // todo something conservative.
+                delay.delay_us(100);
+
+                // This sample time is overkill.
+                // Note that you will need to reset the sample time if you use this channel on this
+                // ADC for something other than reading vref later.
+                self.set_sample_time(VREFINT_CH, SampleTime::T601);
+                let reading = self.read(VREFINT_CH);
+                self.stop_conversions();
+
+                common_regs.ccr.modify(|_, w| w.vrefen().clear_bit());
+
+                // The VDDA power supply voltage applied to the microcontroller may be subject to variation or
+                // not precisely known. The embedded internal voltage reference (VREFINT) and its calibration
+                // data acquired by the ADC during the manufacturing process at VDDA = 3.0 V can be used to
+                // evaluate the actual VDDA voltage level.
+                // The following formula gives the actual VDDA voltage supplying the device:
+                // VDDA = 3.0 V x VREFINT_CAL / VREFINT_DATA
+                // where:
+                // • VREFINT_CAL is the VREFINT calibration value
+                // • VREFINT_DATA is the actual VREFINT output value converted by ADC
+
+                // todo: This address may be different on different MCUs, even within the same family.
+                // Although, it seems relatively consistent. Check User Manuals.
+                let vrefint_cal: u16 = unsafe { ptr::read_volatile(&*(VREFINT_ADDR as *const _)) };
+                self.vdda_calibrated = VREFINT_VOLTAGE * vrefint_cal as f32 / reading as f32;
+
+                self.vdda_calibrated
+            }
+
+            /// Read the internal temperature sensor, and convert to degrees Celsius using the factory
+            /// calibration values. The sensor is only available on ADC1 (ADC3 on H7). For best accuracy,
+            /// run `read_vref()` first, so the reading can be corrected for VDDA.
+            /// See L44 RM, section 16.4.32: "Temperature sensor".
+            pub fn read_temperature(&mut self, clock_cfg: &Clocks) -> f32 {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+
+                // Connect the sensor to its channel, and wake it from power-down. We write raw bits,
+                // since this field's name varies by family. (eg CH17SEL, VSENSESEL, TSEN)
+                common_regs.ccr.modify(|r, w| unsafe { w.bits(r.bits() | CCR_TSEN) });
+
+                // The datasheet specifies a maximum sensor startup time of 120us on most variants.
+                // We busy-wait, instead of using SysTick, which the application may be using.
+                asm::delay(clock_cfg.systick() / 1_000_000 * 120);
+
+                // The sensor requires a minimum sample time of around 5us; use the longest available.
+                self.set_sample_time(TEMP_CH, SampleTime::T601);
+                let reading = self.read(TEMP_CH);
+                self.stop_conversions();
+
+                common_regs.ccr.modify(|r, w| unsafe { w.bits(r.bits() & !CCR_TSEN) });
+
+                // RM: Temperature (in °C) = (TS_CAL2_TEMP – TS_CAL1_TEMP) / (TS_CAL2 – TS_CAL1) ×
+                // (TS_DATA – TS_CAL1) + TS_CAL1_TEMP
+                // The calibration values were taken with VDDA = `VREFINT_VOLTAGE`, so scale the reading
+                // to that, if we've measured VDDA.
+                let ts_cal1: u16 = unsafe { ptr::read_volatile(&*(TS_CAL1_ADDR as *const _)) };
+                let ts_cal2: u16 = unsafe { ptr::read_volatile(&*(TS_CAL2_ADDR as *const _)) };
+
+                let ts_data = if self.vdda_calibrated > 0. {
+                    reading as f32 * self.vdda_calibrated / VREFINT_VOLTAGE
+                } else {
+                    reading as f32
+                };
+
+                (TS_CAL2_TEMP - TS_CAL1_TEMP) / (ts_cal2 as f32 - ts_cal1 as f32)
+                    * (ts_data - ts_cal1 as f32)
+                    + TS_CAL1_TEMP
+            }
+
+            /// Read the backup battery voltage (VBAT), in Volts. This accounts for the internal bridge
+            /// divider, which is only connected during the reading, to avoid draining the battery.
+            /// VBAT is only available on ADC1 (ADC3 on H7). See L44 RM, section 16.4.33.
+            pub fn read_vbat(&mut self) -> f32 {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+
+                // Raw bits, since this field's name varies by family. (eg CH18SEL, VBATSEL, VBATEN)
+                common_regs.ccr.modify(|r, w| unsafe { w.bits(r.bits() | CCR_VBATEN) });
+
+                self.set_sample_time(VBAT_CH, SampleTime::T601);
+                let reading = self.read(VBAT_CH);
+                self.stop_conversions();
+
+                common_regs.ccr.modify(|r, w| unsafe { w.bits(r.bits() & !CCR_VBATEN) });
+
+                self.reading_to_voltage(reading) * VBAT_DIVIDER
+            }
+
             /// Convert a raw measurement into a voltage in Volts, using the calibrated VDDA.
             /// See RM0394, section 16.4.34
             pub fn reading_to_voltage(&self, reading: u16) -> f32 {
@@ -866,6 +1374,94 @@ macro_rules! hal {
                 self.vdda_calibrated / 4_096. * reading as f32
             }
 
+            /// Take a single differential reading. `ch_p` is the positive input channel; the negative
+            /// input is channel `ch_p + 1`. (See the datasheet for which channels support this). Sets
+            /// the channel to differential mode if required. The result is signed, and centered on 0V
+            /// between the inputs. Make sure a differential calibration is applied; this is done by
+            /// `new()`, or with `calibrate(InputType::Differential, ..)`.
+            pub fn read_differential(&mut self, ch_p: u8) -> i16 {
+                if self.regs.difsel.read().bits() & (1 << ch_p) == 0 {
+                    self.set_input_type(ch_p, InputType::Differential);
+                }
+
+                // H7 RM, section 25.4.12: In differential mode, the negative input channel must also
+                // be preselected.
+                #[cfg(feature = "h7")]
+                self.preselect_channel(ch_p + 1);
+
+                // RM: In differential mode, the conversion result is
+                // ADC_DATA = [1 + (V_INP - V_INN) / V_REF+] x 2^(N-1)
+                (self.read(ch_p) as i32 - DIFF_MIDSCALE) as i16
+            }
+
+            /// Convert a signed differential reading, from `read_differential()`, into a voltage
+            /// difference between the inputs, in Volts, using the calibrated VDDA.
+            pub fn differential_reading_to_voltage(&self, reading: i16) -> f32 {
+                self.vdda_calibrated / DIFF_MIDSCALE as f32 * reading as f32
+            }
+
+            #[cfg(feature = "g4")]
+            /// Apply a gain compensation factor to all conversion results, eg to correct an external
+            /// gain error: DATA = DATA_RAW x `coeff` / 4096. `coeff` is a 14-bit value; 4096 is
+            /// unity gain. `None` disables compensation. See G4 RM, section 21.4.29: Gain
+            /// compensation. Sets the `CFGR2` register `GCOMP` field, and `GCOMP` register.
+            pub fn set_gain_compensation(&mut self, coeff: Option<u16>) {
+                // RM: Only allowed when ADSTART = 0 and JADSTART = 0.
+                self.stop_conversions();
+
+                match coeff {
+                    Some(c) => {
+                        self.regs.gcomp.modify(|_, w| unsafe { w.gcompcoeff().bits(c & 0x3fff) });
+                        self.regs.cfgr2.modify(|_, w| w.gcomp().set_bit());
+                    }
+                    None => self.regs.cfgr2.modify(|_, w| w.gcomp().clear_bit()),
+                }
+            }
+
+            /// Configure hardware oversampling, or disable it by passing `None`. This increases
+            /// effective resolution, and reduces noise, at the expense of sample rate, without
+            /// software accumulation. See G4 RM, section 21.4.30: Oversampler. Sets the `CFGR2` register.
+            #[cfg(not(feature = "f3"))]
+            pub fn set_oversampling(&mut self, cfg: Option<OversamplingConfig>) {
+                // RM: Only allowed when ADSTART = 0 and JADSTART = 0.
+                self.stop_conversions();
+
+                // ROVSE (bit 0), JOVSE (bit 1), OVSR, OVSS (bits 8:5), TROVS (bit 9) and ROVSM (bit 10).
+                // OVSR is bits 4:2 as a power of 2 on most families, and bits 25:16 as ratio - 1 on H7.
+                // We use raw bits, since field names vary between PACs.
+                #[cfg(feature = "h7")]
+                const OVSR_MASK: u32 = 0x3ff << 16;
+                #[cfg(not(feature = "h7"))]
+                const OVSR_MASK: u32 = 0b111 << 2;
+                const MASK: u32 = 0b11 | OVSR_MASK | (0b1111 << 5) | (1 << 9) | (1 << 10);
+
+                let bits = match cfg {
+                    Some(c) => {
+                        #[cfg(feature = "h7")]
+                        let ovsr = ((1_u32 << (c.ratio as u32 + 1)) - 1) << 16;
+                        #[cfg(not(feature = "h7"))]
+                        let ovsr = (c.ratio as u32) << 2;
+
+                        let (rovse, jovse, rovsm) = match c.scope {
+                            OversamplingScope::Regular => (true, false, false),
+                            OversamplingScope::RegularResumed => (true, false, true),
+                            OversamplingScope::Injected => (false, true, false),
+                            OversamplingScope::Both => (true, true, false),
+                        };
+
+                        rovse as u32
+                            | (jovse as u32) << 1
+                            | ovsr
+                            | ((c.shift & 0b1111) as u32) << 5
+                            | (c.triggered as u32) << 9
+                            | (rovsm as u32) << 10
+                    }
+                    None => 0,
+                };
+
+                self.regs.cfgr2.modify(|r, w| unsafe { w.bits((r.bits() & !MASK) | bits) });
+            }
+
             /// Start a conversion: Either a single measurement, or continuous conversions.
             /// Blocks until the conversion is complete.
             /// See L4 RM 16.4.15 for details.
@@ -881,6 +1477,9 @@ macro_rules! hal {
                 // • Setting the JADSTART bit in the ADC_CR register (for an injected channel)
                 // • External hardware trigger event (for a regular or injected channel)
                 // (Here, we assume a regular channel)
+                #[cfg(feature = "h7")]
+                self.check_preselection();
+
                 self.regs.cr.modify(|_, w| w.adstart().set_bit());  // Start
 
                 // After the regular sequence is complete, after each conversion is complete,
@@ -914,6 +1513,92 @@ macro_rules! hal {
                 });
             }
 
+            /// Configure the injected group: Up to 4 channels, converted in order, and its trigger.
+            /// Injected conversions preempt the regular sequence; eg for motor current sampling
+            /// synchronized to PWM. Use `TriggerEdge::Software` to start conversions with
+            /// `start_injected_conversion()`. Results are available with `read_injected_result()`
+            /// when the JEOS flag is set. See G4 RM, section 21.4.21: Injected channels management.
+            pub fn set_injected_sequence(&mut self, channels: &[u8], trigger: InjectedTrigger, edge: TriggerEdge) {
+                if channels.is_empty() || channels.len() > 4 {
+                    panic!("The ADC injected sequence must contain 1 to 4 channels.")
+                }
+
+                // RM: JSQR can only be written when JADSTART = 0.
+                self.stop_conversions();
+
+                #[cfg(feature = "h7")]
+                for chan in channels {
+                    self.preselect_channel(*chan);
+                }
+
+                // Unused ranks are left at 0; they're ignored due to JL.
+                let mut ranks = [0; 4];
+                ranks[..channels.len()].copy_from_slice(channels);
+
+                // The JSQR register must be written in a single operation, since on most families,
+                // it feeds the injected context queue.
+                self.regs.jsqr.write(|w| unsafe {
+                    w.jl().bits(channels.len() as u8 - 1);
+                    w.jextsel().bits(trigger as u8);
+                    w.jexten().bits(edge as u8);
+                    w.jsq1().bits(ranks[0]);
+                    w.jsq2().bits(ranks[1]);
+                    w.jsq3().bits(ranks[2]);
+                    w.jsq4().bits(ranks[3])
+                });
+            }
+
+            /// Start injected conversions. If a hardware trigger is selected, conversions start on
+            /// the next trigger; otherwise they start immediately.
+            pub fn start_injected_conversion(&mut self) {
+                self.regs.cr.modify(|_, w| w.jadstart().set_bit());
+            }
+
+            /// Read an injected conversion result, by its rank in the injected sequence. (1 - 4)
+            /// If an offset is applied to the channel, the result is signed; cast it to `i16`.
+            pub fn read_injected_result(&mut self, rank: u8) -> u16 {
+                match rank {
+                    1 => self.regs.jdr1.read().bits() as u16,
+                    2 => self.regs.jdr2.read().bits() as u16,
+                    3 => self.regs.jdr3.read().bits() as u16,
+                    4 => self.regs.jdr4.read().bits() as u16,
+                    _ => panic!("Injected rank out of bounds. Only 4 ranks are available."),
+                }
+            }
+
+            /// Subtract an offset from a channel's conversion results, using one of the four
+            /// offset registers. (`offset_num` is 1 - 4.) Applies to both regular and injected
+            /// conversions. Sets the `ADC_OFRy` register.
+            pub fn set_offset(&mut self, offset_num: u8, channel: u8, offset: u16) {
+                // RM: OFRy can only be written when ADSTART = 0 and JADSTART = 0.
+                self.stop_conversions();
+
+                // OFFSETy_CH is bits 30:26, and OFFSETy starts at bit 0. On families other than
+                // H7, OFFSETy_EN is bit 31; on H7, an offset of 0 disables it.
+                #[cfg(feature = "h7")]
+                let val = ((channel as u32) << 26) | offset as u32;
+                #[cfg(not(feature = "h7"))]
+                let val = (1 << 31) | ((channel as u32) << 26) | (offset as u32 & 0xfff);
+
+                self.write_ofr(offset_num, val);
+            }
+
+            /// Disable one of the four offset registers. (`offset_num` is 1 - 4.)
+            pub fn disable_offset(&mut self, offset_num: u8) {
+                self.stop_conversions();
+                self.write_ofr(offset_num, 0);
+            }
+
+            fn write_ofr(&mut self, offset_num: u8, val: u32) {
+                match offset_num {
+                    1 => self.regs.ofr1.write(|w| unsafe { w.bits(val) }),
+                    2 => self.regs.ofr2.write(|w| unsafe { w.bits(val) }),
+                    3 => self.regs.ofr3.write(|w| unsafe { w.bits(val) }),
+                    4 => self.regs.ofr4.write(|w| unsafe { w.bits(val) }),
+                    _ => panic!("Offset number out of bounds. Only 4 offset registers are available."),
+                }
+            }
+
             #[cfg(not(any(feature = "f4", feature = "l552")))]
             /// Take a reading, using DMA. Sets conversion sequence; no need to set it directly.
             /// Note that the `channel` argument is unused on F3 and L4, since it is hard-coded,
@@ -1060,6 +1745,279 @@ macro_rules! hal {
                 }
             }
 
+            /// Set dual ADC mode. Call this on the master ADC (eg ADC1, or ADC3 for the ADC3/4 pair),
+            /// with both ADCs disabled. `delay` is the delay between the two ADCs' sampling phases in
+            /// interleaved mode, in ADC clock cycles minus 1: 0 - 15. It must be at least the sample
+            /// time, and the slave's conversion must be done before the master's next one.
+            /// Configure the slave ADC's channels and sample times individually; in these modes, it's
+            /// triggered by the master. See G4 RM, section 21.4.31. Sets the `CCR` register `DUAL` and
+            /// `DELAY` fields.
+            pub fn set_multi_mode(&mut self, mode: MultiMode, delay: u8) {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+
+                // RM: The software is allowed to write these bits only when the ADCs are disabled.
+                self.disable();
+
+                // We use raw bits, since these fields' names vary between PACs. (eg `DUAL`, `MULT`)
+                common_regs.ccr.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b1_1111 | 0b1111 << 8)) | mode as u32 | ((delay & 0b1111) as u32) << 8)
+                });
+
+                self.enable();
+            }
+
+            /// Read the master and slave ADCs' last regular conversion results in dual mode, from
+            /// the common data register. Returns `(master, slave)`.
+            pub fn read_multi_result(&self) -> (u16, u16) {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+                let cdr = common_regs.cdr.read().bits();
+
+                (cdr as u16, (cdr >> 16) as u16)
+            }
+
+            #[cfg(not(any(feature = "f4", feature = "l552")))]
+            /// Take readings from a master and slave ADC in dual mode, using DMA. Each word in `buf`
+            /// contains the master's result in its lower 16 bits, and the slave's in the upper 16 bits;
+            /// `buf`'s length is the number of pairs. `adc_channels` is the master's sequence; set up the
+            /// slave's sequence, of the same length, with `set_sequence` and `set_sequence_len` on it.
+            /// Call this on the master ADC, after `set_multi_mode`. Uses the master ADC's DMA request.
+            /// See G4 RM, section 21.4.31: "DMA requests in dual ADC mode". Supports resolutions of
+            /// 10 bits or higher.
+            ///
+            /// # Safety
+            /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete,
+            /// or with a circular channel config, until the DMA channel is stopped.
+            pub unsafe fn read_dma_multi(
+                &mut self, buf: &mut [u32],
+                adc_channels: &[u8],
+                dma_channel: DmaChannel,
+                channel_cfg: ChannelCfg,
+                dma_periph: dma::DmaPeriph,
+            ) {
+                let common_regs = unsafe { &*pac::$ADC_COMMON::ptr() };
+                let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+                self.stop_conversions();
+
+                // MDMA (bits 15:14) = 0b10 transfers both results in one 32-bit word, with DMACFG (bit 13)
+                // selecting circular mode. On H7, this is the DAMDF field, and circular mode is set
+                // in the master's DMNGT field.
+                #[cfg(not(feature = "h7"))]
+                common_regs.ccr.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b111 << 13))
+                        | 0b10 << 14
+                        | ((channel_cfg.circular == dma::Circular::Enabled) as u32) << 13)
+                });
+
+                #[cfg(feature = "h7")]
+                {
+                    common_regs.ccr.modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 14)) | 0b10 << 14) });
+                    self.regs.cfgr.modify(|_, w| {
+                        w.dmngt().bits(if channel_cfg.circular == dma::Circular::Enabled { 0b11 } else { 0b01 })
+                    });
+                }
+
+                #[cfg(any(feature = "f3", feature = "l4"))]
+                let dma_channel = DmaInput::Adc1.dma1_channel();
+
+                #[cfg(feature = "l4")]
+                match dma_periph {
+                    dma::DmaPeriph::Dma1 => {
+                        let mut regs = unsafe { &(*pac::DMA1::ptr()) };
+                        dma::channel_select(&mut regs, DmaInput::Adc1);
+                    }
+                    dma::DmaPeriph::Dma2 => {
+                        let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                        dma::channel_select(&mut regs, DmaInput::Adc1);
+                    }
+                }
+
+                let mut seq_len = 0;
+                for (i, ch) in adc_channels.iter().enumerate() {
+                    self.set_sequence(*ch, i as u8 + 1);
+                    seq_len += 1;
+                }
+                self.set_sequence_len(seq_len);
+
+                // In dual mode, starting the master also starts the slave.
+                self.regs.cr.modify(|_, w| w.adstart().set_bit());
+
+                #[cfg(feature = "h7")]
+                let num_data = len as u32;
+                #[cfg(not(feature = "h7"))]
+                let num_data = len as u16;
+
+                match dma_periph {
+                    dma::DmaPeriph::Dma1 => {
+                        let mut regs = unsafe { &(*pac::DMA1::ptr()) };
+                        dma::cfg_channel(
+                            &mut regs,
+                            dma_channel,
+                            &common_regs.cdr as *const _ as u32,
+                            ptr as u32,
+                            num_data,
+                            dma::Direction::ReadFromPeriph,
+                            dma::DataSize::S32,
+                            dma::DataSize::S32,
+                            channel_cfg,
+                        );
+                    }
+                    #[cfg(not(feature = "g0"))]
+                    dma::DmaPeriph::Dma2 => {
+                        let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                        dma::cfg_channel(
+                            &mut regs,
+                            dma_channel,
+                            &common_regs.cdr as *const _ as u32,
+                            ptr as u32,
+                            num_data,
+                            dma::Direction::ReadFromPeriph,
+                            dma::DataSize::S32,
+                            dma::DataSize::S32,
+                            channel_cfg,
+                        );
+                    }
+                }
+            }
+
+            /// Configure and enable an analog watchdog. It triggers when a monitored channel's
+            /// conversion result is below `low` or above `high`. Thresholds are in conversion result
+            /// units, before alignment. On families other than H7, AWD2 and AWD3 only compare the 8
+            /// MSBs of a 12-bit result, so the 4 LSBs of `low` and `high` are ignored.
+            /// Enable the interrupt with `enable_interrupt(AdcInterrupt::Watchdog1)` etc, and check
+            /// flags with `take_watchdog_flags()`. See G4 RM, section 21.4.28: Analog window watchdog.
+            pub fn set_watchdog(&mut self, watchdog: AnalogWatchdog, channels: WatchdogChannels, low: u16, high: u16) {
+                // RM: AWDxCH, AWDxSGL, AWDxEN, and the thresholds can only be changed when
+                // ADSTART = 0 and JADSTART = 0.
+                self.stop_conversions();
+
+                match watchdog {
+                    AnalogWatchdog::One => {
+                        #[cfg(feature = "h7")]
+                        {
+                            self.regs.ltr1.write(|w| unsafe { w.bits(low as u32) });
+                            self.regs.htr1.write(|w| unsafe { w.bits(high as u32) });
+                        }
+                        #[cfg(not(feature = "h7"))]
+                        self.regs.tr1.modify(|_, w| unsafe {
+                            w.lt1().bits(low);
+                            w.ht1().bits(high)
+                        });
+
+                        let (single, chan) = match channels {
+                            WatchdogChannels::All => (false, 0),
+                            WatchdogChannels::Single(chan) => {
+                                #[cfg(feature = "h7")]
+                                self.preselect_channel(chan);
+                                (true, chan)
+                            }
+                            WatchdogChannels::Mask(_) => panic!("AWD1 can only monitor a single channel, or all channels."),
+                        };
+
+                        cfg_if! {
+                            // The L4 and L5 PACs are missing the AWD1CH field: CFGR bits 30:26.
+                            if #[cfg(any(feature = "l4", feature = "l5"))] {
+                                self.regs.cfgr.modify(|r, w| unsafe {
+                                    w.bits((r.bits() & !(0x1f << 26)) | ((chan as u32) << 26));
+                                    w.awd1sgl().bit(single);
+                                    w.awd1en().set_bit()
+                                });
+                            } else {
+                                self.regs.cfgr.modify(|_, w| unsafe {
+                                    w.awd1sgl().bit(single);
+                                    w.awd1ch().bits(chan);
+                                    w.awd1en().set_bit()
+                                });
+                            }
+                        }
+                    }
+                    AnalogWatchdog::Two | AnalogWatchdog::Three => {
+                        let mask = match channels {
+                            // AWD2CR and AWD3CR have a bit per channel: 20 on H7, and 19 elsewhere.
+                            #[cfg(feature = "h7")]
+                            WatchdogChannels::All => 0xf_ffff,
+                            #[cfg(not(feature = "h7"))]
+                            WatchdogChannels::All => 0x7_ffff,
+                            WatchdogChannels::Single(chan) => 1 << chan,
+                            WatchdogChannels::Mask(mask) => mask,
+                        };
+
+                        cfg_if! {
+                            if #[cfg(feature = "h7")] {
+                                if watchdog == AnalogWatchdog::Two {
+                                    self.regs.ltr2.write(|w| unsafe { w.bits(low as u32) });
+                                    self.regs.htr2.write(|w| unsafe { w.bits(high as u32) });
+                                } else {
+                                    self.regs.ltr3.write(|w| unsafe { w.bits(low as u32) });
+                                    self.regs.htr3.write(|w| unsafe { w.bits(high as u32) });
+                                }
+                            } else {
+                                let (low, high) = ((low >> 4) as u8, (high >> 4) as u8);
+                                if watchdog == AnalogWatchdog::Two {
+                                    self.regs.tr2.modify(|_, w| unsafe {
+                                        w.lt2().bits(low);
+                                        w.ht2().bits(high)
+                                    });
+                                } else {
+                                    self.regs.tr3.modify(|_, w| unsafe {
+                                        w.lt3().bits(low);
+                                        w.ht3().bits(high)
+                                    });
+                                }
+                            }
+                        }
+
+                        // Setting any AWDxCH bit enables AWD2 or AWD3.
+                        if watchdog == AnalogWatchdog::Two {
+                            self.regs.awd2cr.write(|w| unsafe { w.bits(mask) });
+                        } else {
+                            self.regs.awd3cr.write(|w| unsafe { w.bits(mask) });
+                        }
+                    }
+                }
+            }
+
+            /// Disable an analog watchdog.
+            pub fn disable_watchdog(&mut self, watchdog: AnalogWatchdog) {
+                self.stop_conversions();
+
+                match watchdog {
+                    AnalogWatchdog::One => self.regs.cfgr.modify(|_, w| w.awd1en().clear_bit()),
+                    AnalogWatchdog::Two => self.regs.awd2cr.write(|w| unsafe { w.bits(0) }),
+                    AnalogWatchdog::Three => self.regs.awd3cr.write(|w| unsafe { w.bits(0) }),
+                }
+            }
+
+            /// Check if an analog watchdog has triggered, without clearing its flag.
+            pub fn watchdog_triggered(&self, watchdog: AnalogWatchdog) -> bool {
+                let isr = self.regs.isr.read();
+                match watchdog {
+                    AnalogWatchdog::One => isr.awd1().bit_is_set(),
+                    AnalogWatchdog::Two => isr.awd2().bit_is_set(),
+                    AnalogWatchdog::Three => isr.awd3().bit_is_set(),
+                }
+            }
+
+            /// Read and clear all analog watchdog flags. Useful in the ADC ISR, to find which
+            /// watchdog(s) fired, and act on each.
+            pub fn take_watchdog_flags(&mut self) -> WatchdogFlags {
+                let isr = self.regs.isr.read();
+                let flags = WatchdogFlags {
+                    awd1: isr.awd1().bit_is_set(),
+                    awd2: isr.awd2().bit_is_set(),
+                    awd3: isr.awd3().bit_is_set(),
+                };
+
+                // These are write-1-to-clear; only clear the ones we read as set.
+                self.regs.isr.write(|w| {
+                    w.awd1().bit(flags.awd1);
+                    w.awd2().bit(flags.awd2);
+                    w.awd3().bit(flags.awd3)
+                });
+
+                flags
+            }
+
             /// Enable a specific type of ADC interrupt.
             pub fn enable_interrupt(&mut self, interrupt: AdcInterrupt) {
                 self.regs.ier.modify(|_, w| match interrupt {
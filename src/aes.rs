@@ -0,0 +1,723 @@
+//! Support for the AES coprocessor, in GCM (Galois/counter mode), for authenticated encryption.
+//! `Aes` exposes the GCM phases directly. `GcmLink` builds on it to seal, and open frames for an
+//! encrypted link, such as telemetry over a UART, SPI, or radio, using DMA between the AES and
+//! the frame buffer.
+//!
+//! Frames are encrypted, and decrypted in place, so an outbound frame can be assembled in the
+//! UART or SPI TX buffer, sealed there, and sent from it with `write_dma()`, with no intermediate
+//! copies. Similarly, an inbound frame is opened in the buffer it was received in.
+//!
+//! For DMA, run `dma::mux()` with `DmaInput::AesIn` on the input channel, and `DmaInput::AesOut`
+//! on the output channel first.
+//!
+//! See G4 RM, chapter 33: "AES hardware accelerator (AES)", or L552 RM, chapter 41 (L562 only).
+//!
+//! Example, sealing a frame, then sending it over a UART:
+//! ```rust
+//! let mut aes = Aes::new();
+//! let mut link = GcmLink::new(&KEY, SALT);
+//!
+//! // `TX_BUF` is word-aligned, and has room for the frame overhead, and block padding.
+//! let payload_len = encode_telemetry(&mut TX_BUF[FRAME_HEADER_LEN..]);
+//!
+//! unsafe {
+//!     link.seal_start(&mut aes, &mut TX_BUF, payload_len, DmaChannel::C1, DmaChannel::C2,
+//!         Default::default(), DmaPeriph::Dma1).unwrap();
+//! }
+//!
+//! // In the DMA1 channel 2 (AES output) transfer-complete interrupt:
+//! let frame_len = link.seal_finish(&mut aes, &mut TX_BUF, payload_len);
+//! unsafe { uart.write_dma(&TX_BUF[..frame_len], DmaChannel::C3, Default::default(),
+//!     DmaPeriph::Dma1) };
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel, DmaPeriph},
+    pac::RCC,
+};
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
+        use crate::pac::DMA as DMA1;
+    } else {
+        use crate::pac::{DMA1, DMA2};
+    }
+}
+
+// We use raw pointers, since the AES is missing from some PACs.
+cfg_if! {
+    if #[cfg(feature = "l5")] {
+        const AES_BASE: usize = 0x420c_0000;
+    } else if #[cfg(feature = "g0")] {
+        const AES_BASE: usize = 0x4002_6000;
+    } else if #[cfg(feature = "wl")] {
+        const AES_BASE: usize = 0x5800_1800;
+    } else {
+        // G4, and WB (AES1).
+        const AES_BASE: usize = 0x5006_0000;
+    }
+}
+
+// Register offsets.
+const CR: usize = 0x00;
+const SR: usize = 0x04;
+const DINR: usize = 0x08;
+const DOUTR: usize = 0x0c;
+const KEYR0: usize = 0x10;
+const IVR0: usize = 0x20;
+const KEYR4: usize = 0x30;
+
+// AES_CR fields.
+const EN: u32 = 1 << 0;
+/// DATATYPE = 0b10: 8-bit data, byte-swapped, so words written from little-endian byte slices
+/// are processed in order.
+const DATATYPE_BYTES: u32 = 0b10 << 1;
+const MODE_DECRYPT: u32 = 0b10 << 3;
+/// CHMOD = 0b011: GCM.
+const CHMOD_GCM: u32 = 0b011 << 5;
+const CCFC: u32 = 1 << 7;
+const ERRC: u32 = 1 << 8;
+const DMAINEN: u32 = 1 << 11;
+const DMAOUTEN: u32 = 1 << 12;
+const GCMPH_SHIFT: u8 = 13;
+const GCMPH_MASK: u32 = 0b11 << GCMPH_SHIFT;
+const KEYSIZE_256: u32 = 1 << 18;
+const NPBLB_SHIFT: u8 = 20;
+const NPBLB_MASK: u32 = 0b1111 << NPBLB_SHIFT;
+
+// AES_SR fields.
+const CCF: u32 = 1 << 0;
+const RDERR: u32 = 1 << 1;
+const WRERR: u32 = 1 << 2;
+
+/// The AES block size, in bytes.
+pub const BLOCK_LEN: usize = 16;
+/// The GCM authentication tag length, in bytes.
+pub const TAG_LEN: usize = 16;
+/// The GCM initialization vector length, in bytes.
+pub const IV_LEN: usize = 12;
+
+/// The length of the frame counter, which precedes the ciphertext in `GcmLink` frames.
+pub const FRAME_HEADER_LEN: usize = 8;
+/// The bytes `GcmLink` frames add to the payload: The counter, and tag.
+pub const FRAME_OVERHEAD: usize = FRAME_HEADER_LEN + TAG_LEN;
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The computed tag doesn't match the received one; the frame was corrupted, or forged.
+    AuthFailed,
+    /// The frame counter isn't newer than the last accepted one.
+    Replayed,
+    /// The frame is too short, or its buffer is too small for block padding.
+    InvalidFrame,
+    /// A read or write error occurred during a computation. (AES_SR register, RDERR, or WRERR
+    /// field)
+    Access,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Encrypt, or decrypt. Sets AES_CR register, MODE field.
+pub enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// GCM processing phase. Sets AES_CR register, GCMPH field.
+enum Phase {
+    Init = 0b00,
+    Header = 0b01,
+    Payload = 0b10,
+    Final = 0b11,
+}
+
+/// Round `len` up to a whole number of AES blocks.
+pub const fn padded_len(len: usize) -> usize {
+    len.div_ceil(BLOCK_LEN) * BLOCK_LEN
+}
+
+/// Represents the AES coprocessor.
+pub struct Aes {
+    direction: Direction,
+    /// Header (additional authenticated data) length of the current message, in bytes.
+    aad_len: u64,
+    /// Payload length of the current message, in bytes.
+    payload_len: u64,
+    /// Set once a phase receives a partial block; no more data can follow in that phase.
+    partial_fed: bool,
+}
+
+impl Aes {
+    /// Enable and reset the AES's RCC peripheral clock.
+    pub fn new() -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // We use raw bits, since AES RCC fields are missing from some PACs.
+            // G0: AHBENR, and AHBRSTR, bit 16. WL: AHB3ENR, and AHB3RSTR, bit 17.
+            // L5, G4, and WB: AHB2ENR, and AHB2RSTR, bit 16.
+            cfg_if! {
+                if #[cfg(feature = "g0")] {
+                    const BIT: u32 = 1 << 16;
+                    rcc.ahbenr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahbrstr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahbrstr.modify(|r, w| unsafe { w.bits(r.bits() & !BIT) });
+                } else if #[cfg(feature = "wl")] {
+                    const BIT: u32 = 1 << 17;
+                    rcc.ahb3enr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahb3rstr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahb3rstr.modify(|r, w| unsafe { w.bits(r.bits() & !BIT) });
+                } else {
+                    const BIT: u32 = 1 << 16;
+                    rcc.ahb2enr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahb2rstr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+                    rcc.ahb2rstr.modify(|r, w| unsafe { w.bits(r.bits() & !BIT) });
+                }
+            }
+        });
+
+        Self {
+            direction: Direction::Encrypt,
+            aad_len: 0,
+            payload_len: 0,
+            partial_fed: false,
+        }
+    }
+
+    /// Start a GCM message: Load the key, and IV, and compute the hash subkey (init phase). `key`
+    /// must be 16, or 32 bytes long, for AES-128, or AES-256. Follow with `aad()`, if there's
+    /// header data, then `payload()`, or `payload_dma()`, then `finish()`. Blocking.
+    pub fn start_gcm(&mut self, key: &[u8], iv: &[u8; IV_LEN], direction: Direction) {
+        assert!(
+            key.len() == 16 || key.len() == 32,
+            "The AES key must be 16, or 32 bytes long."
+        );
+
+        self.direction = direction;
+        self.aad_len = 0;
+        self.payload_len = 0;
+        self.partial_fed = false;
+
+        // Configuration can only be changed with the peripheral disabled.
+        self.write_reg(CR, CCFC | ERRC);
+
+        let mut cr = DATATYPE_BYTES | CHMOD_GCM | (Phase::Init as u32) << GCMPH_SHIFT;
+        if direction == Direction::Decrypt {
+            cr |= MODE_DECRYPT;
+        }
+        if key.len() == 32 {
+            cr |= KEYSIZE_256;
+        }
+        self.write_reg(CR, cr);
+
+        // The key, and IV registers are big-endian: The first bytes go in the highest register.
+        // For 256-bit keys, the first half goes in KEYR7 to KEYR4.
+        let mut words = key.chunks_exact(4).rev();
+        for i in 0..4 {
+            self.write_reg(KEYR0 + 4 * i, be_word(words.next().unwrap()));
+        }
+        for i in 0..4 {
+            if let Some(word) = words.next() {
+                self.write_reg(KEYR4 + 4 * i, be_word(word));
+            }
+        }
+
+        // The counter's initial value is 2; 1 is reserved for encrypting the tag.
+        self.write_reg(IVR0, 2);
+        for (i, word) in iv.chunks_exact(4).rev().enumerate() {
+            self.write_reg(IVR0 + 4 * (i + 1), be_word(word));
+        }
+
+        // `EN` clears automatically when the init phase completes.
+        self.write_reg(CR, cr | EN);
+        self.wait_ccf();
+    }
+
+    /// Feed header data (additional authenticated data), which is authenticated, but not
+    /// encrypted. Can be called any number of times, but only the last call's length can be a
+    /// partial block. Blocking.
+    pub fn aad(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if self.aad_len == 0 {
+            self.set_phase(Phase::Header);
+        }
+        assert!(
+            !self.partial_fed && self.payload_len == 0,
+            "Header data must be fed in whole blocks, and before the payload."
+        );
+        self.aad_len += data.len() as u64;
+
+        for block in data.chunks(BLOCK_LEN) {
+            self.write_block(block);
+            self.wait_ccf();
+        }
+        self.partial_fed = !data.len().is_multiple_of(BLOCK_LEN);
+    }
+
+    /// Encrypt, or decrypt payload data from `input` into `output`, which must be at least as
+    /// long. Can be called any number of times, but only the last call's length can be a
+    /// partial block. Blocking.
+    pub fn payload(&mut self, input: &[u8], output: &mut [u8]) {
+        assert!(
+            output.len() >= input.len(),
+            "The AES output buffer is too small."
+        );
+        if input.is_empty() {
+            return;
+        }
+        self.begin_payload(input.len());
+
+        for (block, out) in input.chunks(BLOCK_LEN).zip(output.chunks_mut(BLOCK_LEN)) {
+            self.write_block(block);
+            self.wait_ccf();
+
+            // Each block's output must be read in full, even if we only keep part of it.
+            let mut result = [0; BLOCK_LEN];
+            for chunk in result.chunks_exact_mut(4) {
+                chunk.copy_from_slice(&self.read_reg(DOUTR).to_le_bytes());
+            }
+            out.copy_from_slice(&result[..out.len()]);
+        }
+    }
+
+    /// Encrypt, or decrypt `len` bytes of `buf` in place, using DMA. `buf` must be word-aligned,
+    /// and at least `len` rounded up to a whole block; the padding bytes are zeroed before the
+    /// transfer, and hold discarded output after it. Call `finish()` once the output channel's
+    /// transfer is complete. This can only be used once per message, with no preceding
+    /// `payload()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until both DMA transfers are
+    /// complete.
+    pub unsafe fn payload_dma(
+        &mut self,
+        buf: &mut [u8],
+        len: usize,
+        channel_in: DmaChannel,
+        channel_out: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        let padded = padded_len(len);
+        assert!(
+            (buf.as_ptr() as usize).is_multiple_of(4),
+            "The AES DMA buffer must be word-aligned."
+        );
+        assert!(
+            buf.len() >= padded,
+            "The AES DMA buffer must have room for block padding."
+        );
+        assert!(self.payload_len == 0, "Don't mix `payload()`, and DMA.");
+        if len == 0 {
+            return;
+        }
+
+        buf[len..padded].fill(0);
+        self.begin_payload(len);
+
+        let ptr = buf.as_mut_ptr() as u32;
+
+        // Configure the output channel first, so it's ready when the first block completes.
+        cfg_dma(
+            ptr,
+            padded / 4,
+            DOUTR,
+            dma::Direction::ReadFromPeriph,
+            channel_out,
+            channel_cfg.clone(),
+            dma_periph,
+        );
+        cfg_dma(
+            ptr,
+            padded / 4,
+            DINR,
+            dma::Direction::ReadFromMem,
+            channel_in,
+            channel_cfg,
+            dma_periph,
+        );
+
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | DMAINEN | DMAOUTEN);
+    }
+
+    /// Complete the message (final phase), and write the authentication tag to `tag`. After
+    /// decrypting, compare it to the received tag with `tags_match()` before trusting the
+    /// plaintext. Blocking.
+    pub fn finish(&mut self, tag: &mut [u8; TAG_LEN]) {
+        let cr = self.read_reg(CR) & !(DMAINEN | DMAOUTEN | NPBLB_MASK);
+        // The tag is computed with the peripheral in encryption mode, for both directions.
+        self.write_reg(CR, cr & !MODE_DECRYPT);
+        self.set_phase(Phase::Final);
+
+        // Bit lengths of the header, and payload, as 64-bit big-endian values. These aren't
+        // byte-swapped by the peripheral like data, so we swap them here.
+        let aad_bits = self.aad_len * 8;
+        let payload_bits = self.payload_len * 8;
+        self.write_reg(DINR, ((aad_bits >> 32) as u32).swap_bytes());
+        self.write_reg(DINR, (aad_bits as u32).swap_bytes());
+        self.write_reg(DINR, ((payload_bits >> 32) as u32).swap_bytes());
+        self.write_reg(DINR, (payload_bits as u32).swap_bytes());
+
+        self.wait_ccf();
+
+        for chunk in tag.chunks_exact_mut(4) {
+            chunk.copy_from_slice(&self.read_reg(DOUTR).to_le_bytes());
+        }
+
+        self.write_reg(CR, 0);
+    }
+
+    /// Check for, and clear read, and write errors, eg from accessing the data registers during
+    /// a computation.
+    pub fn check_errors(&mut self) -> Result<(), Error> {
+        let sr = self.read_reg(SR);
+        if sr & (RDERR | WRERR) != 0 {
+            let cr = self.read_reg(CR);
+            self.write_reg(CR, cr | ERRC);
+            return Err(Error::Access);
+        }
+        Ok(())
+    }
+
+    /// Set up the payload phase for `len` more bytes.
+    fn begin_payload(&mut self, len: usize) {
+        if self.payload_len == 0 {
+            self.partial_fed = false;
+            self.set_phase(Phase::Payload);
+        }
+        assert!(
+            !self.partial_fed,
+            "Only the last payload call can have a partial block."
+        );
+        self.payload_len += len as u64;
+
+        let padding = (padded_len(len) - len) as u32;
+        if padding != 0 {
+            self.partial_fed = true;
+            // When encrypting, the peripheral must exclude the padding bytes from the tag.
+            // Decryption is authenticated over the zero-padded ciphertext, so it doesn't need this.
+            if self.direction == Direction::Encrypt {
+                let cr = self.read_reg(CR) & !NPBLB_MASK;
+                self.write_reg(CR, cr | padding << NPBLB_SHIFT);
+            }
+        }
+    }
+
+    /// Change the GCM phase, and enable the peripheral, if the previous phase disabled it.
+    fn set_phase(&mut self, phase: Phase) {
+        let cr = self.read_reg(CR) & !GCMPH_MASK;
+        self.write_reg(CR, cr | (phase as u32) << GCMPH_SHIFT | EN);
+    }
+
+    /// Write up to one block to the data input register, zero-padding a partial block.
+    fn write_block(&mut self, block: &[u8]) {
+        let mut padded = [0; BLOCK_LEN];
+        padded[..block.len()].copy_from_slice(block);
+
+        for word in padded.chunks_exact(4) {
+            self.write_reg(
+                DINR,
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+            );
+        }
+    }
+
+    /// Wait for the computation complete flag, then clear it.
+    fn wait_ccf(&mut self) {
+        while self.read_reg(SR) & CCF == 0 {}
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | CCFC);
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((AES_BASE + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((AES_BASE + offset) as *mut u32, value) }
+    }
+}
+
+impl Default for Aes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert 4 bytes to a word for the key, and IV registers, which are big-endian.
+fn be_word(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Compare two tags in constant time, so a forger can't learn how many leading bytes matched.
+pub fn tags_match(a: &[u8; TAG_LEN], b: &[u8; TAG_LEN]) -> bool {
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Seals, and opens frames for one end of an encrypted link. Frames are laid out as
+/// `[counter: 8 bytes, big-endian][ciphertext][tag: 16 bytes]`. The counter is authenticated as
+/// header data, and forms the IV with a 4-byte salt shared by both ends, so each frame uses a
+/// unique IV. Received frames with counters that aren't newer than the last accepted one are
+/// rejected, to prevent replays.
+///
+/// Use a different key, or salt for each direction of the link; otherwise, frames from both
+/// ends could reuse IVs.
+pub struct GcmLink {
+    key: [u8; 32],
+    key_len: usize,
+    salt: [u8; 4],
+    /// The counter for the next sealed frame.
+    tx_counter: u64,
+    /// The counter of the last opened frame, if any.
+    rx_counter: Option<u64>,
+    /// The counter, and tag of the frame being opened, between `open_start()`, and
+    /// `open_finish()`. We save the tag, since it may be overwritten by block padding.
+    pending_rx: Option<(u64, [u8; TAG_LEN])>,
+}
+
+impl GcmLink {
+    /// Create a link endpoint. `key` must be 16, or 32 bytes long.
+    pub fn new(key: &[u8], salt: [u8; 4]) -> Self {
+        assert!(
+            key.len() == 16 || key.len() == 32,
+            "The AES key must be 16, or 32 bytes long."
+        );
+
+        let mut key_buf = [0; 32];
+        key_buf[..key.len()].copy_from_slice(key);
+
+        Self {
+            key: key_buf,
+            key_len: key.len(),
+            salt,
+            tx_counter: 0,
+            rx_counter: None,
+            pending_rx: None,
+        }
+    }
+
+    /// The buffer size needed for a frame with a `payload_len`-byte payload. Block padding
+    /// always fits in the tag's space.
+    pub const fn frame_buf_len(payload_len: usize) -> usize {
+        payload_len + FRAME_OVERHEAD
+    }
+
+    /// Set the counter for the next sealed frame, eg to resume from one stored in backup
+    /// registers after a reset. Never reuse a counter with the same key.
+    pub fn set_tx_counter(&mut self, counter: u64) {
+        self.tx_counter = counter;
+    }
+
+    /// The counter the next sealed frame will use.
+    pub fn tx_counter(&self) -> u64 {
+        self.tx_counter
+    }
+
+    fn iv(&self, counter: u64) -> [u8; IV_LEN] {
+        let mut iv = [0; IV_LEN];
+        iv[..4].copy_from_slice(&self.salt);
+        iv[4..].copy_from_slice(&counter.to_be_bytes());
+        iv
+    }
+
+    /// Start sealing a frame whose plaintext payload is at `frame[FRAME_HEADER_LEN..]`, `len`
+    /// bytes long. Writes the counter, and encrypts the payload in place using DMA. `frame`
+    /// must be word-aligned, and at least `frame_buf_len(len)` long. Call `seal_finish()` once
+    /// the output channel's transfer is complete.
+    ///
+    /// # Safety
+    /// `frame` must stay valid, and not be otherwise accessed, until both DMA transfers are
+    /// complete.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn seal_start(
+        &mut self,
+        aes: &mut Aes,
+        frame: &mut [u8],
+        len: usize,
+        channel_in: DmaChannel,
+        channel_out: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) -> Result<(), Error> {
+        if frame.len() < Self::frame_buf_len(len) {
+            return Err(Error::InvalidFrame);
+        }
+
+        let counter = self.tx_counter;
+        self.tx_counter += 1;
+
+        let header = counter.to_be_bytes();
+        frame[..FRAME_HEADER_LEN].copy_from_slice(&header);
+
+        aes.start_gcm(
+            &self.key[..self.key_len],
+            &self.iv(counter),
+            Direction::Encrypt,
+        );
+        aes.aad(&header);
+        aes.payload_dma(
+            &mut frame[FRAME_HEADER_LEN..],
+            len,
+            channel_in,
+            channel_out,
+            channel_cfg,
+            dma_periph,
+        );
+
+        Ok(())
+    }
+
+    /// Finish sealing a frame started with `seal_start()`: Append the tag. Returns the frame's
+    /// length; send `frame[..len]`.
+    pub fn seal_finish(&mut self, aes: &mut Aes, frame: &mut [u8], len: usize) -> usize {
+        let mut tag = [0; TAG_LEN];
+        aes.finish(&mut tag);
+
+        let tag_start = FRAME_HEADER_LEN + len;
+        frame[tag_start..tag_start + TAG_LEN].copy_from_slice(&tag);
+
+        tag_start + TAG_LEN
+    }
+
+    /// Start opening a received frame, `frame_len` bytes long, decrypting it in place using DMA.
+    /// `frame` must be word-aligned, and at least `frame_buf_len()` of the payload length; the
+    /// bytes after the payload are overwritten. Call `open_finish()` once the output channel's
+    /// transfer is complete.
+    ///
+    /// # Safety
+    /// `frame` must stay valid, and not be otherwise accessed, until both DMA transfers are
+    /// complete.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn open_start(
+        &mut self,
+        aes: &mut Aes,
+        frame: &mut [u8],
+        frame_len: usize,
+        channel_in: DmaChannel,
+        channel_out: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) -> Result<(), Error> {
+        if frame_len < FRAME_OVERHEAD || frame_len > frame.len() {
+            return Err(Error::InvalidFrame);
+        }
+        let len = frame_len - FRAME_OVERHEAD;
+        if frame.len() < Self::frame_buf_len(len) {
+            return Err(Error::InvalidFrame);
+        }
+
+        let mut header = [0; FRAME_HEADER_LEN];
+        header.copy_from_slice(&frame[..FRAME_HEADER_LEN]);
+        let counter = u64::from_be_bytes(header);
+
+        if let Some(last) = self.rx_counter {
+            if counter <= last {
+                return Err(Error::Replayed);
+            }
+        }
+
+        let mut tag = [0; TAG_LEN];
+        tag.copy_from_slice(&frame[frame_len - TAG_LEN..frame_len]);
+        self.pending_rx = Some((counter, tag));
+
+        aes.start_gcm(
+            &self.key[..self.key_len],
+            &self.iv(counter),
+            Direction::Decrypt,
+        );
+        aes.aad(&header);
+        aes.payload_dma(
+            &mut frame[FRAME_HEADER_LEN..],
+            len,
+            channel_in,
+            channel_out,
+            channel_cfg,
+            dma_periph,
+        );
+
+        Ok(())
+    }
+
+    /// Finish opening a frame started with `open_start()`: Verify its tag. On success, returns
+    /// the plaintext payload's length; it's at `frame[FRAME_HEADER_LEN..]`. On failure, discard
+    /// the frame's contents.
+    pub fn open_finish(&mut self, aes: &mut Aes) -> Result<usize, Error> {
+        let (counter, received) = self.pending_rx.take().ok_or(Error::InvalidFrame)?;
+
+        let mut tag = [0; TAG_LEN];
+        aes.finish(&mut tag);
+
+        if !tags_match(&tag, &received) {
+            return Err(Error::AuthFailed);
+        }
+
+        self.rx_counter = Some(counter);
+        Ok(aes.payload_len as usize)
+    }
+}
+
+/// Configure a DMA transfer between memory, and an AES data register.
+fn cfg_dma(
+    mem_addr: u32,
+    len: usize,
+    offset: usize,
+    direction: dma::Direction,
+    channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    dma_periph: DmaPeriph,
+) {
+    #[cfg(feature = "h7")]
+    let len = len as u32;
+    #[cfg(not(feature = "h7"))]
+    let len = len as u16;
+
+    let periph_addr = (AES_BASE + offset) as u32;
+
+    match dma_periph {
+        DmaPeriph::Dma1 => {
+            let mut regs = unsafe { &(*DMA1::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S32,
+                dma::DataSize::S32,
+                channel_cfg,
+            );
+        }
+        #[cfg(not(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))))]
+        DmaPeriph::Dma2 => {
+            // On WB and G0C1, DMA2's register block is a separate type with DMA1's channel
+            // layout.
+            #[cfg(any(feature = "wb", feature = "g0c1"))]
+            let mut regs = unsafe { &*(DMA2::ptr() as *const crate::pac::dma1::RegisterBlock) };
+            #[cfg(not(any(feature = "wb", feature = "g0c1")))]
+            let mut regs = unsafe { &(*DMA2::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S32,
+                dma::DataSize::S32,
+                channel_cfg,
+            );
+        }
+    }
+}
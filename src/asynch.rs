@@ -0,0 +1,102 @@
+//! Support for async/await, eg with Embassy-style executors. This module contains `Signal`, which
+//! connects an interrupt handler to a waiting task. The `I2cAsync`, `SpiAsync`, and `UsartAsync`
+//! types in their respective modules use it to implement the `embedded-hal-async`, and
+//! `embedded-io-async` traits, driven by peripheral interrupts. Enabled with the `async` feature.
+//!
+//! Each peripheral has its own `Signal`, created as a `static`. The peripheral's ISR masks its
+//! interrupts, and signals the task, using the module's `on_interrupt_async()` function. A
+//! `Signal` can also be used directly, eg to await a DMA transfer complete interrupt.
+//!
+//! Example:
+//! ```rust
+//! static I2C1_SIGNAL: Signal = Signal::new();
+//!
+//! #[interrupt]
+//! fn I2C1_EV() {
+//!     i2c::on_interrupt_async(unsafe { &*pac::I2C1::ptr() }, &I2C1_SIGNAL);
+//! }
+//!
+//! // In an async task:
+//! let mut i2c = I2cAsync::new(I2c::new(dp.I2C1, Default::default(), &clock_cfg), &I2C1_SIGNAL);
+//! i2c.write_read(ADDR, &[REG], &mut buf).await?;
+//!
+//! // Awaiting a DMA transfer:
+//! static DMA1_CH1_SIGNAL: Signal = Signal::new();
+//!
+//! #[interrupt]
+//! fn DMA1_CH1() {
+//!     dma::clear_interrupt(DmaPeriph::Dma1, DmaChannel::C1, DmaInterrupt::TransferComplete);
+//!     DMA1_CH1_SIGNAL.signal();
+//! }
+//!
+//! DMA1_CH1_SIGNAL.reset();
+//! unsafe { spi.write_dma(&BUF, DmaChannel::C1, Default::default(), DmaPeriph::Dma1) };
+//! DMA1_CH1_SIGNAL.wait().await;
+//! ```
+
+use core::{
+    cell::{Cell, RefCell},
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use cortex_m::interrupt::{free, Mutex};
+
+/// Wakes a task waiting on an interrupt. The signaled state is latched, so a signal raised before
+/// the task starts waiting isn't lost. We use critical sections instead of atomics, since
+/// Cortex-M0+ (G0) doesn't support atomic read-modify-write operations.
+pub struct Signal {
+    signaled: Mutex<Cell<bool>>,
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signal {
+    /// Create a signal, in the unsignaled state.
+    pub const fn new() -> Self {
+        Self {
+            signaled: Mutex::new(Cell::new(false)),
+            waker: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Signal, and wake the waiting task, if any. Call this from an ISR.
+    pub fn signal(&self) {
+        free(|cs| {
+            self.signaled.borrow(cs).set(true);
+
+            if let Some(waker) = self.waker.borrow(cs).borrow_mut().take() {
+                waker.wake();
+            }
+        });
+    }
+
+    /// Clear the signaled state; eg before starting an operation that will be signaled.
+    pub fn reset(&self) {
+        free(|cs| self.signaled.borrow(cs).set(false));
+    }
+
+    /// Wait until signaled, then clear the signaled state.
+    pub async fn wait(&self) {
+        poll_fn(|cx| {
+            free(|cs| {
+                if self.signaled.borrow(cs).replace(false) {
+                    Poll::Ready(())
+                } else {
+                    let mut waker = self.waker.borrow(cs).borrow_mut();
+                    match waker.as_ref() {
+                        Some(w) if w.will_wake(cx.waker()) => (),
+                        _ => *waker = Some(cx.waker().clone()),
+                    }
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
@@ -0,0 +1,270 @@
+//! Backup domain management. The backup domain contains the RTC, LSE oscillator, backup
+//! registers, and on some MCUs, backup SRAM. It's powered from VBAT when VDD is off, and is
+//! write-protected after reset; call `unlock()` before writing to it.
+//!
+//! Example:
+//! ```rust
+//! backup::unlock();
+//! backup::enable_lse(LseDrive::MediumLow, false);
+//! backup::write_reg(0, 0xdead_beef);
+//! ```
+
+use cortex_m::interrupt::free;
+
+use crate::pac::{PWR, RCC};
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+    if #[cfg(any(feature = "l5", feature = "g0", feature = "g4", feature = "wl"))] {
+        // Backup registers are in the TAMP peripheral. (TAMP_BKPxR)
+        fn bkp_base() -> *mut u8 {
+            crate::pac::TAMP::ptr() as *mut u8
+        }
+        const BKP_OFFSET: usize = 0x100;
+    } else if #[cfg(feature = "l412")] {
+        // Backup registers are in the TAMP peripheral (TAMP_BKPxR), which is missing from the
+        // L412 PAC.
+        fn bkp_base() -> *mut u8 {
+            0x4000_3400 as *mut u8
+        }
+        const BKP_OFFSET: usize = 0x100;
+    } else {
+        // Backup registers are in the RTC peripheral. (RTC_BKPxR)
+        fn bkp_base() -> *mut u8 {
+            crate::pac::RTC::ptr() as *mut u8
+        }
+        const BKP_OFFSET: usize = 0x50;
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "f3")] {
+        /// The number of 32-bit backup registers.
+        pub const NUM_BACKUP_REGS: usize = 16;
+    } else if #[cfg(any(feature = "f4", feature = "wb", feature = "wl"))] {
+        /// The number of 32-bit backup registers.
+        pub const NUM_BACKUP_REGS: usize = 20;
+    } else if #[cfg(feature = "g0")] {
+        /// The number of 32-bit backup registers.
+        pub const NUM_BACKUP_REGS: usize = 5;
+    } else {
+        /// The number of 32-bit backup registers.
+        pub const NUM_BACKUP_REGS: usize = 32;
+    }
+}
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// LSE oscillator drive capability. Higher drive supports crystals with higher load capacitance,
+/// or ESR, at the cost of higher consumption. See AN2867 to select one. Sets RCC_BDCR, LSEDRV field.
+pub enum LseDrive {
+    Low = 0b00,
+    MediumLow = 0b01,
+    MediumHigh = 0b10,
+    High = 0b11,
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// VBAT battery charging resistor. Sets PWR_CR4 (PWR_CR3 on H7), VBRS field.
+pub enum ChargeResistor {
+    /// 5kΩ
+    R5k = 0,
+    /// 1.5kΩ
+    R1_5k = 1,
+}
+
+/// Enable write access to the backup domain: The RTC, RCC_BDCR, and backup registers. Enables the
+/// PWR peripheral clock if required, and sets the DBP bit. See L4 RM, section 5.1.5: Backup domain.
+pub fn unlock() {
+    free(|_| {
+        #[cfg(not(any(feature = "h7", feature = "wb", feature = "wl")))]
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let pwr = unsafe { &(*PWR::ptr()) };
+
+        cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "f4"))] {
+                rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+                pwr.cr.read(); // read to allow the pwr clock to enable
+                pwr.cr.modify(|_, w| w.dbp().set_bit());
+                while pwr.cr.read().dbp().bit_is_clear() {}
+            } else {
+                cfg_if! {
+                    if #[cfg(feature = "g0")] {
+                        rcc.apbenr1.modify(|_, w| w.pwren().set_bit());
+                    } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))] {
+                        rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+                    }
+                    // (The PWR clock is always enabled on H7, WB, and WL)
+                }
+                pwr.cr1.read(); // Read to allow the pwr clock to enable
+                pwr.cr1.modify(|_, w| w.dbp().set_bit());
+                while pwr.cr1.read().dbp().bit_is_clear() {}
+            }
+        }
+    });
+}
+
+/// Disable write access to the backup domain, protecting it from parasitic writes.
+pub fn lock() {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    #[cfg(any(feature = "f3", feature = "f4"))]
+    pwr.cr.modify(|_, w| w.dbp().clear_bit());
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    pwr.cr1.modify(|_, w| w.dbp().clear_bit());
+}
+
+/// Reset the backup domain. This clears the backup registers, and RTC configuration, and stops
+/// the LSE. The domain must be unlocked.
+pub fn reset() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "h7b3")] {
+            rcc.bdcr.modify(|_, w| w.vswrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.vswrst().clear_bit());
+        } else {
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+        }
+    }
+}
+
+#[cfg(not(feature = "f4"))]
+/// Set the LSE drive capability. This can be changed while the LSE is running, eg to start it
+/// with high drive, then reduce it. The domain must be unlocked.
+pub fn set_lse_drive(drive: LseDrive) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    // LSEDRV is bits 4:3 on all supported families, but its PAC field type varies.
+    rcc.bdcr
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 3)) | ((drive as u32) << 3)) });
+}
+
+/// Enable the LSE oscillator, and wait for it to be ready. Set `bypass` if using an external clock,
+/// vice a crystal. The domain must be unlocked.
+#[cfg(not(feature = "f4"))]
+pub fn enable_lse(drive: LseDrive, bypass: bool) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    set_lse_drive(drive);
+    // Can only set lsebyp when lse is off, so do this as a separate step.
+    rcc.bdcr.modify(|_, w| w.lsebyp().bit(bypass));
+    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+    while rcc.bdcr.read().lserdy().bit_is_clear() {}
+}
+
+/// Enable the LSE oscillator, and wait for it to be ready. Set `bypass` if using an external clock,
+/// vice a crystal. The domain must be unlocked.
+#[cfg(feature = "f4")]
+pub fn enable_lse(bypass: bool) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.bdcr.modify(|_, w| w.lsebyp().bit(bypass));
+    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+    while rcc.bdcr.read().lserdy().bit_is_clear() {}
+}
+
+/// Disable the LSE oscillator.
+pub fn disable_lse() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+/// Enable charging the VBAT battery (or supercap) through an internal resistor, when VDD is
+/// present. Don't use this with a non-rechargeable battery. See L4 RM, section 5.1.6: VBAT
+/// battery charging.
+pub fn enable_vbat_charging(resistor: ChargeResistor) {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    // VBE is bit 8, and VBRS is bit 9. (PWR_CR3 on H7, and PWR_CR4 on others)
+    let val = (1 << 8) | ((resistor as u32) << 9);
+
+    #[cfg(feature = "h7")]
+    pwr.cr3
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 8)) | val) });
+    #[cfg(not(feature = "h7"))]
+    pwr.cr4
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 8)) | val) });
+}
+
+#[cfg(not(any(feature = "f3", feature = "f4")))]
+/// Disable VBAT battery charging.
+pub fn disable_vbat_charging() {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    #[cfg(feature = "h7")]
+    pwr.cr3
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 8)) });
+    #[cfg(not(feature = "h7"))]
+    pwr.cr4
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 8)) });
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f405",
+    feature = "f407",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+/// Enable the 4kB backup SRAM's clock, and its low-power regulator, so its contents are retained in
+/// Standby, and VBAT modes. The domain must be unlocked. The backup SRAM starts at 0x3880_0000 on
+/// H7, and 0x4002_4000 on F4. See H743 RM, section 6.4.4: Backup domain, and F429 RM, section 5.1.2.
+pub fn enable_backup_sram() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "h7")] {
+            rcc.ahb4enr.modify(|_, w| w.bkpramen().set_bit());
+            // PWR_CR2: BREN is bit 0, and BRRDY is bit 16.
+            pwr.cr2.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+            while pwr.cr2.read().bits() & (1 << 16) == 0 {}
+        } else {
+            rcc.ahb1enr.modify(|_, w| w.bkpsramen().set_bit());
+            // PWR_CSR: BRE is bit 9, and BRR is bit 3.
+            pwr.csr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 9)) });
+            while pwr.csr.read().bits() & (1 << 3) == 0 {}
+        }
+    }
+}
+
+#[cfg(any(feature = "l4", feature = "g4"))]
+/// Retain SRAM2's contents in Standby mode. Sets PWR_CR3, RRS field. See L4 RM, section 5.3.9.
+pub fn retain_sram2_in_standby(retain: bool) {
+    let pwr = unsafe { &(*PWR::ptr()) };
+    // RRS is bit 8.
+    pwr.cr3.modify(|r, w| unsafe {
+        if retain {
+            w.bits(r.bits() | (1 << 8))
+        } else {
+            w.bits(r.bits() & !(1 << 8))
+        }
+    });
+}
+
+/// Returns a pointer to a backup register. We use raw pointers, since the registers are in the RTC
+/// peripheral on some families, and in TAMP on others, with inconsistent PAC field names.
+fn reg_ptr(index: usize) -> *mut u32 {
+    assert!(index < NUM_BACKUP_REGS, "Invalid backup register index.");
+
+    unsafe { bkp_base().add(BKP_OFFSET + index * 4) as *mut u32 }
+}
+
+/// Read a 32-bit backup register. These retain their contents in Standby mode, on VBAT, and through
+/// resets, other than backup domain resets. The RTC, or TAMP clock must be enabled.
+pub fn read_reg(index: usize) -> u32 {
+    unsafe { core::ptr::read_volatile(reg_ptr(index)) }
+}
+
+/// Write a 32-bit backup register. The domain must be unlocked.
+pub fn write_reg(index: usize, value: u32) {
+    unsafe { core::ptr::write_volatile(reg_ptr(index), value) }
+}
@@ -0,0 +1,398 @@
+//! Software (bit-banged) I2C and SPI drivers, using any GPIO pins. For use when a board's layout
+//! places a bus on pins that don't have the appropriate alternate function. These are slower
+//! and less precise than the hardware peripherals, and block for the duration of transfers.
+//!
+//! Timing is derived from the system clock frequency, using busy-wait loops. Interrupts that
+//! fire during a transfer will stretch the clock; this is tolerated by both protocols.
+
+use cortex_m::asm;
+
+#[cfg(feature = "embedded-hal")]
+use embedded_hal::{
+    blocking::i2c::{Read, Write, WriteRead},
+    spi::FullDuplex,
+};
+
+use crate::{
+    clocks::Clocks,
+    gpio::{OutputType, Pin, PinMode, Pull},
+    spi::SpiModeType,
+};
+
+// Number of half-bit periods to wait for a slave holding SCL low (clock stretching), before
+// giving up.
+const STRETCH_TIMEOUT: u32 = 10_000;
+
+/// Software I2C error
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// The addressed device didn't acknowledge a byte.
+    Nack,
+    /// A device held SCL low for too long.
+    Timeout,
+}
+
+/// Calculate the number of CPU cycles in half of a bit period.
+fn half_period_cycles(clock_cfg: &Clocks, freq: u32) -> u32 {
+    assert!(freq > 0, "Bit-bang frequency must be greater than 0.");
+    let cycles = clock_cfg.sysclk() / freq.saturating_mul(2);
+    if cycles == 0 {
+        1
+    } else {
+        cycles
+    }
+}
+
+/// A software I2C master, using 2 GPIO pins. Pins are configured as open-drain outputs; external
+/// pull-up resistors are recommended, although the internal pull-ups are enabled.
+pub struct SoftI2c {
+    pub scl: Pin,
+    pub sda: Pin,
+    half_period: u32,
+}
+
+impl SoftI2c {
+    /// Create a new software I2C master. `freq` is the SCL frequency in Hz; eg 100_000.
+    /// Configures the pins, and leaves the bus idle.
+    pub fn new(mut scl: Pin, mut sda: Pin, freq: u32, clock_cfg: &Clocks) -> Self {
+        for pin in [&mut scl, &mut sda] {
+            pin.set_high();
+            pin.output_type(OutputType::OpenDrain);
+            pin.pull(Pull::Up);
+            pin.mode(PinMode::Output);
+        }
+
+        Self {
+            scl,
+            sda,
+            half_period: half_period_cycles(clock_cfg, freq),
+        }
+    }
+
+    fn delay(&self) {
+        asm::delay(self.half_period);
+    }
+
+    /// Release SCL, and wait for any slave that's stretching the clock.
+    fn release_scl(&mut self) -> Result<(), Error> {
+        self.scl.set_high();
+
+        let mut i = 0;
+        while self.scl.is_low() {
+            i += 1;
+            if i >= STRETCH_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+            self.delay();
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        self.sda.set_high();
+        self.release_scl()?;
+        self.delay();
+        self.sda.set_low();
+        self.delay();
+        self.scl.set_low();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.sda.set_low();
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.sda.set_high();
+        self.delay();
+        Ok(())
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), Error> {
+        if bit {
+            self.sda.set_high();
+        } else {
+            self.sda.set_low();
+        }
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.scl.set_low();
+        Ok(())
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        self.sda.set_high(); // Release SDA, so the slave can drive it.
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        let bit = self.sda.is_high();
+        self.scl.set_low();
+        Ok(bit)
+    }
+
+    /// Write a byte, and return an error if it wasn't acknowledged.
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        for i in (0..8).rev() {
+            self.write_bit(byte & (1 << i) != 0)?;
+        }
+
+        if self.read_bit()? {
+            Err(Error::Nack)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read a byte. Acknowledges it if `ack` is true; the master doesn't acknowledge the final byte.
+    fn read_byte(&mut self, ack: bool) -> Result<u8, Error> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn write_inner(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.write_byte(addr << 1)?;
+        for byte in bytes {
+            self.write_byte(*byte)?;
+        }
+        Ok(())
+    }
+
+    fn read_inner(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.write_byte((addr << 1) | 1)?;
+        let len = buffer.len();
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_byte(i != len - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Read multiple words to a buffer. Can return an I2C error.
+    pub fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), Error> {
+        self.start()?;
+        let result = self.read_inner(addr, bytes);
+        self.stop()?;
+        result
+    }
+
+    /// Write an array of words. Can return an I2C error.
+    pub fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.start()?;
+        let result = self.write_inner(addr, bytes);
+        self.stop()?;
+        result
+    }
+
+    /// Write and read an array of words, with a repeated start between. Can return an I2C error.
+    pub fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.start()?;
+        let mut result = self.write_inner(addr, bytes);
+
+        if result.is_ok() {
+            // Repeated start.
+            self.sda.set_high();
+            self.delay();
+            self.start()?;
+            result = self.read_inner(addr, buffer);
+        }
+
+        self.stop()?;
+        result
+    }
+}
+
+/// A software SPI master, using 3 GPIO pins, and an optional CS pin. If a CS pin is passed, it's
+/// asserted (low) for the duration of each `write` and `transfer` call. Only supports 8-bit,
+/// MSB-first words.
+pub struct SoftSpi {
+    pub sck: Pin,
+    pub mosi: Pin,
+    pub miso: Pin,
+    pub cs: Option<Pin>,
+    mode: SpiModeType,
+    half_period: u32,
+    /// The byte read during the last `FullDuplex::send`.
+    #[cfg(feature = "embedded-hal")]
+    last_read: u8,
+}
+
+impl SoftSpi {
+    /// Create a new software SPI master. `freq` is the SCK frequency in Hz.
+    pub fn new(
+        mut sck: Pin,
+        mut mosi: Pin,
+        mut miso: Pin,
+        mut cs: Option<Pin>,
+        mode: SpiModeType,
+        freq: u32,
+        clock_cfg: &Clocks,
+    ) -> Self {
+        if mode.polarity as u8 != 0 {
+            sck.set_high();
+        } else {
+            sck.set_low();
+        }
+        sck.mode(PinMode::Output);
+        mosi.mode(PinMode::Output);
+        miso.mode(PinMode::Input);
+
+        if let Some(c) = cs.as_mut() {
+            c.set_high();
+            c.mode(PinMode::Output);
+        }
+
+        Self {
+            sck,
+            mosi,
+            miso,
+            cs,
+            mode,
+            half_period: half_period_cycles(clock_cfg, freq),
+            #[cfg(feature = "embedded-hal")]
+            last_read: 0,
+        }
+    }
+
+    fn delay(&self) {
+        asm::delay(self.half_period);
+    }
+
+    fn set_mosi(&mut self, bit: bool) {
+        if bit {
+            self.mosi.set_high();
+        } else {
+            self.mosi.set_low();
+        }
+    }
+
+    fn sck_idle(&mut self) {
+        if self.mode.polarity as u8 != 0 {
+            self.sck.set_high();
+        } else {
+            self.sck.set_low();
+        }
+    }
+
+    fn sck_active(&mut self) {
+        if self.mode.polarity as u8 != 0 {
+            self.sck.set_low();
+        } else {
+            self.sck.set_high();
+        }
+    }
+
+    /// Write and read a single byte simultaneously.
+    pub fn transfer_one(&mut self, byte: u8) -> u8 {
+        let cpha = self.mode.phase as u8 != 0;
+        let mut result = 0;
+
+        for i in (0..8).rev() {
+            let bit = byte & (1 << i) != 0;
+
+            // With CPHA = 0, data is set up before the first (leading) edge, and sampled on it.
+            // With CPHA = 1, data is set up on the leading edge, and sampled on the trailing edge.
+            if !cpha {
+                self.set_mosi(bit);
+                self.delay();
+                self.sck_active();
+                result = (result << 1) | self.miso.is_high() as u8;
+                self.delay();
+                self.sck_idle();
+            } else {
+                self.sck_active();
+                self.set_mosi(bit);
+                self.delay();
+                self.sck_idle();
+                result = (result << 1) | self.miso.is_high() as u8;
+                self.delay();
+            }
+        }
+
+        result
+    }
+
+    fn cs_low(&mut self) {
+        if let Some(c) = self.cs.as_mut() {
+            c.set_low();
+        }
+    }
+
+    fn cs_high(&mut self) {
+        if let Some(c) = self.cs.as_mut() {
+            c.set_high();
+        }
+    }
+
+    /// Write multiple bytes, discarding what's read.
+    pub fn write(&mut self, words: &[u8]) {
+        self.cs_low();
+        for word in words {
+            self.transfer_one(*word);
+        }
+        self.cs_high();
+    }
+
+    /// Write multiple bytes, replacing each with the byte read.
+    pub fn transfer(&mut self, words: &mut [u8]) {
+        self.cs_low();
+        for word in words.iter_mut() {
+            *word = self.transfer_one(*word);
+        }
+        self.cs_high();
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl Write for SoftI2c {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
+        SoftI2c::write(self, addr, bytes)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl Read for SoftI2c {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), Error> {
+        SoftI2c::read(self, addr, bytes)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl WriteRead for SoftI2c {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        SoftI2c::write_read(self, addr, bytes, buffer)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// Note that the CS pin, if present, isn't managed when using this trait, since transfers are
+// split into individual words.
+impl FullDuplex<u8> for SoftSpi {
+    type Error = core::convert::Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        // Data is exchanged in `send`; this returns the byte read then.
+        Ok(self.last_read)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.last_read = self.transfer_one(byte);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::blocking::spi::transfer::Default<u8> for SoftSpi {}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::blocking::spi::write::Default<u8> for SoftSpi {}
@@ -1,14 +1,44 @@
 //! Support for Controller Area Network (CAN) bus. Thinly wraps the [bxCAN library](https://docs.rs/bxcan/0.5.0/bxcan/).
-//! Note that this is currently for bxCAN only; different from the `fdCAN` used on newer families.
+//! Note that this is for bxCAN only; see the `fdcan` module for the FDCAN used on newer families.
 //!
 //! Requires the `can` feature.
-
-// todo: Add fdCAN support.
+//!
+//! Use `bit_timing()` to compute the bit timing register value for a bit rate, from the APB1
+//! clock. Filter banks, transmit mailboxes, and RX FIFO interrupts are configured through
+//! `bxcan`; its types are re-exported here.
+//!
+//! Example:
+//! ```rust
+//! let mut can = bxcan::Can::builder(Can::new(dp.CAN1, &mut dp.RCC))
+//!     .set_bit_timing(can::bit_timing(&clock_cfg, 500_000).unwrap())
+//!     .enable();
+//!
+//! // Accept standard ID 0x100 - 0x10f. bxcan assigns all filter banks to FIFO 0.
+//! can.modify_filters()
+//!     .enable_bank(0, Mask32::frames_with_std_id(StandardId::new(0x100).unwrap(), StandardId::new(0x7f0).unwrap()));
+//!
+//! // Receive from the FIFO 0 message pending interrupt; eg `CAN1_RX0` on F4.
+//! can.enable_interrupt(Interrupt::Fifo0MessagePending);
+//!
+//! // Transmit, using a free mailbox. If all are busy, the lowest-priority pending frame is
+//! // replaced, and returned.
+//! let frame = Frame::new_data(StandardId::new(0x200).unwrap(), [1, 2, 3]);
+//! if let Ok(Some(replaced)) = can.transmit(&frame) { /* retry */ }
+//!
+//! // In the ISR:
+//! while let Ok(frame) = can.receive() { /* ... */ }
+//! ```
 
 use bxcan;
 use core::ops::Deref;
 
+pub use bxcan::{
+    filter::{ListEntry16, ListEntry32, Mask16, Mask32},
+    ExtendedId, Frame, Id, Interrupt, Mailbox, StandardId,
+};
+
 use crate::{
+    clocks::{Clocks, SpeedError},
     pac::{self, RCC},
     rcc_en_reset,
 };
@@ -51,6 +81,47 @@ where
     }
 }
 
+/// Target sample point, in 1/1000 of the bit time. 87.5% is recommended by CiA, for most bit
+/// rates.
+const SAMPLE_POINT: u32 = 875;
+
+/// Calculate the bit timing register (CAN_BTR) value for a bit rate, in bits per second, from the
+/// APB1 clock. Pass the result to `bxcan::CanBuilder::set_bit_timing()`. Uses the most time
+/// quanta per bit that divide the clock exactly, with the sample point as close to 87.5% as
+/// possible, and a resynchronization jump width of 1 time quantum. Returns an error if no exact
+/// timing is available; eg choose an APB1 frequency that's a multiple of the bit rate.
+pub fn bit_timing(clocks: &Clocks, bitrate: u32) -> Result<u32, SpeedError> {
+    if bitrate == 0 {
+        return Err(SpeedError::new("The CAN bit rate must be nonzero."));
+    }
+
+    let pclk = clocks.apb1();
+
+    // A bit is 1 sync segment time quantum, plus TS1 (1 - 16), and TS2 (1 - 8) quanta.
+    for tq in (8..=25).rev() {
+        if pclk % (bitrate * tq) != 0 {
+            continue;
+        }
+
+        let prescaler = pclk / (bitrate * tq);
+        if prescaler == 0 || prescaler > 1_024 {
+            continue;
+        }
+
+        let ts2 = ((tq * (1_000 - SAMPLE_POINT) + 500) / 1_000).clamp(1, 8);
+        let ts1 = tq - 1 - ts2;
+        if ts1 > 16 {
+            continue;
+        }
+
+        return Ok(((ts2 - 1) << 20) | ((ts1 - 1) << 16) | (prescaler - 1));
+    }
+
+    Err(SpeedError::new(
+        "No CAN bit timing matches this bit rate at the APB1 frequency.",
+    ))
+}
+
 // todo: F3 calls it "CAN", and F4 has 2 CANs.
 
 cfg_if! {
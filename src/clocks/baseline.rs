@@ -5,7 +5,7 @@
 // Similar in from to the H7 clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
+    clocks::{ClockListener, SpeedError, TemperatureGrade},
     pac::{self, FLASH, RCC},
     util::rcc_en_reset,
 };
@@ -169,6 +169,42 @@ pub enum RfWakeupSrc {
     Hse = 0b11,
 }
 
+#[cfg(feature = "wb")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// HSE oscillator current control; the maximum critical crystal transconductance, in mA/V.
+/// Sets RCC_HSECR, HSEGMC field.
+pub enum HseGmc {
+    G0_18 = 0b000,
+    G0_57 = 0b001,
+    G0_78 = 0b010,
+    G1_13 = 0b011,
+    G0_61 = 0b100,
+    G1_65 = 0b101,
+    G2_12 = 0b110,
+    G2_84 = 0b111,
+}
+
+#[cfg(feature = "wb")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// HSE sense amplifier threshold. Sets RCC_HSECR, HSES bit.
+pub enum HseSense {
+    OneThirdVdd = 0,
+    TwoThirdsVdd = 1,
+}
+
+#[cfg(feature = "wb")]
+#[derive(Clone, Copy)]
+/// HSE32 crystal oscillator tuning, for the radio's frequency accuracy. See WB RM, section 7.2.2:
+/// HSE clock, and ST application note AN5042: HSE trimming for RF applications.
+pub struct HseTuning {
+    /// Load capacitance trim; 0 - 63. Higher values lower the frequency. Sets the HSETUNE field.
+    pub cap_tune: u8,
+    pub current: HseGmc,
+    pub sense: HseSense,
+}
+
 // L4 uses 0 - 4 only. Others use 1 - 15, but it's not clear when you'd
 // set more than WS5 or so.
 #[derive(Clone, Copy)]
@@ -226,8 +262,17 @@ impl MsiRange {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// A PLL output. (P, Q, or R divider)
+pub enum PllOutput {
+    P,
+    Q,
+    R,
+}
+
 /// Configures the speeds, and enable status of an individual PLL (PLL1, or SAIPLL). Note that the `enable`
-/// field has no effect for PLL1.
+/// field has no effect for PLL1. Outputs selected as a kernel clock source, eg by
+/// `Clocks::clk48_src`, are enabled even if their enable field isn't set.
 pub struct PllCfg {
     /// Only relevant for PLLSAI1.
     pub enabled: bool,
@@ -527,6 +572,267 @@ pub enum SaiSrc {
     ExtClk = 0b11,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// U[S]ART kernel clock source. Sets RCC_CCIPR register (RCC_CCIPR1 on L5), USARTxSEL, UARTxSEL,
+/// and LPUART1SEL fields. Use HSI16, or LSE to receive in Stop mode.
+pub enum UsartClkSrc {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Hsi16 = 0b10,
+    Lse = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2C kernel clock source. Sets RCC_CCIPR register (RCC_CCIPR1 on L5), I2CxSEL fields. Use HSI16
+/// to wake from Stop mode on address match.
+pub enum I2cClkSrc {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Hsi16 = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// U[S]ARTs with a selectable kernel clock. The value is the field's position in RCC_CCIPR.
+pub enum UsartPeriph {
+    Usart1 = 0,
+    #[cfg(not(feature = "wb"))]
+    Usart2 = 2,
+    #[cfg(not(any(feature = "wb", feature = "wl")))]
+    Usart3 = 4,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    Uart4 = 6,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    Uart5 = 8,
+    Lpuart1 = 10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2C peripherals with a selectable kernel clock. The value is the field's position in RCC_CCIPR.
+/// (I2C2 on G0 is always clocked by PCLK)
+pub enum I2cPeriph {
+    I2c1 = 12,
+    #[cfg(not(any(feature = "g0", feature = "wb")))]
+    I2c2 = 14,
+    #[cfg(not(feature = "g0"))]
+    I2c3 = 16,
+    #[cfg(any(
+        feature = "l5",
+        feature = "g471",
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484"
+    ))]
+    /// Its field is in RCC_CCIPR2, at position 0.
+    I2c4 = 0,
+}
+
+#[cfg(any(feature = "g0", feature = "g4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// I2S kernel clock source. Set in RCC_CCIPR (RCC_CCIPR2 on G0B1 and G0C1). On G0, this is I2S1's
+/// clock; on G4, it's shared by I2S2 and I2S3.
+pub enum I2sClkSrc {
+    Sysclk,
+    /// PLLP on G0, and PLLQ on G4.
+    Pll,
+    Hsi16,
+    /// The I2S_CKIN pin.
+    Ckin,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Core voltage range. See the RM, section "Dynamic voltage scaling management". Set in
+/// `Clocks::setup()`, from the `vos_range` field. Sets PWR_CR1, VOS field, and on G4, PWR_CR5,
+/// R1MODE field.
+pub enum VosRange {
+    #[cfg(feature = "l5")]
+    /// High performance range.
+    Range0,
+    #[cfg(feature = "g4")]
+    /// Range 1 boost mode.
+    Range1Boost,
+    /// Range 1. (Range 1 normal mode on G4)
+    Range1,
+    /// Low-power range.
+    Range2,
+}
+
+impl VosRange {
+    /// The PWR_CR1 VOS field value.
+    fn bits(&self) -> u8 {
+        match self {
+            #[cfg(feature = "l5")]
+            Self::Range0 => 0b00,
+            #[cfg(feature = "g4")]
+            Self::Range1Boost => 0b01,
+            Self::Range1 => 0b01,
+            Self::Range2 => 0b10,
+        }
+    }
+}
+
+/// Set the core voltage range, and wait for the regulator to reach it. When moving to a higher
+/// performance range, run this before increasing clock speeds; when moving to a lower one, run it
+/// after decreasing them, and the flash wait states. `Clocks::setup()` handles this.
+/// See L4 RM, section 5.1.8: Dynamic voltage scaling management.
+pub fn set_vos_range(range: VosRange) {
+    #[cfg(not(any(feature = "wb", feature = "wl")))]
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let pwr = unsafe { &(*pac::PWR::ptr()) };
+
+    // The PWR registers can't be written unless its clock is enabled. (It's always enabled on
+    // WB and WL)
+    cfg_if! {
+        if #[cfg(feature = "g0")] {
+            rcc.apbenr1.modify(|_, w| w.pwren().set_bit());
+        } else if #[cfg(not(any(feature = "wb", feature = "wl")))] {
+            rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+        }
+    }
+    pwr.cr1.read(); // Read to allow the pwr clock to enable
+
+    pwr.cr1.modify(|_, w| unsafe { w.vos().bits(range.bits()) });
+
+    // On G4, Range 1 has normal, and boost modes; R1MODE = 0 selects boost.
+    #[cfg(feature = "g4")]
+    pwr.cr5
+        .modify(|_, w| w.r1mode().bit(range != VosRange::Range1Boost));
+
+    // Wait for the regulator to reach the new voltage.
+    while pwr.sr2.read().vosf().bit_is_set() {}
+}
+
+// PLL input (after PLLM), and VCO output limits. See the datasheet, section "PLL characteristics".
+const PLL_IN_MIN: u32 = 2_660_000;
+const PLL_IN_MAX: u32 = 16_000_000;
+const VCO_MAX: u32 = 344_000_000;
+
+cfg_if! {
+    if #[cfg(any(feature = "g4", feature = "wb", feature = "wl"))] {
+        const VCO_MIN: u32 = 96_000_000;
+    } else {
+        const VCO_MIN: u32 = 64_000_000;
+    }
+}
+
+/// Find the maximum sysclk speed for a given voltage range and temperature grade. See the
+/// datasheet, section "General operating conditions".
+pub fn max_sysclk(vos: VosRange, temperature_grade: TemperatureGrade) -> u32 {
+    cfg_if! {
+        if #[cfg(feature = "l4")] {
+            // todo: L4+ (ie R, S, P, Q) can go up to 120Mhz.
+            let _ = temperature_grade;
+            match vos {
+                VosRange::Range1 => 80_000_000,
+                VosRange::Range2 => 26_000_000,
+            }
+        } else if #[cfg(feature = "l5")] {
+            match vos {
+                // Range 0 is only available up to 105°C junction temperature.
+                VosRange::Range0 => match temperature_grade {
+                    TemperatureGrade::Grade6 => 110_000_000,
+                    _ => 80_000_000,
+                },
+                VosRange::Range1 => 80_000_000,
+                VosRange::Range2 => 26_000_000,
+            }
+        } else if #[cfg(feature = "g4")] {
+            let _ = temperature_grade;
+            match vos {
+                VosRange::Range1Boost => 170_000_000,
+                VosRange::Range1 => 150_000_000,
+                VosRange::Range2 => 26_000_000,
+            }
+        } else if #[cfg(any(feature = "g0", feature = "wb"))] {
+            let _ = temperature_grade;
+            match vos {
+                VosRange::Range1 => 64_000_000,
+                VosRange::Range2 => 16_000_000,
+            }
+        } else {  // WL
+            let _ = temperature_grade;
+            match vos {
+                VosRange::Range1 => 48_000_000,
+                VosRange::Range2 => 16_000_000,
+            }
+        }
+    }
+}
+
+// The CSS flag and clear bits are consistent across L4, L5, G0, G4, WB, and WL, but PAC field names
+// and availability vary; we use raw bits.
+/// RCC_CIFR and RCC_CICR: CSSF and CSSC.
+const CSS_BIT: u32 = 1 << 8;
+/// RCC_CIER, RCC_CIFR, and RCC_CICR: LSECSSIE, LSECSSF, and LSECSSC.
+const LSE_CSS_BIT: u32 = 1 << 9;
+/// RCC_BDCR: LSECSSON is bit 5, and LSECSSD is bit 6.
+const LSECSSON_BIT: u32 = 1 << 5;
+const LSECSSD_BIT: u32 = 1 << 6;
+
+/// Returns true if the HSE clock security system detected an HSE failure. The failure triggers an NMI;
+/// check this in the NMI handler, and clear it with `clear_hse_css()`, or the NMI will re-fire. eg:
+/// ```
+/// #[exception]
+/// unsafe fn NonMaskableInt() {
+///     if clocks::hse_css_failed() {
+///         clocks::clear_hse_css();
+///         // Set a flag for the main loop, which runs `recover_from_hse_failure()`.
+///         HSE_FAILED.store(true, Ordering::Release);
+///     }
+/// }
+/// ```
+pub fn hse_css_failed() -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.cifr.read().bits() & CSS_BIT != 0
+}
+
+/// Clear the HSE clock security system failure flag. (RCC_CICR, CSSC bit)
+pub fn clear_hse_css() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.cicr.write(|w| unsafe { w.bits(CSS_BIT) });
+}
+
+/// Enable the LSE clock security system. On failure, the LSE is no longer used as the RTC
+/// clock, and an interrupt is generated if `interrupt` is set. The LSE CSS interrupt is on EXTI
+/// line 19 on L4; configure it to wake from Stop. G0 has no LSE CSS interrupt enable; it reports
+/// failures through the RTC and TAMP interrupts, and ignores `interrupt`. Run this after the LSE (and on L4, the LSI) is
+/// enabled, and ready, and the RTC clock is selected. The backup domain must be unlocked.
+/// See L4 RM, section 6.2.11: Clock security system on LSE.
+pub fn enable_lse_css(interrupt: bool) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    #[cfg(not(feature = "g0"))]
+    rcc.cier.modify(|r, w| unsafe {
+        if interrupt {
+            w.bits(r.bits() | LSE_CSS_BIT)
+        } else {
+            w.bits(r.bits() & !LSE_CSS_BIT)
+        }
+    });
+    #[cfg(feature = "g0")]
+    let _ = interrupt;
+
+    rcc.bdcr
+        .modify(|r, w| unsafe { w.bits(r.bits() | LSECSSON_BIT) });
+}
+
+/// Returns true if the LSE clock security system detected an LSE failure. (RCC_BDCR, LSECSSD bit)
+/// Once this is set, the LSE CSS can only be disabled by a backup domain reset.
+pub fn lse_css_failed() -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.bdcr.read().bits() & LSECSSD_BIT != 0
+}
+
+/// Clear the LSE clock security system interrupt flag. (RCC_CICR, LSECSSC bit)
+pub fn clear_lse_css() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.cicr.write(|w| unsafe { w.bits(LSE_CSS_BIT) });
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
@@ -556,14 +862,20 @@ pub struct Clocks {
     #[cfg(not(feature = "g0"))]
     /// The divider of HCLK to get the APB2 peripheral clock
     pub apb2_prescaler: ApbPrescaler,
-    // Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
-    // frees up the pin for use as GPIO.
     #[cfg(not(any(feature = "g0", feature = "wl")))]
     /// The input source for the 48Mhz clock used by USB.
     pub clk48_src: Clk48Src,
-    /// Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
-    /// frees up the pin for use as GPIO.
+    /// Bypass the HSE oscillator, for use with an external clock signal on OSC_IN, eg from an
+    /// oscillator or TCXO, instead of a crystal. Frees up OSC_OUT for use as GPIO. On WL, this
+    /// instead powers the TCXO from the PB0-VDDTCXO pin (HSEBYPPWR); its voltage is set with the
+    /// sub-GHz radio's `SetTcxoMode` command.
     pub hse_bypass: bool,
+    #[cfg(feature = "wb")]
+    /// HSE crystal tuning. `None` leaves the reset values. Ignored if `hse_bypass` is set.
+    pub hse_tuning: Option<HseTuning>,
+    /// Enable the HSE clock security system (CSS). If the HSE fails, hardware switches the system
+    /// clock to HSI16 (or on L4 and L5, MSI, depending on `stop_wuck`), and triggers an NMI. See `hse_css_failed()`,
+    /// and `Clocks::recover_from_hse_failure()`.
     pub security_system: bool,
     #[cfg(not(any(feature = "g0", feature = "wl")))]
     /// Enable the HSI48. For L4, this is only applicable for some devices.
@@ -577,10 +889,21 @@ pub struct Clocks {
     #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
     /// SAI1 kernel clock source selection
     pub sai1_src: SaiSrc,
+    /// Core voltage range. Lower ranges reduce power consumption, but limit the maximum clock
+    /// speed. Defaults to the highest performance range: Range 0 on L5, Range 1 boost mode on G4,
+    /// and Range 1 on others.
+    pub vos_range: VosRange,
     #[cfg(feature = "g4")]
-    /// Range 1 boost mode: Used to increase regulator voltage to 1.28v, for when system
-    /// clock frequency is up to 170Mhz. Defaults to true.
+    #[deprecated(
+        since = "1.5.5",
+        note = "Use `vos_range`: `VosRange::Range1Boost` for boost mode, or `VosRange::Range1`."
+    )]
+    /// Range 1 boost mode. Superseded by `vos_range`. Setting this to `false` while `vos_range` is
+    /// `VosRange::Range1Boost` selects Range 1 normal mode, as before `vos_range` existed.
     pub boost_mode: bool,
+    /// The device's temperature grade; used to validate the maximum sysclk speed. Defaults to
+    /// Grade 6. (-40 to 85°C ambient)
+    pub temperature_grade: TemperatureGrade,
 }
 
 // todo: On L4/5, add a way to enable the MSI for use as CLK48.
@@ -608,29 +931,19 @@ impl Clocks {
 
         // Adjust flash wait states according to the HCLK frequency.
         // We need to do this before enabling PLL, or it won't enable.
-        let sysclk = self.sysclk();
-
-        cfg_if! {
-            if #[cfg(feature = "wb")] {
-                let hclk = sysclk / self.hclk4_prescaler.value() as u32;
-            } else if #[cfg(feature = "wl")] {
-                let hclk = sysclk / self.hclk3_prescaler.value() as u32;
-            } else {
-                let hclk = sysclk / self.hclk_prescaler.value() as u32;
-            }
-        }
+        let wait_state = self.flash_wait_state();
 
-        cfg_if! {
-            if #[cfg(feature = "g4")] {
-                if self.boost_mode {
-                    // The sequence to switch from Range1 normal mode to Range1 boost mode is:
-                    // 1. The system clock must be divided by 2 using the AHB prescaler before switching to a
-                    // higher system frequency.
-                    rcc.cfgr.modify(|_, w| unsafe { w.hpre().bits(HclkPrescaler::Div2 as u8) });
-                    // 2. Clear the R1MODE bit is in the PWR_CR5 register.
-                    let pwr = unsafe { &(*pac::PWR::ptr()) };
-                    pwr.cr5.modify(|_, w| w.r1mode().clear_bit());
-                }
+        // If moving to a higher performance voltage range, we must do so before increasing clock
+        // speeds. If moving to Range 2, we do so at the end, after reducing them.
+        if self.vos() != VosRange::Range2 {
+            #[cfg(feature = "g4")]
+            if self.vos() == VosRange::Range1Boost {
+                // The sequence to switch from Range1 normal mode to Range1 boost mode is:
+                // 1. The system clock must be divided by 2 using the AHB prescaler before switching to a
+                // higher system frequency.
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.hpre().bits(HclkPrescaler::Div2 as u8) });
+                // 2. Clear the R1MODE bit is in the PWR_CR5 register. (Handled in `set_vos_range`)
 
                 // (Remaining steps accomplished below)
                 // 3. Adjust the number of wait states according to the new frequency target in range1 boost
@@ -639,92 +952,8 @@ impl Clocks {
                 // 5. Wait for at least 1us and then reconfigure the AHB prescaler to get the needed HCLK
                 // clock frequency.
             }
-        }
 
-        cfg_if! {
-            if #[cfg(feature = "l4")] {  // RM section 3.3.3
-                let wait_state = if hclk <= 16_000_000 {
-                    WaitState::W0
-                } else if hclk <= 32_000_000 {
-                    WaitState::W1
-                } else if hclk <= 48_000_000 {
-                    WaitState::W2
-                } else if hclk <= 64_000_000 {
-                    WaitState::W3
-                } else {
-                    WaitState::W4
-                };
-            } else if #[cfg(feature = "l5")] {  // RM section 6.3.3
-                let wait_state = if hclk <= 20_000_000 {
-                    WaitState::W0
-                } else if hclk <= 40_000_000 {
-                    WaitState::W1
-                } else if hclk <= 60_000_000 {
-                    WaitState::W2
-                } else if hclk <= 80_000_000 {
-                    WaitState::W3
-                } else if hclk <= 100_000_000 {
-                    WaitState::W4
-                } else {
-                    WaitState::W5
-                };
-            } else if #[cfg(feature = "g0")] {  // G0. RM section 3.3.4
-                let wait_state = if hclk <= 24_000_000 {
-                    WaitState::W0
-                } else if hclk <= 48_000_000 {
-                    WaitState::W1
-                } else {
-                    WaitState::W2
-                };
-            } else if #[cfg(feature = "wb")] {  // WB. RM section 3.3.4, Table 4.
-                // Note: This applies to HCLK4 HCLK. (See HCLK4 used above for hclk var.)
-                let wait_state = if hclk <= 18_000_000 {
-                    WaitState::W0
-                } else if hclk <= 36_000_000 {
-                    WaitState::W1
-                } else if hclk <= 54_000_000 {
-                    WaitState::W2
-                } else {
-                    WaitState::W3
-                };
-            } else if #[cfg(any(feature = "wb", feature = "wl"))] {  // WL. RM section 3.3.4, Table 5.
-                // Note: This applies to HCLK3 HCLK. (See HCLK3 used above for hclk var.)
-                let wait_state = if hclk <= 18_000_000 {
-                    WaitState::W0
-                } else if hclk <= 36_000_000 {
-                    WaitState::W1
-                } else {
-                    WaitState::W2
-                };
-            } else {  // G4. RM section 3.3.3
-                let wait_state = if self.boost_mode {
-                    // Vcore Range 1 boost mode
-                    if hclk <= 34_000_000 {
-                        WaitState::W0
-                    } else if hclk <= 68_000_000 {
-                        WaitState::W1
-                    } else if hclk <= 102_000_000 {
-                        WaitState::W2
-                    } else if hclk <= 136_000_000 {
-                        WaitState::W3
-                    } else {
-                        WaitState::W4
-                    }
-                } else {
-                    // Vcore Range 1 normal mode.
-                    if hclk <= 30_000_000 {
-                        WaitState::W0
-                    } else if hclk <= 60_000_000 {
-                        WaitState::W1
-                    } else if hclk <= 90_000_000 {
-                        WaitState::W2
-                    } else if hclk <= 120_000_000 {
-                        WaitState::W3
-                    } else {
-                        WaitState::W4
-                    }
-                };
-            }
+            set_vos_range(self.vos());
         }
 
         // Enable instruction and data caches, for a potential performance increase.
@@ -799,9 +1028,7 @@ impl Clocks {
                 // todo: If LSE is enabled, calibrate MSI.
             }
             InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+                self.enable_hse();
             }
             InputSrc::Hsi => {
                 rcc.cr.modify(|_, w| w.hsion().set_bit());
@@ -821,8 +1048,7 @@ impl Clocks {
                         while rcc.cr.read().msirdy().bit_is_clear() {}
                     }
                     PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                        self.enable_hse();
                     }
                     PllSrc::Hsi => {
                         rcc.cr.modify(|_, w| w.hsion().set_bit());
@@ -843,14 +1069,6 @@ impl Clocks {
             }
         }
 
-        rcc.cr.modify(|_, w| {
-            // Enable bypass mode on HSE, since we're using a ceramic oscillator.
-            #[cfg(feature = "wl")]
-            return w.hsebyppwr().bit(self.hse_bypass);
-            #[cfg(not(feature = "wl"))]
-            w.hsebyp().bit(self.hse_bypass)
-        });
-
         rcc.cfgr.modify(|_, w| unsafe {
             w.sw().bits(self.input_src.bits());
             w.hpre().bits(self.hclk_prescaler as u8);
@@ -898,8 +1116,8 @@ impl Clocks {
                     rcc.pllsyscfgr.modify(|_, w| unsafe {
                         w.pllsrc().bits(pll_src.bits());
                         w.pllren().bit(true);
-                        w.pllqen().bit(self.pll.pllq_en);
-                        w.pllpen().bit(self.pll.pllp_en);
+                        w.pllqen().bit(self.pll_output_enabled(1, PllOutput::Q));
+                        w.pllpen().bit(self.pll_output_enabled(1, PllOutput::P));
                         w.plln().bits(self.pll.divn);
                         w.pllm().bits(self.pll.divm as u8);
                         w.pllr().bits(self.pll.divr as u8);
@@ -910,8 +1128,8 @@ impl Clocks {
                     rcc.pllcfgr.modify(|_, w| unsafe {
                         w.pllsrc().bits(pll_src.bits());
                         w.pllren().bit(true);
-                        w.pllqen().bit(self.pll.pllq_en);
-                        w.pllpen().bit(self.pll.pllp_en);
+                        w.pllqen().bit(self.pll_output_enabled(1, PllOutput::Q));
+                        w.pllpen().bit(self.pll_output_enabled(1, PllOutput::P));
                         w.plln().bits(self.pll.divn);
                         w.pllm().bits(self.pll.divm as u8);
                         w.pllr().bits(self.pll.divr as u8);
@@ -930,7 +1148,8 @@ impl Clocks {
 
             cfg_if! {
                 if #[cfg(feature = "g0")] {
-                    // Set Pen, Qen, and Ren after we enable the PLL.
+                    // Set Pen, Qen, and Ren after we enable the PLL. P and Q are always enabled,
+                    // since many kernel clock selections can use them.
                     rcc.pllsyscfgr.modify(|_, w| {
                         w.pllpen().set_bit();
                         w.pllqen().set_bit();
@@ -949,9 +1168,9 @@ impl Clocks {
                 // todo: Missing some settings I'm not sure what to make of on L.
                 if #[cfg(any(feature = "l4", feature = "l5"))] {
                     rcc.pllsai1cfgr.modify(|_, w| unsafe {
-                        w.pllsai1ren().bit(self.pllsai1.pllr_en);
-                        w.pllsai1qen().bit(self.pllsai1.pllq_en);
-                        w.pllsai1pen().bit(self.pllsai1.pllp_en);
+                        w.pllsai1ren().bit(self.pll_output_enabled(2, PllOutput::R));
+                        w.pllsai1qen().bit(self.pll_output_enabled(2, PllOutput::Q));
+                        w.pllsai1pen().bit(self.pll_output_enabled(2, PllOutput::P));
                         w.pllsai1n().bits(self.pllsai1.divn);
                         #[cfg(not(any(feature = "l4x5", feature = "l4x3")))]
                         w.pllsai1pdiv().bits(self.pllsai1.pdiv);
@@ -961,23 +1180,22 @@ impl Clocks {
                     });
 
                     #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+                    // PLLSAI2 has no Q output.
                     rcc.pllsai2cfgr.modify(|_, w| unsafe {
-                        w.pllsai2ren().bit(self.pllsai1.pllr_en);
-                        // w.pllsai2qen().bit(self.pllsai1.pllq_en);
-                        w.pllsai2pen().bit(self.pllsai1.pllp_en);
-                        w.pllsai2n().bits(self.pllsai1.divn);
+                        w.pllsai2ren().bit(self.pll_output_enabled(3, PllOutput::R));
+                        w.pllsai2pen().bit(self.pll_output_enabled(3, PllOutput::P));
+                        w.pllsai2n().bits(self.pllsai2.divn);
                         #[cfg(not(feature = "l4x5"))]
-                        w.pllsai2pdiv().bits(self.pllsai1.pdiv);
-                        w.pllsai2r().bits(self.pllsai1.divr as u8);
-                        // w.pllsai2q().bits(self.pllsai1.divq as u8);
-                        w.pllsai2p().bit(self.pllsai1.divp as u8 != 0)
+                        w.pllsai2pdiv().bits(self.pllsai2.pdiv);
+                        w.pllsai2r().bits(self.pllsai2.divr as u8);
+                        w.pllsai2p().bit(self.pllsai2.divp as u8 != 0)
                     });
 
                 } else if #[cfg(feature = "wb")] {
                     rcc.pllsai1cfgr.modify(|_, w| unsafe {
-                        w.pllren().bit(self.pllsai1.pllr_en);
-                        w.pllqen().bit(self.pllsai1.pllq_en);
-                        w.pllpen().bit(self.pllsai1.pllp_en);
+                        w.pllren().bit(self.pll_output_enabled(2, PllOutput::R));
+                        w.pllqen().bit(self.pll_output_enabled(2, PllOutput::Q));
+                        w.pllpen().bit(self.pll_output_enabled(2, PllOutput::P));
                         w.plln().bits(self.pllsai1.divn);
                         w.pllr().bits(self.pllsai1.divr as u8);
                         w.pllq().bits(self.pllsai1.divq as u8);
@@ -1062,56 +1280,236 @@ impl Clocks {
         rcc.csr
             .modify(|_, w| unsafe { w.rfwkpsel().bits(self.rf_wakeup_src as u8) });
 
+        // Now that clock speeds are reduced, we can move to the low-power voltage range.
+        if self.vos() == VosRange::Range2 {
+            set_vos_range(self.vos());
+        }
+
         Ok(())
     }
 
-    /// Re-select input source; used after Stop and Standby modes, where the system reverts
-    /// to MSI or HSI after wake.
-    pub fn reselect_input(&self) {
-        let rcc = unsafe { &(*RCC::ptr()) };
-
-        // Re-select the input source; useful for changing input source, or reverting
-        // from stop or standby mode. This assumes we're on a clean init,
-        // or waking up from stop mode etc.
-
-        match self.input_src {
-            InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().bit_is_clear() {}
-
-                rcc.cfgr
-                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+    /// The HCLK frequency that flash wait states are set from. HCLK4 on WB, and HCLK3 on WL.
+    fn flash_hclk(&self) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "wb")] {
+                self.sysclk() / self.hclk4_prescaler.value() as u32
+            } else if #[cfg(feature = "wl")] {
+                self.sysclk() / self.hclk3_prescaler.value() as u32
+            } else {
+                self.hclk()
             }
-            InputSrc::Pll(pll_src) => {
-                // todo: DRY with above.
-                match pll_src {
-                    PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
-                    }
-                    PllSrc::Hsi => {
-                        #[cfg(any(feature = "l4", feature = "l5"))]
-                        // Generally reverts to MSI (see note below)
-                        if let StopWuck::Msi = self.stop_wuck {
-                            rcc.cr.modify(|_, w| w.hsion().set_bit());
-                            while rcc.cr.read().hsirdy().bit_is_clear() {}
-                        }
-                        // If on G, we'll already be on HSI, so need to take action.
-                    }
-                    #[cfg(not(any(feature = "g0", feature = "g4")))]
-                    PllSrc::Msi(range) => {
-                        // Range initializes to 4Mhz, so set that as well.
-                        #[cfg(not(feature = "wb"))]
-                        rcc.cr.modify(|_, w| unsafe {
-                            w.msirange().bits(range as u8);
-                            w.msirgsel().set_bit()
-                        });
-                        #[cfg(feature = "wb")]
-                        rcc.cr
-                            .modify(|_, w| unsafe { w.msirange().bits(range as u8) });
+        }
+    }
 
-                        if let StopWuck::Hsi = self.stop_wuck {
-                            rcc.cr.modify(|_, w| w.msion().set_bit());
+    /// The voltage range to use. This is `vos_range`, except on G4 with the deprecated
+    /// `boost_mode` field cleared.
+    fn vos(&self) -> VosRange {
+        #[cfg(feature = "g4")]
+        #[allow(deprecated)]
+        if !self.boost_mode && self.vos_range == VosRange::Range1Boost {
+            return VosRange::Range1;
+        }
+        self.vos_range
+    }
+
+    /// Calculate the flash wait states required for this configuration's HCLK, and voltage range.
+    fn flash_wait_state(&self) -> WaitState {
+        let hclk = self.flash_hclk();
+
+        cfg_if! {
+            if #[cfg(feature = "l4")] {  // RM section 3.3.3
+                let wait_state = if hclk <= 16_000_000 {
+                    WaitState::W0
+                } else if hclk <= 32_000_000 {
+                    WaitState::W1
+                } else if hclk <= 48_000_000 {
+                    WaitState::W2
+                } else if hclk <= 64_000_000 {
+                    WaitState::W3
+                } else {
+                    WaitState::W4
+                };
+            } else if #[cfg(feature = "l5")] {  // RM section 6.3.3
+                let wait_state = if hclk <= 20_000_000 {
+                    WaitState::W0
+                } else if hclk <= 40_000_000 {
+                    WaitState::W1
+                } else if hclk <= 60_000_000 {
+                    WaitState::W2
+                } else if hclk <= 80_000_000 {
+                    WaitState::W3
+                } else if hclk <= 100_000_000 {
+                    WaitState::W4
+                } else {
+                    WaitState::W5
+                };
+            } else if #[cfg(feature = "g0")] {  // G0. RM section 3.3.4
+                let wait_state = if hclk <= 24_000_000 {
+                    WaitState::W0
+                } else if hclk <= 48_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else if #[cfg(feature = "wb")] {  // WB. RM section 3.3.4, Table 4.
+                // Note: This applies to HCLK4 HCLK. (See HCLK4 used above for hclk var.)
+                let wait_state = if hclk <= 18_000_000 {
+                    WaitState::W0
+                } else if hclk <= 36_000_000 {
+                    WaitState::W1
+                } else if hclk <= 54_000_000 {
+                    WaitState::W2
+                } else {
+                    WaitState::W3
+                };
+            } else if #[cfg(any(feature = "wb", feature = "wl"))] {  // WL. RM section 3.3.4, Table 5.
+                // Note: This applies to HCLK3 HCLK. (See HCLK3 used above for hclk var.)
+                let wait_state = if hclk <= 18_000_000 {
+                    WaitState::W0
+                } else if hclk <= 36_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else {  // G4. RM section 3.3.3
+                let wait_state = if self.vos() == VosRange::Range1Boost {
+                    // Vcore Range 1 boost mode
+                    if hclk <= 34_000_000 {
+                        WaitState::W0
+                    } else if hclk <= 68_000_000 {
+                        WaitState::W1
+                    } else if hclk <= 102_000_000 {
+                        WaitState::W2
+                    } else if hclk <= 136_000_000 {
+                        WaitState::W3
+                    } else {
+                        WaitState::W4
+                    }
+                } else {
+                    // Vcore Range 1 normal mode.
+                    if hclk <= 30_000_000 {
+                        WaitState::W0
+                    } else if hclk <= 60_000_000 {
+                        WaitState::W1
+                    } else if hclk <= 90_000_000 {
+                        WaitState::W2
+                    } else if hclk <= 120_000_000 {
+                        WaitState::W3
+                    } else {
+                        WaitState::W4
+                    }
+                };
+            }
+        }
+
+        // Flash access is slower in the low-power voltage range, so it requires more wait states
+        // for a given HCLK.
+        cfg_if! {
+            if #[cfg(feature = "l4")] {
+                let range2_wait_state = if hclk <= 6_000_000 {
+                    WaitState::W0
+                } else if hclk <= 12_000_000 {
+                    WaitState::W1
+                } else if hclk <= 18_000_000 {
+                    WaitState::W2
+                } else {
+                    WaitState::W3
+                };
+            } else if #[cfg(feature = "l5")] {
+                let range2_wait_state = if hclk <= 8_000_000 {
+                    WaitState::W0
+                } else if hclk <= 16_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else if #[cfg(feature = "g0")] {
+                let range2_wait_state = if hclk <= 8_000_000 {
+                    WaitState::W0
+                } else {
+                    WaitState::W1
+                };
+            } else if #[cfg(feature = "g4")] {
+                let range2_wait_state = if hclk <= 12_000_000 {
+                    WaitState::W0
+                } else if hclk <= 24_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            } else {  // WB and WL
+                let range2_wait_state = if hclk <= 6_000_000 {
+                    WaitState::W0
+                } else if hclk <= 12_000_000 {
+                    WaitState::W1
+                } else {
+                    WaitState::W2
+                };
+            }
+        }
+
+        if self.vos() == VosRange::Range2 {
+            range2_wait_state
+        } else {
+            wait_state
+        }
+    }
+
+    /// Re-select input source; used after Stop and Standby modes, where the system reverts
+    /// to MSI or HSI after wake.
+    /// This reuses the stored configuration, and returns immediately if the input source is
+    /// still selected, so it's safe to run after any `wfi`.
+    pub fn reselect_input(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // If the configured source is still selected (eg the MCU didn't enter Stop, or it was
+        // already re-selected in a different context), there's nothing to do.
+        if self.input_is_selected() {
+            return;
+        }
+
+        // Re-select the input source; useful for changing input source, or reverting
+        // from stop or standby mode. This assumes we're on a clean init,
+        // or waking up from stop mode etc.
+
+        match self.input_src {
+            InputSrc::Hse(_) => {
+                self.enable_hse();
+
+                rcc.cfgr
+                    .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                while !self.input_is_selected() {}
+            }
+            InputSrc::Pll(pll_src) => {
+                // todo: DRY with above.
+                match pll_src {
+                    PllSrc::Hse(_) => {
+                        self.enable_hse();
+                    }
+                    PllSrc::Hsi => {
+                        #[cfg(any(feature = "l4", feature = "l5"))]
+                        // Generally reverts to MSI (see note below)
+                        if let StopWuck::Msi = self.stop_wuck {
+                            rcc.cr.modify(|_, w| w.hsion().set_bit());
+                            while rcc.cr.read().hsirdy().bit_is_clear() {}
+                        }
+                        // If on G, we'll already be on HSI, so need to take action.
+                    }
+                    #[cfg(not(any(feature = "g0", feature = "g4")))]
+                    PllSrc::Msi(range) => {
+                        // Range initializes to 4Mhz, so set that as well.
+                        #[cfg(not(feature = "wb"))]
+                        rcc.cr.modify(|_, w| unsafe {
+                            w.msirange().bits(range as u8);
+                            w.msirgsel().set_bit()
+                        });
+                        #[cfg(feature = "wb")]
+                        rcc.cr
+                            .modify(|_, w| unsafe { w.msirange().bits(range as u8) });
+
+                        if let StopWuck::Hsi = self.stop_wuck {
+                            rcc.cr.modify(|_, w| w.msion().set_bit());
 
                             while rcc.cr.read().msirdy().bit_is_clear() {}
                         }
@@ -1119,14 +1517,15 @@ impl Clocks {
                     PllSrc::None => (),
                 }
 
-                rcc.cr.modify(|_, w| w.pllon().clear_bit());
-                while rcc.cr.read().pllrdy().bit_is_set() {}
+                // The PLL is stopped in Stop mode, but its configuration is retained. Enable it,
+                // and switch to it once it's locked.
+                rcc.cr.modify(|_, w| w.pllon().set_bit());
+                while rcc.cr.read().pllrdy().bit_is_clear() {}
 
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
 
-                rcc.cr.modify(|_, w| w.pllon().set_bit());
-                while rcc.cr.read().pllrdy().bit_is_clear() {}
+                while !self.input_is_selected() {}
             }
             InputSrc::Hsi => {
                 {
@@ -1146,6 +1545,8 @@ impl Clocks {
 
                         rcc.cfgr
                             .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                        while !self.input_is_selected() {}
                     }
                 }
             }
@@ -1167,6 +1568,8 @@ impl Clocks {
 
                     rcc.cfgr
                         .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                    while !self.input_is_selected() {}
                 }
             }
             #[cfg(feature = "g0")]
@@ -1175,6 +1578,7 @@ impl Clocks {
                 while rcc.csr.read().lsirdy().bit_is_clear() {}
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+                while !self.input_is_selected() {}
             }
             #[cfg(feature = "g0")]
             InputSrc::Lse => {
@@ -1182,10 +1586,250 @@ impl Clocks {
                 while rcc.bdcr.read().lserdy().bit_is_clear() {}
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+                while !self.input_is_selected() {}
             }
         }
     }
 
+    /// Set the flash wait states, and wait for the setting to take effect.
+    fn set_flash_wait_state(wait_state: WaitState) {
+        let flash = unsafe { &(*FLASH::ptr()) };
+
+        flash
+            .acr
+            .modify(|_, w| unsafe { w.latency().bits(wait_state as u8) });
+        while flash.acr.read().latency().bits() != wait_state as u8 {}
+    }
+
+    /// Enable the HSE, and wait for it to be ready. The bypass, and WB tuning settings can only be
+    /// changed while the HSE is off, so they're set first.
+    fn enable_hse(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        if rcc.cr.read().hserdy().bit_is_set() {
+            return;
+        }
+
+        #[cfg(feature = "wb")]
+        if let Some(tuning) = self.hse_tuning {
+            if !self.hse_bypass {
+                // We use raw bits, since RCC_HSECR must be unlocked by writing a key to it, then
+                // written with the new value.
+                rcc.hsecr.write(|w| unsafe { w.bits(0xcafe_cafe) });
+                rcc.hsecr.write(|w| unsafe {
+                    w.bits(
+                        ((tuning.cap_tune as u32 & 0x3f) << 8)
+                            | ((tuning.current as u32) << 4)
+                            | ((tuning.sense as u32) << 3),
+                    )
+                });
+            }
+        }
+
+        rcc.cr.modify(|_, w| {
+            #[cfg(feature = "wl")]
+            return w.hsebyppwr().bit(self.hse_bypass);
+            #[cfg(not(feature = "wl"))]
+            w.hsebyp().bit(self.hse_bypass)
+        });
+        rcc.cr.modify(|_, w| w.hseon().set_bit());
+        // Wait for the HSE to be ready.
+        while rcc.cr.read().hserdy().bit_is_clear() {}
+    }
+
+    /// Change the system clock source at runtime, eg to drop to MSI at 2Mhz when idle, and return
+    /// to the PLL when busy. Prescalers, and other settings are unchanged. This adjusts flash wait
+    /// states before increasing speed, or after reducing it, updates this struct, and turns
+    /// off the PLL if it's no longer used.
+    ///
+    /// After the change, runs `clocks_changed()` on each listener, so they can update their clock-dependent
+    /// settings, eg baud rates. If the new configuration is invalid, returns an error, and makes
+    /// no changes. If a listener can't adapt to the new speeds, the change is kept, the remaining
+    /// listeners are still run, and the first listener's error is returned. The new speed must be
+    /// valid for the current voltage range; change that separately, with `set_vos_range()`. If
+    /// changing the PLL, or retuning the MSI that feeds it, while it's the system clock, we
+    /// temporarily switch to HSI16 while reconfiguring it.
+    pub fn change_sysclk(
+        &mut self,
+        input_src: InputSrc,
+        listeners: &mut [&mut dyn ClockListener],
+    ) -> Result<(), SpeedError> {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        let prev_src = self.input_src;
+        let prev_hclk = self.flash_hclk();
+
+        self.input_src = input_src;
+        if let Err(e) = self.validate_speeds() {
+            self.input_src = prev_src;
+            return Err(e);
+        }
+
+        let wait_state = self.flash_wait_state();
+        let increasing = self.flash_hclk() > prev_hclk;
+
+        // Wait states must be increased before increasing the clock speed.
+        if increasing {
+            Self::set_flash_wait_state(wait_state);
+        }
+
+        // The PLL can't be reconfigured while it's running, so switch away from it first. We do
+        // the same before changing MSIRANGE while MSI feeds the PLL, since SYSCLK would otherwise
+        // follow the new MSI speed, multiplied by the PLL, with the old wait states.
+        let switch_to_hsi = match (prev_src, input_src) {
+            (InputSrc::Pll(_), InputSrc::Pll(_)) => true,
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            (InputSrc::Pll(PllSrc::Msi(_)), InputSrc::Msi(_)) => true,
+            _ => false,
+        };
+
+        if switch_to_hsi {
+            rcc.cr.modify(|_, w| w.hsion().set_bit());
+            while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+            rcc.cfgr
+                .modify(|_, w| unsafe { w.sw().bits(InputSrc::Hsi.bits()) });
+            while rcc.cfgr.read().sws().bits() != InputSrc::Hsi.bits() {}
+        }
+
+        // Enable the new source's oscillator, and wait until it's ready. Unlike in `setup()`, we
+        // don't turn MSI off first, since it may be the system clock: MSIRANGE can be changed
+        // while MSI is ready.
+        let osc_src = match input_src {
+            InputSrc::Pll(pll_src) => match pll_src {
+                #[cfg(not(any(feature = "g0", feature = "g4")))]
+                PllSrc::Msi(range) => InputSrc::Msi(range),
+                PllSrc::Hse(freq) => InputSrc::Hse(freq),
+                _ => InputSrc::Hsi,
+            },
+            src => src,
+        };
+
+        match osc_src {
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            InputSrc::Msi(range) => {
+                if rcc.cr.read().msion().bit_is_set() {
+                    while rcc.cr.read().msirdy().bit_is_clear() {}
+                }
+                rcc.cr.modify(|_, w| unsafe {
+                    w.msirange().bits(range as u8);
+                    #[cfg(not(any(feature = "wb", feature = "wl")))]
+                    w.msirgsel().set_bit();
+                    w.msion().set_bit()
+                });
+                while rcc.cr.read().msirdy().bit_is_clear() {}
+            }
+            InputSrc::Hse(_) => {
+                self.enable_hse();
+            }
+            #[cfg(feature = "g0")]
+            InputSrc::Lsi => {
+                rcc.csr.modify(|_, w| w.lsion().set_bit());
+                while rcc.csr.read().lsirdy().bit_is_clear() {}
+            }
+            #[cfg(feature = "g0")]
+            InputSrc::Lse => {
+                rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+                while rcc.bdcr.read().lserdy().bit_is_clear() {}
+            }
+            _ => {
+                rcc.cr.modify(|_, w| w.hsion().set_bit());
+                while rcc.cr.read().hsirdy().bit_is_clear() {}
+            }
+        }
+
+        if let InputSrc::Pll(pll_src) = input_src {
+            rcc.cr.modify(|_, w| w.pllon().clear_bit());
+            while rcc.cr.read().pllrdy().bit_is_set() {}
+
+            // The remaining PLL settings, eg its P and Q outputs, are retained from `setup()`.
+            cfg_if! {
+                if #[cfg(feature = "g0")] {
+                    rcc.pllsyscfgr.modify(|_, w| unsafe {
+                        w.pllsrc().bits(pll_src.bits());
+                        w.plln().bits(self.pll.divn);
+                        w.pllm().bits(self.pll.divm as u8);
+                        w.pllr().bits(self.pll.divr as u8)
+                    });
+                } else {
+                    rcc.pllcfgr.modify(|_, w| unsafe {
+                        w.pllsrc().bits(pll_src.bits());
+                        w.plln().bits(self.pll.divn);
+                        w.pllm().bits(self.pll.divm as u8);
+                        w.pllr().bits(self.pll.divr as u8)
+                    });
+                }
+            }
+
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+        }
+
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.sw().bits(input_src.bits()) });
+        while !self.input_is_selected() {}
+
+        // Wait states can be reduced once the clock speed is reduced.
+        if !increasing {
+            Self::set_flash_wait_state(wait_state);
+        }
+
+        if let InputSrc::Pll(_) = prev_src {
+            if !matches!(input_src, InputSrc::Pll(_)) {
+                rcc.cr.modify(|_, w| w.pllon().clear_bit());
+            }
+        }
+
+        let mut result = Ok(());
+        for listener in listeners.iter_mut() {
+            let listener_result = listener.clocks_changed(self);
+            if result.is_ok() {
+                result = listener_result;
+            }
+        }
+
+        result
+    }
+
+    /// Recover from an HSE failure, detected by the clock security system: If the PLL was running
+    /// from HSE, re-configure it to run from HSI16, using the `pll` settings passed, and re-select
+    /// it; otherwise, run from HSI16 directly. Run this outside the NMI handler, eg from the main
+    /// loop, after the handler clears the failure flag with `clear_hse_css()`. Pass PLL settings that
+    /// give a similar speed from a 16Mhz input. Listeners are passed to `change_sysclk()`.
+    pub fn recover_from_hse_failure(
+        &mut self,
+        pll: PllCfg,
+        listeners: &mut [&mut dyn ClockListener],
+    ) -> Result<(), SpeedError> {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        let input_src = match self.input_src {
+            InputSrc::Pll(PllSrc::Hse(_)) => InputSrc::Pll(PllSrc::Hsi),
+            InputSrc::Hse(_) => InputSrc::Hsi,
+            // The HSE wasn't used for the system clock, so there's nothing to recover.
+            _ => return Ok(()),
+        };
+
+        let prev_pll = core::mem::replace(&mut self.pll, pll);
+
+        // Hardware has already switched the system clock away from HSE, and disabled the PLL if
+        // it was running from it; `change_sysclk` reconfigures the PLL from the current source.
+        let result = self.change_sysclk(input_src, listeners);
+
+        // If the change was rejected, the system clock source is unchanged. Otherwise, an error
+        // is from a listener, after the change.
+        if self.input_src != input_src {
+            self.pll = prev_pll;
+            return result;
+        }
+
+        // Hardware disables the HSE on failure; clear HSEON so it's not re-enabled on a
+        // transient recovery.
+        rcc.cr.modify(|_, w| w.hseon().clear_bit());
+
+        result
+    }
+
     #[cfg(any(feature = "l4", feature = "l5"))]
     /// Use this to change the MSI speed. Run this only if your clock source is MSI.
     /// Ends in a state with MSI on at the new speed, and HSI off.
@@ -1261,18 +1905,7 @@ impl Clocks {
     /// Get the sysclock frequency, in hz.
     pub fn sysclk(&self) -> u32 {
         match self.input_src {
-            InputSrc::Pll(pll_src) => {
-                let input_freq = match pll_src {
-                    #[cfg(not(any(feature = "g0", feature = "g4")))]
-                    PllSrc::Msi(range) => range.value() as u32,
-                    PllSrc::Hsi => 16_000_000,
-                    PllSrc::Hse(freq) => freq,
-                    PllSrc::None => unimplemented!(),
-                };
-                input_freq / self.pll.divm.value() as u32 * self.pll.divn as u32
-                    / self.pll.divr.value() as u32
-            }
-
+            InputSrc::Pll(_) => self.pllr_speed(1),
             #[cfg(not(any(feature = "g0", feature = "g4")))]
             InputSrc::Msi(range) => range.value() as u32,
             InputSrc::Hsi => 16_000_000,
@@ -1284,6 +1917,125 @@ impl Clocks {
         }
     }
 
+    /// Get the PLL input frequency, after the PLLM divider, in hz. This must be between 2.66 and
+    /// 16Mhz. PLLSAI1 and PLLSAI2 use the same input.
+    pub fn pll_input_speed(&self) -> u32 {
+        // The PLL input source is selected with `input_src`. If that's not the PLL, the PLL is
+        // off, so we treat its source as the system clock source.
+        let pll_src = match self.input_src {
+            InputSrc::Pll(pll_src) => pll_src,
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            InputSrc::Msi(range) => PllSrc::Msi(range),
+            InputSrc::Hsi => PllSrc::Hsi,
+            InputSrc::Hse(freq) => PllSrc::Hse(freq),
+            #[cfg(feature = "g0")]
+            InputSrc::Lsi | InputSrc::Lse => PllSrc::None,
+        };
+
+        let input_freq = match pll_src {
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            PllSrc::Msi(range) => range.value(),
+            PllSrc::Hsi => 16_000_000,
+            PllSrc::Hse(freq) => freq,
+            PllSrc::None => 0,
+        };
+
+        input_freq / self.pll.divm.value() as u32
+    }
+
+    /// Get a PLL's configuration. `pll_num` is 1 for the main PLL, 2 for PLLSAI1, and 3 for
+    /// PLLSAI2.
+    fn pll_cfg(&self, pll_num: u8) -> &PllCfg {
+        match pll_num {
+            1 => &self.pll,
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            2 => &self.pllsai1,
+            #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+            3 => &self.pllsai2,
+            _ => panic!("Invalid PLL number."),
+        }
+    }
+
+    /// Calculate a PLL's VCO output frequency, in hz: `pll_input_speed()` × PLLN. `pll_num` is 1 for
+    /// the main PLL, 2 for PLLSAI1, and 3 for PLLSAI2.
+    pub fn vco_output_freq(&self, pll_num: u8) -> u32 {
+        self.pll_input_speed() * self.pll_cfg(pll_num).divn as u32
+    }
+
+    /// Get a PLL's P output frequency, in hz. This is used by SAI, and on some variants, ADC.
+    /// See `vco_output_freq()` for `pll_num`.
+    pub fn pllp_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(pll_num) / self.pll_cfg(pll_num).pvalue() as u32
+    }
+
+    /// Get a PLL's Q output frequency, in hz. This is used by the 48Mhz clock for USB, and RNG.
+    /// See `vco_output_freq()` for `pll_num`.
+    pub fn pllq_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(pll_num) / self.pll_cfg(pll_num).divq.value() as u32
+    }
+
+    /// Get a PLL's R output frequency, in hz. For the main PLL, this is the system clock; for
+    /// PLLSAI1 and PLLSAI2, it's used by ADC. See `vco_output_freq()` for `pll_num`.
+    pub fn pllr_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(pll_num) / self.pll_cfg(pll_num).divr.value() as u32
+    }
+
+    /// Returns true if a PLL output is enabled; either by its `PllCfg` enable field, or since
+    /// it's selected as a kernel clock source, eg by `clk48_src`, or `sai1_src`. The main PLL's R
+    /// output is always enabled, since it's the system clock; on G0 and G4, its P and Q outputs
+    /// are too. See `vco_output_freq()` for `pll_num`.
+    pub fn pll_output_enabled(&self, pll_num: u8, output: PllOutput) -> bool {
+        let cfg = self.pll_cfg(pll_num);
+        let enabled = match output {
+            PllOutput::P => cfg.pllp_en,
+            PllOutput::Q => cfg.pllq_en,
+            PllOutput::R => cfg.pllr_en,
+        };
+        if enabled {
+            return true;
+        }
+
+        match (pll_num, output) {
+            (1, PllOutput::R) => true,
+            #[cfg(any(feature = "g0", feature = "g4"))]
+            (1, _) => true,
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            (1, PllOutput::Q) => self.clk48_src == Clk48Src::Pllq,
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            (1, PllOutput::P) => self.sai1_src == SaiSrc::Pllp,
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            (2, PllOutput::Q) => self.clk48_src == Clk48Src::PllSai1,
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            (2, PllOutput::P) => self.sai1_src == SaiSrc::PllSai1P,
+            _ => false,
+        }
+    }
+
+    /// Get a PLL output's frequency, in hz, or `None` if the PLL isn't running, or the output is
+    /// disabled. PLLSAI1, and PLLSAI2 only run when the main PLL is the system clock source.
+    /// See `vco_output_freq()` for `pll_num`.
+    pub fn pll_output_speed(&self, pll_num: u8, output: PllOutput) -> Option<u32> {
+        let running = matches!(self.input_src, InputSrc::Pll(_))
+            && (pll_num == 1 || self.pll_cfg(pll_num).enabled);
+
+        if !running || !self.pll_output_enabled(pll_num, output) {
+            return None;
+        }
+
+        Some(match output {
+            PllOutput::P => self.pllp_speed(pll_num),
+            PllOutput::Q => self.pllq_speed(pll_num),
+            PllOutput::R => self.pllr_speed(pll_num),
+        })
+    }
+
+    /// Check if the configured input source is currently selected as the system clock. (RCC_CFGR
+    /// SWS field) If not, eg after waking from Stop, run `reselect_input()`.
+    pub fn input_is_selected(&self) -> bool {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.cfgr.read().sws().bits() == self.input_src.bits()
+    }
+
     /// Check if the PLL is enabled. This is useful if checking whether to re-enable the PLL
     /// after exiting Stop or Standby modes, eg so you don't re-enable if it was already re-enabled
     /// in a different context. eg:
@@ -1314,15 +2066,19 @@ impl Clocks {
             }
         } else if #[cfg(feature = "g4")] {
             pub fn usb(&self) -> u32 {
-                48_000_000 // Uses hsi48.
+                match self.clk48_src {
+                    Clk48Src::Hsi48 => 48_000_000,
+                    Clk48Src::Pllq => self.pllq_speed(1),
+                }
             }
         } else { // L4 and L5
             pub fn usb(&self) -> u32 {
                 match self.clk48_src {
                     Clk48Src::Hsi48 => 48_000_000,
-                    Clk48Src::PllSai1 => unimplemented!(),
-                    Clk48Src::Pllq => unimplemented!(),
-                    Clk48Src::Msi => unimplemented!(),
+                    Clk48Src::PllSai1 => self.pllq_speed(2),
+                    Clk48Src::Pllq => self.pllq_speed(1),
+                    // Assumes MSI is at 48Mhz, eg using `enable_msi_48()`.
+                    Clk48Src::Msi => 48_000_000,
                 }
             }
         }
@@ -1378,58 +2134,97 @@ impl Clocks {
     /// Get the SAI audio clock frequency, in hz
     #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
     pub fn sai1_speed(&self) -> u32 {
-        let pll_src = match self.input_src {
-            InputSrc::Msi(msi_rng) => PllSrc::Msi(msi_rng),
-            InputSrc::Hsi => PllSrc::Hsi,
-            InputSrc::Hse(freq) => PllSrc::Hse(freq),
-            InputSrc::Pll(pll_src) => pll_src,
-        };
-
-        // todo: DRY with `sysclk`
-        let input_freq = match pll_src {
-            PllSrc::Msi(range) => range.value() as u32,
-            PllSrc::Hsi => 16_000_000,
-            PllSrc::Hse(freq) => freq,
-            PllSrc::None => unimplemented!(),
-        };
-
         match self.sai1_src {
-            SaiSrc::PllSai1P => {
-                input_freq / self.pll.divm.value() as u32 * self.pllsai1.divn as u32
-                    / self.pllsai1.pvalue() as u32
-            }
-            SaiSrc::Pllp => {
-                input_freq / self.pll.divm.value() as u32 * self.pll.divn as u32
-                    / self.pll.pvalue() as u32
-            }
+            SaiSrc::PllSai1P => self.pllp_speed(2),
+            SaiSrc::Pllp => self.pllp_speed(1),
             SaiSrc::Hsi => 16_000_000,
             SaiSrc::ExtClk => unimplemented!(),
         }
     }
 
-    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        #[cfg(feature = "l4")]
-        let max_clock = 80_000_000;
+    /// Get a U[S]ART's kernel clock frequency, in hz, reading its selection from RCC_CCIPR. This is
+    /// what its baud rate is computed from.
+    pub fn usart_kernel(&self, periph: UsartPeriph) -> u32 {
+        match usart_clk_src(periph) {
+            UsartClkSrc::Pclk => {
+                // USART1 is on APB2; the others are on APB1.
+                if periph == UsartPeriph::Usart1 {
+                    self.apb2()
+                } else {
+                    self.apb1()
+                }
+            }
+            UsartClkSrc::Sysclk => self.sysclk(),
+            UsartClkSrc::Hsi16 => 16_000_000,
+            UsartClkSrc::Lse => 32_768,
+        }
+    }
 
-        #[cfg(feature = "l5")]
-        let max_clock = 110_000_000;
+    /// Get an I2C peripheral's kernel clock frequency, in hz, reading its selection from RCC_CCIPR.
+    pub fn i2c_kernel(&self, periph: I2cPeriph) -> u32 {
+        match i2c_clk_src(periph) {
+            I2cClkSrc::Pclk => self.apb1(),
+            I2cClkSrc::Sysclk => self.sysclk(),
+            I2cClkSrc::Hsi16 => 16_000_000,
+        }
+    }
 
-        #[cfg(feature = "g0")]
-        let max_clock = 64_000_000;
+    /// Get the I2S kernel clock frequency, in hz, reading its selection from RCC_CCIPR. This is
+    /// what the audio frequency is computed from. Returns `None` if the source is the I2S_CKIN
+    /// pin, since its frequency isn't known here, or a PLL output that isn't running.
+    #[cfg(any(feature = "g0", feature = "g4"))]
+    pub fn i2s_kernel(&self) -> Option<u32> {
+        match i2s_clk_src() {
+            I2sClkSrc::Sysclk => Some(self.sysclk()),
+            #[cfg(feature = "g0")]
+            I2sClkSrc::Pll => self.pll_output_speed(1, PllOutput::P),
+            #[cfg(feature = "g4")]
+            I2sClkSrc::Pll => self.pll_output_speed(1, PllOutput::Q),
+            I2sClkSrc::Hsi16 => Some(16_000_000),
+            I2sClkSrc::Ckin => None,
+        }
+    }
 
-        #[cfg(feature = "g4")]
-        let max_clock = 170_000_000;
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+    /// Check that this configuration is valid for low-power run, and low-power sleep modes:
+    /// SYSCLK must be 2Mhz or lower. eg MSI at 2Mhz or lower, or HSI with an AHB prescaler.
+    /// See L4 RM, section 5.3.2: Low-power run mode.
+    pub fn validate_low_power_run(&self) -> Result<(), SpeedError> {
+        if self.sysclk() > 2_000_000 {
+            return Err(SpeedError::new(
+                "Sysclk must be 2Mhz or lower for low-power run",
+            ));
+        }
 
-        #[cfg(feature = "wb")]
-        let max_clock = 64_000_000;
+        if let InputSrc::Pll(_) = self.input_src {
+            return Err(SpeedError::new("The PLL can't be used in low-power run"));
+        }
 
-        #[cfg(feature = "wl")]
-        let max_clock = 48_000_000;
+        Ok(())
+    }
+
+    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
+        let max_clock = max_sysclk(self.vos(), self.temperature_grade);
+
+        if let InputSrc::Pll(_) = self.input_src {
+            if !(PLL_IN_MIN..=PLL_IN_MAX).contains(&self.pll_input_speed()) {
+                return Err(SpeedError::new("PLL input speed out of limits"));
+            }
+
+            if !(VCO_MIN..=VCO_MAX).contains(&self.vco_output_freq(1)) {
+                return Err(SpeedError::new("PLL VCO speed out of limits"));
+            }
 
-        // todo: Check valid PLL output range as well. You can use Cube, mousing over the PLL
-        // todo speed to find these.
+            #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+            if self.pllsai1.enabled && !(VCO_MIN..=VCO_MAX).contains(&self.vco_output_freq(2)) {
+                return Err(SpeedError::new("PLLSAI1 VCO speed out of limits"));
+            }
 
-        // todo: L4+ (ie R, S, P, Q) can go up to 120_000.
+            #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+            if self.pllsai2.enabled && !(VCO_MIN..=VCO_MAX).contains(&self.vco_output_freq(3)) {
+                return Err(SpeedError::new("PLLSAI2 VCO speed out of limits"));
+            }
+        }
 
         #[cfg(any(feature = "l4", feature = "l5", feature = "wb"))]
         if self.pll.divn < 7
@@ -1464,15 +2259,13 @@ impl Clocks {
             return Err(SpeedError::new("A PLL divider is out of limits"));
         }
 
-        // todo: on WB, input src / PlLM * plln Must be between 96 and 344 Mhz.
-        // todo; Cube will validate this. Others probably have a similar restriction.
-        // todo: Put this check here.
-
         // todo: QC these limits
         // todo: Note that this involves repeatedly calculating sysclk.
         // todo. We could work around thsi by calcing it once here.
         if self.sysclk() > max_clock {
-            return Err(SpeedError::new("Sysclk out of limits"));
+            return Err(SpeedError::new(
+                "Sysclk out of limits for this voltage range and temperature grade",
+            ));
         }
 
         // todo: What are the actual hclk limits? Not always sysclk?
@@ -1494,6 +2287,7 @@ impl Clocks {
     }
 }
 
+#[allow(deprecated)] // `boost_mode`
 impl Default for Clocks {
     /// This default configures clocks with a HSI, with system and peripheral clocks at full rated speed.
     /// All peripheral. Speeds -> L4: 80Mhz. L5: 110Mhz. G0: 64Mhz. G4: 170Mhz. WB: 64Mhz.
@@ -1518,6 +2312,8 @@ impl Default for Clocks {
             #[cfg(not(any(feature = "g0", feature = "wl")))]
             clk48_src: Clk48Src::Hsi48,
             hse_bypass: false,
+            #[cfg(feature = "wb")]
+            hse_tuning: None,
             security_system: false,
             #[cfg(not(any(feature = "g0", feature = "wl")))]
             hsi48_on: false,
@@ -1527,10 +2323,208 @@ impl Default for Clocks {
             rf_wakeup_src: RfWakeupSrc::Lse,
             #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
             sai1_src: SaiSrc::Pllp,
+            #[cfg(feature = "l5")]
+            vos_range: VosRange::Range0,
+            #[cfg(feature = "g4")]
+            vos_range: VosRange::Range1Boost,
+            #[cfg(not(any(feature = "l5", feature = "g4")))]
+            vos_range: VosRange::Range1,
             #[cfg(feature = "g4")]
             boost_mode: true,
+            temperature_grade: TemperatureGrade::Grade6,
+        }
+    }
+}
+
+// RCC_ICSCR HSICAL, and HSITRIM field positions and widths vary by family.
+cfg_if! {
+    if #[cfg(feature = "g0")] {
+        const HSICAL_SHIFT: u32 = 0;
+        const HSITRIM_SHIFT: u32 = 8;
+        const HSITRIM_MASK: u32 = 0x7f;
+    } else if #[cfg(feature = "l4")] {
+        const HSICAL_SHIFT: u32 = 16;
+        const HSITRIM_SHIFT: u32 = 24;
+        const HSITRIM_MASK: u32 = 0x1f;
+    } else {
+        const HSICAL_SHIFT: u32 = 16;
+        const HSITRIM_SHIFT: u32 = 24;
+        const HSITRIM_MASK: u32 = 0x7f;
+    }
+}
+
+/// Read the HSI16 factory calibration value. (RCC_ICSCR, HSICAL field)
+pub fn hsi_calibration() -> u8 {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    (rcc.icscr.read().bits() >> HSICAL_SHIFT) as u8
+}
+
+/// Read the HSI16 trim value. (RCC_ICSCR, HSITRIM field)
+pub fn hsi_trim() -> u8 {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    ((rcc.icscr.read().bits() >> HSITRIM_SHIFT) & HSITRIM_MASK) as u8
+}
+
+/// Adjust the HSI16 trim value, which is added to the factory calibration, eg to compensate for
+/// temperature, or after measuring HSI16 against the LSE with a timer. The default is 16 on L4,
+/// and 64 on others, and each step is about 0.2%, or 32-40kHz, depending on the family.
+/// (RCC_ICSCR, HSITRIM field) See L4 RM, section 6.2.2: HSI16 clock.
+pub fn set_hsi_trim(trim: u8) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    assert!(trim as u32 <= HSITRIM_MASK);
+    rcc.icscr.modify(|r, w| unsafe {
+        w.bits((r.bits() & !(HSITRIM_MASK << HSITRIM_SHIFT)) | ((trim as u32) << HSITRIM_SHIFT))
+    });
+}
+
+/// Read a 2-bit kernel clock selection field from RCC_CCIPR (RCC_CCIPR1 on L5). We use raw bits,
+/// since field names and types vary by family.
+fn ccipr_field(shift: u8) -> u8 {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    #[cfg(feature = "l5")]
+    let val = rcc.ccipr1.read().bits();
+    #[cfg(not(feature = "l5"))]
+    let val = rcc.ccipr.read().bits();
+
+    ((val >> shift) & 0b11) as u8
+}
+
+/// Write a 2-bit kernel clock selection field in RCC_CCIPR (RCC_CCIPR1 on L5).
+fn set_ccipr_field(shift: u8, val: u8) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let mask = !(0b11 << shift);
+
+    #[cfg(feature = "l5")]
+    rcc.ccipr1
+        .modify(|r, w| unsafe { w.bits((r.bits() & mask) | ((val as u32) << shift)) });
+    #[cfg(not(feature = "l5"))]
+    rcc.ccipr
+        .modify(|r, w| unsafe { w.bits((r.bits() & mask) | ((val as u32) << shift)) });
+}
+
+/// Select a U[S]ART's kernel clock. Run this before initializing the U[S]ART, or call its
+/// `set_baud()` afterwards, since the baud rate is computed from this clock. If using HSI16, or LSE,
+/// make sure it's enabled.
+pub fn set_usart_clk_src(periph: UsartPeriph, src: UsartClkSrc) {
+    set_ccipr_field(periph as u8, src as u8);
+}
+
+/// Read a U[S]ART's kernel clock selection.
+pub fn usart_clk_src(periph: UsartPeriph) -> UsartClkSrc {
+    match ccipr_field(periph as u8) {
+        0b00 => UsartClkSrc::Pclk,
+        0b01 => UsartClkSrc::Sysclk,
+        0b10 => UsartClkSrc::Hsi16,
+        _ => UsartClkSrc::Lse,
+    }
+}
+
+/// Select an I2C peripheral's kernel clock. Run this before initializing the I2C peripheral,
+/// since its timing is computed from this clock. If using HSI16, make sure it's enabled.
+pub fn set_i2c_clk_src(periph: I2cPeriph, src: I2cClkSrc) {
+    #[cfg(any(
+        feature = "l5",
+        feature = "g471",
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484"
+    ))]
+    if periph == I2cPeriph::I2c4 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.ccipr2
+            .modify(|r, w| unsafe { w.bits((r.bits() & !0b11) | src as u32) });
+        return;
+    }
+
+    set_ccipr_field(periph as u8, src as u8);
+}
+
+/// Read an I2C peripheral's kernel clock selection.
+pub fn i2c_clk_src(periph: I2cPeriph) -> I2cClkSrc {
+    #[cfg(any(
+        feature = "l5",
+        feature = "g471",
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484"
+    ))]
+    let val = if periph == I2cPeriph::I2c4 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        (rcc.ccipr2.read().bits() & 0b11) as u8
+    } else {
+        ccipr_field(periph as u8)
+    };
+    #[cfg(not(any(
+        feature = "l5",
+        feature = "g471",
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484"
+    )))]
+    let val = ccipr_field(periph as u8);
+
+    match val {
+        0b00 => I2cClkSrc::Pclk,
+        0b01 => I2cClkSrc::Sysclk,
+        // 0b11 is reserved.
+        _ => I2cClkSrc::Hsi16,
+    }
+}
+
+#[cfg(any(feature = "g0", feature = "g4"))]
+/// Select the I2S kernel clock. Run this before initializing I2S, since its audio frequency is
+/// computed from this clock. If using HSI16, make sure it's enabled.
+pub fn set_i2s_clk_src(src: I2sClkSrc) {
+    cfg_if! {
+        if #[cfg(any(feature = "g0b1", feature = "g0c1"))] {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ccipr2
+                .modify(|r, w| unsafe { w.bits((r.bits() & !0b11) | i2s_sel_bits(src) as u32) });
+        } else if #[cfg(feature = "g0")] {
+            set_ccipr_field(14, i2s_sel_bits(src));
+        } else {
+            set_ccipr_field(22, i2s_sel_bits(src));
+        }
+    }
+}
+
+#[cfg(any(feature = "g0", feature = "g4"))]
+/// Read the I2S kernel clock selection.
+pub fn i2s_clk_src() -> I2sClkSrc {
+    cfg_if! {
+        if #[cfg(any(feature = "g0b1", feature = "g0c1"))] {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            let val = (rcc.ccipr2.read().bits() & 0b11) as u8;
+        } else if #[cfg(feature = "g0")] {
+            let val = ccipr_field(14);
+        } else {
+            let val = ccipr_field(22);
         }
     }
+
+    // The field encodings differ: G0's I2S1SEL puts HSI16 before I2S_CKIN, and G4's I2S23SEL after.
+    match (val, cfg!(feature = "g0")) {
+        (0b00, _) => I2sClkSrc::Sysclk,
+        (0b01, _) => I2sClkSrc::Pll,
+        (0b10, true) | (0b11, false) => I2sClkSrc::Hsi16,
+        _ => I2sClkSrc::Ckin,
+    }
+}
+
+#[cfg(any(feature = "g0", feature = "g4"))]
+/// Field value for an I2S kernel clock selection. See `i2s_clk_src()`.
+fn i2s_sel_bits(src: I2sClkSrc) -> u8 {
+    match (src, cfg!(feature = "g0")) {
+        (I2sClkSrc::Sysclk, _) => 0b00,
+        (I2sClkSrc::Pll, _) => 0b01,
+        (I2sClkSrc::Hsi16, true) | (I2sClkSrc::Ckin, false) => 0b10,
+        _ => 0b11,
+    }
 }
 
 #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb"))]
@@ -1542,6 +2536,7 @@ impl Default for Clocks {
 /// external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
 /// startup it is also possible to combine automatic trimming with manual trimming action."
 /// Note: This is for HSI48 only. Note that the HSI will turn off after entering Stop or Standby.
+/// For more control, eg manual trimming, and status, use the `crs` module.
 pub fn enable_crs(sync_src: CrsSyncSrc) {
     let crs = unsafe { &(*CRS::ptr()) };
     let rcc = unsafe { &(*RCC::ptr()) };
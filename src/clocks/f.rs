@@ -372,6 +372,16 @@ pub struct Clocks {
 
     #[cfg(feature = "f4")]
     pub pllq: Pllq, // USB prescaler, for target of 48Mhz.
+    #[cfg(all(feature = "f4", not(feature = "f410")))]
+    /// Enable PLLI2S, which clocks I2S. It shares the main PLL's input and `pllm` divider. If the
+    /// PLL isn't the input source, PLLI2S is fed from the oscillator used as SYSCLK.
+    pub plli2s_enabled: bool,
+    #[cfg(all(feature = "f4", not(feature = "f410")))]
+    /// PLLI2S VCO multiplier. 50 - 432.
+    pub plli2sn: u16,
+    #[cfg(all(feature = "f4", not(feature = "f410")))]
+    /// PLLI2S R divider, which produces the I2S clock. 2 - 7.
+    pub plli2sr: u8,
     #[cfg(feature = "f3")]
     pub usb_pre: UsbPrescaler, // USB prescaler, for target of 48Mhz.
     /// The value to divide SYSCLK by, to get systick and peripheral clocks. Also known as AHB divider
@@ -380,8 +390,8 @@ pub struct Clocks {
     pub apb1_prescaler: ApbPrescaler,
     /// The divider of HCLK to get the APB2 peripheral clock
     pub apb2_prescaler: ApbPrescaler,
-    /// Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
-    /// frees up the pin for use as GPIO.
+    /// Bypass the HSE oscillator, for use with an external clock signal on OSC_IN, eg from an
+    /// oscillator or TCXO, instead of a crystal. Frees up OSC_OUT for use as GPIO.
     pub hse_bypass: bool,
     pub security_system: bool,
 }
@@ -453,9 +463,7 @@ impl Clocks {
         // Enable oscillators, and wait until ready.
         match self.input_src {
             InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().bit(true));
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().is_not_ready() {}
+                self.enable_hse();
             }
             InputSrc::Hsi => {
                 rcc.cr.modify(|_, w| w.hsion().bit(true));
@@ -465,8 +473,7 @@ impl Clocks {
                 match pll_src {
                     PllSrc::Hse(_) => {
                         // DRY
-                        rcc.cr.modify(|_, w| w.hseon().bit(true));
-                        while rcc.cr.read().hserdy().is_not_ready() {}
+                        self.enable_hse();
                     }
                     _ => {
                         // Hsi or HsiDiv2: In both cases, set up the HSI.
@@ -476,11 +483,6 @@ impl Clocks {
                 }
             }
         }
-        rcc.cr.modify(|_, w| {
-            // Enable bypass mode on HSE, since we're using a ceramic oscillator.
-            w.hsebyp().bit(self.hse_bypass)
-        });
-
         if let InputSrc::Pll(pll_src) = self.input_src {
             // Turn off the PLL: Required for modifying some of the settings below.
             rcc.cr.modify(|_, w| w.pllon().off());
@@ -519,6 +521,39 @@ impl Clocks {
             while rcc.cr.read().pllrdy().is_not_ready() {}
         }
 
+        #[cfg(all(feature = "f4", not(feature = "f410")))]
+        if self.plli2s_enabled {
+            // PLLI2SCFGR can only be written while PLLI2S is off.
+            rcc.cr.modify(|_, w| w.plli2son().off());
+            while rcc.cr.read().plli2srdy().is_ready() {}
+
+            // PLLI2S doesn't depend on the SYSCLK switch; if the main PLL isn't in use, point its
+            // shared source and M divider at the SYSCLK oscillator.
+            if !matches!(self.input_src, InputSrc::Pll(_)) {
+                rcc.pllcfgr.modify(|_, w| unsafe {
+                    w.pllsrc().bit(matches!(self.input_src, InputSrc::Hse(_)));
+                    w.pllm().bits(self.pllm)
+                });
+            }
+
+            rcc.plli2scfgr.modify(|_, w| unsafe {
+                // These variants have a dedicated PLLI2S M divider; match the main PLL's, so
+                // both share the same VCO input.
+                #[cfg(any(
+                    feature = "f411",
+                    feature = "f412",
+                    feature = "f413",
+                    feature = "f446"
+                ))]
+                w.plli2sm().bits(self.pllm);
+                w.plli2sn().bits(self.plli2sn);
+                w.plli2sr().bits(self.plli2sr)
+            });
+
+            rcc.cr.modify(|_, w| w.plli2son().on());
+            while rcc.cr.read().plli2srdy().is_not_ready() {}
+        }
+
         rcc.cfgr.modify(|_, w| unsafe {
             #[cfg(not(any(feature = "f301", feature = "f3x4", feature = "f4")))]
             w.usbpre().bit(self.usb_pre.bit()); // eg: Divide by 1.5: 72/1.5 = 48Mhz, required by USB clock.
@@ -555,35 +590,59 @@ impl Clocks {
         Ok(())
     }
 
+    /// Enable the HSE, and wait for it to be ready. The bypass setting can only be changed while
+    /// the HSE is off, so it's set first.
+    fn enable_hse(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        if rcc.cr.read().hserdy().is_ready() {
+            return;
+        }
+
+        rcc.cr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
+        rcc.cr.modify(|_, w| w.hseon().bit(true));
+        // Wait for the HSE to be ready.
+        while rcc.cr.read().hserdy().is_not_ready() {}
+    }
+
     /// Re-select innput source; used on Stop and Standby modes, where the system reverts
     /// to HSI after wake.
+    /// This reuses the stored configuration, and returns immediately if the input source is
+    /// still selected, so it's safe to run after any `wfi`.
     pub fn reselect_input(&self) {
         let rcc = unsafe { &(*RCC::ptr()) };
+
+        // If the configured source is still selected (eg the MCU didn't enter Stop, or it was
+        // already re-selected in a different context), there's nothing to do.
+        if self.input_is_selected() {
+            return;
+        }
         // Re-select the input source; it will revert to HSI during `Stop` or `Standby` mode.
 
         // Note: It would save code repetition to pass the `Clocks` struct in and re-run setup
         // todo: But this saves a few reg writes.
         match self.input_src {
             InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().is_not_ready() {}
+                self.enable_hse();
 
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                while !self.input_is_selected() {}
             }
             InputSrc::Pll(_) => {
                 // todo: DRY with above.
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().is_not_ready() {}
+                self.enable_hse();
 
-                rcc.cr.modify(|_, w| w.pllon().off());
-                while rcc.cr.read().pllrdy().is_ready() {}
+                // The PLL is stopped in Stop mode, but its configuration is retained. Enable it,
+                // and switch to it once it's locked.
+                rcc.cr.modify(|_, w| w.pllon().on());
+                while rcc.cr.read().pllrdy().is_not_ready() {}
 
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
 
-                rcc.cr.modify(|_, w| w.pllon().on());
-                while rcc.cr.read().pllrdy().is_not_ready() {}
+                while !self.input_is_selected() {}
             }
             InputSrc::Hsi => (), // Already reset to this.
         }
@@ -620,6 +679,13 @@ impl Clocks {
         }
     }
 
+    /// Check if the configured input source is currently selected as the system clock. (RCC_CFGR
+    /// SWS field) If not, eg after waking from Stop, run `reselect_input()`.
+    pub fn input_is_selected(&self) -> bool {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.cfgr.read().sws().bits() == self.input_src.bits()
+    }
+
     /// Check if the PLL is enabled. This is useful if checking wheather to re-enable the PLL
     /// after exiting Stop or Standby modes, eg so you don't re-enable if it was already re-enabled
     /// in a different context. eg:
@@ -641,11 +707,54 @@ impl Clocks {
         self.hclk()
     }
 
+    /// Get the USB clock frequency, in Hz. On F3, this is the PLL output divided by the USB
+    /// prescaler. On F4, it's the PLL's Q output.
     pub fn usb(&self) -> u32 {
         #[cfg(feature = "f3")]
-        return self.sysclk() / self.usb_pre.value() as u32;
+        return (self.sysclk() as f32 / self.usb_pre.value()) as u32;
+
         #[cfg(feature = "f4")]
-        return 0; // todo
+        return match self.input_src {
+            InputSrc::Pll(pll_src) => {
+                let input_freq = match pll_src {
+                    PllSrc::Hsi => 16_000_000,
+                    PllSrc::Hse(freq) => freq,
+                };
+                input_freq / self.pllm as u32 * self.plln as u32 / self.pllq.value() as u32
+            }
+            // The PLL must be the system clock source for USB, in this library's configuration.
+            _ => 0,
+        };
+    }
+
+    /// Get the I2S kernel clock frequency, in Hz. On F3, this is SYSCLK. On F4, it's PLLI2S's R
+    /// output, or `None` if PLLI2S isn't enabled.
+    pub fn i2s_kernel(&self) -> Option<u32> {
+        #[cfg(feature = "f3")]
+        return Some(self.sysclk());
+
+        #[cfg(feature = "f410")]
+        return None;
+
+        #[cfg(all(feature = "f4", not(feature = "f410")))]
+        return if self.plli2s_enabled {
+            Some(
+                self.pll_input_freq() / self.pllm as u32 * self.plli2sn as u32
+                    / self.plli2sr as u32,
+            )
+        } else {
+            None
+        };
+    }
+
+    #[cfg(all(feature = "f4", not(feature = "f410")))]
+    /// The frequency of the main PLL and PLLI2S's shared input. This is the PLL source if the PLL
+    /// is the input source; otherwise, it's the oscillator used as SYSCLK.
+    fn pll_input_freq(&self) -> u32 {
+        match self.input_src {
+            InputSrc::Hsi | InputSrc::Pll(PllSrc::Hsi) => 16_000_000,
+            InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+        }
     }
 
     pub fn apb1(&self) -> u32 {
@@ -692,6 +801,13 @@ impl Clocks {
             return Err(SpeedError::new("A PLL divider is out of limits"));
         }
 
+        #[cfg(all(feature = "f4", not(feature = "f410")))]
+        if self.plli2s_enabled
+            && (!(50..=432).contains(&self.plli2sn) || !(2..=7).contains(&self.plli2sr))
+        {
+            return Err(SpeedError::new("A PLLI2S divider is out of limits"));
+        }
+
         let max_hclk = max_clock;
 
         // todo: min clock? eg for apxb?
@@ -759,6 +875,12 @@ impl Default for Clocks {
             plln,
             pllp: Pllp::Div2,
             pllq: Pllq::Div8, // Note that this produces an invalid USB speed.
+            #[cfg(not(feature = "f410"))]
+            plli2s_enabled: false,
+            #[cfg(not(feature = "f410"))]
+            plli2sn: 192,
+            #[cfg(not(feature = "f410"))]
+            plli2sr: 2,
             hclk_prescaler: HclkPrescaler::Div1,
             #[cfg(any(feature = "f401", feature = "f410", feature = "f411"))]
             apb1_prescaler: ApbPrescaler::Div2,
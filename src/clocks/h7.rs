@@ -5,7 +5,7 @@
 // Similar in from to the `baseline` clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
+    clocks::{SpeedError, TemperatureGrade},
     pac::{CRS, FLASH, PWR, RCC},
 };
 
@@ -94,10 +94,10 @@ impl InputSrc {
 }
 
 /// Configures the speeds, and enable status of an individual PLL. Note that the `enable`
-/// field has no effect for PLL1.
+/// field has no effect for PLL1. Outputs selected as a kernel clock source, eg by
+/// `Clocks::sai1_src`, are enabled even if their enable field isn't set.
 pub struct PllCfg {
     pub enabled: bool,
-    // pub fractional: bool,
     pub pllp_en: bool,
     pub pllq_en: bool,
     pub pllr_en: bool,
@@ -106,6 +106,15 @@ pub struct PllCfg {
     pub divp: u8,
     pub divq: u8,
     pub divr: u8,
+    /// The fractional part of the multiplication factor, in steps of 1/8192:
+    /// VCO = input × (DIVN + FRACN / 8192). This allows fine-tuning audio clocks, eg for 44.1kHz
+    /// sample rates. Only used if `frac_en` is set. Sets RCC_PLLxFRACR, FRACNx field. See
+    /// `set_output_freq()` to calculate this, and `Clocks::set_fracn()` to change it while running.
+    pub fracn: u16,
+    /// Run the PLL in fractional mode, using `fracn`. Set this to change `fracn` while the PLL
+    /// is running with `Clocks::set_fracn()`, even if it starts at 0. Sets RCC_PLLCFGR,
+    /// PLLxFRACEN field.
+    pub frac_en: bool,
 }
 
 impl Default for PllCfg {
@@ -113,7 +122,6 @@ impl Default for PllCfg {
     fn default() -> Self {
         Self {
             enabled: true,
-            // fractional: false,
             pllp_en: true,
             pllq_en: false,
             pllr_en: false,
@@ -126,6 +134,8 @@ impl Default for PllCfg {
             divp: 2,
             divq: 2, // Allows <150Mhz SAI clock, if it's configureud for PLL1Q.
             divr: 2,
+            fracn: 0,
+            frac_en: false,
         }
     }
 }
@@ -140,6 +150,98 @@ impl PllCfg {
             ..Default::default()
         }
     }
+
+    /// Set DIVN, FRACN, and one output's divider, to produce `freq` (in Hz) from the PLL's input
+    /// frequency (before DIVM), using the current DIVM. Picks the lowest valid VCO frequency, to
+    /// reduce power use. Returns the resulting output frequency, which is within 1/8192 of the
+    /// PLL reference clock of the target; eg a 49.152Mhz SAI master clock (1_024 × 48kHz) from a
+    /// 25Mhz HSE, with DIVM = 5, is 0.1ppm high. Enables the output, and fractional mode if the
+    /// result has a fractional part.
+    ///
+    /// Set the other outputs' dividers to values that divide this VCO frequency as needed; eg to
+    /// get an exact 48Mhz USB clock, and an audio clock at the same time, use a fractional PLL for
+    /// the audio clock, and an integer one (or the HSI48) for USB.
+    pub fn set_output_freq(
+        &mut self,
+        input_freq: u32,
+        output: PllOutput,
+        freq: u32,
+    ) -> Result<u32, SpeedError> {
+        let ref_freq = input_freq / self.divm as u32;
+        if !(1_000_000..=16_000_000).contains(&ref_freq) || freq == 0 {
+            return Err(SpeedError::new("Invalid PLL input speed"));
+        }
+        let (vco_min, vco_max) = vco_range(ref_freq);
+
+        // DIVP1 can't be odd, so we use even P dividers for all PLLs.
+        let (div_min, div_step): (u32, usize) = match output {
+            PllOutput::P => (2, 2),
+            _ => (1, 1),
+        };
+
+        for div in (div_min..=128).step_by(div_step) {
+            let vco = freq as u64 * div as u64;
+            if vco < vco_min as u64 {
+                continue;
+            }
+            if vco > vco_max as u64 {
+                break;
+            }
+
+            // VCO = ref × (DIVN + FRACN / 8192), rounded to the nearest FRACN step.
+            let mult = (vco * 8_192 + ref_freq as u64 / 2) / ref_freq as u64;
+            let divn = (mult / 8_192) as u16;
+            if !(4..=512).contains(&divn) {
+                continue;
+            }
+
+            self.divn = divn;
+            self.fracn = (mult % 8_192) as u16;
+            if self.fracn != 0 {
+                self.frac_en = true;
+            }
+            match output {
+                PllOutput::P => {
+                    self.divp = div as u8;
+                    self.pllp_en = true;
+                }
+                PllOutput::Q => {
+                    self.divq = div as u8;
+                    self.pllq_en = true;
+                }
+                PllOutput::R => {
+                    self.divr = div as u8;
+                    self.pllr_en = true;
+                }
+            }
+
+            return Ok((ref_freq as u64 * mult / 8_192 / div as u64) as u32);
+        }
+
+        Err(SpeedError::new("No PLL settings produce this frequency"))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A PLL output. (P, Q, or R divider)
+pub enum PllOutput {
+    P,
+    Q,
+    R,
+}
+
+/// The valid VCO output range, in Hz, for a PLL reference (post-DIVM) frequency. This matches
+/// the VCO selection in `Clocks::setup()`: References below 2Mhz require the medium range (VCOL).
+/// The RM places 2Mhz itself in VCOL too, but the datasheet allows the wide range (VCOH) from 2Mhz,
+/// which the default configurations rely on.
+fn vco_range(ref_freq: u32) -> (u32, u32) {
+    if ref_freq < 2_000_000 {
+        // Medium VCO range.
+        (150_000_000, 420_000_000)
+    } else {
+        // Wide VCO range. The RM lists 836Mhz, but revision "V" supports 960Mhz.
+        (192_000_000, 960_000_000)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -217,6 +319,28 @@ pub enum DfsdmSrc {
     Sysclk = 1,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SDMMC1 and SDMMC2 kernel clock source. Sets RCC_D1CCIPR register, SDMMCSEL field.
+pub enum SdmmcSrc {
+    /// pll1_q_ck is selected as SDMMC kernel clock (default after reset)
+    Pll1Q = 0,
+    /// pll2_r_ck is selected as SDMMC kernel clock
+    Pll2R = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// FDCAN kernel clock source. Sets RCC_D2CCIP1R register, FDCANSEL field.
+pub enum FdCanSrc {
+    /// hse_ck is selected as FDCAN kernel clock (default after reset)
+    Hse = 0b00,
+    /// pll1_q_ck is selected as FDCAN kernel clock
+    Pll1Q = 0b01,
+    /// pll2_q_ck is selected as FDCAN kernel clock
+    Pll2Q = 0b10,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 /// Clock divider for the HSI. See RCC_CR register, HSIDIV field.
@@ -331,6 +455,46 @@ impl VosRange {
     }
 }
 
+/// Find the maximum sysclk speed for a given voltage scale and temperature grade. Above 105°C
+/// junction temperature, VOS0 isn't available, and these devices are limited to VOS1 speeds.
+/// See the datasheet, Table 23: General operating conditions (H743), or Table 23 (H723-35).
+pub fn max_sysclk(vos: VosRange, temperature_grade: TemperatureGrade) -> u32 {
+    cfg_if! {
+        if #[cfg(feature = "h735")] {
+            match vos {
+                VosRange::VOS0 => match temperature_grade {
+                    // Note: 550Mhz requires the `CPUFREQ_BOOST` option bit to be set.
+                    TemperatureGrade::Grade6 => 550_000_000,
+                    TemperatureGrade::Grade7 => 520_000_000,
+                    TemperatureGrade::Grade3 => 400_000_000,
+                },
+                VosRange::VOS1 => 400_000_000,
+                VosRange::VOS2 => 300_000_000,
+                VosRange::VOS3 => 170_000_000,
+            }
+        } else if #[cfg(feature = "h7b3")] {
+            // Note that on H7B3, the highest range is labeled VOS0 in the RM, but it uses the
+            // same `VOS` field bits as `VosRange::VOS1` here.
+            let _ = temperature_grade;
+            match vos {
+                VosRange::VOS1 => 280_000_000,
+                VosRange::VOS2 => 225_000_000,
+                VosRange::VOS3 => 160_000_000,
+            }
+        } else {
+            match vos {
+                VosRange::VOS0 => match temperature_grade {
+                    TemperatureGrade::Grade6 => 480_000_000,
+                    _ => 400_000_000,
+                },
+                VosRange::VOS1 => 400_000_000,
+                VosRange::VOS2 => 300_000_000,
+                VosRange::VOS3 => 200_000_000,
+            }
+        }
+    }
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
@@ -358,8 +522,8 @@ pub struct Clocks {
     pub d2_prescaler2: ApbPrescaler,
     /// APB4 peripheral clocks
     pub d3_prescaler: ApbPrescaler,
-    /// Bypass the HSE output, for use with oscillators that don't need it. Saves power, and
-    /// frees up the pin for use as GPIO.
+    /// Bypass the HSE oscillator, for use with an external clock signal on OSC_IN, eg from an
+    /// oscillator or TCXO, instead of a crystal. Frees up OSC_OUT for use as GPIO.
     pub hse_bypass: bool,
     /// USBOTG kernel clock selection. Defaults to HSI48.
     pub usb_src: UsbSrc,
@@ -367,6 +531,9 @@ pub struct Clocks {
     pub hsi48_on: bool,
     pub stop_wuck: StopWuck,
     pub vos_range: VosRange,
+    /// The device's temperature grade; used to validate the maximum sysclk speed for
+    /// the selected `vos_range`. Defaults to Grade 6. (-40 to 85°C ambient)
+    pub temperature_grade: TemperatureGrade,
     /// SAI1 and DFSDM1 kernel Aclk clock source selection
     pub sai1_src: SaiSrc,
     #[cfg(not(feature = "h735"))]
@@ -376,6 +543,10 @@ pub struct Clocks {
     pub sai4b_src: SaiSrc,
     /// DFSDM1 kernel clock source selection
     pub dfsdm1_src: DfsdmSrc,
+    /// SDMMC1 and SDMMC2 kernel clock source selection
+    pub sdmmc_src: SdmmcSrc,
+    /// FDCAN kernel clock source selection
+    pub fdcan_src: FdCanSrc,
 }
 
 impl Clocks {
@@ -464,9 +635,7 @@ impl Clocks {
                 while rcc.cr.read().csirdy().bit_is_clear() {}
             }
             InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().bit(true));
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+                self.enable_hse();
             }
             InputSrc::Hsi(div) => {
                 rcc.cr.modify(|_, w| {
@@ -483,8 +652,7 @@ impl Clocks {
                         while rcc.cr.read().csirdy().bit_is_clear() {}
                     }
                     PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().bit(true));
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                        self.enable_hse();
                     }
                     PllSrc::Hsi(div) => {
                         rcc.cr.modify(|_, w| {
@@ -498,11 +666,6 @@ impl Clocks {
             }
         }
 
-        rcc.cr.modify(|_, w| {
-            // Enable bypass mode on HSE, since we're using a ceramic oscillator.
-            w.hsebyp().bit(self.hse_bypass)
-        });
-
         rcc.cfgr.modify(|_, w| unsafe {
             w.sw().bits(self.input_src.bits());
             w.stopwuck().bit(self.stop_wuck as u8 != 0)
@@ -527,9 +690,14 @@ impl Clocks {
             w.sai1sel().bits(self.sai1_src as u8);
             #[cfg(not(feature = "h735"))]
             w.sai23sel().bits(self.sai23_src as u8);
-            w.dfsdm1sel().bit(self.dfsdm1_src as u8 != 0)
+            w.dfsdm1sel().bit(self.dfsdm1_src as u8 != 0);
+            w.fdcansel().bits(self.fdcan_src as u8)
         });
 
+        #[cfg(not(feature = "h7b3"))]
+        rcc.d1ccipr
+            .modify(|_, w| w.sdmmcsel().bit(self.sdmmc_src as u8 != 0));
+
         // Set USART2 to HSI, and USB to HSI48. Temp hardcoded.
         // todo: Add config enums for these, and add them as Clocks fields.
         #[cfg(not(feature = "h7b3"))]
@@ -546,13 +714,10 @@ impl Clocks {
 
         rcc.cr.modify(|_, w| w.hsecsson().bit(self.security_system));
 
-        // todo: Allow configuring the PLL in fractional mode.
-
         rcc.pllckselr
             .modify(|_, w| w.pllsrc().bits(self.pll_src.bits()));
 
-        // Note that with this code setup, PLL2 and PLL3 won't work properly unless using
-        // the input source is PLL1.
+        // PLL2 and PLL3 are configured below independently of PLL1; they share `pll_src`.
         if let InputSrc::Pll1 = self.input_src {
             // Turn off the PLL: Required for modifying some of the settings below.
             rcc.cr.modify(|_, w| w.pll1on().clear_bit());
@@ -569,17 +734,14 @@ impl Clocks {
                 _ => panic!("PLL1 input source must be between 1Mhz and 16Mhz."),
             };
 
-            // todo: Don't enable all these pqr etc by default!
-            // todo don't enable pll2 and 3 by default!!
-            // todo: This has an impact on power consumption.
-
             // todo: MOre DRY
             // H743 RM:
             // 0: Wide VCO range: 192 to 836 MHz (default after reset)
             // 1: Medium VCO range: 150 to 420 MHz
+            // See `vco_range()` for the 2Mhz boundary.
             let pll1_vco = match self.pll_input_speed(self.pll_src, 1) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             // The user application can then configure the proper VCO: if the frequency of the reference
@@ -602,9 +764,9 @@ impl Clocks {
             rcc.pllcfgr.modify(|_, w| {
                 w.pll1rge().bits(pll1_rng_val);
                 w.pll1vcosel().bit(pll1_vco != 0);
-                w.divp1en().bit(true);
-                w.divq1en().bit(self.pll1.pllq_en);
-                w.divr1en().bit(self.pll1.pllr_en)
+                w.divp1en().bit(self.pll_output_enabled(1, PllOutput::P));
+                w.divq1en().bit(self.pll_output_enabled(1, PllOutput::Q));
+                w.divr1en().bit(self.pll_output_enabled(1, PllOutput::R))
             });
 
             rcc.pll1divr.modify(|_, w| unsafe {
@@ -614,6 +776,13 @@ impl Clocks {
                 w.divr1().bits(self.pll1.divr - 1)
             });
 
+            // FRACN is latched when PLL1FRACEN goes from 0 to 1.
+            rcc.pllcfgr.modify(|_, w| w.pll1fracen().clear_bit());
+            rcc.pll1fracr
+                .modify(|_, w| unsafe { w.fracn1().bits(self.pll1.fracn) });
+            rcc.pllcfgr
+                .modify(|_, w| w.pll1fracen().bit(self.pll1.frac_en));
+
             // Now turn PLL back on, once we're configured things that can only be set with it off.
             rcc.cr.modify(|_, w| w.pll1on().set_bit());
             while rcc.cr.read().pll1rdy().bit_is_clear() {}
@@ -633,8 +802,8 @@ impl Clocks {
             };
 
             let pll2_vco = match self.pll_input_speed(self.pll_src, 2) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             rcc.pllckselr.modify(|_, w| w.divm2().bits(self.pll2.divm));
@@ -642,9 +811,9 @@ impl Clocks {
             rcc.pllcfgr.modify(|_, w| {
                 w.pll2rge().bits(pll2_rng_val);
                 w.pll2vcosel().bit(pll2_vco != 0);
-                w.divp2en().bit(self.pll2.pllp_en);
-                w.divq2en().bit(self.pll2.pllq_en);
-                w.divr2en().bit(self.pll2.pllr_en)
+                w.divp2en().bit(self.pll_output_enabled(2, PllOutput::P));
+                w.divq2en().bit(self.pll_output_enabled(2, PllOutput::Q));
+                w.divr2en().bit(self.pll_output_enabled(2, PllOutput::R))
             });
 
             rcc.pll2divr.modify(|_, w| unsafe {
@@ -654,6 +823,13 @@ impl Clocks {
                 w.divr2().bits(self.pll2.divr - 1)
             });
 
+            // FRACN is latched when PLL2FRACEN goes from 0 to 1.
+            rcc.pllcfgr.modify(|_, w| w.pll2fracen().clear_bit());
+            rcc.pll2fracr
+                .modify(|_, w| unsafe { w.fracn2().bits(self.pll2.fracn) });
+            rcc.pllcfgr
+                .modify(|_, w| w.pll2fracen().bit(self.pll2.frac_en));
+
             rcc.cr.modify(|_, w| w.pll2on().set_bit());
             while rcc.cr.read().pll2rdy().bit_is_clear() {}
         }
@@ -671,8 +847,8 @@ impl Clocks {
             };
 
             let pll3_vco = match self.pll_input_speed(self.pll_src, 3) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             rcc.pllckselr.modify(|_, w| w.divm3().bits(self.pll3.divm));
@@ -680,9 +856,9 @@ impl Clocks {
             rcc.pllcfgr.modify(|_, w| {
                 w.pll3rge().bits(pll3_rng_val);
                 w.pll3vcosel().bit(pll3_vco != 0);
-                w.divp3en().bit(self.pll3.pllp_en);
-                w.divq3en().bit(self.pll3.pllq_en);
-                w.divr3en().bit(self.pll3.pllr_en)
+                w.divp3en().bit(self.pll_output_enabled(3, PllOutput::P));
+                w.divq3en().bit(self.pll_output_enabled(3, PllOutput::Q));
+                w.divr3en().bit(self.pll_output_enabled(3, PllOutput::R))
             });
 
             rcc.pll3divr.modify(|_, w| unsafe {
@@ -692,6 +868,13 @@ impl Clocks {
                 w.divr3().bits(self.pll3.divr - 1)
             });
 
+            // FRACN is latched when PLL3FRACEN goes from 0 to 1.
+            rcc.pllcfgr.modify(|_, w| w.pll3fracen().clear_bit());
+            rcc.pll3fracr
+                .modify(|_, w| unsafe { w.fracn3().bits(self.pll3.fracn) });
+            rcc.pllcfgr
+                .modify(|_, w| w.pll3fracen().bit(self.pll3.frac_en));
+
             rcc.cr.modify(|_, w| w.pll3on().set_bit());
             while rcc.cr.read().pll3rdy().bit_is_clear() {}
         }
@@ -704,29 +887,52 @@ impl Clocks {
         Ok(())
     }
 
+    /// Enable the HSE, and wait for it to be ready. The bypass setting can only be changed while
+    /// the HSE is off, so it's set first.
+    fn enable_hse(&self) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        if rcc.cr.read().hserdy().bit_is_set() {
+            return;
+        }
+
+        rcc.cr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
+        rcc.cr.modify(|_, w| w.hseon().bit(true));
+        // Wait for the HSE to be ready.
+        while rcc.cr.read().hserdy().bit_is_clear() {}
+    }
+
     /// Re-select input source; used on Stop and Standby modes, where the system reverts
     /// to HSI after wake.
+    /// This reuses the stored configuration, and returns immediately if the input source is
+    /// still selected, so it's safe to run after any `wfi`.
     pub fn reselect_input(&self) {
         // Re-select the input source; it will revert to HSI during `Stop` or `Standby` mode.
 
         let rcc = unsafe { &(*RCC::ptr()) };
 
+        // If the configured source is still selected (eg the MCU didn't enter Stop, or it was
+        // already re-selected in a different context), there's nothing to do.
+        if self.input_is_selected() {
+            return;
+        }
+
         // Note: It would save code repetition to pass the `Clocks` struct in and re-run setup
         // todo: But this saves a few reg writes.
         match self.input_src {
             InputSrc::Hse(_) => {
-                rcc.cr.modify(|_, w| w.hseon().set_bit());
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+                self.enable_hse();
 
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+
+                while !self.input_is_selected() {}
             }
             InputSrc::Pll1 => {
                 // todo: DRY with above.
                 match self.pll_src {
                     PllSrc::Hse(_) => {
-                        rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                        self.enable_hse();
                     }
                     PllSrc::Hsi(div) => {
                         // Generally reverts to Csi (see note below)
@@ -741,14 +947,15 @@ impl Clocks {
                 }
 
                 // todo: PLL 2 and 3?
-                rcc.cr.modify(|_, w| w.pll1on().clear_bit());
-                while rcc.cr.read().pll1rdy().bit_is_set() {}
+                // The PLL is stopped in Stop mode, but its configuration is retained. Enable it,
+                // and switch to it once it's locked.
+                rcc.cr.modify(|_, w| w.pll1on().set_bit());
+                while rcc.cr.read().pll1rdy().bit_is_clear() {}
 
                 rcc.cfgr
                     .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
 
-                rcc.cr.modify(|_, w| w.pll1on().set_bit());
-                while rcc.cr.read().pll1rdy().bit_is_clear() {}
+                while !self.input_is_selected() {}
             }
             InputSrc::Hsi(div) => {
                 {
@@ -762,6 +969,10 @@ impl Clocks {
                         w.hsion().bit(true)
                     });
                     while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+                    rcc.cfgr
+                        .modify(|_, w| unsafe { w.sw().bits(self.input_src.bits()) });
+                    while !self.input_is_selected() {}
                 }
             }
             InputSrc::Csi => (), // ?
@@ -786,15 +997,158 @@ impl Clocks {
         }
     }
 
-    /// Calculate VCO output frequency: = Fref1_ck x DIVN1
+    /// Get a PLL's configuration.
+    fn pll_cfg(&self, pll_num: u8) -> &PllCfg {
+        match pll_num {
+            1 => &self.pll1,
+            2 => &self.pll2,
+            3 => &self.pll3,
+            _ => panic!("Pll num must be between 1 and 3."),
+        }
+    }
+
+    /// Calculate VCO output frequency: = Fref1_ck x (DIVN1 + FRACN1 / 8192)
     pub fn vco_output_freq(&self, pll_src: PllSrc, pll_num: u8) -> u32 {
         let input_speed = self.pll_input_speed(pll_src, pll_num);
+        let cfg = self.pll_cfg(pll_num);
+
+        let fracn = if cfg.frac_en { cfg.fracn } else { 0 };
+
+        input_speed * cfg.divn as u32 + (input_speed as u64 * fracn as u64 / 8_192) as u32
+    }
+
+    /// Get a PLL's P output frequency, in hz.
+    pub fn pllp_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(self.pll_src, pll_num) / self.pll_cfg(pll_num).divp as u32
+    }
+
+    /// Get a PLL's Q output frequency, in hz.
+    pub fn pllq_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(self.pll_src, pll_num) / self.pll_cfg(pll_num).divq as u32
+    }
+
+    /// Get a PLL's R output frequency, in hz.
+    pub fn pllr_speed(&self, pll_num: u8) -> u32 {
+        self.vco_output_freq(self.pll_src, pll_num) / self.pll_cfg(pll_num).divr as u32
+    }
+
+    /// Returns true if a PLL output is enabled; either by its `PllCfg` enable field, or since
+    /// it's selected as a kernel clock source, eg by `sai1_src`, `usb_src`, `sdmmc_src`, or
+    /// `fdcan_src`. PLL1's P output is always enabled, since it's the system clock.
+    pub fn pll_output_enabled(&self, pll_num: u8, output: PllOutput) -> bool {
+        let cfg = self.pll_cfg(pll_num);
+        let enabled = match output {
+            PllOutput::P => cfg.pllp_en,
+            PllOutput::Q => cfg.pllq_en,
+            PllOutput::R => cfg.pllr_en,
+        };
+        if enabled {
+            return true;
+        }
+
+        #[cfg(not(feature = "h735"))]
+        let sai23_src = Some(self.sai23_src);
+        #[cfg(feature = "h735")]
+        let sai23_src = None;
+
+        let sai_srcs = [
+            Some(self.sai1_src),
+            sai23_src,
+            Some(self.sai4a_src),
+            Some(self.sai4b_src),
+        ];
+        let sai_uses = |src| sai_srcs.contains(&Some(src));
+
+        match (pll_num, output) {
+            (1, PllOutput::P) => true,
+            (1, PllOutput::Q) => {
+                sai_uses(SaiSrc::Pll1Q)
+                    || self.usb_src == UsbSrc::Pll1Q
+                    || self.sdmmc_src == SdmmcSrc::Pll1Q
+                    || self.fdcan_src == FdCanSrc::Pll1Q
+            }
+            (2, PllOutput::P) => sai_uses(SaiSrc::Pll2P),
+            (2, PllOutput::Q) => self.fdcan_src == FdCanSrc::Pll2Q,
+            (2, PllOutput::R) => self.sdmmc_src == SdmmcSrc::Pll2R,
+            (3, PllOutput::P) => sai_uses(SaiSrc::Pll3P),
+            (3, PllOutput::Q) => self.usb_src == UsbSrc::Pll3Q,
+            _ => false,
+        }
+    }
+
+    /// Get a PLL output's frequency, in hz, or `None` if the PLL isn't running, or the output is
+    /// disabled. PLL1 runs when it's the system clock source; PLL2 and PLL3 run when their
+    /// `enabled` field is set.
+    pub fn pll_output_speed(&self, pll_num: u8, output: PllOutput) -> Option<u32> {
+        let running = match pll_num {
+            1 => matches!(self.input_src, InputSrc::Pll1),
+            _ => self.pll_cfg(pll_num).enabled,
+        };
+
+        if !running || !self.pll_output_enabled(pll_num, output) {
+            return None;
+        }
+
+        Some(match output {
+            PllOutput::P => self.pllp_speed(pll_num),
+            PllOutput::Q => self.pllq_speed(pll_num),
+            PllOutput::R => self.pllr_speed(pll_num),
+        })
+    }
+
+    /// Change a PLL's FRACN while it's running, eg to trim an audio clock to track the rate of an
+    /// external source, such as USB audio feedback. The PLL must have been started in
+    /// fractional mode; ie with `frac_en` set. Updates this struct.
+    pub fn set_fracn(&mut self, pll_num: u8, fracn: u16) -> Result<(), SpeedError> {
+        if fracn > 8_191 {
+            return Err(SpeedError::new("A PLL fractional divider is out of limits"));
+        }
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // FRACN is latched when PLLxFRACEN goes from 0 to 1; the PLL keeps running meanwhile.
         match pll_num {
-            1 => input_speed * self.pll1.divn as u32,
-            2 => input_speed * self.pll2.divn as u32,
-            3 => input_speed * self.pll3.divn as u32,
+            1 => {
+                if rcc.pllcfgr.read().pll1fracen().bit_is_clear() {
+                    return Err(SpeedError::new("PLL1 isn't in fractional mode"));
+                }
+                rcc.pllcfgr.modify(|_, w| w.pll1fracen().clear_bit());
+                rcc.pll1fracr
+                    .modify(|_, w| unsafe { w.fracn1().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll1fracen().set_bit());
+                self.pll1.fracn = fracn;
+            }
+            2 => {
+                if rcc.pllcfgr.read().pll2fracen().bit_is_clear() {
+                    return Err(SpeedError::new("PLL2 isn't in fractional mode"));
+                }
+                rcc.pllcfgr.modify(|_, w| w.pll2fracen().clear_bit());
+                rcc.pll2fracr
+                    .modify(|_, w| unsafe { w.fracn2().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll2fracen().set_bit());
+                self.pll2.fracn = fracn;
+            }
+            3 => {
+                if rcc.pllcfgr.read().pll3fracen().bit_is_clear() {
+                    return Err(SpeedError::new("PLL3 isn't in fractional mode"));
+                }
+                rcc.pllcfgr.modify(|_, w| w.pll3fracen().clear_bit());
+                rcc.pll3fracr
+                    .modify(|_, w| unsafe { w.fracn3().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll3fracen().set_bit());
+                self.pll3.fracn = fracn;
+            }
             _ => panic!("Pll num must be between 1 and 3."),
         }
+
+        Ok(())
+    }
+
+    /// Check if the configured input source is currently selected as the system clock. (RCC_CFGR
+    /// SWS field) If not, eg after waking from Stop, run `reselect_input()`.
+    pub fn input_is_selected(&self) -> bool {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.cfgr.read().sws().bits() == self.input_src.bits()
     }
 
     /// Check if the PLL is enabled. This is useful if checking whether to re-enable the PLL
@@ -814,11 +1168,7 @@ impl Clocks {
     /// CPU2 syclock is equal to the HCLK, so use the `hclk()` method.
     pub fn sysclk(&self) -> u32 {
         match self.input_src {
-            InputSrc::Pll1 => {
-                // divm1 is included in `pll_input_speed`.
-                self.pll_input_speed(self.pll_src, 1) * self.pll1.divn as u32
-                    / self.pll1.divp as u32
-            }
+            InputSrc::Pll1 => self.pllp_speed(1),
             InputSrc::Csi => 4_000_000,
             InputSrc::Hsi(div) => 64_000_000 / (div.value() as u32),
             InputSrc::Hse(freq) => freq,
@@ -842,11 +1192,14 @@ impl Clocks {
         self.d1cpreclk()
     }
 
-    /// Get the USB clock frequency, in hz
+    /// Get the USB clock frequency, in Hz, from the source selected by `usb_src`.
     pub fn usb(&self) -> u32 {
-        // let (input_freq, _) = sysclock(self.input_src, self.divm1, self.divn1, self.divp1);
-        // (input_freq * 1_000_000) as u32 / self.divm1 as u32 * self.pll_sai1_mul as u32 / 2
-        0 // todo
+        match self.usb_src {
+            UsbSrc::Disabled => 0,
+            UsbSrc::Pll1Q => self.pllq_speed(1),
+            UsbSrc::Pll3Q => self.pllq_speed(3),
+            UsbSrc::Hsi48 => 48_000_000,
+        }
     }
 
     pub fn apb1(&self) -> u32 {
@@ -866,6 +1219,12 @@ impl Clocks {
         self.hclk() / self.d2_prescaler2.value() as u32
     }
 
+    /// Get the D3 domain APB4 frequency, in hz. This clocks I2C4, SPI6, LPUART1, and other
+    /// peripherals that can run while the D1 and D2 domains are stopped.
+    pub fn apb4(&self) -> u32 {
+        self.hclk() / self.d3_prescaler.value() as u32
+    }
+
     /// Get the frequency used by APB2 timers, in hz
     pub fn apb2_timer(&self) -> u32 {
         if let ApbPrescaler::Div1 = self.d2_prescaler2 {
@@ -885,28 +1244,38 @@ impl Clocks {
         };
 
         match self.sai1_src {
-            SaiSrc::Pll1Q => {
-                self.pll_input_speed(pll_src, 1) * self.pll1.divn as u32 / self.pll1.divq as u32
-            }
-            SaiSrc::Pll2P => {
-                self.pll_input_speed(pll_src, 1) * self.pll2.divn as u32 / self.pll2.divp as u32
-            }
-            SaiSrc::Pll3P => {
-                self.pll_input_speed(pll_src, 1) * self.pll3.divn as u32 / self.pll3.divp as u32
-            }
+            SaiSrc::Pll1Q => self.vco_output_freq(pll_src, 1) / self.pll1.divq as u32,
+            SaiSrc::Pll2P => self.vco_output_freq(pll_src, 2) / self.pll2.divp as u32,
+            SaiSrc::Pll3P => self.vco_output_freq(pll_src, 3) / self.pll3.divp as u32,
             SaiSrc::I2sCkin => unimplemented!(),
             SaiSrc::PerClk => unimplemented!(),
         }
     }
 
-    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        cfg_if! {
-            if #[cfg(feature = "h735")] {
-                let max_sysclk = 480_000_000;
-            } else {
-                let max_sysclk = 550_000_000;
-            }
+    /// Get the SDMMC kernel clock frequency, in hz, from the source selected by `sdmmc_src`, or
+    /// `None` if that PLL output isn't running. Pass this to `Sdmmc::new()`.
+    pub fn sdmmc_kernel(&self) -> Option<u32> {
+        match self.sdmmc_src {
+            SdmmcSrc::Pll1Q => self.pll_output_speed(1, PllOutput::Q),
+            SdmmcSrc::Pll2R => self.pll_output_speed(2, PllOutput::R),
         }
+    }
+
+    /// Get the FDCAN kernel clock frequency, in hz, from the source selected by `fdcan_src`, or
+    /// `None` if that clock isn't running.
+    pub fn fdcan_kernel(&self) -> Option<u32> {
+        match self.fdcan_src {
+            FdCanSrc::Hse => match (self.input_src, self.pll_src) {
+                (InputSrc::Hse(freq), _) | (_, PllSrc::Hse(freq)) => Some(freq),
+                _ => None,
+            },
+            FdCanSrc::Pll1Q => self.pll_output_speed(1, PllOutput::Q),
+            FdCanSrc::Pll2Q => self.pll_output_speed(2, PllOutput::Q),
+        }
+    }
+
+    pub fn validate_speeds(&self) -> Result<(), SpeedError> {
+        let max_sysclk = max_sysclk(self.vos_range, self.temperature_grade);
         // #[cfg(feature = "h743")]
         let max_hclk = 240_000_000;
         // #[cfg(feature = "h743")]
@@ -918,7 +1287,7 @@ impl Clocks {
 
         // todo: Are these valid for all H7 configs?
         if self.pll1.divm > 63
-            || self.pll1.divm > 63
+            || self.pll2.divm > 63
             || self.pll3.divm > 63
             || self.pll1.divn > 512
             || self.pll2.divn > 512
@@ -934,35 +1303,44 @@ impl Clocks {
             return Err(SpeedError::new("A PLL divider is out of limits"));
         }
 
-        if let InputSrc::Pll1 = self.input_src {
-            let pll_input_speed = self.pll_input_speed(self.pll_src, 1);
+        if self.pll1.fracn > 8_191 || self.pll2.fracn > 8_191 || self.pll3.fracn > 8_191 {
+            return Err(SpeedError::new("A PLL fractional divider is out of limits"));
+        }
+
+        for pll_num in 1..=3 {
+            let in_use = match pll_num {
+                1 => matches!(self.input_src, InputSrc::Pll1),
+                _ => self.pll_cfg(pll_num).enabled,
+            };
+            if !in_use {
+                continue;
+            }
+
+            let pll_input_speed = self.pll_input_speed(self.pll_src, pll_num);
             if pll_input_speed < 1_000_000 || pll_input_speed > 16_000_000 {
                 return Err(SpeedError::new("Invalid PLL input speed"));
             }
             // VCO0: Wide VCO range: 192 to 836 MHz (default after reset) (VCOH)
             // Note: The RM appears out of date: Revision "V" allgedly supports 960_000_000
             // VCO speed, to allow a max core speed of 480Mhz.
-            let vco_speed = self.vco_output_freq(self.pll_src, 1);
-            if pll_input_speed <= 2_000_000 && (vco_speed < 192_000_000 || vco_speed > 960_000_000)
-            {
-                return Err(SpeedError::new("Invalid wide VCO speed"));
-            }
             // 1: Medium VCO range: 150 to 420 MHz. (VCOL)
             // Note: You may get power savings
-            if pll_input_speed > 2_000_000 && (vco_speed < 150_000_000 || vco_speed > 420_000_000) {
-                return Err(SpeedError::new("Invalid medium VCO speed"));
+            let vco_speed = self.vco_output_freq(self.pll_src, pll_num);
+            let (vco_min, vco_max) = vco_range(pll_input_speed);
+            if vco_speed < vco_min || vco_speed > vco_max {
+                return Err(SpeedError::new("Invalid VCO speed"));
             }
         }
 
-        // todo: Validate PLL2 and PLL3 speeds.
-
         // todo: More work on this, including feature gates
 
         // todo: QC these limits
         // todo: Note that this involves repeatedly calculating sysclk.
         // todo. We could work around thsi by calcing it once here.
         if self.sysclk() > max_sysclk {
-            return Err(SpeedError::new("Sysclock out of limits"));
+            return Err(SpeedError::new(
+                "Sysclock out of limits for this VOS range and temperature grade",
+            ));
         }
 
         if self.hclk() > max_hclk {
@@ -1016,12 +1394,15 @@ impl Default for Clocks {
             /// Select the input source to use after waking up from `stop` mode. Eg HSI or MSI.
             stop_wuck: StopWuck::Hsi,
             vos_range: VosRange::VOS1,
+            temperature_grade: TemperatureGrade::Grade6,
             sai1_src: SaiSrc::Pll1Q,
             #[cfg(not(feature = "h735"))]
             sai23_src: SaiSrc::Pll1Q,
             sai4a_src: SaiSrc::Pll1Q,
             sai4b_src: SaiSrc::Pll1Q,
             dfsdm1_src: DfsdmSrc::Pclk2,
+            sdmmc_src: SdmmcSrc::Pll1Q,
+            fdcan_src: FdCanSrc::Hse,
         }
     }
 }
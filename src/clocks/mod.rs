@@ -47,6 +47,149 @@ impl SpeedError {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// Device temperature range, as indicated by the last digit of the part number. (eg the `6` in
+/// STM32H743ZIT6). Devices rated for higher temperatures may not be able to run at full speed
+/// at a given voltage scale. See the datasheet, sections "Ordering information", and "General
+/// operating conditions".
+pub enum TemperatureGrade {
+    /// -40 to 85°C ambient; 105°C junction. Suffix 6.
+    Grade6,
+    /// -40 to 105°C ambient; 125°C junction. Suffix 7.
+    Grade7,
+    /// -40 to 125°C ambient; 130 - 140°C junction, depending on the family. Suffix 3.
+    Grade3,
+}
+
+#[cfg(not(any(feature = "g0", feature = "wl")))]
+impl Clocks {
+    /// Check that the USB clock is 48Mhz, within the ±0.25% tolerance full-speed USB requires, and
+    /// that its source is enabled. Run this before setting up USB; eg `usb::new_bus()` does.
+    pub fn validate_usb(&self) -> Result<(), SpeedError> {
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "h7")))]
+        if self.clk48_src == Clk48Src::Hsi48 && !self.hsi48_on {
+            return Err(SpeedError::new(
+                "HSI48 must be enabled to clock USB from it",
+            ));
+        }
+
+        #[cfg(feature = "h7")]
+        match self.usb_src {
+            UsbSrc::Hsi48 if !self.hsi48_on => {
+                return Err(SpeedError::new(
+                    "HSI48 must be enabled to clock USB from it",
+                ));
+            }
+            UsbSrc::Pll1Q if !self.pll_output_enabled(1, PllOutput::Q) => {
+                return Err(SpeedError::new(
+                    "PLL1 Q output must be enabled to clock USB from it",
+                ));
+            }
+            UsbSrc::Pll3Q if !self.pll3.enabled || !self.pll_output_enabled(3, PllOutput::Q) => {
+                return Err(SpeedError::new(
+                    "PLL3 and its Q output must be enabled to clock USB from it",
+                ));
+            }
+            _ => (),
+        }
+
+        if !(47_880_000..=48_120_000).contains(&self.usb()) {
+            return Err(SpeedError::new("USB clock must be 48Mhz"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Implemented by peripherals whose timing depends on bus clock speeds, eg a U[S]ART's baud rate,
+/// or a timer's frequency. Pass these to `Clocks::change_sysclk()`, which calls `clocks_changed()`
+/// after changing speeds, so they can recalculate their prescalers.
+pub trait ClockListener {
+    /// Re-apply the peripheral's settings for the new clock configuration. Returns an error if
+    /// they can't be reached with the new clock speeds; eg a timer frequency that's out of range.
+    fn clocks_changed(&mut self, clocks: &Clocks) -> Result<(), SpeedError>;
+}
+
+#[cfg(not(any(feature = "wb", feature = "wl")))]
+/// Reset all peripherals, using the RCC reset registers, and disable and clear all NVIC interrupts,
+/// and SysTick. Run this before jumping between a bootloader and application, so stale peripheral,
+/// DMA, or interrupt state from one image can't affect the next.
+///
+/// This doesn't reset the backup domain (RTC, LSE, and backup registers), debug, or the flash
+/// interface and PWR, since these hold the flash wait states and core voltage range the current
+/// clock configuration relies on. It doesn't change the clock configuration; note that this disables
+/// GPIO, so run it from code that doesn't rely on peripherals, eg immediately before the jump. Not
+/// available on WB and WL, since some peripherals are shared with the second core.
+pub fn reset_all_peripherals() {
+    let rcc = unsafe { &(*crate::pac::RCC::ptr()) };
+
+    cortex_m::interrupt::free(|_| {
+        // We write reset registers as a whole, as ST's HAL does in `HAL_DeInit()`. Register names
+        // vary by family.
+        macro_rules! reset {
+            ($reg:ident) => {
+                reset!($reg, 0)
+            };
+            ($reg:ident, $exclude:expr) => {
+                rcc.$reg.write(|w| unsafe { w.bits(!($exclude)) });
+                rcc.$reg.write(|w| unsafe { w.bits(0) });
+            };
+        }
+
+        // PWRRST is bit 28 of APB1RSTR (APB1RSTR1 or APBRSTR1), and FLASHRST is bit 8 of
+        // AHB1RSTR (AHBRSTR), where present.
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "f3")] {
+                reset!(ahbrstr);
+                reset!(apb1rstr, 1 << 28);
+                reset!(apb2rstr);
+            } else if #[cfg(feature = "f4")] {
+                reset!(ahb1rstr);
+                #[cfg(not(feature = "f410"))]
+                reset!(ahb2rstr);
+                #[cfg(not(any(feature = "f401", feature = "f410", feature = "f411")))]
+                reset!(ahb3rstr);
+                reset!(apb1rstr, 1 << 28);
+                reset!(apb2rstr);
+            } else if #[cfg(feature = "g0")] {
+                reset!(ioprstr);
+                reset!(ahbrstr, 1 << 8);
+                reset!(apbrstr1, 1 << 28);
+                reset!(apbrstr2);
+            } else if #[cfg(feature = "h7")] {
+                reset!(ahb1rstr);
+                reset!(ahb2rstr);
+                reset!(ahb3rstr);
+                reset!(ahb4rstr);
+                reset!(apb1lrstr);
+                reset!(apb1hrstr);
+                reset!(apb2rstr);
+                reset!(apb3rstr);
+                reset!(apb4rstr);
+            } else {  // L4, L5, and G4
+                reset!(ahb1rstr, 1 << 8);
+                reset!(ahb2rstr);
+                reset!(ahb3rstr);
+                reset!(apb1rstr1, 1 << 28);
+                reset!(apb1rstr2);
+                reset!(apb2rstr);
+            }
+        }
+
+        let mut cp = unsafe { cortex_m::Peripherals::steal() };
+        cp.SYST.disable_counter();
+        cp.SYST.disable_interrupt();
+
+        // Disable all interrupts, and clear any that are pending.
+        unsafe {
+            for i in 0..cp.NVIC.icer.len() {
+                cp.NVIC.icer[i].write(0xffff_ffff);
+                cp.NVIC.icpr[i].write(0xffff_ffff);
+            }
+        }
+    });
+}
+
 // #[derive(Clone, Copy)]
 // #[repr(u8)]
 // pub enum ClocksValid {
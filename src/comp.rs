@@ -0,0 +1,323 @@
+//! Support for the analog comparator (COMP) peripherals. Compares a non-inverting input (a pin)
+//! with an inverting input (a pin, a DAC channel, or a fraction of VREFINT). Useful for zero-cross
+//! and over-current detection.
+//!
+//! The output can trigger an interrupt through its EXTI line, be output on a pin (COMPx_OUT
+//! alternate function), and be used by timers as a break input, OCREF_CLR source, or input
+//! capture source; configure this in the timer's `TISEL`, `AF1`, and `AF2` registers.
+//!
+//! Currently only supports G4. todo: Other families.
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    gpio::Edge,
+    pac::{COMP, EXTI, RCC},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+/// Select the comparator to use.
+pub enum CompDevice {
+    One,
+    Two,
+    Three,
+    Four,
+    #[cfg(not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1")))]
+    Five,
+    #[cfg(not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1")))]
+    Six,
+    #[cfg(not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1")))]
+    Seven,
+}
+
+impl CompDevice {
+    /// The EXTI line the comparator output is connected to. See G4 RM, Table 99:
+    /// EXTI lines connections.
+    pub fn exti_line(&self) -> u8 {
+        match self {
+            Self::One => 21,
+            Self::Two => 22,
+            Self::Three => 29,
+            Self::Four => 30,
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            Self::Five => 31,
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            Self::Six => 32,
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            Self::Seven => 33,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Non-inverting (+) input selection. The pins for each depend on the comparator; see the
+/// datasheet. Sets `COMP_CxCSR` register, `INPSEL` field.
+pub enum NonInvertingInput {
+    Io1 = 0,
+    Io2 = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Inverting (-) input selection. Which DAC channels `DacA` and `DacB` refer to depends on the
+/// comparator; see G4 RM, Table 196: COMPx inverting input assignment. Sets `COMP_CxCSR`
+/// register, `INMSEL` field.
+pub enum InvertingInput {
+    OneQuarterVref = 0b000,
+    OneHalfVref = 0b001,
+    ThreeQuarterVref = 0b010,
+    Vref = 0b011,
+    DacA = 0b100,
+    DacB = 0b101,
+    Io1 = 0b110,
+    Io2 = 0b111,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Hysteresis level. Sets `COMP_CxCSR` register, `HYST` field.
+pub enum Hysteresis {
+    None = 0b000,
+    H10mV = 0b001,
+    H20mV = 0b010,
+    H30mV = 0b011,
+    H40mV = 0b100,
+    H50mV = 0b101,
+    H60mV = 0b110,
+    H70mV = 0b111,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Output polarity. Sets `COMP_CxCSR` register, `POL` field.
+pub enum OutputPolarity {
+    NotInverted = 0,
+    Inverted = 1,
+}
+
+/// Configuration data for a comparator.
+pub struct CompConfig {
+    /// Non-inverting input. Defaults to IO1.
+    pub inp: NonInvertingInput,
+    /// Inverting input. Defaults to 1/2 VREFINT.
+    pub inm: InvertingInput,
+    /// Defaults to no hysteresis.
+    pub hysteresis: Hysteresis,
+    /// Defaults to not inverted.
+    pub polarity: OutputPolarity,
+    /// Blanking source: A timer output compare signal that masks the comparator output, eg to
+    /// ignore current spikes when a power switch turns on. 0 disables blanking; 1 - 7 select a timer
+    /// output, per G4 RM, Table 198: COMPx blanking sources. Defaults to 0.
+    pub blanking: u8,
+}
+
+impl Default for CompConfig {
+    fn default() -> Self {
+        Self {
+            inp: NonInvertingInput::Io1,
+            inm: InvertingInput::OneHalfVref,
+            hysteresis: Hysteresis::None,
+            polarity: OutputPolarity::NotInverted,
+            blanking: 0,
+        }
+    }
+}
+
+/// Modify a comparator's CSR register. Each comparator's register has a distinct type in the PAC.
+macro_rules! modify_csr {
+    ($device:expr, |$r:ident, $w:ident| $body:expr) => {{
+        let regs = unsafe { &(*COMP::ptr()) };
+        match $device {
+            CompDevice::One => regs.c1csr.modify(|$r, $w| $body),
+            CompDevice::Two => regs.c2csr.modify(|$r, $w| $body),
+            CompDevice::Three => regs.c3csr.modify(|$r, $w| $body),
+            CompDevice::Four => regs.c4csr.modify(|$r, $w| $body),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Five => regs.c5csr.modify(|$r, $w| $body),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Six => regs.c6csr.modify(|$r, $w| $body),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Seven => regs.c7csr.modify(|$r, $w| $body),
+        }
+    }};
+}
+
+/// Represents an analog comparator. The comparators share a register block, so we don't own it;
+/// this struct accesses the relevant CSR register directly.
+pub struct Comp {
+    pub device: CompDevice,
+    pub cfg: CompConfig,
+}
+
+impl Comp {
+    /// Configure a comparator, and enable the SYSCFG peripheral clock, which the comparators use.
+    /// Doesn't enable the comparator; use `enable()` for that.
+    pub fn new(device: CompDevice, cfg: CompConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+        });
+
+        // G4 RM, section 24.3.6: The scaler provides VREFINT fractions; the bridge (BRGEN) is
+        // required for the 1/4, 1/2, and 3/4 fractions, and the scaler (SCALEN) for all of them.
+        let scalen = (cfg.inm as u8) <= InvertingInput::Vref as u8;
+        let brgen = (cfg.inm as u8) < InvertingInput::Vref as u8;
+
+        modify_csr!(device, |_r, w| unsafe {
+            w.inpsel().bit(cfg.inp as u8 != 0);
+            w.inmsel().bits(cfg.inm as u8);
+            w.hyst().bits(cfg.hysteresis as u8);
+            w.pol().bit(cfg.polarity as u8 != 0);
+            w.blanksel().bits(cfg.blanking);
+            w.scalen().bit(scalen);
+            w.brgen().bit(brgen)
+        });
+
+        Self { device, cfg }
+    }
+
+    /// Enable the comparator. Note that there's a startup delay before the output is valid; see
+    /// the datasheet (t_START).
+    pub fn enable(&mut self) {
+        modify_csr!(self.device, |_r, w| w.en().set_bit());
+    }
+
+    /// Disable the comparator.
+    pub fn disable(&mut self) {
+        modify_csr!(self.device, |_r, w| w.en().clear_bit());
+    }
+
+    /// Read the comparator's output level, after polarity selection and blanking.
+    /// Reads the `COMP_CxCSR` register, `VALUE` field.
+    pub fn get_output(&self) -> bool {
+        let regs = unsafe { &(*COMP::ptr()) };
+        match self.device {
+            CompDevice::One => regs.c1csr.read().value().bit_is_set(),
+            CompDevice::Two => regs.c2csr.read().value().bit_is_set(),
+            CompDevice::Three => regs.c3csr.read().value().bit_is_set(),
+            CompDevice::Four => regs.c4csr.read().value().bit_is_set(),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Five => regs.c5csr.read().value().bit_is_set(),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Six => regs.c6csr.read().value().bit_is_set(),
+            #[cfg(not(any(
+                feature = "g431",
+                feature = "g441",
+                feature = "g491",
+                feature = "g4a1"
+            )))]
+            CompDevice::Seven => regs.c7csr.read().value().bit_is_set(),
+        }
+    }
+
+    /// Lock the comparator's configuration. It can't be modified until the next system reset.
+    pub fn lock(&mut self) {
+        modify_csr!(self.device, |_r, w| w.lock().set_bit());
+    }
+
+    /// Enable an interrupt when the output changes, using the comparator's EXTI line. `edge`
+    /// selects output rising, falling, or both.
+    pub fn enable_interrupt(&mut self, edge: Edge) {
+        let (rising, falling) = match edge {
+            Edge::Rising => (true, false),
+            Edge::Falling => (false, true),
+            Edge::Either => (true, true),
+        };
+
+        let line = self.device.exti_line();
+
+        free(|_| {
+            let exti = unsafe { &(*EXTI::ptr()) };
+
+            if line < 32 {
+                let mask = 1 << line;
+                exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+                exti.rtsr1.modify(|r, w| unsafe {
+                    w.bits(if rising {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+                exti.ftsr1.modify(|r, w| unsafe {
+                    w.bits(if falling {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+            } else {
+                let mask = 1 << (line - 32);
+                exti.imr2.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+                exti.rtsr2.modify(|r, w| unsafe {
+                    w.bits(if rising {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+                exti.ftsr2.modify(|r, w| unsafe {
+                    w.bits(if falling {
+                        r.bits() | mask
+                    } else {
+                        r.bits() & !mask
+                    })
+                });
+            }
+        });
+    }
+
+    /// Clear the output change interrupt, by clearing its EXTI pending flag.
+    pub fn clear_interrupt(&mut self) {
+        let line = self.device.exti_line();
+        let exti = unsafe { &(*EXTI::ptr()) };
+
+        // These are write-1-to-clear.
+        if line < 32 {
+            exti.pr1.write(|w| unsafe { w.bits(1 << line) });
+        } else {
+            exti.pr2.write(|w| unsafe { w.bits(1 << (line - 32)) });
+        }
+    }
+}
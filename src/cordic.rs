@@ -0,0 +1,392 @@
+//! Support for the CORDIC co-processor, which calculates trigonometric, hyperbolic, and related
+//! functions in hardware. Useful for motor control (eg Park and Clarke transforms), and DSP.
+//! A q1.31 sine and cosine pair takes around 6 CORDIC clock cycles, vice hundreds for a
+//! software implementation.
+//!
+//! Arguments and results are fixed point: q1.31 (`i32`), or q1.15 (`i16`), representing -1 to 1.
+//! Angles are scaled by π; ie -1 to 1 represents -π to π. Convert to and from `f32` with
+//! `to_q31()`, `from_q31()` etc.
+//!
+//! Calculations are blocking: Reading a result stalls the bus until it's ready. For batches of
+//! calculations, use `start_batch()`, and DMA: Run `dma::mux()` with `DmaInput::CordicWrite`,
+//! and `DmaInput::CordicRead` first.
+//!
+//! See G4 RM, chapter 17: "CORDIC co-processor".
+//!
+//! Example:
+//! ```rust
+//! let mut cordic = Cordic::new(dp.CORDIC, 6);
+//!
+//! // sin and cos of π/4.
+//! let (sin, cos) = cordic.sin_cos(to_q31(0.25));
+//! let sin = from_q31(sin); // 0.7071
+//!
+//! // The angle of (x = -1/2, y = 1/2); 3π/4.
+//! let angle = from_q31(cordic.atan2(to_q31(0.5), to_q31(-0.5))) * PI;
+//! ```
+
+use core::ops::Deref;
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel, DmaPeriph},
+    pac::{self, DMA1, DMA2, RCC},
+    util::rcc_en_reset,
+};
+
+use cfg_if::cfg_if;
+
+// CORDIC_CSR fields. We write CSR as raw bits, since each calculation sets all of its fields at
+// once.
+const FUNC_SHIFT: u32 = 0;
+const PRECISION_SHIFT: u32 = 4;
+const SCALE_SHIFT: u32 = 8;
+const IEN: u32 = 1 << 16;
+const DMAREN: u32 = 1 << 17;
+const DMAWEN: u32 = 1 << 18;
+const NRES: u32 = 1 << 19;
+const NARGS: u32 = 1 << 20;
+const RESSIZE: u32 = 1 << 21;
+const ARGSIZE: u32 = 1 << 22;
+const RRDY: u32 = 1 << 31;
+
+// 1, as closely as the formats can represent it; the default modulus for sine and cosine.
+const ONE_Q31: i32 = i32::MAX;
+const ONE_Q15: i16 = i16::MAX;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// A CORDIC function. Sets CORDIC_CSR register, FUNC field. The first result is listed first,
+/// and the second, secondary result, in parentheses. See G4 RM, section 17.3.2 for argument
+/// ranges, and scaling.
+pub enum Function {
+    /// Arguments: Angle, and modulus. Results: Cosine (sine).
+    Cosine = 0,
+    /// Arguments: Angle, and modulus. Results: Sine (cosine).
+    Sine = 1,
+    /// Arguments: x, and y. Results: Phase, ie atan2(y, x) (modulus).
+    Phase = 2,
+    /// Arguments: x, and y. Results: Modulus (phase).
+    Modulus = 3,
+    /// Argument: x. Result: Arctangent.
+    Arctangent = 4,
+    /// Argument: x. Results: Hyperbolic cosine (hyperbolic sine).
+    HyperbolicCosine = 5,
+    /// Argument: x. Results: Hyperbolic sine (hyperbolic cosine).
+    HyperbolicSine = 6,
+    /// Argument: x. Result: Hyperbolic arctangent.
+    HyperbolicArctangent = 7,
+    /// Argument: x. Result: Natural logarithm.
+    NaturalLog = 8,
+    /// Argument: x. Result: Square root.
+    SquareRoot = 9,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The fixed point format of arguments and results. Sets CORDIC_CSR register, ARGSIZE, and
+/// RESSIZE fields, and the NARGS, and NRES fields to match.
+pub enum DataFormat {
+    /// Arguments and results are 32-bit, with 2 of each per calculation: 2 words are written,
+    /// and read.
+    Q31,
+    /// Arguments and results are 16-bit, packed into a single word each per calculation, with
+    /// the first argument or result in the low half.
+    Q15,
+}
+
+/// Convert an `f32` between -1 and 1 to q1.31 fixed point. Values outside this range saturate.
+pub fn to_q31(value: f32) -> i32 {
+    (value * 2_147_483_648.) as i32
+}
+
+/// Convert a q1.31 fixed point value to `f32`.
+pub fn from_q31(value: i32) -> f32 {
+    value as f32 / 2_147_483_648.
+}
+
+/// Convert an `f32` between -1 and 1 to q1.15 fixed point. Values outside this range saturate.
+pub fn to_q15(value: f32) -> i16 {
+    (value * 32_768.) as i16
+}
+
+/// Convert a q1.15 fixed point value to `f32`.
+pub fn from_q15(value: i16) -> f32 {
+    value as f32 / 32_768.
+}
+
+/// Represents the CORDIC co-processor.
+pub struct Cordic<R> {
+    pub regs: R,
+    /// The number of iterations / 4: 1 - 15. Higher values are more precise, but slower; 6 gives
+    /// full precision for most q1.31 functions, and 3 for q1.15.
+    pub precision: u8,
+}
+
+impl<R> Cordic<R>
+where
+    R: Deref<Target = pac::cordic::RegisterBlock>,
+{
+    /// Initialize the CORDIC, including enabling and resetting its RCC peripheral clock.
+    pub fn new(regs: R, precision: u8) -> Self {
+        assert!(
+            (1..=15).contains(&precision),
+            "CORDIC precision must be 1 - 15."
+        );
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    rcc_en_reset!(ahb2, cordic, rcc);
+                } else {
+                    rcc_en_reset!(ahb1, cordic, rcc);
+                }
+            }
+        });
+
+        Self { regs, precision }
+    }
+
+    /// Set the function, scale, and data format. (CORDIC_CSR register) `Q31` uses 2 arguments,
+    /// and 2 results; `Q15` packs them into one word each.
+    fn configure(&mut self, function: Function, scale: u8, format: DataFormat, extra: u32) {
+        let format_bits = match format {
+            DataFormat::Q31 => NARGS | NRES,
+            DataFormat::Q15 => ARGSIZE | RESSIZE,
+        };
+
+        self.regs.csr.write(|w| unsafe {
+            w.bits(
+                ((function as u32) << FUNC_SHIFT)
+                    | ((self.precision as u32) << PRECISION_SHIFT)
+                    | ((scale as u32 & 0b111) << SCALE_SHIFT)
+                    | format_bits
+                    | extra,
+            )
+        });
+    }
+
+    /// Run a calculation in q1.31 format, returning the primary and secondary results. See
+    /// `Function` for arguments and results; `arg2` is ignored by functions with 1 argument.
+    /// `scale` is the scaling factor required by some functions' argument ranges; 0 - 7.
+    pub fn calculate(&mut self, function: Function, scale: u8, arg1: i32, arg2: i32) -> (i32, i32) {
+        self.configure(function, scale, DataFormat::Q31, 0);
+
+        self.regs.wdata.write(|w| unsafe { w.bits(arg1 as u32) });
+        self.regs.wdata.write(|w| unsafe { w.bits(arg2 as u32) });
+
+        let res1 = self.regs.rdata.read().bits() as i32;
+        let res2 = self.regs.rdata.read().bits() as i32;
+        (res1, res2)
+    }
+
+    /// Run a calculation in q1.15 format, returning the primary and secondary results. See
+    /// `calculate()`.
+    pub fn calculate_q15(
+        &mut self,
+        function: Function,
+        scale: u8,
+        arg1: i16,
+        arg2: i16,
+    ) -> (i16, i16) {
+        self.configure(function, scale, DataFormat::Q15, 0);
+
+        self.regs
+            .wdata
+            .write(|w| unsafe { w.bits(pack_q15(arg1, arg2)) });
+
+        let res = self.regs.rdata.read().bits();
+        (res as i16, (res >> 16) as i16)
+    }
+
+    /// Calculate the sine and cosine of an angle, scaled by π. Returns (sine, cosine).
+    pub fn sin_cos(&mut self, angle: i32) -> (i32, i32) {
+        self.calculate(Function::Sine, 0, angle, ONE_Q31)
+    }
+
+    /// Calculate the sine and cosine of an angle, scaled by π. Returns (sine, cosine).
+    pub fn sin_cos_q15(&mut self, angle: i16) -> (i16, i16) {
+        self.calculate_q15(Function::Sine, 0, angle, ONE_Q15)
+    }
+
+    /// Calculate the angle of the vector (x, y), scaled by π.
+    pub fn atan2(&mut self, y: i32, x: i32) -> i32 {
+        self.calculate(Function::Phase, 0, x, y).0
+    }
+
+    /// Calculate the angle of the vector (x, y), scaled by π.
+    pub fn atan2_q15(&mut self, y: i16, x: i16) -> i16 {
+        self.calculate_q15(Function::Phase, 0, x, y).0
+    }
+
+    /// Calculate the magnitude of the vector (x, y); ie sqrt(x² + y²). This saturates if the
+    /// magnitude is 1 or more.
+    pub fn magnitude(&mut self, x: i32, y: i32) -> i32 {
+        self.calculate(Function::Modulus, 0, x, y).0
+    }
+
+    /// Calculate the magnitude of the vector (x, y); ie sqrt(x² + y²). This saturates if the
+    /// magnitude is 1 or more.
+    pub fn magnitude_q15(&mut self, x: i16, y: i16) -> i16 {
+        self.calculate_q15(Function::Modulus, 0, x, y).0
+    }
+
+    /// Calculate the square root of a value between 0 and 1.
+    pub fn sqrt(&mut self, x: i32) -> i32 {
+        // The argument range is 0.027 to 0.75 with a scale of 0. For higher values, we use a
+        // scale of 1; the argument is divided by 2, and the result must be multiplied by 2.
+        if x < to_q31(0.75) {
+            self.calculate(Function::SquareRoot, 0, x, 0).0
+        } else {
+            self.calculate(Function::SquareRoot, 1, x >> 1, 0).0 << 1
+        }
+    }
+
+    /// Calculate the square root of a value between 0 and 1.
+    pub fn sqrt_q15(&mut self, x: i16) -> i16 {
+        if x < to_q15(0.75) {
+            self.calculate_q15(Function::SquareRoot, 0, x, 0).0
+        } else {
+            self.calculate_q15(Function::SquareRoot, 1, x >> 1, 0).0 << 1
+        }
+    }
+
+    /// Configure the CORDIC for a batch of calculations using DMA; start the transfers with
+    /// `write_dma()`, and `read_dma()`. Each calculation takes 2 argument words, and produces 2
+    /// result words in `Q31` format, or one of each, packed, in `Q15` format; see `pack_q15()`.
+    /// Stop with `stop_batch()`.
+    pub fn start_batch(&mut self, function: Function, scale: u8, format: DataFormat) {
+        self.configure(function, scale, format, DMAREN | DMAWEN);
+    }
+
+    /// Disable DMA requests, after a batch is complete. (CORDIC_CSR register, DMAREN, and DMAWEN
+    /// fields)
+    pub fn stop_batch(&mut self) {
+        self.regs
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(DMAREN | DMAWEN)) });
+    }
+
+    /// Write arguments from `buf` to the CORDIC using DMA, after `start_batch()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn write_dma(
+        &mut self,
+        buf: &[u32],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+        cfg_dma(
+            ptr as u32,
+            len,
+            &self.regs.wdata as *const _ as u32,
+            dma::Direction::ReadFromMem,
+            channel,
+            channel_cfg,
+            dma_periph,
+        );
+    }
+
+    /// Read results from the CORDIC into `buf` using DMA, after `start_batch()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [u32],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        cfg_dma(
+            ptr as u32,
+            len,
+            &self.regs.rdata as *const _ as u32,
+            dma::Direction::ReadFromPeriph,
+            channel,
+            channel_cfg,
+            dma_periph,
+        );
+    }
+
+    /// Enable the result ready interrupt. (CORDIC_CSR register, IEN field)
+    pub fn enable_interrupt(&mut self) {
+        self.regs
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() | IEN) });
+    }
+
+    /// Disable the result ready interrupt.
+    pub fn disable_interrupt(&mut self) {
+        self.regs
+            .csr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !IEN) });
+    }
+
+    /// Returns true if a result is ready to read. The flag is cleared when all results are read.
+    /// (CORDIC_CSR register, RRDY field)
+    pub fn result_ready(&self) -> bool {
+        self.regs.csr.read().bits() & RRDY != 0
+    }
+}
+
+/// Configure a DMA transfer between memory, and a CORDIC data register.
+fn cfg_dma(
+    mem_addr: u32,
+    len: usize,
+    periph_addr: u32,
+    direction: dma::Direction,
+    channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    dma_periph: DmaPeriph,
+) {
+    #[cfg(feature = "h7")]
+    let len = len as u32;
+    #[cfg(not(feature = "h7"))]
+    let len = len as u16;
+
+    match dma_periph {
+        DmaPeriph::Dma1 => {
+            let mut regs = unsafe { &(*DMA1::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S32,
+                dma::DataSize::S32,
+                channel_cfg,
+            );
+        }
+        DmaPeriph::Dma2 => {
+            let mut regs = unsafe { &(*DMA2::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S32,
+                dma::DataSize::S32,
+                channel_cfg,
+            );
+        }
+    }
+}
+
+/// Pack 2 q1.15 arguments into a word, for writing to the CORDIC in `Q15` format; eg in a DMA
+/// batch. Unpack results with `as i16` for the first, and `>> 16` for the second.
+pub fn pack_q15(arg1: i16, arg2: i16) -> u32 {
+    (arg1 as u16 as u32) | ((arg2 as u16 as u32) << 16)
+}
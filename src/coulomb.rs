@@ -0,0 +1,144 @@
+//! Coulomb counting, for tracking battery charge. Accumulates charge from either pulses
+//! counted by a timer, or from current samples, eg from an ADC reading a shunt amplifier.
+//! Pulse sources include a fuel-gauge IC's charge output, or a current-to-frequency converter.
+//!
+//! For pulse counting, use an LPTIM, with `LpClockSource::ExternalInput`. It keeps counting in
+//! Stop mode, so the MCU only needs to wake periodically (eg from the RTC) to read the count.
+//! Charge is accumulated in 64 bits, so it doesn't overflow over the battery's life.
+//!
+//! Example, counting pulses from a gauge that outputs one pulse per 600µC discharged:
+//! ```rust
+//! let mut lptim = LpTimer::new(dp.LPTIM1, LpTimerConfig {
+//!     kernel_clock: LpClockSel::Lse,
+//!     clock_source: LpClockSource::ExternalInput,
+//!     clock_filter: LpFilter::Clocks4,
+//!     ..Default::default()
+//! });
+//! lptim.enable();
+//! lptim.set_auto_reload(0xffff);
+//! lptim.start_continuous();
+//!
+//! let mut counter = CoulombCounter::new(1_000);
+//! counter.start_pulses(lptim.read_count(), 0xffff);
+//!
+//! // Periodically, eg from an RTC wakeup interrupt. This must happen at least once per
+//! // 65,536 pulses.
+//! counter.update_pulses(lptim.read_count(), -600);
+//! defmt::println!("Charge: {} µAh", counter.charge_uah());
+//! ```
+//!
+//! Example, integrating current samples, timestamped with a 1Mhz free-running timer:
+//! ```rust
+//! let mut counter = CoulombCounter::new(1_000_000);
+//!
+//! // For each ADC reading. Use a negative current for discharge:
+//! let current_ua = -((adc.reading_to_voltage(reading) / SHUNT_GAIN / SHUNT_OHMS) * 1e6) as i32;
+//! counter.add_current_sample(current_ua, timer.read_count());
+//! ```
+
+/// Accumulates charge. Internally, charge is stored in µA × ticks of the time base passed to
+/// `new()`. With a 1Mhz time base, this covers ±2,500Ah; lower time bases cover more.
+pub struct CoulombCounter {
+    /// Accumulated charge, in µA × ticks.
+    acc: i64,
+    /// Time base frequency, in Hz.
+    tick_freq: u32,
+    /// The most recent current sample, in µA, and its timestamp.
+    last_sample: Option<(i32, u32)>,
+    /// The most recent pulse counter reading.
+    last_count: u16,
+    /// The pulse counter's modulus, ie its autoreload value + 1.
+    count_period: u32,
+}
+
+impl CoulombCounter {
+    /// Create a coulomb counter, with zero charge. `tick_freq` is the frequency, in Hz, of the
+    /// timestamps passed to `add_current_sample()`. If only counting pulses, this sets
+    /// the resolution; 1_000 is a reasonable value.
+    pub fn new(tick_freq: u32) -> Self {
+        assert!(tick_freq > 0);
+
+        Self {
+            acc: 0,
+            tick_freq,
+            last_sample: None,
+            last_count: 0,
+            count_period: 0x1_0000,
+        }
+    }
+
+    /// Start counting pulses. `count` is the pulse counter's current value, eg from
+    /// `LpTimer::read_count()`, and `auto_reload` is the counter's autoreload value.
+    pub fn start_pulses(&mut self, count: u16, auto_reload: u16) {
+        self.last_count = count;
+        self.count_period = auto_reload as u32 + 1;
+    }
+
+    /// Add the pulses counted since the last call, handling counter wraparound. Call this at least
+    /// once per counter period. `charge_per_pulse` is in µC; make it negative if the pulses
+    /// indicate discharge. Returns the number of new pulses.
+    pub fn update_pulses(&mut self, count: u16, charge_per_pulse: i32) -> u32 {
+        let (count, last) = (count as u32, self.last_count as u32);
+
+        let pulses = if count >= last {
+            count - last
+        } else {
+            count + self.count_period - last
+        };
+        self.last_count = count as u16;
+
+        // µC = µA × s, so multiplying by the tick frequency converts to µA × ticks.
+        let charge = pulses as i64 * charge_per_pulse as i64 * self.tick_freq as i64;
+        self.acc = self.acc.saturating_add(charge);
+
+        pulses
+    }
+
+    /// Add a current sample, in µA, with its timestamp in ticks of the time base passed to `new()`.
+    /// The timestamp may wrap. Integrates using the trapezoidal rule, with the previous sample; the
+    /// first sample only sets the starting point. Use a positive current for charge, and negative for
+    /// discharge.
+    pub fn add_current_sample(&mut self, current: i32, timestamp: u32) {
+        if let Some((last_current, last_timestamp)) = self.last_sample {
+            let dt = timestamp.wrapping_sub(last_timestamp) as i64;
+            let charge = (last_current as i64 + current as i64) * dt / 2;
+            self.acc = self.acc.saturating_add(charge);
+        }
+
+        self.last_sample = Some((current, timestamp));
+    }
+
+    /// Add a quantity of charge directly, in µC.
+    pub fn add_charge(&mut self, charge: i64) {
+        self.acc = self
+            .acc
+            .saturating_add(charge.saturating_mul(self.tick_freq as i64));
+    }
+
+    /// Discard the previous current sample, eg after a gap in sampling while in a low-power
+    /// mode, so the next sample isn't integrated over the gap.
+    pub fn restart_sampling(&mut self) {
+        self.last_sample = None;
+    }
+
+    /// The accumulated charge, in µC.
+    pub fn charge_uc(&self) -> i64 {
+        self.acc / self.tick_freq as i64
+    }
+
+    /// The accumulated charge, in µAh.
+    pub fn charge_uah(&self) -> i64 {
+        self.acc / (self.tick_freq as i64 * 3_600)
+    }
+
+    /// The accumulated charge, in mAh.
+    pub fn charge_mah(&self) -> f32 {
+        self.acc as f32 / (self.tick_freq as f32 * 3_600_000.)
+    }
+
+    /// Set the accumulated charge, in µAh; eg to the battery's capacity, once a charger reports
+    /// it's fully charged.
+    pub fn set_charge_uah(&mut self, charge: i64) {
+        self.acc = charge.saturating_mul(self.tick_freq as i64 * 3_600);
+    }
+}
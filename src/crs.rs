@@ -0,0 +1,241 @@
+//! Support for the Clock Recovery System (CRS), which trims the HSI48 oscillator using a
+//! synchronization signal: USB Start-of-Frame (SOF) packets, the LSE, or the CRS_SYNC pin. With
+//! USB SOF sync, this allows USB to be used without a crystal.
+//!
+//! Example, for crystal-less USB:
+//! ```rust
+//! let clock_cfg = Clocks {
+//!     hsi48_on: true,
+//!     clk48_src: Clk48Src::Hsi48,
+//!     ..Default::default()
+//! };
+//! clock_cfg.setup().unwrap();
+//!
+//! let mut crs = Crs::new(dp.CRS, Default::default());
+//! ```
+//!
+//! See L4 RM, section 7: Clock recovery system (CRS). The CRS is disabled in Stop, and Standby
+//! modes; HSI48 runs on its factory calibration until it's re-synchronized after wakeup.
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    clocks::CrsSyncSrc,
+    pac::{CRS, RCC},
+};
+
+use cfg_if::cfg_if;
+
+/// The HSI48 frequency the CRS targets, in Hz.
+const TARGET_FREQ: u32 = 48_000_000;
+
+cfg_if! {
+    if #[cfg(any(feature = "l5", feature = "g4"))] {
+        /// The maximum value of the CRS_CR TRIM field.
+        pub const MAX_TRIM: u8 = 0x7f;
+    } else {
+        /// The maximum value of the CRS_CR TRIM field.
+        pub const MAX_TRIM: u8 = 0x3f;
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Division applied to the SYNC signal. Sets CRS_CFGR, SYNCDIV field.
+pub enum SyncDiv {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+    Div16 = 4,
+    Div32 = 5,
+    Div64 = 6,
+    Div128 = 7,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// SYNC signal polarity. Sets CRS_CFGR, SYNCPOL field.
+pub enum SyncPolarity {
+    Rising = 0,
+    Falling = 1,
+}
+
+#[derive(Clone, Copy)]
+/// CRS interrupts. Sets the CRS_CR register.
+pub enum CrsInterrupt {
+    /// SYNC event OK.
+    SyncOk,
+    /// SYNC warning; the trim value had to be adjusted by more than 1 step.
+    SyncWarn,
+    /// SYNC error, SYNC missed, or trimming overflow/underflow.
+    Error,
+    /// Expected SYNC.
+    ExpectedSync,
+}
+
+/// CRS configuration. The defaults are for USB SOF sync, at 1kHz.
+pub struct CrsConfig {
+    pub sync_src: CrsSyncSrc,
+    pub sync_div: SyncDiv,
+    pub polarity: SyncPolarity,
+    /// Counter reload value: The number of HSI48 cycles between SYNC events, minus 1.
+    /// Sets CRS_CFGR, RELOAD field.
+    pub reload: u16,
+    /// Frequency error limit. Sets CRS_CFGR, FELIM field.
+    pub freq_error_limit: u8,
+    /// Automatically adjust the trim value. If disabled, use `set_trim()`.
+    pub auto_trim: bool,
+}
+
+impl Default for CrsConfig {
+    fn default() -> Self {
+        Self::new(CrsSyncSrc::Usb, 1_000)
+    }
+}
+
+impl CrsConfig {
+    /// Create a configuration, calculating the reload value, and frequency error limit from the
+    /// SYNC signal frequency, after division; eg 1_000 for USB SOF, or 32_768 for the LSE.
+    /// See L4 RM, section 7.4.3: Frequency error evaluation and automatic trimming.
+    pub fn new(sync_src: CrsSyncSrc, sync_freq: u32) -> Self {
+        let cycles = TARGET_FREQ / sync_freq;
+
+        // FELIM = (fTARGET / fSYNC) × STEP[%] / 100% / 2, with a trim step of 0.14%.
+        let freq_error_limit = ((cycles * 14 + 10_000) / 20_000).max(1) as u8;
+
+        Self {
+            sync_src,
+            sync_div: SyncDiv::Div1,
+            polarity: SyncPolarity::Rising,
+            reload: (cycles - 1) as u16,
+            freq_error_limit,
+            auto_trim: true,
+        }
+    }
+}
+
+/// CRS status, read from the CRS_ISR register.
+pub struct CrsStatus {
+    /// The last SYNC event was within tolerance.
+    pub sync_ok: bool,
+    /// The trim value had to be adjusted by more than 1 step.
+    pub sync_warn: bool,
+    /// The frequency error was beyond the maximum trimming range.
+    pub sync_error: bool,
+    /// The SYNC signal was missed.
+    pub sync_missed: bool,
+    /// The trim value overflowed, or underflowed.
+    pub trim_overflow: bool,
+    /// The frequency error counter value at the last SYNC event.
+    pub freq_error_capture: u16,
+    /// True if the HSI48 was too fast at the last SYNC event, eg the counter was counting down.
+    pub too_fast: bool,
+}
+
+/// Represents the Clock Recovery System.
+pub struct Crs {
+    pub regs: CRS,
+}
+
+impl Crs {
+    /// Enable the CRS's RCC clock, configure it, and start it. The HSI48 must be enabled,
+    /// eg with `Clocks::hsi48_on`.
+    pub fn new(regs: CRS, cfg: CrsConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // todo: CRSEN missing on l4x5 pac: https://github.com/stm32-rs/stm32-rs/issues/572
+            cfg_if! {
+                if #[cfg(feature = "l4x5")] {
+                    let val = rcc.apb1enr1.read().bits();
+                    rcc.apb1enr1.write(|w| unsafe { w.bits(val | (1 << 24)) });
+                } else {
+                    rcc.apb1enr1.modify(|_, w| w.crsen().set_bit());
+                }
+            }
+        });
+
+        // The configuration can only be changed while the CRS is disabled.
+        regs.cr.modify(|_, w| w.cen().clear_bit());
+
+        regs.cfgr.modify(|_, w| unsafe {
+            w.reload().bits(cfg.reload);
+            w.felim().bits(cfg.freq_error_limit);
+            w.syncdiv().bits(cfg.sync_div as u8);
+            w.syncsrc().bits(cfg.sync_src as u8);
+            w.syncpol().bit(cfg.polarity as u8 != 0)
+        });
+
+        regs.cr.modify(|_, w| {
+            w.autotrimen().bit(cfg.auto_trim);
+            w.cen().set_bit()
+        });
+
+        Self { regs }
+    }
+
+    /// Enable the CRS's frequency error counter, and automatic trimming.
+    pub fn enable(&mut self) {
+        self.regs.cr.modify(|_, w| w.cen().set_bit());
+    }
+
+    /// Disable the CRS. The trim value is retained.
+    pub fn disable(&mut self) {
+        self.regs.cr.modify(|_, w| w.cen().clear_bit());
+    }
+
+    /// Read the HSI48 trim value; this is adjusted by hardware when automatic trimming is enabled.
+    pub fn trim(&self) -> u8 {
+        self.regs.cr.read().trim().bits()
+    }
+
+    /// Set the HSI48 trim value. The default of half of `MAX_TRIM` is the factory calibration;
+    /// each step is about 0.14%, or 67kHz. This can be used to speed up lock-in at startup, eg with a
+    /// value saved from a previous run. With automatic trimming enabled, hardware will adjust it.
+    pub fn set_trim(&mut self, trim: u8) {
+        assert!(trim <= MAX_TRIM);
+        self.regs.cr.modify(|_, w| unsafe { w.trim().bits(trim) });
+    }
+
+    /// Generate a software SYNC event. Sets CRS_CR, SWSYNC bit.
+    pub fn generate_sync(&mut self) {
+        self.regs.cr.modify(|_, w| w.swsync().set_bit());
+    }
+
+    /// Read the CRS status.
+    pub fn status(&self) -> CrsStatus {
+        let isr = self.regs.isr.read();
+
+        CrsStatus {
+            sync_ok: isr.syncokf().bit_is_set(),
+            sync_warn: isr.syncwarnf().bit_is_set(),
+            sync_error: isr.syncerr().bit_is_set(),
+            sync_missed: isr.syncmiss().bit_is_set(),
+            trim_overflow: isr.trimovf().bit_is_set(),
+            freq_error_capture: isr.fecap().bits(),
+            too_fast: isr.fedir().bit_is_set(),
+        }
+    }
+
+    /// Enable a specific type of CRS interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: CrsInterrupt) {
+        self.regs.cr.modify(|_, w| match interrupt {
+            CrsInterrupt::SyncOk => w.syncokie().set_bit(),
+            CrsInterrupt::SyncWarn => w.syncwarnie().set_bit(),
+            CrsInterrupt::Error => w.errie().set_bit(),
+            CrsInterrupt::ExpectedSync => w.esyncie().set_bit(),
+        });
+    }
+
+    /// Clear an interrupt flag, eg in the ISR. Clearing `Error` also clears the SYNC error, SYNC
+    /// missed, and trim overflow flags.
+    pub fn clear_interrupt(&mut self, interrupt: CrsInterrupt) {
+        self.regs.icr.write(|w| match interrupt {
+            CrsInterrupt::SyncOk => w.syncokc().set_bit(),
+            CrsInterrupt::SyncWarn => w.syncwarnc().set_bit(),
+            CrsInterrupt::Error => w.errc().set_bit(),
+            CrsInterrupt::ExpectedSync => w.esyncc().set_bit(),
+        });
+    }
+}
@@ -76,6 +76,37 @@ pub enum DacBits {
     TwelveR,
 }
 
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Noise or triangle wave generation. Sets DAC_CR register, WAVE1 and WAVE2 fields.
+pub enum WaveGeneration {
+    /// Wave generation disabled.
+    Disabled = 0b00,
+    /// Noise wave generation, using a linear feedback shift register (LFSR).
+    Noise = 0b01,
+    /// Triangle wave generation.
+    Triangle = 0b10,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// For noise generation, unmasks LFSR bits 0 through n. For triangle generation, sets the amplitude
+/// (peak value above the DHR base value) to this value. Sets DAC_CR register, MAMP1 and MAMP2 fields.
+pub enum WaveAmplitude {
+    A1 = 0,
+    A3 = 1,
+    A7 = 2,
+    A15 = 3,
+    A31 = 4,
+    A63 = 5,
+    A127 = 6,
+    A255 = 7,
+    A511 = 8,
+    A1023 = 9,
+    A2047 = 10,
+    A4095 = 11,
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 #[cfg(not(feature = "h7"))]
@@ -276,6 +307,41 @@ where
         });
     }
 
+    /// Enable or disable the output buffer for a channel. The buffer reduces output impedance,
+    /// allowing the DAC to drive external loads without an external op amp. On families with the
+    /// `MCR` register, this modifies the buffer bit of the channel's mode, keeping the other mode
+    /// settings.
+    pub fn set_buffer(&mut self, channel: DacChannel, enabled: bool) {
+        cfg_if! {
+            if #[cfg(any(feature = "f3", feature = "f4"))] {
+                self.regs.cr.modify(|_, w| match channel {
+                    DacChannel::C1 => w.boff1().bit(!enabled),
+                    DacChannel::C2 => w.boff2().bit(!enabled),
+                });
+            } else {
+                #[cfg(feature = "g4")]
+                let mcr = &self.regs.dac_mcr;
+                #[cfg(not(feature = "g4"))]
+                let mcr = &self.regs.mcr;
+
+                // Bit 1 of the MODE field disables the buffer.
+                mcr.modify(|r, w| unsafe {
+                    match channel {
+                        DacChannel::C1 => {
+                            let mode = r.mode1().bits();
+                            w.mode1().bits(if enabled { mode & !0b010 } else { mode | 0b010 })
+                        }
+                        #[cfg(not(feature = "wl"))]
+                        DacChannel::C2 => {
+                            let mode = r.mode2().bits();
+                            w.mode2().bits(if enabled { mode & !0b010 } else { mode | 0b010 })
+                        }
+                    }
+                });
+            }
+        }
+    }
+
     /// Set the DAC output word.
     pub fn write(&mut self, channel: DacChannel, val: u16) {
         // RM: DAC conversion
@@ -528,39 +594,52 @@ where
     }
 
     #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
-    /// Independent trigger with single LFSR generation
-    /// See f303 Reference Manual section 16.5.2
-    pub fn trigger_lfsr(&mut self, channel: DacChannel, trigger: Trigger, data: u16) {
+    /// Disable the trigger for a channel; the DHR register is transferred to the output one APB
+    /// clock cycle after it's written.
+    pub fn disable_trigger(&mut self, channel: DacChannel) {
         #[cfg(any(feature = "l5", feature = "g4"))]
         let cr = &self.regs.dac_cr;
         #[cfg(not(any(feature = "l5", feature = "g4")))]
         let cr = &self.regs.cr;
 
-        // todo: This may not be correct.
-        match channel {
-            DacChannel::C1 => {
-                cr.modify(|_, w| unsafe {
-                    w.mamp1().bits(0b01);
-                    w.wave1().bits(0b01)
-                });
-            }
+        cr.modify(|_, w| match channel {
+            DacChannel::C1 => w.ten1().clear_bit(),
             #[cfg(not(feature = "wl"))]
-            DacChannel::C2 => {
-                cr.modify(|_, w| unsafe {
-                    w.wave2().bits(0b01);
-                    w.mamp2().bits(0b01)
-                });
+            DacChannel::C2 => w.ten2().clear_bit(),
+        });
+    }
+
+    #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
+    /// Trigger a conversion in software. The trigger must be set to the software trigger
+    /// using `set_trigger()`. Sets the `SWTRGR` register.
+    pub fn trigger_software(&mut self, channel: DacChannel) {
+        cfg_if! {
+            if #[cfg(feature = "g4")] {
+                let swtrgr = &self.regs.dac_swtrgr;
+            } else if #[cfg(feature = "h7")] {
+                let swtrgr = &self.regs.swtrgr;
+            } else {
+                let swtrgr = &self.regs.swtrigr;
             }
         }
-        self.set_trigger(channel, trigger);
-        self.write(channel, data);
+
+        swtrgr.write(|w| match channel {
+            DacChannel::C1 => w.swtrig1().set_bit(),
+            DacChannel::C2 => w.swtrig2().set_bit(),
+        });
     }
 
     #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
-    /// Independent trigger with single triangle generation
-    /// See f303 Reference Manual section 16.5.2
-    pub fn trigger_triangle(&mut self, channel: DacChannel, trigger: Trigger, data: u16) {
-        // todo: This may not be correct.
+    /// Set up noise or triangle wave generation. Each trigger event updates the LFSR, or the triangle
+    /// counter. The wave is added to the base value in the DHR register, set with `write()`.
+    /// A trigger must be enabled with `set_trigger()` for wave generation to work.
+    /// See G4 RM, sections 22.4.11: Noise generation, and 22.4.12: Triangle-wave generation.
+    pub fn set_wave_generation(
+        &mut self,
+        channel: DacChannel,
+        wave: WaveGeneration,
+        amplitude: WaveAmplitude,
+    ) {
         #[cfg(any(feature = "l5", feature = "g4"))]
         let cr = &self.regs.dac_cr;
         #[cfg(not(any(feature = "l5", feature = "g4")))]
@@ -569,22 +648,88 @@ where
         match channel {
             DacChannel::C1 => {
                 cr.modify(|_, w| unsafe {
-                    w.wave1().bits(0b10);
-                    w.mamp1().bits(0b10)
+                    w.mamp1().bits(amplitude as u8);
+                    w.wave1().bits(wave as u8)
                 });
             }
             #[cfg(not(feature = "wl"))]
             DacChannel::C2 => {
                 cr.modify(|_, w| unsafe {
-                    w.wave2().bits(0b10);
-                    w.mamp2().bits(0b10)
+                    w.mamp2().bits(amplitude as u8);
+                    w.wave2().bits(wave as u8)
                 });
             }
         }
+    }
+
+    #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
+    /// Independent trigger with single LFSR generation, unmasking LFSR bits 0 and 1. `data` is the
+    /// base value the noise is added to. See f303 Reference Manual section 16.5.2. To set the
+    /// amplitude, use `trigger_lfsr_with_amplitude()`.
+    pub fn trigger_lfsr(&mut self, channel: DacChannel, trigger: Trigger, data: u16) {
+        self.trigger_lfsr_with_amplitude(channel, trigger, WaveAmplitude::A3, data);
+    }
+
+    #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
+    /// Independent trigger with single LFSR generation, with a specified amplitude.
+    pub fn trigger_lfsr_with_amplitude(
+        &mut self,
+        channel: DacChannel,
+        trigger: Trigger,
+        amplitude: WaveAmplitude,
+        data: u16,
+    ) {
+        self.set_wave_generation(channel, WaveGeneration::Noise, amplitude);
         self.set_trigger(channel, trigger);
         self.write(channel, data);
     }
 
+    #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
+    /// Independent trigger with single triangle generation, with an amplitude of 7. `data` is the
+    /// base value the triangle is added to. See f303 Reference Manual section 16.5.2. To set the
+    /// amplitude, use `trigger_triangle_with_amplitude()`.
+    pub fn trigger_triangle(&mut self, channel: DacChannel, trigger: Trigger, data: u16) {
+        self.trigger_triangle_with_amplitude(channel, trigger, WaveAmplitude::A7, data);
+    }
+
+    #[cfg(not(any(feature = "l5", feature = "wl")))] // See note on `set_trigger`.
+    /// Independent trigger with single triangle generation, with a specified amplitude.
+    pub fn trigger_triangle_with_amplitude(
+        &mut self,
+        channel: DacChannel,
+        trigger: Trigger,
+        amplitude: WaveAmplitude,
+        data: u16,
+    ) {
+        self.set_wave_generation(channel, WaveGeneration::Triangle, amplitude);
+        self.set_trigger(channel, trigger);
+        self.write(channel, data);
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l552", feature = "l5", feature = "wl")))]
+    /// Play an arbitrary waveform using DMA. Each trigger event (eg a timer's TRGO, with the timer's
+    /// update frequency setting the sample rate) outputs the next sample from `buf`. Use a circular
+    /// channel config for continuous playback. Since the DHR to DOR transfer occurs before each DMA
+    /// request, the first trigger outputs the value previously written, and `buf[0]` is output on
+    /// the second. Enables the channel.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn play_waveform_dma(
+        &mut self,
+        buf: &[u16],
+        dac_channel: DacChannel,
+        trigger: Trigger,
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        self.set_trigger(dac_channel, trigger);
+        self.write_dma(buf, dac_channel, dma_channel, channel_cfg, dma_periph);
+        self.enable(dac_channel);
+    }
+
     /// Enable the DMA Underrun interrupt - the only interrupt available.
     pub fn enable_interrupt(&mut self, channel: DacChannel) {
         #[cfg(feature = "g4")]
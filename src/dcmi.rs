@@ -0,0 +1,472 @@
+//! Support for the Digital Camera Interface (DCMI), for capturing images from parallel-interface
+//! camera modules, such as the OV2640, OV5640, and OV7670, into a frame buffer using DMA.
+//! Supports hardware (HSYNC, VSYNC), and embedded synchronization codes, cropping, and JPEG mode.
+//!
+//! Configure the camera itself over its SCCB interface using the `i2c` module, and provide its
+//! clock input (XCLK) with a timer PWM output, or the MCO pin. Set the PIXCLK, HSYNC, VSYNC, and
+//! D0 - D7 (or up to D13) pins to their DCMI alternate functions.
+//!
+//! On H7, DCMI DMA requests are routed with the DMAMUX: Run `dma::mux()` with
+//! `DmaInput::Dcmi` first. On F4, the DCMI uses DMA2 stream 1, channel 1, which `read_dma()`
+//! configures directly.
+//!
+//! Example:
+//! ```rust
+//! // A 320x240 RGB565 frame, from an OV7670.
+//! static mut FRAME: [u32; 320 * 240 / 2] = [0; 320 * 240 / 2];
+//!
+//! let mut dcmi = Dcmi::new(DcmiConfig {
+//!     vsync_pol: Polarity::High,
+//!     ..Default::default()
+//! });
+//! dcmi.enable_interrupt(DcmiInterrupt::Frame);
+//!
+//! dma::mux(DmaPeriph::Dma1, DmaChannel::C0, DmaInput::Dcmi);
+//! unsafe {
+//!     dcmi.read_dma(&mut FRAME, DmaChannel::C0, Default::default(), DmaPeriph::Dma1);
+//! }
+//! dcmi.start_capture();
+//!
+//! // In the DCMI ISR, on `DcmiInterrupt::Frame`, the frame is in `FRAME`.
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::pac::RCC;
+
+#[cfg(feature = "h7")]
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel, DmaPeriph},
+    pac::{DMA1, DMA2},
+};
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since the DCMI is named differently on some H7 variants in the PAC, and
+// is missing from some F4 PACs.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const DCMI_BASE: usize = 0x4802_0000;
+    } else {
+        const DCMI_BASE: usize = 0x5005_0000;
+    }
+}
+
+/// RCC_AHB2ENR: DCMIEN. (DCMI_PSSIEN on H723-735)
+const RCC_BIT: u32 = 1 << 0;
+
+// Register offsets.
+const CR: usize = 0x00;
+const SR: usize = 0x04;
+const RIS: usize = 0x08;
+const IER: usize = 0x0c;
+const ICR: usize = 0x14;
+const ESCR: usize = 0x18;
+const ESUR: usize = 0x1c;
+const CWSTRT: usize = 0x20;
+const CWSIZE: usize = 0x24;
+const DR: usize = 0x28;
+
+// DCMI_CR fields.
+const CAPTURE: u32 = 1 << 0;
+const CM: u32 = 1 << 1;
+const CROP: u32 = 1 << 2;
+const JPEG: u32 = 1 << 3;
+const ESS: u32 = 1 << 4;
+const PCKPOL: u32 = 1 << 5;
+const HSPOL: u32 = 1 << 6;
+const VSPOL: u32 = 1 << 7;
+const FCRC_SHIFT: u32 = 8;
+const EDM_SHIFT: u32 = 10;
+const ENABLE: u32 = 1 << 14;
+
+// DCMI_SR fields.
+const FNE: u32 = 1 << 2;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Capture continuously, or a single frame. Sets DCMI_CR register, CM field.
+pub enum CaptureMode {
+    /// Capture frames until `stop_capture()`. Use this with a circular DMA transfer.
+    Continuous,
+    /// Capture a single frame, then stop.
+    Snapshot,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Embedded synchronization codes, for cameras that don't use HSYNC, and VSYNC signals; eg in
+/// ITU-R BT.656 mode. Sets the DCMI_ESCR, and DCMI_ESUR registers.
+pub struct EmbeddedCodes {
+    /// Frame start code.
+    pub frame_start: u8,
+    /// Line start code.
+    pub line_start: u8,
+    /// Line end code.
+    pub line_end: u8,
+    /// Frame end code.
+    pub frame_end: u8,
+    /// Masks applied to the codes, laid out like DCMI_ESCR: The frame start mask is the least
+    /// significant byte, and the frame end mask, the most. Bits set to 1 are compared; eg use
+    /// 0xffff_ffff to compare all bits.
+    pub unmask: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Synchronization mode. Sets DCMI_CR register, ESS field.
+pub enum SyncMode {
+    /// Synchronize with the HSYNC, and VSYNC signals.
+    Hardware,
+    /// Synchronize with codes embedded in the data flow.
+    Embedded(EmbeddedCodes),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// HSYNC, or VSYNC polarity: The signal's level during blanking; ie when data isn't valid. Sets
+/// DCMI_CR register, HSPOL, and VSPOL fields.
+pub enum Polarity {
+    Low,
+    High,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The pixel clock edge data is captured on. Sets DCMI_CR register, PCKPOL field.
+pub enum PixelClockEdge {
+    Falling,
+    Rising,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The number of data lines. Sets DCMI_CR register, EDM field.
+pub enum DataWidth {
+    B8 = 0b00,
+    B10 = 0b01,
+    B12 = 0b10,
+    B14 = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Which frames are captured, in continuous mode. Sets DCMI_CR register, FCRC field.
+pub enum FrameRate {
+    All = 0b00,
+    /// Every other frame; 50% bandwidth reduction.
+    Alternate = 0b01,
+    /// One frame in 4; 75% bandwidth reduction.
+    OneInFour = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A window within the camera's frame to capture. Sets the DCMI_CWSTRT, and DCMI_CWSIZE registers.
+/// Horizontal values are in pixel clocks; eg 2 per pixel for RGB565 with an 8-bit bus.
+pub struct CropWindow {
+    /// The number of pixel clocks to skip at the start of each line.
+    pub x: u16,
+    /// The number of lines to skip at the start of each frame.
+    pub y: u16,
+    /// The number of pixel clocks to capture per line. This must be a multiple of 4 bytes.
+    pub width: u16,
+    /// The number of lines to capture.
+    pub height: u16,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// DCMI interrupts. Enabled in DCMI_IER, flagged in DCMI_RIS, and cleared in DCMI_ICR.
+pub enum DcmiInterrupt {
+    /// A frame was captured.
+    Frame,
+    /// Data arrived before the DMA read the previous data; the frame is corrupt.
+    Overrun,
+    /// Embedded synchronization codes arrived in an unexpected order.
+    SyncError,
+    /// VSYNC went from active to blanking; ie between frames.
+    Vsync,
+    /// A line was captured.
+    Line,
+}
+
+impl DcmiInterrupt {
+    /// The interrupt's bit in DCMI_IER, DCMI_RIS, and DCMI_ICR.
+    fn bit(&self) -> u32 {
+        1 << *self as u8
+    }
+}
+
+/// DCMI configuration.
+pub struct DcmiConfig {
+    /// Defaults to `Snapshot`.
+    pub capture_mode: CaptureMode,
+    /// Defaults to `Hardware`.
+    pub sync_mode: SyncMode,
+    /// Defaults to `Low`.
+    pub hsync_pol: Polarity,
+    /// Defaults to `Low`.
+    pub vsync_pol: Polarity,
+    /// Defaults to `Rising`.
+    pub pclk_edge: PixelClockEdge,
+    /// Defaults to 8 bits.
+    pub data_width: DataWidth,
+    /// Defaults to `All`.
+    pub frame_rate: FrameRate,
+    /// Capture only part of the frame. Can't be used in JPEG mode. Defaults to `None`.
+    pub crop: Option<CropWindow>,
+    /// JPEG mode, for cameras that output compressed frames. HSYNC is used as a data-valid
+    /// signal, and frames are variable-length. Defaults to `false`.
+    pub jpeg: bool,
+}
+
+impl Default for DcmiConfig {
+    fn default() -> Self {
+        Self {
+            capture_mode: CaptureMode::Snapshot,
+            sync_mode: SyncMode::Hardware,
+            hsync_pol: Polarity::Low,
+            vsync_pol: Polarity::Low,
+            pclk_edge: PixelClockEdge::Rising,
+            data_width: DataWidth::B8,
+            frame_rate: FrameRate::All,
+            crop: None,
+            jpeg: false,
+        }
+    }
+}
+
+/// Represents the Digital Camera Interface (DCMI) peripheral.
+pub struct Dcmi {
+    pub cfg: DcmiConfig,
+}
+
+impl Dcmi {
+    /// Enable the DCMI's RCC peripheral clock, and configure it. Capture starts with
+    /// `start_capture()`.
+    pub fn new(cfg: DcmiConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb2enr
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+        });
+
+        let result = Self { cfg };
+
+        // "The DCMI configuration registers should be programmed correctly before enabling the
+        // Enable bit in the DCMI_CR register."
+        result.write_reg(CR, 0);
+
+        let mut cr = ((result.cfg.frame_rate as u32) << FCRC_SHIFT)
+            | ((result.cfg.data_width as u32) << EDM_SHIFT);
+
+        if result.cfg.capture_mode == CaptureMode::Snapshot {
+            cr |= CM;
+        }
+        if result.cfg.pclk_edge == PixelClockEdge::Rising {
+            cr |= PCKPOL;
+        }
+        if result.cfg.hsync_pol == Polarity::High {
+            cr |= HSPOL;
+        }
+        if result.cfg.vsync_pol == Polarity::High {
+            cr |= VSPOL;
+        }
+        if result.cfg.jpeg {
+            cr |= JPEG;
+        }
+
+        if let SyncMode::Embedded(codes) = result.cfg.sync_mode {
+            cr |= ESS;
+            result.write_reg(
+                ESCR,
+                u32::from_be_bytes([
+                    codes.frame_end,
+                    codes.line_end,
+                    codes.line_start,
+                    codes.frame_start,
+                ]),
+            );
+            result.write_reg(ESUR, codes.unmask);
+        }
+
+        if let Some(crop) = result.cfg.crop {
+            cr |= CROP;
+            result.write_reg(CWSTRT, ((crop.y as u32) << 16) | crop.x as u32);
+            result.write_reg(
+                CWSIZE,
+                ((crop.height.saturating_sub(1) as u32) << 16)
+                    | crop.width.saturating_sub(1) as u32,
+            );
+        }
+
+        result.write_reg(CR, cr | ENABLE);
+
+        result
+    }
+
+    /// Start capturing. In snapshot mode, this captures the next frame, and stops. Configure DMA
+    /// with `read_dma()` first.
+    pub fn start_capture(&mut self) {
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | CAPTURE);
+    }
+
+    /// Stop capturing, at the end of the current frame. Blocks until it's stopped.
+    pub fn stop_capture(&mut self) {
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr & !CAPTURE);
+        while self.read_reg(CR) & CAPTURE != 0 {}
+    }
+
+    /// Returns true if capture is active. In snapshot mode, this is cleared after the frame is
+    /// captured.
+    pub fn is_capturing(&self) -> bool {
+        self.read_reg(CR) & CAPTURE != 0
+    }
+
+    /// Returns true if the FIFO has data; eg for reading it without DMA, with `read()`.
+    pub fn fifo_not_empty(&self) -> bool {
+        self.read_reg(SR) & FNE != 0
+    }
+
+    /// Read a word from the FIFO. Using DMA, with `read_dma()`, is strongly recommended, since the
+    /// FIFO is only 8 words deep.
+    pub fn read(&self) -> u32 {
+        self.read_reg(DR)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Configure a DMA transfer of captured frames into `buf`. Run `dma::mux()` with
+    /// `DmaInput::Dcmi` first. `buf` must hold the whole frame, or in JPEG mode, the largest
+    /// expected one, up to 65_535 words. Use a circular transfer (`channel_cfg.circular`) in
+    /// continuous mode.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// in circular mode, until the capture is stopped.
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [u32],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let periph_addr = (DCMI_BASE + DR) as u32;
+
+        match dma_periph {
+            DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u32,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+            DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u32,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "f4")]
+    /// Configure a DMA transfer of captured frames into `buf`, on DMA2 stream 1, channel 1. `buf`
+    /// must hold the whole frame, or in JPEG mode, the largest expected one, up to 65_535 words.
+    /// Set `circular` in continuous mode. Sets the DMA stream's transfer complete interrupt.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// in circular mode, until the capture is stopped.
+    pub unsafe fn read_dma(&mut self, buf: &mut [u32], circular: bool) {
+        // We use raw pointers, since the `dma` module doesn't support F4.
+        const DMA2_BASE: usize = 0x4002_6400;
+        const LIFCR: usize = 0x08;
+        // Stream 1 registers.
+        const S1CR: usize = 0x28;
+        const S1NDTR: usize = 0x2c;
+        const S1PAR: usize = 0x30;
+        const S1M0AR: usize = 0x34;
+        const S1FCR: usize = 0x3c;
+        // RCC_AHB1ENR: DMA2EN.
+        const RCC_DMA2_BIT: u32 = 1 << 22;
+
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb1enr
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_DMA2_BIT) });
+        });
+
+        let reg = |offset: usize| (DMA2_BASE + offset) as *mut u32;
+
+        unsafe {
+            write_volatile(reg(S1CR), read_volatile(reg(S1CR)) & !1);
+            while read_volatile(reg(S1CR)) & 1 != 0 {}
+
+            // Clear stream 1's FEIF, DMEIF, TEIF, HTIF, and TCIF flags.
+            write_volatile(reg(LIFCR), 0b1111_0100_0000);
+
+            write_volatile(reg(S1PAR), (DCMI_BASE + DR) as u32);
+            write_volatile(reg(S1M0AR), ptr as u32);
+            write_volatile(reg(S1NDTR), len as u32);
+            // FIFO mode, with a full FIFO threshold, as recommended for the DCMI.
+            write_volatile(reg(S1FCR), (1 << 2) | 0b11);
+
+            // CHSEL = 1, PL = high, MSIZE and PSIZE = 32 bits, MINC, periph-to-memory, TCIE.
+            let cr = (1 << 25)
+                | (0b10 << 16)
+                | (0b10 << 13)
+                | (0b10 << 11)
+                | (1 << 10)
+                | ((circular as u32) << 8)
+                | (1 << 4);
+            write_volatile(reg(S1CR), cr);
+            write_volatile(reg(S1CR), cr | 1);
+        }
+    }
+
+    /// Enable an interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: DcmiInterrupt) {
+        let ier = self.read_reg(IER);
+        self.write_reg(IER, ier | interrupt.bit());
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: DcmiInterrupt) {
+        let ier = self.read_reg(IER);
+        self.write_reg(IER, ier & !interrupt.bit());
+    }
+
+    /// Returns true if an interrupt's flag is set, whether or not it's enabled.
+    pub fn interrupt_flag(&self, interrupt: DcmiInterrupt) -> bool {
+        self.read_reg(RIS) & interrupt.bit() != 0
+    }
+
+    /// Clear an interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: DcmiInterrupt) {
+        self.write_reg(ICR, interrupt.bit());
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((DCMI_BASE + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((DCMI_BASE + offset) as *mut u32, value) }
+    }
+}
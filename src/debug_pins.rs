@@ -0,0 +1,107 @@
+//! Reuse the debug pins as GPIO. The SWD pins (PA13: SWDIO, and PA14: SWCLK) are in their debug
+//! alternate function after reset; reassigning them disconnects the debugger, and since this
+//! happens on every boot, it can make the MCU impossible to reprogram over SWD.
+//!
+//! To prevent this, `release_swd()` waits for a "rescue window" after boot before reassigning
+//! the pins, and leaves them alone if a debugger is already attached. During the window, a
+//! debugger can connect, eg with `probe-rs` using connect-under-reset, or by holding NRST low
+//! and releasing it when connecting. Alternatively, connect under reset with BOOT0 held high, so
+//! the firmware doesn't run.
+//!
+//! Example:
+//! ```rust
+//! // Wait 2 seconds after boot, then use PA13 and PA14 as GPIO.
+//! if let Some((mut pa13, mut pa14)) = debug_pins::release_swd(2_000, &clock_cfg, PinMode::Output) {
+//!     pa13.set_high();
+//! }
+//! ```
+
+use cortex_m::asm;
+
+#[cfg(not(feature = "g0"))]
+use cortex_m::peripheral::DCB;
+
+use crate::{
+    clocks::Clocks,
+    gpio::{OutputSpeed, Pin, PinMode, Port, Pull},
+};
+
+#[cfg(not(feature = "g0"))]
+use crate::gpio::JTAG_PINS;
+
+/// Returns true if a debugger is attached. (DHCSR, C_DEBUGEN bit) Always false on G0; Cortex-M0+
+/// cores don't allow firmware to read this.
+pub fn debugger_attached() -> bool {
+    #[cfg(not(feature = "g0"))]
+    return DCB::is_debugger_attached();
+    #[cfg(feature = "g0")]
+    return false;
+}
+
+/// Block for the rescue window, in milliseconds.
+fn rescue_window(ms: u32, clocks: &Clocks) {
+    let cycles_per_ms = clocks.systick() / 1_000;
+    for _ in 0..ms {
+        asm::delay(cycles_per_ms);
+    }
+}
+
+/// Wait `rescue_window_ms` milliseconds, then reassign PA13, and PA14 from SWD to `mode`, with
+/// their pull resistors disabled. Returns `None`, and leaves the pins in SWD mode if a debugger
+/// is attached at the end of the window. The pins are returned as (PA13, PA14).
+pub fn release_swd(rescue_window_ms: u32, clocks: &Clocks, mode: PinMode) -> Option<(Pin, Pin)> {
+    rescue_window(rescue_window_ms, clocks);
+
+    if debugger_attached() {
+        return None;
+    }
+
+    let mut swdio = Pin::new(Port::A, 13, mode);
+    let mut swclk = Pin::new(Port::A, 14, mode);
+
+    // SWDIO has a pull-up, and SWCLK a pull-down at reset.
+    swdio.pull(Pull::Floating);
+    swclk.pull(Pull::Floating);
+
+    Some((swdio, swclk))
+}
+
+/// Return PA13, and PA14 to SWD, with their reset configuration, so a debugger can connect again;
+/// eg from a command received over a serial port.
+pub fn restore_swd(mut swdio: Pin, mut swclk: Pin) {
+    swdio.pull(Pull::Up);
+    swdio.output_speed(OutputSpeed::VeryHigh);
+    swdio.mode(PinMode::Alt(0));
+
+    swclk.pull(Pull::Dn);
+    swclk.mode(PinMode::Alt(0));
+
+    swdio.release();
+    swclk.release();
+}
+
+#[cfg(not(feature = "g0"))]
+/// Reassign the JTAG-only pins (`gpio::JTAG_PINS`: PA15: JTDI, PB3: JTDO/SWO, and PB4: NJTRST) to
+/// `mode`. `Pin::new()` disables their reset pull resistors, so this is equivalent to creating
+/// them directly. These aren't used by SWD, so this doesn't need a rescue window, but JTAG
+/// debuggers, and SWO trace output, won't work afterwards. The pins are returned as
+/// (PA15, PB3, PB4).
+pub fn release_jtag(mode: PinMode) -> (Pin, Pin, Pin) {
+    let [jtdi, jtdo, njtrst] = JTAG_PINS.map(|(port, pin)| Pin::new(port, pin, mode));
+
+    (jtdi, jtdo, njtrst)
+}
+
+#[cfg(not(feature = "g0"))]
+/// Return the JTAG-only pins to the debug port, with their reset configuration.
+pub fn restore_jtag(mut jtdi: Pin, mut jtdo: Pin, mut njtrst: Pin) {
+    jtdi.pull(Pull::Up);
+    jtdo.pull(Pull::Floating);
+    jtdo.output_speed(OutputSpeed::VeryHigh);
+    njtrst.pull(Pull::Up);
+
+    for mut pin in [jtdi, jtdo, njtrst] {
+        pin.mode(PinMode::Alt(0));
+        pin.release();
+    }
+}
@@ -0,0 +1,140 @@
+//! A blocking delay provider, using the SysTick (SYST) timer. Its delay lengths are computed from
+//! the `Clocks` configuration, and it implements the embedded-hal `DelayMs`, `DelayUs`, and
+//! `DelayNs` traits, so it can be passed to driver crates. If SysTick is used for something else,
+//! eg an RTOS tick, use a general-purpose timer, which also implements these traits.
+//!
+//! Example:
+//! ```rust
+//! let mut delay = Delay::new(cp.SYST, &clock_cfg);
+//! delay.delay_ms(500);
+//! ```
+
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+
+#[cfg(feature = "embedded-hal")]
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::clocks::Clocks;
+
+/// The maximum SysTick reload value; it's a 24-bit counter.
+const MAX_RELOAD: u32 = 0x00ff_ffff;
+
+/// A blocking delay, using SysTick.
+pub struct Delay {
+    syst: SYST,
+    /// SysTick frequency, in Hz.
+    freq: u32,
+}
+
+impl Delay {
+    /// Create a delay provider, taking ownership of SysTick. SysTick is clocked from the core
+    /// clock. If you change clock speeds after creating this, eg with `Clocks::change_sysclk()`,
+    /// create a new `Delay`.
+    pub fn new(mut syst: SYST, clocks: &Clocks) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+
+        Self {
+            syst,
+            freq: clocks.systick(),
+        }
+    }
+
+    /// Release the SysTick peripheral.
+    pub fn free(self) -> SYST {
+        self.syst
+    }
+
+    /// Block for a number of SysTick ticks. Long delays are split into multiple SysTick periods.
+    fn delay_ticks(&mut self, mut ticks: u64) {
+        while ticks > 0 {
+            let chunk = ticks.min(MAX_RELOAD as u64 + 1) as u32;
+            ticks -= chunk as u64;
+
+            // A reload value of 0 never wraps, so skip a single remaining tick.
+            if chunk < 2 {
+                continue;
+            }
+
+            // The wrap flag is set `reload + 1` ticks after the counter starts.
+            self.syst.set_reload(chunk - 1);
+            self.syst.clear_current();
+            self.syst.enable_counter();
+            while !self.syst.has_wrapped() {}
+            self.syst.disable_counter();
+        }
+    }
+
+    /// Block for a number of microseconds.
+    pub fn delay_us(&mut self, us: u32) {
+        self.delay_ticks(us as u64 * self.freq as u64 / 1_000_000);
+    }
+
+    /// Block for a number of milliseconds.
+    pub fn delay_ms(&mut self, ms: u32) {
+        self.delay_ticks(ms as u64 * self.freq as u64 / 1_000);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        Delay::delay_ms(self, ms as u32);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayMs<u8> for Delay {
+    fn delay_ms(&mut self, ms: u8) {
+        Delay::delay_ms(self, ms as u32);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        Delay::delay_us(self, us as u32);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+// #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        Delay::delay_us(self, us as u32);
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        // Round up, so we don't return early for short delays.
+        self.delay_ticks((ns as u64 * self.freq as u64 + 999_999_999) / 1_000_000_000);
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        Delay::delay_us(self, us);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::delay_ms(self, ms);
+    }
+}
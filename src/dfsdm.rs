@@ -99,6 +99,22 @@ pub enum SpiClock {
     InternalRising = 3,
 }
 
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Serial interface type, and input clock phase for a given channel. Sets CHyCFGR1 register,
+/// SITP field.
+pub enum SerialInterface {
+    /// SPI, with data sampled on the rising edge of the clock. Eg for the left channel of a
+    /// pair of PDM microphones.
+    SpiRising = 0,
+    /// SPI, with data sampled on the falling edge of the clock.
+    SpiFalling = 1,
+    /// Manchester coded input: Rising edge = logic 0, falling edge = logic 1
+    ManchesterRising = 2,
+    /// Manchester coded input: Rising edge = logic 1, falling edge = logic 0
+    ManchesterFalling = 3,
+}
+
 #[derive(Clone, Copy)]
 /// The type of DFSDM interrupt to configure. Reference Section 30.5 of the H742 RM.
 /// Enabled in FLTxCR2. register. Monitor in FLTxISR register. Cleared by writing to the
@@ -171,6 +187,9 @@ pub struct DfsdmConfig {
     pub offset: u32,
     /// SPI clock select for channel y. Ie internal or external.
     pub spi_clock: SpiClock,
+    /// Serial interface type: SPI, or Manchester-coded, and the sampling edge. Defaults to SPI, rising edge.
+    /// Manchester-coded input requires `spi_clock` to be `External`; the clock is recovered from the data.
+    pub serial_interface: SerialInterface,
 }
 
 impl Default for DfsdmConfig {
@@ -185,6 +204,7 @@ impl Default for DfsdmConfig {
             right_shift_bits: 0, // PDM mic AN uses 0x02. 8 seems right to get to 24 bit signed?
             offset: 0,
             spi_clock: SpiClock::Internal,
+            serial_interface: SerialInterface::SpiRising,
         }
     }
 }
@@ -517,6 +537,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -540,6 +561,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -563,6 +585,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -586,6 +609,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -609,6 +633,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -632,6 +657,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -655,6 +681,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -678,6 +705,7 @@ where
                 });
                 cfgr1.modify(|_, w| {
                     w.spicksel().bits(self.config.spi_clock as u8);
+                    w.sitp().bits(self.config.serial_interface as u8);
                     w.chen().set_bit()
                 });
             },
@@ -1364,6 +1392,234 @@ where
         }
     }
 
+    /// Select the channels in a filter's injected group. When `channels` contains more than one
+    /// channel, each injected conversion trigger converts all of them, in order of channel number
+    /// (scan mode); otherwise, each trigger converts a single channel. Run this before enabling
+    /// the filter. Sets FLTxJCHGR register, and FLTxCR1 register, JSCAN field.
+    pub fn set_injected_group(&mut self, filter: Filter, channels: &[DfsdmChannel]) {
+        let mut mask = 0;
+        for ch in channels {
+            mask |= 1 << *ch as u8;
+        }
+        let scan = channels.len() > 1;
+
+        match filter {
+            Filter::F0 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let jchgr = &self.regs.flt0jchgr;
+                        let cr1 = &self.regs.flt0cr1;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let jchgr = &self.regs.dfsdm0_jchgr;
+                        let cr1 = &self.regs.dfsdm0_cr1;
+                    } else {
+                        let jchgr = &self.regs.flt0.jchgr;
+                        let cr1 = &self.regs.flt0.cr1;
+                    }
+                }
+                jchgr.write(|w| unsafe { w.jchg().bits(mask) });
+                cr1.modify(|_, w| w.jscan().bit(scan));
+            }
+            #[cfg(feature = "l4x6")]
+            Filter::F1 => (),
+            #[cfg(not(feature = "l4x6"))]
+            Filter::F1 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let jchgr = &self.regs.flt1jchgr;
+                        let cr1 = &self.regs.flt1cr1;
+                    } else if #[cfg(any(feature = "l4"))] {
+                    } else {
+                        let jchgr = &self.regs.flt1.jchgr;
+                        let cr1 = &self.regs.flt1.cr1;
+                    }
+                }
+                jchgr.write(|w| unsafe { w.jchg().bits(mask) });
+                cr1.modify(|_, w| w.jscan().bit(scan));
+            }
+            Filter::F2 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let jchgr = &self.regs.flt2jchgr;
+                        let cr1 = &self.regs.flt2cr1;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let jchgr = &self.regs.dfsdm2_jchgr;
+                        let cr1 = &self.regs.dfsdm2_cr1;
+                    } else {
+                        let jchgr = &self.regs.flt2.jchgr;
+                        let cr1 = &self.regs.flt2.cr1;
+                    }
+                }
+                jchgr.write(|w| unsafe { w.jchg().bits(mask) });
+                cr1.modify(|_, w| w.jscan().bit(scan));
+            }
+            Filter::F3 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let jchgr = &self.regs.flt3jchgr;
+                        let cr1 = &self.regs.flt3cr1;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let jchgr = &self.regs.dfsdm3_jchgr;
+                        let cr1 = &self.regs.dfsdm3_cr1;
+                    } else {
+                        let jchgr = &self.regs.flt3.jchgr;
+                        let cr1 = &self.regs.flt3.cr1;
+                    }
+                }
+                jchgr.write(|w| unsafe { w.jchg().bits(mask) });
+                cr1.modify(|_, w| w.jscan().bit(scan));
+            }
+        }
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    /// Read injected conversions using DMA. Set up the injected group with `set_injected_group` first.
+    /// Each result is a signed 24-bit value in the upper 24 bits of its word; the lower 3 bits
+    /// contain the channel number. Uses the same DMA request as regular conversions, so only one of
+    /// injected and regular conversions can use DMA on a given filter.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn read_dma_injected(
+        &mut self,
+        buf: &mut [i32],
+        filter: Filter,
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        #[cfg(feature = "l4")]
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                match filter {
+                    Filter::F0 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F0),
+                    Filter::F1 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F1),
+                    Filter::F2 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F2),
+                    Filter::F3 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F3),
+                };
+            }
+            dma::DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                match filter {
+                    Filter::F0 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F0),
+                    Filter::F1 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F1),
+                    Filter::F2 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F2),
+                    Filter::F3 => dma::channel_select(&mut regs, DmaInput::Dfsdm1F3),
+                };
+            }
+        }
+
+        let periph_addr = match filter {
+            Filter::F0 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let cr1 = &self.regs.flt0cr1;
+                        let jdatar = &self.regs.flt0jdatar;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let cr1 = &self.regs.dfsdm0_cr1;
+                        let jdatar = &self.regs.dfsdm0_jdatar;
+                    } else {
+                        let cr1 = &self.regs.flt0.cr1;
+                        let jdatar = &self.regs.flt0.jdatar;
+                    }
+                }
+                cr1.modify(|_, w| w.jdmaen().set_bit());
+                jdatar as *const _ as u32
+            }
+            #[cfg(feature = "l4x6")]
+            Filter::F1 => 0,
+            #[cfg(not(feature = "l4x6"))]
+            Filter::F1 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let cr1 = &self.regs.flt1cr1;
+                        let jdatar = &self.regs.flt1jdatar;
+                    } else if #[cfg(any(feature = "l4"))] {
+                    } else {
+                        let cr1 = &self.regs.flt1.cr1;
+                        let jdatar = &self.regs.flt1.jdatar;
+                    }
+                }
+                cr1.modify(|_, w| w.jdmaen().set_bit());
+                jdatar as *const _ as u32
+            }
+            Filter::F2 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let cr1 = &self.regs.flt2cr1;
+                        let jdatar = &self.regs.flt2jdatar;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let cr1 = &self.regs.dfsdm2_cr1;
+                        let jdatar = &self.regs.dfsdm2_jdatar;
+                    } else {
+                        let cr1 = &self.regs.flt2.cr1;
+                        let jdatar = &self.regs.flt2.jdatar;
+                    }
+                }
+                cr1.modify(|_, w| w.jdmaen().set_bit());
+                jdatar as *const _ as u32
+            }
+            Filter::F3 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "l5"))] {
+                        let cr1 = &self.regs.flt3cr1;
+                        let jdatar = &self.regs.flt3jdatar;
+                    } else if #[cfg(any(feature = "l4"))] {
+                        let cr1 = &self.regs.dfsdm3_cr1;
+                        let jdatar = &self.regs.dfsdm3_jdatar;
+                    } else {
+                        let cr1 = &self.regs.flt3.cr1;
+                        let jdatar = &self.regs.flt3.jdatar;
+                    }
+                }
+                cr1.modify(|_, w| w.jdmaen().set_bit());
+                jdatar as *const _ as u32
+            }
+        };
+
+        #[cfg(feature = "h7")]
+        let len = len as u32;
+        #[cfg(not(feature = "h7"))]
+        let len = len as u16;
+
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    dma_channel,
+                    periph_addr,
+                    ptr as u32,
+                    len,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+            dma::DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    dma_channel,
+                    periph_addr,
+                    ptr as u32,
+                    len,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+        }
+
+        self.start_injected_conversion(filter);
+    }
+
     /// Enable a specific type of interrupt. See H743 RM, section 30.5: DFSDM interrupts
     pub fn enable_interrupt(&mut self, interrupt_type: DfsdmInterrupt, channel: Filter) {
         // todo: Macro to reduce DRY here?
@@ -126,8 +126,16 @@ pub enum DmaInput {
     Tim4Ch3 = 69,
     Tim4Ch4 = 70,
     Tim4Up = 71,
+    AesIn = 102,
+    AesOut = 103,
     Sai1A = 108,
     Sai1B = 109,
+    FmacRead = 110,
+    FmacWrite = 111,
+    CordicRead = 112,
+    CordicWrite = 113,
+    Ucpd1Rx = 114,
+    Ucpd1Tx = 115,
     // todo: These SAI2 values are bogus; can't find on G4 DMA mux.
     Sai2A = 203,
     Sai2B = 204,
@@ -229,6 +237,11 @@ pub enum DmaInput {
     Uart9Tx = 117,
     Uart10Rx = 118,
     Uart10Tx = 119,
+    // FMAC and CORDIC inputs are from RM0468 (H723-735).
+    FmacRead = 120,
+    FmacWrite = 121,
+    CordicRead = 122,
+    CordicWrite = 123,
 }
 
 #[derive(Copy, Clone)]
@@ -241,7 +254,7 @@ pub enum DmaInput2 {
     Spi6Rx = 11,
     Spi6Tx = 12,
     I2c4Rx = 13,
-    I3crTx = 14,
+    I2c4Tx = 14,
     Sai4A = 15,
     Sai4B = 16,
 }
@@ -1820,6 +1833,101 @@ pub fn mux2(periph: DmaPeriph, channel: DmaChannel, input: DmaInput2, mux: &mut
     mux.ccr[channel as usize].modify(|_, w| unsafe { w.dmareq_id().bits(input as u8) });
 }
 
+// BDMA register offsets. We use raw pointers, since the BDMA register block is named and laid out
+// differently than DMA1 and DMA2 in the PAC, and differs between H7 variants.
+// See H743 RM, section 17.6.7: BDMA register map.
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+const BDMA_CH_OFFSET: usize = 0x08;
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+const BDMA_CH_STRIDE: usize = 0x14;
+
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+/// Enable the BDMA's RCC clock. The BDMA is in the D3 domain, and is the only DMA controller that
+/// can serve D3 peripherals (I2C4, SPI6, LPUART1, SAI4 etc) using DMAMUX2. Note that it can only
+/// access SRAM4, and the D3 backup SRAM; buffers passed to it must be located there.
+pub fn enable_bdma() {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.ahb4enr.modify(|_, w| w.bdmaen().set_bit());
+        rcc.ahb4rstr.modify(|_, w| w.bdmarst().set_bit());
+        rcc.ahb4rstr.modify(|_, w| w.bdmarst().clear_bit());
+    });
+}
+
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+/// Configure a BDMA channel. This is the D3 domain equivalent of `cfg_channel()`. Select the
+/// peripheral with `mux2()`. Sets the Transfer Complete interrupt. See H743 RM, section 17.4.3:
+/// BDMA channel configuration procedure.
+#[allow(clippy::too_many_arguments)]
+pub fn cfg_channel_bdma(
+    channel: DmaChannel,
+    periph_addr: u32,
+    mem_addr: u32,
+    num_data: u16,
+    direction: Direction,
+    periph_size: DataSize,
+    mem_size: DataSize,
+    cfg: ChannelCfg,
+) {
+    let ch = unsafe {
+        (pac::BDMA::ptr() as *mut u8).add(BDMA_CH_OFFSET + BDMA_CH_STRIDE * channel as usize)
+    };
+    let (ccr, cndtr, cpar, cm0ar) = unsafe {
+        (
+            ch as *mut u32,
+            ch.add(4) as *mut u32,
+            ch.add(8) as *mut u32,
+            ch.add(12) as *mut u32,
+        )
+    };
+
+    unsafe {
+        // The channel must be disabled before its registers can be written.
+        ccr.write_volatile(ccr.read_volatile() & !1);
+        while ccr.read_volatile() & 1 != 0 {}
+
+        cpar.write_volatile(periph_addr);
+        atomic::compiler_fence(Ordering::SeqCst);
+        cm0ar.write_volatile(mem_addr);
+        cndtr.write_volatile(num_data as u32);
+
+        // CCR: PL: bits 13:12. MSIZE: 11:10. PSIZE: 9:8. MINC: 7. PINC: 6. CIRC: 5. DIR: 4.
+        // TCIE: 1. EN: 0.
+        let val = (cfg.priority as u32) << 12
+            | (mem_size as u32) << 10
+            | (periph_size as u32) << 8
+            | (cfg.mem_incr as u32) << 7
+            | (cfg.periph_incr as u32) << 6
+            | (cfg.circular as u32) << 5
+            | (direction as u32) << 4
+            | 1 << 1;
+
+        ccr.write_volatile(val);
+        ccr.write_volatile(val | 1);
+    }
+}
+
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+/// Stop a BDMA transfer, if in progress.
+pub fn stop_bdma(channel: DmaChannel) {
+    unsafe {
+        let ccr = (pac::BDMA::ptr() as *mut u8)
+            .add(BDMA_CH_OFFSET + BDMA_CH_STRIDE * channel as usize) as *mut u32;
+        ccr.write_volatile(ccr.read_volatile() & !1);
+        while ccr.read_volatile() & 1 != 0 {}
+    }
+}
+
+#[cfg(all(feature = "h7", not(feature = "h7b3")))]
+/// Clear a BDMA channel's transfer complete, half transfer, and transfer error flags. (IFCR)
+pub fn clear_interrupts_bdma(channel: DmaChannel) {
+    unsafe {
+        // IFCR is at offset 4. Each channel has 4 flag bits: CGIF, CTCIF, CHTIF, CTEIF.
+        let ifcr = (pac::BDMA::ptr() as *mut u8).add(4) as *mut u32;
+        ifcr.write_volatile(0b1111 << (4 * channel as u32));
+    }
+}
+
 // todo: Enable this for other MCUs as requried
 /// Enable the DMA mux RCC clock. Applicable to some variants, but no others. (H7 and G0 don't use it,
 /// for example)
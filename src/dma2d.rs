@@ -0,0 +1,454 @@
+//! Support for the Chrom-ART Accelerator (DMA2D). Fills rectangles with a color, copies
+//! rectangles between images, converting their pixel format, and alpha-blends a foreground image
+//! onto a background, without using the CPU. Useful for drawing into LTDC, or SPI display
+//! framebuffers; eg to implement `embedded_graphics::draw_target::DrawTarget::fill_solid()`, and
+//! image drawing.
+//!
+//! Transfers run in the background: `fill()`, `copy()`, and `blend()` start one, and return.
+//! Block until it's complete with `wait()`, or enable the `TransferComplete` interrupt. A new
+//! transfer waits for the previous one to complete.
+//!
+//! On H7, the DMA2D bypasses the CPU's data cache: Clean the cache for source images, and
+//! invalidate it for the destination, or place framebuffers in non-cacheable memory.
+//!
+//! Example:
+//! ```rust
+//! let mut dma2d = Dma2d::new(dp.DMA2D);
+//!
+//! // A 480x272 RGB565 framebuffer.
+//! let fb = Surface::new(FRAMEBUFFER.as_ptr() as u32, 480, 272, PixelFormat::Rgb565);
+//!
+//! // Clear the screen to black, then draw a red rectangle.
+//! dma2d.fill(&fb, 0)?;
+//! dma2d.wait()?;
+//! dma2d.fill(&fb.area(10, 20, 100, 50), 0xf800)?;
+//!
+//! // Blend a 32x32 ARGB8888 icon onto the framebuffer, at half opacity.
+//! let icon = Surface::new(ICON.as_ptr() as u32, 32, 32, PixelFormat::Argb8888);
+//! let dest = fb.area(200, 100, 32, 32);
+//! let fg = Layer {
+//!     alpha_mode: AlphaMode::Multiply,
+//!     alpha: 0x80,
+//!     ..Layer::new(icon)
+//! };
+//! dma2d.blend(&fg, &Layer::new(dest), &dest)?;
+//! dma2d.wait()?;
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    pac::{DMA2D, RCC},
+    util::rcc_en_reset,
+};
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since DMA2D field names vary between the PACs.
+
+// Register offsets.
+const CR: usize = 0x00;
+const ISR: usize = 0x04;
+const IFCR: usize = 0x08;
+const FGMAR: usize = 0x0c;
+const FGOR: usize = 0x10;
+const BGMAR: usize = 0x14;
+const BGOR: usize = 0x18;
+const FGPFCCR: usize = 0x1c;
+const FGCOLR: usize = 0x20;
+const BGPFCCR: usize = 0x24;
+const BGCOLR: usize = 0x28;
+const OPFCCR: usize = 0x34;
+const OCOLR: usize = 0x38;
+const OMAR: usize = 0x3c;
+const OOR: usize = 0x40;
+const NLR: usize = 0x44;
+const LWR: usize = 0x48;
+
+// DMA2D_CR fields.
+const START: u32 = 1 << 0;
+const ABORT: u32 = 1 << 2;
+const MODE_SHIFT: u32 = 16;
+const MODE_MASK: u32 = 0b111 << MODE_SHIFT;
+
+// DMA2D_ISR, and DMA2D_IFCR fields.
+const TEIF: u32 = 1 << 0;
+const TCIF: u32 = 1 << 1;
+const CEIF: u32 = 1 << 5;
+
+// DMA2D_xPFCCR fields.
+const AM_SHIFT: u32 = 16;
+const ALPHA_SHIFT: u32 = 24;
+
+/// The maximum number of pixels per line, and lines. (NLR PL, and NL fields)
+const MAX_WIDTH: u16 = 0x3fff;
+
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        /// The maximum line offset, in pixels. (xOR LO fields)
+        const MAX_LINE_OFFSET: u32 = 0xffff;
+    } else {
+        const MAX_LINE_OFFSET: u32 = 0x3fff;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// The transfer mode. Sets DMA2D_CR register, MODE field.
+enum Mode {
+    MemToMem = 0b00,
+    MemToMemPfc = 0b01,
+    MemToMemBlend = 0b10,
+    RegToMem = 0b11,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// A pixel format. Sets DMA2D_FGPFCCR, DMA2D_BGPFCCR, and DMA2D_OPFCCR registers, CM fields. Only
+/// `Argb8888`, `Rgb888`, `Rgb565`, `Argb1555`, and `Argb4444` are valid for the destination.
+/// `A8`, and `A4` are alpha-only; their color is set with `Layer::color`. The CLUT formats
+/// (L8, AL44, AL88, and L4) aren't supported.
+pub enum PixelFormat {
+    Argb8888 = 0b0000,
+    Rgb888 = 0b0001,
+    Rgb565 = 0b0010,
+    Argb1555 = 0b0011,
+    Argb4444 = 0b0100,
+    A8 = 0b1001,
+    A4 = 0b1010,
+}
+
+impl PixelFormat {
+    /// The number of bits per pixel.
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Argb8888 => 32,
+            Self::Rgb888 => 24,
+            Self::Rgb565 | Self::Argb1555 | Self::Argb4444 => 16,
+            Self::A8 => 8,
+            Self::A4 => 4,
+        }
+    }
+
+    /// Returns true if the DMA2D can write this format.
+    fn is_output(&self) -> bool {
+        (*self as u8) <= Self::Argb4444 as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// How a layer's pixels' alpha is combined with `Layer::alpha`. Sets DMA2D_FGPFCCR, and
+/// DMA2D_BGPFCCR registers, AM fields.
+pub enum AlphaMode {
+    /// Use the pixels' alpha.
+    NoModify = 0b00,
+    /// Replace the pixels' alpha with `Layer::alpha`.
+    Replace = 0b01,
+    /// Multiply the pixels' alpha by `Layer::alpha`.
+    Multiply = 0b10,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// DMA2D interrupts. Enabled in DMA2D_CR, xIE fields, flagged in DMA2D_ISR, and cleared in
+/// DMA2D_IFCR.
+pub enum Dma2dInterrupt {
+    /// A bus error occurred while accessing memory.
+    TransferError,
+    /// The transfer is complete.
+    TransferComplete,
+    /// The last pixel of the line set with `set_line_watermark()` was written.
+    TransferWatermark,
+    /// The CPU accessed the CLUT while the DMA2D was using it.
+    ClutAccessError,
+    /// A CLUT load completed.
+    ClutTransferComplete,
+    /// The transfer was started with an invalid configuration.
+    ConfigError,
+}
+
+impl Dma2dInterrupt {
+    /// The interrupt's bit in DMA2D_ISR, and DMA2D_IFCR. Its enable bit in DMA2D_CR is 8 bits
+    /// higher.
+    fn flag(&self) -> u32 {
+        1 << *self as u8
+    }
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// A bus error occurred while accessing memory. (TEIF)
+    Transfer,
+    /// The DMA2D rejected the configuration. (CEIF)
+    Config,
+    /// The destination's pixel format can't be written, or a surface's dimensions are zero, or
+    /// out of range.
+    InvalidSurface,
+    /// The source's, and destination's dimensions don't match.
+    SizeMismatch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A rectangular image in memory: a framebuffer, a sprite, or a part of one of these.
+pub struct Surface {
+    /// The address of the top left pixel.
+    pub addr: u32,
+    /// Width, in pixels.
+    pub width: u16,
+    /// Height, in lines.
+    pub height: u16,
+    /// The number of pixels from the start of one line to the start of the next. This is larger
+    /// than `width` for an area within a larger image.
+    pub stride: u16,
+    pub format: PixelFormat,
+}
+
+impl Surface {
+    /// A contiguous image; ie one whose stride is its width.
+    pub fn new(addr: u32, width: u16, height: u16, format: PixelFormat) -> Self {
+        Self {
+            addr,
+            width,
+            height,
+            stride: width,
+            format,
+        }
+    }
+
+    /// A rectangle within this image, starting at pixel `x`, and line `y`. Clipped to this
+    /// image's bounds. For `A4` images, `x`, and `width` must be even.
+    pub fn area(&self, x: u16, y: u16, width: u16, height: u16) -> Self {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+
+        let pixel_offset = y as u32 * self.stride as u32 + x as u32;
+
+        Self {
+            addr: self.addr + pixel_offset * self.format.bits() / 8,
+            width: width.min(self.width - x),
+            height: height.min(self.height - y),
+            stride: self.stride,
+            format: self.format,
+        }
+    }
+
+    /// The number of pixels skipped at the end of each line. (xOR registers, LO fields)
+    fn line_offset(&self) -> u32 {
+        (self.stride - self.width) as u32
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.width == 0
+            || self.height == 0
+            || self.width > MAX_WIDTH
+            || self.stride < self.width
+            || self.line_offset() > MAX_LINE_OFFSET
+        {
+            return Err(Error::InvalidSurface);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A source image for `copy()`, or `blend()`, with its alpha settings.
+pub struct Layer {
+    pub surface: Surface,
+    pub alpha_mode: AlphaMode,
+    /// The alpha value used by `alpha_mode`. 0 is transparent, and 0xff is opaque.
+    pub alpha: u8,
+    /// For `A8`, and `A4` surfaces, the RGB888 color the alpha values apply to. Ignored for other
+    /// formats.
+    pub color: u32,
+}
+
+impl Layer {
+    /// A layer that uses its pixels' alpha.
+    pub fn new(surface: Surface) -> Self {
+        Self {
+            surface,
+            alpha_mode: AlphaMode::NoModify,
+            alpha: 0xff,
+            color: 0,
+        }
+    }
+
+    /// The DMA2D_FGPFCCR, or DMA2D_BGPFCCR register value.
+    fn pfccr(&self) -> u32 {
+        self.surface.format as u32
+            | ((self.alpha_mode as u32) << AM_SHIFT)
+            | ((self.alpha as u32) << ALPHA_SHIFT)
+    }
+}
+
+/// Represents the Chrom-ART Accelerator (DMA2D) peripheral.
+pub struct Dma2d {
+    pub regs: DMA2D,
+}
+
+impl Dma2d {
+    /// Enable and reset the DMA2D's RCC peripheral clock.
+    pub fn new(regs: DMA2D) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            #[cfg(feature = "h7")]
+            rcc_en_reset!(ahb3, dma2d, rcc);
+            #[cfg(feature = "f4")]
+            rcc_en_reset!(ahb1, dma2d, rcc);
+        });
+
+        Self { regs }
+    }
+
+    /// Fill `dest` with a color, in `dest`'s pixel format; eg `0xf800` is red for `Rgb565`.
+    /// (Register-to-memory mode)
+    pub fn fill(&mut self, dest: &Surface, color: u32) -> Result<(), Error> {
+        self.set_output(dest)?;
+        self.write_reg(OCOLR, color);
+
+        self.start(Mode::RegToMem);
+        Ok(())
+    }
+
+    /// Copy `src` to `dest`, converting its pixel format if they're different. `src`'s alpha
+    /// settings apply when converting. The surfaces must be the same size.
+    pub fn copy(&mut self, src: &Layer, dest: &Surface) -> Result<(), Error> {
+        check_size(&src.surface, dest)?;
+        self.set_output(dest)?;
+        self.set_foreground(src);
+
+        let mode = if src.surface.format == dest.format && src.alpha_mode == AlphaMode::NoModify {
+            Mode::MemToMem
+        } else {
+            Mode::MemToMemPfc
+        };
+
+        self.start(mode);
+        Ok(())
+    }
+
+    /// Alpha-blend `fg` onto `bg`, writing the result to `dest`; `dest` is often the same
+    /// surface as `bg`. The surfaces must be the same size.
+    pub fn blend(&mut self, fg: &Layer, bg: &Layer, dest: &Surface) -> Result<(), Error> {
+        check_size(&fg.surface, dest)?;
+        check_size(&bg.surface, dest)?;
+        self.set_output(dest)?;
+        self.set_foreground(fg);
+
+        bg.surface.validate()?;
+        self.write_reg(BGMAR, bg.surface.addr);
+        self.write_reg(BGOR, bg.surface.line_offset());
+        self.write_reg(BGCOLR, bg.color & 0xff_ffff);
+        self.write_reg(BGPFCCR, bg.pfccr());
+
+        self.start(Mode::MemToMemBlend);
+        Ok(())
+    }
+
+    /// Block until the current transfer is complete. Returns an error if a transfer, or
+    /// configuration error occurred.
+    pub fn wait(&mut self) -> Result<(), Error> {
+        while self.is_busy() {}
+
+        let isr = self.read_reg(ISR);
+        self.write_reg(IFCR, TEIF | TCIF | CEIF);
+
+        if isr & TEIF != 0 {
+            Err(Error::Transfer)
+        } else if isr & CEIF != 0 {
+            Err(Error::Config)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns true if a transfer is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.read_reg(CR) & START != 0
+    }
+
+    /// Abort the current transfer, and block until it's stopped.
+    pub fn abort(&mut self) {
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | ABORT);
+        while self.is_busy() {}
+    }
+
+    /// Set the line number that triggers the `TransferWatermark` interrupt. Eg to start sending
+    /// the top of a framebuffer to a display before the transfer is complete. (DMA2D_LWR)
+    pub fn set_line_watermark(&mut self, line: u16) {
+        self.write_reg(LWR, line as u32);
+    }
+
+    /// Enable an interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: Dma2dInterrupt) {
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | (interrupt.flag() << 8));
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: Dma2dInterrupt) {
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr & !(interrupt.flag() << 8));
+    }
+
+    /// Returns true if an interrupt's flag is set.
+    pub fn interrupt_flag(&self, interrupt: Dma2dInterrupt) -> bool {
+        self.read_reg(ISR) & interrupt.flag() != 0
+    }
+
+    /// Clear an interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: Dma2dInterrupt) {
+        self.write_reg(IFCR, interrupt.flag());
+    }
+
+    /// Wait for the previous transfer to complete, and set the output address, format, and size.
+    fn set_output(&mut self, dest: &Surface) -> Result<(), Error> {
+        dest.validate()?;
+        if !dest.format.is_output() {
+            return Err(Error::InvalidSurface);
+        }
+
+        while self.is_busy() {}
+
+        self.write_reg(OPFCCR, dest.format as u32);
+        self.write_reg(OMAR, dest.addr);
+        self.write_reg(OOR, dest.line_offset());
+        self.write_reg(NLR, ((dest.width as u32) << 16) | dest.height as u32);
+        Ok(())
+    }
+
+    fn set_foreground(&mut self, fg: &Layer) {
+        self.write_reg(FGMAR, fg.surface.addr);
+        self.write_reg(FGOR, fg.surface.line_offset());
+        self.write_reg(FGCOLR, fg.color & 0xff_ffff);
+        self.write_reg(FGPFCCR, fg.pfccr());
+    }
+
+    fn start(&mut self, mode: Mode) {
+        self.write_reg(IFCR, TEIF | TCIF | CEIF);
+
+        let cr = self.read_reg(CR) & !MODE_MASK;
+        self.write_reg(CR, cr | ((mode as u32) << MODE_SHIFT) | START);
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((DMA2D::ptr() as usize + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((DMA2D::ptr() as usize + offset) as *mut u32, value) }
+    }
+}
+
+/// Check that a source surface is valid, and the same size as the destination.
+fn check_size(src: &Surface, dest: &Surface) -> Result<(), Error> {
+    src.validate()?;
+    if src.width != dest.width || src.height != dest.height {
+        return Err(Error::SizeMismatch);
+    }
+    Ok(())
+}
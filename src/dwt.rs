@@ -0,0 +1,121 @@
+//! Utilities using the Data Watchpoint and Trace (DWT) unit's cycle counter (CYCCNT): Precise
+//! delays that don't use SysTick or a timer, and execution timing, eg for profiling ISRs and driver
+//! code paths. The counter runs at the core clock, and wraps every 2^32 cycles; eg about 9s at
+//! 480Mhz, or 54s at 80Mhz. Durations longer than this can't be measured.
+//!
+//! Not available on G0, since Cortex-M0+ cores don't have a cycle counter.
+//!
+//! Example:
+//! ```rust
+//! dwt::enable(&mut cp.DCB, &mut cp.DWT);
+//!
+//! dwt::delay_us(10, &clock_cfg);
+//!
+//! let (reading, cycles) = dwt::measure(|| adc.read(AdcChannel::C1));
+//!
+//! let sw = Stopwatch::start();
+//! // ...
+//! defmt::println!("Elapsed: {}µs", sw.elapsed_us(&clock_cfg));
+//! ```
+
+use cortex_m::peripheral::{DCB, DWT};
+
+use crate::clocks::Clocks;
+
+/// Enable the DWT cycle counter. This must be run before using the other functions in this
+/// module. Note that debuggers may also configure the DWT.
+pub fn enable(dcb: &mut DCB, dwt: &mut DWT) {
+    // TRCENA must be set for the DWT to operate.
+    dcb.enable_trace();
+    // On Cortex-M7 (H7), the DWT is write-protected through its lock access register.
+    #[cfg(feature = "h7")]
+    DWT::unlock();
+    dwt.enable_cycle_counter();
+}
+
+/// Read the cycle counter. (DWT_CYCCNT)
+pub fn cycles() -> u32 {
+    DWT::cycle_count()
+}
+
+/// The core clock frequency, in Hz, which the cycle counter runs at. (SysTick is clocked from the
+/// core clock)
+fn core_freq(clocks: &Clocks) -> u32 {
+    clocks.systick()
+}
+
+/// Block for a number of core clock cycles. The overhead of this function adds a few cycles.
+pub fn delay_cycles(cycles: u32) {
+    let start = DWT::cycle_count();
+    while DWT::cycle_count().wrapping_sub(start) < cycles {}
+}
+
+/// Block for a number of microseconds. This doesn't use SysTick, or a timer, and is precise to
+/// within a few core clock cycles.
+pub fn delay_us(us: u32, clocks: &Clocks) {
+    let mut cycles = us as u64 * core_freq(clocks) as u64 / 1_000_000;
+
+    // Split long delays, so the counter can't wrap past the starting point during a delay.
+    const MAX_CHUNK: u64 = u32::MAX as u64 / 2;
+    while cycles > 0 {
+        let chunk = cycles.min(MAX_CHUNK);
+        delay_cycles(chunk as u32);
+        cycles -= chunk;
+    }
+}
+
+/// Block for a number of milliseconds.
+pub fn delay_ms(ms: u32, clocks: &Clocks) {
+    for _ in 0..ms {
+        delay_us(1_000, clocks);
+    }
+}
+
+/// Run a closure, and return its result, and the number of core clock cycles it took.
+pub fn measure<F, R>(f: F) -> (R, u32)
+where
+    F: FnOnce() -> R,
+{
+    let start = DWT::cycle_count();
+    let result = f();
+    (result, DWT::cycle_count().wrapping_sub(start))
+}
+
+/// Convert a number of cycles to microseconds.
+pub fn cycles_to_us(cycles: u32, clocks: &Clocks) -> u32 {
+    (cycles as u64 * 1_000_000 / core_freq(clocks) as u64) as u32
+}
+
+#[derive(Clone, Copy)]
+/// Measures elapsed time from when it's started, using the cycle counter.
+pub struct Stopwatch {
+    start: u32,
+}
+
+impl Stopwatch {
+    /// Start a stopwatch.
+    pub fn start() -> Self {
+        Self {
+            start: DWT::cycle_count(),
+        }
+    }
+
+    /// Reset the starting point to now, and return the number of cycles elapsed before the reset.
+    /// Useful for measuring consecutive intervals, eg between ISR runs.
+    pub fn lap(&mut self) -> u32 {
+        let now = DWT::cycle_count();
+        let elapsed = now.wrapping_sub(self.start);
+        self.start = now;
+        elapsed
+    }
+
+    /// The number of core clock cycles elapsed since the stopwatch was started.
+    pub fn elapsed_cycles(&self) -> u32 {
+        DWT::cycle_count().wrapping_sub(self.start)
+    }
+
+    /// The time elapsed since the stopwatch was started, in microseconds.
+    pub fn elapsed_us(&self, clocks: &Clocks) -> u32 {
+        cycles_to_us(self.elapsed_cycles(), clocks)
+    }
+}
@@ -0,0 +1,181 @@
+//! Helpers for reducing electromagnetic interference (EMI) from firmware, eg while tuning a board
+//! for EMC compliance. These include GPIO slew rate (output speed) control by port, the I/O
+//! compensation cell on F4 and H7, and PLL spread-spectrum clock generation on F4.
+//!
+//! Slower output speeds reduce edge rates, and the harmonics they produce; use the slowest speed
+//! that meets each signal's timing. Spread-spectrum clocking spreads the energy of the system
+//! clock, and its harmonics across a band, reducing peak emissions.
+//!
+//! Example:
+//! ```rust
+//! // Slow all of port B's pins, except PB13 - 15, used by SPI2.
+//! emi::set_port_speed(Port::B, 0x1fff, OutputSpeed::Low);
+//!
+//! // F4: Spread the PLL output by 2% (peak), downwards, at 10kHz. Call before `clock_cfg.setup()`.
+//! emi::enable_spread_spectrum(&clock_cfg, 10_000, 20, SpreadMode::Down)?;
+//! clock_cfg.setup()?;
+//! ```
+
+#[cfg(feature = "f4")]
+use core::ptr::write_volatile;
+
+use cortex_m::interrupt::free;
+
+use crate::gpio::{self, OutputSpeed, Port};
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+use crate::pac::{self, RCC};
+
+#[cfg(feature = "f4")]
+use crate::clocks::{Clocks, InputSrc, PllSrc, SpeedError};
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+use cfg_if::cfg_if;
+
+/// Set the output speed (slew rate) of multiple pins on a port, with a single write to the
+/// `OSPEEDR` register. `pins` is a bit mask; eg `0b1010` sets pins 1 and 3. See also
+/// `Pin::output_speed()`, for a single pin.
+pub fn set_port_speed(port: Port, pins: u16, speed: OutputSpeed) {
+    let mut mask = 0;
+    let mut val = 0;
+
+    for pin in 0..16 {
+        if pins & (1 << pin) != 0 {
+            mask |= 0b11 << (pin * 2);
+            val |= (speed as u32) << (pin * 2);
+        }
+    }
+
+    free(|_| unsafe {
+        (*gpio::regs(port))
+            .ospeedr
+            .modify(|r, w| w.bits((r.bits() & !mask) | val));
+    });
+}
+
+// We use raw bits for the compensation cell, since its register, and field names vary between
+// the F4 and H7 PACs. The F4 PAC also marks CMPCR read-only, so we write it through a pointer.
+#[cfg(any(feature = "f4", feature = "h7"))]
+/// SYSCFG_CMPCR (F4): CMP_PD, and SYSCFG_CCCSR (H7): EN.
+const COMPENSATION_EN: u32 = 1 << 0;
+#[cfg(any(feature = "f4", feature = "h7"))]
+/// SYSCFG_CMPCR, and SYSCFG_CCCSR: READY.
+const COMPENSATION_READY: u32 = 1 << 8;
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+/// Enable the I/O compensation cell, and wait for it to be ready. This adjusts output drive to
+/// compensate for process, voltage, and temperature variation, reducing overshoot, and ringing
+/// on fast edges. ST recommends it when using `OutputSpeed::High`, or `VeryHigh` with VDD above
+/// 2.4V. Enables the SYSCFG peripheral clock.
+pub fn enable_io_compensation() {
+    let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+                let val = syscfg.cmpcr.read().bits();
+                unsafe { write_volatile(syscfg.cmpcr.as_ptr(), val | COMPENSATION_EN) };
+            } else {
+                rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
+                syscfg.cccsr.modify(|r, w| unsafe { w.bits(r.bits() | COMPENSATION_EN) });
+            }
+        }
+    });
+
+    while !io_compensation_ready() {}
+}
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+/// Disable the I/O compensation cell, eg to save power.
+pub fn disable_io_compensation() {
+    let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "f4")] {
+            let val = syscfg.cmpcr.read().bits();
+            unsafe { write_volatile(syscfg.cmpcr.as_ptr(), val & !COMPENSATION_EN) };
+        } else {
+            syscfg.cccsr.modify(|r, w| unsafe { w.bits(r.bits() & !COMPENSATION_EN) });
+        }
+    }
+}
+
+#[cfg(any(feature = "f4", feature = "h7"))]
+/// Returns true if the I/O compensation cell is enabled, and ready.
+pub fn io_compensation_ready() -> bool {
+    let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "f4")] {
+            syscfg.cmpcr.read().bits() & COMPENSATION_READY != 0
+        } else {
+            syscfg.cccsr.read().bits() & COMPENSATION_READY != 0
+        }
+    }
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+/// Spread-spectrum modulation. Sets RCC_SSCGR, SPREADSEL bit.
+pub enum SpreadMode {
+    /// The frequency is spread above, and below the nominal PLL frequency.
+    Center = 0,
+    /// The frequency is spread below the nominal PLL frequency, so it's never exceeded.
+    Down = 1,
+}
+
+#[cfg(feature = "f4")]
+/// Enable spread-spectrum modulation of the main PLL. `modulation_freq` is in Hz, and must be at
+/// most 10kHz. `depth` is the peak modulation depth, in 0.1% steps; eg 20 for 2%, up to 2% total.
+/// This must be called before the PLL is enabled; ie before `Clocks::setup()`. Returns an error
+/// if the PLL isn't the system clock source, or the resulting settings are out of range. See F4
+/// RM, section 6.3.18: RCC spread spectrum clock generation register (RCC_SSCGR).
+pub fn enable_spread_spectrum(
+    clocks: &Clocks,
+    modulation_freq: u32,
+    depth: u32,
+    mode: SpreadMode,
+) -> Result<(), SpeedError> {
+    let pll_input = match clocks.input_src {
+        InputSrc::Pll(PllSrc::Hsi) => 16_000_000,
+        InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+        _ => return Err(SpeedError::new("Spread spectrum requires the PLL.")),
+    } / clocks.pllm as u32;
+
+    if modulation_freq == 0 || modulation_freq > 10_000 || depth == 0 || depth > 20 {
+        return Err(SpeedError::new("Spread spectrum settings out of range."));
+    }
+
+    // MODPER = round(f_pll_in / (4 * f_mod))
+    let modper = (pll_input + 2 * modulation_freq) / (4 * modulation_freq);
+    // INCSTEP = round((2^15 - 1) * md * PLLN / (100 * 5 * MODPER)), with md in %. Our depth is
+    // in 0.1%, hence the extra factor of 10.
+    let incstep =
+        ((((1 << 15) - 1) * depth * clocks.plln as u32) + 2_500 * modper) / (5_000 * modper);
+
+    // The fields are 13, and 15 bits, and their product must not exceed 2^15 - 1.
+    if modper >= 1 << 13 || incstep == 0 || incstep >= 1 << 15 || modper * incstep > (1 << 15) - 1 {
+        return Err(SpeedError::new("Spread spectrum settings out of range."));
+    }
+
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.sscgr.write(|w| unsafe {
+        w.modper().bits(modper as u16);
+        w.incstep().bits(incstep as u16);
+        w.spreadsel().bit(mode as u8 != 0);
+        w.sscgen().set_bit()
+    });
+
+    Ok(())
+}
+
+#[cfg(feature = "f4")]
+/// Disable spread-spectrum modulation. Like enabling it, this must be done while the PLL is
+/// disabled.
+pub fn disable_spread_spectrum() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.sscgr.modify(|_, w| w.sscgen().clear_bit());
+}
@@ -0,0 +1,314 @@
+//! eMMC (embedded MultiMediaCard) support: Parsing the Extended CSD register, building the
+//! CMD6 (SWITCH) arguments used to select the bus width, bus timing, and the active partition
+//! (user area, boot partitions, RPMB, or general-purpose partitions), and RPMB frames.
+//!
+//! The `sdmmc` module's `Sdmmc::init_emmc()` uses this to initialize eMMC devices; its
+//! `select_partition()`, `rpmb_read()`, and `rpmb_read_counter()` methods use the partition
+//! switch, and RPMB frames. See JEDEC JESD84-B51.
+//!
+//! RPMB (Replay Protected Memory Block) responses are authenticated with an HMAC-SHA256 MAC,
+//! using a key programmed into the device once. Reading doesn't require the key, but verifying
+//! the MAC does; that, and authenticated writes, are left to the application.
+
+/// The Extended CSD register's size, in bytes.
+pub const EXT_CSD_LEN: usize = 512;
+
+/// MMC command index for SWITCH.
+pub const CMD_SWITCH: u8 = 6;
+/// MMC command index for SEND_EXT_CSD.
+pub const CMD_SEND_EXT_CSD: u8 = 8;
+
+// Extended CSD field byte offsets, from JESD84-B51, section 7.4.
+const PARTITION_CONFIG: usize = 179;
+const BOOT_BUS_CONDITIONS: usize = 177;
+const BUS_WIDTH: usize = 183;
+const HS_TIMING: usize = 185;
+const EXT_CSD_REV: usize = 192;
+const DEVICE_TYPE: usize = 196;
+const RPMB_SIZE_MULT: usize = 168;
+const SEC_COUNT: usize = 212;
+const BOOT_SIZE_MULT: usize = 226;
+
+// RPMB request types.
+pub const RPMB_REQ_READ_COUNTER: u16 = 0x0002;
+pub const RPMB_REQ_READ_DATA: u16 = 0x0004;
+
+// RPMB frame field byte offsets. Fields are big-endian.
+const RPMB_MAC: usize = 196;
+const RPMB_DATA: usize = 228;
+const RPMB_NONCE: usize = 484;
+const RPMB_WRITE_COUNTER: usize = 500;
+const RPMB_ADDRESS: usize = 504;
+const RPMB_BLOCK_COUNT: usize = 506;
+const RPMB_RESULT: usize = 508;
+const RPMB_REQ_RESP: usize = 510;
+
+/// Boot and RPMB partition sizes are in units of 128kB.
+const PARTITION_SIZE_UNIT: u32 = 128 * 1_024;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Data bus width, and data rate. Sets the Extended CSD `BUS_WIDTH` field.
+pub enum BusWidth {
+    W1 = 0,
+    W4 = 1,
+    W8 = 2,
+    /// 4 bits, dual data rate.
+    W4Ddr = 5,
+    /// 8 bits, dual data rate.
+    W8Ddr = 6,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Bus timing interface. Sets the Extended CSD `HS_TIMING` field.
+pub enum Timing {
+    /// Backwards-compatible timing, up to 26Mhz.
+    Legacy = 0,
+    /// High speed, up to 52Mhz.
+    HighSpeed = 1,
+    /// HS200, up to 200Mhz SDR. Requires 1.8V or 1.2V IO, and tuning.
+    Hs200 = 2,
+    /// HS400, up to 200Mhz DDR, on an 8-bit bus.
+    Hs400 = 3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// eMMC partition. Sets the Extended CSD `PARTITION_CONFIG` register, `PARTITION_ACCESS` field.
+pub enum Partition {
+    User = 0,
+    Boot1 = 1,
+    Boot2 = 2,
+    Rpmb = 3,
+    General1 = 4,
+    General2 = 5,
+    General3 = 6,
+    General4 = 7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// eMMC errors.
+pub enum EmmcError {
+    /// The Extended CSD buffer was the wrong length.
+    InvalidExtCsd,
+    /// The device doesn't support the requested bus mode.
+    Unsupported,
+}
+
+/// Build a CMD6 (SWITCH) argument, which writes a byte to the Extended CSD.
+fn switch_arg(index: usize, value: u8) -> u32 {
+    // Access mode (bits 25:24) 0b11: Write byte. Index: bits 23:16. Value: bits 15:8.
+    (0b11 << 24) | ((index as u32) << 16) | ((value as u32) << 8)
+}
+
+/// A parsed copy of the Extended CSD register.
+pub struct ExtCsd {
+    /// Raw register contents.
+    pub raw: [u8; EXT_CSD_LEN],
+}
+
+impl ExtCsd {
+    /// Parse the Extended CSD, from the 512-byte block returned by CMD8.
+    pub fn new(data: &[u8]) -> Result<Self, EmmcError> {
+        if data.len() != EXT_CSD_LEN {
+            return Err(EmmcError::InvalidExtCsd);
+        }
+
+        let mut raw = [0; EXT_CSD_LEN];
+        raw.copy_from_slice(data);
+
+        Ok(Self { raw })
+    }
+
+    /// Extended CSD revision. eg 8 for eMMC 5.1.
+    pub fn revision(&self) -> u8 {
+        self.raw[EXT_CSD_REV]
+    }
+
+    /// The number of 512-byte sectors in the user area.
+    pub fn sector_count(&self) -> u32 {
+        u32::from_le_bytes([
+            self.raw[SEC_COUNT],
+            self.raw[SEC_COUNT + 1],
+            self.raw[SEC_COUNT + 2],
+            self.raw[SEC_COUNT + 3],
+        ])
+    }
+
+    /// The size of the user area, in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.sector_count() as u64 * 512
+    }
+
+    /// The size of each boot partition, in bytes.
+    pub fn boot_partition_size(&self) -> u32 {
+        self.raw[BOOT_SIZE_MULT] as u32 * PARTITION_SIZE_UNIT
+    }
+
+    /// The size of the RPMB partition, in bytes.
+    pub fn rpmb_size(&self) -> u32 {
+        self.raw[RPMB_SIZE_MULT] as u32 * PARTITION_SIZE_UNIT
+    }
+
+    /// The partition currently selected for access.
+    pub fn partition(&self) -> Partition {
+        match self.raw[PARTITION_CONFIG] & 0b111 {
+            0 => Partition::User,
+            1 => Partition::Boot1,
+            2 => Partition::Boot2,
+            3 => Partition::Rpmb,
+            4 => Partition::General1,
+            5 => Partition::General2,
+            6 => Partition::General3,
+            _ => Partition::General4,
+        }
+    }
+
+    /// The partition the device boots from: 1 or 2 for a boot partition, 7 for the user area,
+    /// or 0 if boot is disabled. `BOOT_PARTITION_ENABLE` field.
+    pub fn boot_partition_enable(&self) -> u8 {
+        (self.raw[PARTITION_CONFIG] >> 3) & 0b111
+    }
+
+    /// The boot bus width, and timing. `BOOT_BUS_CONDITIONS` field.
+    pub fn boot_bus_conditions(&self) -> u8 {
+        self.raw[BOOT_BUS_CONDITIONS]
+    }
+
+    /// The current bus width.
+    pub fn bus_width(&self) -> u8 {
+        self.raw[BUS_WIDTH]
+    }
+
+    /// The current timing interface.
+    pub fn timing(&self) -> u8 {
+        self.raw[HS_TIMING] & 0xf
+    }
+
+    /// Returns true if the device supports a bus timing. (`DEVICE_TYPE` field)
+    pub fn supports_timing(&self, timing: Timing) -> bool {
+        let device_type = self.raw[DEVICE_TYPE];
+
+        match timing {
+            Timing::Legacy => true,
+            // High speed at 26Mhz, or 52Mhz.
+            Timing::HighSpeed => device_type & 0b11 != 0,
+            // HS200 at 1.8V, or 1.2V.
+            Timing::Hs200 => device_type & 0b11_0000 != 0,
+            Timing::Hs400 => device_type & 0b1100_0000 != 0,
+        }
+    }
+
+    /// Returns true if the device supports dual data rate, at 1.8V/3V, or 1.2V.
+    pub fn supports_ddr(&self) -> bool {
+        self.raw[DEVICE_TYPE] & 0b1100 != 0
+    }
+
+    /// Build the CMD6 argument to set the bus width. Returns an error if the device doesn't support
+    /// the dual data rate modes. Change the host controller's bus width after the switch completes.
+    pub fn bus_width_arg(&self, width: BusWidth) -> Result<u32, EmmcError> {
+        if matches!(width, BusWidth::W4Ddr | BusWidth::W8Ddr) && !self.supports_ddr() {
+            return Err(EmmcError::Unsupported);
+        }
+
+        Ok(switch_arg(BUS_WIDTH, width as u8))
+    }
+
+    /// Build the CMD6 argument to set the bus timing. Returns an error if the device doesn't
+    /// support it. Increase the host controller's clock after the switch completes.
+    pub fn timing_arg(&self, timing: Timing) -> Result<u32, EmmcError> {
+        if !self.supports_timing(timing) {
+            return Err(EmmcError::Unsupported);
+        }
+
+        Ok(switch_arg(HS_TIMING, timing as u8))
+    }
+
+    /// Build the CMD6 argument to select the partition that subsequent reads and writes access.
+    /// This preserves the boot configuration. Update this struct with `set_partition()` once
+    /// the switch completes, or re-read the Extended CSD.
+    pub fn partition_arg(&self, partition: Partition) -> u32 {
+        let config = (self.raw[PARTITION_CONFIG] & !0b111) | partition as u8;
+        switch_arg(PARTITION_CONFIG, config)
+    }
+
+    /// Update the cached partition selection, after a successful switch.
+    pub fn set_partition(&mut self, partition: Partition) {
+        self.raw[PARTITION_CONFIG] = (self.raw[PARTITION_CONFIG] & !0b111) | partition as u8;
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+/// An RPMB data frame: A 512-byte block sent to, or received from the RPMB partition.
+pub struct RpmbFrame {
+    pub raw: [u8; 512],
+}
+
+impl Default for RpmbFrame {
+    fn default() -> Self {
+        Self { raw: [0; 512] }
+    }
+}
+
+impl RpmbFrame {
+    /// Build a request frame. The block count is left at 0; the device reads it from CMD23.
+    pub fn request(request_type: u16, nonce: &[u8; 16], address: u16) -> Self {
+        let mut result = Self::default();
+
+        result.raw[RPMB_NONCE..RPMB_NONCE + 16].copy_from_slice(nonce);
+        result.raw[RPMB_ADDRESS..RPMB_ADDRESS + 2].copy_from_slice(&address.to_be_bytes());
+        result.raw[RPMB_REQ_RESP..RPMB_REQ_RESP + 2].copy_from_slice(&request_type.to_be_bytes());
+
+        result
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.raw[offset], self.raw[offset + 1]])
+    }
+
+    /// The HMAC-SHA256 MAC. In a multi-frame response, it's only set in the last frame, and
+    /// covers bytes 228 through 511 of each frame.
+    pub fn mac(&self) -> &[u8] {
+        &self.raw[RPMB_MAC..RPMB_DATA]
+    }
+
+    /// The 256 bytes of data.
+    pub fn data(&self) -> &[u8] {
+        &self.raw[RPMB_DATA..RPMB_NONCE]
+    }
+
+    pub fn nonce(&self) -> &[u8] {
+        &self.raw[RPMB_NONCE..RPMB_WRITE_COUNTER]
+    }
+
+    /// The number of authenticated writes performed.
+    pub fn write_counter(&self) -> u32 {
+        u32::from_be_bytes([
+            self.raw[RPMB_WRITE_COUNTER],
+            self.raw[RPMB_WRITE_COUNTER + 1],
+            self.raw[RPMB_WRITE_COUNTER + 2],
+            self.raw[RPMB_WRITE_COUNTER + 3],
+        ])
+    }
+
+    /// The half-sector address.
+    pub fn address(&self) -> u16 {
+        self.read_u16(RPMB_ADDRESS)
+    }
+
+    pub fn block_count(&self) -> u16 {
+        self.read_u16(RPMB_BLOCK_COUNT)
+    }
+
+    /// The operation result. 0 means success. Bit 7 is set when the write counter has expired.
+    pub fn result(&self) -> u16 {
+        self.read_u16(RPMB_RESULT)
+    }
+
+    /// The request, or response type.
+    pub fn req_resp(&self) -> u16 {
+        self.read_u16(RPMB_REQ_RESP)
+    }
+}
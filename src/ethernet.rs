@@ -1,157 +1,1135 @@
-//! WIP. This module contains ethernet code for the H7, for use with its Synopsys ethernet
-//! hardware.
-//! With the `network` feature enabled, allows support for the [smoltcp stack](https://docs.rs/smoltcp/latest/smoltcp/).
+//! This module contains Ethernet code for the H7, for use with its Synopsys Ethernet MAC, and its
+//! DMA. It includes MAC configuration, SMI (MDIO) access to the PHY, DMA descriptor rings for
+//! transmit and receive, and an implementation of smoltcp's `Device` trait, for use with the
+//! [smoltcp stack](https://docs.rs/smoltcp/latest/smoltcp/). Requires the `net` feature.
 //! See H743 RM, chapter 58.
-//! https://www.keil.com/pack/doc/CMSIS/Driver/html/group__eth__interface__gr.html ?
+//!
+//! Set the RMII (or MII) pins to alternate function 11 before initializing. The descriptor rings
+//! must be in memory the Ethernet DMA can access: AXI SRAM, or SRAM1 - 3, but not DTCM. If the
+//! data cache is enabled, configure that region as non-cacheable using the MPU.
+//!
+//! Example:
+//! ```rust
+//! #[link_section = ".axisram.eth"]
+//! static mut DES_RING: DescriptorRing<4, 4> = DescriptorRing::new();
+//!
+//! const PHY_ADDR: u8 = 0;
+//!
+//! let mut eth = Eth::new(
+//!     dp.ETHERNET_DMA,
+//!     dp.ETHERNET_MAC,
+//!     dp.ETHERNET_MTL,
+//!     unsafe { &mut DES_RING },
+//!     Default::default(),
+//!     &clock_cfg,
+//! );
+//!
+//! eth.phy_reset(PHY_ADDR);
+//! eth.phy_autonegotiate(PHY_ADDR);
+//!
+//! // Once auto-negotiation completes, match the MAC to the negotiated link.
+//! if let Some((speed, duplex)) = eth.phy_negotiated_link(PHY_ADDR) {
+//!     eth.set_link(speed, duplex);
+//! }
+//!
+//! // `eth` can now be passed to smoltcp's `InterfaceBuilder::new()`.
+//! ```
 
-use core::ops::Deref;
+use core::{
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
 
 use cortex_m::interrupt::free;
 
 use smoltcp::{
     self,
-    phy::{self, Device, DeviceCapabilities, Medium},
+    phy::{self, Checksum, Device, DeviceCapabilities, Medium},
     time::Instant,
 };
 
 use crate::{
-    pac::{self, ETHERNET_DMA, ETHERNET_MAC, ETHERNET_MTL, RCC},
-    util::RccPeriph,
+    clocks::Clocks,
+    pac::{ETHERNET_DMA, ETHERNET_MAC, ETHERNET_MTL, RCC, SYSCFG},
 };
 
+/// The size of each packet buffer, in bytes. This holds a full Ethernet frame, including a VLAN
+/// tag, and the CRC.
+pub const ETH_BUF_SIZE: usize = 1_536;
+
+/// The maximum frame size passed to the network stack: 1500 bytes of payload, and the 14-byte
+/// header.
+const ETH_MTU: usize = 1_514;
+
+// PTP sub-second increment. We run the system time at 50Mhz, ie 20ns per increment; this must be
+// below the PTP reference clock (HCLK) frequency.
+const PTP_SSINC_NS: u32 = 20;
+const PTP_CLOCK_FREQ: u64 = 1_000_000_000 / PTP_SSINC_NS as u64;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+// Descriptor bits used for PTP timestamps. See H743 RM, section 58.10: Descriptors.
+/// TDES2 (read format): Transmit timestamp enable.
+pub const TDES2_TTSE: u32 = 1 << 30;
+/// TDES3 (write-back format): Transmit timestamp status. If set, TDES0 and TDES1 contain the
+/// packet's timestamp.
+pub const TDES3_TTSS: u32 = 1 << 17;
+/// RDES1 (normal write-back format): Timestamp available. If set, the packet's timestamp is in the
+/// following context descriptor.
+pub const RDES1_TSA: u32 = 1 << 14;
+/// RDES3 (write-back format): Context descriptor. If set, RDES0 and RDES1 contain the previous
+/// packet's timestamp.
+pub const RDES3_CTXT: u32 = 1 << 30;
+
+// Descriptor bits used by the transmit and receive rings.
+/// TDES3, and RDES3: The DMA owns the descriptor.
+const DES3_OWN: u32 = 1 << 31;
+/// TDES3 (read format): First, and last descriptor of the packet.
+const TDES3_FD: u32 = 1 << 29;
+const TDES3_LD: u32 = 1 << 28;
+/// TDES3 (read format): Checksum insertion control: Insert the IP header checksum, and the
+/// TCP, UDP, or ICMP checksum, including the pseudo-header.
+const TDES3_CIC_FULL: u32 = 0b11 << 16;
+/// RDES3 (read format): Interrupt on completion, and buffer 1 address valid.
+const RDES3_IOC: u32 = 1 << 30;
+const RDES3_BUF1V: u32 = 1 << 24;
+/// RDES3 (write-back format): First, and last descriptor of the packet, and error summary.
+const RDES3_FD: u32 = 1 << 29;
+const RDES3_LD: u32 = 1 << 28;
+const RDES3_ES: u32 = 1 << 15;
+
+// IEEE 802.3 standard PHY registers, and their fields.
+const PHY_BCR: u8 = 0;
+const PHY_BCR_RESET: u16 = 1 << 15;
+const PHY_BCR_ANEN: u16 = 1 << 12;
+const PHY_BCR_RESTART_AN: u16 = 1 << 9;
+const PHY_BSR: u8 = 1;
+const PHY_BSR_AN_COMPLETE: u16 = 1 << 5;
+const PHY_BSR_LINK_STATUS: u16 = 1 << 2;
+const PHY_ANAR: u8 = 4;
+const PHY_ANLPAR: u8 = 5;
+// Technology ability fields, in the ANAR and ANLPAR registers.
+const PHY_AN_100FD: u16 = 1 << 8;
+const PHY_AN_100HD: u16 = 1 << 7;
+const PHY_AN_10FD: u16 = 1 << 6;
+const PHY_AN_10HD: u16 = 1 << 5;
+/// ANAR register, selector field: IEEE 802.3.
+const PHY_AN_SELECTOR: u16 = 0b0_0001;
+
+/// A PTP (IEEE 1588) timestamp, or system time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timestamp {
+    pub seconds: u32,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    /// Read a timestamp from the first 2 words of a descriptor: A transmit descriptor with
+    /// `TDES3_TTSS` set, or a receive context descriptor. (`RDES3_CTXT` set)
+    pub fn from_descriptor(des0: u32, des1: u32) -> Self {
+        Self {
+            seconds: des1,
+            nanos: des0,
+        }
+    }
+
+    /// The timestamp, in nanoseconds.
+    pub fn as_nanos(&self) -> u64 {
+        self.seconds as u64 * NANOS_PER_SEC as u64 + self.nanos as u64
+    }
+}
+
+/// A MAC (hardware) address, eg `[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]`.
+pub type MacAddr = [u8; 6];
+
+/// Receive packet filter settings. Sets the `MACPFR` register.
+/// See H743 RM, section 58.5.7: Destination address filtering.
+#[derive(Clone)]
+pub struct MacFilterConfig {
+    /// Pass all packets, regardless of their destination address. Defaults to false.
+    pub promiscuous: bool,
+    /// Pass all multicast packets. Defaults to false.
+    pub pass_all_multicast: bool,
+    /// Filter multicast packets using the hash table, set with `set_multicast_hash`, vice
+    /// perfect filtering. Defaults to true.
+    pub hash_multicast: bool,
+    /// Filter unicast packets using the hash table, vice perfect filtering. Defaults to false.
+    pub hash_unicast: bool,
+    /// Block broadcast packets. Defaults to false.
+    pub block_broadcast: bool,
+}
+
+impl Default for MacFilterConfig {
+    fn default() -> Self {
+        Self {
+            promiscuous: false,
+            pass_all_multicast: false,
+            hash_multicast: true,
+            hash_unicast: false,
+            block_broadcast: false,
+        }
+    }
+}
+
+/// VLAN tag filtering, and stripping. Sets the `MACVTR` register.
+/// See H743 RM, section 58.5.8: VLAN filtering.
+#[derive(Clone, Copy)]
+pub struct VlanConfig {
+    /// The VLAN tag to pass. If `compare_vid_only` is true, only the 12-bit VLAN ID is compared;
+    /// otherwise the full 16 bits, including priority, are.
+    pub tag: u16,
+    pub compare_vid_only: bool,
+    /// Remove the VLAN tag from received packets. Defaults to false.
+    pub strip: bool,
+}
+
+/// Find a MAC address's index in the 64-bit hash table: the upper 6 bits of the bit-reversed
+/// CRC-32 of the address.
+fn mac_hash_index(addr: &MacAddr) -> u8 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in addr {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    ((!crc).reverse_bits() >> 26) as u8
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The interface to the PHY. Sets SYSCFG_PMCR register, EPIS field.
+pub enum PhyInterface {
+    Mii = 0b000,
+    Rmii = 0b100,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Link speed. Sets MACCR register, FES field.
+pub enum LinkSpeed {
+    S10M = 0,
+    S100M = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Duplex mode. Sets MACCR register, DM field.
+pub enum Duplex {
+    Half = 0,
+    Full = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Ethernet DMA interrupts. Enabled in the DMACIER register, and cleared in DMACSR. The value
+/// is the bit position in both.
+pub enum EthInterrupt {
+    /// A packet was transmitted.
+    Transmit = 0,
+    /// A packet was received.
+    Receive = 6,
+}
+
 /// Configuration data for Ethernet
-pub struct EthConfig {}
-
-// impl Default for EthConfig {
-//     fn default() -> Self {
-//         Self {
-//             mode: SpiMode::mode0(),
-//             comm_mode: SpiCommMode::FullDuplex,
-//             slave_select: SlaveSelect::Software,
-//             data_size: DataSize::D8,
-//             fifo_reception_thresh: ReceptionThresh::D8,
-//         }
-//     }
-// }
-
-/// Represents an ethernet peripheral.
-pub struct Eth<RDma, RMac, RMtl> {
-    pub regs_dma: RDma,
-    pub regs_mac: RMac,
-    pub regs_mtl: RMtl,
+pub struct EthConfig {
+    /// Defaults to RMII.
+    pub interface: PhyInterface,
+    /// The device's MAC address. Defaults to `02:00:00:00:00:01`, a locally-administered address.
+    pub mac_addr: MacAddr,
+    /// The initial link speed. Update it with `set_link()` once the PHY's link is up, eg after
+    /// auto-negotiation. Defaults to 100Mbps.
+    pub speed: LinkSpeed,
+    /// The initial duplex mode. Defaults to full.
+    pub duplex: Duplex,
+    /// Insert IP, TCP, UDP, and ICMP checksums of transmitted packets in hardware, vice in the
+    /// network stack. Defaults to true.
+    pub checksum_offload: bool,
+}
+
+impl Default for EthConfig {
+    fn default() -> Self {
+        Self {
+            interface: PhyInterface::Rmii,
+            mac_addr: [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+            speed: LinkSpeed::S100M,
+            duplex: Duplex::Full,
+            checksum_offload: true,
+        }
+    }
+}
+
+/// Represents the ethernet peripheral, with transmit and receive descriptor rings of `TD` and
+/// `RD` descriptors respectively.
+pub struct Eth<const TD: usize, const RD: usize> {
+    pub regs_dma: ETHERNET_DMA,
+    pub regs_mac: ETHERNET_MAC,
+    pub regs_mtl: ETHERNET_MTL,
     pub cfg: EthConfig,
+    ring: &'static mut DescriptorRing<TD, RD>,
+    /// MACMDIOAR register, CR field: The SMI clock divider, from the HCLK speed.
+    smi_clk_range: u8,
 }
 
-impl<RDma, RMac, RMtl> Eth<RDma, RMac, RMtl>
-where
-    RDma: Deref<Target = pac::ethernet_dma::RegisterBlock> + RccPeriph,
-    RMac: Deref<Target = pac::ethernet_mac::RegisterBlock> + RccPeriph,
-    RMtl: Deref<Target = pac::ethernet_mtl::RegisterBlock> + RccPeriph,
-{
-    /// Initialize an ethernet peripheral, including configuration register writes, and enabling and resetting
-    /// its RCC peripheral clock.
-    pub fn new(regs: R, cfg: EthConfig) -> Self {
+impl<const TD: usize, const RD: usize> Eth<TD, RD> {
+    /// Initialize the ethernet peripheral, including configuration register writes, and enabling
+    /// and resetting its RCC peripheral clock. Sets up the DMA, MTL, and MAC, then starts
+    /// transmitting and receiving. The PHY must be providing its clocks, eg the RMII reference
+    /// clock, or the DMA reset won't complete.
+    pub fn new(
+        regs_dma: ETHERNET_DMA,
+        regs_mac: ETHERNET_MAC,
+        regs_mtl: ETHERNET_MTL,
+        ring: &'static mut DescriptorRing<TD, RD>,
+        cfg: EthConfig,
+        clock_cfg: &Clocks,
+    ) -> Self {
+        assert!(
+            TD >= 4 && RD >= 4,
+            "Descriptor rings must have at least 4 descriptors."
+        );
+
         free(|_| {
             let rcc = unsafe { &(*RCC::ptr()) };
-            R::en_reset(rcc);
+            let syscfg = unsafe { &(*SYSCFG::ptr()) };
+
+            // The PHY interface must be selected before the peripheral is taken out of reset.
+            rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
+            syscfg.pmcr.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0b111 << 21)) | (cfg.interface as u32) << 21)
+            });
+
+            // We use raw bits, since the Ethernet RCC fields are inconsistently named among H7
+            // PACs. AHB1ENR: ETH1MACEN, ETH1TXEN, and ETH1RXEN. AHB1RSTR: ETH1MACRST.
+            rcc.ahb1enr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 0b111 << 15) });
+            rcc.ahb1rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 15) });
+            rcc.ahb1rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 15)) });
         });
 
-        Self { regs, cfg }
+        // MACMDIOAR register, CR field: Keep the MDC clock at or below 2.5Mhz.
+        let smi_clk_range = match clock_cfg.hclk() {
+            0..=34_999_999 => 0b0010,            // HCLK / 16
+            35_000_000..=59_999_999 => 0b0011,   // HCLK / 26
+            60_000_000..=99_999_999 => 0b0000,   // HCLK / 42
+            100_000_000..=149_999_999 => 0b0001, // HCLK / 62
+            150_000_000..=249_999_999 => 0b0100, // HCLK / 102
+            _ => 0b0101,                         // HCLK / 124
+        };
+
+        let mut result = Self {
+            regs_dma,
+            regs_mac,
+            regs_mtl,
+            cfg,
+            ring,
+            smi_clk_range,
+        };
+
+        result.init_dma();
+        result.init_mtl();
+        result.init_mac();
+
+        // H743 RM, section 58.9.3, steps 6 and 7: Start the DMA, and enable the MAC's transmitter,
+        // and receiver. (DMACTXCR register, ST field, DMACRXCR register, SR field, and MACCR
+        // register, TE and RE fields)
+        result
+            .regs_dma
+            .dmactx_cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+        result
+            .regs_dma
+            .dmacrx_cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+        result
+            .regs_mac
+            .maccr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 0b11) });
+
+        result
     }
 
     /// H743 RM, section 58.9.1: DMA initialization
-    pub fn init_dma(&mut self) {
-        // Complete the following steps to initialize the DMA:
+    fn init_dma(&mut self) {
+        // We use raw bits, since PAC support for these registers is incomplete.
 
         // 1. Provide a software reset to reset all MAC internal registers and logic (bit 0 of DMA
         // mode register (ETH_DMAMR)).
-        self.regs_dma.dmamr.write(|w| w.swr().set_bit());
+        self.regs_dma
+            .dmamr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1) });
 
         // 2. Wait for the completion of the reset process (poll bit 0 of the DMA mode register
         // (ETH_DMAMR), which is cleared when the reset operation is completed).
-        while self.regs_dma.dmamr.read().swr().bit_is_set() {}
+        while self.regs_dma.dmamr.read().bits() & 1 != 0 {}
 
         // 3. Program the following fields to initialize the System bus mode register
-        // (ETH_DMASBMR):
-        self.regs_dma.dmasbmr.modify(|_, w| {
-            // todo
-            // a)AAL
-            w.aal.bits(0);
-            // b) Fixed burst or undefined burst
-            w.aal.bits(0);
-            // c) Burst mode values in case of AHB bus interface.
-            w.aal.bits(0);
-            // d) If fixed length value is enabled, select the maximum burst length possible on the
-            // AXI Bus (bits [7:1])
-            w.aal.bits(0)
-        });
+        // (ETH_DMASBMR): Address-aligned beats (AAL, bit 12), and fixed burst (FB, bit 0).
+        self.regs_dma
+            .dmasbmr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 12 | 1) });
 
         // 4. Create a transmit and a receive descriptor list. In addition, ensure that the receive
-        // descriptors are owned by the DMA (set bit 31 of TDES3/RDES3 descriptor). For more
-        // information on descriptors, refer to Section 58.10: Descriptors.
-        // Note:
-        // Descriptor address from start to end of the ring should not cross the 4GB boundary.
-        //
-        // 5.
-        // Program ETH_DMACTXRLR and ETH_DMACRXRLR registers (see Channel Tx
-        // descriptor ring length register (ETH_DMACTXRLR) and Channel Rx descriptor ring
-        // length register (ETH_DMACRXRLR)). The programmed ring length must be at least 4.
-        //
-        // 6. Initialize receive and transmit descriptor list address with the base address of transmit
-        // and receive descriptor (Channel Tx descriptor list address register
-        // (ETH_DMACTXDLAR), Channel Rx descriptor list address register
-        // (ETH_DMACRXDLAR)). In addition, program the transmit and receive tail pointer
-        // registers that inform the DMA about the available descriptors (see Channel Tx
-        // descriptor tail pointer register (ETH_DMACTXDTPR) and Channel Rx descriptor tail
-        // pointer register (ETH_DMACRXDTPR)).
-        //
-        // 7. Program ETH_DMACCR, ETH_DMACTXCR and ETH_DMACRXCR registers (see
-        // Channel control register (ETH_DMACCR) and Channel transmit control register
-        // (ETH_DMACTXCR)) to configure the parameters such as the maximum burst-length
-        // (PBL) initiated by the DMA, descriptor skip lengths, OSP for TxDMA, RBSZ for
-        // RxDMA.
-        //
-        // 8. Enable the interrupts by programming the ETH_DMACIER register (see Channel
-        // interrupt enable register (ETH_DMACIER)).
-        //
-        // 9. Start the Receive and Transmit DMAs by setting SR (bit 0) of Channel receive control
-        // register (ETH_DMACRXCR) and ST (bit 0) of the ETH_DMACTXCR (see Channel
-        // transmit control register (ETH_DMACTXCR)).
+        // descriptors are owned by the DMA (set bit 31 of TDES3/RDES3 descriptor).
+        self.ring.tx.init(self.cfg.checksum_offload);
+        self.ring.rx.init();
+
+        // 5. Program ETH_DMACTXRLR and ETH_DMACRXRLR registers. The programmed ring length must
+        // be at least 4. (These are programmed as the length - 1)
+        self.regs_dma
+            .dmactx_rlr
+            .write(|w| unsafe { w.bits(TD as u32 - 1) });
+        self.regs_dma
+            .dmacrx_rlr
+            .write(|w| unsafe { w.bits(RD as u32 - 1) });
+
+        // 6. Initialize receive and transmit descriptor list address with the base address of
+        // transmit and receive descriptor. In addition, program the transmit and receive tail
+        // pointer registers that inform the DMA about the available descriptors.
+        self.regs_dma
+            .dmactx_dlar
+            .write(|w| unsafe { w.bits(self.ring.tx.descs.as_ptr() as u32) });
+        self.regs_dma
+            .dmacrx_dlar
+            .write(|w| unsafe { w.bits(self.ring.rx.descs.as_ptr() as u32) });
+
+        self.regs_dma
+            .dmactx_dtpr
+            .write(|w| unsafe { w.bits(self.ring.tx.descs.as_ptr() as u32) });
+        self.regs_dma
+            .dmacrx_dtpr
+            .write(|w| unsafe { w.bits(&self.ring.rx.descs[RD - 1] as *const _ as u32) });
+
+        // 7. Program ETH_DMACCR, ETH_DMACTXCR and ETH_DMACRXCR registers to configure the
+        // parameters such as the maximum burst-length (PBL) initiated by the DMA, descriptor
+        // skip lengths, OSP for TxDMA, RBSZ for RxDMA.
+        // DMACCR: DSL (bits 20:18) = 0: Descriptors are contiguous.
+        self.regs_dma
+            .dmaccr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << 18)) });
+        // DMACTXCR: TXPBL (bits 21:16) = 32 beats, OSF (bit 4): Operate on second packet.
+        self.regs_dma
+            .dmactx_cr
+            .write(|w| unsafe { w.bits(32 << 16 | 1 << 4) });
+        // DMACRXCR: RXPBL (bits 21:16) = 32 beats, RBSZ (bits 14:1): Receive buffer size.
+        self.regs_dma
+            .dmacrx_cr
+            .write(|w| unsafe { w.bits(32 << 16 | (ETH_BUF_SIZE as u32) << 1) });
+
+        // 8. Enable the interrupts by programming the ETH_DMACIER register. We leave this to
+        // `enable_interrupt()`.
+
+        // 9. Start the Receive and Transmit DMAs. We do this in `new()`, once the MAC is
+        // configured.
     }
 
     /// H743 RM, section 58.9.2: MTL initialization
-    pub fn init_mtl(&mut self) {
+    fn init_mtl(&mut self) {
         // Complete the following steps to initialize the MTL registers:
 
-        // 1. Program the following fields to initialize the operating mode in the ETH_MTLTXQOMR
-        // (see Tx queue operating mode Register (ETH_MTLTXQOMR)).
-        self.regs_mtl.mtltx_qomr.modify(|_, w| {
-            // a) Transmit Store And Forward (TSF) or Transmit Threshold Control (TTC) if the
-            // Threshold mode is used.
-            w.tsf().bits(0);
-            // b) Transmit Queue Enable (TXQEN) to value 2‘b10 to enable Transmit Queue 0.
-            w.txqen().bits(0);
-            // c) Transmit Queue Size (TQS).
-            w.tqs().bits(0)
+        // 1. Program the following fields to initialize the operating mode in the ETH_MTLTXQOMR:
+        // a) Transmit Store And Forward (TSF, bit 1), required for checksum insertion.
+        // b) Transmit Queue Enable (TXQEN, bits 3:2) to value 2‘b10 to enable Transmit Queue 0.
+        // c) Transmit Queue Size (TQS, bits 24:16): 2048 bytes, in 256-byte blocks, - 1.
+        self.regs_mtl
+            .mtltx_qomr
+            .write(|w| unsafe { w.bits(1 << 1 | 0b10 << 2 | 7 << 16) });
+
+        // 2. Program the following fields to initialize the operating mode in the ETH_MTLRXQOMR:
+        // a) Receive Store and Forward (RSF, bit 5).
+        // b) Flow Control Activation and De-activation thresholds aren't used, since we don't
+        // enable flow control.
+        // c) Error Packet and undersized good Packet forwarding (FEP and FUP) are left disabled,
+        // so these packets are dropped.
+        // d) Receive Queue Size (RQS) is read-only on H7.
+        self.regs_mtl
+            .mtlrx_qomr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 5) });
+    }
+
+    /// H743 RM, section 58.9.3: MAC initialization
+    fn init_mac(&mut self) {
+        // 1. Provide the MAC address.
+        self.set_mac_address(self.cfg.mac_addr);
+
+        // 2. Program the packet filter, flow control, and interrupts: We leave these at their
+        // reset values; ie perfect address filtering, with broadcasts passed, no flow control,
+        // and no MAC interrupts. Use `set_mac_filter()` to change the filter.
+
+        // 3. Program the MACCR register: DM (bit 13): Duplex mode, FES (bit 14): Speed,
+        // ACS (bit 20), and CST (bit 21): Strip the CRC from received packets.
+        self.regs_mac.maccr.write(|w| unsafe {
+            w.bits(
+                (self.cfg.duplex as u32) << 13 | (self.cfg.speed as u32) << 14 | 1 << 20 | 1 << 21,
+            )
         });
+    }
 
-        // 2. Program the following fields to initialize the operating mode in the ETH_MTLRXQOMR
-        // register (see Rx queue operating mode register (ETH_MTLRXQOMR)):
-        self.regs_mtl.mtltx_qomr.modify(|_, w| {
-            // a) Receive Store and Forward (RSF) or RTC if Threshold mode is used.
-            w.rsf().bits(0);
-            // b) Flow Control Activation and De-activation thresholds for MTL Receive FIFO (RFA
-            // and RFD).
-            w.rfa().bits(0);
-            w.rfd().bits(0);
-            // c) Error Packet and undersized good Packet forwarding enable (FEP and FUP).
-            w.fep().bits(0);
-            w.fup().bits(0);
-            // d)Receive Queue Size (RQS).
-            w.rqs().bits(0)
+    /// Set the MAC's speed and duplex mode to match the PHY's link, eg after auto-negotiation.
+    /// (MACCR register, FES and DM fields)
+    pub fn set_link(&mut self, speed: LinkSpeed, duplex: Duplex) {
+        self.cfg.speed = speed;
+        self.cfg.duplex = duplex;
+
+        self.regs_mac.maccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << 13) | (duplex as u32) << 13 | (speed as u32) << 14)
         });
     }
 
-    /// H743 RM, section 58.9.3: MAC initialization
-    pub fn init_mac(&mut self) {}
+    /// Read a PHY register over the SMI (MDIO) interface. (MACMDIOAR and MACMDIODR registers)
+    pub fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        // GOC (bits 3:2) = 0b11: Read.
+        self.smi_op(phy_addr, reg, 0b11);
+        self.regs_mac.macmdiodr.read().bits() as u16
+    }
+
+    /// Write a PHY register over the SMI (MDIO) interface. (MACMDIOAR and MACMDIODR registers)
+    pub fn smi_write(&mut self, phy_addr: u8, reg: u8, value: u16) {
+        while self.smi_busy() {}
+        self.regs_mac
+            .macmdiodr
+            .write(|w| unsafe { w.bits(value as u32) });
+        // GOC (bits 3:2) = 0b01: Write.
+        self.smi_op(phy_addr, reg, 0b01);
+    }
+
+    /// Start an SMI operation, and block until it's complete.
+    fn smi_op(&mut self, phy_addr: u8, reg: u8, goc: u32) {
+        while self.smi_busy() {}
+
+        // PA (bits 25:21): PHY address. RDA (bits 20:16): Register address. CR (bits 11:8): Clock
+        // range. MB (bit 0): Start the operation.
+        let bits = (phy_addr as u32 & 0x1f) << 21
+            | (reg as u32 & 0x1f) << 16
+            | (self.smi_clk_range as u32) << 8
+            | goc << 2
+            | 1;
+
+        self.regs_mac.macmdioar.write(|w| unsafe { w.bits(bits) });
+        while self.smi_busy() {}
+    }
+
+    /// MACMDIOAR register, MB field.
+    fn smi_busy(&self) -> bool {
+        self.regs_mac.macmdioar.read().bits() & 1 != 0
+    }
+
+    /// Reset the PHY, and block until the reset is complete. (BCR register, reset field)
+    pub fn phy_reset(&mut self, phy_addr: u8) {
+        self.smi_write(phy_addr, PHY_BCR, PHY_BCR_RESET);
+        while self.smi_read(phy_addr, PHY_BCR) & PHY_BCR_RESET != 0 {}
+    }
+
+    /// Advertise 10 and 100Mbps, full and half duplex, and start auto-negotiation. Check for
+    /// completion with `phy_negotiated_link()`.
+    pub fn phy_autonegotiate(&mut self, phy_addr: u8) {
+        self.smi_write(
+            phy_addr,
+            PHY_ANAR,
+            PHY_AN_100FD | PHY_AN_100HD | PHY_AN_10FD | PHY_AN_10HD | PHY_AN_SELECTOR,
+        );
+        self.smi_write(phy_addr, PHY_BCR, PHY_BCR_ANEN | PHY_BCR_RESTART_AN);
+    }
+
+    /// Returns true if the PHY's link is up. (BSR register, link status field)
+    pub fn phy_link_up(&mut self, phy_addr: u8) -> bool {
+        // The link status bit latches low, so read it twice to get the current status.
+        self.smi_read(phy_addr, PHY_BSR);
+        self.smi_read(phy_addr, PHY_BSR) & PHY_BSR_LINK_STATUS != 0
+    }
+
+    /// Returns the link speed and duplex mode resulting from auto-negotiation, or `None` if it
+    /// hasn't completed. This is the best mode advertised by both the PHY and its link partner.
+    /// Pass the result to `set_link()`. Note that some PHYs report this directly in a
+    /// vendor-specific register, which you can read with `smi_read()`.
+    pub fn phy_negotiated_link(&mut self, phy_addr: u8) -> Option<(LinkSpeed, Duplex)> {
+        if self.smi_read(phy_addr, PHY_BSR) & PHY_BSR_AN_COMPLETE == 0 {
+            return None;
+        }
+
+        let common = self.smi_read(phy_addr, PHY_ANAR) & self.smi_read(phy_addr, PHY_ANLPAR);
+
+        if common & PHY_AN_100FD != 0 {
+            Some((LinkSpeed::S100M, Duplex::Full))
+        } else if common & PHY_AN_100HD != 0 {
+            Some((LinkSpeed::S100M, Duplex::Half))
+        } else if common & PHY_AN_10FD != 0 {
+            Some((LinkSpeed::S10M, Duplex::Full))
+        } else {
+            Some((LinkSpeed::S10M, Duplex::Half))
+        }
+    }
+
+    /// Enable an interrupt. This also enables the normal interrupt summary. (DMACIER register,
+    /// NIE field)
+    pub fn enable_interrupt(&mut self, interrupt: EthInterrupt) {
+        self.regs_dma
+            .dmacier
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 15 | 1 << interrupt as u8) });
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: EthInterrupt) {
+        self.regs_dma
+            .dmacier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << interrupt as u8)) });
+    }
+
+    /// Clear an interrupt flag, and the normal interrupt summary. (DMACSR register, NIS field)
+    pub fn clear_interrupt(&mut self, interrupt: EthInterrupt) {
+        self.regs_dma
+            .dmacsr
+            .write(|w| unsafe { w.bits(1 << 15 | 1 << interrupt as u8) });
+    }
+
+    /// Set the device's MAC address, used for perfect destination address filtering. (`MACA0HR` and
+    /// `MACA0LR` registers)
+    pub fn set_mac_address(&mut self, addr: MacAddr) {
+        // The high register must be written first; the address is latched when the low register
+        // is written.
+        self.regs_mac
+            .maca0hr
+            .write(|w| unsafe { w.bits((addr[5] as u32) << 8 | addr[4] as u32) });
+        self.regs_mac
+            .maca0lr
+            .write(|w| unsafe { w.bits(u32::from_le_bytes([addr[0], addr[1], addr[2], addr[3]])) });
+    }
+
+    /// Set or clear an additional perfect filter address: slot 1 - 3. Received packets with this
+    /// destination address pass the filter. (`MACAxHR` and `MACAxLR` registers)
+    pub fn set_perfect_filter(&mut self, slot: u8, addr: Option<MacAddr>) {
+        // AE (bit 31): Address enable.
+        let (high, low) = match addr {
+            Some(a) => (
+                1 << 31 | (a[5] as u32) << 8 | a[4] as u32,
+                u32::from_le_bytes([a[0], a[1], a[2], a[3]]),
+            ),
+            None => (0, 0xffff_ffff),
+        };
+
+        match slot {
+            1 => {
+                self.regs_mac.maca1hr.write(|w| unsafe { w.bits(high) });
+                self.regs_mac.maca1lr.write(|w| unsafe { w.bits(low) });
+            }
+            2 => {
+                self.regs_mac.maca2hr.write(|w| unsafe { w.bits(high) });
+                self.regs_mac.maca2lr.write(|w| unsafe { w.bits(low) });
+            }
+            3 => {
+                self.regs_mac.maca3hr.write(|w| unsafe { w.bits(high) });
+                self.regs_mac.maca3lr.write(|w| unsafe { w.bits(low) });
+            }
+            _ => panic!("Perfect filter slot must be 1 - 3."),
+        }
+    }
+
+    /// Configure the receive packet filter. (`MACPFR` register) Note that this preserves the VLAN
+    /// filter enable bit, set by `set_vlan_filter`.
+    pub fn set_mac_filter(&mut self, cfg: &MacFilterConfig) {
+        // PR (bit 0), HUC (bit 1), HMC (bit 2), PM (bit 4), DBF (bit 5). HPF (bit 10) passes packets
+        // matching either the hash or perfect filters, so the device's own address still passes
+        // with hash filtering enabled.
+        let bits = cfg.promiscuous as u32
+            | (cfg.hash_unicast as u32) << 1
+            | (cfg.hash_multicast as u32) << 2
+            | (cfg.pass_all_multicast as u32) << 4
+            | (cfg.block_broadcast as u32) << 5
+            | ((cfg.hash_unicast || cfg.hash_multicast) as u32) << 10;
+
+        self.regs_mac
+            .macpfr
+            .modify(|r, w| unsafe { w.bits((r.bits() & (1 << 16)) | bits) });
+    }
+
+    /// Set the multicast hash table from a list of addresses to receive, eg `01:00:5e:00:00:fb`
+    /// for mDNS. This is an imperfect filter: other addresses may hash to the same entries, so
+    /// the network stack must still check addresses. Requires `hash_multicast` in the filter
+    /// config. (`MACHT0R` and `MACHT1R` registers)
+    pub fn set_multicast_hash(&mut self, addrs: &[MacAddr]) {
+        let mut table = 0_u64;
+        for addr in addrs {
+            table |= 1 << mac_hash_index(addr);
+        }
+
+        self.regs_mac
+            .macht0r
+            .write(|w| unsafe { w.bits(table as u32) });
+        self.regs_mac
+            .macht1r
+            .write(|w| unsafe { w.bits((table >> 32) as u32) });
+    }
+
+    /// Enable VLAN tag filtering, or disable it with `None`. When enabled, only packets with a
+    /// matching VLAN tag are received. (`MACVTR` register, and `MACPFR` register, `VTFE` field)
+    pub fn set_vlan_filter(&mut self, cfg: Option<VlanConfig>) {
+        match cfg {
+            Some(c) => {
+                // VL (bits 15:0), ETV (bit 16): 12-bit VID comparison, EVLS (bits 22:21) = 0b11:
+                // Always strip the tag.
+                let bits = c.tag as u32
+                    | (c.compare_vid_only as u32) << 16
+                    | if c.strip { 0b11 << 21 } else { 0 };
+
+                self.regs_mac.macvtr.write(|w| unsafe { w.bits(bits) });
+                self.regs_mac
+                    .macpfr
+                    .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 16) });
+            }
+            None => {
+                self.regs_mac
+                    .macpfr
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 16)) });
+                self.regs_mac.macvtr.write(|w| unsafe { w.bits(0) });
+            }
+        }
+    }
+
+    /// The addend register value that makes the PTP system time run at its nominal rate, from
+    /// the PTP reference clock (HCLK). Fine correction mode adds this to a 32-bit accumulator
+    /// each reference clock cycle; the sub-second counter increments on each overflow.
+    fn ptp_addend(clock_cfg: &Clocks) -> u32 {
+        ((PTP_CLOCK_FREQ << 32) / clock_cfg.hclk() as u64) as u32
+    }
+
+    /// Enable the PTP (IEEE 1588) timestamp unit, using fine correction, and initialize the
+    /// system time to 0. Timestamps all received packets; transmitted packets are timestamped if
+    /// their descriptor has `TDES2_TTSE` set. HCLK must be above 50Mhz.
+    /// See H743 RM, section 58.5.9: IEEE 1588 timestamp support, and section 58.9.4:
+    /// "Programming guidelines for IEEE 1588 timestamping".
+    pub fn enable_ptp(&mut self, clock_cfg: &Clocks) {
+        // We use raw bits, since PAC support for these registers is incomplete.
+        // 1. Mask the timestamp trigger interrupt (MACIER register, TSIE field).
+        self.regs_mac
+            .macier
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 12)) });
+
+        // 2. Enable timestamping (MACTSCR register, TSENA field).
+        // Timestamp all packets (TSENALL), using digital rollover of the sub-second
+        // register at 999,999,999ns (TSCTRLSSR), and PTP version 2 format (TSVER2ENA).
+        self.regs_mac
+            .mactscr
+            .write(|w| unsafe { w.bits(1 | 1 << 8 | 1 << 9 | 1 << 10) });
+
+        // 3. Program the sub-second increment register (MACSSIR), based on the PTP clock frequency.
+        self.regs_mac
+            .macssir
+            .write(|w| unsafe { w.bits(PTP_SSINC_NS << 16) });
+
+        // 4. Using fine correction, program MACTSAR, and set TSADDREG (MACTSCR register) to
+        // update the addend.
+        self.regs_mac
+            .mactsar
+            .write(|w| unsafe { w.bits(Self::ptp_addend(clock_cfg)) });
+        self.regs_mac
+            .mactscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 5) });
+
+        // 5. Poll MACTSCR until TSADDREG is cleared.
+        while self.regs_mac.mactscr.read().bits() & (1 << 5) != 0 {}
+
+        // 6. Program TSCFUPDT in MACTSCR, to select the fine update method.
+        self.regs_mac
+            .mactscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 1) });
+
+        // 7/8. Program the system time, and set TSINIT to initialize it.
+        self.set_ptp_time(Timestamp::default());
+    }
+
+    /// Set the PTP system time. Blocks until the update is complete.
+    pub fn set_ptp_time(&mut self, time: Timestamp) {
+        self.regs_mac
+            .macstsur
+            .write(|w| unsafe { w.bits(time.seconds) });
+        self.regs_mac
+            .macstnur
+            .write(|w| unsafe { w.bits(time.nanos) });
+
+        // MACTSCR register, TSINIT field.
+        self.regs_mac
+            .mactscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 2) });
+        while self.regs_mac.mactscr.read().bits() & (1 << 2) != 0 {}
+    }
+
+    /// Read the current PTP system time.
+    pub fn get_ptp_time(&self) -> Timestamp {
+        // Re-read the seconds register, in case the nanoseconds register rolled over between reads.
+        loop {
+            let seconds = self.regs_mac.macstsr.read().bits();
+            let nanos = self.regs_mac.macstnr.read().bits() & 0x7fff_ffff;
+
+            if self.regs_mac.macstsr.read().bits() == seconds {
+                return Timestamp { seconds, nanos };
+            }
+        }
+    }
+
+    /// Step the PTP system time by a signed offset, in nanoseconds. Use this for coarse corrections,
+    /// eg on initial synchronization; use `adjust_ptp_freq` for ongoing corrections. Blocks until
+    /// the update is complete.
+    pub fn adjust_ptp_time(&mut self, offset_ns: i64) {
+        let magnitude = offset_ns.unsigned_abs();
+        let seconds = (magnitude / NANOS_PER_SEC as u64) as u32;
+        let nanos = (magnitude % NANOS_PER_SEC as u64) as u32;
+
+        // RM: When ADDSUB is set with digital rollover, the nanoseconds value must be programmed
+        // as 10^9 - the value to subtract, and the seconds value as 2^32 - the value to subtract.
+        let (seconds, nanos) = if offset_ns < 0 {
+            (
+                seconds.wrapping_neg(),
+                (1 << 31) | (NANOS_PER_SEC as u32 - nanos),
+            )
+        } else {
+            (seconds, nanos)
+        };
+
+        self.regs_mac.macstsur.write(|w| unsafe { w.bits(seconds) });
+        self.regs_mac.macstnur.write(|w| unsafe { w.bits(nanos) });
+
+        // MACTSCR register, TSUPDT field.
+        self.regs_mac
+            .mactscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 3) });
+        while self.regs_mac.mactscr.read().bits() & (1 << 3) != 0 {}
+    }
+
+    /// Adjust the PTP system time's rate, in parts per billion relative to the reference clock.
+    /// Positive values speed the clock up. Use this to discipline the clock from a servo, eg a
+    /// PI controller on the measured offset from the master clock.
+    pub fn adjust_ptp_freq(&mut self, ppb: i32, clock_cfg: &Clocks) {
+        let base = Self::ptp_addend(clock_cfg) as i64;
+        let addend = base + base * ppb as i64 / NANOS_PER_SEC;
+
+        self.regs_mac
+            .mactsar
+            .write(|w| unsafe { w.bits(addend as u32) });
+        self.regs_mac
+            .mactscr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 5) });
+        while self.regs_mac.mactscr.read().bits() & (1 << 5) != 0 {}
+    }
+
+    /// Set a target time, and enable its interrupt. When the system time reaches the target, the
+    /// timestamp interrupt fires (ETH global interrupt); clear it with `clear_ptp_target`.
+    /// See H743 RM, section 58.11.4: MACPPSTTSR and MACPPSTTNR.
+    pub fn set_ptp_target(&mut self, time: Timestamp) {
+        // Wait for any previous target time to be latched. (MACPPSTTNR register, TRGTBUSY0 field)
+        while self.regs_mac.macppsttnr.read().bits() & (1 << 31) != 0 {}
+
+        // MACPPSCR register, TRGTMODSEL0 field = 0b00: Target time only generates an interrupt.
+        self.regs_mac
+            .macppscr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << 5)) });
+
+        self.regs_mac
+            .macppsttsr
+            .write(|w| unsafe { w.bits(time.seconds) });
+        self.regs_mac
+            .macppsttnr
+            .write(|w| unsafe { w.bits(time.nanos & 0x7fff_ffff) });
+
+        // MACIER register, TSIE field.
+        self.regs_mac
+            .macier
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << 12) });
+    }
+
+    /// Returns true if the target time was reached. (MACTSSR register, TSTARGT0 field)
+    pub fn ptp_target_reached(&self) -> bool {
+        self.regs_mac.mactssr.read().bits() & (1 << 1) != 0
+    }
+
+    /// Clear the target time reached interrupt. Reading MACTSSR clears its status flags.
+    pub fn clear_ptp_target(&mut self) {
+        self.regs_mac.mactssr.read();
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+/// A DMA descriptor, in either read, or write-back format. See H743 RM, section 58.10.
+struct Descriptor {
+    des: [u32; 4],
+}
+
+impl Descriptor {
+    const fn new() -> Self {
+        Self { des: [0; 4] }
+    }
+
+    /// Read a descriptor word. These are volatile, since the DMA writes them.
+    fn read(&self, word: usize) -> u32 {
+        unsafe { read_volatile(&self.des[word]) }
+    }
+
+    fn write(&mut self, word: usize, value: u32) {
+        unsafe { write_volatile(&mut self.des[word], value) }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+struct Buffer([u8; ETH_BUF_SIZE]);
+
+/// A transmit descriptor ring, and its packet buffers.
+pub struct TxRing<const N: usize> {
+    descs: [Descriptor; N],
+    bufs: [Buffer; N],
+    /// The next descriptor to use.
+    next: usize,
+    /// TDES3 checksum insertion control bits.
+    cic: u32,
+}
+
+impl<const N: usize> TxRing<N> {
+    const fn new() -> Self {
+        Self {
+            descs: [Descriptor::new(); N],
+            bufs: [Buffer([0; ETH_BUF_SIZE]); N],
+            next: 0,
+            cic: 0,
+        }
+    }
+
+    /// Give all descriptors to the CPU.
+    fn init(&mut self, checksum_offload: bool) {
+        for desc in self.descs.iter_mut() {
+            for word in 0..4 {
+                desc.write(word, 0);
+            }
+        }
+
+        self.next = 0;
+        self.cic = if checksum_offload { TDES3_CIC_FULL } else { 0 };
+    }
+
+    /// Returns true if the next descriptor is free to transmit a packet.
+    fn available(&self) -> bool {
+        self.descs[self.next].read(3) & DES3_OWN == 0
+    }
+
+    /// Write a packet of `len` bytes into the next buffer using `f`, and pass it to the DMA to
+    /// transmit.
+    fn send<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        let i = self.next;
+        let result = f(&mut self.bufs[i].0[..len]);
+        let buf_addr = self.bufs[i].0.as_ptr() as u32;
+
+        let desc = &mut self.descs[i];
+        desc.write(0, buf_addr);
+        desc.write(1, 0);
+        // B1L (bits 13:0): Buffer 1 length.
+        desc.write(2, len as u32 & 0x3fff);
+
+        // Make sure the buffer, and the other descriptor words are written before the DMA owns
+        // the descriptor.
+        fence(Ordering::Release);
+        // FL (bits 14:0): Packet length. The whole packet is in this descriptor's buffer.
+        desc.write(
+            3,
+            DES3_OWN | TDES3_FD | TDES3_LD | self.cic | len as u32 & 0x7fff,
+        );
+
+        self.next = (i + 1) % N;
+
+        // Writing the tail pointer resumes the DMA, if it's suspended from running out of
+        // descriptors. It must see the descriptor write first.
+        cortex_m::asm::dsb();
+        let regs = unsafe { &(*ETHERNET_DMA::ptr()) };
+        regs.dmactx_dtpr
+            .write(|w| unsafe { w.bits(&self.descs[self.next] as *const _ as u32) });
+
+        result
+    }
+}
+
+/// A receive descriptor ring, and its packet buffers.
+pub struct RxRing<const N: usize> {
+    descs: [Descriptor; N],
+    bufs: [Buffer; N],
+    /// The next descriptor to read.
+    next: usize,
+}
+
+impl<const N: usize> RxRing<N> {
+    const fn new() -> Self {
+        Self {
+            descs: [Descriptor::new(); N],
+            bufs: [Buffer([0; ETH_BUF_SIZE]); N],
+            next: 0,
+        }
+    }
+
+    /// Give all descriptors to the DMA.
+    fn init(&mut self) {
+        for i in 0..N {
+            self.arm(i);
+        }
+
+        self.next = 0;
+    }
+
+    /// Pass a descriptor, and its buffer to the DMA, to receive a packet.
+    fn arm(&mut self, i: usize) {
+        let buf_addr = self.bufs[i].0.as_ptr() as u32;
+
+        let desc = &mut self.descs[i];
+        desc.write(0, buf_addr);
+        desc.write(1, 0);
+        desc.write(2, 0);
+
+        fence(Ordering::Release);
+        desc.write(3, DES3_OWN | RDES3_IOC | RDES3_BUF1V);
+    }
+
+    /// Returns the length of the next received packet, if one is available. Discards packets
+    /// with errors, packets that don't fit in one buffer, and context descriptors.
+    fn next_packet(&mut self) -> Option<usize> {
+        loop {
+            let des3 = self.descs[self.next].read(3);
+
+            if des3 & DES3_OWN != 0 {
+                return None;
+            }
+            // Make sure we read the buffer after the DMA's done with it.
+            fence(Ordering::Acquire);
+
+            let whole_packet = des3 & RDES3_FD != 0 && des3 & RDES3_LD != 0;
+            if whole_packet && des3 & (RDES3_CTXT | RDES3_ES) == 0 {
+                // PL (bits 14:0): Packet length. The CRC is stripped.
+                return Some((des3 & 0x7fff) as usize);
+            }
+
+            self.release();
+        }
+    }
+
+    /// Pass the current descriptor back to the DMA, and move to the next one.
+    fn release(&mut self) {
+        let i = self.next;
+        self.arm(i);
+
+        // Writing the tail pointer resumes the DMA, if it's suspended from running out of
+        // descriptors.
+        cortex_m::asm::dsb();
+        let regs = unsafe { &(*ETHERNET_DMA::ptr()) };
+        regs.dmacrx_dtpr
+            .write(|w| unsafe { w.bits(&self.descs[i] as *const _ as u32) });
+
+        self.next = (i + 1) % N;
+    }
+}
+
+/// Transmit and receive descriptor rings, and their packet buffers, of `TD` and `RD` descriptors
+/// respectively. Each descriptor uses an `ETH_BUF_SIZE` buffer. Place this in a `static`, in
+/// memory the Ethernet DMA can access. Each ring must have at least 4 descriptors.
+pub struct DescriptorRing<const TD: usize, const RD: usize> {
+    tx: TxRing<TD>,
+    rx: RxRing<RD>,
+}
+
+impl<const TD: usize, const RD: usize> DescriptorRing<TD, RD> {
+    /// Create empty rings. This is `const`, for initializing a `static`.
+    pub const fn new() -> Self {
+        Self {
+            tx: TxRing::new(),
+            rx: RxRing::new(),
+        }
+    }
+}
+
+impl<const TD: usize, const RD: usize> Default for DescriptorRing<TD, RD> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A received packet, for smoltcp.
+pub struct RxToken<'a, const N: usize> {
+    ring: &'a mut RxRing<N>,
+    len: usize,
+}
+
+impl<'a, const N: usize> phy::RxToken for RxToken<'a, N> {
+    fn consume<R, F>(self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let i = self.ring.next;
+        let result = f(&mut self.ring.bufs[i].0[..self.len]);
+        self.ring.release();
+
+        result
+    }
+}
+
+/// A free transmit descriptor, for smoltcp.
+pub struct TxToken<'a, const N: usize> {
+    ring: &'a mut TxRing<N>,
+}
+
+impl<'a, const N: usize> phy::TxToken for TxToken<'a, N> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        if len > ETH_BUF_SIZE {
+            return Err(smoltcp::Error::Truncated);
+        }
+
+        self.ring.send(len, f)
+    }
+}
+
+impl<'a, const TD: usize, const RD: usize> Device<'a> for Eth<TD, RD> {
+    type RxToken = RxToken<'a, RD>;
+    type TxToken = TxToken<'a, TD>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let DescriptorRing { tx, rx } = &mut *self.ring;
+
+        // smoltcp may respond to a received packet, so we need a transmit descriptor as well.
+        if !tx.available() {
+            return None;
+        }
+
+        let len = rx.next_packet()?;
+        Some((RxToken { ring: rx, len }, TxToken { ring: tx }))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let tx = &mut self.ring.tx;
+
+        if tx.available() {
+            Some(TxToken { ring: tx })
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = ETH_MTU;
+        caps.max_burst_size = Some(TD.min(RD));
+
+        if self.cfg.checksum_offload {
+            // The MAC inserts checksums when transmitting; smoltcp still verifies them on receive.
+            caps.checksum.ipv4 = Checksum::Rx;
+            caps.checksum.udp = Checksum::Rx;
+            caps.checksum.tcp = Checksum::Rx;
+            caps.checksum.icmpv4 = Checksum::Rx;
+        }
+
+        caps
+    }
 }
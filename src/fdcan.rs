@@ -0,0 +1,836 @@
+//! Support for the Controller Area Network with Flexible Data-rate (FDCAN) peripheral, used on
+//! G4, L5, and H7, for classic CAN, and CAN FD. This is different from the bxCAN used on older
+//! families; see the `can` module for that.
+//!
+//! The message RAM is laid out with 28 standard, and 8 extended ID filters, 2 RX FIFOs, and TX
+//! buffers used as a FIFO, or priority queue. Each RX, and TX element holds up to 64 data bytes.
+//! On G4 and L5, this layout is fixed by hardware, with 3 elements per RX FIFO, and 3 TX buffers.
+//! On H7, where the message RAM is configurable, and shared between FDCAN1 and FDCAN2, we use
+//! 16 elements for each.
+//!
+//! The FDCAN kernel clock is selected in RCC (`FDCANSEL`); it's HSE after reset. Pass its
+//! frequency to `FdCan::new()`, which calculates bit timing from it. On H7, select it with
+//! `Clocks::fdcan_src`, and get its frequency with `Clocks::fdcan_kernel()`.
+//!
+//! Example:
+//! ```rust
+//! let mut can = FdCan::new(
+//!     FdCanDevice::One,
+//!     &FdCanConfig {
+//!         nominal_bitrate: 500_000,
+//!         data_bitrate: Some(2_000_000),
+//!         ..Default::default()
+//!     },
+//!     16_000_000, // HSE
+//! )?;
+//!
+//! // Receive IDs 0x100 - 0x1ff in FIFO 0.
+//! can.set_std_filter(0, &StdFilter {
+//!     filter_type: FilterType::Range,
+//!     id1: 0x100,
+//!     id2: 0x1ff,
+//!     action: FilterAction::Fifo0,
+//! });
+//! can.enable_interrupt(FdCanInterrupt::RxFifo0NewMessage);
+//!
+//! can.transmit(&Frame::new_fd(Id::Standard(0x200), &[1, 2, 3], true).unwrap())?;
+//!
+//! // In the ISR (eg `FDCAN1_IT0`):
+//! can.clear_interrupt(FdCanInterrupt::RxFifo0NewMessage);
+//! while let Some(frame) = can.receive(RxFifo::Fifo0) {
+//!     defmt::println!("Received: {:?}", frame.data());
+//! }
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::pac::RCC;
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since the message RAM isn't described by the PACs, and FDCAN register
+// names vary between them.
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        const FDCAN1_BASE: usize = 0x4000_6400;
+        // FDCAN2, and FDCAN3 are only on G473, G474, G483, and G484.
+        #[allow(dead_code)]
+        const FDCAN2_BASE: usize = 0x4000_6800;
+        #[allow(dead_code)]
+        const FDCAN3_BASE: usize = 0x4000_6c00;
+        /// Message RAM (SRAMCAN) base address.
+        const RAM_BASE: usize = 0x4000_a400;
+        /// RCC_APB1ENR1, and RCC_APB1RSTR1: FDCANEN, and FDCANRST.
+        const RCC_BIT: u32 = 1 << 25;
+    } else if #[cfg(feature = "l5")] {
+        const FDCAN1_BASE: usize = 0x4000_a400;
+        const RAM_BASE: usize = 0x4000_ac00;
+        /// RCC_APB1ENR2, and RCC_APB1RSTR2: FDCAN1EN, and FDCAN1RST.
+        const RCC_BIT: u32 = 1 << 9;
+    } else {
+        const FDCAN1_BASE: usize = 0x4000_a000;
+        const FDCAN2_BASE: usize = 0x4000_a400;
+        const RAM_BASE: usize = 0x4000_ac00;
+        /// RCC_APB1HENR, and RCC_APB1HRSTR: FDCANEN, and FDCANRST.
+        const RCC_BIT: u32 = 1 << 8;
+    }
+}
+
+// Message RAM layout, in words from the start of an instance's section.
+const STD_FILTER_OFFSET: usize = 0;
+const EXT_FILTER_OFFSET: usize = 28;
+const RX_FIFO0_OFFSET: usize = 44;
+const RX_FIFO1_OFFSET: usize = RX_FIFO0_OFFSET + RX_FIFO_SIZE * ELEMENT_SIZE;
+/// The number of standard ID filter elements.
+pub const NUM_STD_FILTERS: u8 = 28;
+/// The number of extended ID filter elements.
+pub const NUM_EXT_FILTERS: u8 = 8;
+/// RX, and TX element size, in words: 2 header words, and 64 data bytes.
+const ELEMENT_SIZE: usize = 18;
+
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const RX_FIFO_SIZE: usize = 16;
+        const TX_BUF_SIZE: usize = 16;
+        const TX_BUF_OFFSET: usize = RX_FIFO1_OFFSET + RX_FIFO_SIZE * ELEMENT_SIZE;
+        const INSTANCE_RAM_SIZE: usize = TX_BUF_OFFSET + TX_BUF_SIZE * ELEMENT_SIZE;
+    } else {
+        const RX_FIFO_SIZE: usize = 3;
+        // The TX event FIFO, which we don't use, is between RX FIFO 1, and the TX buffers.
+        const TX_BUF_OFFSET: usize = 158;
+        const INSTANCE_RAM_SIZE: usize = 212;
+    }
+}
+
+// Register offsets.
+const ECR: usize = 0x040;
+const PSR: usize = 0x044;
+const TDCR: usize = 0x048;
+const DBTP: usize = 0x00c;
+const CCCR: usize = 0x018;
+const NBTP: usize = 0x01c;
+const IR: usize = 0x050;
+const IE: usize = 0x054;
+const ILE: usize = 0x05c;
+const TXBC: usize = 0x0c0;
+const TXFQS: usize = 0x0c4;
+
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const GFC: usize = 0x080;
+        const SIDFC: usize = 0x084;
+        const XIDFC: usize = 0x088;
+        const RXF0C: usize = 0x0a0;
+        const RXF0S: usize = 0x0a4;
+        const RXF0A: usize = 0x0a8;
+        const RXF1C: usize = 0x0b0;
+        const RXF1S: usize = 0x0b4;
+        const RXF1A: usize = 0x0b8;
+        const RXESC: usize = 0x0bc;
+        const TXESC: usize = 0x0c8;
+        const TXBAR: usize = 0x0d0;
+        /// TXBC, TFQM bit.
+        const TFQM: u32 = 1 << 30;
+    } else {
+        /// Called RXGFC on G4, and L5.
+        const GFC: usize = 0x080;
+        const RXF0S: usize = 0x090;
+        const RXF0A: usize = 0x094;
+        const RXF1S: usize = 0x098;
+        const RXF1A: usize = 0x09c;
+        const TXBAR: usize = 0x0cc;
+        /// TXBC, TFQM bit.
+        const TFQM: u32 = 1 << 24;
+    }
+}
+
+// CCCR bits.
+const INIT: u32 = 1 << 0;
+const CCE: u32 = 1 << 1;
+const DAR: u32 = 1 << 6;
+const FDOE: u32 = 1 << 8;
+const BRSE: u32 = 1 << 9;
+
+/// TXFQS: TX FIFO/queue full.
+const TFQF: u32 = 1 << 21;
+/// DBTP: Transceiver delay compensation enable.
+const TDC: u32 = 1 << 23;
+/// PSR: Bus off.
+const BO: u32 = 1 << 7;
+
+// RX, and TX element header bits.
+const XTD: u32 = 1 << 30;
+const RTR: u32 = 1 << 29;
+const FDF: u32 = 1 << 21;
+const BRS: u32 = 1 << 20;
+const DLC_SHIFT: u32 = 16;
+
+/// Valid CAN FD data lengths, indexed by DLC.
+const DLC_LENGTHS: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+#[derive(Clone, Copy, PartialEq)]
+/// Selects an FDCAN peripheral.
+pub enum FdCanDevice {
+    One,
+    #[cfg(any(
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484",
+        feature = "h7"
+    ))]
+    Two,
+    #[cfg(any(feature = "g473", feature = "g474", feature = "g483", feature = "g484"))]
+    Three,
+}
+
+impl FdCanDevice {
+    /// Register base address, and message RAM section address.
+    fn addrs(&self) -> (usize, usize) {
+        match self {
+            Self::One => (FDCAN1_BASE, RAM_BASE),
+            #[cfg(any(
+                feature = "g473",
+                feature = "g474",
+                feature = "g483",
+                feature = "g484",
+                feature = "h7"
+            ))]
+            Self::Two => (FDCAN2_BASE, RAM_BASE + INSTANCE_RAM_SIZE * 4),
+            #[cfg(any(feature = "g473", feature = "g474", feature = "g483", feature = "g484"))]
+            Self::Three => (FDCAN3_BASE, RAM_BASE + INSTANCE_RAM_SIZE * 8),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// How pending TX buffers are sent.
+pub enum TxMode {
+    /// In the order they're added.
+    Fifo,
+    /// Lowest ID (highest priority) first.
+    Queue,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// What to do with frames that don't match a filter. Sets GFC (RXGFC on G4, and L5), ANFS, and
+/// ANFE fields.
+pub enum NonMatching {
+    Fifo0 = 0b00,
+    Fifo1 = 0b01,
+    Reject = 0b10,
+}
+
+/// FDCAN configuration.
+pub struct FdCanConfig {
+    /// Nominal (arbitration phase) bit rate, in bits per second. Defaults to 500kbps.
+    pub nominal_bitrate: u32,
+    /// Data phase bit rate, for CAN FD frames sent with bit rate switching. If `None`, FD frames
+    /// can be sent, and received, but bit rate switching is disabled. Defaults to `None`.
+    pub data_bitrate: Option<u32>,
+    /// Allow CAN FD frames. If false, only classic CAN frames are sent. Defaults to true.
+    pub fd: bool,
+    /// Retransmit frames that lose arbitration, or have errors. Defaults to true.
+    pub auto_retransmit: bool,
+    /// Defaults to `TxMode::Fifo`.
+    pub tx_mode: TxMode,
+    /// Defaults to `NonMatching::Reject`.
+    pub non_matching: NonMatching,
+    /// Reject all remote frames. Defaults to false.
+    pub reject_remote: bool,
+}
+
+impl Default for FdCanConfig {
+    fn default() -> Self {
+        Self {
+            nominal_bitrate: 500_000,
+            data_bitrate: None,
+            fd: true,
+            auto_retransmit: true,
+            tx_mode: TxMode::Fifo,
+            non_matching: NonMatching::Reject,
+            reject_remote: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Filter type. Sets SFT, and EFT fields.
+pub enum FilterType {
+    /// Match IDs from `id1` to `id2`, inclusive.
+    Range = 0b00,
+    /// Match `id1`, or `id2`.
+    Dual = 0b01,
+    /// Match IDs where `id & id2 == id1 & id2`; `id2` is the mask.
+    Mask = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// What to do with matching frames. Sets SFEC, and EFEC fields.
+pub enum FilterAction {
+    /// The filter is disabled.
+    Disable = 0b000,
+    Fifo0 = 0b001,
+    Fifo1 = 0b010,
+    Reject = 0b011,
+}
+
+/// A standard (11-bit) ID filter.
+pub struct StdFilter {
+    pub filter_type: FilterType,
+    pub id1: u16,
+    pub id2: u16,
+    pub action: FilterAction,
+}
+
+/// An extended (29-bit) ID filter.
+pub struct ExtFilter {
+    pub filter_type: FilterType,
+    pub id1: u32,
+    pub id2: u32,
+    pub action: FilterAction,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// A CAN identifier.
+pub enum Id {
+    /// An 11-bit ID.
+    Standard(u16),
+    /// A 29-bit ID.
+    Extended(u32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// An RX FIFO.
+pub enum RxFifo {
+    Fifo0,
+    Fifo1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// FDCAN interrupts. The values are bit positions in the IR, and IE registers.
+pub enum FdCanInterrupt {
+    #[cfg(not(feature = "h7"))]
+    RxFifo0NewMessage = 0,
+    #[cfg(not(feature = "h7"))]
+    RxFifo0Full = 1,
+    #[cfg(not(feature = "h7"))]
+    RxFifo0MessageLost = 2,
+    #[cfg(not(feature = "h7"))]
+    RxFifo1NewMessage = 3,
+    #[cfg(not(feature = "h7"))]
+    RxFifo1Full = 4,
+    #[cfg(not(feature = "h7"))]
+    RxFifo1MessageLost = 5,
+    #[cfg(not(feature = "h7"))]
+    TxComplete = 7,
+    #[cfg(not(feature = "h7"))]
+    TxFifoEmpty = 9,
+    #[cfg(not(feature = "h7"))]
+    ErrorPassive = 17,
+    #[cfg(not(feature = "h7"))]
+    ErrorWarning = 18,
+    #[cfg(not(feature = "h7"))]
+    BusOff = 19,
+    #[cfg(feature = "h7")]
+    RxFifo0NewMessage = 0,
+    #[cfg(feature = "h7")]
+    RxFifo0Full = 2,
+    #[cfg(feature = "h7")]
+    RxFifo0MessageLost = 3,
+    #[cfg(feature = "h7")]
+    RxFifo1NewMessage = 4,
+    #[cfg(feature = "h7")]
+    RxFifo1Full = 6,
+    #[cfg(feature = "h7")]
+    RxFifo1MessageLost = 7,
+    #[cfg(feature = "h7")]
+    TxComplete = 9,
+    #[cfg(feature = "h7")]
+    TxFifoEmpty = 11,
+    #[cfg(feature = "h7")]
+    ErrorPassive = 23,
+    #[cfg(feature = "h7")]
+    ErrorWarning = 24,
+    #[cfg(feature = "h7")]
+    BusOff = 25,
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+/// FDCAN error
+pub enum Error {
+    /// No bit timing matches the bit rate at the kernel clock frequency.
+    BitTiming,
+    /// The TX FIFO or queue is full.
+    TxFull,
+}
+
+#[derive(Clone, Copy)]
+/// A classic CAN, or CAN FD frame.
+pub struct Frame {
+    pub id: Id,
+    /// A remote frame. (Classic CAN only)
+    pub remote: bool,
+    /// A CAN FD frame.
+    pub fd: bool,
+    /// Send the data phase at the data bit rate. (CAN FD only)
+    pub bit_rate_switch: bool,
+    len: u8,
+    data: [u8; 64],
+}
+
+impl Frame {
+    /// Create a classic CAN data frame. Returns `None` if `data` is longer than 8 bytes.
+    pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+        Some(Self::new_unchecked(id, data, false, false))
+    }
+
+    /// Create a CAN FD data frame. Returns `None` if `data` is longer than 64 bytes. CAN FD
+    /// frames longer than 8 bytes have lengths of 12, 16, 20, 24, 32, 48, or 64 bytes; data of
+    /// other lengths is padded with zeros.
+    pub fn new_fd(id: Id, data: &[u8], bit_rate_switch: bool) -> Option<Self> {
+        if data.len() > 64 {
+            return None;
+        }
+        let mut result = Self::new_unchecked(id, data, true, bit_rate_switch);
+        result.len = DLC_LENGTHS[len_to_dlc(data.len()) as usize];
+        Some(result)
+    }
+
+    /// Create a classic CAN remote frame, requesting `len` bytes.
+    pub fn new_remote(id: Id, len: u8) -> Self {
+        let mut result = Self::new_unchecked(id, &[], false, false);
+        result.remote = true;
+        result.len = len.min(8);
+        result
+    }
+
+    fn new_unchecked(id: Id, data: &[u8], fd: bool, bit_rate_switch: bool) -> Self {
+        let mut buf = [0; 64];
+        buf[..data.len()].copy_from_slice(data);
+
+        Self {
+            id,
+            remote: false,
+            fd,
+            bit_rate_switch,
+            len: data.len() as u8,
+            data: buf,
+        }
+    }
+
+    /// The frame's data. Empty for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..self.len as usize]
+        }
+    }
+}
+
+/// The smallest DLC that holds `len` bytes.
+fn len_to_dlc(len: usize) -> u8 {
+    DLC_LENGTHS.iter().position(|l| *l as usize >= len).unwrap() as u8
+}
+
+/// Bit timing for one phase.
+struct BitTiming {
+    prescaler: u32,
+    seg1: u32,
+    seg2: u32,
+}
+
+/// Calculate bit timing, with the most time quanta per bit that divide the kernel clock exactly,
+/// and the sample point as close to `sample_point` (in 1/1000 of the bit time) as possible.
+fn calc_bit_timing(
+    kernel_clk: u32,
+    bitrate: u32,
+    sample_point: u32,
+    max_prescaler: u32,
+    max_seg1: u32,
+    max_seg2: u32,
+) -> Result<BitTiming, Error> {
+    if bitrate == 0 {
+        return Err(Error::BitTiming);
+    }
+
+    for prescaler in 1..=max_prescaler {
+        if !kernel_clk.is_multiple_of(bitrate * prescaler) {
+            continue;
+        }
+
+        // A bit is 1 sync segment time quantum, plus seg1, and seg2 quanta.
+        let tq = kernel_clk / (bitrate * prescaler);
+        if tq < 4 || tq > 1 + max_seg1 + max_seg2 {
+            continue;
+        }
+
+        let seg2 = ((tq * (1_000 - sample_point) + 500) / 1_000).clamp(1, max_seg2);
+        let seg1 = tq - 1 - seg2;
+        if seg1 == 0 || seg1 > max_seg1 {
+            continue;
+        }
+
+        return Ok(BitTiming {
+            prescaler,
+            seg1,
+            seg2,
+        });
+    }
+
+    Err(Error::BitTiming)
+}
+
+/// Represents an FDCAN peripheral.
+pub struct FdCan {
+    base: usize,
+    ram: usize,
+}
+
+impl FdCan {
+    /// Enable and reset the FDCAN's RCC peripheral clock (shared by all FDCAN peripherals), and
+    /// configure the peripheral. `kernel_clk` is the FDCAN kernel clock frequency, in Hz. The
+    /// nominal bit rate uses an 87.5% sample point, and the data bit rate, 75%. Filters are all
+    /// disabled; set them up with `set_std_filter()`, and `set_ext_filter()`.
+    pub fn new(device: FdCanDevice, cfg: &FdCanConfig, kernel_clk: u32) -> Result<Self, Error> {
+        let (base, ram) = device.addrs();
+
+        let nominal = calc_bit_timing(kernel_clk, cfg.nominal_bitrate, 875, 512, 256, 128)?;
+        let data = match cfg.data_bitrate {
+            Some(bitrate) => Some(calc_bit_timing(kernel_clk, bitrate, 750, 32, 32, 16)?),
+            None => None,
+        };
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // Don't reset the peripheral if it's already enabled, since that resets all FDCANs.
+            cfg_if! {
+                if #[cfg(feature = "g4")] {
+                    if rcc.apb1enr1.read().bits() & RCC_BIT == 0 {
+                        rcc.apb1enr1.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1rstr1.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1rstr1.modify(|r, w| unsafe { w.bits(r.bits() & !RCC_BIT) });
+                    }
+                } else if #[cfg(feature = "l5")] {
+                    if rcc.apb1enr2.read().bits() & RCC_BIT == 0 {
+                        rcc.apb1enr2.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1rstr2.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1rstr2.modify(|r, w| unsafe { w.bits(r.bits() & !RCC_BIT) });
+                    }
+                } else {
+                    if rcc.apb1henr.read().bits() & RCC_BIT == 0 {
+                        rcc.apb1henr.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1hrstr.modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+                        rcc.apb1hrstr.modify(|r, w| unsafe { w.bits(r.bits() & !RCC_BIT) });
+                    }
+                }
+            }
+        });
+
+        let mut result = Self { base, ram };
+
+        // Enter initialization mode, and allow configuration changes.
+        result.write(CCCR, result.read(CCCR) | INIT);
+        while result.read(CCCR) & INIT == 0 {}
+        result.write(CCCR, result.read(CCCR) | CCE);
+
+        // Clear the message RAM, which disables all filters.
+        for i in 0..INSTANCE_RAM_SIZE {
+            result.write_ram(i, 0);
+        }
+
+        let mut cccr = result.read(CCCR) & !(DAR | FDOE | BRSE);
+        if !cfg.auto_retransmit {
+            cccr |= DAR;
+        }
+        if cfg.fd {
+            cccr |= FDOE;
+        }
+        if cfg.fd && data.is_some() {
+            cccr |= BRSE;
+        }
+        result.write(CCCR, cccr);
+
+        // Fields are the value - 1. The resynchronization jump width is the same as seg2.
+        result.write(
+            NBTP,
+            (nominal.seg2 - 1) << 25
+                | (nominal.prescaler - 1) << 16
+                | (nominal.seg1 - 1) << 8
+                | (nominal.seg2 - 1),
+        );
+
+        if let Some(d) = data {
+            let mut dbtp =
+                (d.prescaler - 1) << 16 | (d.seg1 - 1) << 8 | (d.seg2 - 1) << 4 | (d.seg2 - 1);
+
+            // Transceiver delay compensation is required at high data bit rates. The offset is
+            // the sample point position, in kernel clock cycles.
+            if d.prescaler <= 2 && cfg.data_bitrate.unwrap() >= 1_000_000 {
+                dbtp |= TDC;
+                result.write(TDCR, (d.prescaler * (1 + d.seg1)).min(127) << 8);
+            }
+            result.write(DBTP, dbtp);
+        }
+
+        result.configure_ram();
+
+        let mut gfc = (cfg.non_matching as u32) << 4 | (cfg.non_matching as u32) << 2;
+        if cfg.reject_remote {
+            gfc |= 0b11;
+        }
+        #[cfg(not(feature = "h7"))]
+        {
+            // LSE, and LSS: the number of extended, and standard filter elements.
+            gfc |= (NUM_EXT_FILTERS as u32) << 24 | (NUM_STD_FILTERS as u32) << 16;
+        }
+        result.write(GFC, gfc);
+
+        let txbc = result.read(TXBC);
+        result.write(
+            TXBC,
+            match cfg.tx_mode {
+                TxMode::Fifo => txbc & !TFQM,
+                TxMode::Queue => txbc | TFQM,
+            },
+        );
+
+        // Route all interrupts to interrupt line 0. (eg `FDCAN1_IT0`)
+        result.write(ILE, 1);
+
+        // Leave initialization mode.
+        result.write(CCCR, result.read(CCCR) & !INIT);
+        while result.read(CCCR) & INIT != 0 {}
+
+        Ok(result)
+    }
+
+    #[cfg(feature = "h7")]
+    /// Set the message RAM section addresses, and sizes. Addresses are byte offsets from the start
+    /// of the message RAM.
+    fn configure_ram(&mut self) {
+        let start = (self.ram - RAM_BASE) as u32;
+        let addr = |offset: usize| start + offset as u32 * 4;
+
+        self.write(
+            SIDFC,
+            (NUM_STD_FILTERS as u32) << 16 | addr(STD_FILTER_OFFSET),
+        );
+        self.write(
+            XIDFC,
+            (NUM_EXT_FILTERS as u32) << 16 | addr(EXT_FILTER_OFFSET),
+        );
+        self.write(RXF0C, (RX_FIFO_SIZE as u32) << 16 | addr(RX_FIFO0_OFFSET));
+        self.write(RXF1C, (RX_FIFO_SIZE as u32) << 16 | addr(RX_FIFO1_OFFSET));
+        // 64-byte data fields, for the RX FIFOs, and RX buffers.
+        self.write(RXESC, 0b111 << 8 | 0b111 << 4 | 0b111);
+        // TX buffers are all used for the FIFO or queue.
+        self.write(TXBC, (TX_BUF_SIZE as u32) << 24 | addr(TX_BUF_OFFSET));
+        self.write(TXESC, 0b111);
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// The message RAM layout is fixed on G4 and L5.
+    fn configure_ram(&mut self) {}
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// Read a word from the message RAM; `offset` is in words.
+    fn read_ram(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.ram + offset * 4) as *const u32) }
+    }
+
+    /// Write a word to the message RAM; `offset` is in words.
+    fn write_ram(&mut self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.ram + offset * 4) as *mut u32, value) }
+    }
+
+    /// Configure a standard ID filter. `index` is 0 - 27. Filters are checked in order, and the
+    /// first match is used.
+    pub fn set_std_filter(&mut self, index: u8, filter: &StdFilter) {
+        assert!(index < NUM_STD_FILTERS, "Invalid filter index.");
+
+        self.write_ram(
+            STD_FILTER_OFFSET + index as usize,
+            (filter.filter_type as u32) << 30
+                | (filter.action as u32) << 27
+                | (filter.id1 as u32 & 0x7ff) << 16
+                | (filter.id2 as u32 & 0x7ff),
+        );
+    }
+
+    /// Configure an extended ID filter. `index` is 0 - 7.
+    pub fn set_ext_filter(&mut self, index: u8, filter: &ExtFilter) {
+        assert!(index < NUM_EXT_FILTERS, "Invalid filter index.");
+
+        let offset = EXT_FILTER_OFFSET + index as usize * 2;
+        self.write_ram(
+            offset,
+            (filter.action as u32) << 29 | (filter.id1 & 0x1fff_ffff),
+        );
+        self.write_ram(
+            offset + 1,
+            (filter.filter_type as u32) << 30 | (filter.id2 & 0x1fff_ffff),
+        );
+    }
+
+    /// Add a frame to the TX FIFO or queue, and request transmission. Returns an error if it's
+    /// full.
+    pub fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        let txfqs = self.read(TXFQS);
+        if txfqs & TFQF != 0 {
+            return Err(Error::TxFull);
+        }
+        let put_index = ((txfqs >> 16) & 0x1f) as usize;
+        let offset = TX_BUF_OFFSET + put_index * ELEMENT_SIZE;
+
+        let mut t0 = match frame.id {
+            Id::Standard(id) => (id as u32 & 0x7ff) << 18,
+            Id::Extended(id) => XTD | (id & 0x1fff_ffff),
+        };
+        if frame.remote {
+            t0 |= RTR;
+        }
+
+        let mut t1 = (len_to_dlc(frame.len as usize) as u32) << DLC_SHIFT;
+        if frame.fd {
+            t1 |= FDF;
+            if frame.bit_rate_switch {
+                t1 |= BRS;
+            }
+        }
+
+        self.write_ram(offset, t0);
+        self.write_ram(offset + 1, t1);
+
+        for (i, chunk) in frame.data[..frame.len as usize].chunks(4).enumerate() {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_ram(offset + 2 + i, u32::from_le_bytes(word));
+        }
+
+        self.write(TXBAR, 1 << put_index);
+
+        Ok(())
+    }
+
+    /// Returns true if the TX FIFO or queue is full.
+    pub fn tx_full(&self) -> bool {
+        self.read(TXFQS) & TFQF != 0
+    }
+
+    /// Read the oldest frame from an RX FIFO, if one is available.
+    pub fn receive(&mut self, fifo: RxFifo) -> Option<Frame> {
+        let (status_reg, ack_reg, fifo_offset) = match fifo {
+            RxFifo::Fifo0 => (RXF0S, RXF0A, RX_FIFO0_OFFSET),
+            RxFifo::Fifo1 => (RXF1S, RXF1A, RX_FIFO1_OFFSET),
+        };
+
+        let status = self.read(status_reg);
+        if status & 0x7f == 0 {
+            return None;
+        }
+        let get_index = (status >> 8) & 0x3f;
+        let offset = fifo_offset + get_index as usize * ELEMENT_SIZE;
+
+        let r0 = self.read_ram(offset);
+        let r1 = self.read_ram(offset + 1);
+
+        let id = if r0 & XTD != 0 {
+            Id::Extended(r0 & 0x1fff_ffff)
+        } else {
+            Id::Standard(((r0 >> 18) & 0x7ff) as u16)
+        };
+
+        let fd = r1 & FDF != 0;
+        let dlc = ((r1 >> DLC_SHIFT) & 0xf) as usize;
+        // Classic CAN DLCs above 8 indicate 8 bytes.
+        let len = if fd {
+            DLC_LENGTHS[dlc]
+        } else {
+            dlc.min(8) as u8
+        };
+
+        let mut frame = Frame::new_unchecked(id, &[], fd, r1 & BRS != 0);
+        frame.remote = r0 & RTR != 0;
+        frame.len = len;
+
+        if !frame.remote {
+            for i in 0..(len as usize).div_ceil(4) {
+                let word = self.read_ram(offset + 2 + i).to_le_bytes();
+                let end = (i * 4 + 4).min(len as usize);
+                frame.data[i * 4..end].copy_from_slice(&word[..end - i * 4]);
+            }
+        }
+
+        // Acknowledge, freeing the element.
+        self.write(ack_reg, get_index);
+
+        Some(frame)
+    }
+
+    /// The number of frames in an RX FIFO.
+    pub fn rx_fifo_level(&self, fifo: RxFifo) -> u8 {
+        let reg = match fifo {
+            RxFifo::Fifo0 => RXF0S,
+            RxFifo::Fifo1 => RXF1S,
+        };
+        (self.read(reg) & 0x7f) as u8
+    }
+
+    /// Returns true if the peripheral is bus-off, due to too many errors. Recover with
+    /// `recover_from_bus_off()`.
+    pub fn is_bus_off(&self) -> bool {
+        self.read(PSR) & BO != 0
+    }
+
+    /// Leave the bus-off state. Hardware sets INIT on bus-off; clearing it starts the recovery
+    /// sequence of 128 occurrences of 11 recessive bits.
+    pub fn recover_from_bus_off(&mut self) {
+        self.write(CCCR, self.read(CCCR) & !INIT);
+    }
+
+    /// Read the (transmit, receive) error counters. (ECR, TEC, and REC fields)
+    pub fn error_counters(&self) -> (u8, u8) {
+        let ecr = self.read(ECR);
+        (ecr as u8, ((ecr >> 8) & 0x7f) as u8)
+    }
+
+    /// Enable an interrupt. Interrupts are on interrupt line 0; eg `FDCAN1_IT0`.
+    pub fn enable_interrupt(&mut self, interrupt: FdCanInterrupt) {
+        self.write(IE, self.read(IE) | (1 << interrupt as u8));
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: FdCanInterrupt) {
+        self.write(IE, self.read(IE) & !(1 << interrupt as u8));
+    }
+
+    /// Returns true if an interrupt flag is set.
+    pub fn is_interrupt_set(&self, interrupt: FdCanInterrupt) -> bool {
+        self.read(IR) & (1 << interrupt as u8) != 0
+    }
+
+    /// Clear an interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: FdCanInterrupt) {
+        self.write(IR, 1 << interrupt as u8);
+    }
+}
@@ -12,8 +12,32 @@ use cfg_if::cfg_if;
 
 const FLASH_KEY1: u32 = 0x4567_0123;
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
-// const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
-// const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+cfg_if! {
+    if #[cfg(any(feature = "l4", feature = "g0", feature = "g4", feature = "wb", feature = "wl"))] {
+        const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
+        const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+
+        // Write protection area register offsets, from the start of the FLASH peripheral. These are
+        // consistent across L4, G0, G4, WB, and WL, but PAC support for the bank 2 registers varies.
+        const WRP1AR_OFFSET: usize = 0x2c;
+        const WRP1BR_OFFSET: usize = 0x30;
+        #[allow(dead_code)] // Single-bank MCUs.
+        const WRP2AR_OFFSET: usize = 0x4c;
+        #[allow(dead_code)]
+        const WRP2BR_OFFSET: usize = 0x50;
+    }
+}
+
+// Width of the WRPxy_STRT, and WRPxy_END page fields; these vary with the number of pages per bank.
+cfg_if! {
+    if #[cfg(feature = "g0")] {
+        const WRP_PAGE_MASK: u32 = 0x3f;
+    } else if #[cfg(any(feature = "g4", feature = "wl"))] {
+        const WRP_PAGE_MASK: u32 = 0x7f;
+    } else if #[cfg(any(feature = "l4", feature = "wb"))] {
+        const WRP_PAGE_MASK: u32 = 0xff;
+    }
+}
 
 #[derive(Clone, Copy, PartialEq)]
 /// Set dual bank mode (DBANK option bit). Eg G4
@@ -32,6 +56,36 @@ pub enum Bank {
     B2 = 1,
 }
 
+#[cfg(any(
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+#[derive(Clone, Copy, PartialEq)]
+/// Each bank has two write protection areas; A, and B. Eg area A of bank 1 is set by the
+/// `FLASH_WRP1AR` register.
+pub enum WrpArea {
+    A,
+    B,
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "g0",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl"
+))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A range of write-protected pages, inclusive of both ends. Page numbers are relative to the start
+/// of the bank.
+pub struct WrpRegion {
+    pub start_page: usize,
+    pub end_page: usize,
+}
+
 #[derive(Copy, Clone, Debug)]
 /// Possible error states for flash operations.
 pub enum Error {
@@ -206,26 +260,31 @@ impl Flash {
         }
     }
 
-    // /// Unlock the FLASH_OPTCR register, for writing option bits.
-    // pub fn unlock_options(&mut self) -> Result<(), Error> {
-    //     #[cfg(not(feature = "h7"))]
-    //     let regs = &self.regs;
-    //     #[cfg(feature = "h7")]
-    //     let regs = self.regs.bank1();
-    //
-    //     if regs.cr.read().lock().bit_is_clear() {
-    //         return Ok(());
-    //     }
-    //
-    //     regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY1) });
-    //     regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY2) });
-    //
-    //     if regs.cr.read().lock().bit_is_clear() {
-    //         Ok(())
-    //     } else {
-    //         Err(Error::Failure)
-    //     }
-    // }
+    #[cfg(any(
+        feature = "l4",
+        feature = "g0",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl"
+    ))]
+    /// Unlock the option bytes, allowing writes to option registers. The flash must be unlocked first.
+    /// See L4 RM, section 3.4.2: Option bytes programming.
+    pub fn unlock_options(&mut self) -> Result<(), Error> {
+        let regs = &self.regs;
+
+        if regs.cr.read().optlock().bit_is_clear() {
+            return Ok(());
+        }
+
+        regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY1) });
+        regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY2) });
+
+        if regs.cr.read().optlock().bit_is_clear() {
+            Ok(())
+        } else {
+            Err(Error::Failure)
+        }
+    }
 
     pub fn lock(&mut self) {
         // The FLASH_CR register cannot be written when the BSY bit in the Flash status register
@@ -250,6 +309,9 @@ impl Flash {
     /// is full zero, and any attempt will set PROGERR flag in the Flash status register
     /// (FLASH_SR)."
     pub fn erase_page(&mut self, bank: Bank, page: usize) -> Result<(), Error> {
+        #[cfg(feature = "wb")]
+        self.check_not_secure(page)?;
+
         self.unlock()?;
         let regs = &self.regs;
 
@@ -481,6 +543,9 @@ impl Flash {
         // todo: Consider a u8-based approach.
         // todo: DRY from `erase_page`.
 
+        #[cfg(feature = "wb")]
+        self.check_not_secure(page)?;
+
         self.unlock()?;
 
         let regs = &self.regs;
@@ -611,6 +676,218 @@ impl Flash {
         Ok(())
     }
 
+    #[cfg(any(
+        feature = "l4",
+        feature = "g0",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl"
+    ))]
+    /// Returns a pointer to a write protection area register, or an error if the bank doesn't
+    /// have them.
+    fn wrp_reg_ptr(&self, bank: Bank, area: WrpArea) -> Result<*mut u32, Error> {
+        let offset = match (bank, area) {
+            (Bank::B1, WrpArea::A) => WRP1AR_OFFSET,
+            (Bank::B1, WrpArea::B) => WRP1BR_OFFSET,
+            #[cfg(any(
+                feature = "l4x5",
+                feature = "l4x6",
+                feature = "g0b0",
+                feature = "g0b1",
+                feature = "g0c1",
+                feature = "g473",
+                feature = "g474",
+                feature = "g483",
+                feature = "g484"
+            ))]
+            (Bank::B2, WrpArea::A) => WRP2AR_OFFSET,
+            #[cfg(any(
+                feature = "l4x5",
+                feature = "l4x6",
+                feature = "g0b0",
+                feature = "g0b1",
+                feature = "g0c1",
+                feature = "g473",
+                feature = "g474",
+                feature = "g483",
+                feature = "g484"
+            ))]
+            (Bank::B2, WrpArea::B) => WRP2BR_OFFSET,
+            #[allow(unreachable_patterns)]
+            _ => return Err(Error::Illegal),
+        };
+
+        Ok(unsafe { (FLASH::ptr() as *mut u8).add(offset) as *mut u32 })
+    }
+
+    #[cfg(any(
+        feature = "l4",
+        feature = "g0",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl"
+    ))]
+    /// Read the pages protected by a write protection area, or `None` if the area is disabled.
+    /// This reflects the option bytes loaded at the last reset, or `launch_option_bytes()`.
+    pub fn write_protection(&self, bank: Bank, area: WrpArea) -> Result<Option<WrpRegion>, Error> {
+        let val = unsafe { core::ptr::read_volatile(self.wrp_reg_ptr(bank, area)?) };
+
+        // WRPxy_STRT starts at bit 0, and WRPxy_END at bit 16.
+        let start_page = (val & WRP_PAGE_MASK) as usize;
+        let end_page = ((val >> 16) & WRP_PAGE_MASK) as usize;
+
+        // "When WRPxy_STRT > WRPxy_END, the area is unprotected."
+        if start_page > end_page {
+            Ok(None)
+        } else {
+            Ok(Some(WrpRegion {
+                start_page,
+                end_page,
+            }))
+        }
+    }
+
+    #[cfg(any(
+        feature = "l4",
+        feature = "g0",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl"
+    ))]
+    /// Write-protect a range of pages, or pass `None` to remove the area's protection. Eg, a bootloader
+    /// can protect its own pages from being erased by the application. This programs the option bytes;
+    /// the change takes effect after `launch_option_bytes()`, or a power-on reset.
+    /// See L4 RM, section 3.5.3: Write protection (WRP), and section 3.4.2: Option bytes programming.
+    pub fn set_write_protection(
+        &mut self,
+        bank: Bank,
+        area: WrpArea,
+        region: Option<WrpRegion>,
+    ) -> Result<(), Error> {
+        let reg = self.wrp_reg_ptr(bank, area)?;
+
+        let (start, end) = match region {
+            Some(r) => {
+                if r.start_page > WRP_PAGE_MASK as usize
+                    || r.end_page > WRP_PAGE_MASK as usize
+                    || r.start_page > r.end_page
+                {
+                    return Err(Error::PageOutOfRange);
+                }
+                (r.start_page as u32, r.end_page as u32)
+            }
+            // A start page after the end page disables the area.
+            None => (WRP_PAGE_MASK, 0),
+        };
+
+        self.unlock()?;
+        if let Err(e) = self.unlock_options() {
+            self.lock();
+            return Err(e);
+        }
+
+        let regs = &self.regs;
+
+        // 1. Check that no Flash memory operation is ongoing by checking the BSY bit in the Flash
+        // status register (FLASH_SR).
+        if regs.sr.read().bsy().bit_is_set() {
+            self.lock();
+            return Err(Error::Busy);
+        }
+
+        clear_error_flags(regs);
+
+        // 2. Write the desired options value in the options registers.
+        unsafe {
+            let val = core::ptr::read_volatile(reg);
+            let mask = WRP_PAGE_MASK | (WRP_PAGE_MASK << 16);
+            core::ptr::write_volatile(reg, (val & !mask) | start | (end << 16));
+        }
+
+        // 3. Set the Options Start bit OPTSTRT in the Flash control register (FLASH_CR).
+        regs.cr.modify(|_, w| w.optstrt().set_bit());
+
+        // 4. Wait for the BSY bit to be cleared.
+        while regs.sr.read().bsy().bit_is_set() {}
+
+        // Setting LOCK also sets OPTLOCK.
+        self.lock();
+
+        Ok(())
+    }
+
+    #[cfg(any(
+        feature = "l4",
+        feature = "g0",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl"
+    ))]
+    /// Reload the option bytes, eg to apply changes from `set_write_protection()`. This resets
+    /// the MCU. Sets FLASH_CR, OBL_LAUNCH bit.
+    pub fn launch_option_bytes(&mut self) -> Result<(), Error> {
+        self.unlock()?;
+        self.unlock_options()?;
+
+        self.regs.cr.modify(|_, w| w.obl_launch().set_bit());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "wb")]
+    /// Returns the first page of the secure flash area, which contains the wireless stack, and FUS,
+    /// or `None` if secure flash is disabled. CPU1 can't access pages from this one onward. This is
+    /// set by CPU2 (the FUS) when installing the wireless stack, and is read-only to CPU1. Reads FLASH_SFR,
+    /// SFSA and FSD fields. See WB RM, section 3.5.1: Secure flash memory.
+    pub fn secure_start_page(&self) -> Option<usize> {
+        // SFSA is bits 7:0, and FSD is bit 8.
+        let sfr = unsafe {
+            core::ptr::read_volatile((FLASH::ptr() as *const u8).add(0x80) as *const u32)
+        };
+
+        if sfr & (1 << 8) != 0 {
+            None
+        } else {
+            Some((sfr & 0xff) as usize)
+        }
+    }
+
+    #[cfg(feature = "wb")]
+    /// Returns the start of the secure SRAM2a, and SRAM2b areas used by CPU2, as offsets in 1kB units
+    /// from the start of each, or `None` for each if disabled. Reads FLASH_SRRVR, SBRSA and
+    /// SNBRSA fields.
+    pub fn secure_sram2_start(&self) -> (Option<u8>, Option<u8>) {
+        // SBRSA is bits 12:8, BRSD is bit 23. SNBRSA is bits 29:25, and NBRSD is bit 30.
+        let srrvr = unsafe {
+            core::ptr::read_volatile((FLASH::ptr() as *const u8).add(0x84) as *const u32)
+        };
+
+        let sram2a = if srrvr & (1 << 23) != 0 {
+            None
+        } else {
+            Some(((srrvr >> 8) & 0x1f) as u8)
+        };
+        let sram2b = if srrvr & (1 << 30) != 0 {
+            None
+        } else {
+            Some(((srrvr >> 25) & 0x1f) as u8)
+        };
+
+        (sram2a, sram2b)
+    }
+
+    #[cfg(feature = "wb")]
+    /// Guard against erasing or writing the secure area; this would otherwise fail with a bus
+    /// error, or, if it were misconfigured, corrupt the wireless stack.
+    fn check_not_secure(&self, page: usize) -> Result<(), Error> {
+        if let Some(start) = self.secure_start_page() {
+            if page >= start {
+                return Err(Error::PageOutOfRange);
+            }
+        }
+        Ok(())
+    }
+
     /// Read flash memory at a given page and offset into an 8-bit-dword buffer.
     #[allow(unused_variables)] // bank arg on single-bank MCUs.
     pub fn read(&self, bank: Bank, page: usize, offset: usize, buf: &mut [u8]) {
@@ -8,6 +8,17 @@ use cfg_if::cfg_if;
 
 const FLASH_KEY1: u32 = 0x4567_0123;
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
+const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
+const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+
+// Option register offsets, from the start of the FLASH peripheral. We use raw pointers for these,
+// since PAC support for them is inconsistent.
+const OPTR_OFFSET: usize = 0x40;
+const SECWM1R1_OFFSET: usize = 0x50;
+const SECWM1R2_OFFSET: usize = 0x54;
+const SECWM2R1_OFFSET: usize = 0x60;
+const SECWM2R2_OFFSET: usize = 0x64;
+const SECHDPCR_OFFSET: usize = 0xc0;
 
 #[derive(Clone, Copy)]
 /// Cortex-M33 secure programming, or nonsecure.
@@ -31,6 +42,18 @@ pub enum Bank {
     B2 = 1,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A bank's secure watermark area, and optional hide protection (HDP) area, in pages relative to
+/// the start of the bank, inclusive of both ends. The secure area is only accessible from the
+/// secure state. The HDP area is at the start of the secure area; eg a secure bootloader. Once
+/// hidden with `hide_protection_area()`, it's inaccessible until the next reset.
+pub struct SecureArea {
+    pub start_page: usize,
+    pub end_page: usize,
+    /// The HDP area runs from `start_page` to this page. `None` disables it.
+    pub hdp_end_page: Option<usize>,
+}
+
 #[derive(Copy, Clone, Debug)]
 /// Possible error states for flash operations.
 pub enum Error {
@@ -435,6 +458,144 @@ impl Flash {
         Ok(())
     }
 
+    /// Unlock the option bytes, allowing writes to option registers. See L5 RM, section 6.4.2:
+    /// Option bytes programming.
+    pub fn unlock_options(&mut self) -> Result<(), Error> {
+        self.unlock(Security::NonSecure)?;
+
+        if self.regs.nscr.read().optlock().bit_is_clear() {
+            return Ok(());
+        }
+
+        self.regs
+            .optkeyr
+            .write(|w| unsafe { w.bits(FLASH_OPT_KEY1) });
+        self.regs
+            .optkeyr
+            .write(|w| unsafe { w.bits(FLASH_OPT_KEY2) });
+
+        if self.regs.nscr.read().optlock().bit_is_clear() {
+            Ok(())
+        } else {
+            Err(Error::Failure)
+        }
+    }
+
+    /// Check if TrustZone is enabled. (FLASH_OPTR, TZEN bit)
+    pub fn trustzone_enabled(&self) -> bool {
+        unsafe { read_reg(OPTR_OFFSET) & (1 << 31) != 0 }
+    }
+
+    /// Read a bank's secure watermark area, and HDP area, or `None` if it has no secure area.
+    /// This reflects the option bytes loaded at the last reset, or `launch_option_bytes()`.
+    pub fn secure_area(&self, bank: Bank) -> Option<SecureArea> {
+        let (r1_offset, r2_offset) = secwm_offsets(bank);
+        let (r1, r2) = unsafe { (read_reg(r1_offset), read_reg(r2_offset)) };
+
+        // SECWMx_PSTRT is bits 6:0, and SECWMx_PEND is bits 22:16.
+        let start_page = (r1 & 0x7f) as usize;
+        let end_page = ((r1 >> 16) & 0x7f) as usize;
+
+        // "If SECWMx_PSTRT > SECWMx_PEND, the secure watermark area is disabled."
+        if start_page > end_page {
+            return None;
+        }
+
+        // HDPx_PEND is bits 22:16, and HDPxEN is bit 31.
+        let hdp_end_page = if r2 & (1 << 31) != 0 {
+            Some(((r2 >> 16) & 0x7f) as usize)
+        } else {
+            None
+        };
+
+        Some(SecureArea {
+            start_page,
+            end_page,
+            hdp_end_page,
+        })
+    }
+
+    /// Configure a bank's secure watermark area, and HDP area, or pass `None` to remove them. This
+    /// programs the option bytes, and must be run from the secure state, with TrustZone enabled.
+    /// The change takes effect after `launch_option_bytes()`, or a power-on reset.
+    /// Returns an error if TrustZone is disabled, if a page is out of range, or if the HDP area
+    /// ends outside the secure area. See L5 RM, section 6.6.6: Secure watermark-based area
+    /// protection, and section 6.6.7: Secure hide protection (HDP).
+    pub fn set_secure_area(&mut self, bank: Bank, area: Option<SecureArea>) -> Result<(), Error> {
+        if !self.trustzone_enabled() {
+            return Err(Error::Illegal);
+        }
+
+        let (r1, r2) = match area {
+            Some(a) => {
+                if a.start_page > 0x7f || a.end_page > 0x7f || a.start_page > a.end_page {
+                    return Err(Error::PageOutOfRange);
+                }
+
+                let r2 = match a.hdp_end_page {
+                    Some(hdp_end) => {
+                        if hdp_end < a.start_page || hdp_end > a.end_page {
+                            return Err(Error::PageOutOfRange);
+                        }
+                        (1 << 31) | ((hdp_end as u32) << 16)
+                    }
+                    None => 0,
+                };
+
+                (a.start_page as u32 | ((a.end_page as u32) << 16), r2)
+            }
+            // A start page after the end page disables the area.
+            None => (0x7f, 0),
+        };
+
+        self.unlock_options()?;
+
+        if self.regs.nssr.read().nsbsy().bit_is_set() {
+            self.lock(Security::NonSecure);
+            return Err(Error::Busy);
+        }
+
+        clear_error_flags(&self.regs, Security::NonSecure);
+
+        let (r1_offset, r2_offset) = secwm_offsets(bank);
+        unsafe {
+            let r1_prev = read_reg(r1_offset);
+            write_reg(r1_offset, (r1_prev & !0x007f_007f) | r1);
+            let r2_prev = read_reg(r2_offset);
+            write_reg(r2_offset, (r2_prev & !((1 << 31) | (0x7f << 16))) | r2);
+        }
+
+        self.regs.nscr.modify(|_, w| w.optstrt().set_bit());
+        while self.regs.nssr.read().nsbsy().bit_is_set() {}
+
+        // Setting NSLOCK also sets OPTLOCK.
+        self.lock(Security::NonSecure);
+
+        Ok(())
+    }
+
+    /// Hide a bank's HDP area, eg from a secure bootloader, immediately before jumping to the
+    /// next stage. Once hidden, the area can't be read, written, executed, or erased until the
+    /// next reset. Must be run from the secure state. Sets FLASH_SECHDPCR, HDPx_ACCDIS bit.
+    pub fn hide_protection_area(&mut self, bank: Bank) {
+        // HDP1_ACCDIS is bit 0, and HDP2_ACCDIS is bit 1.
+        let bit = match bank {
+            Bank::B1 => 1,
+            Bank::B2 => 1 << 1,
+        };
+        unsafe { write_reg(SECHDPCR_OFFSET, read_reg(SECHDPCR_OFFSET) | bit) };
+    }
+
+    /// Reload the option bytes, eg to apply changes from `set_secure_area()`. This resets the MCU.
+    /// Sets FLASH_NSCR, OBL_LAUNCH bit.
+    pub fn launch_option_bytes(&mut self) -> Result<(), Error> {
+        self.unlock_options()?;
+
+        self.regs.nscr.modify(|_, w| w.obl_launch().set_bit());
+
+        Ok(())
+    }
+
     /// Read flash memory at a given page and offset into a buffer.
     pub fn read(&self, bank: Bank, page: usize, offset: usize, buf: &mut [u8]) {
         let mut addr = page_to_address(self.dual_bank, bank, page) as *mut u32;
@@ -450,6 +611,22 @@ impl Flash {
     }
 }
 
+/// Offsets of a bank's secure watermark registers: SECWMxR1, and SECWMxR2.
+fn secwm_offsets(bank: Bank) -> (usize, usize) {
+    match bank {
+        Bank::B1 => (SECWM1R1_OFFSET, SECWM1R2_OFFSET),
+        Bank::B2 => (SECWM2R1_OFFSET, SECWM2R2_OFFSET),
+    }
+}
+
+unsafe fn read_reg(offset: usize) -> u32 {
+    core::ptr::read_volatile((FLASH::ptr() as *const u8).add(offset) as *const u32)
+}
+
+unsafe fn write_reg(offset: usize, value: u32) {
+    core::ptr::write_volatile((FLASH::ptr() as *mut u8).add(offset) as *mut u32, value)
+}
+
 /// Calculate the address of the start of a given page. Each page is 2,048 Kb for non-H7.
 /// For H7, sectors are 128Kb, with 8 sectors per bank.
 fn page_to_address(dual_bank: DualBank, bank: Bank, page: usize) -> usize {
@@ -1,28 +1,450 @@
-//! This module supports the Filter Match ACcelerator (FMAC) peripheral, which
-//! allows for hardware processing of digital filters such as FIR and IIR.
+//! Support for the Filter Math Accelerator (FMAC), which runs FIR and IIR filters in hardware, on
+//! q1.15 fixed point data. Filtering runs continuously once started: Write input samples, and
+//! read output samples, either one at a time, or streaming with DMA.
+//!
+//! The FMAC has 256 16-bit words of local memory, shared between 3 buffers: X2 holds the filter
+//! coefficients, X1 the input samples, and Y the output samples. `configure_fir()`, and
+//! `configure_iir()` size and place these buffers, and load the coefficients.
+//!
+//! For DMA, run `dma::mux()` with `DmaInput::FmacWrite`, and `DmaInput::FmacRead` first.
+//!
+//! See G4 RM, chapter 18: "Filter math accelerator (FMAC)".
+//!
+//! Example, a 5-tap moving average:
+//! ```rust
+//! let mut fmac = Fmac::new(dp.FMAC);
+//!
+//! let coeffs = [cordic::to_q15(0.2); 5];
+//! fmac.configure_fir(&coeffs, 0, 2);
+//! fmac.start();
+//!
+//! let mut output = [0; 64];
+//! fmac.process(&input, &mut output);
+//! ```
 
-// todo: Is this fixed point only?
+use core::ops::Deref;
 
-use crate::pac::{FMAC};
+use cortex_m::interrupt::free;
 
-pub struct Fmac {
-    pub regs: FMAC,
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel, DmaPeriph},
+    pac::{self, DMA1, DMA2, RCC},
+    util::rcc_en_reset,
+};
+
+use cfg_if::cfg_if;
+
+// We write registers as raw bits using these fields, since filter setup writes several fields at
+// once, and their names vary between PACs.
+
+// FMAC_XxBUFCFG field.
+const BUF_SIZE_SHIFT: u32 = 8;
+
+// FMAC_PARAM fields.
+const P_SHIFT: u32 = 0;
+const Q_SHIFT: u32 = 8;
+const R_SHIFT: u32 = 16;
+const FUNC_SHIFT: u32 = 24;
+const START: u32 = 1 << 31;
+
+// FMAC_CR fields.
+const DMAREN: u32 = 1 << 8;
+const DMAWEN: u32 = 1 << 9;
+const CLIPEN: u32 = 1 << 15;
+const RESET: u32 = 1 << 16;
+
+// FMAC_SR fields.
+const YEMPTY: u32 = 1 << 0;
+const X1FULL: u32 = 1 << 1;
+const OVFL: u32 = 1 << 8;
+const UNFL: u32 = 1 << 9;
+const SAT: u32 = 1 << 10;
+
+// FMAC_PARAM FUNC values.
+const FUNC_LOAD_X1: u32 = 1;
+const FUNC_LOAD_X2: u32 = 2;
+const FUNC_LOAD_Y: u32 = 3;
+const FUNC_FIR: u32 = 8;
+const FUNC_IIR: u32 = 9;
+
+/// The size of the FMAC's local memory, in 16-bit words.
+const MEM_SIZE: usize = 256;
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// FMAC errors, from the FMAC_SR register.
+pub enum Error {
+    /// An output sample was read from an empty Y buffer. (UNFL)
+    Underflow,
+    /// An input sample was written to a full X1 buffer. (OVFL)
+    Overflow,
+    /// An accumulator result overflowed. Disable this by enabling clipping. (SAT)
+    Saturation,
 }
 
-impl Fmac {
-    /// Create a struct used to perform operations on Flash.
-    pub fn new(regs: FMAC) -> Self {
-        // todo: Implement and configure dual bank mode.
-        Self { regs }
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// FMAC interrupts. Values are the FMAC_CR register enable bit positions.
+pub enum FmacInterrupt {
+    /// An output sample is ready to read. (RIEN)
+    Read = 0,
+    /// There's space for an input sample. (WIEN)
+    Write = 1,
+    /// Overflow error. (OVFLIEN)
+    Overflow = 2,
+    /// Underflow error. (UNFLIEN)
+    Underflow = 3,
+    /// Saturation error. (SATIEN)
+    Saturation = 4,
+}
+
+/// Represents the Filter Math Accelerator.
+pub struct Fmac<R> {
+    pub regs: R,
+    /// The FMAC_PARAM value used to start filtering; set by `configure_fir()`, or
+    /// `configure_iir()`.
+    param: u32,
+}
+
+impl<R> Fmac<R>
+where
+    R: Deref<Target = pac::fmac::RegisterBlock>,
+{
+    /// Initialize the FMAC, including enabling and resetting its RCC peripheral clock.
+    pub fn new(regs: R) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    rcc_en_reset!(ahb2, fmac, rcc);
+                } else {
+                    rcc_en_reset!(ahb1, fmac, rcc);
+                }
+            }
+        });
+
+        Self { regs, param: 0 }
+    }
+
+    /// Reset the FMAC's buffer pointers, and stop any function in progress. Buffer
+    /// configuration, and memory contents are retained. (FMAC_CR register, RESET field)
+    pub fn reset(&mut self) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | RESET) });
+        while self.regs.cr.read().bits() & RESET != 0 {}
+    }
+
+    /// Lay out the X2, X1, and Y buffers in that order, then load the coefficients into X2.
+    fn configure(&mut self, coeffs: (&[i16], &[i16]), x1_size: usize, y_size: usize) {
+        let x2_size = coeffs.0.len() + coeffs.1.len();
+
+        assert!(
+            x2_size + x1_size + y_size <= MEM_SIZE,
+            "FMAC buffers must fit in its 256-word memory."
+        );
+
+        self.reset();
+
+        // The X1 full, and Y empty watermarks are left at 1 sample, as DMA requires.
+        self.regs
+            .x2bufcfg
+            .write(|w| unsafe { w.bits((x2_size as u32) << BUF_SIZE_SHIFT) });
+        self.regs
+            .x1bufcfg
+            .write(|w| unsafe { w.bits(x2_size as u32 | (x1_size as u32) << BUF_SIZE_SHIFT) });
+        self.regs.ybufcfg.write(|w| unsafe {
+            w.bits((x2_size + x1_size) as u32 | (y_size as u32) << BUF_SIZE_SHIFT)
+        });
+
+        self.regs.param.write(|w| unsafe {
+            w.bits(
+                FUNC_LOAD_X2 << FUNC_SHIFT
+                    | (coeffs.0.len() as u32) << P_SHIFT
+                    | (coeffs.1.len() as u32) << Q_SHIFT
+                    | START,
+            )
+        });
+
+        for c in coeffs.0.iter().chain(coeffs.1.iter()) {
+            self.regs
+                .wdata
+                .write(|w| unsafe { w.bits(*c as u16 as u32) });
+        }
+
+        // START is cleared by hardware when loading is complete.
+        while self.regs.param.read().bits() & START != 0 {}
+    }
+
+    /// Configure an FIR filter, with coefficients b0 to bN-1, ie in the order they multiply the
+    /// newest to oldest input samples. N must be 2 - 127. The output is multiplied by 2^`gain`;
+    /// 0 - 7. `headroom` is the number of extra input, and output samples that can be buffered;
+    /// at least 1. Start filtering with `start()`.
+    pub fn configure_fir(&mut self, coeffs: &[i16], gain: u8, headroom: u8) {
+        let n = coeffs.len();
+        assert!(
+            (2..=127).contains(&n),
+            "FIR filters must have 2 - 127 taps."
+        );
+        assert!(gain <= 7, "FMAC gain must be 0 - 7.");
+        assert!(headroom > 0, "FMAC buffer headroom must be at least 1.");
+
+        self.configure((coeffs, &[]), n + headroom as usize, headroom as usize);
+        self.preload_zeros(FUNC_LOAD_X1, n - 1);
+
+        self.param = FUNC_FIR << FUNC_SHIFT | (n as u32) << P_SHIFT | (gain as u32) << R_SHIFT;
+    }
+
+    /// Configure an IIR filter, with feed-forward coefficients b0 to bN-1 (`b`), and feedback
+    /// coefficients a1 to aM (`a`); a0 is 1. N must be 2 - 64, and M 1 - N-1. The output is
+    /// multiplied by 2^`gain`; 0 - 7. Since coefficients are limited to -1 to 1, scale them
+    /// down, and compensate with `gain`. `headroom` is the number of extra input, and output
+    /// samples that can be buffered; at least 1. Start filtering with `start()`.
+    pub fn configure_iir(&mut self, b: &[i16], a: &[i16], gain: u8, headroom: u8) {
+        let (n, m) = (b.len(), a.len());
+        assert!(
+            (2..=64).contains(&n),
+            "IIR filters must have 2 - 64 feed-forward taps."
+        );
+        assert!(
+            m >= 1 && m < n,
+            "IIR filters must have 1 to N-1 feedback taps."
+        );
+        assert!(gain <= 7, "FMAC gain must be 0 - 7.");
+        assert!(headroom > 0, "FMAC buffer headroom must be at least 1.");
+
+        self.configure((b, a), n + headroom as usize, m + headroom as usize);
+        self.preload_zeros(FUNC_LOAD_X1, n - 1);
+        self.preload_zeros(FUNC_LOAD_Y, m);
+
+        self.param = FUNC_IIR << FUNC_SHIFT
+            | (n as u32) << P_SHIFT
+            | (m as u32) << Q_SHIFT
+            | (gain as u32) << R_SHIFT;
     }
 
-    /// Set up a Finite Impulse Response (FIR) filter.
-    fn run_fir(&mut self, coeffs: &[f32], data: &mut [f32]) {
+    /// Preload a buffer with `count` zeros, so the filter starts from a zero state.
+    fn preload_zeros(&mut self, func: u32, count: usize) {
+        self.regs
+            .param
+            .write(|w| unsafe { w.bits(func << FUNC_SHIFT | (count as u32) << P_SHIFT | START) });
 
+        for _ in 0..count {
+            self.regs.wdata.write(|w| unsafe { w.bits(0) });
+        }
+
+        while self.regs.param.read().bits() & START != 0 {}
     }
 
-    /// Set up an Infinite Impulse Response (IIR) filter.
-    fn run_iir(&mut self, coeffs: &[f32], data: &mut [f32]) {
+    /// Start filtering, as configured by `configure_fir()`, or `configure_iir()`. The filter
+    /// runs until `stop()`, producing an output sample for each input sample. Its initial state
+    /// (previous inputs, and for IIR, outputs) is zeros.
+    pub fn start(&mut self) {
+        assert!(
+            self.param != 0,
+            "Configure a filter before starting the FMAC."
+        );
+        self.regs
+            .param
+            .write(|w| unsafe { w.bits(self.param | START) });
+    }
 
+    /// Stop filtering. (FMAC_PARAM register, START field)
+    pub fn stop(&mut self) {
+        self.regs
+            .param
+            .modify(|r, w| unsafe { w.bits(r.bits() & !START) });
     }
-}
\ No newline at end of file
+
+    /// Write an input sample, blocking until there's space in the X1 buffer.
+    pub fn write(&mut self, sample: i16) {
+        while self.regs.sr.read().bits() & X1FULL != 0 {}
+        self.regs
+            .wdata
+            .write(|w| unsafe { w.bits(sample as u16 as u32) });
+    }
+
+    /// Read an output sample, blocking until one is available in the Y buffer.
+    pub fn read(&mut self) -> i16 {
+        while self.regs.sr.read().bits() & YEMPTY != 0 {}
+        self.regs.rdata.read().bits() as i16
+    }
+
+    /// Filter a block of samples, blocking. `output` must be the same length as `input`.
+    pub fn process(&mut self, input: &[i16], output: &mut [i16]) {
+        assert_eq!(
+            input.len(),
+            output.len(),
+            "FMAC input and output must be the same length."
+        );
+
+        // We read each output as soon as it's available, so the Y buffer can't fill up, and
+        // block further inputs.
+        let mut read_i = 0;
+        for sample in input {
+            self.write(*sample);
+
+            while self.regs.sr.read().bits() & YEMPTY == 0 {
+                output[read_i] = self.regs.rdata.read().bits() as i16;
+                read_i += 1;
+            }
+        }
+
+        for out in &mut output[read_i..] {
+            *out = self.read();
+        }
+    }
+
+    /// If true, saturate accumulator results that overflow the q1.15 output range, instead of
+    /// wrapping. (FMAC_CR register, CLIPEN field)
+    pub fn set_clipping(&mut self, clip: bool) {
+        self.regs.cr.modify(|r, w| unsafe {
+            w.bits(if clip {
+                r.bits() | CLIPEN
+            } else {
+                r.bits() & !CLIPEN
+            })
+        });
+    }
+
+    /// Check the error flags. They're cleared by `reset()`. (FMAC_SR register)
+    pub fn check_errors(&self) -> Result<(), Error> {
+        let sr = self.regs.sr.read().bits();
+
+        if sr & UNFL != 0 {
+            Err(Error::Underflow)
+        } else if sr & OVFL != 0 {
+            Err(Error::Overflow)
+        } else if sr & SAT != 0 {
+            Err(Error::Saturation)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stream input samples from `buf` to the FMAC using DMA. Run this after `start()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn write_dma(
+        &mut self,
+        buf: &[i16],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAWEN) });
+
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+        cfg_dma(
+            ptr as u32,
+            len,
+            &self.regs.wdata as *const _ as u32,
+            dma::Direction::ReadFromMem,
+            channel,
+            channel_cfg,
+            dma_periph,
+        );
+    }
+
+    /// Stream output samples from the FMAC into `buf` using DMA. Run this after `start()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or
+    /// with a circular channel config, until the DMA channel is stopped.
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [i16],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | DMAREN) });
+
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        cfg_dma(
+            ptr as u32,
+            len,
+            &self.regs.rdata as *const _ as u32,
+            dma::Direction::ReadFromPeriph,
+            channel,
+            channel_cfg,
+            dma_periph,
+        );
+    }
+
+    /// Disable DMA requests, after transfers are complete. (FMAC_CR register, DMAREN, and DMAWEN
+    /// fields)
+    pub fn stop_dma(&mut self) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(DMAREN | DMAWEN)) });
+    }
+
+    /// Enable an interrupt. (FMAC_CR register)
+    pub fn enable_interrupt(&mut self, interrupt: FmacInterrupt) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << interrupt as u8) });
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: FmacInterrupt) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << interrupt as u8)) });
+    }
+}
+
+/// Configure a DMA transfer between memory, and an FMAC data register.
+fn cfg_dma(
+    mem_addr: u32,
+    len: usize,
+    periph_addr: u32,
+    direction: dma::Direction,
+    channel: DmaChannel,
+    channel_cfg: ChannelCfg,
+    dma_periph: DmaPeriph,
+) {
+    #[cfg(feature = "h7")]
+    let len = len as u32;
+    #[cfg(not(feature = "h7"))]
+    let len = len as u16;
+
+    match dma_periph {
+        DmaPeriph::Dma1 => {
+            let mut regs = unsafe { &(*DMA1::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S16,
+                dma::DataSize::S16,
+                channel_cfg,
+            );
+        }
+        DmaPeriph::Dma2 => {
+            let mut regs = unsafe { &(*DMA2::ptr()) };
+            dma::cfg_channel(
+                &mut regs,
+                channel,
+                periph_addr,
+                mem_addr,
+                len,
+                direction,
+                dma::DataSize::S16,
+                dma::DataSize::S16,
+                channel_cfg,
+            );
+        }
+    }
+}
@@ -0,0 +1,583 @@
+//! Support for the Flexible Memory Controller (FMC), and the Flexible Static Memory Controller
+//! (FSMC) on F405, F407, F412, and F413. Maps external SRAM, PSRAM, and NOR flash, and on H7, and
+//! F427, F429, F446, and F469, SDRAM, into the address space, where they're accessed like
+//! internal memory. The SRAM type is also used for displays with an 8080-style parallel interface.
+//!
+//! Timings are in nanoseconds, from the memory's datasheet, and are converted to FMC clock cycles
+//! using HCLK. (On H7, this assumes the FMC kernel clock is HCLK3, its default)
+//!
+//! Set the FMC pins (address, data, NE, NOE, NWE, NBL, and SDRAM control) to their alternate
+//! functions, with `OutputSpeed::VeryHigh`.
+//!
+//! Example:
+//! ```rust
+//! let mut fmc = Fmc::new(&clock_cfg);
+//!
+//! // An IS42S16400J 64Mbit SDRAM, on bank 1.
+//! let sdram = fmc.enable_sdram(&SdramConfig {
+//!     timing: SdramTiming {
+//!         load_mode_to_active: 2,
+//!         exit_self_refresh: 70,
+//!         self_refresh: 42,
+//!         row_cycle: 63,
+//!         write_recovery: 14,
+//!         rp_delay: 15,
+//!         rcd_delay: 15,
+//!     },
+//!     ..Default::default()
+//! })?;
+//!
+//! let ram = unsafe { core::slice::from_raw_parts_mut(sdram, SdramConfig::default().size() / 4) };
+//! ram[0] = 0x1234_5678;
+//!
+//! // An ILI9341 display, with an 8080 16-bit interface on NE1, and its D/C line on A16.
+//! let lcd = fmc.enable_nor_psram(NorPsramBank::Bank1, &NorPsramConfig::default())?;
+//! let command = lcd as *mut u16;
+//! // With a 16-bit bus, HADDR is shifted right by 1, so A16 is address bit 17.
+//! let data = unsafe { command.add(1 << 16) };
+//! ```
+//!
+//! On H7, the SDRAM region's default MPU attributes are Device memory, which doesn't allow
+//! unaligned access; configure the MPU to use it as normal memory, eg for buffers.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+use cortex_m::asm;
+
+use crate::{clocks::Clocks, pac::RCC};
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since FMC, and FSMC register, and field names vary between the PACs.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const FMC_BASE: usize = 0x5200_4000;
+        /// RCC_AHB3ENR: FMCEN.
+        const RCC_BIT: u32 = 1 << 12;
+    } else if #[cfg(feature = "l5")] {
+        const FMC_BASE: usize = 0x4402_0000;
+        /// RCC_AHB3ENR: FMCEN.
+        const RCC_BIT: u32 = 1 << 0;
+    } else {
+        const FMC_BASE: usize = 0xa000_0000;
+        /// RCC_AHB3ENR: FMCEN, or FSMCEN.
+        const RCC_BIT: u32 = 1 << 0;
+    }
+}
+
+/// NOR/PSRAM bank 1's address; each of its 4 sub-banks (NE1 - NE4) is 64MB.
+const NOR_PSRAM_ADDR: usize = 0x6000_0000;
+const NOR_PSRAM_BANK_SIZE: usize = 0x0400_0000;
+
+// Register offsets. BCR, and BTR are repeated for each sub-bank, every 8 bytes.
+const BCR1: usize = 0x000;
+const BTR1: usize = 0x004;
+const BWTR1: usize = 0x104;
+
+// BCR bits.
+const MBKEN: u32 = 1 << 0;
+const MUXEN: u32 = 1 << 1;
+const MTYP_SHIFT: u32 = 2;
+const MWID_SHIFT: u32 = 4;
+const FACCEN: u32 = 1 << 6;
+const WREN: u32 = 1 << 12;
+const EXTMOD: u32 = 1 << 14;
+#[cfg(feature = "h7")]
+/// BCR1: Enables the FMC, on H7.
+const FMCEN: u32 = 1 << 31;
+
+cfg_if! {
+    if #[cfg(any(
+        feature = "h7",
+        feature = "f427",
+        feature = "f429",
+        feature = "f446",
+        feature = "f469"
+    ))] {
+        const SDCR1: usize = 0x140;
+        const SDTR1: usize = 0x148;
+        const SDCMR: usize = 0x150;
+        const SDRTR: usize = 0x154;
+        #[cfg(not(feature = "h7"))]
+        const SDSR: usize = 0x158;
+
+        const SDRAM1_ADDR: usize = 0xc000_0000;
+        const SDRAM2_ADDR: usize = 0xd000_0000;
+
+        // SDCMR bits.
+        const CTB2: u32 = 1 << 3;
+        const CTB1: u32 = 1 << 4;
+        const NRFS_SHIFT: u32 = 5;
+        const MRD_SHIFT: u32 = 9;
+        #[cfg(not(feature = "h7"))]
+        /// SDSR: The SDRAM controller is busy.
+        const BUSY: u32 = 1 << 5;
+
+        /// The number of auto-refresh commands sent during initialization.
+        const INIT_REFRESH_COUNT: u32 = 8;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A NOR/PSRAM sub-bank, selected by the NE1 - NE4 pins.
+pub enum NorPsramBank {
+    Bank1 = 0,
+    Bank2 = 1,
+    Bank3 = 2,
+    Bank4 = 3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// NOR/PSRAM memory type. Sets BCR, MTYP field.
+pub enum MemoryType {
+    /// SRAM, or an 8080-style display.
+    Sram = 0b00,
+    Psram = 0b01,
+    /// NOR flash, or OneNAND.
+    Nor = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Memory data bus width. Sets BCR, and SDCR, MWID fields.
+pub enum DataWidth {
+    W8 = 0b00,
+    W16 = 0b01,
+    /// Only available on some packages.
+    W32 = 0b10,
+}
+
+#[derive(Clone, Copy)]
+/// Asynchronous NOR/PSRAM timing, in ns. Uses access mode A, where NOE is asserted after the
+/// address setup time.
+pub struct NorPsramTiming {
+    /// Address setup time; 0 - 15 cycles.
+    pub address_setup: u32,
+    /// Address hold time, for multiplexed memories; 1 - 15 cycles.
+    pub address_hold: u32,
+    /// Data setup time; eg the read access time, or write pulse width. 1 - 255 cycles.
+    pub data_setup: u32,
+    /// Bus turnaround time, between consecutive accesses; 0 - 15 cycles.
+    pub bus_turnaround: u32,
+}
+
+impl Default for NorPsramTiming {
+    /// Conservative timings, suitable for many SRAMs, and displays.
+    fn default() -> Self {
+        Self {
+            address_setup: 10,
+            address_hold: 10,
+            data_setup: 60,
+            bus_turnaround: 10,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+/// NOR/PSRAM configuration.
+pub struct NorPsramConfig {
+    /// Defaults to SRAM.
+    pub memory_type: MemoryType,
+    /// Defaults to 16 bits.
+    pub data_width: DataWidth,
+    /// Multiplex the address, and data lines. Defaults to false.
+    pub address_data_mux: bool,
+    /// Read timing, and write timing if `write_timing` is `None`.
+    pub timing: NorPsramTiming,
+    /// Separate write timing, eg for displays with a shorter write cycle. Defaults to `None`.
+    pub write_timing: Option<NorPsramTiming>,
+    /// Allow writes. Defaults to true.
+    pub write_enable: bool,
+}
+
+impl Default for NorPsramConfig {
+    fn default() -> Self {
+        Self {
+            memory_type: MemoryType::Sram,
+            data_width: DataWidth::W16,
+            address_data_mux: false,
+            timing: Default::default(),
+            write_timing: None,
+            write_enable: true,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+#[derive(Clone, Copy, PartialEq)]
+/// An SDRAM bank, selected by the SDNE0/SDCKE0, or SDNE1/SDCKE1 pins.
+pub enum SdramBank {
+    /// Mapped at 0xc000_0000.
+    One,
+    /// Mapped at 0xd000_0000.
+    Two,
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SDRAM clock, as a division of HCLK. Sets SDCR, SDCLK field.
+pub enum SdClockDiv {
+    Div2 = 0b10,
+    Div3 = 0b11,
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+#[derive(Clone, Copy)]
+/// SDRAM timing, from the memory's datasheet. Times are in ns, except where noted; for timings
+/// given in clock cycles, multiply by the SDRAM clock period. Each must be 1 - 16 cycles.
+pub struct SdramTiming {
+    /// Load mode register to active delay (tMRD), in clock cycles.
+    pub load_mode_to_active: u32,
+    /// Exit self-refresh delay. (tXSR)
+    pub exit_self_refresh: u32,
+    /// Minimum self-refresh period; the row active time. (tRAS)
+    pub self_refresh: u32,
+    /// Row cycle delay. (tRC)
+    pub row_cycle: u32,
+    /// Write recovery time. (tWR, or tDPL)
+    pub write_recovery: u32,
+    /// Row precharge delay. (tRP)
+    pub rp_delay: u32,
+    /// Row to column delay. (tRCD)
+    pub rcd_delay: u32,
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+#[derive(Clone, Copy)]
+/// SDRAM configuration. The default is a 16-bit, 4-bank SDRAM with 8 column, and 12 row address
+/// bits; eg a 64Mbit IS42S16400J.
+pub struct SdramConfig {
+    pub bank: SdramBank,
+    /// Column address bits; 8 - 11.
+    pub column_bits: u8,
+    /// Row address bits; 11 - 13.
+    pub row_bits: u8,
+    pub data_width: DataWidth,
+    /// Four internal banks if true, or 2 if false.
+    pub four_banks: bool,
+    /// CAS latency, in clock cycles; 1 - 3.
+    pub cas_latency: u8,
+    /// Defaults to HCLK / 2.
+    pub clock_div: SdClockDiv,
+    /// Read bursts, anticipating the next read. Defaults to true.
+    pub read_burst: bool,
+    /// Delay, in HCLK cycles, for reading data after the CAS latency; 0 - 2. Defaults to 0.
+    pub read_pipe_delay: u8,
+    pub timing: SdramTiming,
+    /// The time in which all rows must be refreshed, in ms. Defaults to 64ms.
+    pub refresh_period: u32,
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+impl Default for SdramConfig {
+    fn default() -> Self {
+        Self {
+            bank: SdramBank::One,
+            column_bits: 8,
+            row_bits: 12,
+            data_width: DataWidth::W16,
+            four_banks: true,
+            cas_latency: 3,
+            clock_div: SdClockDiv::Div2,
+            read_burst: true,
+            read_pipe_delay: 0,
+            timing: SdramTiming {
+                load_mode_to_active: 2,
+                exit_self_refresh: 70,
+                self_refresh: 42,
+                row_cycle: 70,
+                write_recovery: 20,
+                rp_delay: 20,
+                rcd_delay: 20,
+            },
+            refresh_period: 64,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "h7",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469"
+))]
+impl SdramConfig {
+    /// The SDRAM's size, in bytes.
+    pub fn size(&self) -> usize {
+        let width_bytes = match self.data_width {
+            DataWidth::W8 => 1,
+            DataWidth::W16 => 2,
+            DataWidth::W32 => 4,
+        };
+        let banks = if self.four_banks { 4 } else { 2 };
+
+        (1 << (self.column_bits + self.row_bits)) * banks * width_bytes
+    }
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// FMC error
+pub enum Error {
+    /// A timing, or setting is out of range at the FMC clock frequency.
+    InvalidConfig,
+}
+
+/// Convert a time in ns to clock cycles, rounding up, and check its range.
+fn ns_to_cycles(ns: u32, freq: u32, min: u32, max: u32) -> Result<u32, Error> {
+    let cycles = (ns as u64 * freq as u64).div_ceil(1_000_000_000) as u32;
+    let cycles = cycles.max(min);
+
+    if cycles > max {
+        return Err(Error::InvalidConfig);
+    }
+    Ok(cycles)
+}
+
+/// Represents the FMC, or FSMC peripheral.
+pub struct Fmc {
+    /// HCLK, in Hz.
+    hclk: u32,
+}
+
+impl Fmc {
+    /// Enable the FMC's RCC peripheral clock. On H7, also enable the FMC.
+    pub fn new(clocks: &Clocks) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb3enr
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+        });
+
+        let result = Self {
+            hclk: clocks.hclk(),
+        };
+
+        #[cfg(feature = "h7")]
+        result.write(BCR1, result.read(BCR1) | FMCEN);
+
+        result
+    }
+
+    /// Configure, and enable a NOR/PSRAM sub-bank for asynchronous access. Returns a pointer
+    /// to the start of its 64MB region.
+    pub fn enable_nor_psram(
+        &mut self,
+        bank: NorPsramBank,
+        cfg: &NorPsramConfig,
+    ) -> Result<*mut u8, Error> {
+        let btr = self.nor_psram_timing(&cfg.timing)?;
+        let bwtr = match cfg.write_timing {
+            Some(t) => Some(self.nor_psram_timing(&t)?),
+            None => None,
+        };
+
+        let offset = bank as usize * 8;
+
+        // Keep the reserved bits at their reset values, and BCR1's FMCEN bit on H7.
+        let mut bcr = self.read(BCR1 + offset) & !0xffff;
+        bcr |=
+            MBKEN | (cfg.memory_type as u32) << MTYP_SHIFT | (cfg.data_width as u32) << MWID_SHIFT;
+        if cfg.address_data_mux {
+            bcr |= MUXEN;
+        }
+        if cfg.memory_type == MemoryType::Nor {
+            bcr |= FACCEN;
+        }
+        if cfg.write_enable {
+            bcr |= WREN;
+        }
+        if bwtr.is_some() {
+            bcr |= EXTMOD;
+        }
+
+        self.write(BTR1 + offset, btr);
+        if let Some(bwtr) = bwtr {
+            self.write(BWTR1 + offset, bwtr);
+        }
+        self.write(BCR1 + offset, bcr);
+
+        Ok((NOR_PSRAM_ADDR + bank as usize * NOR_PSRAM_BANK_SIZE) as *mut u8)
+    }
+
+    /// Disable a NOR/PSRAM sub-bank.
+    pub fn disable_nor_psram(&mut self, bank: NorPsramBank) {
+        let offset = bank as usize * 8;
+        self.write(BCR1 + offset, self.read(BCR1 + offset) & !MBKEN);
+    }
+
+    /// Calculate BTR, or BWTR register values, with access mode A.
+    fn nor_psram_timing(&self, timing: &NorPsramTiming) -> Result<u32, Error> {
+        let addset = ns_to_cycles(timing.address_setup, self.hclk, 0, 15)?;
+        let addhld = ns_to_cycles(timing.address_hold, self.hclk, 1, 15)?;
+        let datast = ns_to_cycles(timing.data_setup, self.hclk, 1, 255)?;
+        let busturn = ns_to_cycles(timing.bus_turnaround, self.hclk, 0, 15)?;
+
+        Ok(addset | addhld << 4 | datast << 8 | busturn << 16)
+    }
+
+    #[cfg(any(
+        feature = "h7",
+        feature = "f427",
+        feature = "f429",
+        feature = "f446",
+        feature = "f469"
+    ))]
+    /// Configure an SDRAM bank, and run its initialization sequence: Clock enable, precharge all,
+    /// auto-refresh, and load mode register. Returns a pointer to the start of its region; its
+    /// size is `cfg.size()`. See RM0090 (F4), section 37.7.3: SDRAM controller functional
+    /// description.
+    pub fn enable_sdram(&mut self, cfg: &SdramConfig) -> Result<*mut u32, Error> {
+        if !(8..=11).contains(&cfg.column_bits)
+            || !(11..=13).contains(&cfg.row_bits)
+            || !(1..=3).contains(&cfg.cas_latency)
+            || cfg.read_pipe_delay > 2
+        {
+            return Err(Error::InvalidConfig);
+        }
+
+        let sdclk = self.hclk / cfg.clock_div as u32;
+        let t = &cfg.timing;
+
+        if !(1..=16).contains(&t.load_mode_to_active) {
+            return Err(Error::InvalidConfig);
+        }
+        // Fields are the value - 1.
+        let sdtr = (t.load_mode_to_active - 1)
+            | (ns_to_cycles(t.exit_self_refresh, sdclk, 1, 16)? - 1) << 4
+            | (ns_to_cycles(t.self_refresh, sdclk, 1, 16)? - 1) << 8
+            | (ns_to_cycles(t.row_cycle, sdclk, 1, 16)? - 1) << 12
+            | (ns_to_cycles(t.write_recovery, sdclk, 1, 16)? - 1) << 16
+            | (ns_to_cycles(t.rp_delay, sdclk, 1, 16)? - 1) << 20
+            | (ns_to_cycles(t.rcd_delay, sdclk, 1, 16)? - 1) << 24;
+
+        // NC, NR, MWID, NB, CAS, SDCLK, RBURST, and RPIPE.
+        let mut sdcr = (cfg.column_bits as u32 - 8)
+            | (cfg.row_bits as u32 - 11) << 2
+            | (cfg.data_width as u32) << 4
+            | (cfg.cas_latency as u32) << 7
+            | (cfg.clock_div as u32) << 10
+            | (cfg.read_pipe_delay as u32) << 13;
+        if cfg.four_banks {
+            sdcr |= 1 << 6;
+        }
+        if cfg.read_burst {
+            sdcr |= 1 << 12;
+        }
+
+        // SDCLK, RBURST, and RPIPE are only in SDCR1, and TRC, and TRP only in SDTR1, so bank 2
+        // sets these there too.
+        let (target, addr) = match cfg.bank {
+            SdramBank::One => {
+                self.write(SDCR1, sdcr);
+                self.write(SDTR1, sdtr);
+                (CTB1, SDRAM1_ADDR)
+            }
+            SdramBank::Two => {
+                const SDCR1_MASK: u32 = 0b1_1111 << 10;
+                const SDTR1_MASK: u32 = 0xf << 20 | 0xf << 12;
+
+                self.write(
+                    SDCR1,
+                    (self.read(SDCR1) & !SDCR1_MASK) | (sdcr & SDCR1_MASK),
+                );
+                self.write(SDCR1 + 4, sdcr);
+                self.write(
+                    SDTR1,
+                    (self.read(SDTR1) & !SDTR1_MASK) | (sdtr & SDTR1_MASK),
+                );
+                self.write(SDTR1 + 4, sdtr);
+                (CTB2, SDRAM2_ADDR)
+            }
+        };
+
+        // Clock configuration enable, then wait at least 100µs, for the SDRAM's power up delay.
+        self.sdram_command(0b001, target, 0);
+        asm::delay(self.hclk / 10_000);
+
+        // Precharge all.
+        self.sdram_command(0b010, target, 0);
+        // Auto-refresh.
+        self.sdram_command(0b011, target | (INIT_REFRESH_COUNT - 1) << NRFS_SHIFT, 0);
+        // Load mode register: Burst length 1, sequential, CAS latency, and single location write
+        // bursts.
+        let mode = (cfg.cas_latency as u32) << 4 | 1 << 9;
+        self.sdram_command(0b100, target, mode);
+
+        // Refresh rate, in SDRAM clock cycles per row, with a 20-cycle margin.
+        let rows = 1_u64 << cfg.row_bits;
+        let count = cfg.refresh_period as u64 * sdclk as u64 / 1_000 / rows;
+        // COUNT must be at least 41, and fit in 13 bits.
+        if count < 41 + 20 || count - 20 > 0x1fff {
+            return Err(Error::InvalidConfig);
+        }
+        self.write(SDRTR, (count as u32 - 20) << 1);
+
+        Ok(addr as *mut u32)
+    }
+
+    #[cfg(any(
+        feature = "h7",
+        feature = "f427",
+        feature = "f429",
+        feature = "f446",
+        feature = "f469"
+    ))]
+    /// Send an SDRAM command, and wait for it to be accepted.
+    fn sdram_command(&mut self, mode: u32, target: u32, mode_register: u32) {
+        self.write(SDCMR, mode | target | mode_register << MRD_SHIFT);
+
+        #[cfg(not(feature = "h7"))]
+        while self.read(SDSR) & BUSY != 0 {}
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((FMC_BASE + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((FMC_BASE + offset) as *mut u32, value) }
+    }
+}
@@ -6,18 +6,53 @@
 // todo to change with our current model. Note sure if PAC, or MCU limitation
 // todo: WL is also missing interrupt support.
 
-#[cfg(feature = "embedded-hal")]
+#[cfg(any(feature = "embedded-hal", feature = "embedded-hal-1"))]
 use core::convert::Infallible;
 
-use cortex_m::interrupt::free;
+use core::ops::{Deref, DerefMut};
+
+#[cfg(any(feature = "pin_registry", feature = "exti_dispatch"))]
+use core::cell::Cell;
+
+use cortex_m::{asm, interrupt::free};
+
+#[cfg(any(feature = "pin_registry", feature = "exti_dispatch"))]
+use cortex_m::interrupt::Mutex;
+
+#[cfg(feature = "exti_dispatch")]
+use cortex_m::peripheral::NVIC;
+
+#[cfg(feature = "exti_dispatch")]
+use crate::pac::interrupt;
+
+#[cfg(all(
+    feature = "exti_dispatch",
+    not(any(
+        feature = "f3rt",
+        feature = "f4rt",
+        feature = "l4rt",
+        feature = "l5rt",
+        feature = "g0rt",
+        feature = "g4rt",
+        feature = "h7rt",
+        feature = "wbrt"
+    ))
+))]
+compile_error!("The `exti_dispatch` feature requires the `rt` feature for your family, eg `g4rt`.");
+
+#[cfg(all(feature = "exti_dispatch", any(feature = "f373", feature = "wl")))]
+compile_error!("The `exti_dispatch` feature isn't supported on F373, or WL.");
 
-use crate::pac::{self, EXTI, RCC};
+use crate::{
+    clocks::Clocks,
+    pac::{self, EXTI, RCC},
+};
 
 #[cfg(not(feature = "h7"))]
 use crate::util::rcc_en_reset;
 
 #[cfg(feature = "embedded-hal")]
-use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
 
 // #[cfg(not(any(
 //     // feature = "g0",
@@ -85,7 +120,9 @@ pub enum OutputType {
 #[repr(u8)]
 /// Values for `GPIOx_OSPEEDR`. This configures I/O output speed. See the user manual
 /// for your MCU for what speeds these are. Note that Fast speed (0b10) is not
-/// available on all STM32 families.
+/// available on all STM32 families. High-speed buses like QSPI, OCTOSPI, FMC, and SDMMC
+/// generally need `VeryHigh`. Higher speeds have sharper edges, so prefer the lowest speed
+/// that works, for less EMI and ringing.
 pub enum OutputSpeed {
     Low = 0b00,
     Medium = 0b01,
@@ -129,7 +166,7 @@ pub enum ResetState {
 }
 
 // todo: If you get rid of Port struct, rename this enum Port
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 /// GPIO port letter
 pub enum Port {
     A,
@@ -187,7 +224,112 @@ pub enum Port {
     H,
 }
 
+cfg_if! {
+    if #[cfg(feature = "pkg48")] {
+        /// Ports bonded out on the package, as a bit mask indexed by `Port::cr_val()`. 48-pin
+        /// packages have ports A, B, part of C, and the HSE pins on port F or H.
+        const PORTS_AVAILABLE: u16 = 0b1010_0111;
+    } else if #[cfg(feature = "pkg64")] {
+        /// Ports bonded out on the package, as a bit mask indexed by `Port::cr_val()`. 64-pin
+        /// packages add PD2.
+        const PORTS_AVAILABLE: u16 = 0b1010_1111;
+    } else if #[cfg(feature = "pkg100")] {
+        /// Ports bonded out on the package, as a bit mask indexed by `Port::cr_val()`. 100-pin
+        /// packages have ports A - E, and the HSE pins on port F or H.
+        const PORTS_AVAILABLE: u16 = 0b1011_1111;
+    } else {
+        /// Ports bonded out on the package. Without a package feature, we allow all ports the
+        /// MCU family has.
+        const PORTS_AVAILABLE: u16 = 0xffff;
+    }
+}
+
+/// GPIO error
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// The pin number is above 15.
+    InvalidPin,
+    /// The port isn't bonded out on the package selected with a `pkg` feature.
+    PortNotAvailable,
+    /// A pin didn't reach the expected state in time.
+    Timeout,
+    /// The pin is already claimed by another `Pin`. Only checked with the `pin_registry` feature.
+    PinClaimed,
+    /// The `LCKR` key sequence didn't lock the configuration; eg the port was already locked.
+    LockFailed,
+}
+
 impl Port {
+    /// Returns true if the port is bonded out on the package selected with a `pkg48`, `pkg64`, or
+    /// `pkg100` feature. Always true if no package feature is selected. Note that on smaller
+    /// packages, some pins of available ports may still not be bonded out; eg port C on 48-pin
+    /// parts. Check your MCU's datasheet.
+    pub fn available(&self) -> bool {
+        PORTS_AVAILABLE & (1 << self.cr_val()) != 0
+    }
+
+    /// The port with a given letter, eg `'B'`. Panics if the MCU doesn't have this port; at
+    /// compile time, if used in a const context.
+    pub const fn from_letter(letter: char) -> Self {
+        match letter {
+            'A' => Self::A,
+            'B' => Self::B,
+            #[cfg(not(feature = "wl"))]
+            'C' => Self::C,
+            #[cfg(not(any(feature = "f410", feature = "wl")))]
+            'D' => Self::D,
+            #[cfg(not(any(
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f410",
+                feature = "g0",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            'E' => Self::E,
+            #[cfg(not(any(
+                feature = "f401",
+                feature = "f410",
+                feature = "f411",
+                feature = "l4x1",
+                feature = "l4x2",
+                feature = "l412",
+                feature = "l4x3",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            'F' => Self::F,
+            #[cfg(not(any(
+                feature = "f373",
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f401",
+                feature = "f410",
+                feature = "f411",
+                feature = "l4",
+                feature = "g0",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            'G' => Self::G,
+            #[cfg(not(any(
+                feature = "f373",
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f410",
+                feature = "l4",
+                feature = "g0",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            'H' => Self::H,
+            _ => panic!("This MCU doesn't have a GPIO port with this letter."),
+        }
+    }
+
     /// See F303 RM section 12.1.3: each reg has an associated value
     fn cr_val(&self) -> u8 {
         match self {
@@ -452,6 +594,116 @@ macro_rules! set_exti_g0 {
     }
 }
 
+#[cfg(feature = "pin_registry")]
+/// Pins claimed by `Pin::new()`, as a bit mask per port, indexed by `Port::cr_val()`.
+static CLAIMED_PINS: Mutex<Cell<[u16; 8]>> = Mutex::new(Cell::new([0; 8]));
+
+#[cfg(feature = "pin_registry")]
+/// Claim a pin in the registry. Returns false if it's already claimed.
+fn claim(port: Port, pin: u8) -> bool {
+    free(|cs| {
+        let cell = CLAIMED_PINS.borrow(cs);
+        let mut claimed = cell.get();
+        let mask = &mut claimed[port.cr_val() as usize];
+
+        if *mask & (1 << pin) != 0 {
+            return false;
+        }
+        *mask |= 1 << pin;
+        cell.set(claimed);
+        true
+    })
+}
+
+#[cfg(feature = "pin_registry")]
+/// Returns true if a pin is claimed by a `Pin`. Only available with the `pin_registry` feature.
+pub fn claimed(port: Port, pin: u8) -> bool {
+    free(|cs| CLAIMED_PINS.borrow(cs).get()[port.cr_val() as usize] & (1 << pin) != 0)
+}
+
+#[cfg(feature = "pin_registry")]
+/// Release a pin's claim in the registry; eg one claimed by `configure_table()`. Prefer
+/// `Pin::release()` for pins with a `Pin` struct. Only available with the `pin_registry` feature.
+pub fn release_claim(port: Port, pin: u8) {
+    free(|cs| {
+        let cell = CLAIMED_PINS.borrow(cs);
+        let mut claimed = cell.get();
+        claimed[port.cr_val() as usize] &= !(1 << pin);
+        cell.set(claimed);
+    });
+}
+
+#[cfg(not(feature = "g0"))]
+/// Pins that are assigned to JTAG (alternate function 0) at reset, and aren't used by SWD: PA15
+/// (JTDI), PB3 (JTDO/SWO), and PB4 (NJTRST). These can be used as GPIO by setting their mode; no
+/// remapping is required. SWD pins (PA13, and PA14) are handled by the `debug_pins` module.
+pub const JTAG_PINS: [(Port, u8); 3] = [(Port::A, 15), (Port::B, 3), (Port::B, 4)];
+
+#[derive(Copy, Clone)]
+/// A pin's full configuration, applied in one step by `Pin::new_with_cfg()`, or
+/// `Pin::apply_cfg()`. Build with `PinConfig::new()`, and the setter methods; unset fields keep
+/// their reset values: Floating, push-pull, low speed, and the output register unchanged.
+///
+/// Example:
+/// ```rust
+/// let mut cs = Pin::new_with_cfg(
+///     Port::A,
+///     4,
+///     PinConfig::new(PinMode::Output)
+///         .initial_state(PinState::High)
+///         .speed(OutputSpeed::High),
+/// );
+/// let sda = Pin::new_with_cfg(
+///     Port::B,
+///     7,
+///     PinConfig::new(PinMode::Alt(4))
+///         .output_type(OutputType::OpenDrain)
+///         .pull(Pull::Up),
+/// );
+/// ```
+pub struct PinConfig {
+    /// The mode, including the alternate function number, if applicable.
+    pub mode: PinMode,
+    pub pull: Pull,
+    pub output_type: OutputType,
+    pub speed: OutputSpeed,
+    /// The output level to set before the pin becomes an output. `None` leaves the output
+    /// register unchanged.
+    pub initial_state: Option<PinState>,
+}
+
+impl PinConfig {
+    pub const fn new(mode: PinMode) -> Self {
+        Self {
+            mode,
+            pull: Pull::Floating,
+            output_type: OutputType::PushPull,
+            speed: OutputSpeed::Low,
+            initial_state: None,
+        }
+    }
+
+    pub const fn pull(mut self, pull: Pull) -> Self {
+        self.pull = pull;
+        self
+    }
+
+    pub const fn output_type(mut self, output_type: OutputType) -> Self {
+        self.output_type = output_type;
+        self
+    }
+
+    pub const fn speed(mut self, speed: OutputSpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub const fn initial_state(mut self, state: PinState) -> Self {
+        self.initial_state = Some(state);
+        self
+    }
+}
+
 /// Represents a single GPIO pin. Allows configuration, and reading/setting state.
 pub struct Pin {
     /// The GPIO Port letter. Eg A, B, C.
@@ -471,308 +723,122 @@ impl Pin {
     /// Create a new pin, with a specific mode. Enables the RCC peripheral clock to the port,
     /// if not already enabled. Example: `let pa1 = Pin::new(Port::A, 1, PinMode::Output);` Leaves settings
     /// other than mode and alternate function (if applicable) at their hardware defaults.
+    /// Panics if the pin doesn't exist; see `try_new()`.
+    ///
+    /// With the `pin_registry` feature, this also panics if another `Pin` has claimed this pin;
+    /// eg if a UART, and a timer are both set up on PA9. Reconfigure an existing `Pin` with
+    /// `mode()`, or free it with `release()` instead.
     pub fn new(port: Port, pin: u8, mode: PinMode) -> Self {
+        let mut result = Self::take(port, pin);
+        result.mode(mode);
+
+        // The JTAG-only pins have pull resistors enabled at reset, for the debug port. Release
+        // them when the pin is used for something else, so it behaves like other pins.
+        #[cfg(not(feature = "g0"))]
+        if JTAG_PINS.contains(&(port, pin)) && !matches!(mode, PinMode::Alt(0)) {
+            result.pull(Pull::Floating);
+        }
+
+        result
+    }
+
+    /// Create a new pin, as with `new()`, but return an error, instead of panicking, if the pin
+    /// number is invalid, or if the port isn't available on the package selected with a `pkg`
+    /// feature. Nothing is written to the port's registers in this case. With the `pin_registry`
+    /// feature, returns `Error::PinClaimed` if another `Pin` has claimed it.
+    pub fn try_new(port: Port, pin: u8, mode: PinMode) -> Result<Self, Error> {
+        if pin > 15 {
+            return Err(Error::InvalidPin);
+        }
+        if !port.available() {
+            return Err(Error::PortNotAvailable);
+        }
+        #[cfg(feature = "pin_registry")]
+        if claimed(port, pin) {
+            return Err(Error::PinClaimed);
+        }
+
+        Ok(Self::new(port, pin, mode))
+    }
+
+    /// Create a new pin, and apply a full configuration, as with `apply_cfg()`. Panics in the
+    /// same cases as `new()`. Unlike setting up a pin with `new()`, then other setters, the pin
+    /// doesn't pass through intermediate states, eg briefly driving the wrong level, or being in
+    /// alternate function mode with the wrong function selected.
+    pub fn new_with_cfg(port: Port, pin: u8, cfg: PinConfig) -> Self {
+        let mut result = Self::take(port, pin);
+        result.apply_cfg(&cfg);
+        result
+    }
+
+    /// Check the pin, claim it if using the `pin_registry` feature, and enable its port's clock.
+    fn take(port: Port, pin: u8) -> Self {
         assert!(pin <= 15, "Pin must be 0 - 15.");
+        assert!(port.available(), "Port not available on this package.");
+
+        #[cfg(feature = "pin_registry")]
+        if !claim(port, pin) {
+            panic!(
+                "P{}{} is already claimed by another `Pin`.",
+                (b'A' + port.cr_val()) as char,
+                pin
+            );
+        }
 
-        free(|_| {
-            let rcc = unsafe { &(*RCC::ptr()) };
+        enable_port_clock(port);
 
-            match port {
-                Port::A => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopaen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopa, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpioaen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpioaen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpioarst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpioarst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpioaen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpioa, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopaen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopaen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.ioparst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.ioparst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpioaen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioa, rcc);
-                            }
-                        }
-                    }
-                }
-                Port::B => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopben().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopb, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpioben().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpioben().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiobrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiobrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpioben().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpiob, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopben().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopben().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopbrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopbrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpioben().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpiob, rcc);
-                            }
-                        }
-                    }
-                }
-                #[cfg(not(feature = "wl"))]
-                Port::C => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopcen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopc, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpiocen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpiocen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiocrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiocrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpiocen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpioc, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopcen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopcen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopcrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopcrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpiocen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioc, rcc);
-                            }
-                        }
-                    }
-                }
-                #[cfg(not(any(feature = "f410", feature = "wl")))]
-                Port::D => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopden().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopd, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpioden().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpioden().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiodrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiodrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpioden().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpiod, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopden().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopden().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopdrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopdrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpioden().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpiod, rcc);
-                            }
-                        }
-                    }
-                }
-                #[cfg(not(any(
-                    feature = "f301",
-                    feature = "f3x4",
-                    feature = "f410",
-                    feature = "g0",
-                    feature = "wb",
-                    feature = "wl"
-                )))]
-                Port::E => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopeen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iope, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpioeen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpioeen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpioerst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpioerst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpioeen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpioe, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopeen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopeen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.ioperst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.ioperst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpioeen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioe, rcc);
-                            }
-                        }
-                    }
-                }
-                #[cfg(not(any(
-                    feature = "f401",
-                    feature = "f410",
-                    feature = "f411",
-                    feature = "l4x1",
-                    feature = "l4x2",
-                    feature = "l412",
-                    feature = "l4x3",
-                    feature = "wb",
-                    feature = "wl"
-                )))]
-                Port::F => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iopfen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopf, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpiofen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpiofen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiofrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiofrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpiofen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpiof, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iopfen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iopfen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopfrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iopfrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpiofen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpiof, rcc);
-                            }
-                        }
-                    }
-                }
-                #[cfg(not(any(
-                    feature = "f373",
-                    feature = "f301",
-                    feature = "f3x4",
-                    feature = "f401",
-                    feature = "f410",
-                    feature = "f411",
-                    feature = "l4",
-                    feature = "g0",
-                    feature = "g4",
-                    feature = "wb",
-                    feature = "wl"
-                )))]
-                Port::G => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iophen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, iopg, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpiohen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpiohen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiohrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiohrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpiohen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpioh, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iophen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iophen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iophrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iophrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpiohen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioa, rcc);
-                            }
-                        }
-                    }
-                    #[cfg(feature = "l5")]
-                    // also for RM0351 L4 variants, which we don't currently support
-                    // L5 RM: "[The IOSV bit] is used to validate the VDDIO2 supply for electrical and logical isolation purpose.
-                    // Setting this bit is mandatory to use PG[15:2]."
-                    {
-                        unsafe {
-                            (*crate::pac::PWR::ptr())
-                                .cr2
-                                .modify(|_, w| w.iosv().set_bit());
-                        }
-                    }
-                }
-                #[cfg(not(any(
-                    feature = "f373",
-                    feature = "f301",
-                    feature = "f3x4",
-                    feature = "f410",
-                    feature = "l4",
-                    feature = "g0",
-                    feature = "g4",
-                    feature = "wb",
-                    feature = "wl"
-                )))]
-                Port::H => {
-                    cfg_if! {
-                        if #[cfg(feature = "f3")] {
-                            if rcc.ahbenr.read().iophen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, ioph, rcc);
-                            }
-                        } else if #[cfg(feature = "h7")] {
-                            if rcc.ahb4enr.read().gpiohen().bit_is_clear() {
-                                rcc.ahb4enr.modify(|_, w| w.gpiohen().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiohrst().set_bit());
-                                rcc.ahb4rstr.modify(|_, w| w.gpiohrst().clear_bit());
-                            }
-                        } else if #[cfg(feature = "f4")] {
-                            if rcc.ahb1enr.read().gpiohen().bit_is_clear() {
-                                rcc_en_reset!(ahb1, gpioh, rcc);
-                            }
-                        } else if #[cfg(feature = "g0")] {
-                            if rcc.iopenr.read().iophen().bit_is_clear() {
-                                rcc.iopenr.modify(|_, w| w.iophen().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iophrst().set_bit());
-                                rcc.ioprstr.modify(|_, w| w.iophrst().clear_bit());
-                            }
-                        } else { // L4, L5, G4
-                            if rcc.ahb2enr.read().gpiohen().bit_is_clear() {
-                                rcc_en_reset!(ahb2, gpioa, rcc);
-                            }
-                        }
-                    }
+        Self { port, pin }
+    }
+
+    /// Apply a full configuration in one critical section. The output level, output type,
+    /// speed, pull, and alternate function are set before the mode, so the pin only changes
+    /// function once it's fully configured.
+    pub fn apply_cfg(&mut self, cfg: &PinConfig) {
+        let pin = self.pin as u32;
+        let regs = unsafe { &*self.regs() };
+
+        // We use raw bits, as in `configure_table()`, since alternate function field names vary
+        // by PAC.
+        free(|_| unsafe {
+            if let Some(state) = cfg.initial_state {
+                let offset = match state {
+                    PinState::Low => 16,
+                    PinState::High => 0,
+                };
+                regs.bsrr.write(|w| w.bits(1 << (offset + pin)));
+            }
+
+            regs.otyper
+                .modify(|r, w| w.bits((r.bits() & !(1 << pin)) | (cfg.output_type as u32) << pin));
+            regs.ospeedr.modify(|r, w| {
+                w.bits((r.bits() & !(0b11 << (pin * 2))) | (cfg.speed as u32) << (pin * 2))
+            });
+            regs.pupdr.modify(|r, w| {
+                w.bits((r.bits() & !(0b11 << (pin * 2))) | (cfg.pull as u32) << (pin * 2))
+            });
+
+            if let PinMode::Alt(alt) = cfg.mode {
+                assert!(alt <= 15, "Alt function must be 0 to 15.");
+                let shift = (pin % 8) * 4;
+                let val = |bits: u32| (bits & !(0b1111 << shift)) | (alt as u32) << shift;
+                if pin < 8 {
+                    regs.afrl.modify(|r, w| w.bits(val(r.bits())));
+                } else {
+                    regs.afrh.modify(|r, w| w.bits(val(r.bits())));
                 }
             }
-        });
 
-        let mut result = Self { port, pin };
-        result.mode(mode);
+            regs.moder.modify(|r, w| {
+                w.bits((r.bits() & !(0b11 << (pin * 2))) | (cfg.mode.val() as u32) << (pin * 2))
+            });
+        });
+    }
 
-        result
+    /// Release this pin's claim in the registry, so another `Pin` can be created for it. This
+    /// doesn't change its configuration. Without the `pin_registry` feature, this just drops it.
+    pub fn release(self) {
+        #[cfg(feature = "pin_registry")]
+        release_claim(self.port, self.pin);
     }
 
     /// Set pin mode. Eg, Output, Input, Analog, or Alt. Sets the `MODER` register.
@@ -833,7 +899,8 @@ impl Pin {
 
     // TODO: F373 doesn't have LOCKR on ports C, E, F. You can impl for others
     #[cfg(not(feature = "f373"))]
-    /// Lock or unlock a port configuration. Sets the `LCKR` register.
+    /// Set or clear the pin's bit in the `LCKR` register. This doesn't lock anything on its own;
+    /// the lock takes effect on the key sequence. Use `lock()`, which does both.
     pub fn cfg_lock(&mut self, value: CfgLock) {
         set_field!(
             self.regs(),
@@ -846,6 +913,20 @@ impl Pin {
         );
     }
 
+    #[cfg(not(feature = "f373"))]
+    /// Lock the pin's configuration: its `MODER`, `OTYPER`, `OSPEEDR`, `PUPDR`, `AFRL`, and
+    /// `AFRH` fields, until the next reset. Use this for safety-critical pins, so a firmware fault
+    /// can't reconfigure them. The output level can still be changed. See `lock_port()`.
+    pub fn lock(&mut self) -> Result<(), Error> {
+        lock_port(self.port, 1 << self.pin)
+    }
+
+    #[cfg(not(feature = "f373"))]
+    /// Returns true if the pin's configuration is locked.
+    pub fn is_locked(&self) -> bool {
+        is_locked(self.port, self.pin)
+    }
+
     /// Read the input data register. Eg determine if the pin is high or low. See also `is_high()`
     /// and `is_low()`. Reads from the `IDR` register.
     pub fn get_state(&mut self) -> PinState {
@@ -938,6 +1019,20 @@ impl Pin {
         }
     }
 
+    #[cfg(feature = "l4x6")]
+    /// Disconnect the pin from the ADC, after `connect_to_adc()`. Clears the `ASCR` register.
+    pub fn disconnect_from_adc(&mut self) {
+        set_field!(
+            self.regs(),
+            self.pin,
+            ascr,
+            asc,
+            bit,
+            false,
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        );
+    }
+
     #[cfg(feature = "l4x6")]
     /// For the ADC, DAC, OPAMP and COMP, configure the desired I/O in analog mode
     /// in the GPIOx_MODER register and configure the required function in the ADC,
@@ -980,6 +1075,108 @@ impl Pin {
     pub fn set_low(&mut self) {
         self.set_state(PinState::Low);
     }
+
+    /// Check if the pin is being driven high; ie the state it's set to, vs the voltage read by
+    /// `is_high()`. These differ eg for an open-drain output that's released, but held low
+    /// externally. Reads from the `ODR` register.
+    pub fn is_set_high(&self) -> bool {
+        let odr = unsafe { (*self.regs()).odr.read().bits() };
+        odr & (1 << self.pin) != 0
+    }
+
+    /// Check if the pin is being driven low. Reads from the `ODR` register.
+    pub fn is_set_low(&self) -> bool {
+        !self.is_set_high()
+    }
+
+    /// Toggle the pin's output voltage, based on the state it's set to. Reads from the `ODR`
+    /// register, and sets the `BSRR` register.
+    pub fn toggle(&mut self) {
+        toggle_mask(self.port, 1 << self.pin);
+    }
+
+    /// Configure the pin as an open-drain input/output for a single-wire, bidirectional protocol,
+    /// eg SDI-12, or 1-Wire. `pull` sets the internal pull resistor; use `Pull::Up` for
+    /// open-drain buses without an external pull-up. `bit_rate` is in bits per second, and is used
+    /// by `OpenDrainIo`'s timing functions. The line is released (high) when this returns.
+    pub fn into_open_drain_io(mut self, pull: Pull, bit_rate: u32, clocks: &Clocks) -> OpenDrainIo {
+        self.set_high();
+        self.output_type(OutputType::OpenDrain);
+        self.pull(pull);
+        self.mode(PinMode::Output);
+
+        let bit_cycles = clocks.sysclk() / bit_rate;
+
+        OpenDrainIo {
+            bsrr_release: 1 << self.pin,
+            bsrr_drive: 1 << (self.pin + 16),
+            moder_mask: 0b11 << (self.pin * 2),
+            moder_output: (PinMode::Output.val() as u32) << (self.pin * 2),
+            bit_cycles: if bit_cycles == 0 { 1 } else { bit_cycles },
+            pin: self,
+        }
+    }
+}
+
+/// A GPIO pin whose port, and number are part of its type, eg `TypedPin<'B', 5>` for PB5. Use
+/// these in board-support structs to document, and check at compile time which pin each field
+/// is; an invalid port, or number fails to compile. Derefs to `Pin`, so its methods are all
+/// available. Convert to a `Pin` with `degrade()`, or `into()` to store pins from different
+/// ports together, eg in an array, or to pass to drivers that take a `Pin`.
+///
+/// Example:
+/// ```rust
+/// let mut led: TypedPin<'B', 5> = TypedPin::new(PinMode::Output);
+/// led.set_high();
+///
+/// let cs_pins: [Pin; 2] = [
+///     TypedPin::<'A', 4>::new(PinMode::Output).degrade(),
+///     TypedPin::<'C', 13>::new(PinMode::Output).into(),
+/// ];
+/// ```
+pub struct TypedPin<const PORT: char, const N: u8> {
+    pin: Pin,
+}
+
+impl<const PORT: char, const N: u8> TypedPin<PORT, N> {
+    /// The port, and pin number. Evaluated at compile time, so an invalid pin fails to build.
+    const PORT_PIN: (Port, u8) = {
+        assert!(N <= 15, "Pin must be 0 - 15.");
+        (Port::from_letter(PORT), N)
+    };
+
+    /// Create a new pin, with a specific mode, as with `Pin::new()`.
+    pub fn new(mode: PinMode) -> Self {
+        let (port, pin) = Self::PORT_PIN;
+        Self {
+            pin: Pin::new(port, pin, mode),
+        }
+    }
+
+    /// Erase the port, and number from the type, returning a `Pin`.
+    pub fn degrade(self) -> Pin {
+        self.pin
+    }
+}
+
+impl<const PORT: char, const N: u8> Deref for TypedPin<PORT, N> {
+    type Target = Pin;
+
+    fn deref(&self) -> &Pin {
+        &self.pin
+    }
+}
+
+impl<const PORT: char, const N: u8> DerefMut for TypedPin<PORT, N> {
+    fn deref_mut(&mut self) -> &mut Pin {
+        &mut self.pin
+    }
+}
+
+impl<const PORT: char, const N: u8> From<TypedPin<PORT, N>> for Pin {
+    fn from(pin: TypedPin<PORT, N>) -> Self {
+        pin.pin
+    }
 }
 
 #[cfg(feature = "embedded-hal")]
@@ -1010,37 +1207,410 @@ impl OutputPin for Pin {
     }
 }
 
+#[cfg(feature = "embedded-hal")]
+impl StatefulOutputPin for Pin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_high(self))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_low(self))
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 impl ToggleableOutputPin for Pin {
     type Error = Infallible;
 
     fn toggle(&mut self) -> Result<(), Self::Error> {
-        if self.is_high() {
-            Pin::set_low(self);
-        } else {
-            Pin::set_high(self);
-        }
+        Pin::toggle(self);
         Ok(())
     }
 }
 
-/// Check if a pin's input voltage is high. Reads from the `IDR` register.
-/// Does not require a `Pin` struct.
-pub fn is_high(port: Port, pin: u8) -> bool {
-    get_input_data!(
-        regs(port),
-        pin,
-        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
-    )
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::digital::ErrorType for Pin {
+    type Error = Infallible;
 }
 
-/// Check if a pin's input voltage is low. Reads from the `IDR` register.
-/// Does not require a `Pin` struct.
-pub fn is_low(port: Port, pin: u8) -> bool {
-    !is_high(port, pin)
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::digital::InputPin for Pin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_high(self))
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_low(self))
+    }
 }
 
-/// Set a pin's output voltage to high. Sets the `BSRR` register. Atomic.
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::digital::OutputPin for Pin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Pin::set_low(self);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Pin::set_high(self);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::digital::StatefulOutputPin for Pin {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_high(self))
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_low(self))
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        Pin::toggle(self);
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+/// The direction of an `OpenDrainIo` pin.
+pub enum IoDirection {
+    /// The output driver is disabled. (`MODER` set to input)
+    Input,
+    /// The pin is an open-drain output. The line can still be read, when it's released.
+    Output,
+}
+
+/// A pin used for a single-wire, bidirectional protocol. In the output direction, writing high
+/// releases the line, so other devices can drive it, and it can be read without switching
+/// direction. Register values are precomputed, so `drive_low()`, `release()`, and
+/// `set_direction()` are each a single register write, for turnaround well within a bit time.
+/// Create with `Pin::into_open_drain_io()`.
+///
+/// Timing functions use the bit rate passed at creation, and busy-wait on the system clock, as in
+/// the `bitbang` module. Interrupts during a transfer stretch timing; disable them for
+/// timing-critical sections, eg with `cortex_m::interrupt::free`.
+///
+/// Example, sending an SDI-12 break, then waiting for the sensor's response:
+/// ```rust
+/// let mut io = Pin::new(Port::A, 8, PinMode::Output).into_open_drain_io(Pull::Floating, 1_200, &clock_cfg);
+/// io.drive_low();
+/// io.delay_bits(15); // 12.5ms
+/// io.release();
+/// io.set_direction(IoDirection::Input);
+/// io.wait_for(PinState::Low, 1_200)?;
+/// ```
+pub struct OpenDrainIo {
+    pub pin: Pin,
+    bsrr_release: u32,
+    bsrr_drive: u32,
+    moder_mask: u32,
+    moder_output: u32,
+    /// CPU cycles per bit.
+    bit_cycles: u32,
+}
+
+impl OpenDrainIo {
+    /// Drive the line low. Sets the `BSRR` register. Atomic.
+    pub fn drive_low(&mut self) {
+        unsafe { (*self.pin.regs()).bsrr.write(|w| w.bits(self.bsrr_drive)) };
+    }
+
+    /// Release the line, letting it be pulled high, or driven by another device. Sets the `BSRR`
+    /// register. Atomic.
+    pub fn release(&mut self) {
+        unsafe { (*self.pin.regs()).bsrr.write(|w| w.bits(self.bsrr_release)) };
+    }
+
+    /// Switch between input, and output, with a single `MODER` write. The output level is
+    /// retained while in the input direction.
+    pub fn set_direction(&mut self, direction: IoDirection) {
+        let val = match direction {
+            IoDirection::Input => 0,
+            IoDirection::Output => self.moder_output,
+        };
+
+        free(|_| unsafe {
+            (*self.pin.regs())
+                .moder
+                .modify(|r, w| w.bits((r.bits() & !self.moder_mask) | val));
+        });
+    }
+
+    /// Set the internal pull resistor, eg to enable a pull-up only while listening.
+    pub fn set_pull(&mut self, pull: Pull) {
+        self.pin.pull(pull);
+    }
+
+    /// Returns true if the line is high. Reads from the `IDR` register.
+    pub fn is_high(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    /// Returns true if the line is low. Reads from the `IDR` register.
+    pub fn is_low(&self) -> bool {
+        self.pin.is_low()
+    }
+
+    /// The number of CPU cycles in one bit period; for custom timing.
+    pub fn bit_cycles(&self) -> u32 {
+        self.bit_cycles
+    }
+
+    /// Block for a number of bit periods.
+    pub fn delay_bits(&self, bits: u32) {
+        for _ in 0..bits {
+            asm::delay(self.bit_cycles);
+        }
+    }
+
+    /// Block for half a bit period; eg to sample in the middle of a bit after an edge.
+    pub fn delay_half_bit(&self) {
+        asm::delay(self.bit_cycles / 2);
+    }
+
+    /// Wait for the line to reach a state, eg a start bit, or a response after turnaround. Polls
+    /// 8 times per bit period. Returns an error if the state isn't reached within `timeout_bits`
+    /// bit periods.
+    pub fn wait_for(&self, state: PinState, timeout_bits: u32) -> Result<(), Error> {
+        let step = self.bit_cycles / 8;
+
+        for _ in 0..timeout_bits * 8 {
+            if self.pin.is_high() == matches!(state, PinState::High) {
+                return Ok(());
+            }
+            asm::delay(step);
+        }
+
+        Err(Error::Timeout)
+    }
+
+    /// Release the pin.
+    pub fn free(self) -> Pin {
+        self.pin
+    }
+}
+
+/// A group of `N` pins, possibly across ports, treated as a logical parallel bus. Eg a 4 or 8-bit
+/// LCD data bus, or a set of DIP switches. Bit 0 of the bus value maps to the first pin. Register
+/// masks are precomputed, so `write()` uses a single atomic `BSRR` write per port, and `read()`
+/// a single `IDR` read per port.
+pub struct PinGroup<const N: usize> {
+    pub pins: [Pin; N],
+    /// Index into `ports` for each pin.
+    port_index: [u8; N],
+    /// The ports used by this group, and a mask of the pins used on each.
+    ports: [(Port, u16); N],
+    num_ports: usize,
+}
+
+impl<const N: usize> PinGroup<N> {
+    /// Create a pin group. `pins` are ordered from the LSB of the bus. They should already be
+    /// configured; eg as outputs for `write()`, or inputs for `read()`.
+    pub fn new(pins: [Pin; N]) -> Self {
+        assert!(N <= 16, "A pin group can contain up to 16 pins.");
+
+        let mut port_index = [0; N];
+        let mut ports = [(Port::A, 0); N];
+        let mut num_ports = 0;
+
+        for (i, pin) in pins.iter().enumerate() {
+            let j = match ports[..num_ports].iter().position(|(p, _)| *p == pin.port) {
+                Some(j) => j,
+                None => {
+                    ports[num_ports] = (pin.port, 0);
+                    num_ports += 1;
+                    num_ports - 1
+                }
+            };
+
+            ports[j].1 |= 1 << pin.pin;
+            port_index[i] = j as u8;
+        }
+
+        Self {
+            pins,
+            port_index,
+            ports,
+            num_ports,
+        }
+    }
+
+    /// Set the mode of all pins in the group. Eg to switch a bidirectional bus between reading
+    /// and writing.
+    pub fn mode(&mut self, value: PinMode) {
+        for pin in self.pins.iter_mut() {
+            pin.mode(value);
+        }
+    }
+
+    /// Set the output speed of all pins in the group. Eg `VeryHigh` for a fast parallel bus.
+    pub fn output_speed(&mut self, value: OutputSpeed) {
+        for pin in self.pins.iter_mut() {
+            pin.output_speed(value);
+        }
+    }
+
+    /// Write a value to the bus. Bits above `N` are ignored. Each port is written with a single
+    /// `BSRR` write, so pins on the same port change simultaneously.
+    pub fn write(&mut self, value: u16) {
+        let mut set = [0_u16; N];
+
+        for (i, pin) in self.pins.iter().enumerate() {
+            if value & (1 << i) != 0 {
+                set[self.port_index[i] as usize] |= 1 << pin.pin;
+            }
+        }
+
+        for (j, (port, mask)) in self.ports[..self.num_ports].iter().enumerate() {
+            let reset = mask & !set[j];
+            unsafe {
+                (*regs(*port))
+                    .bsrr
+                    .write(|w| w.bits(set[j] as u32 | ((reset as u32) << 16)));
+            }
+        }
+    }
+
+    /// Read the bus value, from the pins' input data registers. Each port's `IDR` is read once.
+    pub fn read(&self) -> u16 {
+        let mut idr = [0_u16; N];
+
+        for (j, (port, _)) in self.ports[..self.num_ports].iter().enumerate() {
+            idr[j] = unsafe { (*regs(*port)).idr.read().bits() as u16 };
+        }
+
+        let mut result = 0;
+        for (i, pin) in self.pins.iter().enumerate() {
+            if idr[self.port_index[i] as usize] & (1 << pin.pin) != 0 {
+                result |= 1 << i;
+            }
+        }
+
+        result
+    }
+
+    /// Release the pins.
+    pub fn free(self) -> [Pin; N] {
+        self.pins
+    }
+}
+
+/// A shadow copy of a port's output data, for changing many pins together, eg for driving an LED
+/// matrix or 7-segment display. Compose the next output word with `set()`, `toggle()`, and
+/// `write()`, which only modify the shadow, then apply it with `commit()`, eg once per timer tick.
+/// This avoids flicker, or tearing from pins changing at different times, and avoids reading the
+/// `ODR`, or read-modify-write sequences that can race with other code using the port.
+///
+/// `commit()` is a single atomic `BSRR` write, so all masked pins change simultaneously, and pins
+/// outside the mask are unaffected. The masked pins should already be configured as outputs.
+///
+/// Example:
+/// ```rust
+/// // Rows on PB0 - 7.
+/// let mut rows = PortShadow::new(Port::B, 0x00ff);
+///
+/// // In the timer ISR:
+/// rows.write(1 << row);
+/// rows.commit();
+/// ```
+pub struct PortShadow {
+    pub port: Port,
+    /// The pins controlled by the shadow.
+    mask: u16,
+    /// The output word to apply on the next commit.
+    next: u16,
+    /// The output word applied by the last commit.
+    committed: u16,
+}
+
+impl PortShadow {
+    /// Create a shadow controlling the pins set in `mask`. Its initial value is read from the
+    /// port's `ODR`.
+    pub fn new(port: Port, mask: u16) -> Self {
+        let odr = unsafe { (*regs(port)).odr.read().bits() as u16 } & mask;
+
+        Self {
+            port,
+            mask,
+            next: odr,
+            committed: odr,
+        }
+    }
+
+    /// Set a pin's state in the shadow. Takes effect on the next `commit()`.
+    pub fn set(&mut self, pin: u8, value: PinState) {
+        match value {
+            PinState::High => self.next |= (1 << pin) & self.mask,
+            PinState::Low => self.next &= !(1 << pin),
+        }
+    }
+
+    /// Toggle a pin's state in the shadow. Takes effect on the next `commit()`.
+    pub fn toggle(&mut self, pin: u8) {
+        self.next ^= (1 << pin) & self.mask;
+    }
+
+    /// Set the whole shadow word, where bit n is pin n. Bits outside the mask are ignored. Takes
+    /// effect on the next `commit()`.
+    pub fn write(&mut self, value: u16) {
+        self.next = value & self.mask;
+    }
+
+    /// The output word that will be applied on the next `commit()`.
+    pub fn value(&self) -> u16 {
+        self.next
+    }
+
+    /// Returns true if the shadow has changed since the last `commit()`.
+    pub fn pending(&self) -> bool {
+        self.next != self.committed
+    }
+
+    /// Apply the shadow to the port's pins, with a single `BSRR` write. Does nothing if the shadow
+    /// hasn't changed since the last commit.
+    pub fn commit(&mut self) {
+        if !self.pending() {
+            return;
+        }
+
+        let set = self.next;
+        let reset = self.mask & !self.next;
+        unsafe {
+            (*regs(self.port))
+                .bsrr
+                .write(|w| w.bits(set as u32 | ((reset as u32) << 16)));
+        }
+
+        self.committed = self.next;
+    }
+
+    /// Apply the shadow to the port's pins, even if it hasn't changed; eg if other code has
+    /// written to the masked pins since the last commit.
+    pub fn force_commit(&mut self) {
+        self.committed = !self.next;
+        self.commit();
+    }
+}
+
+/// Check if a pin's input voltage is high. Reads from the `IDR` register.
+/// Does not require a `Pin` struct.
+pub fn is_high(port: Port, pin: u8) -> bool {
+    get_input_data!(
+        regs(port),
+        pin,
+        [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+    )
+}
+
+/// Check if a pin's input voltage is low. Reads from the `IDR` register.
+/// Does not require a `Pin` struct.
+pub fn is_low(port: Port, pin: u8) -> bool {
+    !is_high(port, pin)
+}
+
+/// Set a pin's output voltage to high. Sets the `BSRR` register. Atomic.
 /// Does not require a `Pin` struct.
 pub fn set_high(port: Port, pin: u8) {
     set_state(port, pin, PinState::High);
@@ -1069,6 +1639,236 @@ pub fn set_state(port: Port, pin: u8, value: PinState) {
     );
 }
 
+/// Set the output levels of several pins on a port at once, eg for a parallel bus, with a single
+/// `BSRR` write. Bit n of `mask` selects pin n; selected pins are set high if their bit in
+/// `value` is set, and low otherwise. Other pins aren't affected. Atomic.
+/// Does not require a `Pin` struct.
+pub fn write_masked(port: Port, mask: u16, value: u16) {
+    let set = value & mask;
+    let reset = mask & !value;
+
+    unsafe {
+        (*regs(port))
+            .bsrr
+            .write(|w| w.bits(set as u32 | (reset as u32) << 16));
+    }
+}
+
+/// Read all of a port's input levels, from the `IDR` register, where bit n is pin n.
+/// Does not require a `Pin` struct.
+pub fn read_port(port: Port) -> u16 {
+    unsafe { (*regs(port)).idr.read().bits() as u16 }
+}
+
+/// Toggle the output levels of the pins selected by `mask`, where bit n is pin n, with a single
+/// `BSRR` write. Other pins aren't affected, even if they're changed concurrently, eg in an
+/// interrupt. Does not require a `Pin` struct.
+pub fn toggle_mask(port: Port, mask: u16) {
+    let odr = unsafe { (*regs(port)).odr.read().bits() as u16 };
+    write_masked(port, mask, !odr);
+}
+
+#[cfg(not(feature = "f373"))]
+/// Lock the configuration of the pins selected by `mask`, where bit n is pin n, until the next
+/// reset; their `MODER`, `OTYPER`, `OSPEEDR`, `PUPDR`, `AFRL`, and `AFRH` fields can't be changed
+/// after this. Performs the `LCKR` key sequence. Since the key bit stays set until reset, a port
+/// can only be locked once; lock all of a port's pins that need it in a single call. Returns
+/// `Error::LockFailed` if the lock didn't take effect. Does not require a `Pin` struct.
+pub fn lock_port(port: Port, mask: u16) -> Result<(), Error> {
+    const LCKK: u32 = 1 << 16;
+    let mask = mask as u32;
+
+    let lckr = free(|_| unsafe {
+        let regs = &(*regs(port));
+        // The sequence must not be interrupted by another write to `LCKR`, and the value of the
+        // `LCKy` bits must stay the same throughout.
+        regs.lckr.write(|w| w.bits(LCKK | mask));
+        regs.lckr.write(|w| w.bits(mask));
+        regs.lckr.write(|w| w.bits(LCKK | mask));
+        let _ = regs.lckr.read().bits();
+        regs.lckr.read().bits()
+    });
+
+    if lckr & LCKK == 0 || lckr & mask != mask {
+        return Err(Error::LockFailed);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "f373"))]
+/// Returns true if a pin's configuration is locked. Does not require a `Pin` struct.
+pub fn is_locked(port: Port, pin: u8) -> bool {
+    let lckr = unsafe { (*regs(port)).lckr.read().bits() };
+    lckr & (1 << 16) != 0 && lckr & (1 << pin) != 0
+}
+
+#[cfg(feature = "h7")]
+#[derive(Clone, Copy)]
+/// The analog switches between the `PA0`, `PA1`, `PC2`, and `PC3` pads, and their `_C` pads,
+/// which connect directly to ADC inputs. Values are bit positions in `SYSCFG_PMCR`.
+pub enum AnalogSwitch {
+    Pa0 = 24,
+    Pa1 = 25,
+    Pc2 = 26,
+    Pc3 = 27,
+}
+
+#[cfg(feature = "h7")]
+/// Open or close an analog switch. When closed, the pin and its `_C` pad are connected, so the
+/// `_C` ADC input can be reached from the pin's normal GPIO functions. Open it to use both as
+/// separate pins, eg on packages where both are bonded out. Sets `SYSCFG_PMCR` register,
+/// `PxySO` fields.
+pub fn set_analog_switch(switch: AnalogSwitch, closed: bool) {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
+
+        // We use raw bits, since these fields are named inconsistently among H7 PACs. Setting a
+        // `PxySO` bit opens its switch.
+        let syscfg = unsafe { &(*pac::SYSCFG::ptr()) };
+        let bit = 1 << switch as u32;
+        syscfg.pmcr.modify(|r, w| unsafe {
+            if closed {
+                w.bits(r.bits() & !bit)
+            } else {
+                w.bits(r.bits() | bit)
+            }
+        });
+    });
+}
+
+#[cfg(any(
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+    feature = "g4",
+    feature = "l5"
+))]
+/// Disable the UCPD dead battery pull-downs on the USB-C CC pins, which are present after reset.
+/// Do this to use these pins as GPIO, or for other functions, when not using them for USB PD.
+/// When using the UCPD, call `Ucpd::disable_dead_battery()` once it's configured instead. Sets
+/// `PWR_CR3` register, `UCPD_DBDIS` field (shared with `Ucpd`), or on G0, `SYSCFG_CFGR1`
+/// register, `UCPDx_STROBE` fields.
+pub fn disable_ucpd_dead_battery() {
+    cfg_if! {
+        if #[cfg(feature = "g0")] {
+            // We use a raw pointer, since SYSCFG is missing from, or named inconsistently in the
+            // G0 PACs.
+            const SYSCFG_CFGR1: *mut u32 = 0x4001_0000 as *mut u32;
+
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+
+                // We use raw bits, since these fields are missing from some PACs.
+                // APBENR2: SYSCFGEN (bit 0).
+                rcc.apbenr2.modify(|r, w| unsafe { w.bits(r.bits() | 1) });
+                // CFGR1: UCPD1_STROBE (bit 9), and UCPD2_STROBE (bit 10).
+                unsafe {
+                    let val = core::ptr::read_volatile(SYSCFG_CFGR1);
+                    core::ptr::write_volatile(SYSCFG_CFGR1, val | 0b11 << 9);
+                }
+            });
+        } else {
+            crate::ucpd::disable_dead_battery();
+        }
+    }
+}
+
+/// Configure a set of pins from a table of (port, pin, mode, pull, output type, output speed)
+/// entries, eg for pins used by buses, that aren't interacted with directly later. Each port's
+/// `AFRL`, `AFRH`, `OTYPER`, `OSPEEDR`, `PUPDR`, and `MODER` registers are modified at most
+/// once, in that order, so pins switch mode with the rest of their configuration already in
+/// place. Enables the RCC peripheral clock to each port used. Panics if a pin or port is invalid,
+/// or with the `pin_registry` feature, if a pin is already claimed, as with `Pin::new()`. These
+/// claims can be released with `release_claim()`.
+///
+/// Example:
+/// ```rust
+/// gpio::configure_table(&[
+///     // I2C1
+///     (Port::B, 6, PinMode::Alt(4), Pull::Floating, OutputType::OpenDrain, OutputSpeed::Low),
+///     (Port::B, 7, PinMode::Alt(4), Pull::Floating, OutputType::OpenDrain, OutputSpeed::Low),
+///     // SPI1
+///     (Port::A, 5, PinMode::Alt(5), Pull::Floating, OutputType::PushPull, OutputSpeed::VeryHigh),
+///     (Port::A, 6, PinMode::Alt(5), Pull::Floating, OutputType::PushPull, OutputSpeed::VeryHigh),
+///     (Port::A, 7, PinMode::Alt(5), Pull::Floating, OutputType::PushPull, OutputSpeed::VeryHigh),
+/// ]);
+/// ```
+pub fn configure_table(table: &[(Port, u8, PinMode, Pull, OutputType, OutputSpeed)]) {
+    for (i, (port, ..)) in table.iter().enumerate() {
+        // Handle each port once; at its first entry in the table.
+        if table[..i].iter().any(|(p, ..)| p == port) {
+            continue;
+        }
+
+        assert!(port.available(), "Port not available on this package.");
+        enable_port_clock(*port);
+
+        // (mask, value) pairs for each register.
+        let mut afrl = (0_u32, 0_u32);
+        let mut afrh = (0_u32, 0_u32);
+        let mut otyper = (0_u32, 0_u32);
+        let mut ospeedr = (0_u32, 0_u32);
+        let mut pupdr = (0_u32, 0_u32);
+        let mut moder = (0_u32, 0_u32);
+
+        for (_, pin, mode, pull, output_type, speed) in table.iter().filter(|(p, ..)| p == port) {
+            assert!(*pin <= 15, "Pin must be 0 - 15.");
+            #[cfg(feature = "pin_registry")]
+            if !claim(*port, *pin) {
+                panic!(
+                    "P{}{} is already claimed by another `Pin`, or table entry.",
+                    (b'A' + port.cr_val()) as char,
+                    pin
+                );
+            }
+            let pin = *pin as u32;
+
+            if let PinMode::Alt(alt) = mode {
+                let afr = if pin < 8 { &mut afrl } else { &mut afrh };
+                let shift = (pin % 8) * 4;
+                afr.0 |= 0b1111 << shift;
+                afr.1 |= (*alt as u32 & 0b1111) << shift;
+            }
+
+            otyper.0 |= 1 << pin;
+            otyper.1 |= (*output_type as u32) << pin;
+
+            ospeedr.0 |= 0b11 << (pin * 2);
+            ospeedr.1 |= (*speed as u32) << (pin * 2);
+
+            pupdr.0 |= 0b11 << (pin * 2);
+            pupdr.1 |= (*pull as u32) << (pin * 2);
+
+            moder.0 |= 0b11 << (pin * 2);
+            moder.1 |= (mode.val() as u32) << (pin * 2);
+        }
+
+        // We use raw bits here, since field names for the alternate function registers vary by
+        // PAC, and so we can set all of a port's pins with a single write to each register.
+        let regs = unsafe { &*regs(*port) };
+        free(|_| unsafe {
+            if afrl.0 != 0 {
+                regs.afrl
+                    .modify(|r, w| w.bits((r.bits() & !afrl.0) | afrl.1));
+            }
+            if afrh.0 != 0 {
+                regs.afrh
+                    .modify(|r, w| w.bits((r.bits() & !afrh.0) | afrh.1));
+            }
+            regs.otyper
+                .modify(|r, w| w.bits((r.bits() & !otyper.0) | otyper.1));
+            regs.ospeedr
+                .modify(|r, w| w.bits((r.bits() & !ospeedr.0) | ospeedr.1));
+            regs.pupdr
+                .modify(|r, w| w.bits((r.bits() & !pupdr.0) | pupdr.1));
+            regs.moder
+                .modify(|r, w| w.bits((r.bits() & !moder.0) | moder.1));
+        });
+    }
+}
+
 /// Clear an EXTI interrupt, lines 0 - 15. Note that this function currently doesn't support
 /// higher extis, but will work for all GPIO interrupts.
 pub fn clear_exti_interrupt(line: u8) {
@@ -1212,7 +2012,497 @@ pub fn clear_exti_interrupt(line: u8) {
     }
 }
 
-const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
+#[cfg(feature = "exti_dispatch")]
+/// Functions registered with `attach_interrupt()`, indexed by EXTI line.
+static EXTI_CALLBACKS: Mutex<Cell<[Option<fn()>; 16]>> = Mutex::new(Cell::new([None; 16]));
+
+#[cfg(feature = "exti_dispatch")]
+/// Configure a pin as an interrupt source, and register `f` to run when it triggers. The HAL
+/// provides the `EXTIx` interrupt handlers with the `exti_dispatch` feature: They clear the
+/// pending flags, and call the registered functions, so you don't need to write your own. This
+/// unmasks the line's interrupt in the NVIC.
+///
+/// EXTI lines are shared by pin number across ports, so only one pin with a given number can be
+/// attached; eg PA3, and PB3 can't both be. Attaching replaces a line's previous function.
+pub fn attach_interrupt(pin: &mut Pin, edge: Edge, f: fn()) {
+    free(|cs| {
+        let cell = EXTI_CALLBACKS.borrow(cs);
+        let mut callbacks = cell.get();
+        callbacks[pin.pin as usize] = Some(f);
+        cell.set(callbacks);
+    });
+
+    pin.enable_interrupt(edge);
+
+    unsafe { NVIC::unmask(exti_interrupt(pin.pin)) };
+}
+
+#[cfg(feature = "exti_dispatch")]
+/// Unregister the function for an EXTI line, registered with `attach_interrupt()`. The line's
+/// interrupts are still cleared, but ignored.
+pub fn detach_interrupt(line: u8) {
+    free(|cs| {
+        let cell = EXTI_CALLBACKS.borrow(cs);
+        let mut callbacks = cell.get();
+        callbacks[line as usize] = None;
+        cell.set(callbacks);
+    });
+}
+
+#[cfg(feature = "exti_dispatch")]
+/// The NVIC interrupt for an EXTI line.
+fn exti_interrupt(line: u8) -> pac::Interrupt {
+    cfg_if! {
+        if #[cfg(feature = "g0")] {
+            match line {
+                0 | 1 => pac::Interrupt::EXTI0_1,
+                2 | 3 => pac::Interrupt::EXTI2_3,
+                _ => pac::Interrupt::EXTI4_15,
+            }
+        } else if #[cfg(feature = "l5")] {
+            match line {
+                0 => pac::Interrupt::EXTI0,
+                1 => pac::Interrupt::EXTI1,
+                2 => pac::Interrupt::EXTI2,
+                3 => pac::Interrupt::EXTI3,
+                4 => pac::Interrupt::EXTI4,
+                5 => pac::Interrupt::EXTI5,
+                6 => pac::Interrupt::EXTI6,
+                7 => pac::Interrupt::EXTI7,
+                8 => pac::Interrupt::EXTI8,
+                9 => pac::Interrupt::EXTI9,
+                10 => pac::Interrupt::EXTI10,
+                11 => pac::Interrupt::EXTI11,
+                12 => pac::Interrupt::EXTI12,
+                13 => pac::Interrupt::EXTI13,
+                14 => pac::Interrupt::EXTI14,
+                _ => pac::Interrupt::EXTI15,
+            }
+        } else {
+            match line {
+                0 => pac::Interrupt::EXTI0,
+                1 => pac::Interrupt::EXTI1,
+                #[cfg(feature = "f3")]
+                2 => pac::Interrupt::EXTI2_TSC,
+                #[cfg(not(feature = "f3"))]
+                2 => pac::Interrupt::EXTI2,
+                3 => pac::Interrupt::EXTI3,
+                4 => pac::Interrupt::EXTI4,
+                5..=9 => pac::Interrupt::EXTI9_5,
+                _ => pac::Interrupt::EXTI15_10,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "exti_dispatch")]
+/// Read the EXTI pending flags for lines 0 - 15. On L5, and G0, rising, and falling edges have
+/// separate flags; we combine them.
+fn exti_pending() -> u32 {
+    let exti = unsafe { &(*EXTI::ptr()) };
+
+    cfg_if! {
+        if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+            exti.c1pr1.read().bits() & 0xffff
+        } else if #[cfg(feature = "h7")] {
+            exti.cpupr1.read().bits() & 0xffff
+        } else if #[cfg(any(feature = "l5", feature = "g0"))] {
+            (exti.rpr1.read().bits() | exti.fpr1.read().bits()) & 0xffff
+        } else if #[cfg(any(feature = "f373", feature = "f4"))] {
+            exti.pr.read().bits() & 0xffff
+        } else {
+            exti.pr1.read().bits() & 0xffff
+        }
+    }
+}
+
+#[cfg(feature = "exti_dispatch")]
+/// Clear EXTI pending flags. These are cleared by writing 1, so other lines aren't affected.
+fn exti_clear(mask: u32) {
+    let exti = unsafe { &(*EXTI::ptr()) };
+
+    cfg_if! {
+        if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+            exti.c1pr1.write(|w| unsafe { w.bits(mask) });
+        } else if #[cfg(feature = "h7")] {
+            exti.cpupr1.write(|w| unsafe { w.bits(mask) });
+        } else if #[cfg(any(feature = "l5", feature = "g0"))] {
+            exti.rpr1.write(|w| unsafe { w.bits(mask) });
+            exti.fpr1.write(|w| unsafe { w.bits(mask) });
+        } else if #[cfg(any(feature = "f373", feature = "f4"))] {
+            exti.pr.write(|w| unsafe { w.bits(mask) });
+        } else {
+            exti.pr1.write(|w| unsafe { w.bits(mask) });
+        }
+    }
+}
+
+#[cfg(feature = "exti_dispatch")]
+/// Clear the pending flags for EXTI lines `first` to `last`, then run the functions registered
+/// for the ones that triggered.
+fn dispatch_exti(first: u8, last: u8) {
+    let range = (0xffff >> (15 - last)) & (0xffff << first);
+    let pending = exti_pending() & range;
+    exti_clear(pending);
+
+    let callbacks = free(|cs| EXTI_CALLBACKS.borrow(cs).get());
+
+    for line in first..=last {
+        if pending & (1 << line) != 0 {
+            if let Some(f) = callbacks[line as usize] {
+                f();
+            }
+        }
+    }
+}
+
+// Reduce DRY for the EXTI handlers, which dispatch lines `first` to `last`.
+#[cfg(feature = "exti_dispatch")]
+macro_rules! exti_handler {
+    ($name:ident, $first:expr, $last:expr) => {
+        #[interrupt]
+        fn $name() {
+            dispatch_exti($first, $last);
+        }
+    };
+}
+
+#[cfg(feature = "exti_dispatch")]
+cfg_if! {
+    if #[cfg(feature = "g0")] {
+        exti_handler!(EXTI0_1, 0, 1);
+        exti_handler!(EXTI2_3, 2, 3);
+        exti_handler!(EXTI4_15, 4, 15);
+    } else if #[cfg(feature = "l5")] {
+        exti_handler!(EXTI0, 0, 0);
+        exti_handler!(EXTI1, 1, 1);
+        exti_handler!(EXTI2, 2, 2);
+        exti_handler!(EXTI3, 3, 3);
+        exti_handler!(EXTI4, 4, 4);
+        exti_handler!(EXTI5, 5, 5);
+        exti_handler!(EXTI6, 6, 6);
+        exti_handler!(EXTI7, 7, 7);
+        exti_handler!(EXTI8, 8, 8);
+        exti_handler!(EXTI9, 9, 9);
+        exti_handler!(EXTI10, 10, 10);
+        exti_handler!(EXTI11, 11, 11);
+        exti_handler!(EXTI12, 12, 12);
+        exti_handler!(EXTI13, 13, 13);
+        exti_handler!(EXTI14, 14, 14);
+        exti_handler!(EXTI15, 15, 15);
+    } else {
+        exti_handler!(EXTI0, 0, 0);
+        exti_handler!(EXTI1, 1, 1);
+        #[cfg(feature = "f3")]
+        exti_handler!(EXTI2_TSC, 2, 2);
+        #[cfg(not(feature = "f3"))]
+        exti_handler!(EXTI2, 2, 2);
+        exti_handler!(EXTI3, 3, 3);
+        exti_handler!(EXTI4, 4, 4);
+        exti_handler!(EXTI9_5, 5, 9);
+        exti_handler!(EXTI15_10, 10, 15);
+    }
+}
+
+/// Enable the RCC peripheral clock to a port, if not already enabled.
+fn enable_port_clock(port: Port) {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        match port {
+            Port::A => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopaen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopa, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpioaen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpioaen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpioarst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpioarst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpioaen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpioa, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopaen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopaen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.ioparst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.ioparst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpioaen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpioa, rcc);
+                        }
+                    }
+                }
+            }
+            Port::B => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopben().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopb, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpioben().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpioben().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiobrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiobrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpioben().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpiob, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopben().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopben().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopbrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopbrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpioben().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpiob, rcc);
+                        }
+                    }
+                }
+            }
+            #[cfg(not(feature = "wl"))]
+            Port::C => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopcen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopc, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpiocen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpiocen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiocrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiocrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpiocen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpioc, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopcen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopcen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopcrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopcrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpiocen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpioc, rcc);
+                        }
+                    }
+                }
+            }
+            #[cfg(not(any(feature = "f410", feature = "wl")))]
+            Port::D => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopden().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopd, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpioden().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpioden().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiodrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiodrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpioden().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpiod, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopden().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopden().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopdrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopdrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpioden().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpiod, rcc);
+                        }
+                    }
+                }
+            }
+            #[cfg(not(any(
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f410",
+                feature = "g0",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            Port::E => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopeen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iope, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpioeen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpioeen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpioerst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpioerst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpioeen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpioe, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopeen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopeen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.ioperst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.ioperst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpioeen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpioe, rcc);
+                        }
+                    }
+                }
+            }
+            #[cfg(not(any(
+                feature = "f401",
+                feature = "f410",
+                feature = "f411",
+                feature = "l4x1",
+                feature = "l4x2",
+                feature = "l412",
+                feature = "l4x3",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            Port::F => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iopfen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopf, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpiofen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpiofen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiofrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiofrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpiofen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpiof, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iopfen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iopfen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopfrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iopfrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpiofen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpiof, rcc);
+                        }
+                    }
+                }
+            }
+            #[cfg(not(any(
+                feature = "f373",
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f401",
+                feature = "f410",
+                feature = "f411",
+                feature = "l4",
+                feature = "g0",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            Port::G => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iophen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, iopg, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpiohen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpiohen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiohrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiohrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpiohen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpioh, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iophen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iophen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iophrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iophrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpiohen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpioa, rcc);
+                        }
+                    }
+                }
+                #[cfg(feature = "l5")]
+                // also for RM0351 L4 variants, which we don't currently support
+                // L5 RM: "[The IOSV bit] is used to validate the VDDIO2 supply for electrical and logical isolation purpose.
+                // Setting this bit is mandatory to use PG[15:2]."
+                {
+                    unsafe {
+                        (*crate::pac::PWR::ptr())
+                            .cr2
+                            .modify(|_, w| w.iosv().set_bit());
+                    }
+                }
+            }
+            #[cfg(not(any(
+                feature = "f373",
+                feature = "f301",
+                feature = "f3x4",
+                feature = "f410",
+                feature = "l4",
+                feature = "g0",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl"
+            )))]
+            Port::H => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        if rcc.ahbenr.read().iophen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, ioph, rcc);
+                        }
+                    } else if #[cfg(feature = "h7")] {
+                        if rcc.ahb4enr.read().gpiohen().bit_is_clear() {
+                            rcc.ahb4enr.modify(|_, w| w.gpiohen().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiohrst().set_bit());
+                            rcc.ahb4rstr.modify(|_, w| w.gpiohrst().clear_bit());
+                        }
+                    } else if #[cfg(feature = "f4")] {
+                        if rcc.ahb1enr.read().gpiohen().bit_is_clear() {
+                            rcc_en_reset!(ahb1, gpioh, rcc);
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        if rcc.iopenr.read().iophen().bit_is_clear() {
+                            rcc.iopenr.modify(|_, w| w.iophen().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iophrst().set_bit());
+                            rcc.ioprstr.modify(|_, w| w.iophrst().clear_bit());
+                        }
+                    } else { // L4, L5, G4
+                        if rcc.ahb2enr.read().gpiohen().bit_is_clear() {
+                            rcc_en_reset!(ahb2, gpioa, rcc);
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+pub(crate) const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
     // Note that we use this `const` fn and pointer casting since not all ports actually
     // deref to GPIOA in PAC.
     match port {
@@ -0,0 +1,239 @@
+//! Support for the Global TrustZone Controller (GTZC), on L5. Configures which peripherals are
+//! secure, and privileged-only, and reports illegal accesses: eg non-secure code accessing a secure
+//! peripheral. This is the main tool for debugging TrustZone configuration errors, which otherwise
+//! appear as silently-ignored writes, or reads of zero.
+//!
+//! These registers can only be written from the secure state. The GTZC interrupt is secure; enable
+//! it in the NVIC from secure code, and call `take_illegal_access()` in its handler.
+//!
+//! Example:
+//! ```rust
+//! gtzc::set_security(GtzcPeriph::Usart1, false);
+//! gtzc::enable_illegal_access_interrupts();
+//!
+//! #[interrupt]
+//! fn GTZC() {
+//!     while let Some(access) = gtzc::take_illegal_access() {
+//!         defmt::println!("Illegal access: {:?}", access);
+//!     }
+//! }
+//! ```
+//!
+//! See L5 RM, section 5: Global TrustZone controller (GTZC). (U5 isn't supported by this HAL yet)
+
+// We use the secure aliases of the GTZC peripherals, since security attribute writes from the
+// non-secure alias are ignored. PAC support for these is inconsistent, so we use raw pointers.
+/// GTZC_TZSC secure base address.
+const TZSC_BASE: usize = 0x5003_2400;
+/// GTZC_TZIC secure base address.
+const TZIC_BASE: usize = 0x5003_2800;
+
+// TZSC register offsets.
+const TZSC_CR: usize = 0x00;
+const TZSC_SECCFGR1: usize = 0x10;
+const TZSC_PRIVCFGR1: usize = 0x20;
+
+// TZIC register offsets. Each has 3 registers, at consecutive words.
+const TZIC_IER1: usize = 0x00;
+const TZIC_SR1: usize = 0x10;
+const TZIC_FCR1: usize = 0x20;
+
+/// The number of TZIC interrupt enable, status, and flag clear registers.
+const NUM_TZIC_REGS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+/// Peripherals whose security, and privilege attributes can be set. The value is the register
+/// index (0 for SECCFGR1 and PRIVCFGR1; 1 for SECCFGR2 and PRIVCFGR2) × 32, plus the bit. These bits
+/// are the same in the TZIC IER1/2, SR1/2, and FCR1/2 registers.
+pub enum GtzcPeriph {
+    Tim2 = 0,
+    Tim3 = 1,
+    Tim4 = 2,
+    Tim5 = 3,
+    Tim6 = 4,
+    Tim7 = 5,
+    Wwdg = 6,
+    Iwdg = 7,
+    Spi2 = 8,
+    Spi3 = 9,
+    Usart2 = 10,
+    Usart3 = 11,
+    Uart4 = 12,
+    Uart5 = 13,
+    I2c1 = 14,
+    I2c2 = 15,
+    I2c3 = 16,
+    Crs = 17,
+    Dac1 = 18,
+    Opamp = 19,
+    Lptim1 = 20,
+    Lpuart1 = 21,
+    I2c4 = 22,
+    Lptim2 = 23,
+    Lptim3 = 24,
+    Fdcan1 = 25,
+    Usb = 26,
+    Ucpd1 = 27,
+    Vrefbuf = 28,
+    Comp = 29,
+    Tim1 = 30,
+    Spi1 = 31,
+    Tim8 = 32,
+    Usart1 = 33,
+    Tim15 = 34,
+    Tim16 = 35,
+    Tim17 = 36,
+    Sai1 = 37,
+    Sai2 = 38,
+    Dfsdm1 = 39,
+    Crc = 40,
+    Tsc = 41,
+    /// Instruction cache registers.
+    Icache = 42,
+    Adc = 43,
+    Aes = 44,
+    Hash = 45,
+    Rng = 46,
+    Pka = 47,
+    Sdmmc1 = 48,
+    /// FMC registers.
+    Fmc = 49,
+    /// OCTOSPI1 registers.
+    Octospi1 = 50,
+}
+
+impl GtzcPeriph {
+    /// Register index, and bit.
+    fn location(&self) -> (usize, u32) {
+        let val = *self as u8;
+        ((val / 32) as usize, (val % 32) as u32)
+    }
+
+    fn from_location(reg: usize, bit: u32) -> Option<Self> {
+        let val = reg as u32 * 32 + bit;
+        if val > Self::Octospi1 as u32 {
+            return None;
+        }
+        // Safe, since discriminants are contiguous from 0 to `Octospi1`.
+        Some(unsafe { core::mem::transmute::<u8, Self>(val as u8) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The source of an illegal access, reported by the TZIC.
+pub enum IllegalAccess {
+    /// A securable peripheral.
+    Periph(GtzcPeriph),
+    /// Another source, eg RCC, FLASH, SRAM, or the GTZC itself. `register` is the TZIC status
+    /// register index (1 - 3), and `bit` its bit; see L5 RM, section 5.7: GTZC TZIC registers,
+    /// for the mapping.
+    Other { register: u8, bit: u8 },
+}
+
+/// Read a GTZC register.
+fn read(base: usize, offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((base + offset) as *const u32) }
+}
+
+/// Write a GTZC register.
+fn write(base: usize, offset: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((base + offset) as *mut u32, value) }
+}
+
+/// Set or clear a single bit in a GTZC register.
+fn modify_bit(base: usize, offset: usize, bit: u32, set: bool) {
+    let val = read(base, offset);
+    if set {
+        write(base, offset, val | (1 << bit));
+    } else {
+        write(base, offset, val & !(1 << bit));
+    }
+}
+
+/// Set a peripheral as secure, so it can only be accessed from the secure state, or non-secure.
+/// Sets GTZC_TZSC_SECCFGRx.
+pub fn set_security(periph: GtzcPeriph, secure: bool) {
+    let (reg, bit) = periph.location();
+    modify_bit(TZSC_BASE, TZSC_SECCFGR1 + reg * 4, bit, secure);
+}
+
+/// Set a peripheral as privileged, so it can only be accessed by privileged code, or unprivileged.
+/// Sets GTZC_TZSC_PRIVCFGRx.
+pub fn set_privilege(periph: GtzcPeriph, privileged: bool) {
+    let (reg, bit) = periph.location();
+    modify_bit(TZSC_BASE, TZSC_PRIVCFGR1 + reg * 4, bit, privileged);
+}
+
+/// Check if a peripheral is set as secure.
+pub fn is_secure(periph: GtzcPeriph) -> bool {
+    let (reg, bit) = periph.location();
+    read(TZSC_BASE, TZSC_SECCFGR1 + reg * 4) & (1 << bit) != 0
+}
+
+/// Lock the TZSC configuration until the next reset. Sets GTZC_TZSC_CR, LCK bit.
+pub fn lock() {
+    write(TZSC_BASE, TZSC_CR, read(TZSC_BASE, TZSC_CR) | 1);
+}
+
+/// Enable the illegal access interrupt for a single peripheral. Sets GTZC_TZIC_IERx.
+pub fn enable_illegal_access_interrupt(periph: GtzcPeriph) {
+    let (reg, bit) = periph.location();
+    modify_bit(TZIC_BASE, TZIC_IER1 + reg * 4, bit, true);
+}
+
+/// Enable the illegal access interrupt for all sources, including memories, and peripherals
+/// not covered by `GtzcPeriph`.
+pub fn enable_illegal_access_interrupts() {
+    for i in 0..NUM_TZIC_REGS {
+        write(TZIC_BASE, TZIC_IER1 + i * 4, 0xffff_ffff);
+    }
+}
+
+/// Disable the illegal access interrupt for all sources.
+pub fn disable_illegal_access_interrupts() {
+    for i in 0..NUM_TZIC_REGS {
+        write(TZIC_BASE, TZIC_IER1 + i * 4, 0);
+    }
+}
+
+/// Find, clear, and return the next pending illegal access, or `None` if there are none. Call this
+/// in a loop from the GTZC interrupt handler. Note that the TZIC only reports the source; for the
+/// faulting address of a CPU access, see `secure_fault_address()`.
+pub fn take_illegal_access() -> Option<IllegalAccess> {
+    for i in 0..NUM_TZIC_REGS {
+        let sr = read(TZIC_BASE, TZIC_SR1 + i * 4);
+        if sr == 0 {
+            continue;
+        }
+
+        let bit = sr.trailing_zeros();
+        write(TZIC_BASE, TZIC_FCR1 + i * 4, 1 << bit);
+
+        return Some(match GtzcPeriph::from_location(i, bit) {
+            Some(p) => IllegalAccess::Periph(p),
+            // Bits past the last peripheral in SR2, and all SR3 bits aren't securable peripherals.
+            _ => IllegalAccess::Other {
+                register: i as u8 + 1,
+                bit: bit as u8,
+            },
+        });
+    }
+
+    None
+}
+
+/// Returns the address that caused a SecureFault, if valid. Reads the SAU's SFSR register, SFARVALID
+/// bit, and SFAR register. Use this in the SecureFault handler.
+pub fn secure_fault_address() -> Option<u32> {
+    const SFSR: usize = 0xe000_ede4;
+    const SFAR: usize = 0xe000_ede8;
+
+    let sfsr = unsafe { core::ptr::read_volatile(SFSR as *const u32) };
+    // SFARVALID is bit 6.
+    if sfsr & (1 << 6) != 0 {
+        Some(unsafe { core::ptr::read_volatile(SFAR as *const u32) })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,397 @@
+//! Support for the HASH processor, which calculates SHA-1, SHA-224, SHA-256, and MD5 digests,
+//! and HMACs, in hardware. Data can be fed incrementally with `update()`, from the CPU, or in a
+//! single transfer using DMA.
+//!
+//! With the `digest` feature, `Sha256`, `Sha224`, `Sha1`, and `Md5` implement the `digest`
+//! crate's traits, so they can be used with crates that are generic over hash functions. These
+//! all use the same peripheral, so only use one at a time.
+//!
+//! For DMA on H7, run `dma::mux()` with `DmaInput::HashIn` first.
+//!
+//! See H743 RM, chapter 36: "Hash processor (HASH)", or L552 RM, chapter 40.
+//!
+//! Example:
+//! ```rust
+//! let mut hash = Hash::new();
+//!
+//! let mut digest = [0; 32];
+//! hash.start(Algorithm::Sha256);
+//! hash.update(b"hello, ");
+//! hash.update(b"world");
+//! hash.finalize(&mut digest);
+//!
+//! let mut mac = [0; 32];
+//! hash.start_hmac(Algorithm::Sha256, b"secret key");
+//! hash.update(b"message");
+//! hash.finalize_hmac(b"secret key", &mut mac);
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel, DmaPeriph},
+    pac::{DMA1, DMA2, RCC},
+};
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since the HASH is missing from some PACs.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const HASH_BASE: usize = 0x4802_1400;
+    } else {
+        const HASH_BASE: usize = 0x420c_0400;
+    }
+}
+
+// Register offsets.
+const CR: usize = 0x00;
+const DIN: usize = 0x04;
+const STR: usize = 0x08;
+const SR: usize = 0x24;
+const HR0: usize = 0x310;
+
+// HASH_CR fields.
+const INIT: u32 = 1 << 2;
+const DMAE: u32 = 1 << 3;
+/// DATATYPE = 0b10: 8-bit data, byte-swapped, so words written from little-endian byte slices
+/// are processed in order.
+const DATATYPE_BYTES: u32 = 0b10 << 4;
+const MODE_HMAC: u32 = 1 << 6;
+const ALGO0: u32 = 1 << 7;
+const LKEY: u32 = 1 << 16;
+const ALGO1: u32 = 1 << 18;
+
+// HASH_STR fields.
+const DCAL: u32 = 1 << 8;
+
+// HASH_SR fields.
+const DCIS: u32 = 1 << 1;
+const BUSY: u32 = 1 << 3;
+
+/// HMAC keys longer than this are hashed first. (HASH_CR register, LKEY field)
+const HMAC_LONG_KEY: usize = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+/// A hash algorithm. Sets HASH_CR register, ALGO field.
+pub enum Algorithm {
+    Sha1,
+    Md5,
+    Sha224,
+    Sha256,
+}
+
+impl Algorithm {
+    /// The digest length, in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Md5 => 16,
+            Self::Sha224 => 28,
+            Self::Sha256 => 32,
+        }
+    }
+
+    /// The ALGO field bits, which are split in the HASH_CR register.
+    fn bits(&self) -> u32 {
+        match self {
+            Self::Sha1 => 0,
+            Self::Md5 => ALGO0,
+            Self::Sha224 => ALGO1,
+            Self::Sha256 => ALGO1 | ALGO0,
+        }
+    }
+}
+
+/// Represents the HASH processor.
+pub struct Hash {
+    algorithm: Algorithm,
+    /// Bytes that don't yet fill a word, from the previous `update()`.
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl Hash {
+    /// Enable and reset the HASH's RCC peripheral clock.
+    pub fn new() -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // We use raw bits, since HASH RCC fields are missing from some PACs.
+            // H7: AHB2ENR, and AHB2RSTR, bit 5. L5: AHB2ENR, and AHB2RSTR, bit 17.
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    const BIT: u32 = 1 << 5;
+                } else {
+                    const BIT: u32 = 1 << 17;
+                }
+            }
+            rcc.ahb2enr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+            rcc.ahb2rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+            rcc.ahb2rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !BIT) });
+        });
+
+        Self {
+            algorithm: Algorithm::Sha256,
+            partial: [0; 4],
+            partial_len: 0,
+        }
+    }
+
+    /// Start a new digest calculation. Feed it data with `update()`.
+    pub fn start(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
+        self.partial_len = 0;
+
+        self.write_reg(CR, algorithm.bits() | DATATYPE_BYTES | INIT);
+    }
+
+    /// Start a new HMAC calculation, loading the key. Feed it the message with `update()`, then
+    /// finish with `finalize_hmac()`, using the same key.
+    pub fn start_hmac(&mut self, algorithm: Algorithm, key: &[u8]) {
+        self.algorithm = algorithm;
+        self.partial_len = 0;
+
+        let mut cr = algorithm.bits() | DATATYPE_BYTES | MODE_HMAC | INIT;
+        if key.len() > HMAC_LONG_KEY {
+            cr |= LKEY;
+        }
+        self.write_reg(CR, cr);
+
+        self.feed(key);
+        self.end_phase();
+        while self.read_reg(SR) & BUSY != 0 {}
+    }
+
+    /// Feed message data. Can be called any number of times, with any length.
+    pub fn update(&mut self, data: &[u8]) {
+        self.feed(data);
+    }
+
+    /// Finish the digest calculation, and write it to `out`, which must be at least the
+    /// algorithm's output length. Blocks until the calculation is complete.
+    pub fn finalize(&mut self, out: &mut [u8]) {
+        self.end_phase();
+        self.read_digest(out);
+    }
+
+    /// Finish an HMAC calculation, and write it to `out`. `key` must be the same key passed to
+    /// `start_hmac()`.
+    pub fn finalize_hmac(&mut self, key: &[u8], out: &mut [u8]) {
+        self.end_phase();
+        while self.read_reg(SR) & BUSY != 0 {}
+
+        // The outer hash uses the key again.
+        self.feed(key);
+        self.end_phase();
+        self.read_digest(out);
+    }
+
+    /// Calculate the digest of `data` in one call.
+    pub fn hash(&mut self, algorithm: Algorithm, data: &[u8], out: &mut [u8]) {
+        self.start(algorithm);
+        self.update(data);
+        self.finalize(out);
+    }
+
+    /// Write data to the input FIFO, holding back bytes that don't fill a word.
+    fn feed(&mut self, mut data: &[u8]) {
+        if self.partial_len > 0 {
+            let n = (4 - self.partial_len).min(data.len());
+            self.partial[self.partial_len..self.partial_len + n].copy_from_slice(&data[..n]);
+            self.partial_len += n;
+            data = &data[n..];
+
+            if self.partial_len < 4 {
+                return;
+            }
+            self.write_reg(DIN, u32::from_le_bytes(self.partial));
+            self.partial_len = 0;
+        }
+
+        // Writes stall while the FIFO is full, so we don't need to check its status.
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            self.write_reg(
+                DIN,
+                u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+            );
+        }
+
+        let rem = words.remainder();
+        self.partial[..rem.len()].copy_from_slice(rem);
+        self.partial_len = rem.len();
+    }
+
+    /// Write any remaining partial word, set the number of valid bits in it, and start the
+    /// digest calculation for the current phase. (HASH_STR register, NBLW, and DCAL fields)
+    fn end_phase(&mut self) {
+        let nblw = (self.partial_len * 8) as u32;
+        self.write_reg(STR, nblw);
+
+        if self.partial_len > 0 {
+            let mut word = [0; 4];
+            word[..self.partial_len].copy_from_slice(&self.partial[..self.partial_len]);
+            self.write_reg(DIN, u32::from_le_bytes(word));
+            self.partial_len = 0;
+        }
+
+        self.write_reg(STR, nblw | DCAL);
+    }
+
+    /// Wait for the digest, and read it from the HASH_HRx registers.
+    fn read_digest(&mut self, out: &mut [u8]) {
+        let len = self.algorithm.output_len();
+        assert!(
+            out.len() >= len,
+            "The output buffer is too small for this digest."
+        );
+
+        while self.read_reg(SR) & DCIS == 0 {}
+
+        for (i, chunk) in out[..len].chunks_exact_mut(4).enumerate() {
+            chunk.copy_from_slice(&self.read_reg(HR0 + 4 * i).to_be_bytes());
+        }
+    }
+
+    /// Read the digest after a `write_dma()` transfer. Blocks until the calculation is complete.
+    pub fn finalize_dma(&mut self, out: &mut [u8]) {
+        self.read_digest(out);
+    }
+
+    /// Feed the whole message from `buf` using DMA, after `start()`. The digest calculation starts
+    /// automatically when the transfer completes; read it with `finalize_dma()`. `buf` must be
+    /// word-aligned.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete.
+    pub unsafe fn write_dma(
+        &mut self,
+        buf: &[u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: DmaPeriph,
+    ) {
+        assert!(
+            (buf.as_ptr() as usize).is_multiple_of(4),
+            "The HASH DMA buffer must be word-aligned."
+        );
+        assert!(self.partial_len == 0, "Don't mix `update()`, and DMA.");
+
+        // The number of valid bits in the last word must be set before the transfer.
+        self.write_reg(STR, (buf.len() % 4 * 8) as u32);
+
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | DMAE);
+
+        let (ptr, len) = (buf.as_ptr(), buf.len().div_ceil(4));
+
+        #[cfg(feature = "h7")]
+        let len = len as u32;
+        #[cfg(not(feature = "h7"))]
+        let len = len as u16;
+
+        let periph_addr = (HASH_BASE + DIN) as u32;
+
+        match dma_periph {
+            DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+            DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S32,
+                    dma::DataSize::S32,
+                    channel_cfg,
+                );
+            }
+        }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((HASH_BASE + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((HASH_BASE + offset) as *mut u32, value) }
+    }
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "digest")]
+macro_rules! make_digest {
+    ($name:ident, $algorithm:ident, $size:ident) => {
+        /// Implements the `digest` traits for this algorithm, using the HASH processor.
+        /// `Default` enables, and resets the peripheral.
+        pub struct $name {
+            hash: Hash,
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                let mut hash = Hash::new();
+                hash.start(Algorithm::$algorithm);
+                Self { hash }
+            }
+        }
+
+        impl digest::HashMarker for $name {}
+
+        impl digest::OutputSizeUser for $name {
+            type OutputSize = digest::consts::$size;
+        }
+
+        impl digest::Update for $name {
+            fn update(&mut self, data: &[u8]) {
+                self.hash.update(data);
+            }
+        }
+
+        impl digest::FixedOutput for $name {
+            fn finalize_into(mut self, out: &mut digest::Output<Self>) {
+                self.hash.finalize(out);
+            }
+        }
+
+        impl digest::Reset for $name {
+            fn reset(&mut self) {
+                self.hash.start(Algorithm::$algorithm);
+            }
+        }
+    };
+}
+
+#[cfg(feature = "digest")]
+make_digest!(Sha256, Sha256, U32);
+#[cfg(feature = "digest")]
+make_digest!(Sha224, Sha224, U28);
+#[cfg(feature = "digest")]
+make_digest!(Sha1, Sha1, U20);
+#[cfg(feature = "digest")]
+make_digest!(Md5, Md5, U16);
@@ -0,0 +1,725 @@
+//! Support for the High-Resolution Timer (HRTIM), used for switched-mode power supplies,
+//! lighting, and other digital power applications. It has a master timer, and 5 (H7), or 6 (G4)
+//! timing units, each with 2 outputs. On G4, a DLL multiplies the timer clock by up to 32,
+//! for a resolution of 184ps at 170Mhz.
+//!
+//! Each output is driven by a set/reset crossbar: Any combination of events (eg the unit's
+//! period, its compare matches, the master timer's compare matches, or external events) can set
+//! the output active, and another combination can reset it to inactive. This can express
+//! waveforms the general-purpose timers can't, like phase-shifted full bridges, and
+//! multi-phase interleaved converters.
+//!
+//! Also supports dead time insertion between complementary outputs, burst mode (idling the
+//! outputs for some periods of a burst, for light-load efficiency), and fault inputs, which
+//! force the outputs to a safe state in hardware.
+//!
+//! Set up the output pins as alternate function; AF13 on G4, and AF1 or AF2 on H7. On H7, the
+//! HRTIM is clocked from the APB2 timer clock by default.
+//!
+//! See G4 RM, chapter 27: "High-resolution timer (HRTIM)", or H743 RM, chapter 37.
+//!
+//! Example, a 100kHz buck converter at 50% duty, with a 170Mhz timer clock:
+//! ```rust
+//! let mut hrtim = Hrtim::new();
+//!
+//! // 170Mhz x 32 / 100kHz = 54,400 ticks.
+//! hrtim.set_unit(HrUnit::A, &HrUnitCfg { period: 54_400, ..Default::default() });
+//! hrtim.set_compare(HrUnit::A, HrCompare::One, 27_200);
+//!
+//! // Set at the start of each period, and reset at compare 1.
+//! hrtim.set_crossbar(HrOutput::A1, &[HrEvent::Period], &[HrEvent::Compare1]);
+//! hrtim.set_dead_time(HrUnit::A, &DeadTime { rising: 50, falling: 50, prescaler: 3 });
+//!
+//! hrtim.set_fault_input(FaultInput::F1, &FaultCfg::default());
+//! hrtim.enable_fault(HrUnit::A, FaultInput::F1);
+//! hrtim.set_fault_action(HrOutput::A1, FaultAction::Inactive);
+//! hrtim.set_fault_action(HrOutput::A2, FaultAction::Inactive);
+//!
+//! hrtim.enable_outputs(&[HrOutput::A1, HrOutput::A2]);
+//! hrtim.start(&[HrUnit::A]);
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::pac::RCC;
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since the PACs split the HRTIM into a peripheral per timing unit, with
+// different register names between families.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const HRTIM_BASE: usize = 0x4001_7400;
+    } else {
+        const HRTIM_BASE: usize = 0x4001_6800;
+    }
+}
+
+/// The offset of the common registers from the HRTIM base. Timing unit registers are at
+/// 0x80 x the unit index; the master timer is at 0.
+const COMMON: usize = 0x380;
+
+// Timer register offsets, common to the master timer and timing units.
+const TIM_CR: usize = 0x00;
+const TIM_ISR: usize = 0x04;
+const TIM_ICR: usize = 0x08;
+const TIM_CNT: usize = 0x10;
+const TIM_PER: usize = 0x14;
+const TIM_REP: usize = 0x18;
+const TIM_CMP1: usize = 0x1c;
+
+// Timing unit register offsets.
+const DT: usize = 0x38;
+const SET1: usize = 0x3c;
+const OUT: usize = 0x64;
+const FLT: usize = 0x68;
+
+// Common register offsets.
+const CR2: usize = 0x04;
+const ISR: usize = 0x08;
+const ICR: usize = 0x0c;
+const OENR: usize = 0x14;
+const ODISR: usize = 0x18;
+const ODSR: usize = 0x1c;
+const BMCR: usize = 0x20;
+const BMTRGR: usize = 0x24;
+const BMCMPR: usize = 0x28;
+const BMPER: usize = 0x2c;
+#[cfg(feature = "g4")]
+const DLLCR: usize = 0x4c;
+const FLTINR1: usize = 0x50;
+const FLTINR2: usize = 0x54;
+
+// Timer CR fields. (MCR, and TIMxCR)
+const CONT: u32 = 1 << 3;
+const PREEN: u32 = 1 << 27;
+const MREPU: u32 = 1 << 29;
+const TXREPU: u32 = 1 << 17;
+
+// SETxyR, and RSTxyR software trigger field. (SST, and SRT) Cleared by hardware.
+const SST: u32 = 1 << 0;
+
+// OUTxR fields, for output 1. Output 2's are 16 bits higher.
+const POL: u32 = 1 << 1;
+const IDLM: u32 = 1 << 2;
+const IDLES: u32 = 1 << 3;
+const FAULT_SHIFT: u32 = 4;
+const DTEN: u32 = 1 << 8;
+
+// DTxR fields.
+const DTR_SHIFT: u32 = 0;
+const DTPRSC_SHIFT: u32 = 10;
+const DTF_SHIFT: u32 = 16;
+
+// BMCR fields.
+const BME: u32 = 1 << 0;
+const BMOM: u32 = 1 << 1;
+const BMCLK_SHIFT: u32 = 2;
+const BMPRSC_SHIFT: u32 = 6;
+const BMPREN: u32 = 1 << 10;
+const BMSTAT: u32 = 1 << 31;
+
+// Fault input fields, within each fault's byte of FLTINRx.
+const FLTE: u32 = 1 << 0;
+const FLTP: u32 = 1 << 1;
+const FLTSRC: u32 = 1 << 2;
+const FLTF_SHIFT: u32 = 3;
+
+#[cfg(feature = "g4")]
+const DLLRDY: u32 = 1 << 16;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The master timer, or a timing unit. Values are the index used for register offsets, and
+/// enable bits.
+pub enum HrUnit {
+    Master = 0,
+    A = 1,
+    B = 2,
+    C = 3,
+    D = 4,
+    E = 5,
+    #[cfg(feature = "g4")]
+    F = 6,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// A timing unit output. Values are the output's bit in the OENR, and ODISR registers.
+pub enum HrOutput {
+    A1 = 0,
+    A2 = 1,
+    B1 = 2,
+    B2 = 3,
+    C1 = 4,
+    C2 = 5,
+    D1 = 6,
+    D2 = 7,
+    E1 = 8,
+    E2 = 9,
+    #[cfg(feature = "g4")]
+    F1 = 10,
+    #[cfg(feature = "g4")]
+    F2 = 11,
+}
+
+impl HrOutput {
+    /// The offset of the output's timing unit registers.
+    fn unit_offset(&self) -> usize {
+        0x80 * (*self as usize / 2 + 1)
+    }
+
+    /// 0 for output 1, and 1 for output 2.
+    fn index(&self) -> usize {
+        *self as usize % 2
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock prescaler, relative to the HRTIM clock. Sets the MCR, and TIMxCR registers, CK_PSC
+/// field. The DLL multipliers are only available on G4. The minimum period is 0x60 ticks at
+/// x32, scaled down for lower multipliers, and the maximum, 0xffdf.
+pub enum HrPrescaler {
+    #[cfg(feature = "g4")]
+    Mul32 = 0b000,
+    #[cfg(feature = "g4")]
+    Mul16 = 0b001,
+    #[cfg(feature = "g4")]
+    Mul8 = 0b010,
+    #[cfg(feature = "g4")]
+    Mul4 = 0b011,
+    #[cfg(feature = "g4")]
+    Mul2 = 0b100,
+    Div1 = 0b101,
+    Div2 = 0b110,
+    Div4 = 0b111,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// A compare register. The timing unit's compare events are named after these.
+pub enum HrCompare {
+    One = 0,
+    Two = 1,
+    Three = 2,
+    Four = 3,
+}
+
+impl HrCompare {
+    /// The register offset. CMP1CxR sits between CMP1, and CMP2.
+    fn offset(&self) -> usize {
+        match self {
+            Self::One => TIM_CMP1,
+            _ => TIM_CMP1 + 4 + 4 * *self as usize,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// An event that can set, or reset an output, using the crossbar. Sets the SETxyR, and RSTxyR
+/// registers. See the RM's timer events table for which units' compares map to which events.
+pub enum HrEvent {
+    /// A counter reset from an external event, or another timer. (RESYNC)
+    Resync,
+    /// This unit's period.
+    Period,
+    Compare1,
+    Compare2,
+    Compare3,
+    Compare4,
+    MasterPeriod,
+    MasterCompare1,
+    MasterCompare2,
+    MasterCompare3,
+    MasterCompare4,
+    /// A compare event from another timing unit, 1 - 9.
+    TimerEvent(u8),
+    /// An external event, 1 - 10.
+    ExternalEvent(u8),
+    /// A register update.
+    Update,
+}
+
+impl HrEvent {
+    /// The event's bit in the SETxyR, and RSTxyR registers.
+    fn bit(&self) -> u32 {
+        let i = match self {
+            Self::Resync => 1,
+            Self::Period => 2,
+            Self::Compare1 => 3,
+            Self::Compare2 => 4,
+            Self::Compare3 => 5,
+            Self::Compare4 => 6,
+            Self::MasterPeriod => 7,
+            Self::MasterCompare1 => 8,
+            Self::MasterCompare2 => 9,
+            Self::MasterCompare3 => 10,
+            Self::MasterCompare4 => 11,
+            Self::TimerEvent(n) => {
+                assert!((1..=9).contains(n), "Timer events are 1 - 9.");
+                11 + *n as u32
+            }
+            Self::ExternalEvent(n) => {
+                assert!((1..=10).contains(n), "External events are 1 - 10.");
+                20 + *n as u32
+            }
+            Self::Update => 31,
+        };
+        1 << i
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Output state when a fault is active. Sets OUTxR register, FAULTy field.
+pub enum FaultAction {
+    /// Faults don't affect the output.
+    NoAction = 0b00,
+    Active = 0b01,
+    Inactive = 0b10,
+    HighZ = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// A fault input. Values are the index used for register offsets, and enable bits.
+pub enum FaultInput {
+    F1 = 0,
+    F2 = 1,
+    F3 = 2,
+    F4 = 3,
+    F5 = 4,
+    #[cfg(feature = "g4")]
+    F6 = 5,
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+/// Fault input configuration. Sets the FLTINR1, and FLTINR2 registers.
+pub struct FaultCfg {
+    /// If true, the fault is active when the input is high. Defaults to false.
+    pub active_high: bool,
+    /// If true, use the internal source (a comparator output), vice the FLTx pin. Defaults
+    /// to false.
+    pub internal: bool,
+    /// Digital filter: 0 - 15. 0 disables it. See the RM for the sample counts, and clocks.
+    /// Defaults to 0.
+    pub filter: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Dead time between complementary outputs. Output 2 becomes the complement of output 1, with
+/// its set/reset crossbar ignored. Sets the DTxR register.
+pub struct DeadTime {
+    /// Dead time before output 1's rising edge, in dead time ticks: 0 - 511.
+    pub rising: u16,
+    /// Dead time before output 2's rising edge; ie after output 1's falling edge: 0 - 511.
+    pub falling: u16,
+    /// Dead time tick prescaler: 0 - 7. A tick is 2^`prescaler` / 8 HRTIM clock periods.
+    pub prescaler: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Burst mode clock source. Sets BMCR register, BMCLK field.
+pub enum BurstClock {
+    /// The master timer's counter reset, or roll-over.
+    Master = 0b0000,
+    TimerA = 0b0001,
+    TimerB = 0b0010,
+    TimerC = 0b0011,
+    TimerD = 0b0100,
+    TimerE = 0b0101,
+    #[cfg(feature = "g4")]
+    TimerF = 0b0110,
+    /// The HRTIM clock, divided by the burst prescaler.
+    Prescaled = 0b1010,
+}
+
+/// Timer configuration, for the master timer, or a timing unit.
+pub struct HrUnitCfg {
+    /// Clock prescaler. Defaults to x32 on G4, and /1 on H7.
+    pub prescaler: HrPrescaler,
+    /// Period, in (prescaled) timer ticks. Defaults to 0xffdf.
+    pub period: u16,
+    /// Repetition counter: The number of periods between repetition events, minus 1.
+    /// Defaults to 0.
+    pub repetition: u8,
+    /// If true, the counter runs continuously; otherwise, it stops after one period.
+    /// Defaults to true.
+    pub continuous: bool,
+    /// If true, period, and compare updates are buffered, and take effect at the next
+    /// repetition event. Defaults to true.
+    pub preload: bool,
+}
+
+impl Default for HrUnitCfg {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "g4")]
+            prescaler: HrPrescaler::Mul32,
+            #[cfg(feature = "h7")]
+            prescaler: HrPrescaler::Div1,
+            period: 0xffdf,
+            repetition: 0,
+            continuous: true,
+            preload: true,
+        }
+    }
+}
+
+/// Burst mode configuration. Sets the BMCR, BMCMPR, and BMPER registers.
+pub struct BurstCfg {
+    /// The clock that the burst counter counts.
+    pub clock: BurstClock,
+    /// Prescaler for `BurstClock::Prescaled`: Divides the HRTIM clock by 2^`prescaler`; 0 - 15.
+    pub prescaler: u8,
+    /// The burst period, in burst clock ticks.
+    pub period: u16,
+    /// The number of burst clock ticks the outputs idle for, at the start of each period. Must
+    /// be less than `period`.
+    pub idle: u16,
+    /// If true, bursts repeat until `stop_burst()`; otherwise, only one burst runs per trigger.
+    pub continuous: bool,
+}
+
+/// Represents the High-Resolution Timer.
+pub struct Hrtim {}
+
+impl Hrtim {
+    /// Enable and reset the HRTIM's RCC peripheral clock. On G4, this also calibrates the DLL,
+    /// and enables periodic recalibration.
+    pub fn new() -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // We use raw bits, since the HRTIM RCC fields are missing from some PACs.
+            // G4: APB2ENR, and APB2RSTR, bit 26. H7: APB2ENR, and APB2RSTR, bit 29.
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    const BIT: u32 = 1 << 29;
+                } else {
+                    const BIT: u32 = 1 << 26;
+                }
+            }
+            rcc.apb2enr.modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+            rcc.apb2rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() | BIT) });
+            rcc.apb2rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !BIT) });
+        });
+
+        let result = Self {};
+
+        // G4 RM, section 27.3.23: DLL calibration. Start a calibration, and enable periodic
+        // calibration, at the slowest rate. (DLLCR register: CAL, CALEN, and CALRTE fields)
+        #[cfg(feature = "g4")]
+        {
+            result.write_reg(COMMON + DLLCR, 0b11 << 2 | 1 << 1 | 1 << 0);
+            while result.read_reg(COMMON + ISR) & DLLRDY == 0 {}
+        }
+
+        result
+    }
+
+    /// Configure the master timer, or a timing unit. Do this with the timer stopped.
+    pub fn set_unit(&mut self, unit: HrUnit, cfg: &HrUnitCfg) {
+        let base = 0x80 * unit as usize;
+
+        let repu = if unit == HrUnit::Master {
+            MREPU
+        } else {
+            TXREPU
+        };
+
+        let mut cr = self.read_reg(base + TIM_CR) & !(0b111 | CONT | PREEN | repu);
+        cr |= cfg.prescaler as u32;
+        if cfg.continuous {
+            cr |= CONT;
+        }
+        if cfg.preload {
+            cr |= PREEN | repu;
+        }
+        self.write_reg(base + TIM_CR, cr);
+
+        self.write_reg(base + TIM_PER, cfg.period as u32);
+        self.write_reg(base + TIM_REP, cfg.repetition as u32);
+    }
+
+    /// Set a timer's period. With preload enabled, this takes effect at the next repetition
+    /// event, or `software_update()`.
+    pub fn set_period(&mut self, unit: HrUnit, period: u16) {
+        self.write_reg(0x80 * unit as usize + TIM_PER, period as u32);
+    }
+
+    /// Set a compare value. Use this to set duty cycle, or phase shift.
+    pub fn set_compare(&mut self, unit: HrUnit, compare: HrCompare, value: u16) {
+        self.write_reg(0x80 * unit as usize + compare.offset(), value as u32);
+    }
+
+    /// Read a timer's counter.
+    pub fn read_count(&self, unit: HrUnit) -> u16 {
+        self.read_reg(0x80 * unit as usize + TIM_CNT) as u16
+    }
+
+    /// Start timers. (MCR register, MCEN, and TxCEN fields)
+    pub fn start(&mut self, units: &[HrUnit]) {
+        let bits = units.iter().fold(0, |acc, u| acc | 1 << (16 + *u as u32));
+        let mcr = self.read_reg(TIM_CR);
+        self.write_reg(TIM_CR, mcr | bits);
+    }
+
+    /// Stop timers.
+    pub fn stop(&mut self, units: &[HrUnit]) {
+        let bits = units.iter().fold(0, |acc, u| acc | 1 << (16 + *u as u32));
+        let mcr = self.read_reg(TIM_CR);
+        self.write_reg(TIM_CR, mcr & !bits);
+    }
+
+    /// Transfer preloaded values to the active registers now, vice at the next update event.
+    /// (CR2 register, MSWU, and TxSWU fields)
+    pub fn software_update(&mut self, units: &[HrUnit]) {
+        let bits = units.iter().fold(0, |acc, u| acc | 1 << *u as u32);
+        self.write_reg(COMMON + CR2, bits);
+    }
+
+    /// Reset timer counters. (CR2 register, MRST, and TxRST fields)
+    pub fn reset_counters(&mut self, units: &[HrUnit]) {
+        let bits = units.iter().fold(0, |acc, u| acc | 1 << (8 + *u as u32));
+        self.write_reg(COMMON + CR2, bits);
+    }
+
+    /// Set the events that set an output active, and that reset it inactive. This replaces
+    /// any previous crossbar configuration for the output.
+    pub fn set_crossbar(&mut self, output: HrOutput, set: &[HrEvent], reset: &[HrEvent]) {
+        let set_bits = set.iter().fold(0, |acc, e| acc | e.bit());
+        let reset_bits = reset.iter().fold(0, |acc, e| acc | e.bit());
+
+        let offset = output.unit_offset() + SET1 + 8 * output.index();
+        self.write_reg(offset, set_bits);
+        self.write_reg(offset + 4, reset_bits);
+    }
+
+    /// Set an output active, from software. (SETxyR register, SST field)
+    pub fn force_set(&mut self, output: HrOutput) {
+        let offset = output.unit_offset() + SET1 + 8 * output.index();
+        let val = self.read_reg(offset);
+        self.write_reg(offset, val | SST);
+    }
+
+    /// Reset an output inactive, from software. (RSTxyR register, SRT field)
+    pub fn force_reset(&mut self, output: HrOutput) {
+        let offset = output.unit_offset() + SET1 + 8 * output.index() + 4;
+        let val = self.read_reg(offset);
+        self.write_reg(offset, val | SST);
+    }
+
+    /// Modify an output's fields in its unit's OUTxR register. Output 2's fields are 16 bits
+    /// above output 1's.
+    fn modify_out(&mut self, output: HrOutput, mask: u32, value: u32) {
+        let shift = 16 * output.index() as u32;
+        let offset = output.unit_offset() + OUT;
+        let val = self.read_reg(offset) & !(mask << shift);
+        self.write_reg(offset, val | (value & mask) << shift);
+    }
+
+    /// Set an output's polarity. If `inverted` is true, the output is low when active.
+    /// (OUTxR register, POLy field)
+    pub fn set_polarity(&mut self, output: HrOutput, inverted: bool) {
+        self.modify_out(output, POL, if inverted { POL } else { 0 });
+    }
+
+    /// Set an output's state when a fault is active. Do this before enabling the fault input,
+    /// or the output. (OUTxR register, FAULTy field)
+    pub fn set_fault_action(&mut self, output: HrOutput, action: FaultAction) {
+        self.modify_out(output, 0b11 << FAULT_SHIFT, (action as u32) << FAULT_SHIFT);
+    }
+
+    /// Set whether an output idles during burst mode, and its idle state. (OUTxR register,
+    /// IDLMy, and IDLESy fields)
+    pub fn set_burst_idle(&mut self, output: HrOutput, idle_in_burst: bool, idle_active: bool) {
+        let mut val = 0;
+        if idle_in_burst {
+            val |= IDLM;
+        }
+        if idle_active {
+            val |= IDLES;
+        }
+        self.modify_out(output, IDLM | IDLES, val);
+    }
+
+    /// Enable dead time insertion between a timing unit's outputs. Output 2 becomes the
+    /// complement of output 1. Do this with the timer stopped.
+    pub fn set_dead_time(&mut self, unit: HrUnit, dead_time: &DeadTime) {
+        assert!(unit != HrUnit::Master, "The master timer has no outputs.");
+        assert!(
+            dead_time.rising < 512 && dead_time.falling < 512,
+            "Dead times must be 0 - 511."
+        );
+        assert!(
+            dead_time.prescaler < 8,
+            "The dead time prescaler must be 0 - 7."
+        );
+
+        let base = 0x80 * unit as usize;
+
+        self.write_reg(
+            base + DT,
+            (dead_time.rising as u32) << DTR_SHIFT
+                | (dead_time.prescaler as u32) << DTPRSC_SHIFT
+                | (dead_time.falling as u32) << DTF_SHIFT,
+        );
+
+        let out = self.read_reg(base + OUT);
+        self.write_reg(base + OUT, out | DTEN);
+    }
+
+    /// Disable dead time insertion. Output 2 is driven by its own crossbar again.
+    pub fn disable_dead_time(&mut self, unit: HrUnit) {
+        let base = 0x80 * unit as usize;
+        let out = self.read_reg(base + OUT);
+        self.write_reg(base + OUT, out & !DTEN);
+    }
+
+    /// Enable outputs. (OENR register)
+    pub fn enable_outputs(&mut self, outputs: &[HrOutput]) {
+        let bits = outputs.iter().fold(0, |acc, o| acc | 1 << *o as u32);
+        self.write_reg(COMMON + OENR, bits);
+    }
+
+    /// Disable outputs, setting them to their idle state. (ODISR register)
+    pub fn disable_outputs(&mut self, outputs: &[HrOutput]) {
+        let bits = outputs.iter().fold(0, |acc, o| acc | 1 << *o as u32);
+        self.write_reg(COMMON + ODISR, bits);
+    }
+
+    /// Returns true if an output is disabled; eg by `disable_outputs()`, or a fault. (ODSR
+    /// register)
+    pub fn output_disabled(&self, output: HrOutput) -> bool {
+        self.read_reg(COMMON + ODSR) & (1 << output as u32) != 0
+    }
+
+    /// Configure a fault input, and enable it. Route it to timing units with `enable_fault()`.
+    pub fn set_fault_input(&mut self, input: FaultInput, cfg: &FaultCfg) {
+        assert!(cfg.filter < 16, "The fault filter must be 0 - 15.");
+
+        let mut val = FLTE | (cfg.filter as u32) << FLTF_SHIFT;
+        if cfg.active_high {
+            val |= FLTP;
+        }
+        if cfg.internal {
+            val |= FLTSRC;
+        }
+
+        // Faults 1 - 4 use a byte each of FLTINR1; 5 and 6 use FLTINR2.
+        let (offset, shift) = if (input as u8) < 4 {
+            (COMMON + FLTINR1, 8 * input as u32)
+        } else {
+            (COMMON + FLTINR2, 8 * (input as u32 - 4))
+        };
+
+        // The polarity, source, and filter must be set before enabling the input.
+        let reg = self.read_reg(offset) & !(0xff << shift);
+        self.write_reg(offset, reg | (val & !FLTE) << shift);
+        self.write_reg(offset, reg | val << shift);
+    }
+
+    /// Make a fault input affect a timing unit's outputs, as set by `set_fault_action()`.
+    /// (FLTxR register)
+    pub fn enable_fault(&mut self, unit: HrUnit, input: FaultInput) {
+        assert!(unit != HrUnit::Master, "The master timer has no outputs.");
+        let offset = 0x80 * unit as usize + FLT;
+        let val = self.read_reg(offset);
+        self.write_reg(offset, val | 1 << input as u32);
+    }
+
+    /// Returns true if a fault has occurred on this input. (ISR register, FLTx fields)
+    pub fn fault_occurred(&self, input: FaultInput) -> bool {
+        self.read_reg(COMMON + ISR) & (1 << fault_isr_bit(input)) != 0
+    }
+
+    /// Clear a fault flag. Once the fault input is inactive, re-enable the outputs with
+    /// `enable_outputs()`.
+    pub fn clear_fault(&mut self, input: FaultInput) {
+        self.write_reg(COMMON + ICR, 1 << fault_isr_bit(input));
+    }
+
+    /// Configure burst mode, with a given set of timers' counters stopped during the idle
+    /// period. Set which outputs idle with `set_burst_idle()`. Start it with `start_burst()`.
+    pub fn set_burst(&mut self, cfg: &BurstCfg, stopped_units: &[HrUnit]) {
+        assert!(cfg.prescaler < 16, "The burst prescaler must be 0 - 15.");
+        assert!(
+            cfg.idle < cfg.period,
+            "The burst idle time must be less than its period."
+        );
+
+        let mut bmcr =
+            (cfg.clock as u32) << BMCLK_SHIFT | (cfg.prescaler as u32) << BMPRSC_SHIFT | BMPREN;
+        if cfg.continuous {
+            bmcr |= BMOM;
+        }
+        bmcr |= stopped_units
+            .iter()
+            .fold(0, |acc, u| acc | 1 << (16 + *u as u32));
+
+        self.write_reg(COMMON + BMCR, bmcr);
+        self.write_reg(COMMON + BMCMPR, cfg.idle as u32);
+        self.write_reg(COMMON + BMPER, cfg.period as u32);
+    }
+
+    /// Enable burst mode, and trigger it from software. (BMCR register, BME field, and BMTRGR
+    /// register, SW field)
+    pub fn start_burst(&mut self) {
+        let bmcr = self.read_reg(COMMON + BMCR);
+        self.write_reg(COMMON + BMCR, bmcr | BME);
+        self.write_reg(COMMON + BMTRGR, 1);
+    }
+
+    /// Disable burst mode. Outputs resume normal operation at the end of the current burst.
+    pub fn stop_burst(&mut self) {
+        let bmcr = self.read_reg(COMMON + BMCR);
+        self.write_reg(COMMON + BMCR, bmcr & !BME);
+    }
+
+    /// Returns true if a burst is in progress; ie outputs are idling. (BMCR register, BMSTAT
+    /// field)
+    pub fn burst_active(&self) -> bool {
+        self.read_reg(COMMON + BMCR) & BMSTAT != 0
+    }
+
+    /// Returns true if a timer's interrupt flag is set. `bit` is the flag's bit in the MISR,
+    /// or TIMxISR register; eg 0 - 3 for compares 1 - 4, and 4 for repetition.
+    pub fn unit_flag(&self, unit: HrUnit, bit: u8) -> bool {
+        self.read_reg(0x80 * unit as usize + TIM_ISR) & (1 << bit) != 0
+    }
+
+    /// Clear a timer's interrupt flag. See `unit_flag()`.
+    pub fn clear_unit_flag(&mut self, unit: HrUnit, bit: u8) {
+        self.write_reg(0x80 * unit as usize + TIM_ICR, 1 << bit);
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((HRTIM_BASE + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((HRTIM_BASE + offset) as *mut u32, value) }
+    }
+}
+
+impl Default for Hrtim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fault input's flag bit in the common ISR, and ICR registers. On G4, FLT6 follows the
+/// system fault flag.
+fn fault_isr_bit(input: FaultInput) -> u32 {
+    match input as u32 {
+        i @ 0..=4 => i,
+        _ => 6,
+    }
+}
@@ -12,10 +12,17 @@ use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
     clocks::Clocks,
+    low_power::BusyPeriph,
     pac::{self, RCC},
     util::RccPeriph,
 };
 
+#[cfg(not(any(feature = "f3", feature = "h7")))]
+use crate::clocks::I2cPeriph;
+
+#[cfg(feature = "async")]
+use crate::asynch::Signal;
+
 use cfg_if::cfg_if;
 
 #[cfg(not(feature = "l552"))]
@@ -147,6 +154,10 @@ pub struct I2cConfig {
     /// Optionally disable clock stretching. Defaults to false (stretching allowed).
     /// Only relevant in slave mode.
     pub nostretch: bool,
+    /// Keep the I2C's clock enabled in Stop mode, and wake from Stop on address match. (Slave
+    /// mode) The I2C kernel clock must be HSI (or CSI on H7). On H7, this enables autonomous mode
+    /// for I2C4. See `enable_wakeup()`. Defaults to false.
+    pub run_in_stop: bool,
 }
 
 impl Default for I2cConfig {
@@ -158,16 +169,76 @@ impl Default for I2cConfig {
             noise_filter: NoiseFilter::Analog,
             smbus: false,
             nostretch: false,
+            run_in_stop: false,
         }
     }
 }
 
+#[derive(Clone, Copy)]
+/// Raw configuration register values, saved with `I2c::snapshot()`, and restored with `I2c::restore()`.
+/// Use this to restore the peripheral's configuration after its registers are lost, without calling
+/// `I2c::new()` again: eg after Stop 2 on some families, or the peripheral's power domain being
+/// switched off. Waking from Standby is a reset, which also loses SRAM; keep the snapshot in
+/// retained SRAM (eg SRAM2 with `RRS` set), or store `to_raw()` in backup registers in that case.
+/// Note that timing is stored as raw `TIMINGR` values; if the I2C kernel clock changes on wakeup,
+/// call `new()` instead.
+pub struct ConfigSnapshot {
+    /// CR1, CR2, OAR1, OAR2, TIMINGR, and TIMEOUTR
+    regs: [u32; 6],
+}
+
+impl ConfigSnapshot {
+    /// The raw register values: CR1, CR2, OAR1, OAR2, TIMINGR, and TIMEOUTR. Use this to store
+    /// the snapshot, eg in backup registers.
+    pub fn to_raw(&self) -> [u32; 6] {
+        self.regs
+    }
+
+    /// Load a snapshot from values returned by `to_raw()`.
+    pub fn from_raw(regs: [u32; 6]) -> Self {
+        Self { regs }
+    }
+}
+
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
 pub struct I2c<R> {
     pub regs: R,
     pub cfg: I2cConfig,
 }
 
+#[cfg(not(any(feature = "f3", feature = "h7")))]
+/// Get the I2C kernel clock frequency, from its selection in RCC_CCIPR. (I2C2 on G0 is always
+/// clocked by PCLK)
+fn kernel_clock(regs: *const pac::i2c1::RegisterBlock, clocks: &Clocks) -> u32 {
+    if regs == pac::I2C1::ptr() {
+        return clocks.i2c_kernel(I2cPeriph::I2c1);
+    }
+
+    #[cfg(not(feature = "g0"))]
+    if regs == pac::I2C3::ptr() {
+        return clocks.i2c_kernel(I2cPeriph::I2c3);
+    }
+
+    #[cfg(any(
+        feature = "l5",
+        feature = "g471",
+        feature = "g473",
+        feature = "g474",
+        feature = "g483",
+        feature = "g484"
+    ))]
+    if regs == pac::I2C4::ptr() {
+        return clocks.i2c_kernel(I2cPeriph::I2c4);
+    }
+
+    #[cfg(feature = "g0")]
+    return clocks.apb1();
+    #[cfg(feature = "wb")]
+    return clocks.i2c_kernel(I2cPeriph::I2c3);
+    #[cfg(not(any(feature = "g0", feature = "wb")))]
+    return clocks.i2c_kernel(I2cPeriph::I2c2);
+}
+
 impl<R> I2c<R>
 where
     R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
@@ -194,7 +265,20 @@ where
         // programming the PRESC[3:0], SCLH[7:0] and SCLL[7:0] bits in the I2C_TIMINGR register
 
         // For these speed and frequency variables, we use the RM's conventions.
-        let t_i2cclk = clocks.apb1();
+        // On H7, I2C4 is in the D3 domain, and is clocked by PCLK4 by default, vice PCLK1.
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let t_i2cclk = if core::ptr::eq(&*regs, pac::I2C4::ptr() as *const _) {
+                    clocks.apb4()
+                } else {
+                    clocks.apb1()
+                };
+            } else if #[cfg(feature = "f3")] {
+                let t_i2cclk = clocks.apb1();
+            } else {
+                let t_i2cclk = kernel_clock(&*regs as *const _, clocks);
+            }
+        }
 
         // assert!(t_i2cclk < (t_low - f_f) / 4);
         // assert!(t_i2cclk < t_high);
@@ -361,6 +445,14 @@ where
         // Enable the peripheral
         result.regs.cr1.write(|w| w.pe().set_bit());
 
+        if result.cfg.run_in_stop {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::en_in_stop(rcc);
+            });
+            result.enable_wakeup(true);
+        }
+
         result
     }
 
@@ -395,6 +487,85 @@ where
         }
     }
 
+    /// Save the peripheral's configuration registers. Run this before entering a low-power
+    /// mode where register contents may be lost. See `ConfigSnapshot` for where to store it.
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        ConfigSnapshot {
+            regs: [
+                self.regs.cr1.read().bits(),
+                self.regs.cr2.read().bits(),
+                self.regs.oar1.read().bits(),
+                self.regs.oar2.read().bits(),
+                self.regs.timingr.read().bits(),
+                self.regs.timeoutr.read().bits(),
+            ],
+        }
+    }
+
+    /// Restore the peripheral's configuration, from a snapshot taken with `snapshot()`. Re-enables and
+    /// resets the RCC peripheral clock, writes the configuration registers with the I2C disabled, then
+    /// enables it. Run this after waking from a low-power mode.
+    pub fn restore(&mut self, snapshot: &ConfigSnapshot) {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::en_reset(rcc);
+        });
+
+        let [cr1, cr2, oar1, oar2, timingr, timeoutr] = snapshot.regs;
+
+        // Timing and filter settings must be configured with the peripheral disabled.
+        self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+        self.regs.timingr.write(|w| unsafe { w.bits(timingr) });
+        self.regs.timeoutr.write(|w| unsafe { w.bits(timeoutr) });
+        self.regs.oar1.write(|w| unsafe { w.bits(oar1) });
+        self.regs.oar2.write(|w| unsafe { w.bits(oar2) });
+        // Don't restore the START or STOP bits, which would start a transfer.
+        self.regs
+            .cr2
+            .write(|w| unsafe { w.bits(cr2 & !((1 << 13) | (1 << 14))) });
+        self.regs.cr1.write(|w| unsafe { w.bits(cr1 & !1) });
+        self.regs.cr1.modify(|_, w| w.pe().bit(cr1 & 1 != 0));
+    }
+
+    /// Enable or disable wakeup from Stop mode on address match. (Slave mode) The I2C kernel clock
+    /// must be HSI (or CSI on H7) for this to work, since the other clocks stop. See H743 RM, section
+    /// 47.4.16: Wakeup from Stop mode on address match.
+    pub fn enable_wakeup(&mut self, enable: bool) {
+        // WUPEN can only be changed while the peripheral is disabled.
+        let originally_enabled = self.regs.cr1.read().pe().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+            while self.regs.cr1.read().pe().bit_is_set() {}
+        }
+
+        self.regs.cr1.modify(|_, w| w.wupen().bit(enable));
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().set_bit());
+        }
+    }
+
+    #[cfg(all(feature = "h7", not(feature = "h7b3")))]
+    /// Enable or disable autonomous mode for I2C4, and the BDMA and SRAM4 it uses. In autonomous mode,
+    /// the D3 domain keeps their clocks running while the CPU's domain is in Stop mode, so a sensor can
+    /// be polled using the BDMA, eg triggered by LPTIM. See H743 RM, section 8.5.7: Peripheral
+    /// allocation, and 8.7.33: RCC D3 Autonomous mode Register.
+    pub fn enable_autonomous(&mut self, enable: bool) {
+        assert!(
+            core::ptr::eq(&*self.regs, pac::I2C4::ptr() as *const _),
+            "Autonomous mode is only available on I2C4."
+        );
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.d3amr.modify(|_, w| {
+                w.i2c4amen().bit(enable);
+                w.bdmaamen().bit(enable);
+                w.sram4amen().bit(enable)
+            });
+        });
+    }
+
     /// Read multiple words to a buffer. Can return an error due to Bus, Arbitration, or NACK.
     pub fn read(&mut self, addr: u8, bytes: &mut [u8]) -> Result<(), Error> {
         // Wait for any previous address sequence to end
@@ -707,6 +878,72 @@ where
         }
     }
 
+    #[cfg(all(feature = "h7", not(feature = "h7b3")))]
+    /// Write data using the BDMA. I2C4 is in the D3 domain, and can only use the BDMA; its
+    /// requests are routed through DMAMUX2. `buf` must be in SRAM4. Enable the BDMA with
+    /// `dma::enable_bdma()`, and route the request with `dma::mux2()`, using `DmaInput2::I2c4Tx`,
+    /// first.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete.
+    pub unsafe fn write_bdma(
+        &mut self,
+        addr: u8,
+        buf: &[u8],
+        autoend: bool,
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+    ) {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+
+        self.regs.cr1.modify(|_, w| w.txdmaen().set_bit());
+        while self.regs.cr1.read().txdmaen().bit_is_clear() {}
+
+        self.set_cr2_write(addr, len as u8, autoend);
+
+        dma::cfg_channel_bdma(
+            channel,
+            &self.regs.txdr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromMem,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+    }
+
+    #[cfg(all(feature = "h7", not(feature = "h7b3")))]
+    /// Read data using the BDMA. See the note on `write_bdma()`; use `DmaInput2::I2c4Rx`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete.
+    pub unsafe fn read_bdma(
+        &mut self,
+        addr: u8,
+        buf: &mut [u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        self.regs.cr1.modify(|_, w| w.rxdmaen().set_bit());
+        while self.regs.cr1.read().rxdmaen().bit_is_clear() {}
+
+        self.set_cr2_read(addr, len as u8);
+
+        dma::cfg_channel_bdma(
+            channel,
+            &self.regs.rxdr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromPeriph,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+    }
+
     // /// Write, and read data, using DMA. This is the primary read api.
     // /// See L44 RM, 37.4.16: "Reception using DMA"
     // /// Note that the `channel` argument is only used on F3 and L4; on other platforms,
@@ -789,6 +1026,16 @@ where
     // }
 }
 
+impl<R> BusyPeriph for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// Returns true if a communication is in progress on the bus. (ISR register, BUSY field)
+    fn is_busy(&self) -> bool {
+        self.regs.isr.read().busy().bit_is_set()
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> Write for I2c<R>
@@ -827,3 +1074,267 @@ where
         I2c::write_read(self, addr, bytes, buffer)
     }
 }
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::i2c::Error for Error {
+    fn kind(&self) -> embedded_hal_1::i2c::ErrorKind {
+        use embedded_hal_1::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        match self {
+            Self::Bus => ErrorKind::Bus,
+            Self::Arbitration => ErrorKind::ArbitrationLoss,
+            Self::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::i2c::ErrorType for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+/// Transfer parameters for an operation in an `embedded-hal` 1.0 I2C transaction.
+#[cfg(feature = "embedded-hal-1")]
+struct OpParams {
+    read: bool,
+    len: u8,
+    /// The first in a group of adjacent operations of the same type. It's preceded by a START,
+    /// or a repeated start.
+    first_in_group: bool,
+    /// RELOAD continues the transfer after NBYTES, instead of ending it.
+    reload: bool,
+    last: bool,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl OpParams {
+    fn new(operations: &[embedded_hal_1::i2c::Operation<'_>], i: usize) -> Self {
+        use embedded_hal_1::i2c::Operation;
+
+        let is_read = |op: &Operation| matches!(op, Operation::Read(_));
+        let read = is_read(&operations[i]);
+        let last = i == operations.len() - 1;
+
+        Self {
+            read,
+            len: match &operations[i] {
+                Operation::Read(buf) => buf.len(),
+                Operation::Write(buf) => buf.len(),
+            } as u8,
+            first_in_group: i == 0 || is_read(&operations[i - 1]) != read,
+            reload: !last && is_read(&operations[i + 1]) == read,
+            last,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// Start an operation in a transaction. For the first in a group, wait for the previous group
+    /// to complete (TC) before calling this; for others, wait for a reload (TCR).
+    fn start_op(&mut self, addr: u8, params: &OpParams) {
+        if params.first_in_group {
+            self.regs.cr2.write(|w| unsafe {
+                w.add10().bit(self.cfg.address_bits as u8 != 0);
+                w.sadd().bits(u16(addr << 1));
+                w.rd_wrn().bit(params.read);
+                w.nbytes().bits(params.len);
+                w.reload().bit(params.reload);
+                w.autoend().bit(params.last);
+                w.start().set_bit()
+            });
+        } else {
+            self.regs.cr2.modify(|_, w| unsafe {
+                w.nbytes().bits(params.len);
+                w.reload().bit(params.reload);
+                w.autoend().bit(params.last)
+            });
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::i2c::I2c for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// Adjacent operations of the same type are sent as a single transfer, using reload mode, and
+    /// operations of different types are separated by a repeated start. A STOP is sent after the
+    /// last operation. Each operation is limited to 255 bytes.
+    fn transaction(
+        &mut self,
+        addr: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error> {
+        use embedded_hal_1::i2c::Operation;
+
+        // Wait for any previous address sequence to end automatically.
+        while self.regs.cr2.read().start().bit_is_set() {}
+
+        for i in 0..operations.len() {
+            let params = OpParams::new(operations, i);
+
+            if !params.first_in_group {
+                busy_wait!(self.regs, tcr);
+            } else if i != 0 {
+                busy_wait!(self.regs, tc);
+            }
+
+            self.start_op(addr, &params);
+
+            match &mut operations[i] {
+                Operation::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        busy_wait!(self.regs, rxne);
+                        *byte = self.regs.rxdr.read().rxdata().bits();
+                    }
+                }
+                Operation::Write(buf) => {
+                    for byte in buf.iter() {
+                        busy_wait!(self.regs, txis);
+                        self.regs.txdr.write(|w| unsafe { w.txdata().bits(*byte) });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// I2C_CR1 interrupt enable bits used by `I2cAsync`: TXIE, RXIE, NACKIE, TCIE, and ERRIE.
+#[cfg(feature = "async")]
+const CR1_ASYNC_IRQS: u32 = (1 << 1) | (1 << 2) | (1 << 4) | (1 << 6) | (1 << 7);
+
+#[cfg(feature = "async")]
+/// Handle an I2C event or error interrupt, for `I2cAsync`. Call this from the peripheral's ISRs.
+/// Masks the interrupts enabled by `I2cAsync`, and signals the waiting task.
+pub fn on_interrupt_async(regs: &pac::i2c1::RegisterBlock, signal: &Signal) {
+    regs.cr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_ASYNC_IRQS) });
+    signal.signal();
+}
+
+#[cfg(feature = "async")]
+/// An I2C peripheral, which implements the `embedded-hal-async` `I2c` trait. Transfers are driven by
+/// the peripheral's interrupts; its ISRs must call `on_interrupt_async()`, with the same `Signal`.
+pub struct I2cAsync<R> {
+    pub i2c: I2c<R>,
+    signal: &'static Signal,
+}
+
+#[cfg(feature = "async")]
+impl<R> I2cAsync<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    pub fn new(i2c: I2c<R>, signal: &'static Signal) -> Self {
+        Self { i2c, signal }
+    }
+
+    /// Wait until a status flag is set, or an error occurs. Similar to `busy_wait!`, but yields
+    /// to the executor.
+    async fn wait_flag<F>(&mut self, flag: F) -> Result<(), Error>
+    where
+        F: Fn(&pac::i2c1::isr::R) -> bool,
+    {
+        let regs = &self.i2c.regs;
+
+        loop {
+            let isr = regs.isr.read();
+
+            if flag(&isr) {
+                return Ok(());
+            } else if isr.berr().bit_is_set() {
+                regs.icr.write(|w| w.berrcf().set_bit());
+                return Err(Error::Bus);
+            } else if isr.arlo().bit_is_set() {
+                regs.icr.write(|w| w.arlocf().set_bit());
+                return Err(Error::Arbitration);
+            } else if isr.nackf().bit_is_set() {
+                regs.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+
+                // If a pending TXIS flag is set, write dummy data to TXDR
+                if regs.isr.read().txis().bit_is_set() {
+                    regs.txdr.write(|w| unsafe { w.txdata().bits(0) });
+                }
+
+                // If TXDR is not flagged as empty, write 1 to flush it
+                if regs.isr.read().txe().bit_is_clear() {
+                    regs.isr.write(|w| w.txe().set_bit());
+                }
+
+                return Err(Error::Nack);
+            }
+
+            // The ISR masks these again when it fires.
+            self.signal.reset();
+            regs.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR1_ASYNC_IRQS) });
+            self.signal.wait().await;
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_hal_1::i2c::ErrorType for I2cAsync<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_hal_async::i2c::I2c for I2cAsync<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+{
+    /// See the notes on the blocking `transaction()` implementation.
+    async fn transaction(
+        &mut self,
+        addr: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error> {
+        use embedded_hal_1::i2c::Operation;
+
+        while self.i2c.regs.cr2.read().start().bit_is_set() {}
+
+        for i in 0..operations.len() {
+            let params = OpParams::new(operations, i);
+
+            if !params.first_in_group {
+                self.wait_flag(|isr| isr.tcr().bit_is_set()).await?;
+            } else if i != 0 {
+                self.wait_flag(|isr| isr.tc().bit_is_set()).await?;
+            }
+
+            self.i2c.start_op(addr, &params);
+
+            match &mut operations[i] {
+                Operation::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        self.wait_flag(|isr| isr.rxne().bit_is_set()).await?;
+                        *byte = self.i2c.regs.rxdr.read().rxdata().bits();
+                    }
+                }
+                Operation::Write(buf) => {
+                    for byte in buf.iter() {
+                        self.wait_flag(|isr| isr.txis().bit_is_set()).await?;
+                        self.i2c
+                            .regs
+                            .txdr
+                            .write(|w| unsafe { w.txdata().bits(*byte) });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
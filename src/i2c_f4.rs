@@ -14,6 +14,7 @@ use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
     clocks::Clocks,
+    low_power::BusyPeriph,
     pac::{self, i2c1, RCC},
     util::rcc_en_reset,
 };
@@ -252,6 +253,16 @@ where
     }
 }
 
+impl<R> BusyPeriph for I2c<R>
+where
+    R: Deref<Target = i2c1::RegisterBlock>,
+{
+    /// Returns true if a communication is in progress on the bus. (SR2 register, BUSY field)
+    fn is_busy(&self) -> bool {
+        self.regs.sr2.read().busy().bit_is_set()
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> WriteRead for I2c<R>
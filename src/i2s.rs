@@ -0,0 +1,553 @@
+//! Support for the Inter-IC Sound (I2S) protocol, using SPI peripherals in I2S mode. Used to
+//! send and receive audio data, eg to and from codecs and DACs like the CS43L22.
+//!
+//! The audio frequency is set from the I2S kernel clock, read from `Clocks::i2s_kernel()`. On F4,
+//! this is PLLI2S, configured with the `plli2s_enabled`, `plli2sn`, and `plli2sr` fields of
+//! `Clocks`. On G0 and G4, select the source with `clocks::set_i2s_clk_src()`; it defaults to
+//! SYSCLK.
+
+use core::{ops::Deref, ptr};
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    clocks::Clocks,
+    pac::{self, RCC},
+    util::RccPeriph,
+};
+
+#[cfg(not(feature = "f4"))]
+use crate::dma::{self, ChannelCfg, DmaChannel};
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
+        use crate::pac::DMA as DMA1;
+    } else if #[cfg(not(feature = "f4"))] {
+        use crate::pac::DMA1;
+    }
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+/// A DMA controller. The `dma` module isn't available on F4, so I2S configures its streams
+/// directly.
+pub enum DmaPeriph {
+    Dma1,
+    Dma2,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// A DMA stream. Each SPI request is available on fixed streams and channels; see the RM's DMA
+/// request mapping table. eg SPI2_TX is DMA1 stream 4, channel 0, and SPI3_TX is DMA1 stream 5 or
+/// 7, channel 0.
+pub enum DmaStream {
+    S0 = 0,
+    S1 = 1,
+    S2 = 2,
+    S3 = 3,
+    S4 = 4,
+    S5 = 5,
+    S6 = 6,
+    S7 = 7,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2S configuration mode. Sets `SPI_I2SCFGR` register, `I2SCFG` field.
+pub enum I2sMode {
+    SlaveTransmit = 0b00,
+    SlaveReceive = 0b01,
+    MasterTransmit = 0b10,
+    MasterReceive = 0b11,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// I2S standard selection. Sets `SPI_I2SCFGR` register, `I2SSTD` field.
+pub enum I2sStandard {
+    /// I2S Philips standard
+    Philips = 0b00,
+    /// MSB justified standard (left justified)
+    MsbJustified = 0b01,
+    /// LSB justified standard (right justified)
+    LsbJustified = 0b10,
+    /// PCM standard
+    Pcm = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Data length, and channel length. Sets `SPI_I2SCFGR` register, `DATLEN` and `CHLEN` fields.
+pub enum DataFormat {
+    /// 16-bit data, in a 16-bit channel.
+    D16Ch16,
+    /// 16-bit data, in a 32-bit channel.
+    D16Ch32,
+    /// 24-bit data, in a 32-bit channel.
+    D24Ch32,
+    /// 32-bit data, in a 32-bit channel.
+    D32Ch32,
+}
+
+impl DataFormat {
+    /// `DATLEN` and `CHLEN` field values.
+    fn bits(&self) -> (u8, bool) {
+        match self {
+            Self::D16Ch16 => (0b00, false),
+            Self::D16Ch32 => (0b00, true),
+            Self::D24Ch32 => (0b01, true),
+            Self::D32Ch32 => (0b10, true),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Steady state clock polarity. Sets `SPI_I2SCFGR` register, `CKPOL` field.
+pub enum ClockPolarity {
+    IdleLow = 0,
+    IdleHigh = 1,
+}
+
+/// Configuration data for I2S.
+pub struct I2sConfig {
+    /// Master or slave, transmit or receive. Defaults to master transmit.
+    pub mode: I2sMode,
+    /// I2S standard. Defaults to Philips.
+    pub standard: I2sStandard,
+    /// Data and channel lengths. Defaults to 16-bit data in a 16-bit channel.
+    pub data_format: DataFormat,
+    /// Clock polarity. Defaults to idle low.
+    pub clock_polarity: ClockPolarity,
+    /// Output the master clock (MCK) on its pin, at 256 x the audio frequency. Required by many
+    /// codecs. Defaults to false.
+    pub master_clock_output: bool,
+    /// The audio (sample) frequency, in Hz. Only used in master mode. Defaults to 48kHz.
+    pub audio_freq: u32,
+    /// The frequency of an external clock on the I2S_CKIN pin, in Hz. Used as the kernel clock in
+    /// master mode when `Clocks::i2s_kernel()` can't determine it. Defaults to `None`.
+    pub ckin_freq: Option<u32>,
+}
+
+impl Default for I2sConfig {
+    fn default() -> Self {
+        Self {
+            mode: I2sMode::MasterTransmit,
+            standard: I2sStandard::Philips,
+            data_format: DataFormat::D16Ch16,
+            clock_polarity: ClockPolarity::IdleLow,
+            master_clock_output: false,
+            audio_freq: 48_000,
+            ckin_freq: None,
+        }
+    }
+}
+
+/// Represents an SPI peripheral, configured in I2S mode.
+pub struct I2s<R> {
+    pub regs: R,
+    pub cfg: I2sConfig,
+}
+
+impl<R> I2s<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Initialize an SPI peripheral in I2S mode, including configuration register writes, and enabling
+    /// and resetting its RCC peripheral clock. The audio frequency is computed from
+    /// `clock_cfg.i2s_kernel()`, or from `cfg.ckin_freq` if that's unavailable.
+    pub fn new(regs: R, cfg: I2sConfig, clock_cfg: &Clocks) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::en_reset(rcc);
+        });
+
+        // G4 RM, section 39.7.9: I2S clock generator.
+        // When the master clock is generated (MCKOE = 1):
+        // Fs = I2SxCLK / (256 x (2 x I2SDIV + ODD))
+        // When the master clock is disabled (MCKOE = 0):
+        // Fs = I2SxCLK / (32 x (CHLEN + 1) x (2 x I2SDIV + ODD))
+        let (datlen, chlen) = cfg.data_format.bits();
+        let i2s_clock = match clock_cfg.i2s_kernel().or(cfg.ckin_freq) {
+            Some(freq) => freq,
+            // The prescaler is only used in master mode.
+            None if cfg.mode as u8 & 0b10 == 0 => 0,
+            None => panic!("Unknown I2S kernel clock. Set `ckin_freq` if clocked from I2S_CKIN."),
+        };
+
+        let mult = if cfg.master_clock_output {
+            256
+        } else if chlen {
+            64
+        } else {
+            32
+        };
+
+        // Round to the nearest value, using fixed point with one decimal place.
+        let div_val = ((i2s_clock * 10) / (cfg.audio_freq * mult) + 5) / 10;
+
+        // "I2SDIV [7:0] = 0 or I2SDIV [7:0] = 1 are forbidden values."
+        let (i2sdiv, odd) = if div_val < 4 {
+            (2, false)
+        } else if div_val > 511 {
+            (255, true)
+        } else {
+            ((div_val / 2) as u8, div_val % 2 == 1)
+        };
+
+        // G4 RM, section 39.7.11: I2S master mode / I2S slave mode.
+        // 1. Select the I2SDIV[7:0] bits in the SPIx_I2SPR register to define the serial clock baud
+        // rate to reach the proper audio sample frequency. The ODD bit in the SPIx_I2SPR register
+        // also has to be defined.
+        regs.i2spr.write(|w| unsafe {
+            w.i2sdiv().bits(i2sdiv);
+            w.odd().bit(odd);
+            // 3. Set the MCKOE bit in the SPIx_I2SPR register if the master clock MCK needs to be
+            // provided to the external DAC/ADC audio component.
+            w.mckoe().bit(cfg.master_clock_output)
+        });
+
+        // 2. Select the CKPOL bit to define the steady level for the communication clock.
+        // 4. Set the I2SMOD bit in the SPIx_I2SCFGR register to activate the I2S functions and
+        // choose the I2S standard through the I2SSTD[1:0] and PCMSYNC bits, the data length
+        // through the DATLEN[1:0] bits and the number of bits per channel by configuring the CHLEN
+        // bit. Select also the I2S master mode and direction (Transmitter or Receiver) through the
+        // I2SCFG[1:0] bits in the SPIx_I2SCFGR register.
+        regs.i2scfgr.write(|w| unsafe {
+            w.i2smod().set_bit();
+            w.i2sstd().bits(cfg.standard as u8);
+            w.datlen().bits(datlen);
+            w.chlen().bit(chlen);
+            w.ckpol().bit(cfg.clock_polarity as u8 != 0);
+            w.i2scfg().bits(cfg.mode as u8)
+        });
+
+        // 6. The I2SE bit in SPIx_I2SCFGR register must be set.
+        regs.i2scfgr.modify(|_, w| w.i2se().set_bit());
+
+        Self { regs, cfg }
+    }
+
+    /// Disable the peripheral. G4 RM: "To switch off the I2S, by clearing I2SE, it is mandatory to wait
+    /// for TXE = 1 and BSY = 0."
+    pub fn disable(&mut self) {
+        while self.regs.sr.read().txe().bit_is_clear() {}
+        while self.regs.sr.read().bsy().bit_is_set() {}
+        self.regs.i2scfgr.modify(|_, w| w.i2se().clear_bit());
+    }
+
+    /// Write 16-bit words, blocking until complete. Samples alternate between left and right channels.
+    /// For 24 and 32-bit data formats, each sample is 2 words: The most significant half first.
+    pub fn write(&mut self, words: &[u16]) {
+        for word in words {
+            while self.regs.sr.read().txe().bit_is_clear() {}
+            unsafe { ptr::write_volatile(self.regs.dr.as_ptr() as *mut u16, *word) };
+        }
+    }
+
+    /// Read 16-bit words to a buffer, blocking until complete. See `write()` for the data layout.
+    pub fn read(&mut self, buf: &mut [u16]) {
+        for word in buf.iter_mut() {
+            while self.regs.sr.read().rxne().bit_is_clear() {}
+            *word = unsafe { ptr::read_volatile(self.regs.dr.as_ptr() as *const u16) };
+        }
+    }
+
+    /// Transmit data using DMA. Use a circular channel config, and the half-transfer and transfer
+    /// complete interrupts, for continuous audio streaming. Note that the `channel` argument is unused
+    /// on F3, since it is hard-coded, and can't be configured using the DMAMUX peripheral.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or in
+    /// circular mode, until `stop_dma()` is called.
+    #[cfg(not(feature = "f4"))]
+    pub unsafe fn write_dma(
+        &mut self,
+        buf: &[u16],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+
+        #[cfg(feature = "f3")]
+        let channel = R::write_chan();
+
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S16,
+                    dma::DataSize::S16,
+                    channel_cfg,
+                );
+            }
+            #[cfg(not(any(
+                feature = "f3x4",
+                all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+            )))]
+            dma::DmaPeriph::Dma2 => {
+                // On G0B1 and G0C1, DMA2's register block is a separate type, with the same
+                // layout as DMA1's first 5 channels.
+                #[cfg(any(feature = "g0b1", feature = "g0c1"))]
+                let mut regs = unsafe { &*(pac::DMA2::ptr() as *const pac::dma1::RegisterBlock) };
+                #[cfg(not(any(feature = "g0b1", feature = "g0c1")))]
+                let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S16,
+                    dma::DataSize::S16,
+                    channel_cfg,
+                );
+            }
+        }
+
+        self.regs.cr2.modify(|_, w| w.txdmaen().set_bit());
+    }
+
+    /// Receive data using DMA. See `write_dma()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or in
+    /// circular mode, until `stop_dma()` is called.
+    #[cfg(not(feature = "f4"))]
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [u16],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        #[cfg(feature = "f3")]
+        let channel = R::read_chan();
+
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S16,
+                    dma::DataSize::S16,
+                    channel_cfg,
+                );
+            }
+            #[cfg(not(any(
+                feature = "f3x4",
+                all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+            )))]
+            dma::DmaPeriph::Dma2 => {
+                // On G0B1 and G0C1, DMA2's register block is a separate type, with the same
+                // layout as DMA1's first 5 channels.
+                #[cfg(any(feature = "g0b1", feature = "g0c1"))]
+                let mut regs = unsafe { &*(pac::DMA2::ptr() as *const pac::dma1::RegisterBlock) };
+                #[cfg(not(any(feature = "g0b1", feature = "g0c1")))]
+                let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S16,
+                    dma::DataSize::S16,
+                    channel_cfg,
+                );
+            }
+        }
+
+        self.regs.cr2.modify(|_, w| w.rxdmaen().set_bit());
+    }
+
+    /// Stop a DMA transfer. Stops the channel, and disables the `TXDMAEN` and `RXDMAEN` bits.
+    #[cfg(not(feature = "f4"))]
+    pub fn stop_dma(&mut self, channel: DmaChannel, dma_periph: dma::DmaPeriph) {
+        dma::stop(dma_periph, channel);
+
+        self.regs.cr2.modify(|_, w| {
+            w.txdmaen().clear_bit();
+            w.rxdmaen().clear_bit()
+        });
+    }
+
+    /// Transmit data using DMA, on F4. `channel` is the stream's channel selection (CHSEL); see
+    /// `DmaStream`. Set `circular`, and handle the half-transfer and transfer complete interrupts,
+    /// for continuous audio streaming.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or in
+    /// circular mode, until `stop_dma()` is called.
+    #[cfg(feature = "f4")]
+    pub unsafe fn write_dma(
+        &mut self,
+        buf: &[u16],
+        stream: DmaStream,
+        channel: u8,
+        circular: bool,
+        dma_periph: DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
+        cfg_stream(
+            dma_periph,
+            stream,
+            channel,
+            periph_addr,
+            ptr as u32,
+            len as u16,
+            true,
+            circular,
+        );
+
+        self.regs.cr2.modify(|_, w| w.txdmaen().set_bit());
+    }
+
+    /// Receive data using DMA, on F4. See `write_dma()`.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the transfer is complete, or in
+    /// circular mode, until `stop_dma()` is called.
+    #[cfg(feature = "f4")]
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [u16],
+        stream: DmaStream,
+        channel: u8,
+        circular: bool,
+        dma_periph: DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let periph_addr = &self.regs.dr as *const _ as u32;
+
+        cfg_stream(
+            dma_periph,
+            stream,
+            channel,
+            periph_addr,
+            ptr as u32,
+            len as u16,
+            false,
+            circular,
+        );
+
+        self.regs.cr2.modify(|_, w| w.rxdmaen().set_bit());
+    }
+
+    /// Stop a DMA transfer, on F4. Disables the stream, and the `TXDMAEN` and `RXDMAEN` bits.
+    #[cfg(feature = "f4")]
+    pub fn stop_dma(&mut self, stream: DmaStream, dma_periph: DmaPeriph) {
+        let regs = dma_regs(dma_periph);
+
+        regs.st[stream as usize]
+            .cr
+            .modify(|_, w| w.en().clear_bit());
+        while regs.st[stream as usize].cr.read().en().bit_is_set() {}
+
+        self.regs.cr2.modify(|_, w| {
+            w.txdmaen().clear_bit();
+            w.rxdmaen().clear_bit()
+        });
+    }
+}
+
+#[cfg(feature = "f4")]
+/// Get a DMA controller's registers, enabling its RCC clock.
+fn dma_regs(dma_periph: DmaPeriph) -> &'static pac::dma2::RegisterBlock {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        match dma_periph {
+            DmaPeriph::Dma1 => rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit()),
+            DmaPeriph::Dma2 => rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit()),
+        }
+    });
+
+    match dma_periph {
+        DmaPeriph::Dma1 => unsafe { &(*pac::DMA1::ptr()) },
+        DmaPeriph::Dma2 => unsafe { &(*pac::DMA2::ptr()) },
+    }
+}
+
+#[cfg(feature = "f4")]
+#[allow(clippy::too_many_arguments)]
+/// Configure and enable a DMA stream for 16-bit transfers to or from the SPI data register. See
+/// F407 RM, section 10.3.17: Stream configuration procedure.
+fn cfg_stream(
+    dma_periph: DmaPeriph,
+    stream: DmaStream,
+    channel: u8,
+    periph_addr: u32,
+    mem_addr: u32,
+    num_data: u16,
+    mem_to_periph: bool,
+    circular: bool,
+) {
+    let regs = dma_regs(dma_periph);
+    let st = &regs.st[stream as usize];
+
+    // 1. If the stream is enabled, disable it by resetting the EN bit in the DMA_SxCR register,
+    // then read this bit in order to confirm that there is no ongoing stream operation.
+    st.cr.modify(|_, w| w.en().clear_bit());
+    while st.cr.read().en().bit_is_set() {}
+
+    // "All the stream dedicated bits set in the status register (DMA_LISR and DMA_HISR) from the
+    // previous data block DMA transfer should be cleared before the stream can be re-enabled."
+    // Each stream's flags are at these offsets, in LIFCR for streams 0-3, and HIFCR for 4-7.
+    let shift = [0, 6, 16, 22][stream as usize % 4];
+    if (stream as u8) < 4 {
+        regs.lifcr.write(|w| unsafe { w.bits(0b11_1101 << shift) });
+    } else {
+        regs.hifcr.write(|w| unsafe { w.bits(0b11_1101 << shift) });
+    }
+
+    // 2. Set the peripheral port register address in the DMA_SxPAR register.
+    st.par.write(|w| unsafe { w.bits(periph_addr) });
+    // 3. Set the memory address in the DMA_SxM0AR register.
+    st.m0ar.write(|w| unsafe { w.bits(mem_addr) });
+    // 4. Configure the total number of data items to be transferred in the DMA_SxNDTR register.
+    st.ndtr.write(|w| w.ndt().bits(num_data));
+
+    // 5. Select the DMA channel (request) using CHSEL[2:0] in the DMA_SxCR register.
+    // 7. Configure the stream priority, and 9. the data transfer direction, peripheral and memory
+    // incremented/fixed mode, data widths, circular mode, and the interrupts. We leave the FIFO
+    // (8.) in direct mode.
+    st.cr.modify(|_, w| unsafe {
+        w.chsel().bits(channel);
+        w.pl().bits(0b10);
+        w.dir().bits(mem_to_periph as u8);
+        w.pinc().clear_bit();
+        w.minc().set_bit();
+        w.psize().bits(0b01);
+        w.msize().bits(0b01);
+        w.circ().bit(circular);
+        w.teie().set_bit();
+        w.htie().bit(circular);
+        w.tcie().set_bit()
+    });
+
+    // 10. Activate the stream by setting the EN bit in the DMA_SxCR register.
+    st.cr.modify(|_, w| w.en().set_bit());
+}
@@ -1,7 +1,22 @@
 //! Inter-processor communication controller (IPCC).
 //! Used on STM32WB for communication between cores.
-
-use crate::pac::{self, IPCC, RCC};
+//!
+//! Includes a log forwarding protocol: Core 2 posts log frames (eg `defmt` frames) to a
+//! dedicated channel, and core 1 forwards them to its debug transport, so both cores can be
+//! debugged with a single probe.
+//!
+//! Also lets core 1 publish its clock speeds to core 2 through shared memory, with
+//! `publish_clocks()`, so drivers on core 2 can compute correct timings.
+
+use core::{
+    ptr,
+    sync::atomic::{self, Ordering},
+};
+
+use crate::{
+    clocks::Clocks,
+    pac::{IPCC, RCC},
+};
 
 use cortex_m::interrupt::free;
 
@@ -527,3 +542,235 @@ impl Ipcc {
         }
     }
 }
+
+/// Maximum log frame payload size, in bytes, for `LogFrameBuf`.
+pub const LOG_FRAME_MAX: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors for IPCC log forwarding.
+pub enum IpccLogError {
+    /// The previous frame hasn't been retrieved by the receiving core yet. Try again later,
+    /// eg on a TX free interrupt.
+    Busy,
+    /// The frame is larger than `LOG_FRAME_MAX`.
+    TooLarge,
+}
+
+#[repr(C)]
+/// Shared memory for one log frame, eg a raw `defmt` frame, or a formatted text line. Place a
+/// static of this type in memory accessible to both cores; on WB, this is SRAM2, eg with
+/// `#[link_section = ".sram2"]`. Each log channel uses its own buffer.
+pub struct LogFrameBuf {
+    len: u16,
+    data: [u8; LOG_FRAME_MAX],
+}
+
+impl LogFrameBuf {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            data: [0; LOG_FRAME_MAX],
+        }
+    }
+}
+
+impl Ipcc {
+    /// Post a log frame from core 2 to core 1, using simplex mode on a dedicated channel. Run this
+    /// on core 2. Non-blocking: Returns `IpccLogError::Busy` if core 1 hasn't retrieved the
+    /// previous frame yet.
+    pub fn send_log_frame(
+        &mut self,
+        channel: IpccChannel,
+        buf: &mut LogFrameBuf,
+        frame: &[u8],
+    ) -> Result<(), IpccLogError> {
+        if frame.len() > LOG_FRAME_MAX {
+            return Err(IpccLogError::TooLarge);
+        }
+
+        // RM, section 37.3.2: When CHnF = 1, the channel is occupied; the last communication
+        // data hasn't been retrieved by the receiving processor.
+        if !self.channel_is_free(Core::C2, channel) {
+            return Err(IpccLogError::Busy);
+        }
+
+        buf.data[..frame.len()].copy_from_slice(frame);
+        buf.len = frame.len() as u16;
+
+        // Make sure the frame is written to shared memory before core 1 can access it.
+        atomic::fence(Ordering::SeqCst);
+
+        // Set the channel to occupied; this generates the RX occupied interrupt on core 1.
+        self.set_flag_channel(Core::C2, channel);
+
+        Ok(())
+    }
+
+    /// If core 2 has posted a log frame on `channel`, pass it to `forward`, then free the channel.
+    /// Run this on core 1, eg in the IPCC RX occupied ISR. `forward` sends the frame to the
+    /// debug transport; eg RTT, or a UART. Returns true if a frame was forwarded.
+    pub fn forward_log_frame(
+        &mut self,
+        channel: IpccChannel,
+        buf: &LogFrameBuf,
+        forward: impl FnOnce(&[u8]),
+    ) -> bool {
+        if self.channel_is_free(Core::C2, channel) {
+            return false;
+        }
+
+        atomic::fence(Ordering::SeqCst);
+
+        let len = (buf.len as usize).min(LOG_FRAME_MAX);
+        forward(&buf.data[..len]);
+
+        // Once the receiving processor has retrieved the communication data from the memory, it
+        // clears the channel status flag CHnF back to free with CHnC. This generates the TX free
+        // interrupt on core 2.
+        self.clear_flag_channel(Core::C1, channel);
+
+        true
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+/// Clock speeds, in Hz, as published by core 1 to core 2 with `publish_clocks()`. Drivers on
+/// core 2 can use these to compute baud rates, timer periods etc, without access to the `Clocks`
+/// config core 1 applied.
+pub struct ClockSpeeds {
+    pub sysclk: u32,
+    /// Core 1 (M4) and its AHB bus. (HCLK1)
+    pub hclk1: u32,
+    /// Core 2 (M0+). (HCLK2)
+    pub hclk2: u32,
+    /// AHB4, APB3, and shared SRAM. (HCLK4)
+    pub hclk4: u32,
+    pub apb1: u32,
+    pub apb1_timer: u32,
+    pub apb2: u32,
+    pub apb2_timer: u32,
+}
+
+impl ClockSpeeds {
+    /// Calculate speeds from a clock config.
+    pub fn from_clocks(clocks: &Clocks) -> Self {
+        let sysclk = clocks.sysclk();
+
+        Self {
+            sysclk,
+            hclk1: clocks.hclk(),
+            hclk2: sysclk / clocks.hclk2_prescaler.value() as u32,
+            hclk4: sysclk / clocks.hclk4_prescaler.value() as u32,
+            apb1: clocks.apb1(),
+            apb1_timer: clocks.apb1_timer(),
+            apb2: clocks.apb2(),
+            apb2_timer: clocks.apb2_timer(),
+        }
+    }
+}
+
+#[repr(C)]
+/// Shared memory for publishing clock speeds from core 1 to core 2. Place a static of this type
+/// in memory accessible to both cores, at the same address in both cores' programs; eg with
+/// `#[link_section = ".sram2"]`, and matching linker scripts.
+///
+/// Writes are guarded by a sequence counter, which is odd while an update is in progress, so
+/// core 2 never reads a partially-updated set of speeds.
+pub struct SharedClockCfg {
+    seq: u32,
+    speeds: ClockSpeeds,
+}
+
+impl SharedClockCfg {
+    pub const fn new() -> Self {
+        Self {
+            seq: 0,
+            speeds: ClockSpeeds {
+                sysclk: 0,
+                hclk1: 0,
+                hclk2: 0,
+                hclk4: 0,
+                apb1: 0,
+                apb1_timer: 0,
+                apb2: 0,
+                apb2_timer: 0,
+            },
+        }
+    }
+
+    /// Read the published clock speeds, or `None` if core 1 hasn't published them yet. Core 2
+    /// can poll this at startup, before core 1's first IPCC notification arrives.
+    pub fn read(&self) -> Option<ClockSpeeds> {
+        loop {
+            let seq = unsafe { ptr::read_volatile(&self.seq) };
+            if seq == 0 {
+                return None;
+            }
+            if seq % 2 == 1 {
+                // Core 1 is mid-update.
+                continue;
+            }
+
+            atomic::fence(Ordering::SeqCst);
+            let speeds = unsafe { ptr::read_volatile(&self.speeds) };
+            atomic::fence(Ordering::SeqCst);
+
+            if unsafe { ptr::read_volatile(&self.seq) } == seq {
+                return Some(speeds);
+            }
+        }
+    }
+}
+
+impl Ipcc {
+    /// Publish clock speeds from core 1 to core 2, and notify it on `channel`, using simplex mode.
+    /// Run this on core 1 after applying the clock config, and after each change to it; eg after
+    /// `Clocks::setup()`, or exiting low power run mode. If core 2 hasn't acknowledged a previous
+    /// notification, the speeds are still updated, and it reads the latest ones.
+    pub fn publish_clocks(
+        &mut self,
+        channel: IpccChannel,
+        shared: &mut SharedClockCfg,
+        clocks: &Clocks,
+    ) {
+        let speeds = ClockSpeeds::from_clocks(clocks);
+        let seq = unsafe { ptr::read_volatile(&shared.seq) };
+
+        // The counter is odd while we update the speeds.
+        let odd = seq | 1;
+        unsafe { ptr::write_volatile(&mut shared.seq, odd) };
+        atomic::fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(&mut shared.speeds, speeds) };
+        atomic::fence(Ordering::SeqCst);
+
+        // Skip 0 on wraparound, since it indicates nothing's been published.
+        let next = match odd.wrapping_add(1) {
+            0 => 2,
+            n => n,
+        };
+        unsafe { ptr::write_volatile(&mut shared.seq, next) };
+
+        if self.channel_is_free(Core::C1, channel) {
+            // This generates the RX occupied interrupt on core 2.
+            self.set_flag_channel(Core::C1, channel);
+        }
+    }
+
+    /// If core 1 has published new clock speeds on `channel`, return them, and free the channel.
+    /// Run this on core 2, eg in the IPCC RX occupied ISR, then update drivers' timing.
+    pub fn receive_clocks(
+        &mut self,
+        channel: IpccChannel,
+        shared: &SharedClockCfg,
+    ) -> Option<ClockSpeeds> {
+        if self.channel_is_free(Core::C1, channel) {
+            return None;
+        }
+
+        let speeds = shared.read();
+        self.clear_flag_channel(Core::C2, channel);
+
+        speeds
+    }
+}
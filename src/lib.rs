@@ -40,7 +40,10 @@
 //! stm32-hal2 = { version = "^1.5.0", features = ["l4x3", "l4rt"]}
 //! ```
 //!
-//! If you need `embedded-hal` traits, include the `embedded-hal` feature.
+//! If you need `embedded-hal` traits, include the `embedded-hal` feature. For `embedded-hal` 1.0,
+//! and `embedded-hal-nb` traits, include the `embedded_hal_1` feature; both can be used together.
+//! For async I2C, SPI, and UART (`embedded-hal-async`, and `embedded-io-async` traits), include
+//! the `async` feature.
 //!
 //! You can review [this section of Cargo.toml](https://github.com/David-OConnor/stm32-hal/blob/main/Cargo.toml#L61)
 //! to see which MCU and runtime features are available.
@@ -92,7 +95,8 @@
 //!}
 //! ```
 //!
-//! Supports the RTIC `Monotonic` trait. To enable, use the `monotonic` feature.
+//! Supports the RTIC `Monotonic` trait. To enable, use the `monotonic` feature. See the `monotonic`
+//! module for a 32-bit timer implementation.
 //!
 //! [This article](https://www.anyleaf.org/blog/writing-embedded-firmware-using-rust) provides some information
 //! on using this library, as well as background information on Rust embedded in general.
@@ -371,6 +375,28 @@ pub use stm32wl::stm32wle5 as pac;
 #[cfg(not(any(feature = "f301", feature = "f302")))]
 pub mod adc;
 
+#[cfg(any(
+    feature = "l562",
+    feature = "g041",
+    feature = "g061",
+    feature = "g081",
+    feature = "g0c1",
+    feature = "g441",
+    feature = "g483",
+    feature = "g484",
+    feature = "g4a1",
+    feature = "wb",
+    feature = "wl"
+))]
+pub mod aes;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+pub mod backup;
+
+pub mod bitbang;
+
 // bxCAN families: F3, F4, L4,
 // fdCAN families: L5, U5, G4, H7
 // H7 suppords fd and can_ccu. (What's that?)
@@ -382,6 +408,19 @@ pub mod adc;
 pub mod can;
 
 pub mod clocks;
+
+// todo: COMP on other families.
+#[cfg(feature = "g4")]
+pub mod comp;
+
+#[cfg(any(feature = "g4", feature = "h735"))]
+pub mod cordic;
+
+pub mod coulomb;
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb"))]
+pub mod crs;
+
 // todo: You could get CRC working on most of these with some effort.
 #[cfg(not(any(
     feature = "f4",
@@ -392,6 +431,17 @@ pub mod clocks;
     feature = "wl"
 )))]
 pub mod crc;
+
+#[cfg(any(
+    feature = "f407",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469",
+    feature = "h7"
+))]
+pub mod dcmi;
+
 #[cfg(not(any(
     feature = "f401",
     feature = "f411",
@@ -402,6 +452,10 @@ pub mod crc;
 // WB doesn't have a DAC. Some G0 variants do - add it! Most F4 variants have it, some don't
 pub mod dac;
 
+pub mod debug_pins;
+
+pub mod delay;
+
 #[cfg(not(any(
     feature = "f3",
     feature = "f4",
@@ -421,18 +475,73 @@ pub mod dfsdm;
 #[cfg(not(any(feature = "f4", feature = "l552")))]
 pub mod dma;
 
-#[cfg(all(feature = "h7", feature = "net"))]
+#[cfg(any(feature = "f427", feature = "f429", feature = "f469", feature = "h7"))]
+pub mod dma2d;
+
+// Cortex-M0+ (G0) has no DWT cycle counter.
+#[cfg(not(feature = "g0"))]
+pub mod dwt;
+
+pub mod emi;
+
+pub mod emmc;
+
+#[cfg(any(feature = "g4", feature = "l5", feature = "h7"))]
+pub mod fdcan;
+
+#[cfg(all(feature = "h7", not(feature = "h7b3"), feature = "net"))]
 pub mod ethernet;
 
 pub mod flash;
 
-// todo: PAC doesn't yet support these newer H7 MCUs that use FMAC.
-// #[cfg(any(feature = "h723", feature = "h725", feature = "h733", feature = "h735"))]
-// todo: Also G4.
-// pub mod fmac;
+#[cfg(any(
+    feature = "f405",
+    feature = "f407",
+    feature = "f412",
+    feature = "f413",
+    feature = "f427",
+    feature = "f429",
+    feature = "f446",
+    feature = "f469",
+    feature = "l4x5",
+    feature = "l4x6",
+    feature = "l5",
+    feature = "g473",
+    feature = "g474",
+    feature = "g483",
+    feature = "g484",
+    feature = "h7"
+))]
+pub mod fmc;
+
+#[cfg(any(feature = "g4", feature = "h735"))]
+pub mod fmac;
 
 pub mod gpio;
 
+#[cfg(feature = "l5")]
+pub mod gtzc;
+
+#[cfg(any(
+    feature = "l562",
+    feature = "h753",
+    feature = "h753v",
+    feature = "h7b3"
+))]
+pub mod hash;
+
+#[cfg(any(
+    feature = "g474",
+    feature = "g484",
+    feature = "h743",
+    feature = "h743v",
+    feature = "h747cm4",
+    feature = "h747cm7",
+    feature = "h753",
+    feature = "h753v",
+))]
+pub mod hrtim;
+
 #[cfg(feature = "wb")]
 pub mod hsem;
 
@@ -443,14 +552,43 @@ pub mod i2c_f4;
 #[cfg(feature = "f4")]
 pub use i2c_f4 as i2c;
 
+// I2S is supported on SPI peripherals on these families. (L4, L5, and WB use SAI for audio)
+#[cfg(any(feature = "f3", feature = "f4", feature = "g0", feature = "g4"))]
+pub mod i2s;
+
 #[cfg(feature = "wb")]
 pub mod ipcc;
 
 pub mod low_power;
 
+#[cfg(feature = "monotonic")]
+pub mod monotonic;
+
+#[cfg(any(feature = "l4", feature = "g4"))]
+pub mod opamp;
+
+#[cfg(any(feature = "l562", feature = "h7b3", feature = "h735"))]
+pub mod otfdec;
+
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0",
+)))]
+pub mod lptim;
+
+pub mod panic;
+
+pub mod periodic;
+
 #[cfg(any(feature = "h747cm4", feature = "h747cm7"))]
 pub mod power;
 
+pub mod post;
+
 // F3, F4, G0, and WL don't have Quad SPI. L5 and newer H variants (eg H735) use OctoSPI,
 // also supported by this module.
 #[cfg(not(any(
@@ -493,9 +631,35 @@ pub mod rtc;
 )))]
 pub mod sai;
 
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0",
+)))]
+pub mod scheduler;
+
+pub mod sd_detect;
+
+#[cfg(any(feature = "h7", feature = "l5"))]
+pub mod sdmmc;
+
 pub mod spi;
 
+#[cfg(feature = "wl")]
+pub mod subghz;
+
 pub mod timer;
+pub mod trace;
+
+#[cfg(any(feature = "f3", feature = "l4", feature = "l5", feature = "wb"))]
+pub mod tsc;
+
+#[cfg(any(feature = "g4", feature = "l5"))]
+pub mod ucpd;
+
 pub mod usart;
 
 // See note at top of `usb` module for info on G0; not avail on modules the PAC has avail.
@@ -622,9 +786,58 @@ pub fn debug_workaround() {
     })
 }
 
-/// In the prelude, we export helper macros.
+/// In the prelude, we export helper macros, the traits needed to call trait methods on this
+/// crate's types, and time conversion helpers. Import it with `use stm32_hal2::prelude::*;`.
+///
+/// Traits are exported anonymously (`as _`), so their methods are in scope without their names
+/// clashing with our own types, eg `i2c::I2c`, or with each other, eg the 0.2 and 1.0 `InputPin`.
+/// What's exported is the same on all families; only the feature gates below vary.
 pub mod prelude {
     pub use access_global;
     pub use make_globals;
     pub use make_simple_globals;
+
+    pub use core::time::Duration;
+
+    pub use crate::{clocks::ClockListener as _, instant::Instant, low_power::BusyPeriph as _};
+
+    #[cfg(not(any(
+        feature = "f4",
+        feature = "g0",
+        feature = "g4",
+        feature = "l5",
+        feature = "wb",
+        feature = "wl"
+    )))]
+    pub use crate::crc::CrcExt as _;
+
+    #[cfg(feature = "embedded-hal")]
+    pub use embedded_hal::{
+        blocking::{
+            delay::{DelayMs as _, DelayUs as _},
+            i2c::{Read as _, Write as _, WriteRead as _},
+            serial::Write as _,
+            spi::{Transfer as _, Write as _},
+        },
+        digital::v2::{
+            InputPin as _, OutputPin as _, StatefulOutputPin as _, ToggleableOutputPin as _,
+        },
+        serial::{Read as _, Write as _},
+        spi::FullDuplex as _,
+    };
+
+    #[cfg(feature = "embedded-hal-1")]
+    pub use embedded_hal_1::{
+        delay::DelayNs as _,
+        digital::{InputPin as _, OutputPin as _, StatefulOutputPin as _},
+        i2c::I2c as _,
+        pwm::SetDutyCycle as _,
+        spi::{SpiBus as _, SpiDevice as _},
+    };
+
+    #[cfg(feature = "embedded-hal-1")]
+    pub use embedded_hal_nb::serial::{Read as _, Write as _};
+
+    #[cfg(feature = "async")]
+    pub use embedded_hal_async::{i2c::I2c as _, spi::SpiBus as _};
 }
@@ -1,16 +1,45 @@
 //! This module contains code used to place the MCU in low power modes.
 //! Reference section 5.3.3: `Low power modes` of the L4 Reference Manual.
+//!
+//! Also includes WKUP pin configuration, and `wakeup_status()`, which reports what caused the
+//! most recent reset or wakeup.
+//!
+//! Stop, and Standby modes stop peripheral clocks, corrupting any DMA transfer, or I2C, or SPI
+//! transaction in progress. Check `stop_allowed()` before entering them. Drivers, or application
+//! code can also block them with `veto_stop()`, eg from when a transfer is started, to its
+//! completion interrupt:
+//! ```rust
+//! if low_power::stop_allowed(&[&i2c, &spi], true).is_ok() {
+//!     low_power::stop(StopMode::Two);
+//! }
+//! ```
 
 #[cfg(not(feature = "h7"))]
 use crate::pac::PWR;
 
-#[cfg(any(feature = "l4", feature = "l5"))]
+#[cfg(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4",
+    feature = "l5",
+    feature = "g0",
+    feature = "g4"
+))]
 use crate::pac;
 
 #[cfg(any(feature = "l4", feature = "l5"))]
-use crate::clocks::{Clocks, MsiRange};
+use crate::clocks::MsiRange;
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+use crate::clocks::{Clocks, SpeedError};
+
+use core::cell::Cell;
 
-use cortex_m::{asm::wfi, Peripherals};
+use cortex_m::{
+    asm::wfi,
+    interrupt::{free, Mutex},
+    Peripherals,
+};
 
 use cfg_if::cfg_if;
 
@@ -19,12 +48,62 @@ use cfg_if::cfg_if;
 #[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum StopMode {
+    /// The main regulator stays on: Fastest wakeup, with the highest consumption.
     Zero = 0,
+    /// The low-power regulator supplies the core domain.
     One = 1,
     #[cfg(not(feature = "g4"))]
+    /// Most of the core domain is in a lower leakage mode. Only some peripherals (eg LPUART1,
+    /// LPTIM1, I2C3, RTC) are functional.
     Two = 2,
 }
 
+#[cfg(any(feature = "f3", feature = "f4"))]
+#[derive(Clone, Copy)]
+/// The voltage regulator mode used during Stop mode. Sets PWR_CR, LPDS field.
+pub enum StopRegulator {
+    /// The regulator stays in main mode: Faster wakeup.
+    Main,
+    /// The regulator is in low-power mode: Lower consumption.
+    LowPower,
+}
+
+#[cfg(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4",
+    feature = "l5",
+    feature = "g0",
+    feature = "g4"
+))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// A WKUP pin, used to wake from Standby or Shutdown. See the datasheet for which GPIO each
+/// corresponds to, and which are available on your variant. (eg PA0 for WKUP1)
+pub enum WakeupPin {
+    P1 = 0,
+    P2 = 1,
+    P3 = 2,
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    P4 = 3,
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    P5 = 4,
+    #[cfg(feature = "g0")]
+    P6 = 5,
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The edge on a WKUP pin that triggers wakeup. Sets PWR_CR4, WPx fields. (On F3 and F4, only
+/// rising edges are supported)
+pub enum WakeupPolarity {
+    /// Wake on a rising edge. (High level)
+    Rising = 0,
+    /// Wake on a falling edge. (Low level)
+    Falling = 1,
+}
+
 /// L4 RM, table 24
 /// This assumes you're using MSI as the clock source, and changes speed by lowering the MSI speed.
 /// You must select an MSI speed of 2Mhz or lower. Note that you may need to adjust peripheral
@@ -43,10 +122,33 @@ pub fn low_power_run(clocks: &mut Clocks, speed: MsiRange) {
     pwr.cr1.modify(|_, w| w.lpr().set_bit())
 }
 
+/// Enter low-power run mode, where the core is supplied by the low-power regulator. `clocks`
+/// must already be applied, with SYSCLK at 2Mhz or lower; this returns an error otherwise.
+/// Consider setting `vos_range` to `Range2` as well. L4 RM, table 24. G4 RM, table 42.
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+pub fn enter_low_power_run(clocks: &Clocks) -> Result<(), SpeedError> {
+    clocks.validate_low_power_run()?;
+
+    let pwr = unsafe { &(*PWR::ptr()) };
+    pwr.cr1.modify(|_, w| w.lpr().set_bit());
+
+    Ok(())
+}
+
+/// Enter low-power sleep mode: Enters low-power run, then sleep now. On wakeup, the MCU
+/// remains in low-power run; use `return_from_low_power_run()` to exit it. L4 RM, table 26.
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+pub fn low_power_sleep(clocks: &Clocks) -> Result<(), SpeedError> {
+    enter_low_power_run(clocks)?;
+    sleep();
+
+    Ok(())
+}
+
 /// L4 RM, table 24
 /// Return to normal run mode from low-power run. Requires you to increase the clock speed
 /// manually after running this.
-#[cfg(any(feature = "l4", feature = "l5"))]
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
 pub fn return_from_low_power_run() {
     let pwr = unsafe { &(*PWR::ptr()) };
 
@@ -81,6 +183,111 @@ pub fn sleep_on_exit() {
     wfi();
 }
 
+/// The number of active `veto_stop()` calls.
+static STOP_VETOES: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Implemented by peripheral drivers that can report a transaction in progress, which entering
+/// Stop, or Standby mode would corrupt. See `stop_allowed()`.
+pub trait BusyPeriph {
+    fn is_busy(&self) -> bool;
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The reason `stop_allowed()` blocked entering a low power mode.
+pub enum StopBlocked {
+    /// There are active `veto_stop()` calls.
+    Vetoed,
+    /// A DMA1, or DMA2 channel is enabled.
+    DmaActive,
+    /// A peripheral passed to `stop_allowed()` is busy. Contains its index.
+    PeriphBusy(usize),
+}
+
+/// Block entering Stop, or Standby modes with `stop_allowed()` until a matching
+/// `release_stop_veto()`. Calls nest; eg call this when starting a transfer, and release it
+/// in its transfer-complete interrupt.
+pub fn veto_stop() {
+    free(|cs| {
+        let vetoes = STOP_VETOES.borrow(cs);
+        vetoes.set(vetoes.get() + 1);
+    });
+}
+
+/// Release a `veto_stop()` call.
+pub fn release_stop_veto() {
+    free(|cs| {
+        let vetoes = STOP_VETOES.borrow(cs);
+        vetoes.set(vetoes.get().saturating_sub(1));
+    });
+}
+
+/// Returns true if a DMA1, or DMA2 channel is enabled; ie has a transfer in progress, or is
+/// circular. (The EN bit of the DMA_CCRx, or on F4 and H7, DMA_SxCR registers) On H7, this
+/// doesn't check the BDMA, or MDMA, and on G0, only DMA1 is checked.
+pub fn dma_active() -> bool {
+    // We use raw pointers, since the channel register layout varies between the PACs.
+    cfg_if! {
+        if #[cfg(any(feature = "f4", feature = "h7"))] {
+            // DMA_SxCR
+            let cr_offset = |ch: usize| 0x10 + 0x18 * ch;
+            const NUM_CHANNELS: usize = 8;
+        } else {
+            // DMA_CCRx
+            let cr_offset = |ch: usize| 0x08 + 0x14 * ch;
+            #[cfg(any(feature = "l5", feature = "g4"))]
+            const NUM_CHANNELS: usize = 8;
+            #[cfg(not(any(feature = "l5", feature = "g4")))]
+            const NUM_CHANNELS: usize = 7;
+        }
+    }
+
+    cfg_if! {
+        if #[cfg(all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1"))))] {
+            let dma1 = crate::pac::DMA::ptr() as usize;
+        } else {
+            let dma1 = crate::pac::DMA1::ptr() as usize;
+        }
+    }
+
+    #[cfg(not(any(
+        feature = "f3x4",
+        all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+    )))]
+    let bases = [dma1, crate::pac::DMA2::ptr() as usize];
+    #[cfg(any(
+        feature = "f3x4",
+        all(feature = "g0", not(any(feature = "g0b1", feature = "g0c1")))
+    ))]
+    let bases = [dma1];
+
+    bases.iter().any(|base| {
+        (0..NUM_CHANNELS).any(|ch| unsafe {
+            core::ptr::read_volatile((base + cr_offset(ch)) as *const u32) & 1 != 0
+        })
+    })
+}
+
+/// Check that entering Stop, or Standby mode won't corrupt a transfer: that there are no active
+/// `veto_stop()` calls, that `periphs` aren't busy, and if `check_dma` is true, that no DMA
+/// channels are enabled. Set `check_dma` to false if using circular DMA transfers that you stop
+/// separately.
+pub fn stop_allowed(periphs: &[&dyn BusyPeriph], check_dma: bool) -> Result<(), StopBlocked> {
+    if free(|cs| STOP_VETOES.borrow(cs).get()) != 0 {
+        return Err(StopBlocked::Vetoed);
+    }
+
+    if let Some(i) = periphs.iter().position(|p| p.is_busy()) {
+        return Err(StopBlocked::PeriphBusy(i));
+    }
+
+    if check_dma && dma_active() {
+        return Err(StopBlocked::DmaActive);
+    }
+
+    Ok(())
+}
+
 cfg_if! {
     if #[cfg(any(feature = "f3", feature = "f4"))] {
         /// Enter `Stop` mode: the middle of the 3 low-power states avail on the
@@ -89,12 +296,19 @@ cfg_if! {
         /// Interrupt vector must be enabled in the NVIC). Refer to Table 82.
         /// F303 RM, table 20. F4 RM, Table 27. H742 RM, Table 38. (CSrtop on H7).
         /// Run `Clocks::reselect_input()` after to re-enable PLL etc after exiting this mode.
+        /// Puts the voltage regulator in low-power mode; see `stop_with_regulator()` to keep it in
+        /// main mode for faster wakeup.
         pub fn stop() {
+            stop_with_regulator(StopRegulator::LowPower);
+        }
+
+        /// Enter `Stop` mode, as in `stop()`, with a choice of voltage regulator mode.
+        pub fn stop_with_regulator(regulator: StopRegulator) {
             let mut scb = unsafe { Peripherals::steal().SCB };
             let pwr = unsafe { &(*PWR::ptr()) };
 
             // todo: On some F4 variants, you may need to `select voltage regulator
-            // todo mode by configuring LPDS, MRUDS, LPUDS and UDEN bits in PWR_CR.`
+            // todo mode by configuring MRUDS, LPUDS and UDEN bits in PWR_CR.`
             //WFI (Wait for Interrupt) or WFE (Wait for Event) while:
 
             // Set SLEEPDEEP bit in ARM® Cortex®-M4 System Control register
@@ -112,7 +326,7 @@ cfg_if! {
                 // 0: Voltage regulator on during Stop mode
                 // 1: Voltage regulator in low-power mode during Stop mode
                 // pwr.cr.modify(|_, w| w.pdds().clear_bit());
-                 w.lpds().set_bit()
+                w.lpds().bit(matches!(regulator, StopRegulator::LowPower))
             });
 
 
@@ -190,34 +404,7 @@ cfg_if! {
 
             // – WUFx bits are cleared in power status register 1 (PWR_SR1)
             // (Clear by setting cwfuf bits in `pwr_scr`.)
-            cfg_if! {
-                if #[cfg(feature = "l4")] {
-                    pwr.scr.write(|w| {
-                        w.wuf1().set_bit();
-                        w.wuf2().set_bit();
-                        w.wuf3().set_bit();
-                        w.wuf4().set_bit();
-                        w.wuf5().set_bit()
-                    });
-                } else if #[cfg(feature = "g0")] {
-                    pwr.scr.write(|w| {
-                        w.cwuf1().set_bit();
-                        w.cwuf2().set_bit();
-                        // w.cwuf3().set_bit(); // todo: PAC ommission?
-                        w.cwuf4().set_bit();
-                        w.cwuf5().set_bit();
-                        w.cwuf6().set_bit()
-                    });
-                } else {
-                    pwr.scr.write(|w| {
-                        w.cwuf1().set_bit();
-                        w.cwuf2().set_bit();
-                        w.cwuf3().set_bit();
-                        w.cwuf4().set_bit();
-                        w.cwuf5().set_bit()
-                    });
-                }
-            }
+            clear_wakeup_flags();
 
             // todo: `The RTC flag corresponding to the chosen wakeup source (RTC Alarm
             // todo: A, RTC Alarm B, RTC wakeup, tamper or timestamp flags) is cleared`.
@@ -248,14 +435,7 @@ cfg_if! {
             pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(0b100) });
             // – WUFx bits are cleared in power status register 1 (PWR_SR1)
             // (Clear by setting cwfuf bits in `pwr_scr`.)
-
-            // pwr.scr.write(|_, w| {
-            //     w.cwuf1().set_bit();
-            //     w.cwuf2().set_bit();
-            //     w.cwuf3().set_bit();
-            //     w.cwuf4().set_bit();
-            //     w.cwuf5().set_bit();
-            // })
+            clear_wakeup_flags();
 
             // Or, unimplemented:
             // On return from ISR while:
@@ -338,6 +518,163 @@ cfg_if! {
     }
 }
 
+cfg_if! {
+    if #[cfg(any(feature = "f3", feature = "f4"))] {
+        /// Enable a WKUP pin, which wakes the MCU from Standby on a rising edge. Sets PWR_CSR, EWUPx
+        /// fields. Note that most F4 variants only have WKUP1. F303 RM, section 4.4.2.
+        pub fn enable_wakeup_pin(pin: WakeupPin) {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            // EWUPx are bits 8, 9, and 10. Field names vary by variant, so we write bits directly.
+            pwr.csr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << (8 + pin as u8))) });
+        }
+
+        /// Disable a WKUP pin.
+        pub fn disable_wakeup_pin(pin: WakeupPin) {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            pwr.csr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (8 + pin as u8))) });
+        }
+
+        /// Clear the Wakeup, and Standby flags. (PWR_CSR WUF and SBF, cleared using PWR_CR CWUF
+        /// and CSBF)
+        pub fn clear_wakeup_flags() {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            pwr.cr.modify(|_, w| w.cwuf().set_bit().csbf().set_bit());
+        }
+    } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))] {
+        /// Enable a WKUP pin, which wakes the MCU from Stop 2 (with the wakeup interrupt enabled),
+        /// Standby, or Shutdown. Sets PWR_CR3, EWUPx, and PWR_CR4, WPx fields. L4 RM, section 5.4.3.
+        pub fn enable_wakeup_pin(pin: WakeupPin, polarity: WakeupPolarity) {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            let bit = 1 << pin as u8;
+
+            // Changing the polarity can set the wakeup flag, so set it before enabling the pin,
+            // then clear the flag.
+            pwr.cr4.modify(|r, w| unsafe {
+                match polarity {
+                    WakeupPolarity::Rising => w.bits(r.bits() & !bit),
+                    WakeupPolarity::Falling => w.bits(r.bits() | bit),
+                }
+            });
+            pwr.scr.write(|w| unsafe { w.bits(bit) });
+            pwr.cr3.modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+        }
+
+        /// Disable a WKUP pin.
+        pub fn disable_wakeup_pin(pin: WakeupPin) {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            pwr.cr3.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin as u8)) });
+        }
+
+        /// Clear the WKUP pin flags, and the Standby flag. (PWR_SR1 WUFx and SBF, cleared using
+        /// PWR_SCR) Note that the internal wakeup flag (WUFI) is cleared by clearing the source
+        /// peripheral's flag, eg the RTC's.
+        pub fn clear_wakeup_flags() {
+            let pwr = unsafe { &(*PWR::ptr()) };
+            // CWUFx are bits 0 - 5, and CSBF is bit 8. We write bits directly, since some PACs
+            // are missing CWUF3.
+            pwr.scr.write(|w| unsafe { w.bits(0b11_1111 | (1 << 8)) });
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4",
+    feature = "l5",
+    feature = "g0",
+    feature = "g4"
+))]
+#[derive(Clone, Copy, Debug, Default)]
+/// What caused the most recent reset or wakeup, read from PWR and RCC status flags. Read this
+/// early after startup with `wakeup_status()`, then clear the flags with
+/// `clear_wakeup_flags()` and `clear_reset_flags()`, so the next wakeup's report is accurate.
+///
+/// Exiting Stop modes resumes execution after the `wfi`, without a reset; these flags aren't
+/// affected. Exiting Standby or Shutdown resets the MCU: After Standby, `from_standby` is set,
+/// and `wakeup_pins` shows which WKUP pins triggered it; if none did, the source was internal,
+/// eg the RTC or IWDG. Exiting Shutdown appears as a BOR (power) reset.
+pub struct WakeupStatus {
+    /// The MCU was in Standby mode. (PWR SBF)
+    pub from_standby: bool,
+    /// WKUP pin flags. Bit 0 is WKUP1. (PWR WUFx)
+    pub wakeup_pins: u8,
+    /// Reset from the NRST pin. (RCC PINRSTF)
+    pub pin_reset: bool,
+    /// Power-on, or brownout reset. (RCC PORRSTF, BORRSTF, or PWRRSTF)
+    pub power_reset: bool,
+    /// Software reset, eg `SCB::sys_reset()`. (RCC SFTRSTF)
+    pub software_reset: bool,
+    /// Independent watchdog reset. (RCC IWDGRSTF)
+    pub iwdg_reset: bool,
+    /// Window watchdog reset. (RCC WWDGRSTF)
+    pub wwdg_reset: bool,
+    /// Reset from entering Stop or Standby, when disallowed by option bytes. (RCC LPWRRSTF)
+    pub low_power_reset: bool,
+}
+
+#[cfg(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4",
+    feature = "l5",
+    feature = "g0",
+    feature = "g4"
+))]
+/// Read the wakeup, and reset status flags. See `WakeupStatus`.
+pub fn wakeup_status() -> WakeupStatus {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    // We read bits directly, since flag names vary between families. RCC_CSR's reset flags are
+    // at the same positions on all of these, other than F4's additional BORRSTF.
+    let csr = rcc.csr.read().bits();
+
+    cfg_if! {
+        if #[cfg(any(feature = "f3", feature = "f4"))] {
+            // PWR_CSR: WUF is bit 0, and SBF is bit 1.
+            let sr = pwr.csr.read().bits();
+            let from_standby = sr & (1 << 1) != 0;
+            let wakeup_pins = (sr & 1) as u8;
+        } else {
+            // PWR_SR1: WUFx are bits 0 - 5, and SBF is bit 8.
+            let sr = pwr.sr1.read().bits();
+            let from_standby = sr & (1 << 8) != 0;
+            let wakeup_pins = (sr & 0b11_1111) as u8;
+        }
+    }
+
+    #[cfg(feature = "f4")]
+    let power_reset = csr & ((1 << 27) | (1 << 25)) != 0;
+    #[cfg(not(feature = "f4"))]
+    let power_reset = csr & (1 << 27) != 0;
+
+    WakeupStatus {
+        from_standby,
+        wakeup_pins,
+        pin_reset: csr & (1 << 26) != 0,
+        power_reset,
+        software_reset: csr & (1 << 28) != 0,
+        iwdg_reset: csr & (1 << 29) != 0,
+        wwdg_reset: csr & (1 << 30) != 0,
+        low_power_reset: csr & (1 << 31) != 0,
+    }
+}
+
+#[cfg(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4",
+    feature = "l5",
+    feature = "g0",
+    feature = "g4"
+))]
+/// Clear the RCC reset flags, by setting RCC_CSR, RMVF.
+pub fn clear_reset_flags() {
+    let rcc = unsafe { &(*pac::RCC::ptr()) };
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+}
+
 /// This function is used by both `sleep_now` (non-H7), and `csleep` (H7), so that the names
 /// can correctly reflect functionality.
 fn sleep() {
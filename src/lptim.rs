@@ -0,0 +1,465 @@
+//! Support for low-power timers (LPTIM). These can run from the LSE, LSI, or HSI16 clocks, or from
+//! an external clock on their input pin, and continue operating in Stop mode. Select the LPTIM kernel
+//! clock in the RCC `CCIPR` register.
+//!
+//! Includes timeout mode, where each trigger (eg an external pulse) resets the counter; the
+//! autoreload match interrupt then indicates that no pulse arrived within the timeout period.
+//! This is useful for detecting a missing external signal while in Stop mode.
+//!
+//! Also supports PWM output, and quadrature encoder mode. Since the timer keeps running in Stop
+//! mode when clocked from LSE or LSI, its interrupts can provide periodic wakeups without keeping
+//! a full APB timer alive.
+//!
+//! Input capture channels are available on the LPTIM peripherals of newer families (eg U5 and H5),
+//! which this library doesn't yet support.
+
+use core::ops::Deref;
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    pac::{self, RCC},
+    util::{rcc_en_reset, rcc_en_stop},
+};
+
+use cfg_if::cfg_if;
+use paste::paste;
+
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        use crate::pac::lptimer1 as lptim_p;
+    } else {
+        use crate::pac::lptim1 as lptim_p;
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Clock prescaler. Sets `CFGR` register, `PRESC` field.
+pub enum LpPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    Div32 = 0b101,
+    Div64 = 0b110,
+    Div128 = 0b111,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// LPTIM kernel clock source. To keep counting in Stop mode, use LSE or LSI.
+/// Sets the RCC `CCIPR` register, `LPTIMxSEL` field. (`D2CCIP2R` on H7)
+pub enum LpClockSel {
+    /// APB clock. Stops in Stop mode.
+    Pclk,
+    Lsi,
+    #[cfg(not(feature = "h7"))]
+    Hsi16,
+    Lse,
+}
+
+impl LpClockSel {
+    /// The `LPTIMxSEL` field value.
+    fn bits(&self) -> u8 {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                match self {
+                    Self::Pclk => 0b000,
+                    Self::Lse => 0b011,
+                    Self::Lsi => 0b100,
+                }
+            } else {
+                match self {
+                    Self::Pclk => 0b00,
+                    Self::Lsi => 0b01,
+                    Self::Hsi16 => 0b10,
+                    Self::Lse => 0b11,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Encoder mode sub-mode: which edges of the inputs are counted. Sets `CFGR` register, `CKPOL`
+/// field, when in encoder mode.
+pub enum EncoderMode {
+    /// Count on rising edges only.
+    Rising = 0b00,
+    /// Count on falling edges only.
+    Falling = 0b01,
+    /// Count on both edges; 4 counts per encoder period.
+    Both = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Selects which clock the counter uses. Sets `CFGR` register, `CKSEL` and `COUNTMODE` fields.
+pub enum LpClockSource {
+    /// The counter is clocked by the internal kernel clock (selected in RCC), through the prescaler.
+    Internal,
+    /// The counter is incremented by each valid edge on the external input (LPTIM_IN1). The kernel
+    /// clock is still used for the digital filters.
+    ExternalInput,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Trigger enable and polarity. Sets `CFGR` register, `TRIGEN` field.
+pub enum TriggerEdge {
+    /// Software trigger; the counter is started by software.
+    Software = 0b00,
+    Rising = 0b01,
+    Falling = 0b10,
+    Both = 0b11,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Digital filter for the trigger and external clock inputs, in number of consecutive kernel clock
+/// periods the signal must be stable for. Sets `CFGR` register, `TRGFLT` and `CKFLT` fields.
+pub enum LpFilter {
+    /// Any level change is considered valid.
+    None = 0b00,
+    Clocks2 = 0b01,
+    Clocks4 = 0b10,
+    Clocks8 = 0b11,
+}
+
+#[derive(Clone, Copy)]
+/// Low-power timer interrupt types. Enable in `IER`, check in `ISR`, clear in `ICR`.
+pub enum LpTimerInterrupt {
+    /// Compare match (CMPM)
+    CompareMatch,
+    /// Autoreload match (ARRM). In timeout mode, indicates the timeout elapsed with no trigger.
+    AutoReloadMatch,
+    /// External trigger edge event (EXTTRIG)
+    ExternalTrigger,
+    /// Compare register update OK (CMPOK)
+    CompareUpdateOk,
+    /// Autoreload register update OK (ARROK)
+    AutoReloadUpdateOk,
+}
+
+/// Low-power timer configuration.
+pub struct LpTimerConfig {
+    /// Kernel clock source. Defaults to the APB clock.
+    pub kernel_clock: LpClockSel,
+    /// Clock prescaler. Defaults to Div1.
+    pub prescaler: LpPrescaler,
+    /// Internal (kernel) clock, or counting external input pulses. Defaults to internal.
+    pub clock_source: LpClockSource,
+    /// Trigger source, from the RM's `TRIGSEL` table: 0 is LPTIM_ETR (the external trigger pin);
+    /// other values select RTC alarms, tamper events, and comparator outputs. Defaults to 0.
+    pub trigger_source: u8,
+    /// Trigger edge. Defaults to software (no external trigger).
+    pub trigger_edge: TriggerEdge,
+    /// Digital filter for the trigger input. Defaults to none.
+    pub trigger_filter: LpFilter,
+    /// Digital filter for the external clock input. Defaults to none.
+    pub clock_filter: LpFilter,
+    /// Timeout mode: If true, a trigger event arriving while the timer is running resets and restarts
+    /// the counter. Defaults to false.
+    pub timeout: bool,
+    /// If true, `ARR` and `CMP` updates take effect at the end of the current period, instead of
+    /// immediately. Defaults to false.
+    pub preload: bool,
+    /// Keep the timer's RCC clock enabled in Stop mode, in case it was cleared to save power in
+    /// Sleep mode. (RCC_APB1SMENRx, or on H7, RCC_APB1LLPENR) The kernel clock must also run in
+    /// Stop; see `kernel_clock`. Defaults to false.
+    pub run_in_stop: bool,
+}
+
+impl Default for LpTimerConfig {
+    fn default() -> Self {
+        Self {
+            kernel_clock: LpClockSel::Pclk,
+            prescaler: LpPrescaler::Div1,
+            clock_source: LpClockSource::Internal,
+            trigger_source: 0,
+            trigger_edge: TriggerEdge::Software,
+            trigger_filter: LpFilter::None,
+            clock_filter: LpFilter::None,
+            timeout: false,
+            preload: false,
+            run_in_stop: false,
+        }
+    }
+}
+
+/// Represents a low-power timer (LPTIM) peripheral.
+pub struct LpTimer<R> {
+    pub regs: R,
+    pub cfg: LpTimerConfig,
+}
+
+impl<R> LpTimer<R>
+where
+    R: Deref<Target = lptim_p::RegisterBlock>,
+{
+    /// Write the `CFGR` register. This must be done with the timer disabled.
+    fn write_cfgr(regs: &R, cfg: &LpTimerConfig) {
+        regs.cfgr.write(|w| unsafe {
+            w.presc().bits(cfg.prescaler as u8);
+            w.cksel().clear_bit();
+            w.countmode()
+                .bit(cfg.clock_source == LpClockSource::ExternalInput);
+            w.trigsel().bits(cfg.trigger_source);
+            w.trigen().bits(cfg.trigger_edge as u8);
+            w.trgflt().bits(cfg.trigger_filter as u8);
+            w.ckflt().bits(cfg.clock_filter as u8);
+            w.timout().bit(cfg.timeout);
+            w.preload().bit(cfg.preload)
+        });
+    }
+
+    /// Set the autoreload (period) value. The timer must be enabled. Blocks until the write
+    /// is synchronized to the LPTIM kernel clock.
+    pub fn set_auto_reload(&mut self, arr: u16) {
+        self.regs.icr.write(|w| w.arrokcf().set_bit());
+        self.regs.arr.write(|w| unsafe { w.arr().bits(arr) });
+        while self.regs.isr.read().arrok().bit_is_clear() {}
+    }
+
+    /// Set the compare value. The timer must be enabled. Blocks until the write is synchronized
+    /// to the LPTIM kernel clock.
+    pub fn set_compare(&mut self, cmp: u16) {
+        self.regs.icr.write(|w| w.cmpokcf().set_bit());
+        self.regs.cmp.write(|w| unsafe { w.cmp().bits(cmp) });
+        while self.regs.isr.read().cmpok().bit_is_clear() {}
+    }
+
+    /// Enable the timer. Does not start counting.
+    pub fn enable(&mut self) {
+        self.regs.cr.modify(|_, w| w.enable().set_bit());
+    }
+
+    /// Disable the timer. Resets the counter.
+    pub fn disable(&mut self) {
+        self.regs.cr.modify(|_, w| w.enable().clear_bit());
+    }
+
+    /// Start counting in continuous mode. If an external trigger is configured, counting starts on the
+    /// first trigger event.
+    pub fn start_continuous(&mut self) {
+        self.regs.cr.modify(|_, w| w.cntstrt().set_bit());
+    }
+
+    /// Start a single count period (one-shot). If an external trigger is configured, counting starts on
+    /// the next trigger event.
+    pub fn start_single(&mut self) {
+        self.regs.cr.modify(|_, w| w.sngstrt().set_bit());
+    }
+
+    /// Read the counter value. Since the counter runs asynchronously to the APB clock, we read
+    /// it until 2 consecutive reads match, as the RM recommends.
+    pub fn read_count(&self) -> u16 {
+        loop {
+            let a = self.regs.cnt.read().cnt().bits();
+            let b = self.regs.cnt.read().cnt().bits();
+            if a == b {
+                return a;
+            }
+        }
+    }
+
+    /// Set up timeout mode, for detecting missing external pulses: Each trigger edge resets the
+    /// counter. If no trigger arrives within `timeout_ticks` counter ticks, the autoreload match
+    /// flag is set; enable the `AutoReloadMatch` interrupt to wake from Stop mode. Counting starts on
+    /// the first trigger.
+    pub fn start_timeout(&mut self, timeout_ticks: u16, edge: TriggerEdge) {
+        self.cfg.timeout = true;
+        self.cfg.trigger_edge = edge;
+
+        self.disable();
+        Self::write_cfgr(&self.regs, &self.cfg);
+        self.enable();
+
+        self.set_auto_reload(timeout_ticks);
+        self.start_continuous();
+    }
+
+    /// Output a PWM signal on the LPTIM_OUT pin, and start counting continuously. The output is
+    /// high while the counter is above `compare`, and low otherwise, with a period of `period + 1`
+    /// counter ticks; set `inverted` to reverse this. `compare` must be less than `period`.
+    /// See L4 RM, section 34.4.9: Waveform generation.
+    pub fn start_pwm(&mut self, period: u16, compare: u16, inverted: bool) {
+        self.disable();
+        Self::write_cfgr(&self.regs, &self.cfg);
+        self.regs.cfgr.modify(|_, w| {
+            // WAVE = 0: PWM mode, vice set-once.
+            w.wave().clear_bit();
+            w.wavpol().bit(inverted)
+        });
+        self.enable();
+
+        self.set_auto_reload(period);
+        self.set_compare(compare);
+        self.start_continuous();
+    }
+
+    /// Change the PWM duty cycle, while running. Takes effect immediately, or at the end of the
+    /// current period if `preload` is set in the config.
+    pub fn set_duty(&mut self, compare: u16) {
+        self.set_compare(compare);
+    }
+
+    /// Start quadrature encoder mode, counting the phase difference between the LPTIM_IN1 and
+    /// LPTIM_IN2 inputs. The counter counts up or down between 0 and `period`. Requires an
+    /// internal kernel clock at least 4 times the input frequency. On most families, this is
+    /// only available on LPTIM1. See L4 RM, section 34.4.14: Encoder mode.
+    pub fn start_encoder(&mut self, period: u16, mode: EncoderMode) {
+        self.cfg.clock_source = LpClockSource::Internal;
+
+        self.disable();
+        Self::write_cfgr(&self.regs, &self.cfg);
+        self.regs.cfgr.modify(|_, w| unsafe {
+            w.enc().set_bit();
+            w.ckpol().bits(mode as u8)
+        });
+        self.enable();
+
+        self.set_auto_reload(period);
+        self.start_continuous();
+    }
+
+    /// In encoder mode, returns true if the counter is counting up. This reflects the most recent
+    /// direction change. (`ISR` register, `UP` and `DOWN` flags)
+    pub fn counting_up(&mut self) -> bool {
+        let isr = self.regs.isr.read();
+        if isr.up().bit_is_set() {
+            self.regs.icr.write(|w| w.upcf().set_bit());
+            true
+        } else if isr.down().bit_is_set() {
+            self.regs.icr.write(|w| w.downcf().set_bit());
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Enable an interrupt. Note that this can only be done with the timer disabled.
+    pub fn enable_interrupt(&mut self, interrupt: LpTimerInterrupt) {
+        self.regs.ier.modify(|_, w| match interrupt {
+            LpTimerInterrupt::CompareMatch => w.cmpmie().set_bit(),
+            LpTimerInterrupt::AutoReloadMatch => w.arrmie().set_bit(),
+            LpTimerInterrupt::ExternalTrigger => w.exttrigie().set_bit(),
+            LpTimerInterrupt::CompareUpdateOk => w.cmpokie().set_bit(),
+            LpTimerInterrupt::AutoReloadUpdateOk => w.arrokie().set_bit(),
+        });
+    }
+
+    /// Disable an interrupt. Note that this can only be done with the timer disabled.
+    pub fn disable_interrupt(&mut self, interrupt: LpTimerInterrupt) {
+        self.regs.ier.modify(|_, w| match interrupt {
+            LpTimerInterrupt::CompareMatch => w.cmpmie().clear_bit(),
+            LpTimerInterrupt::AutoReloadMatch => w.arrmie().clear_bit(),
+            LpTimerInterrupt::ExternalTrigger => w.exttrigie().clear_bit(),
+            LpTimerInterrupt::CompareUpdateOk => w.cmpokie().clear_bit(),
+            LpTimerInterrupt::AutoReloadUpdateOk => w.arrokie().clear_bit(),
+        });
+    }
+
+    /// Clear an interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: LpTimerInterrupt) {
+        self.regs.icr.write(|w| match interrupt {
+            LpTimerInterrupt::CompareMatch => w.cmpmcf().set_bit(),
+            LpTimerInterrupt::AutoReloadMatch => w.arrmcf().set_bit(),
+            LpTimerInterrupt::ExternalTrigger => w.exttrigcf().set_bit(),
+            LpTimerInterrupt::CompareUpdateOk => w.cmpokcf().set_bit(),
+            LpTimerInterrupt::AutoReloadUpdateOk => w.arrokcf().set_bit(),
+        });
+    }
+
+    /// Check if an interrupt flag is set.
+    pub fn interrupt_pending(&self, interrupt: LpTimerInterrupt) -> bool {
+        let isr = self.regs.isr.read();
+        match interrupt {
+            LpTimerInterrupt::CompareMatch => isr.cmpm().bit_is_set(),
+            LpTimerInterrupt::AutoReloadMatch => isr.arrm().bit_is_set(),
+            LpTimerInterrupt::ExternalTrigger => isr.exttrig().bit_is_set(),
+            LpTimerInterrupt::CompareUpdateOk => isr.cmpok().bit_is_set(),
+            LpTimerInterrupt::AutoReloadUpdateOk => isr.arrok().bit_is_set(),
+        }
+    }
+}
+
+macro_rules! make_lptim {
+    ($TIMER:ident, $tim:ident) => {
+        paste! {
+            impl LpTimer<pac::$TIMER> {
+                /// Initialize a low-power timer, including enabling and resetting its RCC peripheral
+                /// clock, and writing its configuration. Leaves the timer enabled, but not counting.
+                pub fn new(regs: pac::$TIMER, cfg: LpTimerConfig) -> Self {
+                    free(|_| {
+                        let rcc = unsafe { &(*RCC::ptr()) };
+                        rcc_en_reset!(apb1, $tim, rcc);
+
+                        if cfg.run_in_stop {
+                            rcc_en_stop!(apb1, $tim, rcc);
+                        }
+
+                        cfg_if! {
+                            if #[cfg(feature = "h7b3")] {
+                                rcc.cdccip2r.modify(|_, w| unsafe { w.[<$tim sel>]().bits(cfg.kernel_clock.bits()) });
+                            } else if #[cfg(feature = "h7")] {
+                                rcc.d2ccip2r.modify(|_, w| unsafe { w.[<$tim sel>]().bits(cfg.kernel_clock.bits()) });
+                            } else if #[cfg(feature = "l5")] {
+                                rcc.ccipr1.modify(|_, w| unsafe { w.[<$tim sel>]().bits(cfg.kernel_clock.bits()) });
+                            } else {
+                                rcc.ccipr.modify(|_, w| unsafe { w.[<$tim sel>]().bits(cfg.kernel_clock.bits()) });
+                            }
+                        }
+                    });
+
+                    // "The LPTIM_CFGR register must only be modified when the LPTIM is disabled."
+                    Self::write_cfgr(&regs, &cfg);
+                    regs.cr.modify(|_, w| w.enable().set_bit());
+
+                    Self { regs, cfg }
+                }
+            }
+        }
+    };
+}
+
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        make_lptim!(LPTIMER1, lptim1);
+    } else {
+        make_lptim!(LPTIM1, lptim1);
+    }
+}
+
+// LPTIM2 uses the `APB1ENR2` register on these families, so we handle it separately. (WL's LPTIM2
+// has its own register block in the PAC, so isn't supported yet.)
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb"))]
+impl LpTimer<pac::LPTIM2> {
+    /// Initialize a low-power timer, including enabling and resetting its RCC peripheral
+    /// clock, and writing its configuration. Leaves the timer enabled, but not counting.
+    pub fn new(regs: pac::LPTIM2, cfg: LpTimerConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr2.modify(|_, w| w.lptim2en().set_bit());
+            rcc.apb1rstr2.modify(|_, w| w.lptim2rst().set_bit());
+            rcc.apb1rstr2.modify(|_, w| w.lptim2rst().clear_bit());
+
+            if cfg.run_in_stop {
+                rcc.apb1smenr2.modify(|_, w| w.lptim2smen().set_bit());
+            }
+
+            #[cfg(feature = "l5")]
+            rcc.ccipr1
+                .modify(|_, w| unsafe { w.lptim2sel().bits(cfg.kernel_clock.bits()) });
+            #[cfg(not(feature = "l5"))]
+            rcc.ccipr
+                .modify(|_, w| unsafe { w.lptim2sel().bits(cfg.kernel_clock.bits()) });
+        });
+
+        Self::write_cfgr(&regs, &cfg);
+        regs.cr.modify(|_, w| w.enable().set_bit());
+
+        Self { regs, cfg }
+    }
+}
@@ -0,0 +1,185 @@
+//! An RTIC 1.0 `Monotonic` implementation, using a 32-bit general-purpose timer (TIM2, or TIM5)
+//! counting at 1Mhz. Counter overflows are tracked in the timer's interrupt, extending the time to
+//! 64 bits, so it doesn't wrap. Compared to the `Monotonic` implementation on `Timer`, this uses a
+//! fixed tick rate, and integer math, so it doesn't drift, and schedules accurately over long
+//! periods. Enabled with the `monotonic` feature.
+//!
+//! Example:
+//! ```rust
+//! #[rtic::app(device = pac, dispatchers = [USART1])]
+//! mod app {
+//!     #[monotonic(binds = TIM2, default = true)]
+//!     type Mono = MonoTimer<pac::TIM2>;
+//!
+//!     #[init]
+//!     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+//!         let clock_cfg = Clocks::default();
+//!         clock_cfg.setup().unwrap();
+//!
+//!         let mono = MonoTimer::new_tim2(cx.device.TIM2, &clock_cfg);
+//!         blink::spawn_after(Duration::from_millis(500)).unwrap();
+//!
+//!         (Shared {}, Local {}, init::Monotonics(mono))
+//!     }
+//! }
+//! ```
+
+use rtic_monotonic::Monotonic;
+
+use crate::{clocks::Clocks, instant::Instant, pac, timer::Timer};
+
+use cfg_if::cfg_if;
+use paste::paste;
+
+/// The counter frequency, in Hz.
+const TICK_FREQ: u32 = 1_000_000;
+
+// TIMx_DIER, and TIMx_SR bits. We use raw bits, since PAC support for CC interrupts varies.
+const UPDATE_BIT: u32 = 1;
+const CC1_BIT: u32 = 1 << 1;
+
+/// A monotonic timer for RTIC, using a 32-bit timer. Bind the RTIC monotonic to the timer's
+/// interrupt.
+pub struct MonoTimer<TIM> {
+    pub timer: Timer<TIM>,
+    /// The number of counter overflows handled in `on_interrupt()`.
+    overflows: u32,
+}
+
+macro_rules! make_mono {
+    ($TIMX:ident, $tim:ident) => {
+        impl MonoTimer<pac::$TIMX> {
+            paste! {
+                /// Create a monotonic timer, including enabling and resetting its RCC peripheral
+                /// clock. The APB1 timer clock must be a multiple of 1Mhz. RTIC starts the timer,
+                /// using `Monotonic::reset()`.
+                pub fn [<new_ $tim>](regs: pac::$TIMX, clocks: &Clocks) -> Self {
+                    let mut timer = Timer::[<new_ $tim>](regs, 1., Default::default(), clocks);
+                    timer.disable();
+
+                    timer.set_prescaler((clocks.apb1_timer() / TICK_FREQ - 1) as u16);
+                    timer.set_auto_reload(u32::MAX);
+
+                    // Load the prescaler, and clear the update flag this sets.
+                    timer.regs.egr.write(|w| w.ug().set_bit());
+                    timer.regs.sr.write(|w| unsafe { w.bits(0) });
+
+                    Self {
+                        timer,
+                        overflows: 0,
+                    }
+                }
+            }
+
+            /// The number of ticks since the timer was reset.
+            fn ticks(&self) -> u64 {
+                let count = self.timer.read_count();
+                let overflow_pending = self.timer.regs.sr.read().bits() & UPDATE_BIT != 0;
+
+                // If the counter overflowed before we read it, but the interrupt hasn't been
+                // handled yet, the count is low, and we account for the overflow here. If it
+                // overflowed after we read it, the count is high, and we ignore it.
+                let mut overflows = self.overflows as u64;
+                if overflow_pending && count < u32::MAX / 2 {
+                    overflows += 1;
+                }
+
+                (overflows << 32) | count as u64
+            }
+        }
+
+        impl Monotonic for MonoTimer<pac::$TIMX> {
+            type Instant = Instant;
+            type Duration = core::time::Duration;
+
+            fn now(&mut self) -> Self::Instant {
+                Instant {
+                    count_ns: (self.ticks() * (1_000_000_000 / TICK_FREQ) as u64) as i64,
+                }
+            }
+
+            /// We use the capture compare 1 channel. If the instant is more than one counter
+            /// period away, this fires early; RTIC then re-arms it.
+            fn set_compare(&mut self, instant: Self::Instant) {
+                let ticks = (instant.count_ns.max(0) as u64) / (1_000_000_000 / TICK_FREQ) as u64;
+                self.timer
+                    .regs
+                    .ccr1()
+                    .write(|w| unsafe { w.bits(ticks as u32) });
+            }
+
+            fn clear_compare_flag(&mut self) {
+                // SR flags are cleared by writing 0; writing 1 has no effect.
+                self.timer.regs.sr.write(|w| unsafe { w.bits(!CC1_BIT) });
+            }
+
+            fn zero() -> Self::Instant {
+                Instant::default()
+            }
+
+            unsafe fn reset(&mut self) {
+                self.timer.disable();
+                self.timer.reset_count();
+                self.overflows = 0;
+
+                self.timer.regs.sr.write(|w| w.bits(0));
+                self.timer
+                    .regs
+                    .dier
+                    .modify(|r, w| w.bits(r.bits() | UPDATE_BIT | CC1_BIT));
+
+                self.timer.enable();
+            }
+
+            fn on_interrupt(&mut self) {
+                if self.timer.regs.sr.read().bits() & UPDATE_BIT != 0 {
+                    self.timer.regs.sr.write(|w| unsafe { w.bits(!UPDATE_BIT) });
+                    self.overflows += 1;
+                }
+            }
+
+            /// Enable the compare interrupt. The update interrupt stays enabled, to count overflows.
+            fn enable_timer(&mut self) {
+                self.timer
+                    .regs
+                    .dier
+                    .modify(|r, w| unsafe { w.bits(r.bits() | CC1_BIT) });
+            }
+
+            /// Disable the compare interrupt, when RTIC's timer queue is empty.
+            fn disable_timer(&mut self) {
+                self.timer
+                    .regs
+                    .dier
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !CC1_BIT) });
+            }
+        }
+    };
+}
+
+cfg_if! {
+    if #[cfg(not(any(
+        feature = "f410",
+        feature = "g070",
+        feature = "l5", // todo PAC bug?
+        feature = "wb55", // todo PAC bug?
+    )))] {
+        make_mono!(TIM2, tim2);
+    }
+}
+
+cfg_if! {
+    if #[cfg(any(
+       feature = "f373",
+       feature = "l4x5",
+       feature = "l4x6",
+       feature = "h7",
+       feature = "g473",
+       feature = "g474",
+       feature = "g483",
+       feature = "g484",
+       all(feature = "f4", not(feature = "f410")),
+   ))] {
+        make_mono!(TIM5, tim5);
+    }
+}
@@ -1,20 +1,401 @@
-//! Support for operational amplifiers.
-//! WIP / non-functional
+//! Support for the on-chip operational amplifiers (OPAMP). These can be used in standalone mode
+//! with external feedback, as voltage followers, or as programmable gain amplifiers (PGA), eg
+//! as an ADC front-end without external amplification.
+//!
+//! Which pins are connected to each input depends on the op-amp and MCU; see the datasheet. On
+//! G4, setting `internal_output` routes the output directly to an ADC channel.
+//!
+//! Currently supports L4 and G4.
 
-use core::ops::Deref;
+use cortex_m::interrupt::free;
 
-use crate::pac::opamp;
+use crate::{
+    clocks::Clocks,
+    pac::{OPAMP, RCC},
+};
 
-/// Represents an operational amplifier peripheral.
-pub struct Opamp<R> {
-    pub regs: R,
+use cfg_if::cfg_if;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Select the op-amp to use.
+pub enum OpampDevice {
+    One,
+    #[cfg(any(feature = "l4x5", feature = "l4x6", feature = "g4"))]
+    Two,
+    #[cfg(feature = "g4")]
+    Three,
+    #[cfg(all(
+        feature = "g4",
+        not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+    ))]
+    Four,
+    #[cfg(all(
+        feature = "g4",
+        not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+    ))]
+    Five,
+    #[cfg(all(feature = "g4", not(any(feature = "g431", feature = "g441"))))]
+    Six,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Op-amp operating mode. On L4, this sets `OPAMPx_CSR` register, `OPAMODE` field. On G4,
+/// it sets the `VM_SEL` field.
+pub enum OpampMode {
+    /// Use external components for feedback. The inverting input is selected by the `inm`
+    /// config field.
+    Standalone,
+    /// Unity-gain voltage follower; the inverting input is internally connected to the output.
+    Follower,
+    /// Programmable gain amplifier, using an internal feedback network. Non-inverting; gain is
+    /// set by the `pga_gain` config field.
+    Pga,
 }
 
-impl<R> Opamp<R>
-    where
-        R: Deref<Target = opamp::RegisterBlock>,
-{
-    pub fn new() -> Self {
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        #[derive(Clone, Copy)]
+        #[repr(u8)]
+        /// Non-inverting (+) input selection. `Vinp3` is connected to a DAC output on some
+        /// op-amps; see G4 RM, Table 199: Operational amplifier possible connections.
+        /// Sets `OPAMPx_CSR` register, `VP_SEL` field.
+        pub enum NonInvertingInput {
+            Vinp0 = 0b00,
+            Vinp1 = 0b01,
+            Vinp2 = 0b10,
+            Vinp3 = 0b11,
+        }
+
+        #[derive(Clone, Copy)]
+        #[repr(u8)]
+        /// Programmable gain amplifier gain, in non-inverting mode. Sets `OPAMPx_CSR` register,
+        /// `PGA_GAIN` field.
+        pub enum PgaGain {
+            G2 = 0b000,
+            G4 = 0b001,
+            G8 = 0b010,
+            G16 = 0b011,
+            G32 = 0b100,
+            G64 = 0b101,
+        }
+    } else {
+        #[derive(Clone, Copy)]
+        #[repr(u8)]
+        /// Non-inverting (+) input selection. Sets `OPAMPx_CSR` register, `VP_SEL` field.
+        pub enum NonInvertingInput {
+            /// The op-amp's VINP pin.
+            Gpio = 0,
+            /// The DAC channel output. (DAC1 for OPAMP1; DAC2 for OPAMP2.)
+            Dac = 1,
+        }
 
+        #[derive(Clone, Copy)]
+        #[repr(u8)]
+        /// Programmable gain amplifier gain. Sets `OPAMPx_CSR` register, `PGA_GAIN` field.
+        pub enum PgaGain {
+            G2 = 0b00,
+            G4 = 0b01,
+            G8 = 0b10,
+            G16 = 0b11,
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Inverting (-) input selection, in standalone mode. On L4, `Vinm1` is the dedicated
+/// low-leakage input, where available. Sets `OPAMPx_CSR` register, `VM_SEL` field.
+pub enum InvertingInput {
+    Vinm0 = 0b00,
+    Vinm1 = 0b01,
+}
+
+/// Configuration data for an op-amp.
+pub struct OpampConfig {
+    /// Defaults to Follower.
+    pub mode: OpampMode,
+    /// Non-inverting input. Defaults to VINP0 (G4), or the VINP pin. (L4)
+    pub inp: NonInvertingInput,
+    /// Inverting input; only used in `Standalone` mode. Defaults to VINM0.
+    pub inm: InvertingInput,
+    /// Gain; only used in `Pga` mode. Defaults to 2.
+    pub pga_gain: PgaGain,
+    #[cfg(feature = "g4")]
+    /// High-speed mode. Defaults to false.
+    pub high_speed: bool,
+    #[cfg(feature = "g4")]
+    /// Connect the output internally to an ADC channel, instead of to the VOUT pin. Defaults
+    /// to false.
+    pub internal_output: bool,
+    #[cfg(feature = "l4")]
+    /// Low-power mode. Defaults to false.
+    pub low_power: bool,
+    #[cfg(feature = "l4")]
+    /// Set this if VDDA is above 2.4V. Applies to all op-amps. Defaults to true.
+    pub high_vdda_range: bool,
+}
+
+impl Default for OpampConfig {
+    fn default() -> Self {
+        Self {
+            mode: OpampMode::Follower,
+            #[cfg(feature = "g4")]
+            inp: NonInvertingInput::Vinp0,
+            #[cfg(feature = "l4")]
+            inp: NonInvertingInput::Gpio,
+            inm: InvertingInput::Vinm0,
+            pga_gain: PgaGain::G2,
+            #[cfg(feature = "g4")]
+            high_speed: false,
+            #[cfg(feature = "g4")]
+            internal_output: false,
+            #[cfg(feature = "l4")]
+            low_power: false,
+            #[cfg(feature = "l4")]
+            high_vdda_range: true,
+        }
+    }
+}
+
+/// Modify an op-amp's CSR register. Each op-amp's register has a distinct type in the PAC.
+macro_rules! modify_csr {
+    ($device:expr, |$r:ident, $w:ident| $body:expr) => {{
+        let regs = unsafe { &(*OPAMP::ptr()) };
+        match $device {
+            OpampDevice::One => regs.opamp1_csr.modify(|$r, $w| $body),
+            #[cfg(any(feature = "l4x5", feature = "l4x6", feature = "g4"))]
+            OpampDevice::Two => regs.opamp2_csr.modify(|$r, $w| $body),
+            #[cfg(feature = "g4")]
+            OpampDevice::Three => regs.opamp3_csr.modify(|$r, $w| $body),
+            #[cfg(all(
+                feature = "g4",
+                not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+            ))]
+            OpampDevice::Four => regs.opamp4_csr.modify(|$r, $w| $body),
+            #[cfg(all(
+                feature = "g4",
+                not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+            ))]
+            OpampDevice::Five => regs.opamp5_csr.modify(|$r, $w| $body),
+            #[cfg(all(feature = "g4", not(any(feature = "g431", feature = "g441"))))]
+            OpampDevice::Six => regs.opamp6_csr.modify(|$r, $w| $body),
+        }
+    }};
+}
+
+/// Read a single-bit field from an op-amp's CSR register. Returns true if it's set.
+macro_rules! read_csr_bit {
+    ($device:expr, $field:ident) => {{
+        let regs = unsafe { &(*OPAMP::ptr()) };
+        match $device {
+            OpampDevice::One => regs.opamp1_csr.read().$field().bit_is_set(),
+            #[cfg(any(feature = "l4x5", feature = "l4x6", feature = "g4"))]
+            OpampDevice::Two => regs.opamp2_csr.read().$field().bit_is_set(),
+            #[cfg(feature = "g4")]
+            OpampDevice::Three => regs.opamp3_csr.read().$field().bit_is_set(),
+            #[cfg(all(
+                feature = "g4",
+                not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+            ))]
+            OpampDevice::Four => regs.opamp4_csr.read().$field().bit_is_set(),
+            #[cfg(all(
+                feature = "g4",
+                not(any(feature = "g431", feature = "g441", feature = "g491", feature = "g4a1"))
+            ))]
+            OpampDevice::Five => regs.opamp5_csr.read().$field().bit_is_set(),
+            #[cfg(all(feature = "g4", not(any(feature = "g431", feature = "g441"))))]
+            OpampDevice::Six => regs.opamp6_csr.read().$field().bit_is_set(),
+        }
+    }};
+}
+
+#[cfg(feature = "l4")]
+/// Modify an op-amp's offset trimming register. (Normal mode; not low-power)
+macro_rules! modify_otr {
+    ($device:expr, |$r:ident, $w:ident| $body:expr) => {{
+        let regs = unsafe { &(*OPAMP::ptr()) };
+        match $device {
+            OpampDevice::One => regs.opamp1_otr.modify(|$r, $w| $body),
+            #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+            OpampDevice::Two => regs.opamp2_otr.modify(|$r, $w| $body),
+        }
+    }};
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Which differential pair of the input stage to trim.
+enum TrimPair {
+    Nmos,
+    Pmos,
+}
+
+/// Represents an operational amplifier. The op-amps share a register block, so we don't own it;
+/// this struct accesses the relevant registers directly.
+pub struct Opamp {
+    pub device: OpampDevice,
+    pub cfg: OpampConfig,
+}
+
+impl Opamp {
+    /// Configure an op-amp, and enable its peripheral clock. (The SYSCFG clock on G4.)
+    /// Doesn't enable the op-amp; use `enable()` for that.
+    pub fn new(device: OpampDevice, cfg: OpampConfig) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            #[cfg(feature = "l4")]
+            rcc.apb1enr1.modify(|_, w| w.opampen().set_bit());
+            #[cfg(feature = "g4")]
+            rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+        });
+
+        cfg_if! {
+            if #[cfg(feature = "g4")] {
+                // G4 RM, section 25.5.1: VM_SEL selects follower and PGA modes. In PGA mode,
+                // `PGA_GAIN` bits 4:3 = 00 selects non-inverting, with the internal feedback
+                // network.
+                let vm_sel = match cfg.mode {
+                    OpampMode::Standalone => cfg.inm as u8,
+                    OpampMode::Pga => 0b10,
+                    OpampMode::Follower => 0b11,
+                };
+
+                modify_csr!(device, |_r, w| unsafe {
+                    w.vp_sel().bits(cfg.inp as u8);
+                    w.vm_sel().bits(vm_sel);
+                    w.pga_gain().bits(cfg.pga_gain as u8);
+                    w.opahsm().bit(cfg.high_speed);
+                    w.opaintoen().bit(cfg.internal_output)
+                });
+            } else {
+                // L4 RM, section 24.5.1: OPAMODE: 00, 01: Standalone. 10: PGA. 11: Follower.
+                let opamode = match cfg.mode {
+                    OpampMode::Standalone => 0b00,
+                    OpampMode::Pga => 0b10,
+                    OpampMode::Follower => 0b11,
+                };
+
+                // In PGA mode, VM_SEL = 0b1x connects the inverting input to the feedback network.
+                let vm_sel = match cfg.mode {
+                    OpampMode::Pga => 0b10,
+                    _ => cfg.inm as u8,
+                };
+
+                // `OPA_RANGE` is only present in OPAMP1's register, and must be set while all
+                // op-amps are disabled.
+                let regs = unsafe { &(*OPAMP::ptr()) };
+                regs.opamp1_csr.modify(|_, w| w.opa_range().bit(cfg.high_vdda_range));
+
+                modify_csr!(device, |_r, w| unsafe {
+                    w.opamode().bits(opamode);
+                    w.vp_sel().bit(cfg.inp as u8 != 0);
+                    w.vm_sel().bits(vm_sel);
+                    w.pga_gain().bits(cfg.pga_gain as u8);
+                    w.opalpm().bit(cfg.low_power)
+                });
+            }
+        }
+
+        Self { device, cfg }
+    }
+
+    /// Enable the op-amp.
+    pub fn enable(&mut self) {
+        modify_csr!(self.device, |_r, w| w.opaen().set_bit());
+    }
+
+    /// Disable the op-amp.
+    pub fn disable(&mut self) {
+        modify_csr!(self.device, |_r, w| w.opaen().clear_bit());
+    }
+
+    /// Set the PGA gain. Only affects `Pga` mode.
+    pub fn set_pga_gain(&mut self, gain: PgaGain) {
+        modify_csr!(self.device, |_r, w| unsafe {
+            w.pga_gain().bits(gain as u8)
+        });
+        self.cfg.pga_gain = gain;
+    }
+
+    #[cfg(feature = "g4")]
+    /// Lock the op-amp's configuration. It can't be modified until the next system reset.
+    pub fn lock(&mut self) {
+        modify_csr!(self.device, |_r, w| w.lock().set_bit());
+    }
+
+    /// Calibrate the input offset, using user trimming values, eg when VDDA or temperature differ
+    /// from the factory trimming conditions. Trims the NMOS, then PMOS differential pairs, using
+    /// a binary search on the `CALOUT` flag. See L4 RM, section 24.3.6: Calibration, or G4 RM,
+    /// section 25.3.7: Calibration. The op-amp is left disabled, with user trimming values
+    /// selected.
+    pub fn calibrate(&mut self, clock_cfg: &Clocks) {
+        // The offset trimming time (t_OFFTRIM) is up to 2ms; see the datasheet.
+        let trim_delay = clock_cfg.sysclk() / 1_000 * 2;
+
+        modify_csr!(self.device, |_r, w| {
+            w.usertrim().set_bit();
+            w.calon().set_bit()
+        });
+
+        self.enable();
+
+        for pair in [TrimPair::Nmos, TrimPair::Pmos] {
+            // G4 CALSEL: 0b11 = 0.9 VDDA for the NMOS pair; 0b01 = 0.1 VDDA for the PMOS pair.
+            // L4 CALSEL: 0 = NMOS pair; 1 = PMOS pair.
+            #[cfg(feature = "g4")]
+            modify_csr!(self.device, |_r, w| unsafe {
+                w.calsel()
+                    .bits(if pair == TrimPair::Nmos { 0b11 } else { 0b01 })
+            });
+            #[cfg(feature = "l4")]
+            modify_csr!(self.device, |_r, w| w.calsel().bit(pair == TrimPair::Pmos));
+
+            let mut trim = 16;
+            let mut delta = 8;
+
+            while delta != 0 {
+                self.set_trim(pair, trim);
+                cortex_m::asm::delay(trim_delay);
+
+                // `CALOUT` high means the trimming value is too low.
+                if read_csr_bit!(self.device, calout) {
+                    trim += delta;
+                } else {
+                    trim -= delta;
+                }
+                delta >>= 1;
+            }
+
+            // Check if the right value is the current one, or one step higher.
+            self.set_trim(pair, trim);
+            cortex_m::asm::delay(trim_delay);
+            if read_csr_bit!(self.device, calout) {
+                trim += 1;
+                self.set_trim(pair, trim);
+            }
+        }
+
+        modify_csr!(self.device, |_r, w| w.calon().clear_bit());
+        self.disable();
+    }
+
+    /// Set an offset trimming value.
+    fn set_trim(&mut self, pair: TrimPair, trim: u8) {
+        cfg_if! {
+            if #[cfg(feature = "g4")] {
+                modify_csr!(self.device, |_r, w| unsafe {
+                    match pair {
+                        TrimPair::Nmos => w.trimoffsetn().bits(trim),
+                        TrimPair::Pmos => w.trimoffsetp().bits(trim),
+                    }
+                });
+            } else {
+                modify_otr!(self.device, |_r, w| unsafe {
+                    match pair {
+                        TrimPair::Nmos => w.trimoffsetn().bits(trim),
+                        TrimPair::Pmos => w.trimoffsetp().bits(trim),
+                    }
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Support for the On-The-Fly Decryption engine (OTFDEC). This decrypts code, and data stored
+//! encrypted in external Octo-SPI flash as it's read in memory-mapped mode, eg when executing in
+//! place (XIP). Each OTFDEC has 4 regions, with separate keys, and address ranges. Images are
+//! encrypted with AES-128 in CTR mode, using the region's key, nonce, and version; eg with
+//! STM32CubeProgrammer, or ST's OTFDEC tooling.
+//!
+//! Configure the OTFDEC before enabling memory-mapped mode on the Octo-SPI. Lock the key, and
+//! configuration (eg in a bootloader) so the key can't be read back, or changed until the next
+//! reset. Keys are write-only; verify them using the key CRC.
+//!
+//! Available on L562, H7B3, and H735. (L562 has OTFDEC1, for OCTOSPI1, and H7 has OTFDEC1, and
+//! OTFDEC2, for OCTOSPI1, and OCTOSPI2 respectively.) See L5 RM, section: On-the-fly decryption
+//! engine (OTFDEC).
+//!
+//! Example, decrypting the first 64kB of external flash, on OCTOSPI1:
+//! ```rust
+//! let mut otfdec = Otfdec::new(OtfdecDevice::One);
+//!
+//! let crc = otfdec.configure_region(0, &OtfdecRegion {
+//!     start_addr: 0x9000_0000,
+//!     end_addr: 0x9000_ffff,
+//!     key: KEY,
+//!     nonce: NONCE,
+//!     version: 1,
+//!     mode: OtfdecMode::All,
+//! }, true);
+//! assert_eq!(crc, EXPECTED_KEY_CRC);
+//! ```
+
+use cortex_m::interrupt::free;
+
+use crate::pac::RCC;
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since PAC support for OTFDEC is inconsistent across these variants.
+cfg_if! {
+    if #[cfg(feature = "l5")] {
+        /// OTFDEC1 base address.
+        const OTFDEC1_BASE: usize = 0x420c_5000;
+        /// RCC_AHB2ENR, and RCC_AHB2RSTR: OTFDEC1EN, and OTFDEC1RST.
+        const RCC_BIT_1: u32 = 1 << 21;
+    } else {
+        /// OTFDEC1 base address.
+        const OTFDEC1_BASE: usize = 0x5200_b800;
+        /// OTFDEC2 base address.
+        const OTFDEC2_BASE: usize = 0x5200_bc00;
+        /// RCC_AHB3ENR, and RCC_AHB3RSTR: OTFD1EN, and OTFD1RST.
+        const RCC_BIT_1: u32 = 1 << 22;
+        /// RCC_AHB3ENR, and RCC_AHB3RSTR: OTFD2EN, and OTFD2RST.
+        const RCC_BIT_2: u32 = 1 << 23;
+    }
+}
+
+/// The number of regions per OTFDEC.
+pub const NUM_REGIONS: u8 = 4;
+
+// Register offsets.
+const CR: usize = 0x00;
+/// Region 1 configuration register. Each region's registers are 0x30 apart.
+const R1CFGR: usize = 0x20;
+const REGION_SPACING: usize = 0x30;
+// Offsets within a region's registers.
+const RXSTARTADDR: usize = 0x04;
+const RXENDADDR: usize = 0x08;
+const RXNONCER0: usize = 0x0c;
+const RXKEYR0: usize = 0x14;
+const ISR: usize = 0x300;
+const ICR: usize = 0x304;
+const IER: usize = 0x308;
+
+// RxCFGR bits.
+const REG_EN: u32 = 1 << 0;
+const CONFIGLOCK: u32 = 1 << 1;
+const KEYLOCK: u32 = 1 << 2;
+const MODE_SHIFT: u32 = 4;
+const KEYCRC_SHIFT: u32 = 8;
+const VERSION_SHIFT: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Selects an OTFDEC peripheral.
+pub enum OtfdecDevice {
+    /// OTFDEC1, which decrypts reads from OCTOSPI1.
+    One,
+    #[cfg(feature = "h7")]
+    /// OTFDEC2, which decrypts reads from OCTOSPI2.
+    Two,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Which accesses in a region are decrypted. Sets OTFDEC_RxCFGR, MODE field.
+pub enum OtfdecMode {
+    /// Only instruction accesses are decrypted.
+    Instruction = 0b00,
+    /// Only data accesses are decrypted.
+    Data = 0b01,
+    /// All read accesses are decrypted.
+    All = 0b10,
+    /// Only instruction accesses are decrypted, using an enhanced cipher. Data reads return 0.
+    InstructionEnhanced = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// OTFDEC error interrupts. Sets the OTFDEC_IER register.
+pub enum OtfdecInterrupt {
+    /// Security error; eg a non-secure, or unprivileged register access.
+    Security = 0,
+    /// Execute-only, or execute-never error; eg a data read in `InstructionEnhanced` mode.
+    ExecuteOnly = 1,
+    /// Key error: A read from an enabled region whose key wasn't fully written.
+    Key = 2,
+}
+
+/// An encrypted region's configuration.
+pub struct OtfdecRegion {
+    /// The region's first address, in the Octo-SPI memory-mapped range, eg 0x9000_0000. Must be
+    /// 4kB-aligned.
+    pub start_addr: u32,
+    /// The region's last address, eg 0x9000_ffff. Must end in 0xfff.
+    pub end_addr: u32,
+    /// The AES-128 key. `key[0]` is written to RxKEYR0, ie key bits 31:0.
+    pub key: [u32; 4],
+    /// The nonce used by the AES-CTR initialization vector. Sets OTFDEC_RxNONCER0, and RxNONCER1.
+    pub nonce: [u32; 2],
+    /// Image version, which is also part of the initialization vector. Sets OTFDEC_RxCFGR,
+    /// REGx_VERSION field.
+    pub version: u16,
+    pub mode: OtfdecMode,
+}
+
+/// Represents an OTFDEC peripheral.
+pub struct Otfdec {
+    base: usize,
+}
+
+impl Otfdec {
+    /// Enable and reset an OTFDEC's RCC peripheral clock.
+    pub fn new(device: OtfdecDevice) -> Self {
+        let (base, rcc_bit) = match device {
+            OtfdecDevice::One => (OTFDEC1_BASE, RCC_BIT_1),
+            #[cfg(feature = "h7")]
+            OtfdecDevice::Two => (OTFDEC2_BASE, RCC_BIT_2),
+        };
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            cfg_if! {
+                if #[cfg(feature = "l5")] {
+                    rcc.ahb2enr.modify(|r, w| unsafe { w.bits(r.bits() | rcc_bit) });
+                    rcc.ahb2rstr.modify(|r, w| unsafe { w.bits(r.bits() | rcc_bit) });
+                    rcc.ahb2rstr.modify(|r, w| unsafe { w.bits(r.bits() & !rcc_bit) });
+                } else {
+                    rcc.ahb3enr.modify(|r, w| unsafe { w.bits(r.bits() | rcc_bit) });
+                    rcc.ahb3rstr.modify(|r, w| unsafe { w.bits(r.bits() | rcc_bit) });
+                    rcc.ahb3rstr.modify(|r, w| unsafe { w.bits(r.bits() & !rcc_bit) });
+                }
+            }
+        });
+
+        Self { base }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    /// The offset of a region's configuration register.
+    fn region_offset(region: u8) -> usize {
+        assert!(region < NUM_REGIONS, "Invalid OTFDEC region.");
+        R1CFGR + region as usize * REGION_SPACING
+    }
+
+    /// Configure, and enable an encrypted region. `region` is 0 - 3. Set `lock` to lock the region's
+    /// key, and configuration until the next reset. Returns the key's 8-bit CRC, which can be
+    /// compared against the expected value to verify the key, without reading it back. Panics if the
+    /// region's configuration is locked.
+    pub fn configure_region(&mut self, region: u8, cfg: &OtfdecRegion, lock: bool) -> u8 {
+        let offset = Self::region_offset(region);
+        assert!(
+            self.read(offset) & CONFIGLOCK == 0,
+            "The OTFDEC region configuration is locked."
+        );
+        assert!(cfg.start_addr & 0xfff == 0 && cfg.end_addr & 0xfff == 0xfff);
+
+        // The region must be disabled while it's configured.
+        self.write(offset, self.read(offset) & !REG_EN);
+
+        self.write(offset + RXSTARTADDR, cfg.start_addr);
+        self.write(offset + RXENDADDR, cfg.end_addr);
+        self.write(offset + RXNONCER0, cfg.nonce[0]);
+        self.write(offset + RXNONCER0 + 4, cfg.nonce[1]);
+
+        // The key registers must be written in order, from KEYR0 to KEYR3. The CRC is computed
+        // once all 4 are written. Key writes are ignored if the key is locked.
+        for (i, word) in cfg.key.iter().enumerate() {
+            self.write(offset + RXKEYR0 + i * 4, *word);
+        }
+
+        let mut cfgr =
+            ((cfg.version as u32) << VERSION_SHIFT) | ((cfg.mode as u32) << MODE_SHIFT) | REG_EN;
+        if lock {
+            cfgr |= KEYLOCK | CONFIGLOCK;
+        }
+        self.write(offset, cfgr);
+
+        self.key_crc(region)
+    }
+
+    /// Disable decryption for a region; reads from it return the raw (encrypted) data.
+    pub fn disable_region(&mut self, region: u8) {
+        let offset = Self::region_offset(region);
+        self.write(offset, self.read(offset) & !REG_EN);
+    }
+
+    /// Read a region's key CRC. (OTFDEC_RxCFGR, KEYCRC field)
+    pub fn key_crc(&self, region: u8) -> u8 {
+        (self.read(Self::region_offset(region)) >> KEYCRC_SHIFT) as u8
+    }
+
+    /// Returns true if a region's configuration is locked until the next reset.
+    pub fn region_locked(&self, region: u8) -> bool {
+        self.read(Self::region_offset(region)) & CONFIGLOCK != 0
+    }
+
+    /// Enable or disable encryption mode, where data written through an enabled region is encrypted,
+    /// eg for preparing encrypted images on-device. Leave it disabled for normal decryption.
+    /// (OTFDEC_CR, ENC bit)
+    pub fn set_encryption_mode(&mut self, enabled: bool) {
+        let val = self.read(CR);
+        self.write(CR, if enabled { val | 1 } else { val & !1 });
+    }
+
+    /// Enable an error interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: OtfdecInterrupt) {
+        self.write(IER, self.read(IER) | (1 << interrupt as u8));
+    }
+
+    /// Returns true if an error interrupt flag is set.
+    pub fn interrupt_pending(&self, interrupt: OtfdecInterrupt) -> bool {
+        self.read(ISR) & (1 << interrupt as u8) != 0
+    }
+
+    /// Clear an error interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: OtfdecInterrupt) {
+        self.write(ICR, 1 << interrupt as u8);
+    }
+}
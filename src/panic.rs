@@ -0,0 +1,229 @@
+//! Selectable panic behavior. Enable one of these features to have the HAL provide the
+//! `#[panic_handler]`; don't use a panic handler crate like `panic-probe` alongside it:
+//!
+//! - `panic_reset`: Record the panic location, and reset the MCU. For products in the field,
+//!   where recovering is better than stopping.
+//! - `panic_halt`: Record the panic location, and halt; at a breakpoint, if a debugger is
+//!   attached. For development boards.
+//! - `panic_park`: Record the panic location, blink a distress pattern on an LED registered with
+//!   `set_distress_led()`, then enter the lowest-power mode available, without resetting. For
+//!   battery-powered products, where a reset loop would drain the battery.
+//!
+//! The location is stored in the last 2 backup registers, so it survives the reset; read it after
+//! startup with `last_panic()`. The RTC, or TAMP clock must be enabled for this, eg by setting up
+//! the RTC. Without any of these features, the record functions are still available for use in
+//! your own panic handler.
+//!
+//! Example:
+//! ```rust
+//! // Early in `main`:
+//! if let Some(record) = panic::last_panic() {
+//!     if record.file_hash == panic::file_hash("src/main.rs") {
+//!         // Report the panic line to the server, etc.
+//!     }
+//!     panic::clear_panic_record();
+//! }
+//!
+//! panic::set_distress_led(Port::A, 5, false, clock_cfg.sysclk());
+//! ```
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use crate::{backup, gpio::Port};
+
+#[cfg(any(
+    feature = "panic_reset",
+    feature = "panic_halt",
+    feature = "panic_park"
+))]
+use core::panic::PanicInfo;
+
+#[cfg(any(
+    all(feature = "panic_reset", feature = "panic_halt"),
+    all(feature = "panic_reset", feature = "panic_park"),
+    all(feature = "panic_halt", feature = "panic_park"),
+))]
+compile_error!("Enable at most one of the `panic_reset`, `panic_halt`, and `panic_park` features.");
+
+/// The backup register holding the panicking file's hash.
+const REG_FILE: usize = backup::NUM_BACKUP_REGS - 2;
+/// The backup register holding `RECORD_MAGIC` in its upper half, and the line in its lower half.
+const REG_LINE: usize = backup::NUM_BACKUP_REGS - 1;
+
+/// Marks a valid panic record.
+const RECORD_MAGIC: u32 = 0x9a1c;
+
+/// Distress pattern timing, in milliseconds.
+const FLASH_MS: u32 = 150;
+const PAUSE_MS: u32 = 1_000;
+/// The number of flashes in each repetition of the distress pattern.
+const NUM_FLASHES: u8 = 3;
+/// The number of times to repeat the distress pattern, before parking.
+const NUM_REPEATS: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The location of the most recent panic, as recorded in backup registers.
+pub struct PanicRecord {
+    /// The FNV-1a hash of the source file path; compare with `file_hash()`. If the location
+    /// wasn't available, this is the hash of an empty path.
+    pub file_hash: u32,
+    /// The line number, saturating at 65,535.
+    pub line: u16,
+}
+
+#[derive(Clone, Copy)]
+/// An LED to blink the distress pattern on.
+struct DistressLed {
+    port: Port,
+    pin: u8,
+    active_low: bool,
+    /// Core clock cycles per millisecond, for blocking delays.
+    cycles_per_ms: u32,
+}
+
+static DISTRESS_LED: Mutex<Cell<Option<DistressLed>>> = Mutex::new(Cell::new(None));
+
+/// Hash a source file path, as `PanicRecord` stores it. (32-bit FNV-1a)
+pub const fn file_hash(file: &str) -> u32 {
+    let bytes = file.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+
+    hash
+}
+
+/// Store a panic location in the last 2 backup registers. Unlocks the backup domain. The panic
+/// handlers call this; it's public for use in custom ones.
+pub fn record_panic(file: &str, line: u32) {
+    backup::unlock();
+    backup::write_reg(REG_FILE, file_hash(file));
+    backup::write_reg(REG_LINE, RECORD_MAGIC << 16 | line.min(0xffff));
+}
+
+/// Read the most recent panic's location, if one has been recorded since the last
+/// `clear_panic_record()`, or backup domain reset.
+pub fn last_panic() -> Option<PanicRecord> {
+    let line = backup::read_reg(REG_LINE);
+
+    if line >> 16 != RECORD_MAGIC {
+        return None;
+    }
+
+    Some(PanicRecord {
+        file_hash: backup::read_reg(REG_FILE),
+        line: line as u16,
+    })
+}
+
+/// Clear the panic record, so the next `last_panic()` only reports a new panic. Unlocks the
+/// backup domain.
+pub fn clear_panic_record() {
+    backup::unlock();
+    backup::write_reg(REG_FILE, 0);
+    backup::write_reg(REG_LINE, 0);
+}
+
+/// Register an LED to blink a distress pattern on, with the `panic_park` feature. The pin must be
+/// configured as an output. If `active_low` is true, the LED lights when the pin is low.
+/// `sysclk` is the core clock speed, in Hz, for timing the pattern.
+pub fn set_distress_led(port: Port, pin: u8, active_low: bool, sysclk: u32) {
+    free(|cs| {
+        DISTRESS_LED.borrow(cs).set(Some(DistressLed {
+            port,
+            pin,
+            active_low,
+            cycles_per_ms: sysclk / 1_000,
+        }))
+    });
+}
+
+/// Blink the distress pattern on the registered LED, if there is one: `NUM_FLASHES` short
+/// flashes, then a pause, `NUM_REPEATS` times. Blocking.
+pub fn blink_distress() {
+    let led = match free(|cs| DISTRESS_LED.borrow(cs).get()) {
+        Some(l) => l,
+        None => return,
+    };
+
+    let set_lit = |lit: bool| {
+        if lit != led.active_low {
+            crate::gpio::set_high(led.port, led.pin);
+        } else {
+            crate::gpio::set_low(led.port, led.pin);
+        }
+    };
+
+    for _ in 0..NUM_REPEATS {
+        for _ in 0..NUM_FLASHES {
+            set_lit(true);
+            cortex_m::asm::delay(FLASH_MS * led.cycles_per_ms);
+            set_lit(false);
+            cortex_m::asm::delay(FLASH_MS * led.cycles_per_ms);
+        }
+        cortex_m::asm::delay(PAUSE_MS * led.cycles_per_ms);
+    }
+}
+
+/// Disable interrupts, and enter the lowest-power mode available, staying there until a reset,
+/// or wakeup pin. This is Standby mode, or on H7, WB, and WL, CStop.
+pub fn park() -> ! {
+    cortex_m::interrupt::disable();
+
+    loop {
+        cfg_if::cfg_if! {
+            if #[cfg(any(
+                feature = "f3",
+                feature = "f4",
+                feature = "l4",
+                feature = "l5",
+                feature = "g0",
+                feature = "g4"
+            ))] {
+                crate::low_power::standby();
+            } else {
+                crate::low_power::cstop();
+            }
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "panic_reset",
+    feature = "panic_halt",
+    feature = "panic_park"
+))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    match info.location() {
+        Some(loc) => record_panic(loc.file(), loc.line()),
+        None => record_panic("", 0),
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "panic_reset")] {
+            cortex_m::peripheral::SCB::sys_reset();
+        } else if #[cfg(feature = "panic_halt")] {
+            // DHCSR, C_DEBUGEN: A breakpoint without a debugger attached causes a hard fault.
+            let dhcsr = unsafe { core::ptr::read_volatile(0xe000_edf0 as *const u32) };
+            if dhcsr & 1 != 0 {
+                cortex_m::asm::bkpt();
+            }
+            loop {
+                core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+            }
+        } else {
+            blink_distress();
+            park();
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Run multiple periodic callbacks from a single hardware timer. Register functions with
+//! `every()`; the timer's interrupt handler calls `tick()`, which runs the ones that are due. This
+//! saves dedicating a timer to each periodic job, eg blinking an LED, polling a sensor, and
+//! sending telemetry.
+//!
+//! Scheduling is drift-free: Each callback's next run is its previous due time plus its period,
+//! not the time it actually ran, so timing errors don't accumulate. Callbacks run in the timer's
+//! interrupt context, so keep them short.
+//!
+//! If the handler can be delayed by more than a tick, eg by long callbacks, or higher-priority
+//! interrupts, call `advance()` with the number of ticks elapsed instead of `tick()`, eg measured
+//! from a free-running timer's counter. Missed runs are then skipped, and counted in
+//! `overruns()`, instead of being run back-to-back to catch up; a stall can't turn into a burst
+//! of calls that starves the main loop, and the watchdog it pets. `overruns()` is also useful as
+//! a health check before petting a watchdog.
+//!
+//! Example:
+//! ```rust
+//! let mut timer = Timer::new_tim6(dp.TIM6, 1_000., Default::default(), &clock_cfg);
+//! timer.enable_interrupt(TimerInterrupt::Update);
+//!
+//! periodic::init(1_000);
+//! periodic::every(Duration::from_millis(500), toggle_led).unwrap();
+//! periodic::every(Duration::from_secs(10), send_telemetry).unwrap();
+//!
+//! timer.enable();
+//! unsafe { NVIC::unmask(pac::Interrupt::TIM6_DACUNDER) };
+//!
+//! #[interrupt]
+//! fn TIM6_DACUNDER() {
+//!     unsafe { (*pac::TIM6::ptr()).sr.modify(|_, w| w.uif().clear_bit()) };
+//!     periodic::tick();
+//! }
+//! ```
+
+use core::{cell::Cell, time::Duration};
+
+use cortex_m::interrupt::{free, Mutex};
+
+/// The maximum number of callbacks that can be registered at once.
+pub const MAX_CALLBACKS: usize = 8;
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// All `MAX_CALLBACKS` slots are in use.
+    Full,
+    /// There's no callback with this id.
+    InvalidId,
+}
+
+#[derive(Clone, Copy)]
+/// A registered callback.
+struct Entry {
+    /// Period, in ticks.
+    period: u64,
+    /// The tick this callback is next due.
+    next_due: u64,
+    f: fn(),
+}
+
+/// Tick frequency, in Hz; the frequency `tick()` is called at.
+static TICK_HZ: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+/// Ticks since `init()`.
+static NOW: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+static ENTRIES: Mutex<Cell<[Option<Entry>; MAX_CALLBACKS]>> =
+    Mutex::new(Cell::new([None; MAX_CALLBACKS]));
+/// The number of runs skipped, since `init()`, or the last `clear_overruns()`.
+static OVERRUNS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Set the tick frequency, in Hz: The update frequency of the timer whose interrupt calls
+/// `tick()`. Resets the tick count, and removes all callbacks.
+pub fn init(tick_hz: u32) {
+    assert!(tick_hz > 0, "The tick frequency must be nonzero.");
+
+    free(|cs| {
+        TICK_HZ.borrow(cs).set(tick_hz);
+        NOW.borrow(cs).set(0);
+        ENTRIES.borrow(cs).set([None; MAX_CALLBACKS]);
+        OVERRUNS.borrow(cs).set(0);
+    });
+}
+
+/// Register `f` to run every `period`, starting one period from now. The period is rounded to a
+/// whole number of ticks, and must be at least one tick. Returns an id that can be passed to
+/// `cancel()`. Can be called from a callback.
+pub fn every(period: Duration, f: fn()) -> Result<usize, Error> {
+    free(|cs| {
+        let tick_hz = TICK_HZ.borrow(cs).get();
+        assert!(tick_hz > 0, "Call `periodic::init()` first.");
+
+        let period = (period.as_nanos() * tick_hz as u128 / 1_000_000_000) as u64;
+        assert!(period > 0, "The period must be at least 1 tick.");
+
+        let cell = ENTRIES.borrow(cs);
+        let mut entries = cell.get();
+
+        let id = entries
+            .iter()
+            .position(|e| e.is_none())
+            .ok_or(Error::Full)?;
+
+        entries[id] = Some(Entry {
+            period,
+            next_due: NOW.borrow(cs).get() + period,
+            f,
+        });
+        cell.set(entries);
+
+        Ok(id)
+    })
+}
+
+/// Unregister a callback, freeing its slot. Can be called from a callback, including the one
+/// being cancelled.
+pub fn cancel(id: usize) -> Result<(), Error> {
+    free(|cs| {
+        let cell = ENTRIES.borrow(cs);
+        let mut entries = cell.get();
+
+        match entries.get_mut(id) {
+            Some(e @ Some(_)) => {
+                *e = None;
+                cell.set(entries);
+                Ok(())
+            }
+            _ => Err(Error::InvalidId),
+        }
+    })
+}
+
+/// Advance the tick count by one, and run the callbacks that are due. Call this from the timer's
+/// interrupt handler, after clearing its update flag.
+pub fn tick() {
+    advance(1);
+}
+
+/// Advance the tick count by `ticks`, and run the callbacks that are due. Each callback runs at
+/// most once per call; runs missed due to a late call are skipped. Use this instead of `tick()`
+/// if the timer's interrupt may be handled late.
+pub fn advance(ticks: u32) {
+    let mut due: [Option<fn()>; MAX_CALLBACKS] = [None; MAX_CALLBACKS];
+
+    free(|cs| {
+        let now = NOW.borrow(cs).get() + ticks as u64;
+        NOW.borrow(cs).set(now);
+
+        let cell = ENTRIES.borrow(cs);
+        let mut entries = cell.get();
+        let mut overruns = 0;
+
+        for (entry, due) in entries.iter_mut().zip(due.iter_mut()) {
+            if let Some(e) = entry {
+                if e.next_due > now {
+                    continue;
+                }
+                *due = Some(e.f);
+                e.next_due += e.period;
+
+                // Skip runs we've missed, staying in phase with the original schedule.
+                if e.next_due <= now {
+                    let missed = (now - e.next_due) / e.period + 1;
+                    e.next_due += missed * e.period;
+                    overruns += missed as u32;
+                }
+            }
+        }
+
+        cell.set(entries);
+
+        let count = OVERRUNS.borrow(cs);
+        count.set(count.get().saturating_add(overruns));
+    });
+
+    // Run callbacks outside the critical section, so other interrupts aren't blocked, and
+    // callbacks can register, or cancel others.
+    for f in due.iter().flatten() {
+        f();
+    }
+}
+
+/// Time since `init()`, as counted in ticks.
+pub fn uptime() -> Duration {
+    let (now, tick_hz) = free(|cs| (NOW.borrow(cs).get(), TICK_HZ.borrow(cs).get()));
+
+    if tick_hz == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos((now as u128 * 1_000_000_000 / tick_hz as u128) as u64)
+}
+
+/// The number of callback runs skipped due to late `advance()` calls, since `init()`, or the last
+/// `clear_overruns()`. Nonzero indicates callbacks, or interrupts are taking too long.
+pub fn overruns() -> u32 {
+    free(|cs| OVERRUNS.borrow(cs).get())
+}
+
+pub fn clear_overruns() {
+    free(|cs| OVERRUNS.borrow(cs).set(0));
+}
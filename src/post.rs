@@ -0,0 +1,295 @@
+//! Power-on self-test (POST) support. Drivers and application code register quick self-tests,
+//! which are run at boot, and produce a consolidated report. Useful for production testing.
+//!
+//! Includes built-in tests for a RAM pattern on a scratch buffer, RTC ticking, LSE presence,
+//! and an ADC VREFINT sanity check. Also includes a pin loopback utility, for bed-of-nails
+//! testing of assembled boards.
+//!
+//! Example:
+//! ```rust
+//! let mut scratch = [0_u32; 64];
+//! let mut ram = || post::ram_pattern(&mut scratch);
+//! let mut lse = || post::lse_present();
+//! let mut rtc_test = || post::rtc_ticking(&mut rtc, &clock_cfg);
+//!
+//! let mut post: Post<4> = Post::new();
+//! post.register("RAM", &mut ram).unwrap();
+//! post.register("LSE", &mut lse).unwrap();
+//! post.register("RTC", &mut rtc_test).unwrap();
+//!
+//! let report = post.run();
+//! if !report.passed() {
+//!     for name in report.failures() {
+//!         defmt::println!("POST failure: {}", name);
+//!     }
+//! }
+//! ```
+
+use core::ptr;
+
+use crate::{
+    clocks::Clocks,
+    gpio::{Pin, PinMode, PinState, Pull},
+    pac::RCC,
+    rtc::Rtc,
+};
+
+#[cfg(not(any(feature = "f301", feature = "f302")))]
+use crate::adc::{VREFINT_ADDR, VREFINT_VOLTAGE};
+
+// Patterns written to the scratch buffer by `ram_pattern`. Alternating bits catch shorted
+// neighboring cells; all 0s and 1s catch stuck bits.
+const RAM_PATTERNS: [u32; 4] = [0x5555_5555, 0xAAAA_AAAA, 0x0000_0000, 0xFFFF_FFFF];
+
+// Minimum and maximum VDDA, in V, that we accept as sane for the VREFINT test. From the
+// DS operating conditions tables.
+const VDDA_MIN: f32 = 1.62;
+const VDDA_MAX: f32 = 3.6;
+
+// How long to wait for the RTC sub-seconds register to change, in ms. With the default
+// prescalers, it changes every ~4ms.
+const RTC_TICK_WAIT_MS: u32 = 20;
+
+// Number of CPU cycles to wait after changing a loopback pin's level, before reading its pair.
+// Allows for fixture capacitance, and the input synchronizer.
+const LOOPBACK_SETTLE_CYCLES: u32 = 1_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The outcome of a single self-test.
+pub enum TestResult {
+    /// The test completed, and the hardware behaved as expected.
+    Pass,
+    /// The test completed, and the hardware did not behave as expected.
+    Fail,
+    /// The test was not run; eg because the hardware under test isn't configured.
+    Skipped,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// POST error type.
+pub enum PostError {
+    /// No more room to register tests. Increase the `N` parameter of `Post`.
+    Full,
+}
+
+/// A self-test registered with `Post`.
+struct SelfTest<'a> {
+    name: &'static str,
+    test: &'a mut dyn FnMut() -> TestResult,
+}
+
+/// Collects self-tests, and runs them in the order registered. `N` is the maximum number of
+/// tests that can be registered.
+pub struct Post<'a, const N: usize> {
+    tests: [Option<SelfTest<'a>>; N],
+    len: usize,
+}
+
+impl<'a, const N: usize> Post<'a, N> {
+    /// Create a new POST runner, with no tests registered.
+    pub fn new() -> Self {
+        Self {
+            tests: core::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+
+    /// Register a test. `name` is used to identify it in the report.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        test: &'a mut dyn FnMut() -> TestResult,
+    ) -> Result<(), PostError> {
+        if self.len >= N {
+            return Err(PostError::Full);
+        }
+
+        self.tests[self.len] = Some(SelfTest { name, test });
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Run all registered tests, and return a consolidated report.
+    pub fn run(&mut self) -> PostReport<N> {
+        let mut result = PostReport { results: [None; N] };
+
+        for (i, test) in self.tests.iter_mut().enumerate() {
+            if let Some(t) = test {
+                result.results[i] = Some((t.name, (t.test)()));
+            }
+        }
+
+        result
+    }
+}
+
+impl<'a, const N: usize> Default for Post<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Results of running all tests registered with `Post`.
+pub struct PostReport<const N: usize> {
+    /// Test names and results, in the order they were registered.
+    pub results: [Option<(&'static str, TestResult)>; N],
+}
+
+impl<const N: usize> PostReport<N> {
+    /// Returns true if no test failed. Skipped tests don't count as failures.
+    pub fn passed(&self) -> bool {
+        self.num_failed() == 0
+    }
+
+    /// The number of tests that failed.
+    pub fn num_failed(&self) -> usize {
+        self.failures().count()
+    }
+
+    /// The names of tests that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.results.iter().filter_map(|r| match r {
+            Some((name, TestResult::Fail)) => Some(*name),
+            _ => None,
+        })
+    }
+
+    /// Look up the result of a test by name.
+    pub fn get(&self, name: &str) -> Option<TestResult> {
+        self.results
+            .iter()
+            .flatten()
+            .find(|(n, _)| *n == name)
+            .map(|(_, r)| *r)
+    }
+}
+
+/// Write several patterns to a scratch buffer, and verify they read back correctly. Also writes
+/// each word's index to it, to catch address line faults. The buffer's contents are not preserved.
+pub fn ram_pattern(buf: &mut [u32]) -> TestResult {
+    if buf.is_empty() {
+        return TestResult::Skipped;
+    }
+
+    for pattern in RAM_PATTERNS {
+        for word in buf.iter_mut() {
+            unsafe { ptr::write_volatile(word, pattern) };
+        }
+        for word in buf.iter() {
+            if unsafe { ptr::read_volatile(word) } != pattern {
+                return TestResult::Fail;
+            }
+        }
+    }
+
+    // Address-as-data.
+    for (i, word) in buf.iter_mut().enumerate() {
+        unsafe { ptr::write_volatile(word, i as u32) };
+    }
+    for (i, word) in buf.iter().enumerate() {
+        if unsafe { ptr::read_volatile(word) } != i as u32 {
+            return TestResult::Fail;
+        }
+    }
+
+    TestResult::Pass
+}
+
+/// Check that the LSE oscillator is running. Reads the `RCC_BDCR` register, `LSEON` and `LSERDY`
+/// fields. Skipped if the LSE hasn't been enabled, eg by `Clocks::setup()`, or `Rtc::new()`.
+pub fn lse_present() -> TestResult {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let bdcr = rcc.bdcr.read();
+
+    if bdcr.lseon().bit_is_clear() {
+        TestResult::Skipped
+    } else if bdcr.lserdy().bit_is_set() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// Check that the RTC is running, by verifying its sub-seconds register changes over a short
+/// period. The delay is a busy loop, based on the sysclk speed, so SysTick isn't affected.
+pub fn rtc_ticking(rtc: &mut Rtc, clock_cfg: &Clocks) -> TestResult {
+    let start = rtc.get_subseconds();
+    cortex_m::asm::delay(clock_cfg.sysclk() / 1_000 * RTC_TICK_WAIT_MS);
+
+    if rtc.get_subseconds() != start {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+#[cfg(not(any(feature = "f301", feature = "f302")))]
+/// Check that a raw ADC reading of the internal voltage reference (VREFINT) is consistent with
+/// its factory calibration value, ie that it implies a VDDA within the MCU's operating range.
+/// Take this reading with VREFINT enabled in the common ADC regs (`VREFEN` bit), and a long
+/// sample time.
+pub fn vrefint_sanity(vrefint_reading: u16) -> TestResult {
+    if vrefint_reading == 0 {
+        return TestResult::Fail;
+    }
+
+    let vrefint_cal: u16 = unsafe { ptr::read_volatile(&*(VREFINT_ADDR as *const _)) };
+    let vdda = VREFINT_VOLTAGE * vrefint_cal as f32 / vrefint_reading as f32;
+
+    if (VDDA_MIN..=VDDA_MAX).contains(&vdda) {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+#[derive(Clone, Copy)]
+/// Details about the first pin pair that failed `pin_loopback_test`.
+pub struct LoopbackFailure {
+    /// The index of the failed pair, in the slice passed to `pin_loopback_test`.
+    pub pair: usize,
+    /// The level we were driving when the sensed pin didn't match.
+    pub driven: PinState,
+}
+
+/// Drive the first pin of each pair, and verify the level on its paired pin. For bed-of-nails
+/// production testing, where a fixture connects the pairs together. Tests both high and low
+/// levels; the sensing pin's internal pull is set opposite to the driven level, so an open
+/// connection is detected. Pins are left as floating inputs when complete.
+pub fn pin_loopback_test(pairs: &mut [(Pin, Pin)]) -> Result<(), LoopbackFailure> {
+    let mut result = Ok(());
+
+    for (i, (drive, sense)) in pairs.iter_mut().enumerate() {
+        drive.mode(PinMode::Output);
+        sense.mode(PinMode::Input);
+
+        for level in [PinState::High, PinState::Low] {
+            let (pull, expected_high) = match level {
+                PinState::High => (Pull::Dn, true),
+                PinState::Low => (Pull::Up, false),
+            };
+
+            sense.pull(pull);
+            drive.set_state(level);
+            cortex_m::asm::delay(LOOPBACK_SETTLE_CYCLES);
+
+            if sense.is_high() != expected_high {
+                result = Err(LoopbackFailure {
+                    pair: i,
+                    driven: level,
+                });
+                break;
+            }
+        }
+
+        drive.mode(PinMode::Input);
+        sense.pull(Pull::Floating);
+
+        if result.is_err() {
+            break;
+        }
+    }
+
+    result
+}
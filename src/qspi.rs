@@ -1,6 +1,43 @@
 //! Quad Serial Peripheral Interface (SPI) bus: A specialized interface used for
 //! high-speed communications with external flash memory. Also supports OctoSPI
 //! on variants that support it.
+//!
+//! Flash operations are described by `Command`s, with optional instruction, address, alternate
+//! byte, dummy cycle, and data phases. Use them in indirect mode (`command()`,
+//! `write_command()`, and `read_command()`), status-polling mode (`poll_status()`), eg to wait
+//! for a write to finish, and memory-mapped mode (`memory_mapped()`), where the flash is read
+//! directly from the CPU's address space, eg to execute code in place (XIP).
+//!
+//! Example, with a typical NOR flash:
+//! ```rust
+//! let write_enable = Command { instruction: Some(0x06), ..Default::default() };
+//! qspi.command(&write_enable)?;
+//!
+//! // Page program, with data on 4 lines.
+//! qspi.write_command(&Command {
+//!     instruction: Some(0x32),
+//!     address: Some(0x1000),
+//!     data_mode: Some(ProtocolMode::Quad),
+//!     ..Default::default()
+//! }, &data)?;
+//!
+//! // Wait until the status register's BUSY bit (bit 0) is clear.
+//! qspi.poll_status(&Command {
+//!     instruction: Some(0x05),
+//!     data_mode: Some(ProtocolMode::Single),
+//!     ..Default::default()
+//! }, 0x01, 0x00, 1, 0x10)?;
+//!
+//! // Fast read, quad output, then map the flash at 0x9000_0000.
+//! qspi.memory_mapped(&Command {
+//!     instruction: Some(0x6b),
+//!     address: Some(0),
+//!     dummy_cycles: 8,
+//!     data_mode: Some(ProtocolMode::Quad),
+//!     ..Default::default()
+//! });
+//! let first_word = unsafe { core::ptr::read_volatile(0x9000_0000 as *const u32) };
+//! ```
 
 use crate::{clocks::Clocks, pac::RCC};
 
@@ -18,8 +55,6 @@ use core::ptr;
 
 use cortex_m::interrupt::free;
 
-// todo: Status-polling mode.
-
 // todo: Is this avail in PAC? Feature-gate if diff on diff platforms?
 const MEM_MAPPED_BASE_ADDR: usize = 0x9000_0000;
 
@@ -49,7 +84,7 @@ pub enum DataMode {
     Ddr = 1,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 #[repr(u8)]
 /// Sets the Qspi Functional Mode. Affects the FMODE field of the CCR reg.
 pub enum FunctionalMode {
@@ -84,6 +119,68 @@ pub enum SamplingEdge {
 pub enum QspiError {
     Busy,
     Underflow,
+    /// A transfer error; eg an invalid address. (SR, TEF bit)
+    Transfer,
+}
+
+// We use raw bits for command sequences, so that each register is written once, in the order
+// that starts the transfer, and since the QUADSPI and OCTOSPI fields differ.
+// SR, and FCR bits.
+const TEF: u32 = 1 << 0;
+const TCF: u32 = 1 << 1;
+const FTF: u32 = 1 << 2;
+const SMF: u32 = 1 << 3;
+/// SR: FIFO level.
+const FLEVEL_SHIFT: u32 = 8;
+// CR bits.
+const ABORT: u32 = 1 << 1;
+const APMS: u32 = 1 << 22;
+const PMM: u32 = 1 << 23;
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+const FMODE_SHIFT: u32 = 28;
+
+/// A command sequence, for indirect, status-polling, and memory-mapped modes. Each phase is
+/// optional. The address size is set by `QspiConfig::address_size`, and the data phase uses the
+/// `QspiConfig::data_mode` (SDR or DDR).
+#[derive(Copy, Clone)]
+pub struct Command {
+    /// The instruction byte; eg 0x0b for a fast read. `None` skips the instruction phase.
+    /// Defaults to `None`.
+    pub instruction: Option<u8>,
+    /// Defaults to `Single`.
+    pub instruction_mode: ProtocolMode,
+    /// The address; ignored in memory-mapped mode. `None` skips the address phase. Defaults to
+    /// `None`. In memory-mapped mode, set this to any value to include the address phase.
+    pub address: Option<u32>,
+    /// Defaults to `Single`.
+    pub address_mode: ProtocolMode,
+    /// Alternate bytes, sent after the address; eg a continuous read mode byte. Defaults to `None`.
+    pub alt_bytes: Option<u32>,
+    /// Defaults to 8 bits.
+    pub alt_bytes_size: AddressSize,
+    /// Defaults to `Single`.
+    pub alt_bytes_mode: ProtocolMode,
+    /// Clock cycles between the address, or alternate bytes, and data phases; 0 - 31. Defaults
+    /// to 0.
+    pub dummy_cycles: u8,
+    /// The data phase's lines. `None` for commands without a data phase. Defaults to `None`.
+    pub data_mode: Option<ProtocolMode>,
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Self {
+            instruction: None,
+            instruction_mode: ProtocolMode::Single,
+            address: None,
+            address_mode: ProtocolMode::Single,
+            alt_bytes: None,
+            alt_bytes_size: AddressSize::A8,
+            alt_bytes_mode: ProtocolMode::Single,
+            dummy_cycles: 0,
+            data_mode: None,
+        }
+    }
 }
 
 // todo: Use bank on suitable MCUs? Which? F7 / H7?
@@ -404,6 +501,222 @@ impl Qspi {
         Ok(())
     }
 
+    /// Set up, and start a command. `data_len` is the data phase length, in bytes. In indirect
+    /// mode, writing the address (or the instruction, if there's no address phase) starts the
+    /// transfer, so these are written last.
+    fn start_command(&mut self, cmd: &Command, mode: FunctionalMode, data_len: usize) {
+        assert!(
+            cmd.dummy_cycles < 32,
+            "Dummy cycles must be between 0 and 31."
+        );
+        while self.is_busy() {}
+
+        self.regs
+            .fcr
+            .write(|w| unsafe { w.bits(TEF | TCF | SMF | (1 << 4)) });
+
+        if data_len > 0 {
+            self.regs
+                .dlr
+                .write(|w| unsafe { w.bits(data_len as u32 - 1) });
+        }
+
+        if let Some(ab) = cmd.alt_bytes {
+            self.regs.abr.write(|w| unsafe { w.bits(ab) });
+        }
+
+        let ddr = cmd.data_mode.is_some() && matches!(self.cfg.data_mode, DataMode::Ddr);
+
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                self.regs.cr.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0b11 << FMODE_SHIFT)) | (mode as u32) << FMODE_SHIFT)
+                });
+
+                // OCTOSPI_CCR: IMODE, ADMODE, ADSIZE, ABMODE, ABSIZE, DMODE, and DDTR.
+                let mut ccr = 0;
+                if cmd.instruction.is_some() {
+                    ccr |= cmd.instruction_mode as u32;
+                }
+                if cmd.address.is_some() {
+                    ccr |= (cmd.address_mode as u32) << 8 | (self.cfg.address_size as u32) << 12;
+                }
+                if cmd.alt_bytes.is_some() {
+                    ccr |= (cmd.alt_bytes_mode as u32) << 16 | (cmd.alt_bytes_size as u32) << 20;
+                }
+                if let Some(data_mode) = cmd.data_mode {
+                    ccr |= (data_mode as u32) << 24;
+                }
+                if ddr {
+                    ccr |= 1 << 27;
+                }
+
+                self.regs.tcr.write(|w| unsafe { w.bits(cmd.dummy_cycles as u32) });
+                self.regs.ccr.write(|w| unsafe { w.bits(ccr) });
+
+                if let Some(instruction) = cmd.instruction {
+                    self.regs.ir.write(|w| unsafe { w.bits(instruction as u32) });
+                }
+            } else {
+                // QUADSPI_CCR: INSTRUCTION, IMODE, ADMODE, ADSIZE, ABMODE, ABSIZE, DCYC, DMODE,
+                // FMODE, and DDRM.
+                let mut ccr = (mode as u32) << 26 | (cmd.dummy_cycles as u32) << 18;
+                if let Some(instruction) = cmd.instruction {
+                    ccr |= instruction as u32 | (cmd.instruction_mode as u32) << 8;
+                }
+                if cmd.address.is_some() {
+                    ccr |= (cmd.address_mode as u32) << 10 | (self.cfg.address_size as u32) << 12;
+                }
+                if cmd.alt_bytes.is_some() {
+                    ccr |= (cmd.alt_bytes_mode as u32) << 14 | (cmd.alt_bytes_size as u32) << 16;
+                }
+                if let Some(data_mode) = cmd.data_mode {
+                    ccr |= (data_mode as u32) << 24;
+                }
+                if ddr {
+                    ccr |= 1 << 31;
+                }
+
+                self.regs.ccr.write(|w| unsafe { w.bits(ccr) });
+            }
+        }
+
+        if mode != FunctionalMode::MemoryMapped {
+            if let Some(addr) = cmd.address {
+                self.regs.ar.write(|w| unsafe { w.bits(addr) });
+            }
+        }
+    }
+
+    /// Wait for a transfer to complete, or fail.
+    fn wait_complete(&mut self) -> Result<(), QspiError> {
+        loop {
+            let sr = self.regs.sr.read().bits();
+            if sr & TEF != 0 {
+                self.regs.fcr.write(|w| unsafe { w.bits(TEF) });
+                return Err(QspiError::Transfer);
+            }
+            if sr & TCF != 0 {
+                self.regs.fcr.write(|w| unsafe { w.bits(TCF) });
+                break;
+            }
+        }
+
+        while self.is_busy() {}
+        Ok(())
+    }
+
+    /// Send a command without a data phase, in indirect mode; eg write enable, or erase.
+    pub fn command(&mut self, cmd: &Command) -> Result<(), QspiError> {
+        self.start_command(cmd, FunctionalMode::IndirectWrite, 0);
+        self.wait_complete()
+    }
+
+    /// Send a command, and write data, in indirect mode; eg a page program. `cmd.data_mode` must
+    /// be `Some`. There's no length limit; the FIFO is refilled as it empties.
+    pub fn write_command(&mut self, cmd: &Command, data: &[u8]) -> Result<(), QspiError> {
+        assert!(cmd.data_mode.is_some() && !data.is_empty());
+        self.start_command(cmd, FunctionalMode::IndirectWrite, data.len());
+
+        for byte in data {
+            // FTF is set while there's room in the FIFO, for at least the threshold number of
+            // bytes.
+            loop {
+                let sr = self.regs.sr.read().bits();
+                if sr & TEF != 0 {
+                    self.regs.fcr.write(|w| unsafe { w.bits(TEF) });
+                    return Err(QspiError::Transfer);
+                }
+                if sr & FTF != 0 {
+                    break;
+                }
+            }
+            unsafe { ptr::write_volatile(self.regs.dr.as_ptr() as *mut u8, *byte) };
+        }
+
+        self.wait_complete()
+    }
+
+    /// Send a command, and read data, in indirect mode; eg a fast read. `cmd.data_mode` must be
+    /// `Some`. There's no length limit.
+    pub fn read_command(&mut self, cmd: &Command, buf: &mut [u8]) -> Result<(), QspiError> {
+        assert!(cmd.data_mode.is_some() && !buf.is_empty());
+        self.start_command(cmd, FunctionalMode::IndirectRead, buf.len());
+
+        for byte in buf.iter_mut() {
+            loop {
+                let sr = self.regs.sr.read().bits();
+                if sr & TEF != 0 {
+                    self.regs.fcr.write(|w| unsafe { w.bits(TEF) });
+                    return Err(QspiError::Transfer);
+                }
+                if (sr >> FLEVEL_SHIFT) & 0x3f != 0 {
+                    break;
+                }
+            }
+            *byte = unsafe { ptr::read_volatile(self.regs.dr.as_ptr() as *const u8) };
+        }
+
+        self.wait_complete()
+    }
+
+    /// Repeatedly send a command, eg read status register, until `(status & mask) == value`,
+    /// using status-polling mode. `len` is the status length, in bytes (1 - 4). `interval` is the
+    /// number of clock cycles between reads. Blocks until matched.
+    pub fn poll_status(
+        &mut self,
+        cmd: &Command,
+        mask: u32,
+        value: u32,
+        len: u8,
+        interval: u16,
+    ) -> Result<(), QspiError> {
+        assert!(cmd.data_mode.is_some() && (1..=4).contains(&len));
+        while self.is_busy() {}
+
+        self.regs.psmkr.write(|w| unsafe { w.bits(mask) });
+        self.regs.psmar.write(|w| unsafe { w.bits(value) });
+        self.regs.pir.write(|w| unsafe { w.bits(interval as u32) });
+        // Stop automatically on match, with AND match mode.
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !PMM) | APMS) });
+
+        self.start_command(cmd, FunctionalMode::StatusPolling, len as usize);
+
+        loop {
+            let sr = self.regs.sr.read().bits();
+            if sr & TEF != 0 {
+                self.regs.fcr.write(|w| unsafe { w.bits(TEF) });
+                return Err(QspiError::Transfer);
+            }
+            if sr & SMF != 0 {
+                self.regs.fcr.write(|w| unsafe { w.bits(SMF) });
+                break;
+            }
+        }
+
+        while self.is_busy() {}
+        Ok(())
+    }
+
+    /// Enter memory-mapped mode, using `cmd` to read. The flash can then be read directly at
+    /// 0x9000_0000, eg to execute code in place. `cmd.address` must be `Some` (its value is
+    /// ignored; the address comes from the read access), and `cmd.data_mode` must be `Some`.
+    /// Use `abort()` to leave memory-mapped mode, eg before indirect writes.
+    pub fn memory_mapped(&mut self, cmd: &Command) {
+        assert!(cmd.address.is_some() && cmd.data_mode.is_some());
+        self.start_command(cmd, FunctionalMode::MemoryMapped, 0);
+    }
+
+    /// Abort the current command, or leave memory-mapped mode.
+    pub fn abort(&mut self) {
+        self.regs
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | ABORT) });
+        while self.regs.cr.read().bits() & ABORT != 0 {}
+    }
+
     // todo: write_indirect_dma fn.
 
     /// Read one word from memory in memory-mapped mode
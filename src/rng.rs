@@ -1,4 +1,10 @@
 //! Support for the Random Number Generator (RNG) peripheral.
+//!
+//! On L5 and WL, the RNG's noise source sampling, conditioning, and health tests are configurable,
+//! using `Rng::configure()`. Certified products should apply, and document the configuration
+//! from their RM's "RNG configuration" table, and lock it. See L5 RM, RNG chapter: "RNG
+//! initialization", and "Entropy source validation". (H5 and U5 have the same registers, but
+//! aren't supported yet)
 
 use cortex_m::interrupt::free;
 
@@ -9,6 +15,105 @@ use crate::{
 
 use cfg_if::cfg_if;
 
+cfg_if! {
+    if #[cfg(any(feature = "l5", feature = "wl"))] {
+        // RNG_CR fields. We use raw bits, since these are missing from some PACs.
+        const CED_BIT: u32 = 1 << 5;
+        const CONFIG3_SHIFT: u32 = 8;
+        const NISTC_BIT: u32 = 1 << 12;
+        const CONFIG2_SHIFT: u32 = 13;
+        const CLKDIV_SHIFT: u32 = 16;
+        const CONFIG1_SHIFT: u32 = 20;
+        const CONDRST_BIT: u32 = 1 << 30;
+        const CONFIGLOCK_BIT: u32 = 1 << 31;
+        /// The bits of RNG_CR set by `RngConfig`.
+        const CONFIG_MASK: u32 = CED_BIT
+            | (0xf << CONFIG3_SHIFT)
+            | NISTC_BIT
+            | (0b111 << CONFIG2_SHIFT)
+            | (0xf << CLKDIV_SHIFT)
+            | (0x3f << CONFIG1_SHIFT);
+
+        /// RNG_HTCR offset.
+        const HTCR_OFFSET: usize = 0x10;
+
+        #[derive(Clone, Copy, PartialEq)]
+        #[repr(u8)]
+        /// Divides the RNG clock before sampling the noise source. The noise source sampling rate
+        /// must be within its limits; see the datasheet. Sets RNG_CR, CLKDIV field.
+        pub enum RngClockDiv {
+            Div1 = 0,
+            Div2 = 1,
+            Div4 = 2,
+            Div8 = 3,
+            Div16 = 4,
+            Div32 = 5,
+            Div64 = 6,
+            Div128 = 7,
+            Div256 = 8,
+            Div512 = 9,
+            Div1024 = 10,
+            Div2048 = 11,
+            Div4096 = 12,
+            Div8192 = 13,
+            Div16384 = 14,
+            Div32768 = 15,
+        }
+
+        #[derive(Clone, Copy, PartialEq)]
+        /// RNG noise source, conditioning, and health test configuration. The default is the RM's
+        /// NIST-certifiable configuration A.
+        pub struct RngConfig {
+            /// Noise source oscillator, and sampling configuration. Sets RNG_CR, RNG_CONFIG1 field.
+            pub config1: u8,
+            /// Sets RNG_CR, RNG_CONFIG2 field.
+            pub config2: u8,
+            /// Sets RNG_CR, RNG_CONFIG3 field.
+            pub config3: u8,
+            pub clock_div: RngClockDiv,
+            /// If false, conditioning uses the hardware default values for NIST compliance. If true,
+            /// it uses the values from `config1` - `config3`. Sets RNG_CR, NISTC bit.
+            pub nist_custom: bool,
+            /// Enable the clock error detection. Sets RNG_CR, CED bit, which is active-low.
+            pub clock_error_detection: bool,
+            /// Health test configuration. Sets the RNG_HTCR register.
+            pub health_test: u32,
+        }
+
+        impl Default for RngConfig {
+            fn default() -> Self {
+                Self {
+                    config1: 0x0f,
+                    config2: 0,
+                    config3: 0x0d,
+                    clock_div: RngClockDiv::Div1,
+                    nist_custom: false,
+                    clock_error_detection: true,
+                    health_test: 0x0000_aac7,
+                }
+            }
+        }
+
+        impl RngConfig {
+            /// The RNG_CR bits for this configuration.
+            fn cr_bits(&self) -> u32 {
+                let mut val = ((self.config1 as u32 & 0x3f) << CONFIG1_SHIFT)
+                    | ((self.config2 as u32 & 0b111) << CONFIG2_SHIFT)
+                    | ((self.config3 as u32 & 0xf) << CONFIG3_SHIFT)
+                    | ((self.clock_div as u32) << CLKDIV_SHIFT);
+
+                if self.nist_custom {
+                    val |= NISTC_BIT;
+                }
+                if !self.clock_error_detection {
+                    val |= CED_BIT;
+                }
+                val
+            }
+        }
+    }
+}
+
 /// Represents a RNG peripheral.
 pub struct Rng {
     pub regs: RNG,
@@ -53,6 +158,65 @@ impl Rng {
         self.regs.sr.read().drdy().bit_is_set()
     }
 
+    #[cfg(any(feature = "l5", feature = "wl"))]
+    /// Apply a noise source, conditioning, and health test configuration. This resets the
+    /// conditioning logic, and leaves the RNG enabled. Set `lock` to prevent further changes until
+    /// the next reset. (RNG_CR, CONFIGLOCK bit) Panics if the configuration is already locked.
+    pub fn configure(&mut self, cfg: &RngConfig, lock: bool) {
+        let cr = self.regs.cr.read().bits();
+        assert!(cr & CONFIGLOCK_BIT == 0, "The RNG configuration is locked.");
+
+        // The configuration is written along with CONDRST set, in a single write. HTCR writes
+        // only take effect while CONDRST is set.
+        let cr = (cr & !CONFIG_MASK) | cfg.cr_bits();
+        self.regs.cr.write(|w| unsafe { w.bits(cr | CONDRST_BIT) });
+
+        unsafe {
+            let htcr = (RNG::ptr() as *mut u8).add(HTCR_OFFSET) as *mut u32;
+            core::ptr::write_volatile(htcr, cfg.health_test);
+        }
+
+        self.regs.cr.write(|w| unsafe { w.bits(cr) });
+        // Wait for the conditioning reset to complete.
+        while self.regs.cr.read().bits() & CONDRST_BIT != 0 {}
+
+        if lock {
+            self.regs
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CONFIGLOCK_BIT) });
+        }
+
+        self.regs.cr.modify(|_, w| w.rngen().set_bit());
+    }
+
+    #[cfg(any(feature = "l5", feature = "wl"))]
+    /// Read the current configuration from the RNG_CR, and RNG_HTCR registers, eg to log it.
+    pub fn config(&self) -> RngConfig {
+        let cr = self.regs.cr.read().bits();
+        let health_test = unsafe {
+            core::ptr::read_volatile((RNG::ptr() as *const u8).add(HTCR_OFFSET) as *const u32)
+        };
+
+        RngConfig {
+            config1: ((cr >> CONFIG1_SHIFT) & 0x3f) as u8,
+            config2: ((cr >> CONFIG2_SHIFT) & 0b111) as u8,
+            config3: ((cr >> CONFIG3_SHIFT) & 0xf) as u8,
+            // Safe, since all 4-bit values are valid variants.
+            clock_div: unsafe {
+                core::mem::transmute::<u8, RngClockDiv>(((cr >> CLKDIV_SHIFT) & 0xf) as u8)
+            },
+            nist_custom: cr & NISTC_BIT != 0,
+            clock_error_detection: cr & CED_BIT == 0,
+            health_test,
+        }
+    }
+
+    #[cfg(any(feature = "l5", feature = "wl"))]
+    /// Returns true if the configuration is locked until the next reset. (RNG_CR, CONFIGLOCK bit)
+    pub fn config_locked(&self) -> bool {
+        self.regs.cr.read().bits() & CONFIGLOCK_BIT != 0
+    }
+
     /// Enable an interrupt. An interrupt isgenerated when a random number is ready or when an error
     /// occurs. Therefore at each interrupt, check that: No error occured (SEIS and CEIS bits should be set
     /// to 0 in the RNG_SR register. A random number is ready. The DRDY bit must be set to 1 in the
@@ -4,7 +4,10 @@
 
 //! Uses [Chrono](https://docs.rs/chrono) for dates and times.
 
-use crate::pac::{EXTI, PWR, RCC, RTC};
+use crate::{
+    backup,
+    pac::{EXTI, RCC, RTC},
+};
 use core::convert::TryInto;
 
 use cortex_m::interrupt::free;
@@ -129,49 +132,26 @@ impl Rtc {
         // See L4 RM, `Backup domain access` section.
         free(|_| {
             let rcc = unsafe { &(*RCC::ptr()) };
-            let pwr = unsafe { &(*PWR::ptr()) };
 
             cfg_if! {
-                if #[cfg(any(feature = "f3", feature = "f4"))] {
-                    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
-                    pwr.cr.read(); // read to allow the pwr clock to enable
-                    pwr.cr.modify(|_, w| w.dbp().set_bit());
-                    while pwr.cr.read().dbp().bit_is_clear() {}
-                } else if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "l412", feature = "wb", feature = "wl"))] {
+                if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "l412", feature = "wb", feature = "wl"))] {
                     // 1. Enable the power interface clock by setting the PWREN bits in the Section 6.4.18:
-                    // APB1 peripheral clock enable register 1 (RCC_APB1ENR1)
-                    #[cfg(not(any(feature = "wb", feature = "wl")))]
-                    rcc.apb1enr1.modify(|_, w| {
-                        w.pwren().set_bit();
-                        w.rtcapben().set_bit()
-                    });
-                    #[cfg(any(feature = "wb", feature = "wl"))]
+                    // APB1 peripheral clock enable register 1 (RCC_APB1ENR1) (Handled by `backup::unlock()`)
                     rcc.apb1enr1.modify(|_, w| w.rtcapben().set_bit());
-
                     rcc.apb1smenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read(); // Read to allow the pwr clock to enable
-                    // 2. Set the DBP bit in the Power control register 1 (PWR_CR1) to enable access to the
-                    // backup domain
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit()); // Unlock the backup domain
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
                 } else if #[cfg(any(feature = "g0"))] {
-                    rcc.apbenr1.modify(|_, w| {
-                        w.pwren().set_bit();
-                        w.rtcapben().set_bit()
-                    });
+                    rcc.apbenr1.modify(|_, w| w.rtcapben().set_bit());
                     rcc.apbsmenr1.modify(|_, w| w.rtcapbsmen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read();
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit());
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
-                } else { // eg h7
+                } else if #[cfg(feature = "h7")] {
                     rcc.apb4enr.modify(|_, w| w.rtcapben().set_bit());
                     rcc.apb4lpenr.modify(|_, w| w.rtcapblpen().set_bit());  // In sleep and stop modes.
-                    pwr.cr1.read(); // read to allow the pwr clock to enable
-                    pwr.cr1.modify( | _, w| w.dbp().set_bit());
-                    while pwr.cr1.read().dbp().bit_is_clear() {}
                 }
             }
 
+            // 2. Set the DBP bit in the Power control register 1 (PWR_CR1) to enable access to the
+            // backup domain
+            backup::unlock();
+
             // Set up the LSI or LSE as required.
             match config.clock_source {
                 RtcClockSource::Lsi => {
@@ -779,6 +759,16 @@ impl Rtc {
         Ok(())
     }
 
+    /// Get the raw sub-seconds value, from the `SSR` register. This counts down from the
+    /// synchronous prescaler value, at the `ck_apre` frequency.
+    pub fn get_subseconds(&mut self) -> u16 {
+        let ss = self.regs.ssr.read().ss().bits();
+        // The SS field is 32 bits on WL, which supports a binary counter mode.
+        #[cfg(feature = "wl")]
+        let ss = ss as u16;
+        ss
+    }
+
     /// Get the seconds component of the current time.
     pub fn get_seconds(&mut self) -> u8 {
         let tr = self.regs.tr.read();
@@ -0,0 +1,250 @@
+//! A minimal scheduler for periodic tasks, such as polling sensors, that sleeps in Stop mode
+//! between them. Register closures with their periods; the scheduler runs the ones that are due,
+//! programs an LPTIM compare match for the next one, and (with `run()`) enters Stop mode until
+//! then.
+//!
+//! The LPTIM provides the time base, so it must keep running in Stop: clock it from LSE or LSI,
+//! and set `run_in_stop` in its config. Enable the LPTIM's interrupt in the NVIC, so its compare
+//! match can wake the MCU. The interrupt handler itself can be empty; `service()` clears the flag.
+//!
+//! Example, polling a sensor every second, and a battery monitor every minute:
+//! ```rust
+//! let mut timer = LpTimer::new(
+//!     dp.LPTIM1,
+//!     LpTimerConfig {
+//!         kernel_clock: LpClockSel::Lse,
+//!         prescaler: LpPrescaler::Div32, // 1.024kHz ticks
+//!         run_in_stop: true,
+//!         ..Default::default()
+//!     },
+//! );
+//!
+//! let mut poll_sensor = || { /* read a sensor */ };
+//! let mut check_battery = || { /* read VBAT */ };
+//!
+//! let mut sched: Scheduler<4> = Scheduler::new(&mut timer, 32_768 / 32);
+//! sched.add(1_000, &mut poll_sensor).unwrap();
+//! sched.add(60_000, &mut check_battery).unwrap();
+//!
+//! unsafe { NVIC::unmask(pac::Interrupt::LPTIM1) };
+//!
+//! sched.run(&mut timer, &[&i2c], StopMode::Two, &clock_cfg);
+//! ```
+
+use core::ops::Deref;
+
+use crate::lptim::{LpTimer, LpTimerInterrupt};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "g4")] {
+        use crate::pac::lptimer1 as lptim_p;
+    } else {
+        use crate::pac::lptim1 as lptim_p;
+    }
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+use crate::{
+    clocks::Clocks,
+    low_power::{self, BusyPeriph, StopMode},
+};
+
+/// The longest interval we program between wakeups, in timer ticks. This is kept below the
+/// 16-bit counter range, so we can't miss a counter wrap while stopped.
+const MAX_WAKE_INTERVAL: u16 = 0xf000;
+
+/// The shortest interval we program, in timer ticks. Writes to `CMP` take a few kernel clock
+/// cycles to synchronize; a closer compare value could pass before it takes effect.
+const MIN_WAKE_INTERVAL: u16 = 4;
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// All task slots are in use.
+    Full,
+    /// There's no task with this id.
+    InvalidTask,
+}
+
+/// A registered task.
+struct Task<'a> {
+    /// Period, in timer ticks.
+    period: u64,
+    /// The time this task is next due, in timer ticks.
+    next_due: u64,
+    f: &'a mut dyn FnMut(),
+}
+
+/// Runs closures periodically, with up to `N` tasks. Keeps track of time by accumulating the
+/// LPTIM's counter, which it runs continuously over its full 16-bit range.
+pub struct Scheduler<'a, const N: usize> {
+    tasks: [Option<Task<'a>>; N],
+    /// LPTIM counter frequency, in Hz: Its kernel clock, divided by its prescaler.
+    tick_hz: u32,
+    /// Time since `new()`, in timer ticks.
+    now: u64,
+    /// The counter value at the most recent `update_time()`.
+    last_count: u16,
+}
+
+impl<'a, const N: usize> Scheduler<'a, N> {
+    /// Create a scheduler, and start the timer counting continuously, with its compare match
+    /// interrupt enabled. `tick_hz` is the timer's counter frequency: its kernel clock
+    /// frequency, divided by its prescaler.
+    pub fn new<R>(timer: &mut LpTimer<R>, tick_hz: u32) -> Self
+    where
+        R: Deref<Target = lptim_p::RegisterBlock>,
+    {
+        assert!(tick_hz > 0, "The timer tick frequency must be nonzero.");
+
+        // Interrupts can only be enabled with the timer disabled.
+        timer.disable();
+        timer.enable_interrupt(LpTimerInterrupt::CompareMatch);
+        timer.enable();
+
+        timer.set_auto_reload(0xffff);
+        timer.set_compare(MAX_WAKE_INTERVAL);
+        timer.start_continuous();
+
+        Self {
+            tasks: core::array::from_fn(|_| None),
+            tick_hz,
+            now: 0,
+            last_count: timer.read_count(),
+        }
+    }
+
+    /// Convert milliseconds to timer ticks.
+    fn ms_to_ticks(&self, ms: u32) -> u64 {
+        ms as u64 * self.tick_hz as u64 / 1_000
+    }
+
+    /// Register a task, to run every `period_ms` milliseconds. It first runs on the next
+    /// `service()`. Returns an id that can be passed to `remove()`, and `set_period()`.
+    pub fn add(&mut self, period_ms: u32, f: &'a mut dyn FnMut()) -> Result<usize, Error> {
+        let period = self.ms_to_ticks(period_ms);
+        assert!(period > 0, "The task period must be at least 1 timer tick.");
+
+        let id = self
+            .tasks
+            .iter()
+            .position(|t| t.is_none())
+            .ok_or(Error::Full)?;
+
+        self.tasks[id] = Some(Task {
+            period,
+            next_due: self.now,
+            f,
+        });
+
+        Ok(id)
+    }
+
+    /// Unregister a task, freeing its slot.
+    pub fn remove(&mut self, id: usize) -> Result<(), Error> {
+        match self.tasks.get_mut(id) {
+            Some(t @ Some(_)) => {
+                *t = None;
+                Ok(())
+            }
+            _ => Err(Error::InvalidTask),
+        }
+    }
+
+    /// Change a task's period. Its next run is rescheduled relative to its previous one.
+    pub fn set_period(&mut self, id: usize, period_ms: u32) -> Result<(), Error> {
+        let period = self.ms_to_ticks(period_ms);
+        assert!(period > 0, "The task period must be at least 1 timer tick.");
+
+        match self.tasks.get_mut(id) {
+            Some(Some(task)) => {
+                task.next_due = task.next_due.saturating_sub(task.period) + period;
+                task.period = period;
+                Ok(())
+            }
+            _ => Err(Error::InvalidTask),
+        }
+    }
+
+    /// Time since the scheduler was created, in milliseconds, as of the most recent `service()`.
+    pub fn now_ms(&self) -> u64 {
+        self.now * 1_000 / self.tick_hz as u64
+    }
+
+    /// Advance our time count by the number of ticks since the last update.
+    fn update_time<R>(&mut self, timer: &LpTimer<R>)
+    where
+        R: Deref<Target = lptim_p::RegisterBlock>,
+    {
+        let count = timer.read_count();
+        self.now += count.wrapping_sub(self.last_count) as u64;
+        self.last_count = count;
+    }
+
+    /// Run all tasks that are due, then program the timer's compare match for the next one.
+    /// Call this after each wakeup, if not using `run()`. Tasks that are late by more than a
+    /// period skip the missed runs, instead of running repeatedly to catch up.
+    pub fn service<R>(&mut self, timer: &mut LpTimer<R>)
+    where
+        R: Deref<Target = lptim_p::RegisterBlock>,
+    {
+        timer.clear_interrupt(LpTimerInterrupt::CompareMatch);
+        self.update_time(timer);
+
+        for task in self.tasks.iter_mut().flatten() {
+            if task.next_due <= self.now {
+                (task.f)();
+
+                task.next_due += task.period;
+                if task.next_due <= self.now {
+                    task.next_due = self.now + task.period;
+                }
+            }
+        }
+
+        // The tasks may have taken a while; update the time before programming the next wakeup.
+        self.update_time(timer);
+
+        let next_due = self
+            .tasks
+            .iter()
+            .flatten()
+            .map(|t| t.next_due)
+            .min()
+            .unwrap_or(u64::MAX);
+
+        // Wake at least once per `MAX_WAKE_INTERVAL`, so we can track counter wraps.
+        let interval = next_due
+            .saturating_sub(self.now)
+            .clamp(MIN_WAKE_INTERVAL as u64, MAX_WAKE_INTERVAL as u64)
+            as u16;
+
+        timer.set_compare(self.last_count.wrapping_add(interval));
+    }
+
+    /// Run the scheduler forever: Service tasks, then enter Stop mode until the next one is due,
+    /// if `low_power::stop_allowed()` permits; otherwise, enter Sleep mode. `periphs` and DMA
+    /// are checked as in `stop_allowed()`. Re-selects the clock input after each wakeup.
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+    pub fn run<R>(
+        &mut self,
+        timer: &mut LpTimer<R>,
+        periphs: &[&dyn BusyPeriph],
+        mode: StopMode,
+        clocks: &Clocks,
+    ) -> !
+    where
+        R: Deref<Target = lptim_p::RegisterBlock>,
+    {
+        loop {
+            self.service(timer);
+
+            if low_power::stop_allowed(periphs, true).is_ok() {
+                low_power::stop(mode);
+                clocks.reselect_input();
+            } else {
+                low_power::sleep_now();
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Card-detect (CD) and write-protect (WP) handling for SD card sockets. Debounces the socket's
+//! card-detect switch into insertion and removal events, and lets a card driver detect a card
+//! that was removed, or swapped, during a transfer.
+//!
+//! This module works with any SD card driver; eg the `sdmmc` module on H7 and L5, or one using SPI.
+//!
+//! Example:
+//! ```rust
+//! let mut detect_pin = Pin::new(Port::C, 13, PinMode::Input);
+//! detect_pin.pull(Pull::Up);
+//!
+//! let mut card_detect = CardDetect::new(detect_pin, None, Default::default());
+//!
+//! // Called periodically, eg from a 1ms SysTick interrupt:
+//! match card_detect.poll(millis) {
+//!     Some(CardEvent::Inserted) => {
+//!         // (Re)initialize the card, and store the insertion it belongs to.
+//!         card_generation = card_detect.generation();
+//!     }
+//!     Some(CardEvent::Removed) => (),
+//!     None => (),
+//! }
+//!
+//! // Before, and after each block transfer:
+//! card_detect.check_write(card_generation)?;
+//! ```
+
+use crate::gpio::Pin;
+
+/// A debounced change in card presence, returned by `CardDetect::poll()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardEvent {
+    Inserted,
+    Removed,
+}
+
+/// Card presence errors, for use by card drivers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CardError {
+    /// There's no card in the socket.
+    NotPresent,
+    /// The card was removed after it was initialized; possibly mid-transfer. It must be
+    /// re-initialized, and any transfer in progress repeated, once a card is inserted.
+    Removed,
+    /// The card's write-protect switch is set.
+    WriteProtected,
+}
+
+#[derive(Clone)]
+/// Card detect configuration.
+pub struct CardDetectConfig {
+    /// Set `true` if the card-detect pin is high when a card is inserted. Most sockets short the
+    /// pin to ground on insertion, requiring a pull-up. Defaults to false.
+    pub detect_active_high: bool,
+    /// Set `true` if the write-protect pin is high when the card is write-protected. Defaults to true.
+    pub wp_active_high: bool,
+    /// The time the card-detect pin must be stable before a change is reported, in the units passed
+    /// to `poll()`. Defaults to 50, eg 50ms with a millisecond tick.
+    pub debounce_time: u32,
+}
+
+impl Default for CardDetectConfig {
+    fn default() -> Self {
+        Self {
+            detect_active_high: false,
+            wp_active_high: true,
+            debounce_time: 50,
+        }
+    }
+}
+
+/// Tracks an SD card socket's card-detect, and optional write-protect switches.
+pub struct CardDetect {
+    pub detect_pin: Pin,
+    pub wp_pin: Option<Pin>,
+    pub cfg: CardDetectConfig,
+    /// Debounced card presence.
+    present: bool,
+    /// The pin state last sampled, and when it last changed.
+    last_sample: bool,
+    last_change: u32,
+    /// Incremented on each insertion.
+    generation: u32,
+}
+
+impl CardDetect {
+    /// Create a card detect struct. Configure the pins as inputs, with pull-ups as required by
+    /// your socket, before calling this. The initial state is read without debouncing.
+    pub fn new(detect_pin: Pin, wp_pin: Option<Pin>, cfg: CardDetectConfig) -> Self {
+        let mut result = Self {
+            detect_pin,
+            wp_pin,
+            cfg,
+            present: false,
+            last_sample: false,
+            last_change: 0,
+            generation: 0,
+        };
+
+        result.present = result.sample();
+        result.last_sample = result.present;
+        if result.present {
+            result.generation = 1;
+        }
+
+        result
+    }
+
+    /// Read the card-detect pin, without debouncing.
+    fn sample(&self) -> bool {
+        self.detect_pin.is_high() == self.cfg.detect_active_high
+    }
+
+    /// Sample the card-detect pin, and report a debounced insertion or removal. Call this
+    /// periodically, or from the pin's EXTI interrupt and periodically until it returns `Some`.
+    /// `now` is a free-running tick count, eg milliseconds; it may wrap.
+    pub fn poll(&mut self, now: u32) -> Option<CardEvent> {
+        let sample = self.sample();
+
+        if sample != self.last_sample {
+            self.last_sample = sample;
+            self.last_change = now;
+            return None;
+        }
+
+        if sample == self.present || now.wrapping_sub(self.last_change) < self.cfg.debounce_time {
+            return None;
+        }
+
+        self.present = sample;
+
+        if sample {
+            self.generation = self.generation.wrapping_add(1);
+            Some(CardEvent::Inserted)
+        } else {
+            Some(CardEvent::Removed)
+        }
+    }
+
+    /// Returns the debounced card presence.
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Returns true if the card's write-protect switch is set. Always false if there's no
+    /// write-protect pin.
+    pub fn is_write_protected(&self) -> bool {
+        match &self.wp_pin {
+            Some(pin) => pin.is_high() == self.cfg.wp_active_high,
+            None => false,
+        }
+    }
+
+    /// Identifies the current card insertion. Store this when initializing a card, and pass it to
+    /// `check()` or `check_write()`, to detect if the card was removed or swapped since.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Check that the card initialized during insertion `generation` is still present. This
+    /// samples the pin directly, so a removal is detected mid-transfer, before it's debounced.
+    pub fn check(&self, generation: u32) -> Result<(), CardError> {
+        if !self.present || !self.sample() {
+            // A card that was initialized, and is no longer present, was removed.
+            if generation != 0 && generation == self.generation {
+                return Err(CardError::Removed);
+            }
+            return Err(CardError::NotPresent);
+        }
+
+        // A different card is present than the one that was initialized.
+        if generation != self.generation {
+            return Err(CardError::Removed);
+        }
+
+        Ok(())
+    }
+
+    /// As `check()`, but also fails if the card is write-protected.
+    pub fn check_write(&self, generation: u32) -> Result<(), CardError> {
+        self.check(generation)?;
+
+        if self.is_write_protected() {
+            return Err(CardError::WriteProtected);
+        }
+
+        Ok(())
+    }
+}
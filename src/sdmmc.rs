@@ -0,0 +1,1079 @@
+//! Support for the SD/SDIO/MMC card interface (SDMMC), for SD cards (SDSC, SDHC, and SDXC), and
+//! eMMC devices. Supports card initialization, 1, and 4-bit buses (and 8-bit for eMMC), and
+//! single, and multiple block reads and writes, either copied by the CPU, or using the SDMMC's
+//! internal DMA (IDMA).
+//!
+//! For eMMC, initialize with `init_emmc()` instead of `init_card()`. This reads the Extended CSD
+//! (see the `emmc` module), and switches to high speed timing, and the configured bus width.
+//! Select the boot, RPMB, or general-purpose partitions with `select_partition()`, and read the
+//! RPMB partition with `rpmb_read()`, and `rpmb_read_counter()`.
+//!
+//! With the `embedded_sdmmc` feature, `Sdmmc` implements `embedded_sdmmc::BlockDevice`, so FAT
+//! filesystems on the card can be mounted with the `embedded-sdmmc` crate.
+//!
+//! The SDMMC kernel clock is selected in RCC (`SDMMCSEL`); on H7, it's PLL1 Q after reset, and on
+//! L5, the 48Mhz clock. Pass its frequency to `Sdmmc::new()`, which calculates the card clock
+//! divider from it. Set the CK, CMD, and D0 - D3 pins to their SDMMC alternate functions, with
+//! `OutputSpeed::High` or `VeryHigh`, and pull-ups on CMD, and D0 - D3 if the board doesn't
+//! have them. See the `sd_detect` module for card-detect, and write-protect switches.
+//!
+//! Example:
+//! ```rust
+//! let mut sd = Sdmmc::new(SdmmcDevice::One, Default::default(), clock_cfg.pllq_speed(1));
+//! let card = sd.init_card()?;
+//! defmt::println!("Card size: {} blocks", card.num_blocks);
+//!
+//! let mut buf = [0; 1_024];
+//! sd.read_blocks(0, &mut buf)?; // Read blocks 0 and 1.
+//!
+//! // Or, with embedded-sdmmc:
+//! let mut volume_mgr = embedded_sdmmc::VolumeManager::new(sd, time_source);
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::{asm, interrupt::free};
+
+use crate::{
+    emmc::{self, ExtCsd, Partition, RpmbFrame, Timing, CMD_SEND_EXT_CSD, CMD_SWITCH},
+    pac::RCC,
+    util::rcc_en_reset,
+};
+
+use cfg_if::cfg_if;
+
+#[cfg(feature = "embedded_sdmmc")]
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+// We use raw pointers, since SDMMC register, and field names vary between the PACs.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const SDMMC1_BASE: usize = 0x5200_7000;
+        const SDMMC2_BASE: usize = 0x4802_2400;
+    } else {
+        const SDMMC1_BASE: usize = 0x420c_8000;
+    }
+}
+
+/// All block transfers use 512-byte blocks.
+pub const BLOCK_SIZE: usize = 512;
+
+// Register offsets.
+const POWER: usize = 0x00;
+const CLKCR: usize = 0x04;
+const ARGR: usize = 0x08;
+const CMDR: usize = 0x0c;
+const RESP1R: usize = 0x14;
+const DTIMER: usize = 0x24;
+const DLENR: usize = 0x28;
+const DCTRL: usize = 0x2c;
+const STAR: usize = 0x34;
+const ICR: usize = 0x38;
+const IDMACTRLR: usize = 0x50;
+const IDMABASE0R: usize = 0x58;
+const FIFOR: usize = 0x80;
+
+// CLKCR bits.
+const WIDBUS_SHIFT: u32 = 14;
+const HWFC_EN: u32 = 1 << 17;
+
+// CMDR bits.
+const CMDTRANS: u32 = 1 << 6;
+const CMDSTOP: u32 = 1 << 7;
+const WAITRESP_SHIFT: u32 = 8;
+const CPSMEN: u32 = 1 << 12;
+
+// DCTRL bits.
+const DTDIR: u32 = 1 << 1;
+/// DBLOCKSIZE: 2^9 = 512 bytes.
+const DBLOCKSIZE: u32 = 9 << 4;
+
+// STAR, and ICR bits.
+const CCRCFAIL: u32 = 1 << 0;
+const DCRCFAIL: u32 = 1 << 1;
+const CTIMEOUT: u32 = 1 << 2;
+const DTIMEOUT: u32 = 1 << 3;
+const TXUNDERR: u32 = 1 << 4;
+const RXOVERR: u32 = 1 << 5;
+const CMDREND: u32 = 1 << 6;
+const CMDSENT: u32 = 1 << 7;
+const DATAEND: u32 = 1 << 8;
+const TXFIFOHE: u32 = 1 << 14;
+const RXFIFOHF: u32 = 1 << 15;
+const RXFIFOE: u32 = 1 << 19;
+const BUSYD0: u32 = 1 << 20;
+const IDMATE: u32 = 1 << 27;
+/// All static flags; written to ICR to clear them.
+const ICR_ALL: u32 = 0x1fe0_0fff;
+const DATA_ERRORS: u32 = DCRCFAIL | DTIMEOUT | TXUNDERR | RXOVERR | IDMATE;
+
+/// The FIFO is half full, or half empty, at 8 words.
+const FIFO_HALF_WORDS: usize = 8;
+
+/// The card clock during identification, in Hz. Must be at most 400kHz.
+const INIT_FREQ: u32 = 400_000;
+
+// SD command indexes. ACMDs are sent after CMD55.
+const CMD_GO_IDLE_STATE: u8 = 0;
+const CMD_ALL_SEND_CID: u8 = 2;
+const CMD_SEND_RELATIVE_ADDR: u8 = 3;
+const CMD_SELECT_CARD: u8 = 7;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SEND_CSD: u8 = 9;
+const CMD_STOP_TRANSMISSION: u8 = 12;
+const CMD_SEND_STATUS: u8 = 13;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE_BLOCK: u8 = 17;
+const CMD_READ_MULTIPLE_BLOCK: u8 = 18;
+const CMD_SET_BLOCK_COUNT: u8 = 23;
+const CMD_WRITE_BLOCK: u8 = 24;
+const CMD_WRITE_MULTIPLE_BLOCK: u8 = 25;
+const CMD_APP_CMD: u8 = 55;
+const ACMD_SET_BUS_WIDTH: u8 = 6;
+const ACMD_SD_SEND_OP_COND: u8 = 41;
+// MMC-only command indexes. (CMD3, CMD6, and CMD8 are also used, with different meanings than on
+// SD)
+const CMD_MMC_SEND_OP_COND: u8 = 1;
+
+/// CMD8 argument: 2.7 - 3.6V, and check pattern 0xaa.
+const IF_COND_ARG: u32 = 0x1aa;
+/// ACMD41 argument: 3.2 - 3.4V window.
+const OCR_VOLTAGE: u32 = 0x0030_0000;
+/// OCR: Host capacity support (ACMD41 argument), and card capacity status (response).
+const OCR_HCS: u32 = 1 << 30;
+/// OCR: Power up status; clear while the card is busy.
+const OCR_READY: u32 = 1 << 31;
+/// The number of ACMD41 attempts before giving up; around 1s at 400kHz.
+const OP_COND_ATTEMPTS: u32 = 2_000;
+/// CMD1 argument: 2.7 - 3.6V, and sector addressing. (Access mode 0b10)
+const MMC_OCR_ARG: u32 = 0x40ff_8000;
+/// The relative address we assign to eMMC devices, with CMD3.
+const MMC_RCA: u16 = 1;
+/// The maximum card clock with legacy, and high speed eMMC timing, in Hz.
+const MMC_LEGACY_FREQ: u32 = 26_000_000;
+const MMC_HS_FREQ: u32 = 52_000_000;
+
+/// R1 card status error bits.
+const R1_ERRORS: u32 = 0xfdff_e008;
+/// R1 card status: SWITCH_ERROR. (eMMC)
+const R1_SWITCH_ERROR: u32 = 1 << 7;
+/// R1 card status: ready for data.
+const R1_READY_FOR_DATA: u32 = 1 << 8;
+/// R1 card status: the `tran` (transfer) state, in the CURRENT_STATE field.
+const R1_STATE_TRAN: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+/// Selects an SDMMC peripheral.
+pub enum SdmmcDevice {
+    One,
+    #[cfg(feature = "h7")]
+    Two,
+}
+
+impl SdmmcDevice {
+    fn base(&self) -> usize {
+        match self {
+            Self::One => SDMMC1_BASE,
+            #[cfg(feature = "h7")]
+            Self::Two => SDMMC2_BASE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Data bus width. Sets CLKCR, WIDBUS field.
+pub enum BusWidth {
+    W1 = 0b00,
+    W4 = 0b01,
+    /// eMMC only.
+    W8 = 0b10,
+}
+
+/// SDMMC configuration.
+pub struct SdmmcConfig {
+    /// Data bus width. Defaults to 4 bits.
+    pub bus_width: BusWidth,
+    /// Card clock frequency after initialization, in Hz. The actual frequency is the kernel clock
+    /// divided by an even number, at or below this. Default speed cards support up to 25Mhz.
+    /// Defaults to 25Mhz.
+    pub clock_freq: u32,
+}
+
+impl Default for SdmmcConfig {
+    fn default() -> Self {
+        Self {
+            bus_width: BusWidth::W4,
+            clock_freq: 25_000_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Card capacity type. eMMC devices up to 2GB use byte addressing, and are reported as `Sdsc`;
+/// larger ones use sector addressing, and are reported as `Sdhc`.
+pub enum CardType {
+    /// Standard capacity, up to 2GB. Addressed by byte.
+    Sdsc,
+    /// High, or extended capacity. (SDHC, or SDXC) Addressed by block.
+    Sdhc,
+}
+
+#[derive(Clone, Copy)]
+/// Information about an initialized card.
+pub struct CardInfo {
+    pub card_type: CardType,
+    /// The relative card address, assigned during initialization.
+    pub rca: u16,
+    /// The card identification register. The first word is bits 127:96.
+    pub cid: [u32; 4],
+    /// The card-specific data register. The first word is bits 127:96.
+    pub csd: [u32; 4],
+    /// The card's capacity, in 512-byte blocks. For eMMC, this is the user area's.
+    pub num_blocks: u32,
+    /// True if this is an eMMC device, initialized with `init_emmc()`.
+    pub mmc: bool,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// SDMMC error
+pub enum Error {
+    /// The card didn't respond to a command; eg there's no card.
+    CommandTimeout,
+    CommandCrc,
+    DataTimeout,
+    DataCrc,
+    /// RX FIFO overrun, or TX FIFO underrun.
+    Fifo,
+    /// An IDMA transfer error; eg the buffer is in memory the IDMA can't access.
+    Dma,
+    /// The card reported an error. Contains its R1 card status.
+    CardStatus(u32),
+    /// The card isn't supported; eg it's an MMC card passed to `init_card()`, doesn't support
+    /// 3.3V, or doesn't support the configured bus width.
+    Unsupported,
+    /// The card didn't finish powering up.
+    Timeout,
+    /// No card is initialized; run `init_card()` first.
+    NoCard,
+    /// A buffer's length isn't a multiple of the block size, or a DMA buffer isn't word-aligned.
+    InvalidBuffer,
+    /// The operation requires a different eMMC partition to be selected; eg the RPMB partition
+    /// for `rpmb_read()`, or the card isn't an eMMC device.
+    WrongPartition,
+    /// An RPMB response frame reported an error. Contains its result field.
+    Rpmb(u16),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Command response type. Sets CMDR, WAITRESP field.
+enum Response {
+    None = 0b00,
+    Short = 0b01,
+    /// Short response, without a CRC. (R3)
+    ShortNoCrc = 0b10,
+    Long = 0b11,
+}
+
+/// Extract bits `lsb` to `lsb + len - 1` from a 128-bit register response.
+fn reg_bits(reg: &[u32; 4], lsb: u32, len: u32) -> u32 {
+    let val =
+        (reg[0] as u128) << 96 | (reg[1] as u128) << 64 | (reg[2] as u128) << 32 | reg[3] as u128;
+    ((val >> lsb) & ((1 << len) - 1)) as u32
+}
+
+/// Calculate the card's capacity in 512-byte blocks, from its CSD.
+fn csd_num_blocks(csd: &[u32; 4]) -> u32 {
+    match reg_bits(csd, 126, 2) {
+        // CSD version 1.0: capacity = (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN bytes.
+        0 => {
+            let c_size = reg_bits(csd, 62, 12);
+            let c_size_mult = reg_bits(csd, 47, 3);
+            let read_bl_len = reg_bits(csd, 80, 4);
+            ((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u32
+        }
+        // CSD version 2.0: capacity = (C_SIZE + 1) * 512kB.
+        _ => (reg_bits(csd, 48, 22) + 1) * 1_024,
+    }
+}
+
+/// Represents an SDMMC peripheral.
+pub struct Sdmmc {
+    base: usize,
+    pub cfg: SdmmcConfig,
+    kernel_clk: u32,
+    /// The card clock frequency, in Hz.
+    bus_freq: u32,
+    card: Option<CardInfo>,
+    /// The Extended CSD, for eMMC devices.
+    ext_csd: Option<ExtCsd>,
+}
+
+impl Sdmmc {
+    /// Enable and reset the SDMMC's RCC peripheral clock, power on the card interface, and
+    /// set the card clock to 400kHz, for initialization. `kernel_clk` is the SDMMC kernel clock
+    /// frequency, in Hz; on H7, see `Clocks::sdmmc_kernel()`. Initialize the card with
+    /// `init_card()`.
+    pub fn new(device: SdmmcDevice, cfg: SdmmcConfig, kernel_clk: u32) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            cfg_if! {
+                if #[cfg(feature = "h7")] {
+                    match device {
+                        SdmmcDevice::One => {
+                            rcc_en_reset!(ahb3, sdmmc1, rcc);
+                        }
+                        SdmmcDevice::Two => {
+                            rcc_en_reset!(ahb2, sdmmc2, rcc);
+                        }
+                    }
+                } else {
+                    rcc_en_reset!(ahb2, sdmmc1, rcc);
+                }
+            }
+        });
+
+        let mut result = Self {
+            base: device.base(),
+            cfg,
+            kernel_clk,
+            bus_freq: 0,
+            card: None,
+            ext_csd: None,
+        };
+
+        result.set_clock(INIT_FREQ, BusWidth::W1);
+
+        // Power on (PWRCTRL = 0b11), then wait at least 74 card clock cycles before sending
+        // commands. This assumes the CPU clock is at least the kernel clock.
+        result.write_reg(POWER, 0b11);
+        asm::delay(kernel_clk / INIT_FREQ * 80);
+
+        result
+    }
+
+    /// Set the card clock, and bus width. Uses hardware flow control, which stops the card clock
+    /// when the FIFO is full, or empty, so CPU-copied transfers don't overrun, or underrun.
+    fn set_clock(&mut self, freq: u32, bus_width: BusWidth) {
+        // SDMMC_CK = kernel clock / (2 * CLKDIV); CLKDIV = 0 passes the kernel clock through.
+        let clkdiv = if freq >= self.kernel_clk {
+            0
+        } else {
+            self.kernel_clk.div_ceil(2 * freq).min(0x3ff)
+        };
+
+        self.bus_freq = if clkdiv == 0 {
+            self.kernel_clk
+        } else {
+            self.kernel_clk / (2 * clkdiv)
+        };
+
+        self.write_reg(CLKCR, clkdiv | (bus_width as u32) << WIDBUS_SHIFT | HWFC_EN);
+    }
+
+    /// Initialize the card: Identify it, assign its address, select it, and set the bus width,
+    /// and clock speed from the config. Supports SD version 1 (SDSC), and version 2 cards (SDSC,
+    /// SDHC, and SDXC). Run this again after a card is inserted.
+    pub fn init_card(&mut self) -> Result<CardInfo, Error> {
+        self.card = None;
+        self.ext_csd = None;
+
+        if self.cfg.bus_width == BusWidth::W8 {
+            return Err(Error::Unsupported);
+        }
+
+        self.set_clock(INIT_FREQ, BusWidth::W1);
+
+        self.command(CMD_GO_IDLE_STATE, 0, Response::None)?;
+
+        // Version 2 cards echo the check pattern. Version 1 cards don't respond.
+        let v2 = match self.command(CMD_SEND_IF_COND, IF_COND_ARG, Response::Short) {
+            Ok(()) => {
+                if self.read_reg(RESP1R) & 0xfff != IF_COND_ARG {
+                    return Err(Error::Unsupported);
+                }
+                true
+            }
+            Err(Error::CommandTimeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let op_cond_arg = if v2 {
+            OCR_VOLTAGE | OCR_HCS
+        } else {
+            OCR_VOLTAGE
+        };
+
+        let mut ocr = 0;
+        for _ in 0..OP_COND_ATTEMPTS {
+            // MMC cards don't respond to CMD55.
+            match self.command(CMD_APP_CMD, 0, Response::Short) {
+                Err(Error::CommandTimeout) => return Err(Error::Unsupported),
+                r => r?,
+            }
+            self.command(ACMD_SD_SEND_OP_COND, op_cond_arg, Response::ShortNoCrc)?;
+
+            ocr = self.read_reg(RESP1R);
+            if ocr & OCR_READY != 0 {
+                break;
+            }
+        }
+
+        if ocr & OCR_READY == 0 {
+            return Err(Error::Timeout);
+        }
+
+        let card_type = if ocr & OCR_HCS != 0 {
+            CardType::Sdhc
+        } else {
+            CardType::Sdsc
+        };
+
+        self.command(CMD_ALL_SEND_CID, 0, Response::Long)?;
+        let cid = self.read_long_response();
+
+        self.command(CMD_SEND_RELATIVE_ADDR, 0, Response::Short)?;
+        let rca = (self.read_reg(RESP1R) >> 16) as u16;
+        let rca_arg = (rca as u32) << 16;
+
+        self.command(CMD_SEND_CSD, rca_arg, Response::Long)?;
+        let csd = self.read_long_response();
+
+        self.command_r1(CMD_SELECT_CARD, rca_arg)?;
+        self.wait_busy();
+
+        if card_type == CardType::Sdsc {
+            self.command_r1(CMD_SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        if self.cfg.bus_width == BusWidth::W4 {
+            self.command_r1(CMD_APP_CMD, rca_arg)?;
+            // ACMD6 argument: 0b10 for 4 bits.
+            self.command_r1(ACMD_SET_BUS_WIDTH, 0b10)?;
+        }
+
+        self.set_clock(self.cfg.clock_freq, self.cfg.bus_width);
+
+        let card = CardInfo {
+            card_type,
+            rca,
+            cid,
+            csd,
+            num_blocks: csd_num_blocks(&csd),
+            mmc: false,
+        };
+        self.card = Some(card);
+
+        Ok(card)
+    }
+
+    /// Initialize an eMMC device: Identify it, assign its address, select it, read its Extended
+    /// CSD, then switch to high speed timing if the configured clock is above 26Mhz, and to the
+    /// configured bus width. The clock is limited to 26Mhz, or 52Mhz with high speed timing.
+    /// The user area is selected after reset. See JEDEC JESD84-B51, section 6.4: Card
+    /// identification mode.
+    pub fn init_emmc(&mut self) -> Result<CardInfo, Error> {
+        self.card = None;
+        self.ext_csd = None;
+        self.set_clock(INIT_FREQ, BusWidth::W1);
+
+        self.command(CMD_GO_IDLE_STATE, 0, Response::None)?;
+
+        let mut ocr = 0;
+        for _ in 0..OP_COND_ATTEMPTS {
+            // SD cards don't respond to CMD1.
+            match self.command(CMD_MMC_SEND_OP_COND, MMC_OCR_ARG, Response::ShortNoCrc) {
+                Err(Error::CommandTimeout) => return Err(Error::Unsupported),
+                r => r?,
+            }
+
+            ocr = self.read_reg(RESP1R);
+            if ocr & OCR_READY != 0 {
+                break;
+            }
+        }
+
+        if ocr & OCR_READY == 0 {
+            return Err(Error::Timeout);
+        }
+
+        // OCR bits 30:29: access mode; 0b10 for sector addressing.
+        let card_type = if ocr & OCR_HCS != 0 {
+            CardType::Sdhc
+        } else {
+            CardType::Sdsc
+        };
+
+        self.command(CMD_ALL_SEND_CID, 0, Response::Long)?;
+        let cid = self.read_long_response();
+
+        // Unlike SD cards, eMMC devices are assigned an address by the host.
+        let rca_arg = (MMC_RCA as u32) << 16;
+        self.command_r1(CMD_SEND_RELATIVE_ADDR, rca_arg)?;
+
+        self.command(CMD_SEND_CSD, rca_arg, Response::Long)?;
+        let csd = self.read_long_response();
+
+        self.command_r1(CMD_SELECT_CARD, rca_arg)?;
+        self.wait_busy();
+
+        if card_type == CardType::Sdsc {
+            self.command_r1(CMD_SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        let mut ext_csd_buf = [0; emmc::EXT_CSD_LEN];
+        self.read_data(CMD_SEND_EXT_CSD, 0, &mut ext_csd_buf, false, false)?;
+        let ext_csd = ExtCsd::new(&ext_csd_buf).map_err(|_| Error::Unsupported)?;
+
+        // Set the card first; `switch()` uses its address.
+        let card = CardInfo {
+            card_type,
+            rca: MMC_RCA,
+            cid,
+            csd,
+            num_blocks: match card_type {
+                // Devices above 2GB report their size in the Extended CSD.
+                CardType::Sdhc => ext_csd.sector_count(),
+                CardType::Sdsc => csd_num_blocks(&csd),
+            },
+            mmc: true,
+        };
+        self.card = Some(card);
+
+        let mut max_freq = MMC_LEGACY_FREQ;
+        if self.cfg.clock_freq > MMC_LEGACY_FREQ && ext_csd.supports_timing(Timing::HighSpeed) {
+            let arg = ext_csd
+                .timing_arg(Timing::HighSpeed)
+                .map_err(|_| Error::Unsupported)?;
+            self.switch(arg)?;
+            max_freq = MMC_HS_FREQ;
+        }
+
+        let width = match self.cfg.bus_width {
+            BusWidth::W1 => emmc::BusWidth::W1,
+            BusWidth::W4 => emmc::BusWidth::W4,
+            BusWidth::W8 => emmc::BusWidth::W8,
+        };
+        if width != emmc::BusWidth::W1 {
+            let arg = ext_csd
+                .bus_width_arg(width)
+                .map_err(|_| Error::Unsupported)?;
+            self.switch(arg)?;
+        }
+
+        self.set_clock(self.cfg.clock_freq.min(max_freq), self.cfg.bus_width);
+
+        self.ext_csd = Some(ext_csd);
+
+        Ok(card)
+    }
+
+    /// The Extended CSD of the initialized eMMC device, if any. This is read during
+    /// `init_emmc()`, and its partition selection kept up to date by `select_partition()`.
+    pub fn ext_csd(&self) -> Option<&ExtCsd> {
+        self.ext_csd.as_ref()
+    }
+
+    /// Select the eMMC partition that subsequent reads and writes access: The user area, a boot,
+    /// or general-purpose partition, or the RPMB partition. Block addresses are relative to the
+    /// start of the partition. The boot configuration is preserved.
+    pub fn select_partition(&mut self, partition: Partition) -> Result<(), Error> {
+        let arg = match &self.ext_csd {
+            Some(ext_csd) => ext_csd.partition_arg(partition),
+            None => return Err(Error::WrongPartition),
+        };
+
+        self.switch(arg)?;
+
+        if let Some(ext_csd) = &mut self.ext_csd {
+            ext_csd.set_partition(partition);
+        }
+        Ok(())
+    }
+
+    /// Read the RPMB (Replay Protected Memory Block) write counter. `nonce` should be random;
+    /// check that the response echoes it, and verify its MAC with the device's authentication
+    /// key, to confirm the response is genuine. The RPMB partition must be selected with
+    /// `select_partition()`.
+    pub fn rpmb_read_counter(&mut self, nonce: &[u8; 16]) -> Result<RpmbFrame, Error> {
+        let mut response = [RpmbFrame::default()];
+        self.rpmb_transaction(
+            RpmbFrame::request(emmc::RPMB_REQ_READ_COUNTER, nonce, 0),
+            &mut response,
+        )?;
+        Ok(response[0])
+    }
+
+    /// Read data from the RPMB partition, starting at the 256-byte half-sector `address`, into
+    /// one frame per half-sector. Each frame's data is at `RpmbFrame::data()`. The last frame
+    /// contains the MAC over all frames; verify it, and the echoed `nonce`, with the device's
+    /// authentication key to confirm the data is genuine. Reading doesn't require the key. The
+    /// RPMB partition must be selected with `select_partition()`.
+    pub fn rpmb_read(
+        &mut self,
+        address: u16,
+        nonce: &[u8; 16],
+        frames: &mut [RpmbFrame],
+    ) -> Result<(), Error> {
+        self.rpmb_transaction(
+            RpmbFrame::request(emmc::RPMB_REQ_READ_DATA, nonce, address),
+            frames,
+        )
+    }
+
+    /// Send an RPMB request frame, then read the response frames. Each is sent as a block
+    /// transfer, with its block count set with CMD23.
+    fn rpmb_transaction(
+        &mut self,
+        request: RpmbFrame,
+        response: &mut [RpmbFrame],
+    ) -> Result<(), Error> {
+        let card = self.card.ok_or(Error::NoCard)?;
+
+        match &self.ext_csd {
+            Some(ext_csd) if ext_csd.partition() == Partition::Rpmb => (),
+            _ => return Err(Error::WrongPartition),
+        }
+
+        if response.is_empty() {
+            return Err(Error::InvalidBuffer);
+        }
+
+        self.command_r1(CMD_SET_BLOCK_COUNT, 1)?;
+        self.write_data(CMD_WRITE_MULTIPLE_BLOCK, 0, &request.raw, false, false)?;
+        self.wait_ready(&card)?;
+
+        // `RpmbFrame` is a transparent wrapper around its 512-byte block, so the frames are
+        // contiguous blocks.
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(
+                response.as_mut_ptr() as *mut u8,
+                response.len() * BLOCK_SIZE,
+            )
+        };
+
+        self.command_r1(CMD_SET_BLOCK_COUNT, response.len() as u32)?;
+        self.read_data(CMD_READ_MULTIPLE_BLOCK, 0, buf, false, false)?;
+
+        for frame in response.iter() {
+            // Bit 7 flags an expired write counter, which doesn't affect reads.
+            if frame.result() & 0x7f != 0 {
+                return Err(Error::Rpmb(frame.result()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a byte of the Extended CSD with CMD6 (SWITCH), wait for the device to finish, and
+    /// check that the switch succeeded.
+    fn switch(&self, arg: u32) -> Result<(), Error> {
+        let card = self.card.ok_or(Error::NoCard)?;
+
+        self.command_r1(CMD_SWITCH, arg)?;
+        self.wait_busy();
+
+        self.command_r1(CMD_SEND_STATUS, (card.rca as u32) << 16)?;
+        if self.read_reg(RESP1R) & R1_SWITCH_ERROR != 0 {
+            return Err(Error::CardStatus(self.read_reg(RESP1R)));
+        }
+
+        self.wait_ready(&card)
+    }
+
+    /// Information about the initialized card, if any.
+    pub fn card(&self) -> Option<&CardInfo> {
+        self.card.as_ref()
+    }
+
+    /// The card clock frequency, in Hz.
+    pub fn bus_freq(&self) -> u32 {
+        self.bus_freq
+    }
+
+    /// Read blocks, starting at `block_addr`, into `buf`; its length must be a multiple of 512
+    /// bytes. The data is copied by the CPU.
+    pub fn read_blocks(&mut self, block_addr: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.read_blocks_inner(block_addr, buf, false)
+    }
+
+    /// Write blocks from `buf`, starting at `block_addr`; its length must be a multiple of 512
+    /// bytes. The data is copied by the CPU. Blocks until the card has finished programming.
+    pub fn write_blocks(&mut self, block_addr: u32, buf: &[u8]) -> Result<(), Error> {
+        self.write_blocks_inner(block_addr, buf, false)
+    }
+
+    /// Read blocks, starting at `block_addr`, into `buf`, using the SDMMC's internal DMA.
+    /// `buf` must be word-aligned, and its length a multiple of 512 bytes. On H7, the buffer must
+    /// be in memory the IDMA can access: AXI SRAM for SDMMC1, not DTCM.
+    pub fn read_blocks_dma(&mut self, block_addr: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.read_blocks_inner(block_addr, buf, true)
+    }
+
+    /// Write blocks from `buf`, starting at `block_addr`, using the SDMMC's internal DMA. The
+    /// same buffer requirements as `read_blocks_dma()` apply. Blocks until the card has finished
+    /// programming.
+    pub fn write_blocks_dma(&mut self, block_addr: u32, buf: &[u8]) -> Result<(), Error> {
+        self.write_blocks_inner(block_addr, buf, true)
+    }
+
+    // The transfer functions take `&self`, since `BlockDevice` methods do.
+
+    fn read_blocks_inner(&self, block_addr: u32, buf: &mut [u8], dma: bool) -> Result<(), Error> {
+        let card = self.check_transfer(buf.as_ptr(), buf.len(), dma)?;
+        let num_blocks = buf.len() / BLOCK_SIZE;
+
+        let cmd = if num_blocks == 1 {
+            CMD_READ_SINGLE_BLOCK
+        } else {
+            CMD_READ_MULTIPLE_BLOCK
+        };
+
+        self.read_data(cmd, card_addr(&card, block_addr), buf, dma, num_blocks > 1)
+    }
+
+    fn write_blocks_inner(&self, block_addr: u32, buf: &[u8], dma: bool) -> Result<(), Error> {
+        let card = self.check_transfer(buf.as_ptr(), buf.len(), dma)?;
+        let num_blocks = buf.len() / BLOCK_SIZE;
+
+        let cmd = if num_blocks == 1 {
+            CMD_WRITE_BLOCK
+        } else {
+            CMD_WRITE_MULTIPLE_BLOCK
+        };
+
+        self.write_data(cmd, card_addr(&card, block_addr), buf, dma, num_blocks > 1)?;
+        self.wait_ready(&card)
+    }
+
+    /// Send a command that reads data into `buf`, and receive the data. `stop` sends CMD12
+    /// afterwards, for multiple-block reads without a preset block count.
+    fn read_data(
+        &self,
+        cmd: u8,
+        arg: u32,
+        buf: &mut [u8],
+        dma: bool,
+        stop: bool,
+    ) -> Result<(), Error> {
+        self.setup_data(
+            buf.len(),
+            true,
+            if dma { Some(buf.as_ptr() as u32) } else { None },
+        );
+
+        let mut result = self.command_transfer(cmd, arg);
+
+        if result.is_ok() {
+            result = if dma {
+                self.wait_data_end()
+            } else {
+                self.read_fifo(buf)
+            };
+        }
+
+        self.end_data(stop, result)
+    }
+
+    /// Send a command that writes data from `buf`, and send the data. `stop` is as for
+    /// `read_data()`. Doesn't wait for the card to finish programming.
+    fn write_data(
+        &self,
+        cmd: u8,
+        arg: u32,
+        buf: &[u8],
+        dma: bool,
+        stop: bool,
+    ) -> Result<(), Error> {
+        self.setup_data(
+            buf.len(),
+            false,
+            if dma { Some(buf.as_ptr() as u32) } else { None },
+        );
+
+        let mut result = self.command_transfer(cmd, arg);
+
+        if result.is_ok() {
+            result = if dma {
+                self.wait_data_end()
+            } else {
+                self.write_fifo(buf)
+            };
+        }
+
+        self.end_data(stop, result)
+    }
+
+    /// Check that a card is initialized, and the buffer is valid for a transfer.
+    fn check_transfer(&self, ptr: *const u8, len: usize, dma: bool) -> Result<CardInfo, Error> {
+        let card = self.card.ok_or(Error::NoCard)?;
+
+        let misaligned = dma && !(ptr as usize).is_multiple_of(4);
+        if len == 0 || !len.is_multiple_of(BLOCK_SIZE) || misaligned {
+            return Err(Error::InvalidBuffer);
+        }
+
+        Ok(card)
+    }
+
+    /// Set up the data path state machine (DPSM), and IDMA for a transfer. The DPSM starts when
+    /// the command is sent, with CMDTRANS set.
+    fn setup_data(&self, len: usize, read: bool, dma_addr: Option<u32>) {
+        self.write_reg(ICR, ICR_ALL);
+
+        // Data timeout, in card clock cycles: 500ms.
+        self.write_reg(DTIMER, self.bus_freq / 2);
+        self.write_reg(DLENR, len as u32);
+        self.write_reg(DCTRL, DBLOCKSIZE | if read { DTDIR } else { 0 });
+
+        match dma_addr {
+            Some(addr) => {
+                self.write_reg(IDMABASE0R, addr);
+                self.write_reg(IDMACTRLR, 1);
+            }
+            None => self.write_reg(IDMACTRLR, 0),
+        }
+    }
+
+    /// After a transfer, stop a multiple-block transfer, disable IDMA, and clear flags. Returns
+    /// the transfer's result, or the stop command's error.
+    fn end_data(&self, stop: bool, result: Result<(), Error>) -> Result<(), Error> {
+        let stop_result = if stop { self.command_stop() } else { Ok(()) };
+
+        self.write_reg(IDMACTRLR, 0);
+        self.write_reg(ICR, ICR_ALL);
+
+        result.and(stop_result)
+    }
+
+    /// Copy data from the RX FIFO, until the transfer ends.
+    fn read_fifo(&self, buf: &mut [u8]) -> Result<(), Error> {
+        let mut i = 0;
+
+        loop {
+            let status = self.read_reg(STAR);
+            check_data_errors(status)?;
+
+            if status & RXFIFOHF != 0 {
+                for _ in 0..FIFO_HALF_WORDS {
+                    let word = self.read_reg(FIFOR).to_le_bytes();
+                    buf[i..i + 4].copy_from_slice(&word);
+                    i += 4;
+                }
+            } else if status & DATAEND != 0 {
+                break;
+            }
+        }
+
+        // Data remaining in the FIFO after the transfer ends.
+        while i < buf.len() && self.read_reg(STAR) & RXFIFOE == 0 {
+            let word = self.read_reg(FIFOR).to_le_bytes();
+            buf[i..i + 4].copy_from_slice(&word);
+            i += 4;
+        }
+
+        Ok(())
+    }
+
+    /// Copy data to the TX FIFO, until the transfer ends.
+    fn write_fifo(&self, buf: &[u8]) -> Result<(), Error> {
+        let mut i = 0;
+
+        loop {
+            let status = self.read_reg(STAR);
+            check_data_errors(status)?;
+
+            if status & DATAEND != 0 {
+                return Ok(());
+            }
+
+            if status & TXFIFOHE != 0 && i < buf.len() {
+                for _ in 0..FIFO_HALF_WORDS {
+                    let word = u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+                    self.write_reg(FIFOR, word);
+                    i += 4;
+                }
+            }
+        }
+    }
+
+    /// Wait for an IDMA transfer to end.
+    fn wait_data_end(&self) -> Result<(), Error> {
+        loop {
+            let status = self.read_reg(STAR);
+            check_data_errors(status)?;
+
+            if status & DATAEND != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send a command, and wait for its response.
+    fn command(&self, index: u8, arg: u32, response: Response) -> Result<(), Error> {
+        self.send_command(index, arg, response, 0)
+    }
+
+    /// Send a command with an R1 response, and check the card status for errors.
+    fn command_r1(&self, index: u8, arg: u32) -> Result<(), Error> {
+        self.command(index, arg, Response::Short)?;
+        check_r1(self.read_reg(RESP1R))
+    }
+
+    /// Send a read, or write command, starting the DPSM.
+    fn command_transfer(&self, index: u8, arg: u32) -> Result<(), Error> {
+        self.send_command(index, arg, Response::Short, CMDTRANS)?;
+        check_r1(self.read_reg(RESP1R))
+    }
+
+    /// Stop a multiple-block transfer, and wait for the card to leave the busy state.
+    fn command_stop(&self) -> Result<(), Error> {
+        self.send_command(CMD_STOP_TRANSMISSION, 0, Response::Short, CMDSTOP)?;
+        self.wait_busy();
+        check_r1(self.read_reg(RESP1R))
+    }
+
+    fn send_command(
+        &self,
+        index: u8,
+        arg: u32,
+        response: Response,
+        flags: u32,
+    ) -> Result<(), Error> {
+        // Clear the command flags; leave the data flags, in case a transfer is in progress.
+        self.write_reg(ICR, CCRCFAIL | CTIMEOUT | CMDREND | CMDSENT);
+
+        self.write_reg(ARGR, arg);
+        self.write_reg(
+            CMDR,
+            index as u32 | (response as u32) << WAITRESP_SHIFT | CPSMEN | flags,
+        );
+
+        loop {
+            let status = self.read_reg(STAR);
+
+            if status & CTIMEOUT != 0 {
+                return Err(Error::CommandTimeout);
+            }
+            if status & CCRCFAIL != 0 {
+                return Err(Error::CommandCrc);
+            }
+
+            match response {
+                Response::None => {
+                    if status & CMDSENT != 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {
+                    if status & CMDREND != 0 {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read a 136-bit (R2) response. The first word is bits 127:96.
+    fn read_long_response(&self) -> [u32; 4] {
+        [
+            self.read_reg(RESP1R),
+            self.read_reg(RESP1R + 4),
+            self.read_reg(RESP1R + 8),
+            self.read_reg(RESP1R + 12),
+        ]
+    }
+
+    /// Wait while the card holds D0 low, eg after CMD7, or CMD12.
+    fn wait_busy(&self) {
+        while self.read_reg(STAR) & BUSYD0 != 0 {}
+    }
+
+    /// Wait until the card is back in the transfer state, and ready for data, eg after a write.
+    fn wait_ready(&self, card: &CardInfo) -> Result<(), Error> {
+        loop {
+            self.command_r1(CMD_SEND_STATUS, (card.rca as u32) << 16)?;
+            let status = self.read_reg(RESP1R);
+
+            if status & R1_READY_FOR_DATA != 0 && (status >> 9) & 0xf == R1_STATE_TRAN {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+/// The command argument for a block: SDSC cards are addressed by byte, and others, by block.
+fn card_addr(card: &CardInfo, block_addr: u32) -> u32 {
+    match card.card_type {
+        CardType::Sdsc => block_addr * BLOCK_SIZE as u32,
+        CardType::Sdhc => block_addr,
+    }
+}
+
+/// Check an R1 card status for errors.
+fn check_r1(status: u32) -> Result<(), Error> {
+    if status & R1_ERRORS != 0 {
+        Err(Error::CardStatus(status))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check the status register for data transfer errors.
+fn check_data_errors(status: u32) -> Result<(), Error> {
+    if status & DATA_ERRORS == 0 {
+        return Ok(());
+    }
+
+    Err(if status & DCRCFAIL != 0 {
+        Error::DataCrc
+    } else if status & DTIMEOUT != 0 {
+        Error::DataTimeout
+    } else if status & IDMATE != 0 {
+        Error::Dma
+    } else {
+        Error::Fifo
+    })
+}
+
+#[cfg(feature = "embedded_sdmmc")]
+impl BlockDevice for Sdmmc {
+    type Error = Error;
+
+    fn read(
+        &self,
+        blocks: &mut [Block],
+        start_block_idx: BlockIdx,
+        _reason: &str,
+    ) -> Result<(), Self::Error> {
+        // `Block` doesn't guarantee its layout, so we read each block separately.
+        for (i, block) in blocks.iter_mut().enumerate() {
+            self.read_blocks_inner(start_block_idx.0 + i as u32, &mut block.contents, false)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        for (i, block) in blocks.iter().enumerate() {
+            self.write_blocks_inner(start_block_idx.0 + i as u32, &block.contents, false)?;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        match self.card {
+            Some(card) => Ok(BlockCount(card.num_blocks)),
+            None => Err(Error::NoCard),
+        }
+    }
+}
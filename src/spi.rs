@@ -9,7 +9,14 @@ use cortex_m::interrupt::free;
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::spi::FullDuplex;
 
+#[cfg(feature = "embedded-hal-1")]
+use crate::{clocks::Clocks, gpio::Pin};
+
+#[cfg(feature = "async")]
+use crate::asynch::Signal;
+
 use crate::{
+    low_power::BusyPeriph,
     pac::{self, RCC},
     util::RccPeriph,
 };
@@ -177,6 +184,18 @@ pub enum SpiCommMode {
     ReceiveOnly,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// Select master or slave configuration. Sets `CR1` register, `MSTR` field (`CFG2` register, `MASTER`
+/// field on H7).
+pub enum SpiRole {
+    /// This device generates the clock, and manages the slave select line.
+    Master,
+    /// This device is the target of another controller, which generates the clock. Use
+    /// `SlaveSelect::HardwareOutDisable` to use the NSS pin as a chip select input, or
+    /// `SlaveSelect::Software` to always be selected.
+    Slave,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// Used for managing NSS / CS pin. Sets CR1 register, SSM field.
 pub enum SlaveSelect {
@@ -201,7 +220,7 @@ pub enum SlaveSelect {
 
 cfg_if! {
     if #[cfg(feature = "embedded_hal")] {
-        type SpiModeType = embedded_hal::spi::Mode;
+        pub(crate) type SpiModeType = embedded_hal::spi::Mode;
     } else {
         #[derive(Clone, Copy)]
         #[repr(u8)]
@@ -270,7 +289,7 @@ cfg_if! {
             }
         }
 
-        type SpiModeType = SpiMode;
+        pub(crate) type SpiModeType = SpiMode;
     }
 }
 
@@ -282,10 +301,17 @@ pub struct SpiConfig {
     pub comm_mode: SpiCommMode,
     /// Controls use of hardware vs software CS/NSS pin. Defaults to software.
     pub slave_select: SlaveSelect,
+    /// Master or slave operation. Defaults to master.
+    pub role: SpiRole,
     /// Data size. Defaults to 8 bits.
     pub data_size: DataSize,
-    /// FIFO reception threshhold. Defaults to 8 bits.
+    /// FIFO reception threshhold. Defaults to 8 bits. Set to 16 bits when using data sizes
+    /// over 8 bits, or to read 2 8-bit frames at once (data packing) with the `_u16` methods.
     pub fifo_reception_thresh: ReceptionThresh,
+    /// Hardware CRC polynomial. If `Some`, enables CRC calculation. The CRC length is 16 bits
+    /// if the data size is over 8 bits, and 8 bits otherwise. On H7, the CRC is only sent, and
+    /// checked by `transfer_crc()`. Defaults to `None`.
+    pub crc_poly: Option<u16>,
     // pub cs_delay: f32,
     // pub swap_miso_mosi: bool,
     // pub suspend_when_inactive: bool,
@@ -305,12 +331,40 @@ impl Default for SpiConfig {
             mode: mode0,
             comm_mode: SpiCommMode::FullDuplex,
             slave_select: SlaveSelect::Software,
+            role: SpiRole::Master,
             data_size: DataSize::D8,
             fifo_reception_thresh: ReceptionThresh::D8,
+            crc_poly: None,
         }
     }
 }
 
+#[derive(Clone, Copy)]
+/// Raw configuration register values, saved with `Spi::snapshot()`, and restored with `Spi::restore()`.
+/// Use this to restore the peripheral's configuration after its registers are lost, without calling
+/// `Spi::new()` again: eg after Stop 2 on some families, or the peripheral's power domain being
+/// switched off. Waking from Standby is a reset, which also loses SRAM; keep the snapshot in
+/// retained SRAM (eg SRAM2 with `RRS` set), or store `to_raw()` in backup registers in that case.
+/// Note that the baud rate is stored as a prescaler; if you change clock speeds on wakeup, call
+/// `reclock()` after restoring.
+pub struct ConfigSnapshot {
+    /// CR1, CR2, and CRCPR (CFG1, CFG2, and CRCPOLY on H7)
+    regs: [u32; 3],
+}
+
+impl ConfigSnapshot {
+    /// The raw register values: CR1, CR2, and CRCPR (CFG1, CFG2, and CRCPOLY on H7). Use this to
+    /// store the snapshot, eg in backup registers.
+    pub fn to_raw(&self) -> [u32; 3] {
+        self.regs
+    }
+
+    /// Load a snapshot from values returned by `to_raw()`.
+    pub fn from_raw(regs: [u32; 3]) -> Self {
+        Self { regs }
+    }
+}
+
 /// Represents a Serial Peripheral Interface (SPI) peripheral.
 pub struct Spi<R> {
     pub regs: R,
@@ -336,12 +390,20 @@ where
 
                 regs.cfg1.modify(|_, w| {
                     w.mbr().bits(baud_rate as u8);
-                    w.dsize().bits(cfg.data_size as u8)
-
+                    w.dsize().bits(cfg.data_size as u8);
+                    // The CRC is appended automatically at the end of a transfer of TSIZE frames;
+                    // see `transfer_crc()`.
+                    w.crcen().bit(cfg.crc_poly.is_some());
+                    w.crcsize().bits(cfg.data_size as u8)
                 });
 
-                // ssi: select slave = master mode
-                regs.cr1.write(|w| w.ssi().slave_not_selected());
+                if let Some(poly) = cfg.crc_poly {
+                    regs.crcpoly.write(|w| unsafe { w.crcpoly().bits(poly as u32) });
+                }
+
+                // ssi: In master mode, this must be high. In slave mode with software NSS management,
+                // this must be low for the slave to be selected.
+                regs.cr1.write(|w| w.ssi().bit(cfg.role == SpiRole::Master));
 
                 // todo: Data size on H7.
 
@@ -378,7 +440,8 @@ where
                 regs.cfg2.write(|w| {
                     w.cpha().bit(cfg.mode.phase as u8 != 0);
                         w.cpol().bit(cfg.mode.polarity as u8 != 0);
-                        w.master().master();
+                        w.master().bit(cfg.role == SpiRole::Master);
+                        w.ssm().bit(cfg.slave_select == SlaveSelect::Software);
                         w.lsbfrst().msbfirst()
                         // w.ssom().bit(config.suspend_when_inactive);
                         // w.ssm().bit(config.managed_cs == false);
@@ -390,7 +453,7 @@ where
                 });
 
                 // spe: enable the SPI bus
-                regs.cr1.write(|w| w.ssi().slave_not_selected().spe().enabled());
+                regs.cr1.write(|w| w.ssi().bit(cfg.role == SpiRole::Master).spe().enabled());
             } else {
                 // L44 RM, section 40.4.7: Configuration of SPI
                 // The configuration procedure is almost the same for master and slave. For specific mode
@@ -400,6 +463,18 @@ where
                 // 1. Write proper GPIO registers: Configure GPIO for MOSI, MISO and SCK pins.
                 // (Handled in GPIO modules and user code)
 
+                // 4. Write to SPI_CRCPR register: Configure the CRC polynomial if needed.
+                // (We do this first, since it must be set while the SPI is disabled)
+                if let Some(poly) = cfg.crc_poly {
+                    regs.crcpr.write(|w| unsafe { w.crcpoly().bits(poly) });
+                }
+
+                // Bit 11 of CR1 is CRCL (CRC length) on families with a data size field, and DFF
+                // (data frame format) on F4; in both cases, set it for 16-bit operations. This field
+                // is named inconsistently in PACs, so we set it directly.
+                let bit_11 = (cfg.data_size as u8 > DataSize::D8 as u8) as u32;
+                regs.cr1.modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 11)) | (bit_11 << 11)) });
+
                 // 2. Write to the SPI_CR1 register:
                 regs.cr1.modify(|_, w| unsafe {
                     // a) Configure the serial clock baud rate using the BR[2:0] bits (Note: 4)
@@ -418,13 +493,14 @@ where
                     w.lsbfirst().clear_bit();
                     // e) Configure the CRCL and CRCEN bits if CRC is needed (while SCK clock signal is
                     // at idle state).
-                    w.crcen().clear_bit();
+                    w.crcen().bit(cfg.crc_poly.is_some());
                     // f) Configure SSM and SSI (Notes: 2 & 3).
                     w.ssm().bit(cfg.slave_select == SlaveSelect::Software);
-                    w.ssi().set_bit(); // todo?
+                    // In slave mode with software NSS management, SSI low selects the slave.
+                    w.ssi().bit(cfg.role == SpiRole::Master);
                     // g) Configure the MSTR bit (in multimaster NSS configuration, avoid conflict state on
                     // NSS if master is configured to prevent MODF error).
-                    w.mstr().set_bit();
+                    w.mstr().bit(cfg.role == SpiRole::Master);
                     w.spe().set_bit() // Enable SPI
                 });
 
@@ -449,7 +525,6 @@ where
                 // CHPA and TI bits cleared in NSSP mode).
 
                 // f) Initialize LDMA_TX and LDMA_RX bits if DMA is used in packed mode.
-                // 4. Write to SPI_CRCPR register: Configure the CRC polynomial if needed.
                 // 5. Write proper DMA registers: Configure DMA streams dedicated for SPI Tx and Rx in
                 // DMA registers if the DMA streams are used.
             }
@@ -480,6 +555,57 @@ where
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
     }
 
+    /// Save the peripheral's configuration registers. Run this before entering a low-power
+    /// mode where register contents may be lost. Call `disable()` first if a transfer may be in progress.
+    /// See `ConfigSnapshot` for where to store it.
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let regs = [
+                    self.regs.cfg1.read().bits(),
+                    self.regs.cfg2.read().bits(),
+                    self.regs.crcpoly.read().bits(),
+                ];
+            } else {
+                let regs = [
+                    self.regs.cr1.read().bits(),
+                    self.regs.cr2.read().bits(),
+                    self.regs.crcpr.read().bits(),
+                ];
+            }
+        }
+
+        ConfigSnapshot { regs }
+    }
+
+    /// Restore the peripheral's configuration, from a snapshot taken with `snapshot()`. Re-enables and
+    /// resets the RCC peripheral clock, writes the configuration registers with the SPI disabled, then
+    /// enables it. Run this after waking from a low-power mode.
+    pub fn restore(&mut self, snapshot: &ConfigSnapshot) {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            R::en_reset(rcc);
+        });
+
+        let [r0, r1, r2] = snapshot.regs;
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+                self.regs.crcpoly.write(|w| unsafe { w.bits(r2) });
+                self.regs.cfg1.write(|w| unsafe { w.bits(r0) });
+                self.regs.cfg2.write(|w| unsafe { w.bits(r1) });
+                self.regs.cr1.write(|w| w.ssi().bit(self.cfg.role == SpiRole::Master).spe().enabled());
+            } else {
+                self.regs.crcpr.write(|w| unsafe { w.bits(r2) });
+                self.regs.cr2.write(|w| unsafe { w.bits(r1) });
+                // Write CR1 with SPE cleared, then set SPE, per the configuration procedure.
+                self.regs.cr1.write(|w| unsafe { w.bits(r0 & !(1 << 6)) });
+                self.regs.cr1.modify(|_, w| w.spe().bit(r0 & (1 << 6) != 0));
+            }
+        }
+    }
+
     /// L44 RM, section 40.4.9: "Procedure for disabling the SPI"
     /// When SPI is disabled, it is mandatory to follow the disable procedures described in this
     /// paragraph. It is important to do this before the system enters a low-power mode when the
@@ -520,7 +646,8 @@ where
         }
     }
 
-    /// Read a single byte if available, or block until it's available.
+    /// Read a single byte if available, or block until it's available. If an overrun occured,
+    /// clears the overrun flag, and returns `Error::Overrun`.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
     pub fn read(&mut self) -> Result<u8, Error> {
         let sr = self.regs.sr.read();
@@ -534,6 +661,7 @@ where
         }
 
         if sr.ovr().bit_is_set() {
+            self.clear_overrun();
             return Err(Error::Overrun);
         } else if sr.modf().bit_is_set() {
             return Err(Error::ModeFault);
@@ -580,7 +708,9 @@ where
                 // todo: note: H7 can support words beyond u8. (Can others too?)
                 unsafe { ptr::write_volatile(&self.regs.txdr as *const _ as *mut u8, byte) };
                 // write CSTART to start a transaction in master mode
-                self.regs.cr1.modify(|_, w| w.cstart().started());
+                if self.cfg.role == SpiRole::Master {
+                    self.regs.cr1.modify(|_, w| w.cstart().started());
+                }
             }
              else {
                 while !self.regs.sr.read().txe().bit_is_set() {}
@@ -613,6 +743,256 @@ where
         Ok(())
     }
 
+    /// Check the status register for errors.
+    fn check_errors(&mut self) -> Result<(), Error> {
+        let sr = self.regs.sr.read();
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                let crce = sr.crce().bit_is_set();
+            } else {
+                let crce = sr.crcerr().bit_is_set();
+            }
+        }
+
+        if sr.ovr().bit_is_set() {
+            self.clear_overrun();
+            Err(Error::Overrun)
+        } else if sr.modf().bit_is_set() {
+            Err(Error::ModeFault)
+        } else if crce {
+            Err(Error::Crc)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read a single 16-bit word if available, or block until it's available. Use this with data
+    /// sizes over 8 bits, or to read 2 8-bit frames at once if `fifo_reception_thresh` is set to 16 bits.
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        self.check_errors()?;
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                while !self.regs.sr.read().rxp().bit_is_set() {}
+                Ok(unsafe { ptr::read_volatile(self.regs.rxdr.as_ptr() as *const u16) })
+            } else {
+                while !self.regs.sr.read().rxne().bit_is_set() {}
+                Ok(unsafe { ptr::read_volatile(self.regs.dr.as_ptr() as *const u16) })
+            }
+        }
+    }
+
+    /// Write a single 16-bit word, blocking until there's room in the transmit buffer. Use this with
+    /// data sizes over 8 bits, or to write 2 8-bit frames at once.
+    pub fn write_one_u16(&mut self, word: u16) -> Result<(), Error> {
+        self.check_errors()?;
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                while !self.regs.sr.read().txp().bit_is_set() {}
+                unsafe { ptr::write_volatile(self.regs.txdr.as_ptr() as *mut u16, word) };
+                if self.cfg.role == SpiRole::Master {
+                    self.regs.cr1.modify(|_, w| w.cstart().started());
+                }
+            } else {
+                while !self.regs.sr.read().txe().bit_is_set() {}
+                unsafe { ptr::write_volatile(self.regs.dr.as_ptr() as *mut u16, word) };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write multiple 16-bit words on the SPI line, blocking until complete.
+    pub fn write_u16(&mut self, words: &[u16]) -> Result<(), Error> {
+        for word in words {
+            self.write_one_u16(*word)?;
+            self.read_u16()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write multiple 16-bit words, replacing each with the word read, blocking until complete.
+    pub fn transfer_u16(&mut self, words: &mut [u16]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            self.write_one_u16(*word)?;
+            *word = self.read_u16()?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Perform a transfer with hardware CRC: The CRC is sent after the last byte, and the CRC
+    /// received from the other device is checked. Returns `Error::Crc` on mismatch. Requires
+    /// `crc_poly` to be set in the config, and 8-bit data size. Resets the CRC before starting.
+    /// See L44 RM, section 40.4.14: CRC calculation. Does nothing if `words` is empty.
+    pub fn transfer_crc(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        // With no data, CRCNEXT would never be set, and no CRC frame received.
+        if words.is_empty() {
+            return Ok(());
+        }
+
+        self.reset_crc();
+
+        let len = words.len();
+        for (i, word) in words.iter_mut().enumerate() {
+            self.write_one(*word)?;
+            // "CRCNEXT bit must be set after the last data to be transferred is written to the TX buffer."
+            if i == len - 1 {
+                self.regs.cr1.modify(|_, w| w.crcnext().set_bit());
+            }
+            *word = self.read()?;
+        }
+
+        // Receive the CRC frame. The CRCERR flag is set after it's received, if it doesn't match.
+        while !self.regs.sr.read().rxne().bit_is_set() {}
+        unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) };
+        while self.regs.sr.read().bsy().bit_is_set() {}
+
+        if self.regs.sr.read().crcerr().bit_is_set() {
+            self.clear_crc_error();
+            return Err(Error::Crc);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "h7")]
+    /// Perform a transfer with hardware CRC: The CRC is sent after the last byte, and the CRC
+    /// received from the other device is checked. Returns `Error::Crc` on mismatch. Requires
+    /// `crc_poly` to be set in the config, and 8-bit data size. The H7 SPI only appends, and
+    /// checks the CRC at the end of a transfer of `TSIZE` frames, so this sets `CR2` register,
+    /// `TSIZE` field to the length of `words`, and sets it back to 0 (unlimited) afterwards. This
+    /// also resets the CRC. `words` can be at most 65,535 bytes. See H743 RM, SPI chapter: CRC
+    /// computation. Does nothing if `words` is empty.
+    pub fn transfer_crc(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        if words.is_empty() {
+            return Ok(());
+        }
+        assert!(
+            words.len() <= u16::MAX as usize,
+            "SPI CRC transfers can be at most 65,535 bytes."
+        );
+
+        // TSIZE can only be changed while the SPI is disabled. Disabling it also resets the CRC.
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs
+            .cr2
+            .modify(|_, w| w.tsize().bits(words.len() as u16));
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        let mut result = Ok(());
+        for word in words.iter_mut() {
+            if let Err(e) = self.write_one(*word) {
+                result = Err(e);
+                break;
+            }
+            match self.read() {
+                Ok(w) => *word = w,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        if result.is_ok() {
+            // The CRC frame is sent, and the received one checked after the last data frame. EOT
+            // is set once it's complete.
+            while self.regs.sr.read().eot().bit_is_clear() {}
+
+            if self.regs.sr.read().crce().bit_is_set() {
+                self.clear_crc_error();
+                result = Err(Error::Crc);
+            }
+        }
+
+        self.regs.ifcr.write(|w| {
+            w.eotc().set_bit();
+            w.txtfc().set_bit()
+        });
+
+        // Return to unlimited transfers, as used by the other methods.
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs.cr2.modify(|_, w| w.tsize().bits(0));
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+
+        result
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Reset the CRC calculation, by toggling the `CRCEN` bit while the SPI is disabled.
+    pub fn reset_crc(&mut self) {
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs.cr1.modify(|_, w| w.crcen().clear_bit());
+        self.regs.cr1.modify(|_, w| w.crcen().set_bit());
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+    }
+
+    /// Read the CRC calculated from transmitted data. Reads the `TXCRCR` register.
+    pub fn crc_tx(&self) -> u16 {
+        #[cfg(feature = "h7")]
+        return self.regs.txcrc.read().bits() as u16;
+        #[cfg(not(feature = "h7"))]
+        return self.regs.txcrcr.read().bits() as u16;
+    }
+
+    /// Read the CRC calculated from received data. Reads the `RXCRCR` register.
+    pub fn crc_rx(&self) -> u16 {
+        #[cfg(feature = "h7")]
+        return self.regs.rxcrc.read().bits() as u16;
+        #[cfg(not(feature = "h7"))]
+        return self.regs.rxcrcr.read().bits() as u16;
+    }
+
+    /// Clear the CRC error (CRCERR) flag.
+    pub fn clear_crc_error(&mut self) {
+        #[cfg(feature = "h7")]
+        self.regs.ifcr.write(|w| w.crcec().set_bit());
+        #[cfg(not(feature = "h7"))]
+        self.regs.sr.modify(|_, w| w.crcerr().clear_bit());
+    }
+
+    /// Read a byte from the receive buffer without blocking; for use in slave mode, in the RXNE
+    /// (RXP on H7) interrupt handler. Enable this with `enable_interrupt(SpiInterrupt::RxBufNotEmpty)`.
+    /// On overrun, clears the flag, and returns `Error::Overrun`; the byte received when the overrun
+    /// occurred is lost.
+    pub fn read_isr(&mut self) -> Result<u8, Error> {
+        let sr = self.regs.sr.read();
+
+        if sr.ovr().bit_is_set() {
+            self.clear_overrun();
+            return Err(Error::Overrun);
+        } else if sr.modf().bit_is_set() {
+            return Err(Error::ModeFault);
+        }
+
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                Ok(unsafe { ptr::read_volatile(&self.regs.rxdr as *const _ as *const u8) })
+            } else {
+                Ok(unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) })
+            }
+        }
+    }
+
+    /// Clear the overrun (OVR) flag. On families other than H7, this is done by reading
+    /// the `DR` register, then the `SR` register; the data read is discarded.
+    /// (L44 RM, section 40.4.10: Overrun flag (OVR))
+    pub fn clear_overrun(&mut self) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.ifcr.write(|w| w.ovrc().set_bit());
+            } else {
+                unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) };
+                self.regs.sr.read();
+            }
+        }
+    }
+
     /// Transmit data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
     /// Note that the `channel` argument is unused on F3 and L4, since it is hard-coded,
     /// and can't be configured using the DMAMUX peripheral. (`dma::mux()` fn).
@@ -980,6 +1360,23 @@ where
     }
 }
 
+impl<R> BusyPeriph for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Returns true if a transfer is in progress. (SR register, BSY field; CR1 register, CSTART
+    /// field on H7)
+    fn is_busy(&self) -> bool {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.cr1.read().cstart().bit_is_set()
+            } else {
+                self.regs.sr.read().bsy().bit_is_set()
+            }
+        }
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 impl<R> FullDuplex<u8> for Spi<R>
 where
@@ -1013,3 +1410,278 @@ impl<R> embedded_hal::blocking::spi::write::Default<u8> for Spi<R> where
     R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph
 {
 }
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_1::spi::Error for Error {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        use embedded_hal_1::spi::ErrorKind;
+
+        match self {
+            Self::Overrun => ErrorKind::Overrun,
+            Self::ModeFault => ErrorKind::ModeFault,
+            Self::Crc => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::spi::ErrorType for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::spi::SpiBus<u8> for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            self.write_one(0)?;
+            *word = Spi::read(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        Spi::write(self, words)
+    }
+
+    /// If `read` and `write` have different lengths, the shorter one is padded; with 0s if it's
+    /// `write`, and by discarding bytes if it's `read`.
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        for i in 0..read.len().max(write.len()) {
+            self.write_one(write.get(i).copied().unwrap_or(0))?;
+            let byte = Spi::read(self)?;
+
+            if let Some(word) = read.get_mut(i) {
+                *word = byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        Spi::transfer(self, words)
+    }
+
+    /// Each byte is read back as it's written, so there's nothing to flush.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+/// An SPI bus with a dedicated chip select (CS) pin, which implements the embedded-hal 1.0
+/// `SpiDevice` trait. CS is driven low for the duration of each transaction. To share a bus
+/// between multiple devices, use `Spi` with the `embedded-hal-bus` crate instead.
+pub struct SpiCsDevice<R> {
+    pub spi: Spi<R>,
+    pub cs: Pin,
+    /// Core clock speed, in Hz; used for delay operations.
+    core_freq: u32,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> SpiCsDevice<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Create a device from an SPI bus, and a CS pin that's configured as an output. Sets CS high.
+    pub fn new(spi: Spi<R>, mut cs: Pin, clocks: &Clocks) -> Self {
+        cs.set_high();
+
+        Self {
+            spi,
+            cs,
+            core_freq: clocks.systick(),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::spi::ErrorType for SpiCsDevice<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_1::spi::SpiDevice<u8> for SpiCsDevice<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal_1::spi::Operation<'_, u8>],
+    ) -> Result<(), Error> {
+        use embedded_hal_1::spi::{Operation, SpiBus};
+
+        self.cs.set_low();
+
+        let mut result = Ok(());
+        for op in operations.iter_mut() {
+            result = match op {
+                Operation::Read(buf) => SpiBus::read(&mut self.spi, buf),
+                Operation::Write(buf) => SpiBus::write(&mut self.spi, buf),
+                Operation::Transfer(read, write) => SpiBus::transfer(&mut self.spi, read, write),
+                Operation::TransferInPlace(buf) => SpiBus::transfer_in_place(&mut self.spi, buf),
+                Operation::DelayNs(ns) => {
+                    let cycles = *ns as u64 * self.core_freq as u64 / 1_000_000_000;
+                    cortex_m::asm::delay(cycles as u32 + 1);
+                    Ok(())
+                }
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // Release CS even if an operation failed, so the device isn't left selected.
+        self.cs.set_high();
+
+        result
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        /// SPI_IER interrupt enable bits used by `SpiAsync`: RXPIE, OVRIE, and MODFIE.
+        #[cfg(feature = "async")]
+        const ASYNC_IRQS: u32 = (1 << 0) | (1 << 6) | (1 << 9);
+    } else {
+        /// SPI_CR2 interrupt enable bits used by `SpiAsync`: ERRIE, and RXNEIE.
+        #[cfg(feature = "async")]
+        const ASYNC_IRQS: u32 = (1 << 5) | (1 << 6);
+    }
+}
+
+#[cfg(feature = "async")]
+/// Handle an SPI interrupt, for `SpiAsync`. Call this from the peripheral's ISR. Masks the
+/// interrupts enabled by `SpiAsync`, and signals the waiting task.
+pub fn on_interrupt_async(regs: &pac::spi1::RegisterBlock, signal: &Signal) {
+    #[cfg(feature = "h7")]
+    regs.ier
+        .modify(|r, w| unsafe { w.bits(r.bits() & !ASYNC_IRQS) });
+    #[cfg(not(feature = "h7"))]
+    regs.cr2
+        .modify(|r, w| unsafe { w.bits(r.bits() & !ASYNC_IRQS) });
+
+    signal.signal();
+}
+
+#[cfg(feature = "async")]
+/// An SPI peripheral, which implements the `embedded-hal-async` `SpiBus` trait. Each byte is
+/// received using the peripheral's interrupt; its ISR must call `on_interrupt_async()`, with the
+/// same `Signal`. For large transfers, consider DMA, awaiting the DMA channel's transfer complete
+/// interrupt with a `Signal`.
+pub struct SpiAsync<R> {
+    pub spi: Spi<R>,
+    signal: &'static Signal,
+}
+
+#[cfg(feature = "async")]
+impl<R> SpiAsync<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    pub fn new(spi: Spi<R>, signal: &'static Signal) -> Self {
+        Self { spi, signal }
+    }
+
+    /// Write a byte, and wait for the byte received in exchange. The transmit buffer is always
+    /// empty here, since we read each byte before writing the next.
+    async fn exchange(&mut self, byte: u8) -> Result<u8, Error> {
+        self.spi.write_one(byte)?;
+
+        loop {
+            let sr = self.spi.regs.sr.read();
+
+            #[cfg(feature = "h7")]
+            let rx_ready = sr.rxp().bit_is_set();
+            #[cfg(not(feature = "h7"))]
+            let rx_ready = sr.rxne().bit_is_set();
+
+            // `Spi::read()` reports errors.
+            if rx_ready || sr.ovr().bit_is_set() || sr.modf().bit_is_set() {
+                return self.spi.read();
+            }
+
+            // The ISR masks these again when it fires.
+            self.signal.reset();
+            #[cfg(feature = "h7")]
+            self.spi
+                .regs
+                .ier
+                .modify(|r, w| unsafe { w.bits(r.bits() | ASYNC_IRQS) });
+            #[cfg(not(feature = "h7"))]
+            self.spi
+                .regs
+                .cr2
+                .modify(|r, w| unsafe { w.bits(r.bits() | ASYNC_IRQS) });
+
+            self.signal.wait().await;
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_hal_1::spi::ErrorType for SpiAsync<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_hal_async::spi::SpiBus<u8> for SpiAsync<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(0).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        for word in words {
+            self.exchange(*word).await?;
+        }
+
+        Ok(())
+    }
+
+    /// See the notes on the blocking `transfer()` implementation.
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        for i in 0..read.len().max(write.len()) {
+            let byte = self.exchange(write.get(i).copied().unwrap_or(0)).await?;
+
+            if let Some(word) = read.get_mut(i) {
+                *word = byte;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(*word).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
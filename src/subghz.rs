@@ -0,0 +1,986 @@
+//! Support for the STM32WL's sub-GHz radio, through its internal SPI link (SUBGHZSPI). The radio
+//! is an SX126x-compatible transceiver, controlled with opcode commands: This module provides
+//! raw command, register, and buffer access, and typed helpers for LoRa and (G)FSK configuration,
+//! transmission, and reception.
+//!
+//! The RF switch is external, and board-specific; register a function that drives it with
+//! `set_rf_switch()`, and it's called before each TX, and RX. If the board uses a TCXO powered
+//! from the radio's `VDDTCXO` pin, set `tcxo` in the config, and set `hse_bypass` in the clock
+//! config.
+//!
+//! The radio's IRQ line is routed to the `SUBGHZ_RADIO` interrupt. Enable it with
+//! `enable_interrupt()`, and unmask it in the NVIC; in the handler, read, and clear the cause with
+//! `irq_status()`, and `clear_irq()`.
+//!
+//! See RM0453, chapter 4: "Sub-GHz radio (SUBGHZ)", and chapter 5: "Sub-GHz radio SPI interface
+//! (SUBGHZSPI)".
+//!
+//! Example, sending a LoRa packet at 868.1MHz:
+//! ```rust
+//! let mut radio = SubGhz::new(
+//!     SubGhzConfig {
+//!         regulator: RegulatorMode::Smps,
+//!         tcxo: Some(TcxoCfg { voltage: TcxoVoltage::V1_7, startup_us: 5_000 }),
+//!     },
+//!     &clock_cfg,
+//! );
+//! radio.set_rf_switch(|state| match state {
+//!     RfState::TxHp => { gpio::set_high(Port::C, 4); gpio::set_high(Port::C, 5) },
+//!     RfState::Rx => { gpio::set_high(Port::C, 4); gpio::set_low(Port::C, 5) },
+//!     _ => { gpio::set_low(Port::C, 4); gpio::set_low(Port::C, 5) },
+//! });
+//!
+//! radio.set_packet_type(PacketType::LoRa);
+//! radio.set_rf_frequency(868_100_000);
+//! radio.set_lora_sync_word(LoRaSyncWord::Public);
+//! radio.set_lora_modulation(&LoRaModParams {
+//!     sf: 7,
+//!     bw: LoRaBandwidth::Bw125,
+//!     cr: LoRaCodingRate::Cr4_5,
+//!     ldro: false,
+//! });
+//! radio.set_lora_packet(&Default::default());
+//! radio.set_pa_config(PaConfig::HP_14);
+//! radio.set_tx_params(14, RampTime::Us40);
+//! radio.set_irq_cfg(Irq::TxDone as u16 | Irq::Timeout as u16);
+//!
+//! radio.transmit(b"hello", 0);
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::{clocks::Clocks, pac::RCC};
+
+// We use raw pointers for the SUBGHZSPI, and the radio control bits in PWR and RCC, since these
+// are named inconsistently across PAC versions.
+const SPI_BASE: usize = 0x5801_0000;
+const PWR_BASE: usize = 0x5800_0400;
+const EXTI_BASE: usize = 0x5800_0800;
+
+// SUBGHZSPI register offsets.
+const SPI_CR1: usize = 0x00;
+const SPI_CR2: usize = 0x04;
+const SPI_SR: usize = 0x08;
+const SPI_DR: usize = 0x0c;
+
+// SUBGHZSPI_CR1 fields.
+const MSTR: u32 = 1 << 2;
+const BR_SHIFT: u8 = 3;
+const SPE: u32 = 1 << 6;
+const SSI: u32 = 1 << 8;
+const SSM: u32 = 1 << 9;
+
+// SUBGHZSPI_CR2 fields.
+/// DS = 0b0111: 8-bit data.
+const DS_8_BIT: u32 = 0b0111 << 8;
+const FRXTH: u32 = 1 << 12;
+
+// SUBGHZSPI_SR fields.
+const RXNE: u32 = 1 << 0;
+const TXE: u32 = 1 << 1;
+
+// PWR register offsets, and fields.
+const PWR_SR2: usize = 0x14;
+const RFBUSYS: u32 = 1 << 1;
+const PWR_SUBGHZSPICR: usize = 0x90;
+const NSS: u32 = 1 << 15;
+
+/// EXTI_C1IMR2: Line 44 is the radio IRQ.
+const EXTI_C1IMR2: usize = 0x90;
+const RADIO_IRQ_LINE: u32 = 1 << 12;
+
+// RCC fields.
+const SUBGHZSPIEN: u32 = 1 << 0;
+const RFRSTF: u32 = 1 << 14;
+const RFRST: u32 = 1 << 15;
+
+/// The highest SUBGHZSPI clock speed, in Hz.
+const MAX_SPI_SPEED: u32 = 16_000_000;
+
+/// The radio's crystal (or TCXO) frequency, in Hz. Frequencies are set in units of
+/// `XTAL_FREQ / 2^25`.
+const XTAL_FREQ: u64 = 32_000_000;
+
+// Radio register addresses.
+const REG_GFSK_SYNC_WORD: u16 = 0x06c0;
+const REG_LORA_SYNC_WORD: u16 = 0x0740;
+
+/// Radio command opcodes. RM0453, section 4.9: "Sub-GHz radio commands".
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    ClearDeviceErrors = 0x07,
+    ClearIrqStatus = 0x02,
+    GetDeviceErrors = 0x17,
+    GetIrqStatus = 0x12,
+    GetPacketStatus = 0x14,
+    GetPacketType = 0x11,
+    GetRssiInst = 0x15,
+    GetRxBufferStatus = 0x13,
+    GetStats = 0x10,
+    GetStatus = 0xc0,
+    Calibrate = 0x89,
+    CalibrateImage = 0x98,
+    ReadBuffer = 0x1e,
+    ReadRegister = 0x1d,
+    ResetStats = 0x00,
+    SetBufferBaseAddress = 0x8f,
+    SetCad = 0xc5,
+    SetDioIrqParams = 0x08,
+    SetFs = 0xc1,
+    SetLoRaSymbTimeout = 0xa0,
+    SetModulationParams = 0x8b,
+    SetPaConfig = 0x95,
+    SetPacketParams = 0x8c,
+    SetPacketType = 0x8a,
+    SetRegulatorMode = 0x96,
+    SetRfFrequency = 0x86,
+    SetRx = 0x82,
+    SetRxDutyCycle = 0x94,
+    SetRxTxFallbackMode = 0x93,
+    SetSleep = 0x84,
+    SetStandby = 0x80,
+    SetStopRxTimerOnPreamble = 0x9f,
+    SetTcxoMode = 0x97,
+    SetTx = 0x83,
+    SetTxContinuousWave = 0xd1,
+    SetTxContinuousPreamble = 0xd2,
+    SetTxParams = 0x8e,
+    WriteBuffer = 0x0e,
+    WriteRegister = 0x0d,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The radio didn't finish a command in time.
+    CmdTimeout,
+    /// The radio couldn't process a command, eg due to an invalid opcode, or parameter.
+    CmdProcessing,
+    /// The radio failed to execute a command.
+    CmdExecution,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u16)]
+/// Radio interrupt sources, as bits of the IRQ mask, and status.
+pub enum Irq {
+    TxDone = 1 << 0,
+    RxDone = 1 << 1,
+    PreambleDetected = 1 << 2,
+    SyncWordValid = 1 << 3,
+    HeaderValid = 1 << 4,
+    HeaderErr = 1 << 5,
+    CrcErr = 1 << 6,
+    CadDone = 1 << 7,
+    CadDetected = 1 << 8,
+    Timeout = 1 << 9,
+}
+
+impl Irq {
+    /// Check if this interrupt is set in a status returned by `irq_status()`.
+    pub fn is_set(self, status: u16) -> bool {
+        status & self as u16 != 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The state the RF switch should be in. Passed to the function registered with
+/// `set_rf_switch()`.
+pub enum RfState {
+    /// Not transmitting, or receiving.
+    Off,
+    Rx,
+    /// Transmitting, using the low-power PA. (RFO_LP)
+    TxLp,
+    /// Transmitting, using the high-power PA. (RFO_HP)
+    TxHp,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The radio's power supply. Sets using the `SetRegulatorMode` command.
+pub enum RegulatorMode {
+    Ldo = 0,
+    /// Use the SMPS; more efficient, but requires an inductor on `VFBSMPS`.
+    Smps = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The voltage supplied to the TCXO from the `VDDTCXO` pin.
+pub enum TcxoVoltage {
+    V1_6 = 0,
+    V1_7 = 1,
+    V1_8 = 2,
+    V2_2 = 3,
+    V2_4 = 4,
+    V2_7 = 5,
+    V3_0 = 6,
+    V3_3 = 7,
+}
+
+#[derive(Clone, Copy)]
+/// TCXO control, for boards where it's powered from the radio's `VDDTCXO` pin.
+pub struct TcxoCfg {
+    pub voltage: TcxoVoltage,
+    /// Time the TCXO takes to stabilize after powering up, in µs.
+    pub startup_us: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock used in standby mode.
+pub enum StandbyClk {
+    /// The 13MHz RC oscillator.
+    Rc = 0,
+    /// The HSE32 crystal, or TCXO. Faster to switch to TX, or RX from.
+    Hse = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Packet type. Sets using the `SetPacketType` command.
+pub enum PacketType {
+    Fsk = 0,
+    LoRa = 1,
+    Bpsk = 2,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The PA power ramp time.
+pub enum RampTime {
+    Us10 = 0,
+    Us20 = 1,
+    Us40 = 2,
+    Us80 = 3,
+    Us200 = 4,
+    Us800 = 5,
+    Us1700 = 6,
+    Us3400 = 7,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// PA configuration. Sets using the `SetPaConfig` command. Use one of the associated consts for
+/// the optimal settings at common output powers, from RM0453.
+pub struct PaConfig {
+    pub duty_cycle: u8,
+    pub hp_max: u8,
+    /// Use the high-power PA, instead of the low-power one.
+    pub high_power: bool,
+}
+
+impl PaConfig {
+    /// Low-power PA, +15dBm, with `set_tx_params()` power 14.
+    pub const LP_15: Self = Self {
+        duty_cycle: 0x06,
+        hp_max: 0x00,
+        high_power: false,
+    };
+    /// Low-power PA, +14dBm, with `set_tx_params()` power 14.
+    pub const LP_14: Self = Self {
+        duty_cycle: 0x04,
+        hp_max: 0x00,
+        high_power: false,
+    };
+    /// Low-power PA, +10dBm, with `set_tx_params()` power 13.
+    pub const LP_10: Self = Self {
+        duty_cycle: 0x01,
+        hp_max: 0x00,
+        high_power: false,
+    };
+    /// High-power PA, +22dBm, with `set_tx_params()` power 22.
+    pub const HP_22: Self = Self {
+        duty_cycle: 0x04,
+        hp_max: 0x07,
+        high_power: true,
+    };
+    /// High-power PA, +20dBm, with `set_tx_params()` power 22.
+    pub const HP_20: Self = Self {
+        duty_cycle: 0x03,
+        hp_max: 0x05,
+        high_power: true,
+    };
+    /// High-power PA, +17dBm, with `set_tx_params()` power 22.
+    pub const HP_17: Self = Self {
+        duty_cycle: 0x02,
+        hp_max: 0x03,
+        high_power: true,
+    };
+    /// High-power PA, +14dBm, with `set_tx_params()` power 22.
+    pub const HP_14: Self = Self {
+        duty_cycle: 0x02,
+        hp_max: 0x02,
+        high_power: true,
+    };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LoRa bandwidth, in kHz.
+pub enum LoRaBandwidth {
+    Bw7 = 0x00,
+    Bw10 = 0x08,
+    Bw15 = 0x01,
+    Bw20 = 0x09,
+    Bw31 = 0x02,
+    Bw41 = 0x0a,
+    Bw62 = 0x03,
+    Bw125 = 0x04,
+    Bw250 = 0x05,
+    Bw500 = 0x06,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LoRa forward error correction coding rate.
+pub enum LoRaCodingRate {
+    Cr4_5 = 1,
+    Cr4_6 = 2,
+    Cr4_7 = 3,
+    Cr4_8 = 4,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The LoRa sync word. Public networks, such as LoRaWAN, use `Public`.
+pub enum LoRaSyncWord {
+    Public,
+    Private,
+}
+
+#[derive(Clone, Copy)]
+/// LoRa modulation parameters. Sets using the `SetModulationParams` command.
+pub struct LoRaModParams {
+    /// Spreading factor, from 5 to 12.
+    pub sf: u8,
+    pub bw: LoRaBandwidth,
+    pub cr: LoRaCodingRate,
+    /// Low data rate optimization. Enable this when the symbol time is 16.38ms, or more; eg
+    /// SF11, and SF12 at 125kHz.
+    pub ldro: bool,
+}
+
+#[derive(Clone, Copy)]
+/// LoRa packet parameters. Sets using the `SetPacketParams` command. `transmit()` overrides the
+/// payload length.
+pub struct LoRaPacketParams {
+    /// Preamble length, in symbols.
+    pub preamble_len: u16,
+    /// Use a fixed-length packet, with no header. (Implicit header mode)
+    pub implicit_header: bool,
+    pub payload_len: u8,
+    pub crc: bool,
+    /// Invert the IQ signals. LoRaWAN uses this for downlinks.
+    pub invert_iq: bool,
+}
+
+impl Default for LoRaPacketParams {
+    fn default() -> Self {
+        Self {
+            preamble_len: 8,
+            implicit_header: false,
+            payload_len: 0xff,
+            crc: true,
+            invert_iq: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// GFSK Gaussian pulse shape filter.
+pub enum FskPulseShape {
+    None = 0x00,
+    Bt0_3 = 0x08,
+    Bt0_5 = 0x09,
+    Bt0_7 = 0x0a,
+    Bt1_0 = 0x0b,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// GFSK RX bandwidth, in kHz.
+pub enum FskBandwidth {
+    Bw4_8 = 0x1f,
+    Bw9_7 = 0x1e,
+    Bw19_5 = 0x1d,
+    Bw39 = 0x1c,
+    Bw58_6 = 0x0c,
+    Bw78_2 = 0x1b,
+    Bw117_3 = 0x0b,
+    Bw156_2 = 0x1a,
+    Bw234_3 = 0x0a,
+    Bw312 = 0x19,
+    Bw467 = 0x09,
+}
+
+#[derive(Clone, Copy)]
+/// (G)FSK modulation parameters. Sets using the `SetModulationParams` command.
+pub struct FskModParams {
+    /// Bit rate, in bits per second.
+    pub bitrate: u32,
+    pub pulse_shape: FskPulseShape,
+    pub bandwidth: FskBandwidth,
+    /// Frequency deviation, in Hz.
+    pub fdev: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// GFSK CRC type.
+pub enum FskCrc {
+    Off = 0x01,
+    Byte1 = 0x00,
+    Byte2 = 0x02,
+    Byte1Inverted = 0x04,
+    Byte2Inverted = 0x06,
+}
+
+#[derive(Clone, Copy)]
+/// (G)FSK packet parameters. Sets using the `SetPacketParams` command. `transmit()` overrides
+/// the payload length.
+pub struct FskPacketParams {
+    /// Preamble length, in bits.
+    pub preamble_len: u16,
+    /// Preamble detector length, in bits: 0 (off), 8, 16, 24, or 32.
+    pub preamble_detect_len: u8,
+    /// Sync word length, in bits; up to 64. Set the sync word with `set_fsk_sync_word()`.
+    pub sync_word_len: u8,
+    /// Variable-length packets, with the length in the first byte.
+    pub variable_len: bool,
+    pub payload_len: u8,
+    pub crc: FskCrc,
+    pub whitening: bool,
+}
+
+impl Default for FskPacketParams {
+    fn default() -> Self {
+        Self {
+            preamble_len: 32,
+            preamble_detect_len: 16,
+            sync_word_len: 32,
+            variable_len: true,
+            payload_len: 0xff,
+            crc: FskCrc::Byte2Inverted,
+            whitening: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// The status of the most recent LoRa packet received.
+pub struct LoRaPacketStatus {
+    /// Average RSSI over the packet, in dBm.
+    pub rssi: i16,
+    /// SNR, in dB.
+    pub snr: i8,
+    /// Estimated RSSI of the LoRa signal, after despreading, in dBm.
+    pub signal_rssi: i16,
+}
+
+#[derive(Clone, Copy)]
+/// Sub-GHz radio configuration, applied in `new()`.
+pub struct SubGhzConfig {
+    pub regulator: RegulatorMode,
+    /// Enable the TCXO supply, if the board has a TCXO powered from `VDDTCXO`.
+    pub tcxo: Option<TcxoCfg>,
+}
+
+impl Default for SubGhzConfig {
+    fn default() -> Self {
+        Self {
+            regulator: RegulatorMode::Ldo,
+            tcxo: None,
+        }
+    }
+}
+
+/// Represents the sub-GHz radio, and its SPI interface.
+pub struct SubGhz {
+    pub cfg: SubGhzConfig,
+    rf_switch: Option<fn(RfState)>,
+    /// Whether the high-power PA is selected, so `transmit()` sets the switch accordingly.
+    high_power: bool,
+    /// The most recent `SetPacketParams` parameters, so we can update the payload length.
+    packet_params: [u8; 9],
+    /// The index of the payload length in `packet_params`.
+    payload_len_i: usize,
+    /// Whether the radio is in Sleep mode, so it needs waking before the next command.
+    sleeping: bool,
+}
+
+impl SubGhz {
+    /// Enable the SUBGHZSPI clock, reset the radio, and configure it. Leaves the radio in standby
+    /// mode, using the RC oscillator.
+    pub fn new(cfg: SubGhzConfig, clocks: &Clocks) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+
+            // RCC_APB3ENR, and RCC_APB3RSTR, SUBGHZSPIEN.
+            rcc.apb3enr
+                .modify(|r, w| unsafe { w.bits(r.bits() | SUBGHZSPIEN) });
+            rcc.apb3rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() | SUBGHZSPIEN) });
+            rcc.apb3rstr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !SUBGHZSPIEN) });
+
+            // Reset the radio. (RCC_CSR register, RFRST field)
+            rcc.csr.modify(|r, w| unsafe { w.bits(r.bits() | RFRST) });
+            rcc.csr.modify(|r, w| unsafe { w.bits(r.bits() & !RFRST) });
+            while rcc.csr.read().bits() & RFRSTF != 0 {}
+        });
+
+        // PCLK3 is HCLK3. Use the fastest SPI clock the radio allows.
+        let pclk3 = clocks.sysclk() / clocks.hclk3_prescaler.value() as u32;
+        let mut br = 0;
+        while pclk3 / (2 << br) > MAX_SPI_SPEED && br < 7 {
+            br += 1;
+        }
+
+        write_reg(SPI_BASE, SPI_CR2, DS_8_BIT | FRXTH);
+        write_reg(
+            SPI_BASE,
+            SPI_CR1,
+            MSTR | (br as u32) << BR_SHIFT | SSI | SSM | SPE,
+        );
+
+        let mut result = Self {
+            cfg,
+            rf_switch: None,
+            high_power: false,
+            packet_params: [0; 9],
+            payload_len_i: 0,
+            sleeping: false,
+        };
+
+        result.set_standby(StandbyClk::Rc);
+        result.write_command(OpCode::SetRegulatorMode, &[cfg.regulator as u8]);
+
+        if let Some(tcxo) = cfg.tcxo {
+            // The startup time is in units of 15.625µs.
+            let delay = (tcxo.startup_us * 64 / 1_000).to_be_bytes();
+            result.write_command(
+                OpCode::SetTcxoMode,
+                &[tcxo.voltage as u8, delay[1], delay[2], delay[3]],
+            );
+            // Calibrate all blocks, now that the radio's clock is available. This flags an
+            // HSE32 start error from before the TCXO was powered, which we clear.
+            result.write_command(OpCode::Calibrate, &[0x7f]);
+            result.wait_busy();
+            result.clear_device_errors();
+        }
+
+        result.write_command(OpCode::SetBufferBaseAddress, &[0, 0]);
+
+        result
+    }
+
+    /// Register a function that sets the board's RF switch. It's called before each TX, and
+    /// RX, and when entering standby, or sleep.
+    pub fn set_rf_switch(&mut self, f: fn(RfState)) {
+        self.rf_switch = Some(f);
+    }
+
+    fn rf_switch(&self, state: RfState) {
+        if let Some(f) = self.rf_switch {
+            f(state);
+        }
+    }
+
+    /// Check if the radio is busy, and can't accept commands. (PWR_SR2 register, RFBUSYS field)
+    pub fn busy(&self) -> bool {
+        read_reg(PWR_BASE, PWR_SR2) & RFBUSYS != 0
+    }
+
+    fn wait_busy(&self) {
+        while self.busy() {}
+    }
+
+    /// Pull the radio's NSS low, to select it. (PWR_SUBGHZSPICR register, NSS field)
+    fn select(&mut self) {
+        if self.sleeping {
+            // Selecting the radio wakes it; it's busy until it's ready.
+            write_reg(PWR_BASE, PWR_SUBGHZSPICR, 0);
+            cortex_m::asm::delay(1_000);
+            write_reg(PWR_BASE, PWR_SUBGHZSPICR, NSS);
+            self.sleeping = false;
+        }
+        self.wait_busy();
+        write_reg(PWR_BASE, PWR_SUBGHZSPICR, 0);
+    }
+
+    fn deselect(&mut self) {
+        write_reg(PWR_BASE, PWR_SUBGHZSPICR, NSS);
+    }
+
+    /// Exchange a byte over the SUBGHZSPI.
+    fn transfer(&mut self, byte: u8) -> u8 {
+        while read_reg(SPI_BASE, SPI_SR) & TXE == 0 {}
+        // 8-bit access, so the data packing doesn't send 2 bytes.
+        unsafe { write_volatile((SPI_BASE + SPI_DR) as *mut u8, byte) };
+
+        while read_reg(SPI_BASE, SPI_SR) & RXNE == 0 {}
+        unsafe { read_volatile((SPI_BASE + SPI_DR) as *const u8) }
+    }
+
+    /// Send a command, with its parameters.
+    pub fn write_command(&mut self, opcode: OpCode, params: &[u8]) {
+        self.select();
+        self.transfer(opcode as u8);
+        for param in params {
+            self.transfer(*param);
+        }
+        self.deselect();
+    }
+
+    /// Send a command that returns data, and read it into `buf`. Returns the status byte the
+    /// radio sends before the data.
+    pub fn read_command(&mut self, opcode: OpCode, buf: &mut [u8]) -> Result<u8, Error> {
+        self.select();
+        self.transfer(opcode as u8);
+        let status = self.transfer(0);
+        for val in buf.iter_mut() {
+            *val = self.transfer(0);
+        }
+        self.deselect();
+
+        check_status(status)?;
+        Ok(status)
+    }
+
+    /// Write to the radio's registers, starting at `addr`.
+    pub fn write_register(&mut self, addr: u16, data: &[u8]) {
+        self.select();
+        self.transfer(OpCode::WriteRegister as u8);
+        for byte in addr.to_be_bytes().iter().chain(data) {
+            self.transfer(*byte);
+        }
+        self.deselect();
+    }
+
+    /// Read the radio's registers, starting at `addr`.
+    pub fn read_register(&mut self, addr: u16, buf: &mut [u8]) {
+        self.select();
+        self.transfer(OpCode::ReadRegister as u8);
+        for byte in addr.to_be_bytes() {
+            self.transfer(byte);
+        }
+        self.transfer(0); // Status
+        for val in buf.iter_mut() {
+            *val = self.transfer(0);
+        }
+        self.deselect();
+    }
+
+    /// Write to the radio's 256-byte data buffer, starting at `offset`.
+    pub fn write_buffer(&mut self, offset: u8, data: &[u8]) {
+        self.select();
+        self.transfer(OpCode::WriteBuffer as u8);
+        self.transfer(offset);
+        for byte in data {
+            self.transfer(*byte);
+        }
+        self.deselect();
+    }
+
+    /// Read from the radio's 256-byte data buffer, starting at `offset`.
+    pub fn read_buffer(&mut self, offset: u8, buf: &mut [u8]) {
+        self.select();
+        self.transfer(OpCode::ReadBuffer as u8);
+        self.transfer(offset);
+        self.transfer(0); // Status
+        for val in buf.iter_mut() {
+            *val = self.transfer(0);
+        }
+        self.deselect();
+    }
+
+    /// Get the radio's status byte; the chip mode is in bits 6:4. Returns an error if the most
+    /// recent command failed.
+    pub fn status(&mut self) -> Result<u8, Error> {
+        self.read_command(OpCode::GetStatus, &mut [])
+    }
+
+    /// Get the radio's error flags, such as calibration, and oscillator start errors.
+    pub fn device_errors(&mut self) -> Result<u16, Error> {
+        let mut buf = [0; 2];
+        self.read_command(OpCode::GetDeviceErrors, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub fn clear_device_errors(&mut self) {
+        self.write_command(OpCode::ClearDeviceErrors, &[0, 0]);
+    }
+
+    /// Enter standby mode, using the selected clock.
+    pub fn set_standby(&mut self, clock: StandbyClk) {
+        self.write_command(OpCode::SetStandby, &[clock as u8]);
+        self.rf_switch(RfState::Off);
+    }
+
+    /// Enter sleep mode. If `warm` is true, the configuration is retained; otherwise, it's reset
+    /// on wakeup. The radio wakes on the next command.
+    pub fn set_sleep(&mut self, warm: bool) {
+        self.rf_switch(RfState::Off);
+        self.write_command(OpCode::SetSleep, &[if warm { 0b100 } else { 0 }]);
+        self.sleeping = true;
+    }
+
+    /// Set the RF frequency, in Hz. Also calibrates the image rejection for the band, which is
+    /// required after changing bands.
+    pub fn set_rf_frequency(&mut self, freq: u32) {
+        // RM0453, section 4.9.4: Image calibration frequency ranges.
+        let cal = match freq {
+            0..=440_000_000 => [0x6b, 0x6f],
+            440_000_001..=510_000_000 => [0x75, 0x81],
+            510_000_001..=787_000_000 => [0xc1, 0xc5],
+            787_000_001..=870_000_000 => [0xd7, 0xdb],
+            _ => [0xe1, 0xe9],
+        };
+        self.write_command(OpCode::CalibrateImage, &cal);
+
+        let val = ((freq as u64) << 25) / XTAL_FREQ;
+        self.write_command(OpCode::SetRfFrequency, &(val as u32).to_be_bytes());
+    }
+
+    pub fn set_packet_type(&mut self, packet_type: PacketType) {
+        self.write_command(OpCode::SetPacketType, &[packet_type as u8]);
+    }
+
+    pub fn set_lora_modulation(&mut self, params: &LoRaModParams) {
+        assert!(
+            (5..=12).contains(&params.sf),
+            "The LoRa spreading factor must be between 5 and 12."
+        );
+        self.write_command(
+            OpCode::SetModulationParams,
+            &[
+                params.sf,
+                params.bw as u8,
+                params.cr as u8,
+                params.ldro as u8,
+            ],
+        );
+    }
+
+    pub fn set_lora_packet(&mut self, params: &LoRaPacketParams) {
+        let preamble = params.preamble_len.to_be_bytes();
+        self.packet_params = [
+            preamble[0],
+            preamble[1],
+            params.implicit_header as u8,
+            params.payload_len,
+            params.crc as u8,
+            params.invert_iq as u8,
+            0,
+            0,
+            0,
+        ];
+        self.payload_len_i = 3;
+        self.write_packet_params();
+    }
+
+    pub fn set_lora_sync_word(&mut self, sync_word: LoRaSyncWord) {
+        let val: [u8; 2] = match sync_word {
+            LoRaSyncWord::Public => [0x34, 0x44],
+            LoRaSyncWord::Private => [0x14, 0x24],
+        };
+        self.write_register(REG_LORA_SYNC_WORD, &val);
+    }
+
+    pub fn set_fsk_modulation(&mut self, params: &FskModParams) {
+        let br = (32 * XTAL_FREQ / params.bitrate as u64) as u32;
+        let fdev = (((params.fdev as u64) << 25) / XTAL_FREQ) as u32;
+        let br = br.to_be_bytes();
+        let fdev = fdev.to_be_bytes();
+
+        self.write_command(
+            OpCode::SetModulationParams,
+            &[
+                br[1],
+                br[2],
+                br[3],
+                params.pulse_shape as u8,
+                params.bandwidth as u8,
+                fdev[1],
+                fdev[2],
+                fdev[3],
+            ],
+        );
+    }
+
+    pub fn set_fsk_packet(&mut self, params: &FskPacketParams) {
+        let preamble_detect = match params.preamble_detect_len {
+            0 => 0,
+            8 => 4,
+            16 => 5,
+            24 => 6,
+            32 => 7,
+            _ => panic!("The preamble detector length must be 0, 8, 16, 24, or 32 bits."),
+        };
+        let preamble = params.preamble_len.to_be_bytes();
+        self.packet_params = [
+            preamble[0],
+            preamble[1],
+            preamble_detect,
+            params.sync_word_len,
+            0, // No address filtering
+            params.variable_len as u8,
+            params.payload_len,
+            params.crc as u8,
+            params.whitening as u8,
+        ];
+        self.payload_len_i = 6;
+        self.write_packet_params();
+    }
+
+    /// Set the (G)FSK sync word; up to 8 bytes.
+    pub fn set_fsk_sync_word(&mut self, sync_word: &[u8]) {
+        assert!(
+            sync_word.len() <= 8,
+            "The sync word must be 8 bytes, or less."
+        );
+        self.write_register(REG_GFSK_SYNC_WORD, sync_word);
+    }
+
+    fn write_packet_params(&mut self) {
+        let params = self.packet_params;
+        self.write_command(OpCode::SetPacketParams, &params);
+    }
+
+    /// Configure the PA. Call `set_tx_params()` afterwards to set the power.
+    pub fn set_pa_config(&mut self, cfg: PaConfig) {
+        self.high_power = cfg.high_power;
+        self.write_command(
+            OpCode::SetPaConfig,
+            &[cfg.duty_cycle, cfg.hp_max, !cfg.high_power as u8, 0x01],
+        );
+    }
+
+    /// Set the TX power, in dBm, and PA ramp time. The power is from -17 to 14 with the
+    /// low-power PA, and -9 to 22 with the high-power one.
+    pub fn set_tx_params(&mut self, power: i8, ramp_time: RampTime) {
+        self.write_command(OpCode::SetTxParams, &[power as u8, ramp_time as u8]);
+    }
+
+    /// Select which interrupts are enabled, and routed to the CPU. `mask` is a combination of
+    /// `Irq` bits.
+    pub fn set_irq_cfg(&mut self, mask: u16) {
+        let mask = mask.to_be_bytes();
+        self.write_command(
+            OpCode::SetDioIrqParams,
+            &[mask[0], mask[1], mask[0], mask[1], 0, 0, 0, 0],
+        );
+    }
+
+    /// Read the interrupt status; check it with `Irq::is_set()`.
+    pub fn irq_status(&mut self) -> Result<u16, Error> {
+        let mut buf = [0; 2];
+        self.read_command(OpCode::GetIrqStatus, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    /// Clear interrupts. `mask` is a combination of `Irq` bits.
+    pub fn clear_irq(&mut self, mask: u16) {
+        self.write_command(OpCode::ClearIrqStatus, &mask.to_be_bytes());
+    }
+
+    /// Enable the radio's IRQ line on EXTI line 44, so it can trigger the `SUBGHZ_RADIO`
+    /// interrupt.
+    pub fn enable_interrupt(&mut self) {
+        free(|_| {
+            let val = read_reg(EXTI_BASE, EXTI_C1IMR2);
+            write_reg(EXTI_BASE, EXTI_C1IMR2, val | RADIO_IRQ_LINE);
+        });
+    }
+
+    /// Transmit a packet; up to 255 bytes. `timeout_us` is the maximum time to stay in TX
+    /// mode, in µs, or 0 for no timeout. Non-blocking: Wait for `Irq::TxDone`.
+    pub fn transmit(&mut self, data: &[u8], timeout_us: u32) {
+        assert!(data.len() <= 255, "The packet must be 255 bytes, or less.");
+
+        self.write_buffer(0, data);
+
+        self.packet_params[self.payload_len_i] = data.len() as u8;
+        self.write_packet_params();
+
+        self.rf_switch(if self.high_power {
+            RfState::TxHp
+        } else {
+            RfState::TxLp
+        });
+        self.write_command(OpCode::SetTx, &timeout_bytes(timeout_us));
+    }
+
+    /// Start receiving. `timeout_us` is the maximum time to wait for a packet, in µs; 0 for a
+    /// single packet, with no timeout, or `u32::MAX` to receive continuously. Non-blocking:
+    /// Wait for `Irq::RxDone`, then read the packet with `read_packet()`.
+    pub fn start_rx(&mut self, timeout_us: u32) {
+        let timeout = if timeout_us == u32::MAX {
+            [0xff; 3]
+        } else {
+            timeout_bytes(timeout_us)
+        };
+
+        self.rf_switch(RfState::Rx);
+        self.write_command(OpCode::SetRx, &timeout);
+    }
+
+    /// Read the most recently received packet into `buf`. Returns its length.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut status = [0; 2];
+        self.read_command(OpCode::GetRxBufferStatus, &mut status)?;
+
+        let len = (status[0] as usize).min(buf.len());
+        self.read_buffer(status[1], &mut buf[..len]);
+
+        Ok(len)
+    }
+
+    /// Read the most recent LoRa packet's signal strength, and SNR.
+    pub fn lora_packet_status(&mut self) -> Result<LoRaPacketStatus, Error> {
+        let mut buf = [0; 3];
+        self.read_command(OpCode::GetPacketStatus, &mut buf)?;
+
+        Ok(LoRaPacketStatus {
+            rssi: -(buf[0] as i16) / 2,
+            snr: buf[1] as i8 / 4,
+            signal_rssi: -(buf[2] as i16) / 2,
+        })
+    }
+
+    /// Read the instantaneous RSSI, in dBm, while receiving.
+    pub fn rssi(&mut self) -> Result<i16, Error> {
+        let mut buf = [0];
+        self.read_command(OpCode::GetRssiInst, &mut buf)?;
+        Ok(-(buf[0] as i16) / 2)
+    }
+}
+
+/// Convert a timeout in µs to the radio's 24-bit format, in units of 15.625µs.
+fn timeout_bytes(timeout_us: u32) -> [u8; 3] {
+    let val = (timeout_us as u64 * 64 / 1_000).min(0xff_fffe) as u32;
+    let val = val.to_be_bytes();
+    [val[1], val[2], val[3]]
+}
+
+/// Check the command status field of a status byte, in bits 3:1.
+fn check_status(status: u8) -> Result<(), Error> {
+    match (status >> 1) & 0b111 {
+        3 => Err(Error::CmdTimeout),
+        4 => Err(Error::CmdProcessing),
+        5 => Err(Error::CmdExecution),
+        _ => Ok(()),
+    }
+}
+
+fn read_reg(base: usize, offset: usize) -> u32 {
+    unsafe { read_volatile((base + offset) as *const u32) }
+}
+
+fn write_reg(base: usize, offset: usize, value: u32) {
+    unsafe { write_volatile((base + offset) as *mut u32, value) }
+}
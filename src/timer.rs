@@ -1,7 +1,7 @@
 //! Provides support for timers. Includes initialization, interrupts,
 //! and PWM features.
 //!
-//! Low-power timers (LPTIM) are not yet supported.
+//! Low-power timers (LPTIM) are supported in the `lptim` module.
 
 // todo: WB and WL should support pwm features
 
@@ -31,10 +31,10 @@ cfg_if! {
 
 use num_traits::float::FloatCore; // To round floats.
 
-// todo: LPTIM (low-power timers) and HRTIM (high-resolution timers). And Advanced control functionality
+// todo: Advanced control functionality
 
 use crate::{
-    clocks::Clocks,
+    clocks::{ClockListener, Clocks, SpeedError},
     instant::Instant,
     pac::{self, RCC},
     util::{rcc_en_reset, RccPeriph},
@@ -43,6 +43,24 @@ use crate::{
 #[cfg(not(any(feature = "f4", feature = "l552")))]
 use crate::dma::{self, ChannelCfg, DmaChannel};
 
+#[cfg(not(any(
+    feature = "g0",
+    feature = "f4",
+    feature = "l552",
+    feature = "f3",
+    feature = "l4"
+)))]
+use crate::gpio::{self, Edge, Port};
+
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4x5",
+    feature = "l5",
+    feature = "g0"
+)))]
+use crate::gpio::Pin;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::DmaInput;
 
@@ -51,8 +69,6 @@ use crate::pac::DMA as DMA1;
 #[cfg(not(feature = "g0"))]
 use crate::pac::DMA1;
 
-// todo: Low power timer enabling etc. eg on L4, RCC_APB1ENR1.LPTIM1EN
-
 #[derive(Clone, Copy, Debug)]
 /// Used for when attempting to set a timer period that is out of range.
 pub struct ValueError {}
@@ -390,6 +406,58 @@ pub struct Timer<TIM> {
     pub ns_per_tick: f32,
 }
 
+#[cfg(feature = "embedded-hal-1")]
+/// A single timer channel, which implements the embedded-hal 1.0 `SetDutyCycle` trait. Create it
+/// with `Timer::pwm_channel()`, after configuring the channel with `enable_pwm_output()`.
+pub struct PwmChannel<'a, TIM> {
+    timer: &'a mut Timer<TIM>,
+    channel: TimChannel,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<TIM> Timer<TIM> {
+    /// Borrow a channel, for use with the embedded-hal 1.0 `SetDutyCycle` trait.
+    pub fn pwm_channel(&mut self, channel: TimChannel) -> PwmChannel<'_, TIM> {
+        PwmChannel {
+            timer: self,
+            channel,
+        }
+    }
+}
+
+/// TIMx_SMCR bits cleared when setting up a `DebouncedInput`: TS (including TS[4:3] on newer
+/// families) and SMS (including SMS[3]).
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4x5",
+    feature = "l5",
+    feature = "g0"
+)))]
+const SMCR_SLAVE_MASK: u32 = 0b111 | (0b111 << 4) | (1 << 16) | (0b11 << 20);
+
+/// A digital input debounced in hardware, using a timer's channel 1 input. Each edge on the input
+/// resets the timer's counter; its update event only fires once the input has been stable for the
+/// configured duration, at which point the new state is latched. Short glitches are additionally
+/// rejected by the channel's digital input filter. This avoids handling an interrupt for every
+/// bounce, and managing a separate debounce timer.
+///
+/// The pin must be configured in alternate function mode, mapped to the timer's channel 1. Call
+/// `update()` in the timer's interrupt handler.
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "l4x5",
+    feature = "l5",
+    feature = "g0"
+)))]
+pub struct DebouncedInput<TIM> {
+    pub timer: Timer<TIM>,
+    pub pin: Pin,
+    /// The latched, debounced state.
+    state: bool,
+}
+
 macro_rules! make_timer {
     ($TIMX:ident, $tim:ident, $apb:expr, $res:ident) => {
         impl Timer<pac::$TIMX> {
@@ -611,6 +679,21 @@ macro_rules! make_timer {
                 self.clear_interrupt(TimerInterrupt::Update);
             }
 
+            /// Select which events generate an update interrupt or DMA request. Setting this to
+            /// `OverUnderFlow` prevents `reinitialize()`, or a slave mode controller reset, from
+            /// triggering an update interrupt. Sets `TIMx_CR1` register, `URS` field.
+            pub fn set_update_request_source(&mut self, src: UpdateReqSrc) {
+                self.regs.cr1.modify(|_, w| w.urs().bit(src as u8 != 0));
+                self.cfg.update_request_source = src;
+            }
+
+            /// Disable, or re-enable update event generation. While disabled, no update interrupts
+            /// occur, and the shadow registers (eg ARR, PSC, CCRx) keep their values; the counter and
+            /// prescaler are still reinitialized on overflow. Sets `TIMx_CR1` register, `UDIS` field.
+            pub fn set_update_disable(&mut self, disable: bool) {
+                self.regs.cr1.modify(|_, w| w.udis().bit(disable));
+            }
+
             /// Read the current counter value.
             pub fn read_count(&self) -> u32 {
                 // todo: This depends on resolution. We read the whole
@@ -834,6 +917,107 @@ macro_rules! make_timer {
                 }
             }
 
+            /// Capture a parallel bus, by reading a GPIO port's IDR register into `buf` using DMA, on
+            /// each edge of a clock signal on the timer's channel 1 input; eg a camera's pixel clock, or
+            /// a parallel ADC's data-ready output. This is a software alternative to DCMI, suitable for slow
+            /// buses. Connect the data lines to pins 0 - 7 of the port, for `DataSize::S8`, or 0 - 15, for
+            /// `DataSize::S16`. `buf`'s length must be a multiple of the data size.
+            ///
+            /// The channel 1 pin must be configured in alternate function mode. The DMA request is
+            /// the timer's channel 1 request: Select it with `dma::mux()` as required. DMA must keep up
+            /// with the clock; this works best with a high AHB speed, and no competing DMA transfers. This
+            /// leaves the timer enabled; disable it, or stop the DMA channel to end the capture.
+            ///
+            /// # Safety
+            /// `buf` must stay valid, and not be otherwise accessed, until the capture is complete, or
+            /// with a circular channel config, until the DMA channel is stopped.
+            #[cfg(not(any(feature = "g0", feature = "f4", feature = "l552", feature = "f3", feature = "l4")))]
+            #[allow(clippy::too_many_arguments)]
+            pub unsafe fn read_gpio_dma(
+                &mut self,
+                buf: &mut [u8],
+                port: Port,
+                edge: Edge,
+                data_size: dma::DataSize,
+                dma_channel: DmaChannel,
+                channel_cfg: ChannelCfg,
+                dma_periph: dma::DmaPeriph,
+            ) {
+                let bytes_per_word = match data_size {
+                    dma::DataSize::S8 => 1,
+                    dma::DataSize::S16 => 2,
+                    dma::DataSize::S32 => 4,
+                };
+                assert!(buf.len() % bytes_per_word == 0);
+
+                let (ptr, len) = (buf.as_mut_ptr(), buf.len() / bytes_per_word);
+
+                let periph_addr = &(*gpio::regs(port)).idr as *const _ as u32;
+
+                #[cfg(feature = "h7")]
+                let num_data = len as u32;
+                #[cfg(not(feature = "h7"))]
+                let num_data = len as u16;
+
+                self.disable();
+
+                // We use raw bits here, since channel 1 field names vary between timers, and PACs.
+                // Set CC1 as an input, mapped to TI1 (CC1S = 0b01), with a short input filter
+                // (IC1F = 0b0011), to reject glitches on the clock line. IC1PSC = 0: Capture each edge.
+                self.regs.ccmr1_input().modify(|r, w| w.bits((r.bits() & !0xff) | 0b0011_0001));
+
+                // Select the edge: CC1P is bit 1, and CC1NP is bit 3. Enable capture: CC1E is bit 0.
+                let edge_bits = match edge {
+                    Edge::Rising => 0,
+                    Edge::Falling => 1 << 1,
+                    Edge::Either => (1 << 1) | (1 << 3),
+                };
+                self.regs.ccer.modify(|r, w| w.bits((r.bits() & !0b1011) | edge_bits | 1));
+
+                // Generate a DMA request on each capture. CC1DE is bit 9.
+                self.regs.dier.modify(|r, w| w.bits(r.bits() | (1 << 9)));
+
+                match dma_periph {
+                    dma::DmaPeriph::Dma1 => {
+                        let mut regs = unsafe { &(*DMA1::ptr()) };
+                        dma::cfg_channel(
+                            &mut regs,
+                            dma_channel,
+                            periph_addr,
+                            ptr as u32,
+                            num_data,
+                            dma::Direction::ReadFromPeriph,
+                            data_size,
+                            data_size,
+                            channel_cfg,
+                        );
+                    }
+                    #[cfg(not(feature = "g0"))]
+                    dma::DmaPeriph::Dma2 => {
+                        // On WB, DMA2's register block is a separate type with DMA1's channel
+                        // layout.
+                        #[cfg(feature = "wb")]
+                        let mut regs =
+                            unsafe { &*(pac::DMA2::ptr() as *const pac::dma1::RegisterBlock) };
+                        #[cfg(not(feature = "wb"))]
+                        let mut regs = unsafe { &(*pac::DMA2::ptr()) };
+                        dma::cfg_channel(
+                            &mut regs,
+                            dma_channel,
+                            periph_addr,
+                            ptr as u32,
+                            num_data,
+                            dma::Direction::ReadFromPeriph,
+                            data_size,
+                            data_size,
+                            channel_cfg,
+                        );
+                    }
+                }
+
+                self.enable();
+            }
+
             /// Get the time elapsed since the start of the timer.
             /// Used by `Monotonic` if enabled using the `monotonic` feature, but usable
             /// on its own.
@@ -855,6 +1039,60 @@ macro_rules! make_timer {
             }
         }
 
+        impl ClockListener for Timer<pac::$TIMX> {
+            /// Update the stored timer clock speed, and recalculate the prescaler and auto-reload
+            /// values to keep the current frequency. The enabled channels' compare values are
+            /// scaled with the auto-reload value, so PWM duty cycles are kept too. Returns an
+            /// error, leaving the timer's settings unchanged, if the frequency is out of range at
+            /// the new clock speed.
+            fn clocks_changed(&mut self, clocks: &Clocks) -> Result<(), SpeedError> {
+                let psc = self.regs.psc.read().bits() as f32;
+                let arr_old = self.regs.arr.read().bits();
+
+                // `set_freq()` doubles the frequency for center-aligned modes; undo that here.
+                let mut freq = self.clock_speed as f32 / ((psc + 1.) * (arr_old as f32 + 1.));
+                match self.cfg.alignment {
+                    Alignment::Edge => (),
+                    _ => freq /= 2.,
+                }
+
+                let prev_clock_speed = self.clock_speed;
+                self.clock_speed = match $apb {
+                    1 => clocks.apb1_timer(),
+                    _ => clocks.apb2_timer(),
+                };
+
+                if self.set_freq(freq).is_err() {
+                    self.clock_speed = prev_clock_speed;
+                    return Err(SpeedError::new(
+                        "The timer frequency is out of range at the new clock speed.",
+                    ));
+                }
+
+                let arr_new = self.regs.arr.read().bits();
+
+                // We use raw pointers, since CCR register names vary between timers, and PACs. The
+                // CCxE bits in CCER (offset 0x20) read as 0 for channels a timer doesn't have.
+                // CCR1 - 4 are at offsets 0x34 - 0x40.
+                let base = pac::$TIMX::ptr() as usize;
+                let ccer = unsafe { core::ptr::read_volatile((base + 0x20) as *const u32) };
+
+                for ch in 0..4 {
+                    if ccer & (1 << (ch * 4)) == 0 {
+                        continue;
+                    }
+                    let ccr = (base + 0x34 + ch * 4) as *mut u32;
+                    unsafe {
+                        let scaled = core::ptr::read_volatile(ccr) as u64 * (arr_new as u64 + 1)
+                            / (arr_old as u64 + 1);
+                        core::ptr::write_volatile(ccr, scaled as u32);
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
         #[cfg(feature = "monotonic")]
         impl Monotonic for Timer<pac::$TIMX> {
             type Instant = Instant;
@@ -960,6 +1198,31 @@ macro_rules! make_timer {
             }
         }
 
+        #[cfg(feature = "embedded-hal-1")]
+        impl embedded_hal_1::pwm::ErrorType for PwmChannel<'_, pac::$TIMX> {
+            type Error = core::convert::Infallible;
+        }
+
+        #[cfg(feature = "embedded-hal-1")]
+        impl embedded_hal_1::pwm::SetDutyCycle for PwmChannel<'_, pac::$TIMX> {
+            /// For 32-bit timers with ARR above `u16::MAX`, duty cycles are scaled to ARR.
+            fn max_duty_cycle(&self) -> u16 {
+                self.timer.get_max_duty().min(u16::MAX as $res) as u16
+            }
+
+            fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+                let max = self.timer.get_max_duty();
+                let duty = if max > u16::MAX as $res {
+                    (duty as u64 * max as u64 / u16::MAX as u64) as $res
+                } else {
+                    duty as $res
+                };
+
+                self.timer.set_duty(self.channel, duty);
+                Ok(())
+            }
+        }
+
         /// Implementation of the embedded-hal CountDown trait
         /// To use Countdown it is prefered to configure new timer in Oneshot mode :
         ///
@@ -1027,6 +1290,26 @@ macro_rules! make_timer {
     }
 }
 
+// Only advanced-control timers, and TIM15-17 have a repetition counter.
+macro_rules! rep_counter {
+    ($TIMX:ident) => {
+        impl Timer<pac::$TIMX> {
+            /// Only generate an update event (and interrupt) every `periods` counter overflows, using
+            /// the repetition counter. Useful to prevent a fast timer, eg one used for PWM, from
+            /// flooding the CPU with update interrupts, when only occasional bookkeeping is needed.
+            /// `periods` may be up to 256; or up to 65,536 for TIM1, TIM8 and TIM20 on G4 and H7.
+            /// Note that in center-aligned mode, both overflows and underflows count.
+            /// Since preloaded registers (eg ARR and CCRx) are only transferred at update events, this
+            /// also reduces how often they update. Takes effect at the next update event, or
+            /// immediately with `reinitialize()`. Sets `TIMx_RCR` register.
+            pub fn set_update_decimation(&mut self, periods: u32) {
+                assert!((1..=65_536).contains(&periods));
+                self.regs.rcr.write(|w| unsafe { w.bits(periods - 1) });
+            }
+        }
+    };
+}
+
 // We use macros to support the varying number of capture compare channels available on
 // different timers.
 // Note that there's lots of DRY between these implementations.
@@ -1410,6 +1693,80 @@ macro_rules! cc_4_channels {
                 self.reinitialize();
             }
         }
+
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "l4x5", feature = "l5", feature = "g0")))]
+        impl DebouncedInput<pac::$TIMX> {
+            /// Set up a debounced input. The input must hold a level for `stable_time` (in seconds)
+            /// before it's accepted; this must be within the timer's period range. Pass a timer
+            /// that's not otherwise in use; its frequency and mode settings are overwritten.
+            pub fn new(mut timer: Timer<pac::$TIMX>, pin: Pin, stable_time: f32) -> Result<Self, ValueError> {
+                timer.disable();
+                timer.set_period(stable_time)?;
+
+                // Stop the counter once the input's been stable for a full period, and only request
+                // an update interrupt on that overflow; not when an edge resets the counter.
+                timer.regs.cr1.modify(|_, w| {
+                    w.opm().set_bit();
+                    w.urs().set_bit()
+                });
+
+                // Use the longest input filter: 8 consecutive samples at f_DTS / 32. We write IC1F
+                // (CCMR1 bits 7:4) raw, since some PACs, eg WB, don't have the field.
+                timer.set_capture_compare_input(TimChannel::C1, CaptureCompare::InputTi1);
+                timer.regs.ccmr1_input().modify(|r, w| unsafe { w.bits(r.bits() | (0b1111 << 4)) });
+
+                // Trigger from the TI1 edge detector, which fires on both edges (TS = 00100), in
+                // combined reset + trigger mode (SMS = 1000): Each edge resets the counter, and starts it
+                // if stopped. See G4 RM, section 29.3.20. We write raw bits, since SMS[3] is a separate
+                // field, whose name varies between PACs.
+                timer.regs.smcr.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !SMCR_SLAVE_MASK) | (0b100 << 4) | (1 << 16))
+                });
+
+                // Load the prescaler. With URS set, this doesn't set the update interrupt flag.
+                timer.reinitialize();
+                timer.clear_interrupt(TimerInterrupt::Update);
+                timer.enable_interrupt(TimerInterrupt::Update);
+
+                let state = pin.is_high();
+
+                Ok(Self { timer, pin, state })
+            }
+
+            /// Call this in the timer's interrupt handler. Clears the interrupt, and latches the
+            /// input's level. Returns the new state if it changed since the last update, eg
+            /// `Some(false)` for a button press on an active-low input.
+            pub fn update(&mut self) -> Option<bool> {
+                self.timer.clear_interrupt(TimerInterrupt::Update);
+
+                let state = self.pin.is_high();
+                if state == self.state {
+                    return None;
+                }
+
+                self.state = state;
+                Some(state)
+            }
+
+            /// Returns the latched, debounced state: `true` if high.
+            pub fn is_high(&self) -> bool {
+                self.state
+            }
+
+            /// Returns the latched, debounced state: `true` if low.
+            pub fn is_low(&self) -> bool {
+                !self.state
+            }
+
+            /// Stop debouncing, and release the timer and pin.
+            pub fn free(mut self) -> (Timer<pac::$TIMX>, Pin) {
+                self.timer.disable_interrupt(TimerInterrupt::Update);
+                self.timer.disable();
+                self.timer.regs.smcr.modify(|r, w| unsafe { w.bits(r.bits() & !SMCR_SLAVE_MASK) });
+
+                (self.timer, self.pin)
+            }
+        }
     }
 }
 
@@ -2037,6 +2394,8 @@ cfg_if! {
 
 #[cfg(not(any(feature = "f373")))]
 make_timer!(TIM1, tim1, 2, u16);
+#[cfg(not(any(feature = "f373")))]
+rep_counter!(TIM1);
 
 #[cfg(not(any(feature = "f373", feature = "g0", feature = "g4")))]
 cc_4_channels!(TIM1, u16);
@@ -2121,6 +2480,7 @@ cfg_if! {
         feature = "h7",
     ))] {
         make_timer!(TIM8, tim8, 2, u16);
+        rep_counter!(TIM8);
         // todo: Some issues with field names or something on l562 here.
         #[cfg(not(feature = "l5"))] // PAC bug.
         cc_4_channels!(TIM8, u16);
@@ -2133,6 +2493,7 @@ cfg_if! {
 cfg_if! {
     if #[cfg(feature = "g4")] {
         make_timer!(TIM8, tim8, 2, u32);
+        rep_counter!(TIM8);
         cc_4_channels!(TIM8, u32);
     }
 }
@@ -2150,6 +2511,7 @@ cfg_if! {
         feature = "wl"
     )))] {
         make_timer!(TIM15, tim15, 2, u16);
+        rep_counter!(TIM15);
         // todo: TIM15 on some variant has 2 channels (Eg H7). On others, like L4x3, it appears to be 1.
         cc_1_channel!(TIM15, u16);
     }
@@ -2158,6 +2520,8 @@ cfg_if! {
 #[cfg(not(feature = "f4"))]
 make_timer!(TIM16, tim16, 2, u16);
 #[cfg(not(feature = "f4"))]
+rep_counter!(TIM16);
+#[cfg(not(feature = "f4"))]
 cc_1_channel!(TIM16, u16);
 
 cfg_if! {
@@ -2169,6 +2533,7 @@ cfg_if! {
         feature = "f4",
     )))] {
         make_timer!(TIM17, tim17, 2, u16);
+        rep_counter!(TIM17);
         cc_1_channel!(TIM17, u16);
     }
 }
@@ -2194,6 +2559,8 @@ cfg_if! {
 // todo: G4 (maybe not all variants?) have TIM20.
 #[cfg(any(feature = "f303"))]
 make_timer!(TIM20, tim20, 2, u16);
+#[cfg(feature = "f303")]
+rep_counter!(TIM20);
 #[cfg(any(feature = "f303"))]
 cc_4_channels!(TIM20, u16);
 
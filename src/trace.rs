@@ -0,0 +1,217 @@
+//! Lightweight event tracing, for measuring ISR latency and task jitter on devices without
+//! trace hardware. Timestamp user-defined events with a free-running timer, and store them in a
+//! lock-free ring buffer; drain it from a low-priority context, eg the main loop, and send the
+//! events over UART, RTT etc.
+//!
+//! If the buffer fills before it's drained, the oldest events are overwritten, and counted as
+//! dropped.
+//!
+//! Example:
+//! ```rust
+//! const EVT_ADC_ISR: u16 = 0;
+//! const EVT_CONTROL_LOOP: u16 = 1;
+//!
+//! static TRACE: EventTrace<128> = EventTrace::new();
+//!
+//! // Set up a free-running timer; eg TIM2, which has a 32-bit counter, at 1Mhz:
+//! let mut timer = Timer::new_tim2(dp.TIM2, 1., Default::default(), &clock_cfg);
+//! timer.set_prescaler((clock_cfg.apb1_timer() / 1_000_000 - 1) as u16);
+//! timer.set_auto_reload(u32::MAX);
+//! timer.enable();
+//!
+//! // In an ISR:
+//! TRACE.record(EVT_ADC_ISR, timer.read_count());
+//!
+//! // In the main loop:
+//! while let Some(event) = TRACE.pop() {
+//!     defmt::println!("{}: {}", event.id, event.timestamp);
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+#[cfg(feature = "g0")]
+use cortex_m::interrupt::free;
+
+/// A single timestamped event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceEvent {
+    /// User-defined event identifier.
+    pub id: u16,
+    /// The timer count when the event was recorded.
+    pub timestamp: u32,
+}
+
+impl TraceEvent {
+    /// The number of timer ticks between an earlier event and this one. This handles counter
+    /// wraparound for 32-bit timers; for 16-bit timers, mask the result with `0xffff`.
+    pub fn ticks_since(&self, earlier: &Self) -> u32 {
+        self.timestamp.wrapping_sub(earlier.timestamp)
+    }
+}
+
+/// A ring buffer slot. `tag` contains the low 16 bits of the event's sequence number in its high
+/// half, and the event id in its low half. It's invalidated while the slot is being written, so
+/// a reader can detect a slot that's being overwritten.
+struct Slot {
+    tag: AtomicU32,
+    timestamp: AtomicU32,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        tag: AtomicU32::new(0),
+        timestamp: AtomicU32::new(0),
+    };
+}
+
+/// Builds the tag value for an event sequence number.
+fn tag(seq: usize, id: u16) -> u32 {
+    ((seq as u16 as u32) << 16) | id as u32
+}
+
+/// A tag that doesn't match `seq`, used to mark a slot as being written.
+fn busy_tag(seq: usize) -> u32 {
+    tag(seq ^ 0x8000, 0)
+}
+
+/// A lock-free event trace buffer, holding up to `N` events. Events can be recorded from any
+/// context, including ISRs of different priorities, and should be drained from a single
+/// context. Create it as a `static`, using `new()`.
+pub struct EventTrace<const N: usize> {
+    slots: [Slot; N],
+    /// Sequence number of the next event to be recorded.
+    head: AtomicUsize,
+    /// Sequence number of the next event to be drained.
+    tail: AtomicUsize,
+    dropped: AtomicU32,
+}
+
+impl<const N: usize> Default for EventTrace<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventTrace<N> {
+    /// Create an empty trace buffer. `N` must be between 1 and 16,384.
+    pub const fn new() -> Self {
+        assert!(N > 0 && N <= 0x4000);
+
+        Self {
+            slots: [Slot::EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Reserve a sequence number for a new event.
+    fn reserve(&self) -> usize {
+        // Cortex-M0+ doesn't support atomic read-modify-write operations, so we use a (very
+        // short) critical section there.
+        #[cfg(feature = "g0")]
+        return free(|_| {
+            let seq = self.head.load(Ordering::Relaxed);
+            self.head.store(seq.wrapping_add(1), Ordering::Relaxed);
+            seq
+        });
+
+        #[cfg(not(feature = "g0"))]
+        return self.head.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an event. `timestamp` is typically a free-running timer's count, from
+    /// `Timer::read_count()`.
+    pub fn record(&self, id: u16, timestamp: u32) {
+        let seq = self.reserve();
+        let slot = &self.slots[seq % N];
+
+        slot.tag.store(busy_tag(seq), Ordering::Release);
+        slot.timestamp.store(timestamp, Ordering::Release);
+        slot.tag.store(tag(seq, id), Ordering::Release);
+    }
+
+    /// Remove and return the oldest event, if available. Returns `None` if the buffer is empty,
+    /// or if the oldest event is still being written.
+    pub fn pop(&self) -> Option<TraceEvent> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let mut tail = self.tail.load(Ordering::Relaxed);
+
+            if head == tail {
+                return None;
+            }
+
+            // If the writers have lapped us, skip the overwritten events.
+            let pending = head.wrapping_sub(tail);
+            if pending > N {
+                self.add_dropped((pending - N) as u32);
+                tail = head.wrapping_sub(N);
+            }
+
+            let slot = &self.slots[tail % N];
+            let tag_before = slot.tag.load(Ordering::Acquire);
+
+            if (tag_before >> 16) as u16 != tail as u16 {
+                // This slot's write hasn't completed yet.
+                self.tail.store(tail, Ordering::Relaxed);
+                return None;
+            }
+
+            let timestamp = slot.timestamp.load(Ordering::Acquire);
+            let tag_after = slot.tag.load(Ordering::Acquire);
+
+            self.tail.store(tail.wrapping_add(1), Ordering::Relaxed);
+
+            if tag_after == tag_before {
+                return Some(TraceEvent {
+                    id: tag_before as u16,
+                    timestamp,
+                });
+            }
+
+            // The slot was overwritten while we read it; discard the event, and try the next.
+            self.add_dropped(1);
+        }
+    }
+
+    /// Only the draining context modifies the dropped count, so this doesn't need to be an
+    /// atomic read-modify-write.
+    fn add_dropped(&self, count: u32) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        self.dropped
+            .store(dropped.saturating_add(count), Ordering::Relaxed);
+    }
+
+    /// Drain all available events, passing each to `f`, in the order they were recorded.
+    pub fn drain(&self, mut f: impl FnMut(TraceEvent)) {
+        while let Some(event) = self.pop() {
+            f(event);
+        }
+    }
+
+    /// The number of events waiting to be drained.
+    pub fn len(&self) -> usize {
+        let pending = self
+            .head
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.tail.load(Ordering::Relaxed));
+
+        pending.min(N)
+    }
+
+    /// Returns true if there are no events waiting to be drained.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of events lost due to the buffer overflowing, since the last call to
+    /// `take_dropped()`. Call this from the same context that drains events.
+    pub fn take_dropped(&self) -> u32 {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        dropped
+    }
+}
@@ -0,0 +1,397 @@
+//! Support for the Touch Sensing Controller (TSC), for capacitive buttons, sliders, and
+//! proximity sensors, without ST's touch sensing middleware.
+//!
+//! The TSC uses charge transfer: It repeatedly charges an electrode (the channel IO), and
+//! transfers the charge to a sampling capacitor, counting transfers until the capacitor's voltage
+//! reaches a threshold. A finger raises the electrode's capacitance, so fewer transfers are needed;
+//! ie the count drops when touched.
+//!
+//! IOs are arranged in up to 8 groups of 4. Each group used has one sampling capacitor IO, and one
+//! or more channel IOs. Each acquisition measures one channel per group, with all enabled groups
+//! measured in parallel. Set sampling capacitor IOs to alternate function open drain, and channel
+//! IOs to alternate function push-pull, using the TSC alternate function (AF9 on L4, and WB, AF3
+//! on F3).
+//!
+//! See L4x6 RM, chapter 27: "Touch sensing controller (TSC)".
+//!
+//! Example:
+//! ```rust
+//! // A button on G1_IO2 (PB13 on L476), with its sampling capacitor on G1_IO1 (PB12).
+//! let mut tsc = Tsc::new(dp.TSC, Default::default());
+//! tsc.set_sampling_ios(&[TscIo::new(1, 1)]);
+//! tsc.set_channels(&[TscIo::new(1, 2)]);
+//!
+//! let mut button = TouchButton::new(1, 50, 3);
+//!
+//! loop {
+//!     tsc.acquire()?;
+//!     if button.update(tsc.read(1)) {
+//!         // Touched.
+//!     }
+//! }
+//! ```
+
+use core::ptr::{read_volatile, write_volatile};
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    pac::{RCC, TSC},
+    util::rcc_en_reset,
+};
+
+// We use raw pointers, since the group count registers are individual fields in the PAC, and
+// aren't consistently present among variants.
+
+// Register offsets.
+const CR: usize = 0x00;
+const IER: usize = 0x04;
+const ICR: usize = 0x08;
+const ISR: usize = 0x0c;
+const IOHCR: usize = 0x10;
+const IOSCR: usize = 0x20;
+const IOCCR: usize = 0x28;
+const IOGCSR: usize = 0x30;
+const IOG1CR: usize = 0x34;
+
+// TSC_CR fields.
+const TSCE: u32 = 1 << 0;
+const START: u32 = 1 << 1;
+
+// TSC_ISR fields.
+const EOAF: u32 = 1 << 0;
+const MCEF: u32 = 1 << 1;
+
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// TSC error
+pub enum Error {
+    /// A group's count reached the max count value before its sampling capacitor charged; eg
+    /// from a shorted, or missing sampling capacitor.
+    MaxCount,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Charge transfer pulse generator prescaler, from HCLK. Sets TSC_CR register, PGPSC field.
+pub enum PulsePrescaler {
+    D1 = 0,
+    D2 = 1,
+    D4 = 2,
+    D8 = 3,
+    D16 = 4,
+    D32 = 5,
+    D64 = 6,
+    D128 = 7,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The count an acquisition ends at with a max count error. Sets TSC_CR register, MCV field.
+pub enum MaxCount {
+    C255 = 0,
+    C511 = 1,
+    C1023 = 2,
+    C2047 = 3,
+    C4095 = 4,
+    C8191 = 5,
+    C16383 = 6,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// TSC interrupts. Enabled in TSC_IER, and cleared in TSC_ICR. The value is the bit position in
+/// both.
+pub enum TscInterrupt {
+    /// End of acquisition.
+    EndOfAcquisition = 0,
+    /// Max count error.
+    MaxCountError = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// A TSC IO: Group 1 - 8, and IO 1 - 4 within the group; eg `G2_IO3` is `TscIo::new(2, 3)`.
+/// See the pinout section of your MCU's datasheet for which GPIO pins these are.
+pub struct TscIo {
+    pub group: u8,
+    pub io: u8,
+}
+
+impl TscIo {
+    pub const fn new(group: u8, io: u8) -> Self {
+        Self { group, io }
+    }
+
+    /// This IO's bit in the TSC_IOHCR, TSC_IOSCR, and TSC_IOCCR registers.
+    fn bit(&self) -> u32 {
+        assert!(
+            (1..=8).contains(&self.group) && (1..=4).contains(&self.io),
+            "TSC groups are 1 - 8, and IOs are 1 - 4."
+        );
+
+        1 << ((self.group - 1) * 4 + self.io - 1)
+    }
+}
+
+/// Configuration data for the TSC.
+pub struct TscConfig {
+    /// Defaults to HCLK / 16.
+    pub prescaler: PulsePrescaler,
+    /// Charge transfer pulse high time, in pulse generator clock cycles: 1 - 16. This charges
+    /// the electrode. Defaults to 2.
+    pub pulse_high: u8,
+    /// Charge transfer pulse low time, in pulse generator clock cycles: 1 - 16. This transfers
+    /// charge to the sampling capacitor. Defaults to 2.
+    pub pulse_low: u8,
+    /// Defaults to 16383.
+    pub max_count: MaxCount,
+    /// Spread spectrum: Varies the pulse high time by up to this many HCLK cycles (1 - 128), to
+    /// reduce EMI, and improve noise immunity. `None` disables it. Defaults to `None`.
+    pub spread_spectrum: Option<u8>,
+}
+
+impl Default for TscConfig {
+    fn default() -> Self {
+        Self {
+            prescaler: PulsePrescaler::D16,
+            pulse_high: 2,
+            pulse_low: 2,
+            max_count: MaxCount::C16383,
+            spread_spectrum: None,
+        }
+    }
+}
+
+/// Represents the Touch Sensing Controller (TSC) peripheral.
+pub struct Tsc {
+    pub regs: TSC,
+    pub cfg: TscConfig,
+}
+
+impl Tsc {
+    /// Initialize the TSC, including enabling and resetting its RCC peripheral clock. Configures
+    /// and enables the charge transfer pulse generator; set IOs with `set_sampling_ios()`, and
+    /// `set_channels()`.
+    pub fn new(regs: TSC, cfg: TscConfig) -> Self {
+        assert!(
+            (1..=16).contains(&cfg.pulse_high) && (1..=16).contains(&cfg.pulse_low),
+            "TSC pulse high and low times must be 1 - 16 cycles."
+        );
+
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc_en_reset!(ahb1, tsc, rcc);
+        });
+
+        let result = Self { regs, cfg };
+
+        // CTPH (bits 31:28), and CTPL (bits 27:24): Pulse high, and low times - 1. PGPSC (bits
+        // 14:12): Pulse generator prescaler. MCV (bits 7:5): Max count value. IODEF (bit 4) = 0:
+        // Unused IOs are driven low, which discharges the sampling capacitors between
+        // acquisitions.
+        let mut cr = ((result.cfg.pulse_high as u32 - 1) << 28)
+            | ((result.cfg.pulse_low as u32 - 1) << 24)
+            | ((result.cfg.prescaler as u32) << 12)
+            | ((result.cfg.max_count as u32) << 5)
+            | TSCE;
+
+        if let Some(deviation) = result.cfg.spread_spectrum {
+            assert!(
+                (1..=128).contains(&deviation),
+                "TSC spread spectrum deviation must be 1 - 128 cycles."
+            );
+            // SSD (bits 23:17): Deviation - 1. SSE (bit 16): Spread spectrum enable.
+            cr |= ((deviation as u32 - 1) << 17) | (1 << 16);
+        }
+
+        result.write_reg(CR, cr);
+
+        result
+    }
+
+    /// Set the sampling capacitor IOs; one per group used. This also disables these IOs' Schmitt
+    /// trigger hysteresis, as required. (TSC_IOSCR, and TSC_IOHCR registers)
+    pub fn set_sampling_ios(&mut self, ios: &[TscIo]) {
+        let mask = ios.iter().fold(0, |acc, io| acc | io.bit());
+
+        self.write_reg(IOSCR, mask);
+        self.disable_hysteresis(mask);
+    }
+
+    /// Set the channel IOs to measure in the next acquisition, and enable their groups. Use at
+    /// most one channel per group; to measure several channels in a group, set each, and acquire,
+    /// in turn. This also disables these IOs' Schmitt trigger hysteresis. (TSC_IOCCR,
+    /// TSC_IOGCSR, and TSC_IOHCR registers)
+    pub fn set_channels(&mut self, ios: &[TscIo]) {
+        let mut mask = 0;
+        let mut groups = 0;
+
+        for io in ios {
+            mask |= io.bit();
+            groups |= 1 << (io.group - 1);
+        }
+
+        self.write_reg(IOCCR, mask);
+        self.write_reg(IOGCSR, groups);
+        self.disable_hysteresis(mask);
+    }
+
+    /// Clear the Schmitt trigger hysteresis bits for IOs, as recommended for TSC IOs. (TSC_IOHCR)
+    fn disable_hysteresis(&mut self, mask: u32) {
+        let iohcr = self.read_reg(IOHCR);
+        self.write_reg(IOHCR, iohcr & !mask);
+    }
+
+    /// Start an acquisition of the channels set with `set_channels()`, and return. Check for
+    /// completion with `is_complete()`, or the `EndOfAcquisition` interrupt, then read results
+    /// with `read()`.
+    pub fn start(&mut self) {
+        self.write_reg(ICR, EOAF | MCEF);
+
+        let cr = self.read_reg(CR);
+        self.write_reg(CR, cr | START);
+    }
+
+    /// Returns true if the acquisition is complete, whether or not it ended with a max count
+    /// error. (TSC_ISR register, EOAF field)
+    pub fn is_complete(&self) -> bool {
+        self.read_reg(ISR) & EOAF != 0
+    }
+
+    /// Returns true if the last acquisition ended with a max count error. (TSC_ISR register, MCEF
+    /// field)
+    pub fn max_count_error(&self) -> bool {
+        self.read_reg(ISR) & MCEF != 0
+    }
+
+    /// Start an acquisition, and block until it's complete.
+    pub fn acquire(&mut self) -> Result<(), Error> {
+        self.start();
+
+        // Both flags are set on a max count error.
+        while !self.is_complete() {}
+
+        if self.max_count_error() {
+            return Err(Error::MaxCount);
+        }
+
+        Ok(())
+    }
+
+    /// Read a group's count from the last acquisition; group is 1 - 8. This is lower when its
+    /// channel is touched. (TSC_IOGxCR register)
+    pub fn read(&self, group: u8) -> u16 {
+        assert!((1..=8).contains(&group), "TSC groups are 1 - 8.");
+
+        (self.read_reg(IOG1CR + 4 * (group as usize - 1)) & 0x3fff) as u16
+    }
+
+    /// Returns true if a group's acquisition is complete; group is 1 - 8. (TSC_IOGCSR register,
+    /// GxS field)
+    pub fn group_complete(&self, group: u8) -> bool {
+        assert!((1..=8).contains(&group), "TSC groups are 1 - 8.");
+
+        self.read_reg(IOGCSR) & (1 << (16 + group - 1)) != 0
+    }
+
+    /// Enable an interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: TscInterrupt) {
+        let ier = self.read_reg(IER);
+        self.write_reg(IER, ier | (1 << interrupt as u8));
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: TscInterrupt) {
+        let ier = self.read_reg(IER);
+        self.write_reg(IER, ier & !(1 << interrupt as u8));
+    }
+
+    /// Clear an interrupt flag.
+    pub fn clear_interrupt(&mut self, interrupt: TscInterrupt) {
+        self.write_reg(ICR, 1 << interrupt as u8);
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((TSC::ptr() as usize + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((TSC::ptr() as usize + offset) as *mut u32, value) }
+    }
+}
+
+/// A capacitive button, detected from a TSC group's counts. A touch is detected when the count
+/// falls `threshold` below the untouched baseline, for `debounce` consecutive readings; it's
+/// released after the same number of readings back above it. The baseline is set from the first
+/// reading, so don't touch the button at startup, and slowly tracks drift, eg from temperature,
+/// while untouched.
+pub struct TouchButton {
+    /// The TSC group this button's channel is in.
+    pub group: u8,
+    /// How far the count must drop below the baseline to detect a touch.
+    pub threshold: u16,
+    /// The number of consecutive readings required to change state.
+    pub debounce: u8,
+    /// The untouched count. 0 until the first reading.
+    baseline: u16,
+    /// Consecutive readings that disagree with the current state.
+    count: u8,
+    touched: bool,
+}
+
+impl TouchButton {
+    pub fn new(group: u8, threshold: u16, debounce: u8) -> Self {
+        Self {
+            group,
+            threshold,
+            debounce,
+            baseline: 0,
+            count: 0,
+            touched: false,
+        }
+    }
+
+    /// Update the button's state from a reading, eg from `Tsc::read()`, and return true if it's
+    /// touched.
+    pub fn update(&mut self, reading: u16) -> bool {
+        if self.baseline == 0 {
+            self.baseline = reading;
+        }
+
+        let touch_detected = self.baseline.saturating_sub(reading) >= self.threshold;
+
+        if touch_detected == self.touched {
+            self.count = 0;
+        } else {
+            self.count += 1;
+            if self.count >= self.debounce {
+                self.touched = touch_detected;
+                self.count = 0;
+            }
+        }
+
+        // Track drift slowly while untouched, so a touch doesn't move the baseline.
+        if !self.touched && !touch_detected {
+            self.baseline = ((self.baseline as u32 * 15 + reading as u32) / 16) as u16;
+        }
+
+        self.touched
+    }
+
+    /// Returns true if the button is touched, as of the last `update()`.
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    /// The untouched count the threshold is measured from.
+    pub fn baseline(&self) -> u16 {
+        self.baseline
+    }
+
+    /// Re-set the baseline from the next reading; eg after changing the TSC configuration.
+    pub fn reset_baseline(&mut self) {
+        self.baseline = 0;
+        self.count = 0;
+        self.touched = false;
+    }
+}
@@ -0,0 +1,603 @@
+//! Support for the USB Type-C / Power Delivery interface (UCPD). This handles the CC line
+//! analog front end: Type-C attach detection, and orientation, and the PD physical layer:
+//! BMC-encoded message transmission and reception, with DMA, and hard reset signaling. It doesn't
+//! implement the PD protocol, or policy engine layers; use this with a PD stack for that.
+//!
+//! Available on G4, and L5. Set the CC pins (G4: PB6: CC1, PB4: CC2; L5: PB15: CC1, PA15: CC2)
+//! to analog mode. See G4 RM, section 46: USB Type-C / USB Power Delivery interface (UCPD).
+//!
+//! Example, as a sink:
+//! ```rust
+//! let mut ucpd = Ucpd::new(&clock_cfg);
+//! ucpd.disable_dead_battery();
+//! ucpd.set_role(PowerRole::Sink);
+//!
+//! // Once a source is attached (eg from the `TypeCEvent1`, or `TypeCEvent2` interrupt):
+//! if let Some(line) = ucpd.attached_line() {
+//!     ucpd.set_cc_line(line);
+//!     ucpd.enable_rx();
+//!     unsafe { ucpd.read_dma(&mut RX_BUF, DmaChannel::C1, Default::default(), DmaPeriph::Dma1) };
+//! }
+//!
+//! // On the `RxMsgEnd` interrupt:
+//! ucpd.clear_interrupt(UcpdInterrupt::RxMsgEnd);
+//! let (ordered_set, len) = ucpd.read_rx_status()?;
+//! ```
+
+use cortex_m::interrupt::free;
+
+use crate::{
+    clocks::Clocks,
+    pac::{PWR, RCC},
+};
+
+#[cfg(not(feature = "l552"))]
+use crate::{
+    dma::{self, ChannelCfg, DmaChannel},
+    pac::DMA1,
+};
+
+use cfg_if::cfg_if;
+
+// We use raw pointers, since the UCPD is missing, or incomplete in some of the PACs
+// for these families.
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        /// UCPD1 base address.
+        const UCPD1_BASE: usize = 0x4000_a000;
+    } else {
+        /// UCPD1 base address.
+        const UCPD1_BASE: usize = 0x4000_dc00;
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "g4")] {
+        /// RCC_APB1ENR2, and RCC_APB1RSTR2: UCPD1EN, and UCPD1RST.
+        const RCC_BIT: u32 = 1 << 8;
+    } else {
+        /// RCC_APB1ENR2, and RCC_APB1RSTR2: UCPD1EN, and UCPD1RST.
+        const RCC_BIT: u32 = 1 << 23;
+    }
+}
+
+/// PWR_CR3: UCPD_DBDIS (`UCPD1_DBDIS` on G4). Disables the dead battery pull-downs on the CC
+/// lines. This is in PWR_CR3 on both G4, and L5; see `PWR_CR3_UCPD_DBDIS` in their CMSIS device
+/// headers. (It's in PWR_UCPDR on U5, but that offset is PWR_PDCRB on L5.)
+const PWR_UCPD_DBDIS: u32 = 1 << 14;
+
+/// Disable the dead battery pull-downs on the CC lines. Shared by `Ucpd::disable_dead_battery()`,
+/// and `gpio::disable_ucpd_dead_battery()`, which doesn't require the UCPD to be set up.
+pub(crate) fn disable_dead_battery() {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+
+        let pwr = unsafe { &(*PWR::ptr()) };
+        pwr.cr3
+            .modify(|r, w| unsafe { w.bits(r.bits() | PWR_UCPD_DBDIS) });
+    });
+}
+
+// Register offsets.
+const CFG1: usize = 0x00;
+const CR: usize = 0x0c;
+const IMR: usize = 0x10;
+const SR: usize = 0x14;
+const ICR: usize = 0x18;
+const TX_ORDSET: usize = 0x1c;
+const TX_PAYSZ: usize = 0x20;
+const TXDR: usize = 0x24;
+const RX_ORDSET: usize = 0x28;
+const RX_PAYSZ: usize = 0x2c;
+const RXDR: usize = 0x30;
+
+// CFG1 fields.
+const HBITCLKDIV_SHIFT: u32 = 0;
+const IFRGAP_SHIFT: u32 = 6;
+const TRANSWIN_SHIFT: u32 = 11;
+const PSC_USBPDCLK_SHIFT: u32 = 17;
+const RXORDSETEN_SHIFT: u32 = 20;
+#[cfg(not(feature = "l552"))]
+const TXDMAEN: u32 = 1 << 29;
+#[cfg(not(feature = "l552"))]
+const RXDMAEN: u32 = 1 << 30;
+const UCPDEN: u32 = 1 << 31;
+
+// CR fields.
+const TXMODE_MASK: u32 = 0b11;
+const TXSEND: u32 = 1 << 2;
+const TXHRST: u32 = 1 << 3;
+const PHYRXEN: u32 = 1 << 5;
+const PHYCCSEL: u32 = 1 << 6;
+const ANASUBMODE_SHIFT: u32 = 7;
+const ANAMODE: u32 = 1 << 9;
+const CCENABLE_SHIFT: u32 = 10;
+
+// SR bits used directly.
+const TXIS: u32 = 1 << 0;
+const RXNE: u32 = 1 << 8;
+const RXERR: u32 = 1 << 13;
+const TYPEC_VSTATE_CC1_SHIFT: u32 = 16;
+const TYPEC_VSTATE_CC2_SHIFT: u32 = 18;
+
+/// Target half-bit clock frequency; twice the 300kbps BMC bit rate.
+const HBIT_FREQ: u32 = 600_000;
+/// The maximum UCPD clock, after the prescaler.
+const UCPD_CLK_MAX: u32 = 12_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The current advertised by a source, on its Rp pull-up. Sets UCPD_CR, ANASUBMODE field.
+pub enum RpValue {
+    /// Default USB power. (500mA on USB 2, 900mA on USB 3)
+    Default = 0b01,
+    /// 1.5A
+    A1_5 = 0b10,
+    /// 3.0A
+    A3_0 = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// The Type-C power role; this sets the CC line termination. Sets UCPD_CR, ANAMODE field.
+pub enum PowerRole {
+    /// Present Rp pull-ups, advertising a current.
+    Source(RpValue),
+    /// Present Rd pull-downs.
+    Sink,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// A CC line.
+pub enum CcLine {
+    Cc1,
+    Cc2,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+/// The voltage state of a CC line, as seen by a sink; this indicates the current the source
+/// advertises. (UCPD_SR, TYPEC_VSTATE_CCx fields)
+pub enum CcState {
+    /// Nothing is attached to this line, or it's connected to a cable's Ra.
+    Open = 0b00,
+    /// The source advertises default USB power.
+    Default = 0b01,
+    /// The source advertises 1.5A.
+    A1_5 = 0b10,
+    /// The source advertises 3.0A.
+    A3_0 = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+/// The ordered set (start of packet) at the start of a message. This indicates whether a message
+/// is for the port partner (SOP), or the cable (SOP', SOP''). (UCPD_RX_ORDSET, RXORDSET field)
+pub enum OrderedSet {
+    Sop = 0,
+    SopPrime = 1,
+    SopDoublePrime = 2,
+    SopPrimeDebug = 3,
+    SopDoublePrimeDebug = 4,
+    CableReset = 5,
+    SopExtension1 = 6,
+    SopExtension2 = 7,
+}
+
+impl OrderedSet {
+    /// The K-code sequence to transmit; used with UCPD_TX_ORDSET.
+    fn tx_value(&self) -> u32 {
+        // K-codes.
+        const SYNC1: u32 = 0b11000;
+        const SYNC2: u32 = 0b10001;
+        const SYNC3: u32 = 0b00110;
+        const RST1: u32 = 0b00111;
+        const RST2: u32 = 0b11001;
+
+        let codes = match self {
+            Self::Sop => [SYNC1, SYNC1, SYNC1, SYNC2],
+            Self::SopPrime => [SYNC1, SYNC1, SYNC3, SYNC3],
+            Self::SopDoublePrime => [SYNC1, SYNC3, SYNC1, SYNC3],
+            Self::SopPrimeDebug => [SYNC1, RST2, RST2, SYNC3],
+            Self::SopDoublePrimeDebug => [SYNC1, RST2, SYNC3, SYNC2],
+            Self::CableReset => [RST1, SYNC1, RST1, SYNC3],
+            _ => panic!("Extension ordered sets can't be transmitted."),
+        };
+
+        codes[0] | codes[1] << 5 | codes[2] << 10 | codes[3] << 15
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// UCPD interrupts. The values are bit positions in UCPD_IMR, UCPD_SR, and UCPD_ICR.
+pub enum UcpdInterrupt {
+    /// The transmit data register is empty. Cleared by writing to it.
+    TxIs = 0,
+    /// A transmission was discarded, eg due to an incoming message.
+    TxMsgDiscarded = 1,
+    /// A message was sent.
+    TxMsgSent = 2,
+    /// A transmission was aborted.
+    TxMsgAborted = 3,
+    /// A hard reset transmission was discarded.
+    HardResetDiscarded = 4,
+    /// A hard reset was sent.
+    HardResetSent = 5,
+    /// Transmit underrun.
+    TxUnderrun = 6,
+    /// The receive data register isn't empty. Cleared by reading it.
+    RxNe = 8,
+    /// An ordered set was detected, at the start of a message.
+    RxOrderedSetDetected = 9,
+    /// A hard reset was received.
+    RxHardResetDetected = 10,
+    /// Receive overrun.
+    RxOverrun = 11,
+    /// A message was received. Check `read_rx_status()` for errors.
+    RxMsgEnd = 12,
+    /// The CC1 line's voltage state changed; eg on attach, or detach.
+    TypeCEvent1 = 14,
+    /// The CC2 line's voltage state changed.
+    TypeCEvent2 = 15,
+    /// A fast role swap signal was detected.
+    FastRoleSwap = 20,
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq)]
+/// UCPD error
+pub enum Error {
+    /// A received message had a CRC error, or an invalid symbol.
+    Rx,
+    /// A transmission was discarded, eg due to an incoming message.
+    TxDiscarded,
+    /// A transmission was aborted.
+    TxAborted,
+}
+
+/// Represents the UCPD peripheral.
+pub struct Ucpd {
+    base: usize,
+}
+
+impl Ucpd {
+    /// Enable and reset the UCPD's RCC peripheral clock, and configure its clock dividers for the
+    /// PD bit rate. Enables reception of SOP messages, and hard resets. The CC lines are left
+    /// disabled; set them up with `set_role()`.
+    pub fn new(clocks: &Clocks) -> Self {
+        free(|_| {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr2
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+            rcc.apb1rstr2
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_BIT) });
+            rcc.apb1rstr2
+                .modify(|r, w| unsafe { w.bits(r.bits() & !RCC_BIT) });
+        });
+
+        // The UCPD kernel clock is PCLK1 on G4, and HSI16 on L5.
+        #[cfg(feature = "g4")]
+        let kernel_clk = clocks.apb1();
+        #[cfg(feature = "l5")]
+        let kernel_clk = {
+            let _ = clocks;
+            16_000_000
+        };
+
+        // Divide by 1, 2, 4, 8, or 16, to keep the UCPD clock at or below 12Mhz.
+        let mut psc = 0;
+        while kernel_clk >> psc > UCPD_CLK_MAX && psc < 4 {
+            psc += 1;
+        }
+        let ucpd_clk = kernel_clk >> psc;
+
+        let hbitclkdiv = (ucpd_clk + HBIT_FREQ / 2) / HBIT_FREQ - 1;
+
+        // The transition window, and interframe gap are in half-bit clock periods. These give
+        // ~13us, and ~28us respectively, per the PD spec.
+        let transwin = 7;
+        let ifrgap = 16;
+
+        // Receive SOP, and hard reset ordered sets.
+        let rxordseten = 0b1001;
+
+        let mut result = Self { base: UCPD1_BASE };
+
+        // CFG1 can only be written while the UCPD is disabled.
+        result.write_reg(
+            CFG1,
+            hbitclkdiv << HBITCLKDIV_SHIFT
+                | ifrgap << IFRGAP_SHIFT
+                | transwin << TRANSWIN_SHIFT
+                | psc << PSC_USBPDCLK_SHIFT
+                | rxordseten << RXORDSETEN_SHIFT,
+        );
+        result.write_reg(CFG1, result.read_reg(CFG1) | UCPDEN);
+
+        result
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_reg(&mut self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    fn modify_cr(&mut self, clear: u32, set: u32) {
+        self.write_reg(CR, (self.read_reg(CR) & !clear) | set);
+    }
+
+    /// Disable the dead battery pull-downs on the CC lines, which are present after reset, so a
+    /// source can power the MCU without firmware running. Do this once the UCPD is configured, so
+    /// the CC lines aren't left floating. (PWR_CR3, UCPD_DBDIS bit)
+    pub fn disable_dead_battery(&mut self) {
+        disable_dead_battery();
+    }
+
+    /// Set the power role, and enable the terminations on both CC lines, for attach detection.
+    pub fn set_role(&mut self, role: PowerRole) {
+        let mut val = 0b11 << CCENABLE_SHIFT;
+        match role {
+            PowerRole::Source(rp) => val |= (rp as u32) << ANASUBMODE_SHIFT,
+            PowerRole::Sink => val |= ANAMODE,
+        }
+
+        self.modify_cr(
+            ANAMODE | 0b11 << ANASUBMODE_SHIFT | 0b11 << CCENABLE_SHIFT,
+            val,
+        );
+    }
+
+    /// Read a CC line's voltage state. Only valid as a sink.
+    pub fn cc_state(&self, line: CcLine) -> CcState {
+        let shift = match line {
+            CcLine::Cc1 => TYPEC_VSTATE_CC1_SHIFT,
+            CcLine::Cc2 => TYPEC_VSTATE_CC2_SHIFT,
+        };
+
+        match (self.read_reg(SR) >> shift) & 0b11 {
+            0b00 => CcState::Open,
+            0b01 => CcState::Default,
+            0b10 => CcState::A1_5,
+            _ => CcState::A3_0,
+        }
+    }
+
+    /// As a sink, returns the CC line a source is attached on, if any. This indicates the cable's
+    /// orientation; use it with `set_cc_line()`. Debounce this, eg by checking it again after
+    /// 100ms (tCCDebounce), before treating the port as attached.
+    pub fn attached_line(&self) -> Option<CcLine> {
+        if self.cc_state(CcLine::Cc1) != CcState::Open {
+            Some(CcLine::Cc1)
+        } else if self.cc_state(CcLine::Cc2) != CcState::Open {
+            Some(CcLine::Cc2)
+        } else {
+            None
+        }
+    }
+
+    /// Select the CC line used by the PD physical layer, after attach.
+    pub fn set_cc_line(&mut self, line: CcLine) {
+        match line {
+            CcLine::Cc1 => self.modify_cr(PHYCCSEL, 0),
+            CcLine::Cc2 => self.modify_cr(0, PHYCCSEL),
+        }
+    }
+
+    /// Enable the PD receiver. (UCPD_CR, PHYRXEN bit)
+    pub fn enable_rx(&mut self) {
+        self.modify_cr(0, PHYRXEN);
+    }
+
+    /// Disable the PD receiver; eg on detach.
+    pub fn disable_rx(&mut self) {
+        self.modify_cr(PHYRXEN, 0);
+    }
+
+    /// Transmit a message, blocking until it's sent. `buf` is the message header, and data
+    /// objects; the CRC is added by hardware.
+    pub fn write(&mut self, ordered_set: OrderedSet, buf: &[u8]) -> Result<(), Error> {
+        self.start_tx(ordered_set, buf.len());
+
+        for byte in buf {
+            while self.read_reg(SR) & TXIS == 0 {
+                self.check_tx_error()?;
+            }
+            self.write_reg(TXDR, *byte as u32);
+        }
+
+        while !self.is_interrupt_set(UcpdInterrupt::TxMsgSent) {
+            self.check_tx_error()?;
+        }
+        self.clear_interrupt(UcpdInterrupt::TxMsgSent);
+
+        Ok(())
+    }
+
+    /// Set up a transmission, and start it.
+    fn start_tx(&mut self, ordered_set: OrderedSet, len: usize) {
+        self.write_reg(TX_ORDSET, ordered_set.tx_value());
+        self.write_reg(TX_PAYSZ, len as u32);
+        self.modify_cr(TXMODE_MASK, TXSEND);
+    }
+
+    fn check_tx_error(&mut self) -> Result<(), Error> {
+        if self.is_interrupt_set(UcpdInterrupt::TxMsgDiscarded) {
+            self.clear_interrupt(UcpdInterrupt::TxMsgDiscarded);
+            Err(Error::TxDiscarded)
+        } else if self.is_interrupt_set(UcpdInterrupt::TxMsgAborted) {
+            self.clear_interrupt(UcpdInterrupt::TxMsgAborted);
+            Err(Error::TxAborted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read a received byte, if available. Use this to receive without DMA, eg on the `RxNe`
+    /// interrupt.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if self.read_reg(SR) & RXNE != 0 {
+            Some(self.read_reg(RXDR) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Once a message is received (the `RxMsgEnd` interrupt), read its ordered set, and length,
+    /// in bytes, including the header, but not the CRC.
+    pub fn read_rx_status(&self) -> Result<(OrderedSet, usize), Error> {
+        if self.read_reg(SR) & RXERR != 0 {
+            return Err(Error::Rx);
+        }
+
+        let ordered_set = match self.read_reg(RX_ORDSET) & 0b111 {
+            0 => OrderedSet::Sop,
+            1 => OrderedSet::SopPrime,
+            2 => OrderedSet::SopDoublePrime,
+            3 => OrderedSet::SopPrimeDebug,
+            4 => OrderedSet::SopDoublePrimeDebug,
+            5 => OrderedSet::CableReset,
+            6 => OrderedSet::SopExtension1,
+            _ => OrderedSet::SopExtension2,
+        };
+
+        Ok((ordered_set, (self.read_reg(RX_PAYSZ) & 0x3ff) as usize))
+    }
+
+    /// Send a hard reset. The `HardResetSent`, or `HardResetDiscarded` interrupt indicates the
+    /// result. (UCPD_CR, TXHRST bit)
+    pub fn send_hard_reset(&mut self) {
+        self.modify_cr(0, TXHRST);
+    }
+
+    #[cfg(not(feature = "l552"))]
+    /// Transmit a message using DMA. The `TxMsgSent` interrupt indicates completion. Set up the
+    /// channel with `dma::mux()`, and `DmaInput::Ucpd1Tx` first.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the message is sent.
+    pub unsafe fn write_dma(
+        &mut self,
+        ordered_set: OrderedSet,
+        buf: &[u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+
+        self.write_reg(CFG1, self.read_reg(CFG1) | TXDMAEN);
+
+        let periph_addr = (self.base + TXDR) as u32;
+
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S8,
+                    dma::DataSize::S8,
+                    channel_cfg,
+                );
+            }
+            dma::DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*crate::pac::DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromMem,
+                    dma::DataSize::S8,
+                    dma::DataSize::S8,
+                    channel_cfg,
+                );
+            }
+        }
+
+        self.start_tx(ordered_set, len);
+    }
+
+    #[cfg(not(feature = "l552"))]
+    /// Receive messages using DMA. Call this after `enable_rx()`; each message's bytes are written
+    /// to `buf`. On the `RxMsgEnd` interrupt, check the message with `read_rx_status()`, and
+    /// restart the transfer for the next message. Set up the channel with `dma::mux()`, and
+    /// `DmaInput::Ucpd1Rx` first.
+    ///
+    /// # Safety
+    /// `buf` must stay valid, and not be otherwise accessed, until the message is received.
+    pub unsafe fn read_dma(
+        &mut self,
+        buf: &mut [u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma_periph: dma::DmaPeriph,
+    ) {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+
+        self.write_reg(CFG1, self.read_reg(CFG1) | RXDMAEN);
+
+        let periph_addr = (self.base + RXDR) as u32;
+
+        match dma_periph {
+            dma::DmaPeriph::Dma1 => {
+                let mut regs = unsafe { &(*DMA1::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S8,
+                    dma::DataSize::S8,
+                    channel_cfg,
+                );
+            }
+            dma::DmaPeriph::Dma2 => {
+                let mut regs = unsafe { &(*crate::pac::DMA2::ptr()) };
+                dma::cfg_channel(
+                    &mut regs,
+                    channel,
+                    periph_addr,
+                    ptr as u32,
+                    len as u16,
+                    dma::Direction::ReadFromPeriph,
+                    dma::DataSize::S8,
+                    dma::DataSize::S8,
+                    channel_cfg,
+                );
+            }
+        }
+    }
+
+    /// Enable an interrupt.
+    pub fn enable_interrupt(&mut self, interrupt: UcpdInterrupt) {
+        self.write_reg(IMR, self.read_reg(IMR) | (1 << interrupt as u8));
+    }
+
+    /// Disable an interrupt.
+    pub fn disable_interrupt(&mut self, interrupt: UcpdInterrupt) {
+        self.write_reg(IMR, self.read_reg(IMR) & !(1 << interrupt as u8));
+    }
+
+    /// Returns true if an interrupt flag is set.
+    pub fn is_interrupt_set(&self, interrupt: UcpdInterrupt) -> bool {
+        self.read_reg(SR) & (1 << interrupt as u8) != 0
+    }
+
+    /// Clear an interrupt flag. `TxIs`, and `RxNe` are cleared by writing, or reading data
+    /// instead.
+    pub fn clear_interrupt(&mut self, interrupt: UcpdInterrupt) {
+        self.write_reg(ICR, 1 << interrupt as u8);
+    }
+}
@@ -8,7 +8,7 @@
 // todo: Missing some features (like additional interrupts) on the USARTv3 peripheral . (L5, G etc)
 
 use crate::{
-    clocks::Clocks,
+    clocks::{ClockListener, Clocks, SpeedError},
     pac::{self, RCC},
     util::{BaudPeriph, RccPeriph},
 };
@@ -36,6 +36,9 @@ use embedded_hal::{
 #[cfg(feature = "embedded-hal")]
 use nb;
 
+#[cfg(feature = "async")]
+use crate::asynch::Signal;
+
 use cfg_if::cfg_if;
 
 // todo: Prescaler (USART_PRESC) register on v3 (L5, G, H etc)
@@ -137,6 +140,15 @@ pub struct UsartConfig {
     #[cfg(not(feature = "f4"))]
     /// Optionally, disable the overrun functionality. Defaults to `false`.
     pub overrun_disabled: bool,
+    /// Single-wire half-duplex mode: Transmit, and receive on the TX pin; the RX pin is unused.
+    /// The TX line is released when not transmitting; configure the TX pin as open-drain, with
+    /// a pull-up. Use `write_read_half_duplex()` for turnaround. Defaults to `false`.
+    pub half_duplex: bool,
+    #[cfg(not(feature = "f4"))]
+    /// Keep the USART's clock enabled in Stop mode, so it can receive, and wake the MCU with its
+    /// interrupts. Sets USART_CR1, UESM. The USART kernel clock must be HSI, or LSE (or CSI on H7).
+    /// Defaults to `false`.
+    pub run_in_stop: bool,
 }
 
 impl Default for UsartConfig {
@@ -151,6 +163,9 @@ impl Default for UsartConfig {
             fifo_enabled: false,
             #[cfg(not(feature = "f4"))]
             overrun_disabled: false,
+            half_duplex: false,
+            #[cfg(not(feature = "f4"))]
+            run_in_stop: false,
         }
     }
 }
@@ -215,6 +230,13 @@ where
             .cr3
             .modify(|_, w| w.ovrdis().bit(result.config.overrun_disabled));
 
+        // HDSEL can only be written while the USART is disabled. In IrDA mode, it must be kept
+        // cleared; this is handled below.
+        result
+            .regs
+            .cr3
+            .modify(|_, w| w.hdsel().bit(result.config.half_duplex));
+
         // Must be done before enabling.
         #[cfg(any(feature = "g4", feature = "h7"))]
         result
@@ -222,6 +244,15 @@ where
             .cr1
             .modify(|_, w| w.fifoen().bit(result.config.fifo_enabled));
 
+        #[cfg(not(feature = "f4"))]
+        if result.config.run_in_stop {
+            free(|_| {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                R::en_in_stop(rcc);
+            });
+            result.regs.cr1.modify(|_, w| w.uesm().set_bit());
+        }
+
         // 2. Select the desired baud rate using the USART_BRR register.
         result.set_baud(baud, clock_cfg);
         // 3. Program the number of stop bits in USART_CR2.
@@ -293,9 +324,9 @@ where
         }
 
         // To set BAUD rate, see L4 RM section 38.5.4: "USART baud rate generation".
-        // todo: This assumes the USART clock is APB1 or 2 depending on which USART.
-        // todo: Take into account the selectable USART clock in both
-        // todo util::baud implementation, and `clocks` module.
+        // On L4, L5, G, and W-series, this reads the kernel clock selection from RCC_CCIPR;
+        // set it with `clocks::set_usart_clk_src()`. On F3, F4, and H7, this assumes the USART
+        // clock is APB1 or 2 depending on which USART.
         let fclk = R::baud(clock_cfg);
 
         let usart_div = match self.config.oversampling {
@@ -357,6 +388,19 @@ where
         }
     }
 
+    /// In half-duplex mode, transmit data, then receive a response, eg from a Dynamixel servo, or
+    /// an SDI-12 sensor. The receiver is disabled while transmitting, so our own transmission
+    /// isn't read back, and is re-enabled as soon as the last frame is sent. Blocks until `rx` is
+    /// full. See G4 RM, section 37.5.17: USART single-wire half-duplex communication.
+    pub fn write_read_half_duplex(&mut self, tx: &[u8], rx: &mut [u8]) {
+        self.regs.cr1.modify(|_, w| w.re().clear_bit());
+        // `write()` waits for transmission complete (TC) after the last frame.
+        self.write(tx);
+        self.regs.cr1.modify(|_, w| w.re().set_bit());
+
+        self.read(rx);
+    }
+
     /// Receive data into a u8 buffer. See L44 RM, section 38.5.3: "Character reception procedure"
     pub fn read(&mut self, buf: &mut [u8]) {
         for i in 0..buf.len() {
@@ -737,6 +781,17 @@ where
     }
 }
 
+impl<R> ClockListener for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Re-apply the baud rate, using the new clock speeds.
+    fn clocks_changed(&mut self, clocks: &Clocks) -> Result<(), SpeedError> {
+        self.set_baud(self.baud, clocks);
+        Ok(())
+    }
+}
+
 /// Serial error
 #[non_exhaustive]
 #[derive(Debug)]
@@ -831,3 +886,249 @@ where
         Ok(())
     }
 }
+
+#[cfg(feature = "embedded-hal-1")]
+impl embedded_hal_nb::serial::Error for Error {
+    fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
+        use embedded_hal_nb::serial::ErrorKind;
+
+        match self {
+            Self::Framing => ErrorKind::FrameFormat,
+            Self::Noise => ErrorKind::Noise,
+            Self::Overrun => ErrorKind::Overrun,
+            Self::Parity => ErrorKind::Parity,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_nb::serial::ErrorType for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_nb::serial::Read<u8> for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Returns `WouldBlock` if no data has been received. We use raw bits for the error flags,
+    /// since their field names vary across PACs: PE is bit 0, FE bit 1, NE bit 2, and ORE bit 3, in
+    /// both USART_ISR (USART_SR on F4), and USART_ICR.
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                let status = self.regs.sr.read();
+            } else {
+                let status = self.regs.isr.read();
+            }
+        }
+
+        let bits = status.bits();
+        let error = if bits & 0b1000 != 0 {
+            Some(Error::Overrun)
+        } else if bits & 0b0100 != 0 {
+            Some(Error::Noise)
+        } else if bits & 0b0010 != 0 {
+            Some(Error::Framing)
+        } else if bits & 0b0001 != 0 {
+            Some(Error::Parity)
+        } else {
+            None
+        };
+
+        if let Some(e) = error {
+            // On F4, the flags are cleared by reading SR, then DR.
+            #[cfg(feature = "f4")]
+            self.regs.dr.read();
+            #[cfg(not(feature = "f4"))]
+            self.regs.icr.write(|w| unsafe { w.bits(0b1111) });
+
+            return Err(nb::Error::Other(e));
+        }
+
+        if status.rxne().bit_is_set() {
+            Ok(Usart::read_one(self))
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<R> embedded_hal_nb::serial::Write<u8> for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Returns `WouldBlock` if the transmit data register isn't empty.
+    fn write(&mut self, word: u8) -> nb::Result<(), Error> {
+        cfg_if! {
+            if #[cfg(feature = "f4")] {
+                if self.regs.sr.read().txe().bit_is_clear() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.regs.dr.write(|w| unsafe { w.dr().bits(word as u16) });
+            } else {
+                if self.regs.isr.read().txe().bit_is_clear() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.regs.tdr.write(|w| unsafe { w.tdr().bits(word as u16) });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `WouldBlock` until the transmission is complete.
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        #[cfg(feature = "f4")]
+        let complete = self.regs.sr.read().tc().bit_is_set();
+        #[cfg(not(feature = "f4"))]
+        let complete = self.regs.isr.read().tc().bit_is_set();
+
+        if complete {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        use embedded_io_async::ErrorKind;
+
+        match self {
+            Self::Overrun => ErrorKind::Other,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+// USART_CR1 interrupt enable bits used by `UsartAsync`.
+#[cfg(feature = "async")]
+const RXNEIE: u32 = 1 << 5;
+#[cfg(feature = "async")]
+const TCIE: u32 = 1 << 6;
+#[cfg(feature = "async")]
+const TXEIE: u32 = 1 << 7;
+#[cfg(feature = "async")]
+const PEIE: u32 = 1 << 8;
+#[cfg(feature = "async")]
+const CR1_ASYNC_IRQS: u32 = RXNEIE | TCIE | TXEIE | PEIE;
+
+#[cfg(feature = "async")]
+/// Handle a USART interrupt, for `UsartAsync`. Call this from the peripheral's ISR. Masks the
+/// interrupts enabled by `UsartAsync`, and signals the waiting task.
+pub fn on_interrupt_async(regs: &pac::usart1::RegisterBlock, signal: &Signal) {
+    regs.cr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_ASYNC_IRQS) });
+    signal.signal();
+}
+
+#[cfg(feature = "async")]
+/// A USART peripheral, which implements the `embedded-io-async` `Read` and `Write` traits, driven by
+/// the peripheral's interrupt. Its ISR must call `on_interrupt_async()`, with the same `Signal`.
+/// Received bytes aren't buffered while no read is in progress, so with continuous incoming data,
+/// keep a read pending, or use DMA.
+pub struct UsartAsync<R> {
+    pub usart: Usart<R>,
+    signal: &'static Signal,
+}
+
+#[cfg(feature = "async")]
+impl<R> UsartAsync<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    pub fn new(usart: Usart<R>, signal: &'static Signal) -> Self {
+        Self { usart, signal }
+    }
+
+    /// Run a non-blocking operation until it completes, enabling the `irq` interrupt, and waiting
+    /// for the ISR while it would block.
+    async fn poll_nb<T, F>(&mut self, irq: u32, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut(&mut Usart<R>) -> nb::Result<T, Error>,
+    {
+        loop {
+            match op(&mut self.usart) {
+                Ok(v) => return Ok(v),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => (),
+            }
+
+            // The ISR masks this again when it fires.
+            self.signal.reset();
+            self.usart
+                .regs
+                .cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() | irq) });
+            self.signal.wait().await;
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_io_async::ErrorType for UsartAsync<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    type Error = Error;
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_io_async::Read for UsartAsync<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Waits for at least one byte, then returns the bytes received without waiting further.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        use embedded_hal_nb::serial::Read;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Errors are flagged along with RXNE, so RXNEIE covers them; PEIE covers parity errors.
+        buf[0] = self.poll_nb(RXNEIE | PEIE, Read::read).await?;
+
+        let mut count = 1;
+        while count < buf.len() {
+            match Read::read(&mut self.usart) {
+                Ok(byte) => buf[count] = byte,
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R> embedded_io_async::Write for UsartAsync<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        use embedded_hal_nb::serial::Write;
+
+        for byte in buf {
+            self.poll_nb(TXEIE, |usart| Write::write(usart, *byte))
+                .await?;
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        use embedded_hal_nb::serial::Write;
+
+        self.poll_nb(TCIE, Write::flush).await
+    }
+}
@@ -1,7 +1,8 @@
 //! USB support, including for simulated COM ports. This module is a thin wrapper required to work with
 //! the `stm32_usbd` crate.
 //!
-//! Requires the `usb` feature.
+//! Requires the `usb` feature. Create the bus allocator used by `usb-device` classes with
+//! `new_bus()`.
 //!
 //! Used on F303, L4x2, L4x3, L4x5, L5, G0, and G4. F4, L4x6 and H7 use the `usb_otg` module.
 //! For G0 series, USB is only available on G0B0, G0B1, G0C1, which the PAC doesn't yet differentiate,
@@ -20,7 +21,11 @@
  Strangely, the register modification commands for the l4x5 have OTG in their names
 */
 
-use crate::{pac, util::rcc_en_reset};
+use crate::{
+    clocks::{Clocks, SpeedError},
+    pac,
+    util::rcc_en_reset,
+};
 
 #[cfg(any(feature = "l4", feature = "l5", feature = "g0"))]
 use crate::pac::PWR;
@@ -29,6 +34,7 @@ use crate::pac::USB;
 
 pub use stm32_usbd::UsbBus;
 use stm32_usbd::UsbPeripheral;
+use usb_device::bus::UsbBusAllocator;
 
 use cfg_if::cfg_if;
 
@@ -128,6 +134,30 @@ unsafe impl UsbPeripheral for Peripheral {
 /// Type of the UsbBus
 pub type UsbBusType = UsbBus<Peripheral>;
 
+/// Set up the USB peripheral, and create the bus allocator used by `usb-device` classes, eg
+/// `usbd-serial`, or `usbd-hid`. Checks that the USB clock is 48Mhz, and on L4 and L5, enables the
+/// VddUSB supply. Packet memory is managed by the `stm32-usbd` crate, using the `EP_MEMORY`, and
+/// `EP_MEMORY_SIZE` settings above.
+///
+/// Example:
+/// ```rust
+/// let usb_bus = usb::new_bus(dp.USB, &clock_cfg).unwrap();
+/// let mut serial = SerialPort::new(&usb_bus);
+/// ```
+pub fn new_bus(regs: USB, clocks: &Clocks) -> Result<UsbBusAllocator<UsbBusType>, SpeedError> {
+    clocks.validate_usb()?;
+
+    #[cfg(any(feature = "l4", feature = "l5"))]
+    {
+        // VddUSB is controlled by the PWR peripheral, whose clock may not be enabled yet.
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+        enable_usb_pwr();
+    }
+
+    Ok(UsbBus::new(Peripheral { regs }))
+}
+
 #[cfg(any(feature = "l4", feature = "l5", feature = "g0"))]
 /// Enables the Vdd USB power supply. Note that we also need to enable `PWREN` in APB1,
 /// but we handle this using the RTC setup. Use a raw pointer if doing this without the RTC
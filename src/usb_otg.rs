@@ -3,6 +3,8 @@
 //!
 //! Requires the `usbotg_fs` or `usbotg_hs` features.
 //! Used on F4, L4x6, and H7. Others use the `usb` module.
+//!
+//! Check the 48Mhz USB clock with `Clocks::validate_usb()` before creating the bus.
 
 // Based on `stm3h7xx-hal`
 
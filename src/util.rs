@@ -8,6 +8,9 @@ use crate::{
     pac::{self, rcc::RegisterBlock},
 };
 
+#[cfg(not(any(feature = "f3", feature = "f4", feature = "h7")))]
+use crate::clocks::UsartPeriph;
+
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::pac::DMA1;
 
@@ -135,21 +138,77 @@ macro_rules! rcc_en_reset {
 
 pub(crate) use rcc_en_reset;
 
+/// Keeps peripheral clocks enabled in Sleep and Stop modes, on various RCC registers.
+/// (RCC_xxxSMENR, or on H7, RCC_xxxLPENR) Arguments are the same as `rcc_en_reset`. Does nothing
+/// on F3 and F4, where Stop mode stops all peripheral clocks.
+macro_rules! rcc_en_stop {
+    (apb1, $periph:expr, $rcc:expr) => {
+        paste::paste! { cfg_if::cfg_if! {
+            if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb", feature = "wl"))] {
+                $rcc.apb1smenr1.modify(|_, w| w.[<$periph smen>]().set_bit());
+            } else if #[cfg(feature = "g0")] {
+                $rcc.apbsmenr1.modify(|_, w| w.[<$periph smen>]().set_bit());
+            } else if #[cfg(feature = "h7")] {
+                $rcc.apb1llpenr.modify(|_, w| w.[<$periph lpen>]().set_bit());
+            } else {
+                let _ = $rcc;
+            }
+        }}
+    };
+    (apb2, $periph:expr, $rcc:expr) => {
+        paste::paste! { cfg_if::cfg_if! {
+            if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb", feature = "wl"))] {
+                $rcc.apb2smenr.modify(|_, w| w.[<$periph smen>]().set_bit());
+            } else if #[cfg(feature = "g0")] {
+                $rcc.apbsmenr2.modify(|_, w| w.[<$periph smen>]().set_bit());
+            } else if #[cfg(feature = "h7")] {
+                $rcc.apb2lpenr.modify(|_, w| w.[<$periph lpen>]().set_bit());
+            } else {
+                let _ = $rcc;
+            }
+        }}
+    };
+    (apb4, $periph:expr, $rcc:expr) => {
+        paste::paste! {
+            $rcc.apb4lpenr.modify(|_, w| w.[<$periph lpen>]().set_bit());
+        }
+    };
+}
+
+// Only used outside this module by `lptim`.
+#[cfg(not(any(
+    feature = "f3",
+    feature = "f4",
+    feature = "g030",
+    feature = "g050",
+    feature = "g070",
+    feature = "g0b0",
+)))]
+pub(crate) use rcc_en_stop;
+
 // todo: This trait is currently a one-off for usart
+/// Provides the U[S]ART kernel clock frequency that baud rates are computed from. On families with
+/// a selectable kernel clock, this reads the selection from RCC_CCIPR.
 pub trait BaudPeriph {
     fn baud(clock_cfg: &Clocks) -> u32;
 }
 
 impl BaudPeriph for pac::USART1 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb2()
+        #[cfg(any(feature = "f3", feature = "f4", feature = "h7"))]
+        return clock_cfg.apb2();
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "h7")))]
+        return clock_cfg.usart_kernel(UsartPeriph::Usart1);
     }
 }
 
 #[cfg(not(any(feature = "wb", feature = "wl")))]
 impl BaudPeriph for pac::USART2 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+        #[cfg(any(feature = "f3", feature = "f4", feature = "h7"))]
+        return clock_cfg.apb1();
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "h7")))]
+        return clock_cfg.usart_kernel(UsartPeriph::Usart2);
     }
 }
 
@@ -166,7 +225,10 @@ impl BaudPeriph for pac::USART2 {
 )))]
 impl BaudPeriph for pac::USART3 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+        #[cfg(any(feature = "f3", feature = "f4", feature = "h7"))]
+        return clock_cfg.apb1();
+        #[cfg(not(any(feature = "f3", feature = "f4", feature = "h7")))]
+        return clock_cfg.usart_kernel(UsartPeriph::Usart3);
     }
 }
 
@@ -174,13 +236,19 @@ cfg_if! {
     if #[cfg(any(feature = "l4x6", feature = "h7"))] {
         impl BaudPeriph for pac::UART4 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                #[cfg(feature = "h7")]
+                return clock_cfg.apb1();
+                #[cfg(not(feature = "h7"))]
+                return clock_cfg.usart_kernel(UsartPeriph::Uart4);
             }
         }
 
         impl BaudPeriph for pac::UART5 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                #[cfg(feature = "h7")]
+                return clock_cfg.apb1();
+                #[cfg(not(feature = "h7"))]
+                return clock_cfg.usart_kernel(UsartPeriph::Uart5);
             }
         }
 
@@ -272,6 +340,12 @@ impl VrefPeriph for pac::ADC5 {
 pub trait RccPeriph {
     fn en_reset(rcc: &RegisterBlock);
 
+    /// Keep the peripheral's clocks enabled in Stop mode, so it can use a kernel clock that runs
+    /// in Stop, (eg HSI, or LSE) and wake the MCU. These are enabled after reset on families
+    /// that have them, so this only matters if they were cleared elsewhere, eg to save power in
+    /// Sleep mode. Does nothing for peripherals that can't run in Stop mode.
+    fn en_in_stop(_rcc: &RegisterBlock) {}
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel;
     #[cfg(any(feature = "f3", feature = "l4"))]
@@ -363,6 +437,10 @@ impl RccPeriph for pac::I2C1 {
         rcc_en_reset!(apb1, i2c1, rcc);
     }
 
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb1, i2c1, rcc);
+    }
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel {
         DmaInput::I2c1Rx.dma1_channel()
@@ -390,6 +468,10 @@ impl RccPeriph for pac::I2C2 {
         rcc_en_reset!(apb1, i2c2, rcc);
     }
 
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb1, i2c2, rcc);
+    }
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel {
         DmaInput::I2c2Rx.dma1_channel()
@@ -416,6 +498,26 @@ impl RccPeriph for pac::I2C3 {
     fn en_reset(rcc: &RegisterBlock) {
         rcc_en_reset!(apb1, i2c3, rcc);
     }
+
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb1, i2c3, rcc);
+    }
+}
+
+// I2C4 is in the D3 domain (SRD domain on H7B3), on APB4.
+#[cfg(feature = "h7")]
+impl RccPeriph for pac::I2C4 {
+    fn en_reset(rcc: &RegisterBlock) {
+        rcc_en_reset!(apb4, i2c4, rcc);
+    }
+
+    /// Also enables autonomous mode, so I2C4's kernel clock keeps running while the CPU's domain
+    /// is in Stop mode.
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb4, i2c4, rcc);
+        #[cfg(not(feature = "h7b3"))]
+        rcc.d3amr.modify(|_, w| w.i2c4amen().set_bit());
+    }
 }
 
 #[cfg(not(feature = "f301"))] // todo: Not sure what's going on  here.
@@ -650,6 +752,10 @@ impl RccPeriph for pac::USART1 {
         rcc_en_reset!(apb2, usart1, rcc);
     }
 
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb2, usart1, rcc);
+    }
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel {
         DmaInput::Usart1Rx.dma1_channel()
@@ -686,6 +792,10 @@ impl RccPeriph for pac::USART2 {
         }
     }
 
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb1, usart2, rcc);
+    }
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel {
         DmaInput::Usart2Rx.dma1_channel()
@@ -731,6 +841,10 @@ impl RccPeriph for pac::USART3 {
         }
     }
 
+    fn en_in_stop(rcc: &RegisterBlock) {
+        rcc_en_stop!(apb1, usart3, rcc);
+    }
+
     #[cfg(any(feature = "f3", feature = "l4"))]
     fn read_chan() -> DmaChannel {
         DmaInput::Usart3Rx.dma1_channel()
@@ -932,7 +1046,7 @@ cfg_if! {
     }
 }
 
-// todo: APB1LR2 on L5, and AHB4 on H7. Fix it. (I2C4)
+// todo: APB1LR2 on L5. Fix it. (I2C4)
 // I2cDevice::Four => {
 
 // We currently only set up DAC1 DMA, and it's split by channels, not device.